@@ -0,0 +1,23 @@
+#!/bin/sh
+# Check that all crates, the README and the MSRV workflow
+# declare the same minimum supported Rust version
+set -eu
+cd "$(dirname "$0")/.."
+
+msrv=$(sed -n 's/^rust-version = "\(.*\)"$/\1/p' rsnes/Cargo.toml)
+status=0
+for manifest in */Cargo.toml; do
+    if ! grep -qx "rust-version = \"$msrv\"" "$manifest"; then
+        echo "$manifest does not declare rust-version $msrv"
+        status=1
+    fi
+done
+if ! grep -q "stable Rust $msrv or newer" README.md; then
+    echo "README.md does not mention Rust $msrv"
+    status=1
+fi
+if [ "$(grep -c "dtolnay/rust-toolchain@$msrv" .github/workflows/msrv.yml)" -lt 2 ]; then
+    echo ".github/workflows/msrv.yml does not test all crates with Rust $msrv"
+    status=1
+fi
+exit $status
@@ -0,0 +1,60 @@
+/* C bindings for embedding rsnes, see capi/src/lib.rs for documentation */
+#ifndef RSNES_H
+#define RSNES_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RSNES_OK 0
+#define RSNES_ERROR_NULL (-1)
+#define RSNES_ERROR_INVALID_ROM (-2)
+#define RSNES_ERROR_NO_ROM (-3)
+#define RSNES_ERROR_INVALID_STATE (-4)
+#define RSNES_ERROR_BUFFER_TOO_SMALL (-5)
+#define RSNES_ERROR_INVALID_PORT (-6)
+#define RSNES_ERROR_PANIC (-7)
+
+#define RSNES_REGION_NTSC 0
+#define RSNES_REGION_PAL 1
+
+#define RSNES_BUTTON_B (1 << 0)
+#define RSNES_BUTTON_Y (1 << 1)
+#define RSNES_BUTTON_SELECT (1 << 2)
+#define RSNES_BUTTON_START (1 << 3)
+#define RSNES_BUTTON_UP (1 << 4)
+#define RSNES_BUTTON_DOWN (1 << 5)
+#define RSNES_BUTTON_LEFT (1 << 6)
+#define RSNES_BUTTON_RIGHT (1 << 7)
+#define RSNES_BUTTON_A (1 << 8)
+#define RSNES_BUTTON_X (1 << 9)
+#define RSNES_BUTTON_L (1 << 10)
+#define RSNES_BUTTON_R (1 << 11)
+
+typedef struct RsnesDevice RsnesDevice;
+
+RsnesDevice *rsnes_device_new(uint32_t region);
+void rsnes_device_free(RsnesDevice *device);
+
+int32_t rsnes_device_load_rom(RsnesDevice *device, const uint8_t *data, size_t len);
+int32_t rsnes_device_run_frame(RsnesDevice *device);
+
+/* RGBA pixels, valid until the device is used mutably again */
+const uint8_t *rsnes_device_frame_buffer(const RsnesDevice *device, uint32_t *width,
+                                         uint32_t *height, size_t *pitch);
+/* interleaved stereo samples, returns the number of stereo samples */
+size_t rsnes_device_poll_audio(RsnesDevice *device, int16_t *buffer, size_t max_frames);
+int32_t rsnes_device_set_buttons(RsnesDevice *device, uint32_t port, uint16_t buttons);
+
+int32_t rsnes_device_save_state(const RsnesDevice *device, uint8_t *buffer, size_t capacity,
+                                size_t *size);
+int32_t rsnes_device_load_state(RsnesDevice *device, const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
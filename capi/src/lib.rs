@@ -0,0 +1,349 @@
+//! C bindings for embedding rsnes in other frontends
+//!
+//! The functions are declared in `include/rsnes.h`. A device is created with
+//! [`rsnes_device_new`], which returns an opaque pointer, that must be passed
+//! to all other functions and finally freed with [`rsnes_device_free`].
+//!
+//! A typical frontend loads a ROM with [`rsnes_device_load_rom`] and then
+//! repeatedly sets the controller state, runs a frame and fetches the picture
+//! and the audio samples, which were generated during the frame.
+//!
+//! Panics never unwind into C. A function, which panicked, returns
+//! [`RSNES_ERROR_PANIC`] or a null pointer or zero, if it doesn't return an
+//! error code. The device may be inconsistent afterwards and should only be freed.
+
+use rsnes::{
+    backend::{ArrayFrameBuffer, AudioBackend},
+    cartridge::Cartridge,
+    controller::ButtonState,
+    device::{Device, Region},
+    spc700::StereoSample,
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+const MASTER_CYCLES_PER_TICK: u16 = 2;
+
+/// Stack size of the thread constructing a device.
+///
+/// The device contains the frame buffer and is moved around several times
+/// while it is constructed, which may exceed the stack of the calling thread.
+const CONSTRUCTION_STACK_SIZE: usize = 0x2000000;
+
+/// Buffered samples beyond this count are dropped until they are polled
+const MAX_BUFFERED_SAMPLES: usize = 0x4000;
+
+pub const RSNES_OK: i32 = 0;
+/// A pointer argument is null
+pub const RSNES_ERROR_NULL: i32 = -1;
+/// The ROM could not be read
+pub const RSNES_ERROR_INVALID_ROM: i32 = -2;
+/// No ROM is loaded yet
+pub const RSNES_ERROR_NO_ROM: i32 = -3;
+/// The save state could not be loaded
+pub const RSNES_ERROR_INVALID_STATE: i32 = -4;
+/// The buffer is too small, the required size is returned separately
+pub const RSNES_ERROR_BUFFER_TOO_SMALL: i32 = -5;
+/// The controller port is neither 1 nor 2
+pub const RSNES_ERROR_INVALID_PORT: i32 = -6;
+/// The emulator panicked
+pub const RSNES_ERROR_PANIC: i32 = -7;
+
+pub const RSNES_REGION_NTSC: u32 = 0;
+pub const RSNES_REGION_PAL: u32 = 1;
+
+#[derive(Default, Clone)]
+struct SampleQueue(Arc<Mutex<VecDeque<StereoSample>>>);
+
+impl AudioBackend for SampleQueue {
+    fn push_sample(&mut self, sample: StereoSample) {
+        let mut samples = self.0.lock().unwrap();
+        if samples.len() < MAX_BUFFERED_SAMPLES {
+            samples.push_back(sample)
+        }
+    }
+}
+
+/// The opaque device handle given to C
+pub struct RsnesDevice {
+    device: Device<SampleQueue, ArrayFrameBuffer>,
+    samples: SampleQueue,
+    has_rom: bool,
+}
+
+impl RsnesDevice {
+    fn new(region: Region) -> Box<Self> {
+        std::thread::Builder::new()
+            .stack_size(CONSTRUCTION_STACK_SIZE)
+            .spawn(move || {
+                let samples = SampleQueue::default();
+                Box::new(Self {
+                    device: Device::new(samples.clone(), ArrayFrameBuffer::new(), region, false),
+                    samples,
+                    has_rom: false,
+                })
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    fn run_frame(&mut self) {
+        self.device.run_cycle::<MASTER_CYCLES_PER_TICK>();
+        while !self.device.new_frame {
+            self.device.run_cycle::<MASTER_CYCLES_PER_TICK>();
+        }
+    }
+
+    /// Load a save state, the device is left unchanged if the state is invalid
+    fn load_state(&mut self, state: &[u8]) -> bool {
+        save_state::deserialize_checked(&mut self.device, state).is_ok()
+    }
+}
+
+/// Run `f` and return `on_panic` instead of unwinding into C, if it panics
+fn guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+/// Convert a possibly null pointer and length into a slice
+unsafe fn slice<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    if data.is_null() {
+        None
+    } else {
+        Some(core::slice::from_raw_parts(data, len))
+    }
+}
+
+/// Create a device without a ROM. Returns null if the region is unknown.
+#[no_mangle]
+pub extern "C" fn rsnes_device_new(region: u32) -> *mut RsnesDevice {
+    guard(core::ptr::null_mut(), || {
+        let region = match region {
+            RSNES_REGION_NTSC => Region::Ntsc,
+            RSNES_REGION_PAL => Region::Pal,
+            _ => return core::ptr::null_mut(),
+        };
+        Box::into_raw(RsnesDevice::new(region))
+    })
+}
+
+/// Free a device created with [`rsnes_device_new`]
+///
+/// # Safety
+///
+/// `device` must be null or a pointer returned by [`rsnes_device_new`],
+/// which was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_device_free(device: *mut RsnesDevice) {
+    guard((), || {
+        if !device.is_null() {
+            drop(Box::from_raw(device))
+        }
+    })
+}
+
+/// Load a ROM image from memory and reset the CPU
+///
+/// # Safety
+///
+/// `device` must be a valid device and `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_device_load_rom(
+    device: *mut RsnesDevice,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    guard(RSNES_ERROR_PANIC, || {
+        let (Some(device), Some(data)) = (device.as_mut(), slice(data, len)) else {
+            return RSNES_ERROR_NULL;
+        };
+        match Cartridge::from_bytes(data) {
+            Ok(cartridge) => {
+                device.device.load_cartridge(cartridge);
+                device.has_rom = true;
+                RSNES_OK
+            }
+            Err(_) => RSNES_ERROR_INVALID_ROM,
+        }
+    })
+}
+
+/// Emulate until the next frame begins
+///
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_device_run_frame(device: *mut RsnesDevice) -> i32 {
+    guard(RSNES_ERROR_PANIC, || {
+        let Some(device) = device.as_mut() else {
+            return RSNES_ERROR_NULL;
+        };
+        if !device.has_rom {
+            return RSNES_ERROR_NO_ROM;
+        }
+        device.run_frame();
+        RSNES_OK
+    })
+}
+
+/// Get the picture of the last frame as RGBA pixels.
+///
+/// The dimensions of the picture are written to `width` and `height`
+/// and the distance between two rows in bytes is written to `pitch`.
+/// The pointer stays valid until the device is used mutably again.
+///
+/// # Safety
+///
+/// `device` must be a valid device and the other pointers must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_device_frame_buffer(
+    device: *const RsnesDevice,
+    width: *mut u32,
+    height: *mut u32,
+    pitch: *mut usize,
+) -> *const u8 {
+    guard(core::ptr::null(), || {
+        let Some(device) = device.as_ref() else {
+            return core::ptr::null();
+        };
+        let frame_buffer = &device.device.ppu.frame_buffer;
+        let size = frame_buffer.size();
+        for (ptr, value) in [(width, size.width), (height, size.height)] {
+            if let Some(ptr) = ptr.as_mut() {
+                *ptr = value
+            }
+        }
+        if let Some(pitch) = pitch.as_mut() {
+            *pitch = size.width as usize * 4
+        }
+        frame_buffer.get_bytes().as_ptr()
+    })
+}
+
+/// Move up to `max_frames` stereo samples into `buffer` as interleaved
+/// left and right 16-bit values. Returns the number of moved stereo samples.
+///
+/// # Safety
+///
+/// `device` must be a valid device and `buffer` must have room
+/// for `2 * max_frames` values.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_device_poll_audio(
+    device: *mut RsnesDevice,
+    buffer: *mut i16,
+    max_frames: usize,
+) -> usize {
+    guard(0, || {
+        let Some(device) = device.as_mut() else {
+            return 0;
+        };
+        if buffer.is_null() {
+            return 0;
+        }
+        let buffer = core::slice::from_raw_parts_mut(buffer, max_frames * 2);
+        let mut samples = device.samples.0.lock().unwrap();
+        let count = samples.len().min(max_frames);
+        for (dst, sample) in buffer.chunks_exact_mut(2).zip(samples.drain(..count)) {
+            dst.copy_from_slice(&[sample.l, sample.r])
+        }
+        count
+    })
+}
+
+/// Set the pressed buttons of the standard controller at port 1 or 2.
+///
+/// The button bits are the same as in `rsnes::controller::buttons`.
+///
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_device_set_buttons(
+    device: *mut RsnesDevice,
+    port: u32,
+    buttons: u16,
+) -> i32 {
+    guard(RSNES_ERROR_PANIC, || {
+        let Some(device) = device.as_mut() else {
+            return RSNES_ERROR_NULL;
+        };
+        let controllers = &mut device.device.controllers;
+        match port {
+            1 | 2 => {
+                controllers.set_buttons(port as usize - 1, 0, ButtonState(buttons));
+                RSNES_OK
+            }
+            _ => RSNES_ERROR_INVALID_PORT,
+        }
+    })
+}
+
+/// Write a save state into `buffer`.
+///
+/// The size of the save state is always written to `size`.
+/// If the buffer is smaller than that, [`RSNES_ERROR_BUFFER_TOO_SMALL`]
+/// is returned and the content of the buffer is unspecified.
+///
+/// # Safety
+///
+/// `device` must be a valid device, `buffer` must be null or point to
+/// `capacity` writable bytes and `size` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_device_save_state(
+    device: *const RsnesDevice,
+    buffer: *mut u8,
+    capacity: usize,
+    size: *mut usize,
+) -> i32 {
+    guard(RSNES_ERROR_PANIC, || {
+        let (Some(device), Some(size)) = (device.as_ref(), size.as_mut()) else {
+            return RSNES_ERROR_NULL;
+        };
+        let buffer = if buffer.is_null() {
+            &mut []
+        } else {
+            core::slice::from_raw_parts_mut(buffer, capacity)
+        };
+        match save_state::serialize_into(&device.device, buffer) {
+            Ok(len) => {
+                *size = len;
+                RSNES_OK
+            }
+            Err(err) => {
+                *size = err.required;
+                RSNES_ERROR_BUFFER_TOO_SMALL
+            }
+        }
+    })
+}
+
+/// Load a save state written by [`rsnes_device_save_state`].
+/// The device is left unchanged if the state is invalid.
+///
+/// # Safety
+///
+/// `device` must be a valid device and `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_device_load_state(
+    device: *mut RsnesDevice,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    guard(RSNES_ERROR_PANIC, || {
+        let (Some(device), Some(data)) = (device.as_mut(), slice(data, len)) else {
+            return RSNES_ERROR_NULL;
+        };
+        if device.load_state(data) {
+            RSNES_OK
+        } else {
+            RSNES_ERROR_INVALID_STATE
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests;
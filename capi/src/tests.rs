@@ -0,0 +1,93 @@
+use super::*;
+
+/// Create a LoROM image, whose program loops forever
+fn new_rom() -> Vec<u8> {
+    let mut rom = vec![0xea; 0x8000];
+    let header = &mut rom[0x7fc0..];
+    header[..21].copy_from_slice(b"CAPI TEST            ");
+    header[21] = 0x20;
+    header[23] = 5;
+    header[25] = 1;
+    header[60..62].copy_from_slice(&0x8000u16.to_le_bytes());
+    // `bra` to itself
+    rom[..2].copy_from_slice(&[0x80, 0xfe]);
+    rom
+}
+
+#[test]
+fn run_frames() {
+    unsafe {
+        assert!(rsnes_device_new(2).is_null());
+        let device = rsnes_device_new(RSNES_REGION_NTSC);
+        assert_eq!(rsnes_device_run_frame(device), RSNES_ERROR_NO_ROM);
+        let rom = new_rom();
+        assert_eq!(
+            rsnes_device_load_rom(device, rom.as_ptr(), 16),
+            RSNES_ERROR_INVALID_ROM
+        );
+        assert_eq!(
+            rsnes_device_load_rom(device, rom.as_ptr(), rom.len()),
+            RSNES_OK
+        );
+        assert_eq!(
+            rsnes_device_set_buttons(device, 3, 0),
+            RSNES_ERROR_INVALID_PORT
+        );
+        assert_eq!(rsnes_device_set_buttons(device, 1, 0x100), RSNES_OK);
+        for _ in 0..3 {
+            assert_eq!(rsnes_device_run_frame(device), RSNES_OK);
+        }
+
+        let (mut width, mut height, mut pitch) = (0, 0, 0);
+        let pixels = rsnes_device_frame_buffer(device, &mut width, &mut height, &mut pitch);
+        assert!(!pixels.is_null());
+        assert_eq!((width, height, pitch), (256, 224, 1024));
+
+        let mut samples = [0; 64];
+        assert_eq!(
+            rsnes_device_poll_audio(device, samples.as_mut_ptr(), 32),
+            32
+        );
+        rsnes_device_free(device);
+    }
+}
+
+#[test]
+fn save_and_load_state() {
+    unsafe {
+        let device = rsnes_device_new(RSNES_REGION_PAL);
+        let rom = new_rom();
+        rsnes_device_load_rom(device, rom.as_ptr(), rom.len());
+        rsnes_device_run_frame(device);
+
+        let mut size = 0;
+        let result = rsnes_device_save_state(device, core::ptr::null_mut(), 0, &mut size);
+        assert_eq!(result, RSNES_ERROR_BUFFER_TOO_SMALL);
+        let mut state = vec![0; size];
+        let result = rsnes_device_save_state(device, state.as_mut_ptr(), size, &mut size);
+        assert_eq!(result, RSNES_OK);
+
+        rsnes_device_run_frame(device);
+        assert_eq!(
+            rsnes_device_load_state(device, state.as_ptr(), size / 2),
+            RSNES_ERROR_INVALID_STATE
+        );
+        assert_eq!(
+            rsnes_device_load_state(device, state.as_ptr(), size),
+            RSNES_OK
+        );
+        let mut loaded = vec![0; size];
+        rsnes_device_save_state(device, loaded.as_mut_ptr(), size, &mut size);
+        assert_eq!(loaded, state);
+        rsnes_device_free(device);
+    }
+}
+
+#[test]
+fn panics_are_caught() {
+    assert_eq!(
+        guard(RSNES_ERROR_PANIC, || panic!("test panic")),
+        RSNES_ERROR_PANIC
+    );
+    assert_eq!(guard(RSNES_ERROR_PANIC, || RSNES_OK), RSNES_OK);
+}
@@ -0,0 +1,321 @@
+//! A browser frontend for rsnes
+//!
+//! The emulator is driven from JavaScript (see `index.html`): it creates an
+//! [`Emulator`] for a canvas, loads a ROM and then calls
+//! [`Emulator::run_frame`] on every animation frame, which emulates as many
+//! frames as have passed in the frame rate of the cartridge region.
+//! The picture is drawn with WebGL and the audio is scheduled with WebAudio.
+
+use rsnes::{
+    backend::{ArrayFrameBuffer, AudioBackend, ClockSource},
+    cartridge::Cartridge,
+    controller::ButtonState,
+    device::{Device, Region},
+    ppu::{MAX_FRAME_HEIGHT, MAX_FRAME_WIDTH},
+    spc700::StereoSample,
+};
+use std::sync::{Arc, Mutex};
+use wasm_bindgen::prelude::*;
+use web_sys::{
+    AudioContext, AudioContextOptions, HtmlCanvasElement, WebGlProgram,
+    WebGlRenderingContext as Gl, WebGlShader, WebGlUniformLocation,
+};
+
+const MASTER_CYCLES_PER_TICK: u16 = 2;
+const SAMPLE_RATE: f32 = 32000.0;
+/// Audio is scheduled this many seconds ahead to avoid gaps
+const AUDIO_LATENCY: f64 = 0.05;
+/// Samples are dropped while more than this many seconds are scheduled
+const MAX_AUDIO_AHEAD: f64 = 0.2;
+/// At most this many frames are emulated per animation frame,
+/// the emulation falls behind instead of catching up after a stall
+const MAX_FRAMES_PER_CALL: u32 = 4;
+
+const VERTEX_SHADER: &str = r#"
+attribute vec2 pos;
+uniform vec2 scale;
+varying vec2 uv;
+void main() {
+    uv = (pos * vec2(0.5, -0.5) + 0.5) * scale;
+    gl_Position = vec4(pos, 0.0, 1.0);
+}
+"#;
+
+const FRAGMENT_SHADER: &str = r#"
+precision mediump float;
+uniform sampler2D frame;
+varying vec2 uv;
+void main() {
+    gl_FragColor = texture2D(frame, uv);
+}
+"#;
+
+#[derive(Default, Clone)]
+struct SampleQueue(Arc<Mutex<Vec<StereoSample>>>);
+
+impl AudioBackend for SampleQueue {
+    fn push_sample(&mut self, sample: StereoSample) {
+        self.0.lock().unwrap().push(sample)
+    }
+}
+
+/// The wall clock of the browser, which is used by real-time clocks
+#[derive(Debug, Clone, Copy)]
+struct BrowserClock;
+
+impl ClockSource for BrowserClock {
+    fn unix_time(&self) -> u64 {
+        (js_sys::Date::now() / 1000.0) as u64
+    }
+}
+
+fn compile_shader(gl: &Gl, kind: u32, source: &str) -> Result<WebGlShader, JsValue> {
+    let shader = gl.create_shader(kind).ok_or("could not create a shader")?;
+    gl.shader_source(&shader, source);
+    gl.compile_shader(&shader);
+    if gl
+        .get_shader_parameter(&shader, Gl::COMPILE_STATUS)
+        .as_bool()
+        .unwrap_or(false)
+    {
+        Ok(shader)
+    } else {
+        Err(gl.get_shader_info_log(&shader).unwrap_or_default().into())
+    }
+}
+
+fn link_program(gl: &Gl) -> Result<WebGlProgram, JsValue> {
+    let program = gl.create_program().ok_or("could not create a program")?;
+    gl.attach_shader(
+        &program,
+        &compile_shader(gl, Gl::VERTEX_SHADER, VERTEX_SHADER)?,
+    );
+    gl.attach_shader(
+        &program,
+        &compile_shader(gl, Gl::FRAGMENT_SHADER, FRAGMENT_SHADER)?,
+    );
+    gl.link_program(&program);
+    if gl
+        .get_program_parameter(&program, Gl::LINK_STATUS)
+        .as_bool()
+        .unwrap_or(false)
+    {
+        Ok(program)
+    } else {
+        Err(gl.get_program_info_log(&program).unwrap_or_default().into())
+    }
+}
+
+/// Draws the frame buffer as a texture covering the whole canvas
+struct Video {
+    gl: Gl,
+    scale: WebGlUniformLocation,
+}
+
+impl Video {
+    fn new(canvas: &HtmlCanvasElement) -> Result<Self, JsValue> {
+        let gl: Gl = canvas
+            .get_context("webgl")?
+            .ok_or("WebGL is not supported")?
+            .dyn_into()?;
+        let program = link_program(&gl)?;
+        gl.use_program(Some(&program));
+
+        let vertices: [f32; 8] = [-1.0, -1.0, 1.0, -1.0, -1.0, 1.0, 1.0, 1.0];
+        gl.bind_buffer(Gl::ARRAY_BUFFER, gl.create_buffer().as_ref());
+        // the view is used before any memory gets allocated
+        let view = unsafe { js_sys::Float32Array::view(&vertices) };
+        gl.buffer_data_with_array_buffer_view(Gl::ARRAY_BUFFER, &view, Gl::STATIC_DRAW);
+        let pos = gl.get_attrib_location(&program, "pos") as u32;
+        gl.vertex_attrib_pointer_with_i32(pos, 2, Gl::FLOAT, false, 0, 0);
+        gl.enable_vertex_attrib_array(pos);
+
+        gl.bind_texture(Gl::TEXTURE_2D, gl.create_texture().as_ref());
+        for (param, value) in [
+            (Gl::TEXTURE_MIN_FILTER, Gl::NEAREST),
+            (Gl::TEXTURE_MAG_FILTER, Gl::NEAREST),
+            (Gl::TEXTURE_WRAP_S, Gl::CLAMP_TO_EDGE),
+            (Gl::TEXTURE_WRAP_T, Gl::CLAMP_TO_EDGE),
+        ] {
+            gl.tex_parameteri(Gl::TEXTURE_2D, param, value as i32);
+        }
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            Gl::TEXTURE_2D,
+            0,
+            Gl::RGBA as i32,
+            MAX_FRAME_WIDTH as i32,
+            MAX_FRAME_HEIGHT as i32,
+            0,
+            Gl::RGBA,
+            Gl::UNSIGNED_BYTE,
+            None,
+        )?;
+        let scale = gl
+            .get_uniform_location(&program, "scale")
+            .ok_or("missing uniform")?;
+        Ok(Self { gl, scale })
+    }
+
+    fn draw(&self, frame_buffer: &ArrayFrameBuffer) -> Result<(), JsValue> {
+        let gl = &self.gl;
+        let size = frame_buffer.size();
+        gl.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
+            Gl::TEXTURE_2D,
+            0,
+            0,
+            0,
+            size.width as i32,
+            size.height as i32,
+            Gl::RGBA,
+            Gl::UNSIGNED_BYTE,
+            Some(frame_buffer.get_bytes()),
+        )?;
+        gl.uniform2f(
+            Some(&self.scale),
+            size.width as f32 / MAX_FRAME_WIDTH as f32,
+            size.height as f32 / MAX_FRAME_HEIGHT as f32,
+        );
+        gl.viewport(0, 0, gl.drawing_buffer_width(), gl.drawing_buffer_height());
+        gl.draw_arrays(Gl::TRIANGLE_STRIP, 0, 4);
+        Ok(())
+    }
+}
+
+/// Plays the samples of every frame as a WebAudio buffer
+struct Audio {
+    context: AudioContext,
+    /// The context time at which the next buffer starts playing
+    next_time: f64,
+}
+
+impl Audio {
+    fn new() -> Result<Self, JsValue> {
+        let mut options = AudioContextOptions::new();
+        options.sample_rate(SAMPLE_RATE);
+        Ok(Self {
+            context: AudioContext::new_with_context_options(&options)?,
+            next_time: 0.0,
+        })
+    }
+
+    fn play(&mut self, samples: &[StereoSample]) -> Result<(), JsValue> {
+        let now = self.context.current_time();
+        if samples.is_empty() || self.next_time - now > MAX_AUDIO_AHEAD {
+            return Ok(());
+        }
+        let buffer = self
+            .context
+            .create_buffer(2, samples.len() as u32, SAMPLE_RATE)?;
+        let to_f32 = |s: i16| f32::from(s) / 32768.0;
+        let left = samples.iter().map(|s| to_f32(s.l)).collect::<Vec<_>>();
+        let right = samples.iter().map(|s| to_f32(s.r)).collect::<Vec<_>>();
+        buffer.copy_to_channel(&left, 0)?;
+        buffer.copy_to_channel(&right, 1)?;
+        let source = self.context.create_buffer_source()?;
+        source.set_buffer(Some(&buffer));
+        source.connect_with_audio_node(&self.context.destination())?;
+        self.next_time = self.next_time.max(now + AUDIO_LATENCY);
+        source.start_with_when(self.next_time)?;
+        self.next_time += buffer.duration();
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+pub struct Emulator {
+    device: Box<Device<SampleQueue, ArrayFrameBuffer>>,
+    samples: SampleQueue,
+    has_rom: bool,
+    /// The timestamp of the previous call to [`Emulator::run_frame`]
+    last_time: Option<f64>,
+    /// The emulation time in milliseconds which is yet to be emulated
+    pending_time: f64,
+    video: Video,
+    audio: Audio,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    /// Create an emulator drawing into `canvas`
+    #[wasm_bindgen(constructor)]
+    pub fn new(canvas: &HtmlCanvasElement) -> Result<Emulator, JsValue> {
+        let samples = SampleQueue::default();
+        let mut device = Device::new(
+            samples.clone(),
+            ArrayFrameBuffer::new(),
+            Region::Ntsc,
+            false,
+        );
+        device.set_clock_source(Arc::new(BrowserClock));
+        Ok(Self {
+            device: Box::new(device),
+            samples,
+            has_rom: false,
+            last_time: None,
+            pending_time: 0.0,
+            video: Video::new(canvas)?,
+            audio: Audio::new()?,
+        })
+    }
+
+    /// Load the ROM image of a cartridge, the console region is
+    /// chosen by the country code in the cartridge header
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        let cartridge = Cartridge::from_bytes(data).map_err(|err| err.to_string())?;
+        let mut device = Device::new(
+            self.samples.clone(),
+            ArrayFrameBuffer::new(),
+            Region::from_cartridge(&cartridge),
+            false,
+        );
+        device.set_clock_source(Arc::new(BrowserClock));
+        device.load_cartridge(cartridge);
+        *self.device = device;
+        self.samples.0.lock().unwrap().clear();
+        self.has_rom = true;
+        self.last_time = None;
+        self.pending_time = 0.0;
+        Ok(())
+    }
+
+    /// Set the pressed buttons of the standard controller at port 1 or 2
+    pub fn set_buttons(&mut self, port: u32, buttons: u16) {
+        if let Some(port) = (port as usize).checked_sub(1) {
+            self.device
+                .controllers
+                .set_buttons(port, 0, ButtonState(buttons));
+        }
+    }
+
+    /// Resume the audio, browsers only allow this after a user interaction
+    pub fn resume_audio(&self) -> Result<(), JsValue> {
+        self.audio.context.resume().map(drop)
+    }
+
+    /// Emulate the frames due since the previous call, draw the last one and
+    /// schedule their audio.
+    /// `time` is the timestamp in milliseconds passed to `requestAnimationFrame`.
+    pub fn run_frame(&mut self, time: f64) -> Result<(), JsValue> {
+        if !self.has_rom {
+            return Ok(());
+        }
+        let frame_time = 1000.0 / self.device.region().frame_rate();
+        let elapsed = self.last_time.map_or(frame_time, |last| time - last);
+        self.last_time = Some(time);
+        self.pending_time =
+            (self.pending_time + elapsed.max(0.0)).min(f64::from(MAX_FRAMES_PER_CALL) * frame_time);
+        if self.pending_time < frame_time {
+            return Ok(());
+        }
+        while self.pending_time >= frame_time {
+            self.pending_time -= frame_time;
+            self.device.run_cycle::<MASTER_CYCLES_PER_TICK>();
+            while !self.device.new_frame {
+                self.device.run_cycle::<MASTER_CYCLES_PER_TICK>();
+            }
+        }
+        self.video.draw(&self.device.ppu.frame_buffer)?;
+        let samples = core::mem::take(&mut *self.samples.0.lock().unwrap());
+        self.audio.play(&samples)
+    }
+}
@@ -0,0 +1,112 @@
+// The picture with the post-processing filters, the shader is compiled
+// when the emulator starts (see `shaders` in `main.rs`)
+
+struct ScreenInfo {
+    // size of the picture in normalized device coordinates
+    scale: vec2<f32>;
+    output_size: vec2<f32>;
+    source_size: vec2<f32>;
+    filter_mode: u32;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] t_pos: vec2<f32>;
+};
+
+[[group(0), binding(0)]] var tex: texture_2d<f32>;
+[[group(0), binding(2)]] var<uniform> info: ScreenInfo;
+
+let FILTER_BILINEAR: u32 = 1u;
+let FILTER_SCANLINES: u32 = 2u;
+let FILTER_CRT: u32 = 3u;
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    var v_pos = vec2<f32>(1.0, 1.0);
+    if (index == 0u || index > 3u) {
+        v_pos.x = -1.0;
+    }
+    if ((index & 1u) == 0u) {
+        v_pos.y = -1.0;
+    }
+    var out: VertexOutput;
+    // black bars fill the rest of the screen
+    out.position = vec4<f32>(v_pos * info.scale, 0.0, 1.0);
+    out.t_pos = vec2<f32>(v_pos.x + 1.0, 1.0 - v_pos.y) * 0.5;
+    return out;
+}
+
+fn fetch(pos: vec2<i32>) -> vec3<f32> {
+    let size = vec2<i32>(info.source_size);
+    return textureLoad(tex, clamp(pos, vec2<i32>(0, 0), size - vec2<i32>(1, 1)), 0).rgb;
+}
+
+fn nearest(pos: vec2<f32>) -> vec3<f32> {
+    return fetch(vec2<i32>(floor(pos * info.source_size)));
+}
+
+fn bilinear(pos: vec2<f32>) -> vec3<f32> {
+    let texel = pos * info.source_size - vec2<f32>(0.5, 0.5);
+    let base = vec2<i32>(floor(texel));
+    let f = fract(texel);
+    let fx = vec3<f32>(f.x, f.x, f.x);
+    let top = mix(fetch(base), fetch(base + vec2<i32>(1, 0)), fx);
+    let bottom = mix(fetch(base + vec2<i32>(0, 1)), fetch(base + vec2<i32>(1, 1)), fx);
+    return mix(top, bottom, vec3<f32>(f.y, f.y, f.y));
+}
+
+// darken the color towards the border between two scanlines
+fn scanline(color: vec3<f32>, pos: vec2<f32>, strength: f32) -> vec3<f32> {
+    // interlaced pictures have twice the amount of lines
+    var lines = info.source_size.y;
+    if (lines > 240.0) {
+        lines = lines * 0.5;
+    }
+    let dist = abs(fract(pos.y * lines) - 0.5) * 2.0;
+    return color * (1.0 - strength * dist * dist);
+}
+
+fn crt(screen_pos: vec2<f32>, column: i32) -> vec3<f32> {
+    // curve the screen like the glass of a television
+    var centered = screen_pos * 2.0 - vec2<f32>(1.0, 1.0);
+    let curve = 0.04 * dot(centered, centered);
+    centered = centered * (vec2<f32>(1.0, 1.0) + vec2<f32>(0.75, 1.0) * curve);
+    let pos = centered * 0.5 + vec2<f32>(0.5, 0.5);
+    if (any(pos < vec2<f32>(0.0, 0.0)) || any(pos > vec2<f32>(1.0, 1.0))) {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+    // the beam blurs the pixels horizontally
+    let texel = pos * info.source_size;
+    let blurred = bilinear(vec2<f32>(pos.x, (floor(texel.y) + 0.5) / info.source_size.y));
+    var color = mix(nearest(pos), blurred, vec3<f32>(0.6, 0.6, 0.6));
+    color = scanline(color, pos, 0.5);
+    // the shadow mask gives every column of output pixels a tint
+    var mask = vec3<f32>(0.8, 0.8, 0.8);
+    if (column == 0) {
+        mask.x = 1.15;
+    } else if (column == 1) {
+        mask.y = 1.15;
+    } else {
+        mask.z = 1.15;
+    }
+    color = color * mask;
+    // vignette
+    let edge = pos * (vec2<f32>(1.0, 1.0) - pos);
+    return color * clamp(pow(edge.x * edge.y * 16.0, 0.15), 0.0, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_main(input: VertexOutput) -> [[location(0)]] vec4<f32> {
+    var color: vec3<f32>;
+    if (info.filter_mode == FILTER_BILINEAR) {
+        color = bilinear(input.t_pos);
+    } else if (info.filter_mode == FILTER_SCANLINES) {
+        color = scanline(nearest(input.t_pos), input.t_pos, 0.45);
+    } else if (info.filter_mode == FILTER_CRT) {
+        color = crt(input.t_pos, i32(input.position.x) % 3);
+    } else {
+        color = nearest(input.t_pos);
+    }
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,72 @@
+//! Loading of compressed cartridge files
+//!
+//! Files ending with `.zip` are searched for the first file with a known
+//! cartridge file extension. Files ending with `.gz` are decompressed.
+//! All other files are read as they are.
+//!
+//! The extracted file must not be larger than [`MAX_ROM_SIZE`], so that
+//! archives can't exhaust the memory by announcing or unpacking huge files.
+
+use std::{
+    io::{self, Cursor, Read},
+    path::Path,
+};
+
+/// File extensions of cartridge dumps inside of archives
+const ROM_EXTENSIONS: [&str; 4] = ["sfc", "smc", "swc", "fig"];
+
+/// The size of the largest cartridge file: 8 MiB of ROM and a copier header
+const MAX_ROM_SIZE: u64 = 0x80_0000 + 0x200;
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+/// Read the extracted file, whose size is announced as `size`
+fn read_limited(file: impl Read, size: u64) -> io::Result<Vec<u8>> {
+    let mut rom = Vec::with_capacity(size.min(MAX_ROM_SIZE) as usize);
+    file.take(MAX_ROM_SIZE + 1).read_to_end(&mut rom)?;
+    if rom.len() as u64 > MAX_ROM_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the cartridge file in the archive is too large",
+        ));
+    }
+    Ok(rom)
+}
+
+fn read_zip(content: Vec<u8>) -> io::Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(content))?;
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        if file.is_file() && has_extension(Path::new(file.name()), &ROM_EXTENSIONS) {
+            let size = file.size();
+            return read_limited(file, size);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no cartridge file found in the archive",
+    ))
+}
+
+fn read_gzip(content: Vec<u8>) -> io::Result<Vec<u8>> {
+    read_limited(flate2::read::MultiGzDecoder::new(Cursor::new(content)), 0)
+}
+
+/// Read a cartridge file and extract it, if it is an archive
+pub fn read_rom(path: &Path) -> io::Result<Vec<u8>> {
+    let content = std::fs::read(path)?;
+    if has_extension(path, &["zip"]) {
+        read_zip(content)
+    } else if has_extension(path, &["gz"]) {
+        read_gzip(content)
+    } else {
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,37 @@
+use super::*;
+use std::io::Write;
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn zip(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    writer
+        .start_file(name, zip::write::FileOptions::default())
+        .unwrap();
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap().into_inner()
+}
+
+#[test]
+fn extract_archives() {
+    let rom = [0xea; 0x8000];
+    assert_eq!(read_gzip(gzip(&rom)).unwrap(), rom);
+    assert_eq!(read_zip(zip("GAME.SFC", &rom)).unwrap(), rom);
+    let err = read_zip(zip("readme.txt", &rom)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn reject_huge_files() {
+    let rom = vec![0; MAX_ROM_SIZE as usize];
+    assert_eq!(read_gzip(gzip(&rom)).unwrap().len(), rom.len());
+    let rom = vec![0; MAX_ROM_SIZE as usize + 1];
+    let err = read_gzip(gzip(&rom)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = read_zip(zip("game.sfc", &rom)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
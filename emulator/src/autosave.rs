@@ -0,0 +1,165 @@
+//! Writing the battery backed SRAM and the resume state to disk
+//!
+//! The SRAM is written when the emulator exits, when another cartridge
+//! is loaded and periodically while the game runs, but only if it changed
+//! since it was last written. Files are written to a temporary file first
+//! and then renamed, so a crash never leaves a truncated SRAM file behind.
+//!
+//! On unix systems an interrupt (Ctrl+C in the terminal) lets the
+//! emulator exit like closing the window does.
+
+use crate::state_io::{SlotFile, Thumbnail};
+use rsnes::{
+    backend::{ArrayFrameBuffer, AudioBackend, FrameBuffer},
+    device::Device,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// The default interval of periodic SRAM writes in seconds
+pub const DEFAULT_SRAM_FLUSH_INTERVAL: u32 = 60;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst)
+}
+
+/// Catch interrupts, so that [`interrupted`] reports them
+/// instead of the process being terminated right away
+pub fn catch_interrupts() {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// Check if the emulator got interrupted
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Write `data` to a temporary file next to `path` and rename it
+fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)
+}
+
+pub struct SramSaver {
+    path: PathBuf,
+    interval: Option<Duration>,
+    next_flush: Instant,
+    /// The contents of the SRAM file on disk
+    written: Option<Vec<u8>>,
+    verbose: bool,
+}
+
+impl SramSaver {
+    /// Periodically write the SRAM to `path`, an interval of zero disables it
+    pub fn new(path: PathBuf, interval_secs: u32, verbose: bool) -> Self {
+        let interval = Some(Duration::from_secs(interval_secs.into())).filter(|i| !i.is_zero());
+        Self {
+            written: std::fs::read(&path).ok(),
+            path,
+            interval,
+            next_flush: Instant::now() + interval.unwrap_or_default(),
+            verbose,
+        }
+    }
+
+    /// Write the SRAM of the current cartridge and continue with another file
+    pub fn set_path<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        snes: &Device<B, FB>,
+        path: PathBuf,
+    ) {
+        self.flush(snes);
+        self.written = std::fs::read(&path).ok();
+        self.path = path;
+    }
+
+    /// Write the SRAM if it changed since it was written the last time
+    pub fn flush<B: AudioBackend, FB: FrameBuffer>(&mut self, snes: &Device<B, FB>) {
+        let data = match snes.sram_file() {
+            Some(data) if self.written.as_ref() != Some(&data) => data,
+            _ => return,
+        };
+        match write_atomic(&self.path, &data) {
+            Ok(()) => {
+                if self.verbose {
+                    println!("[info] Wrote SRAM file \"{}\"", self.path.display())
+                }
+                self.written = Some(data)
+            }
+            Err(err) => eprintln!(
+                "[warning] Could not write SRAM file \"{}\" ({})",
+                self.path.display(),
+                err
+            ),
+        }
+    }
+
+    /// Write the SRAM if the interval elapsed
+    pub fn tick<B: AudioBackend, FB: FrameBuffer>(&mut self, snes: &Device<B, FB>, now: Instant) {
+        if let Some(interval) = self.interval.filter(|_| now >= self.next_flush) {
+            self.flush(snes);
+            self.next_flush = now + interval
+        }
+    }
+}
+
+/// The path of the state written at exit, which is derived like the
+/// paths of the save state slots
+pub fn resume_path(states: &Path) -> PathBuf {
+    states.with_extension("resume")
+}
+
+/// Write the state to continue from on the next start
+pub fn write_resume_state<B: AudioBackend>(
+    snes: &mut Device<B, ArrayFrameBuffer>,
+    path: &Path,
+    title: &str,
+) {
+    let state = snes.serialize_at_frame_boundary();
+    let thumbnail = Thumbnail::from_frame_buffer(&snes.ppu.frame_buffer);
+    let file = SlotFile::new(title, 0, thumbnail, state);
+    if let Err(err) = write_atomic(path, &file.to_bytes()) {
+        eprintln!(
+            "[warning] Could not write resume state \"{}\" ({})",
+            path.display(),
+            err
+        )
+    }
+}
+
+/// Load the state written at the last exit, if it belongs to the cartridge
+pub fn load_resume_state<B: AudioBackend>(
+    snes: &mut Device<B, ArrayFrameBuffer>,
+    path: &Path,
+    title: &str,
+) -> bool {
+    let file = match std::fs::read(path).map(|content| SlotFile::from_bytes(&content)) {
+        Ok(Ok(file)) if file.rom_title == title => file,
+        _ => return false,
+    };
+    match save_state::deserialize_checked(snes, &file.state) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!(
+                "[warning] Could not load resume state \"{}\" ({})",
+                path.display(),
+                err
+            );
+            false
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Subcommands, which don't open a window
+//!
+//! `rom-info` prints what was detected from the cartridge header and
+//! `verify` runs a game without video and audio output for a number
+//! of frames and compares the hash of the picture, e.g. in CI scripts.
+
+use crate::{cartridge_from_file, config, emulate_frame};
+use rsnes::{
+    backend::{ArrayFrameBuffer, AudioDummy},
+    device::{Device, Region},
+};
+use std::path::PathBuf;
+
+/// Stack size of the thread running the emulation, the device
+/// contains the frame buffer, which may exceed the main stack
+const RUNNER_STACK_SIZE: usize = 0x2000000;
+
+#[derive(clap::Args, Clone)]
+pub struct RomInfoOptions {
+    /// Game cartridge file
+    #[clap(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Apply an IPS or BPS patch to the cartridge file
+    #[clap(long, parse(from_os_str), value_name = "FILE")]
+    patch: Option<PathBuf>,
+
+    /// Also print the raw header information
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+#[derive(clap::Args, Clone)]
+pub struct VerifyOptions {
+    /// Game cartridge file
+    #[clap(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Amount of frames to emulate
+    #[clap(long, value_name = "N")]
+    frames: u32,
+
+    /// The expected hash of the picture after the last frame in hexadecimal.
+    /// Without it the hash is only printed.
+    #[clap(long, value_name = "HASH")]
+    expect_hash: Option<String>,
+
+    /// Override the console region of the cartridge
+    #[clap(short, long, possible_values = ["auto", "ntsc", "pal"])]
+    region: Option<String>,
+
+    /// Apply an IPS or BPS patch to the cartridge file
+    #[clap(long, parse(from_os_str), value_name = "FILE")]
+    patch: Option<PathBuf>,
+
+    /// Count the memory accesses per 8 KiB page and PPU register
+    /// and print them after the hash
+    #[clap(long)]
+    heatmap: bool,
+}
+
+fn size_text(bytes: u32) -> String {
+    if bytes >= 0x100000 && bytes & 0xfffff == 0 {
+        format!("{} MiB", bytes >> 20)
+    } else {
+        format!("{} KiB", bytes >> 10)
+    }
+}
+
+pub fn rom_info(options: &RomInfoOptions) -> ! {
+    let cartridge = cartridge_from_file(&options.input, options.patch.as_deref(), None, false)
+        .unwrap_or_else(|err| error!("{}\n", err));
+    let header = cartridge.header();
+    let region = match cartridge.get_country_frame_rate() {
+        rsnes::cartridge::CountryFrameRate::Pal => "PAL",
+        _ => "NTSC",
+    };
+    let coprocessor = header
+        .coprocessor()
+        .map_or_else(|| "none".to_owned(), |chip| format!("{:?}", chip));
+    println!("Title:       {}", cartridge.title());
+    println!("Mapping:     {:?}", header.rom_type());
+    println!(
+        "Speed:       {}",
+        if header.is_fast() {
+            "FastROM"
+        } else {
+            "SlowROM"
+        }
+    );
+    println!("Coprocessor: {}", coprocessor);
+    println!("ROM size:    {}", size_text(header.rom_size()));
+    println!("SRAM size:   {}", size_text(header.ram_size()));
+    println!("Country:     {} ({})", header.country(), region);
+    println!("Version:     1.{}", header.version());
+    println!("Checksum:    {:04x}", header.checksum());
+    let format = header.image_format();
+    if format.copier_header {
+        println!("Note:        a copier header was stripped");
+    }
+    if format.interleaved {
+        println!("Note:        the ROM was deinterleaved");
+    }
+    if options.verbose {
+        println!("{:#?}", header);
+    }
+    std::process::exit(0)
+}
+
+/// Parse a hash in hexadecimal with an optional `0x` prefix
+fn parse_hash(hash: &str) -> Option<u64> {
+    u64::from_str_radix(hash.trim_start_matches("0x"), 16).ok()
+}
+
+pub fn verify(options: &VerifyOptions) -> ! {
+    let expected = options
+        .expect_hash
+        .as_ref()
+        .map(|hash| parse_hash(hash).unwrap_or_else(|| error!("Invalid hash \"{}\"\n", hash)));
+    let cartridge = cartridge_from_file(&options.input, options.patch.as_deref(), None, false)
+        .unwrap_or_else(|err| error!("{}\n", err));
+    let region = match options.region.as_deref().and_then(config::parse_region) {
+        Some(rsnes::cartridge::CountryFrameRate::Pal) => Region::Pal,
+        Some(rsnes::cartridge::CountryFrameRate::Ntsc) => Region::Ntsc,
+        _ => Region::from_cartridge(&cartridge),
+    };
+    let frames = options.frames;
+    let heatmap = options.heatmap;
+    let (hash, report) = std::thread::Builder::new()
+        .stack_size(RUNNER_STACK_SIZE)
+        .spawn(move || {
+            let mut snes = Device::new(AudioDummy, ArrayFrameBuffer::new(), region, false);
+            snes.load_cartridge(cartridge);
+            if heatmap {
+                snes.enable_heatmap();
+            }
+            for _ in 0..frames {
+                emulate_frame(&mut snes);
+            }
+            let report = snes.take_heatmap().map(|heatmap| heatmap.report());
+            (snes.ppu.frame_buffer.hash(), report)
+        })
+        .unwrap()
+        .join()
+        .unwrap_or_else(|_| std::process::exit(1));
+    println!("{:016x}", hash);
+    if let Some(report) = report {
+        print!("{}", report);
+    }
+    match expected {
+        Some(expected) if expected != hash => {
+            eprintln!("[error] Expected the hash {:016x}", expected);
+            std::process::exit(1)
+        }
+        _ => std::process::exit(0),
+    }
+}
@@ -1,3 +1,4 @@
+use rsnes::controller::DeviceKind;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use toml::value::{Table, Value};
@@ -8,6 +9,11 @@ static CONFIG_FILE_PATHS: &'static [(bool, &'static str)] = &[
     (false, "/etc/rsnes.toml"),
 ];
 
+/// The documented configuration written by `--write-default-config`
+static DEFAULT_CONFIG: &str = include_str!("../example.toml");
+/// Audio latency in milliseconds, if none is configured
+pub const DEFAULT_AUDIO_LATENCY: u32 = 40;
+
 #[derive(Debug)]
 pub enum ConfigLoadError {
     Io(std::io::Error),
@@ -93,11 +99,17 @@ pub struct ControllerProfileStandardScancodes {
 pub enum ControllerProfile {
     Standard {
         scancodes: ControllerProfileStandardScancodes,
+        turbo: rsnes::controller::Turbo,
     },
     Mouse {
         xspeed: f64,
         yspeed: f64,
     },
+    /// The Super Scope is aimed with the mouse
+    SuperScope {
+        xspeed: f64,
+        yspeed: f64,
+    },
 }
 
 impl ControllerProfile {
@@ -111,7 +123,12 @@ impl ControllerProfile {
         )?;
         match ty.as_str() {
             "standard" => Self::load_standard(map),
-            "mouse" => Self::load_mouse(map),
+            "mouse" => {
+                Self::load_speeds(map).map(|(xspeed, yspeed)| Self::Mouse { xspeed, yspeed })
+            }
+            "super-scope" => {
+                Self::load_speeds(map).map(|(xspeed, yspeed)| Self::SuperScope { xspeed, yspeed })
+            }
             _ => Err(ConfigLoadError::UnknownValue {
                 field: "type",
                 value: ty.clone(),
@@ -119,7 +136,8 @@ impl ControllerProfile {
         }
     }
 
-    fn load_mouse(map: &Table) -> Result<Self, ConfigLoadError> {
+    /// Load the movement multipliers of the mouse
+    fn load_speeds(map: &Table) -> Result<(f64, f64), ConfigLoadError> {
         macro_rules! getspeed {
             ($name:literal) => {{
                 map.get($name)
@@ -128,14 +146,11 @@ impl ControllerProfile {
                     .unwrap_or(1.0)
             }};
         }
-        Ok(Self::Mouse {
-            xspeed: getspeed!("xspeed"),
-            yspeed: getspeed!("yspeed"),
-        })
+        Ok((getspeed!("xspeed"), getspeed!("yspeed")))
     }
 
     fn load_standard(map: &Table) -> Result<Self, ConfigLoadError> {
-        if let Some(map) = map.get("scancodes") {
+        let scancodes = if let Some(map) = map.get("scancodes") {
             macro_rules! getreq {
                 ($name:literal) => {{
                     map.get($name)
@@ -143,43 +158,75 @@ impl ControllerProfile {
                         .transpose()?
                 }};
             }
-            Ok(Self::Standard {
-                scancodes: ControllerProfileStandardScancodes {
-                    a: getreq!("A"),
-                    b: getreq!("B"),
-                    x: getreq!("X"),
-                    y: getreq!("Y"),
-                    up: getreq!("Up"),
-                    down: getreq!("Down"),
-                    left: getreq!("Left"),
-                    right: getreq!("Right"),
-                    l: getreq!("L"),
-                    r: getreq!("R"),
-                    start: getreq!("Start"),
-                    select: getreq!("Select"),
-                },
-            })
+            ControllerProfileStandardScancodes {
+                a: getreq!("A"),
+                b: getreq!("B"),
+                x: getreq!("X"),
+                y: getreq!("Y"),
+                up: getreq!("Up"),
+                down: getreq!("Down"),
+                left: getreq!("Left"),
+                right: getreq!("Right"),
+                l: getreq!("L"),
+                r: getreq!("R"),
+                start: getreq!("Start"),
+                select: getreq!("Select"),
+            }
         } else {
-            Ok(Self::default_standard())
+            Self::default_scancodes()
+        };
+        Ok(Self::Standard {
+            scancodes,
+            turbo: Self::load_turbo(map)?,
+        })
+    }
+
+    /// Load the names of the turbo buttons and their period in frames
+    fn load_turbo(map: &Table) -> Result<rsnes::controller::Turbo, ConfigLoadError> {
+        use rsnes::controller::{buttons, Turbo};
+        let period = map
+            .get("turbo-period")
+            .map(|v| getval!(v, Integer))
+            .transpose()?
+            .map_or(Turbo::DEFAULT_PERIOD, |&n| n.clamp(1, 60) as u16);
+        let mut turbo = 0;
+        for name in map
+            .get("turbo")
+            .map(|v| getval!(v, Array))
+            .transpose()?
+            .into_iter()
+            .flatten()
+        {
+            let name = getval!(name, String)?;
+            turbo |= buttons::from_name(name).ok_or_else(|| ConfigLoadError::UnknownValue {
+                field: "turbo",
+                value: name.clone(),
+            })?;
         }
+        Ok(Turbo::new(turbo, period))
     }
 
     fn default_standard() -> Self {
         Self::Standard {
-            scancodes: ControllerProfileStandardScancodes {
-                a: Some(0x24),
-                b: Some(0x25),
-                x: Some(0x26),
-                y: Some(0x27),
-                up: Some(0x11),
-                left: Some(0x1e),
-                down: Some(0x1f),
-                right: Some(0x20),
-                l: Some(0x10),
-                r: Some(0x12),
-                start: Some(0x38),
-                select: Some(0x64),
-            },
+            scancodes: Self::default_scancodes(),
+            turbo: Default::default(),
+        }
+    }
+
+    fn default_scancodes() -> ControllerProfileStandardScancodes {
+        ControllerProfileStandardScancodes {
+            a: Some(0x24),
+            b: Some(0x25),
+            x: Some(0x26),
+            y: Some(0x27),
+            up: Some(0x11),
+            left: Some(0x1e),
+            down: Some(0x1f),
+            right: Some(0x20),
+            l: Some(0x10),
+            r: Some(0x12),
+            start: Some(0x38),
+            select: Some(0x64),
         }
     }
 
@@ -187,7 +234,7 @@ impl ControllerProfile {
         &self,
         scancode: u32,
         is_pressed: bool,
-        controller: &mut rsnes::controller::Controller,
+        buttons: &mut rsnes::controller::ButtonState,
     ) -> bool {
         match self {
             Self::Standard {
@@ -206,6 +253,7 @@ impl ControllerProfile {
                         start,
                         select,
                     },
+                ..
             } => {
                 use rsnes::controller::buttons::*;
                 let mut key = 0;
@@ -233,16 +281,7 @@ impl ControllerProfile {
                 }
                 let handled = key > 0;
                 if handled {
-                    match controller {
-                        rsnes::controller::Controller::Standard(controller) => {
-                            if is_pressed {
-                                controller.pressed_buttons |= key
-                            } else {
-                                controller.pressed_buttons &= !key
-                            }
-                        }
-                        _ => (),
-                    }
+                    buttons.set(key, is_pressed)
                 }
                 handled
             }
@@ -256,10 +295,17 @@ impl ControllerProfile {
         is_pressed: bool,
         controller: &mut rsnes::controller::Controller,
     ) {
+        use winit::event::MouseButton;
         match controller {
             rsnes::controller::Controller::Mouse(mouse) => match button {
-                winit::event::MouseButton::Left => mouse.left_button = is_pressed,
-                winit::event::MouseButton::Right => mouse.right_button = is_pressed,
+                MouseButton::Left => mouse.left_button = is_pressed,
+                MouseButton::Right => mouse.right_button = is_pressed,
+                _ => (),
+            },
+            rsnes::controller::Controller::SuperScope(scope) => match button {
+                MouseButton::Left => scope.fire = is_pressed,
+                MouseButton::Right => scope.cursor = is_pressed,
+                MouseButton::Middle => scope.pause = is_pressed,
                 _ => (),
             },
             _ => (),
@@ -272,22 +318,56 @@ impl ControllerProfile {
         dy: f64,
         controller: &mut rsnes::controller::Controller,
     ) {
-        match self {
-            Self::Mouse { xspeed, yspeed } => match controller {
-                rsnes::controller::Controller::Mouse(mouse) => {
-                    let [dx, dy] = [dx * xspeed, dy * yspeed];
-                    let off =
-                        [dx, dy].map(|v| v.round().clamp(i32::MIN as f64, i32::MAX as f64) as i32);
-                    mouse.add_offset(off)
-                }
-                _ => (),
-            },
+        use rsnes::{
+            controller::Controller,
+            ppu::{MAX_SCREEN_HEIGHT, SCREEN_WIDTH},
+        };
+        match (self, controller) {
+            (Self::Mouse { xspeed, yspeed }, Controller::Mouse(mouse)) => {
+                let [dx, dy] = [dx * xspeed, dy * yspeed];
+                let off =
+                    [dx, dy].map(|v| v.round().clamp(i32::MIN as f64, i32::MAX as f64) as i32);
+                mouse.add_offset(off)
+            }
+            (Self::SuperScope { xspeed, yspeed }, Controller::SuperScope(scope)) => {
+                // the gun starts aiming at the center of the screen
+                let [x, y] = scope
+                    .position
+                    .unwrap_or([SCREEN_WIDTH as u16 / 2, MAX_SCREEN_HEIGHT as u16 / 2]);
+                let move_to = |pos: u16, delta: f64, size: u32| {
+                    (f64::from(pos) + delta)
+                        .round()
+                        .clamp(0.0, f64::from(size - 1)) as u16
+                };
+                scope.position = Some([
+                    move_to(x, dx * xspeed, SCREEN_WIDTH),
+                    move_to(y, dy * yspeed, MAX_SCREEN_HEIGHT),
+                ])
+            }
             _ => (),
         }
     }
 
+    /// The turbo buttons of a standard controller
+    pub fn turbo(&self) -> rsnes::controller::Turbo {
+        match self {
+            Self::Standard { turbo, .. } => *turbo,
+            Self::Mouse { .. } | Self::SuperScope { .. } => Default::default(),
+        }
+    }
+
+    /// The profile is driven by the mouse
     pub fn is_mouse(&self) -> bool {
-        matches!(self, Self::Mouse { .. })
+        matches!(self, Self::Mouse { .. } | Self::SuperScope { .. })
+    }
+
+    /// The device driven by the profile
+    pub fn device(&self) -> DeviceKind {
+        match self {
+            Self::Standard { .. } => DeviceKind::Standard,
+            Self::Mouse { .. } => DeviceKind::Mouse,
+            Self::SuperScope { .. } => DeviceKind::SuperScope,
+        }
     }
 }
 
@@ -297,23 +377,101 @@ impl Default for ControllerProfile {
     }
 }
 
-pub fn controller_profile_to_port(
-    profile: Option<&ControllerProfile>,
-) -> rsnes::controller::ControllerPort {
-    use rsnes::controller::{Controller, ControllerPort, Mouse, StandardController};
-    ControllerPort::new(match profile {
-        None => Controller::None,
-        Some(ControllerProfile::Standard { .. }) => Controller::Standard(StandardController::new()),
-        Some(ControllerProfile::Mouse { .. }) => Controller::Mouse(Mouse::default()),
+/// Choose the device and the controller profile of both controller ports.
+///
+/// The `devices` given on the command line or in the profile take precedence
+/// over the devices the game database `detected`, which take precedence over
+/// the devices of the controller profiles. A mouse or a Super Scope without
+/// a matching controller profile is driven with the default speed. A Multitap
+/// is driven by a standard profile as its first controller.
+pub fn select_devices(
+    profiles: [Option<&ControllerProfile>; 2],
+    devices: [Option<DeviceKind>; 2],
+    detected: Option<[DeviceKind; 2]>,
+) -> [(DeviceKind, Option<ControllerProfile>); 2] {
+    [0, 1].map(|port| {
+        let profile = profiles[port];
+        let device = devices[port]
+            .or(detected.map(|detected| detected[port]))
+            .unwrap_or_else(|| profile.map_or(DeviceKind::None, ControllerProfile::device));
+        let (xspeed, yspeed) = (1.0, 1.0);
+        let profile = match device {
+            _ if profile.is_some_and(|profile| profile.device() == device) => profile.cloned(),
+            DeviceKind::Mouse => Some(ControllerProfile::Mouse { xspeed, yspeed }),
+            DeviceKind::SuperScope => Some(ControllerProfile::SuperScope { xspeed, yspeed }),
+            _ => profile.cloned(),
+        };
+        (device, profile)
     })
 }
 
+/// Parse a device name as used in the configuration and on the command line,
+/// `Some(None)` selects the device automatically
+pub fn parse_device(device: &str) -> Option<Option<DeviceKind>> {
+    match device {
+        "auto" => Some(None),
+        device => DeviceKind::from_name(device).map(Some),
+    }
+}
+
+/// Parse a region name as used in the configuration and on the command line
+pub fn parse_region(region: &str) -> Option<rsnes::cartridge::CountryFrameRate> {
+    match region {
+        "auto" => Some(rsnes::cartridge::CountryFrameRate::Any),
+        "pal" => Some(rsnes::cartridge::CountryFrameRate::Pal),
+        "ntsc" => Some(rsnes::cartridge::CountryFrameRate::Ntsc),
+        _ => None,
+    }
+}
+
+/// Parse the power-on contents of the work RAM as used on the command line:
+/// `zeros`, `pattern` or `random[:SEED]`. A missing seed is taken from the system time.
+pub fn parse_ram_init(ram_init: &str) -> Option<rsnes::device::RamInit> {
+    use rsnes::device::RamInit;
+    match ram_init.split_once(':') {
+        None => match ram_init {
+            "zeros" => Some(RamInit::Zeros),
+            "pattern" => Some(RamInit::Pattern),
+            "random" => Some(RamInit::Random(
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |time| time.as_nanos() as u64),
+            )),
+            _ => None,
+        },
+        Some(("random", seed)) => seed.parse().ok().map(RamInit::Random),
+        Some(_) => None,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Profile {
     pub port1: Option<String>,
     pub port2: Option<String>,
+    /// The devices of the controller ports, `None` selects them automatically
+    pub devices: [Option<DeviceKind>; 2],
     pub region: rsnes::cartridge::CountryFrameRate,
     pub threaded: bool,
+    /// Master cycles the CPU may run ahead of the APU
+    pub apu_sync: u32,
+    pub render_threads: usize,
+    /// Limit the sprites and sprite tiles per scanline like the console
+    pub sprite_limit: bool,
+    /// Skip the cycles of idle loops, a speed hack
+    pub skip_idle_loops: bool,
+    /// Correct the colors like a television
+    pub color_correction: bool,
+    pub interpolation: rsnes::spc700::Interpolation,
+    pub video: crate::video::VideoOptions,
+    pub sync: crate::pacing::SyncMode,
+    /// Audio latency in milliseconds
+    pub audio_latency: u32,
+    pub show_fps: bool,
+    pub show_input: bool,
+    /// Interval of periodic SRAM writes in seconds, zero disables them
+    pub sram_flush_interval: u32,
+    /// Write a save state at exit and continue from it on the next start
+    pub auto_resume: bool,
 }
 
 impl Profile {
@@ -328,16 +486,21 @@ impl Profile {
         }
         let port1 = get_port!("port1");
         let port2 = get_port!("port2");
+        let mut devices = [None; 2];
+        for (device, field) in devices.iter_mut().zip(["port1-device", "port2-device"]) {
+            if let Some(name) = map.get(field) {
+                let name = getval!(name, String)?;
+                *device = parse_device(name).ok_or_else(|| ConfigLoadError::UnknownValue {
+                    field,
+                    value: name.clone(),
+                })?
+            }
+        }
         let region = map
             .get("region")
             .map(|v| getval!(v, String))
             .transpose()?
-            .and_then(|region| match region.as_str() {
-                "auto" => Some(rsnes::cartridge::CountryFrameRate::Any),
-                "pal" => Some(rsnes::cartridge::CountryFrameRate::Pal),
-                "ntsc" => Some(rsnes::cartridge::CountryFrameRate::Ntsc),
-                _ => None,
-            })
+            .and_then(|region| parse_region(region))
             .unwrap_or(rsnes::cartridge::CountryFrameRate::Any);
         let threaded = map
             .get("threaded")
@@ -345,13 +508,138 @@ impl Profile {
             .transpose()?
             .copied()
             .unwrap_or(true);
+        let apu_sync = map
+            .get("apu-sync")
+            .map(|v| getval!(v, Integer))
+            .transpose()?
+            .map_or(rsnes::device::DEFAULT_APU_SYNC_CYCLES, |&n| {
+                n.clamp(1, u32::MAX.into()) as u32
+            });
+        let render_threads = map
+            .get("render-threads")
+            .map(|v| getval!(v, Integer))
+            .transpose()?
+            .map_or(0, |&n| n.max(0) as usize);
+        let sprite_limit = map
+            .get("sprite-limit")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(true);
+        let skip_idle_loops = map
+            .get("skip-idle-loops")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(false);
+        let color_correction = map
+            .get("color-correction")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(false);
+        let interpolation = match map.get("interpolation") {
+            Some(mode) => {
+                let mode = getval!(mode, String)?;
+                rsnes::spc700::Interpolation::from_name(mode).ok_or_else(|| {
+                    ConfigLoadError::UnknownValue {
+                        field: "interpolation",
+                        value: mode.clone(),
+                    }
+                })?
+            }
+            None => Default::default(),
+        };
+        let video = Self::load_video(map)?;
+        let sync = match map.get("sync") {
+            Some(sync) => {
+                let sync = getval!(sync, String)?;
+                crate::pacing::SyncMode::from_name(sync).ok_or_else(|| {
+                    ConfigLoadError::UnknownValue {
+                        field: "sync",
+                        value: sync.clone(),
+                    }
+                })?
+            }
+            None => crate::pacing::SyncMode::Timer,
+        };
+        let audio_latency = map
+            .get("audio-latency")
+            .map(|v| getval!(v, Integer))
+            .transpose()?
+            .map_or(DEFAULT_AUDIO_LATENCY, |&n| n.clamp(0, 1000) as u32);
+        let show_fps = map
+            .get("show-fps")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(false);
+        let show_input = map
+            .get("show-input")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(false);
+        let sram_flush_interval = map
+            .get("sram-flush-interval")
+            .map(|v| getval!(v, Integer))
+            .transpose()?
+            .map_or(crate::autosave::DEFAULT_SRAM_FLUSH_INTERVAL, |&n| {
+                n.clamp(0, u32::MAX.into()) as u32
+            });
+        let auto_resume = map
+            .get("auto-resume")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(false);
         Ok(Self {
             port1,
             port2,
+            devices,
             region,
             threaded,
+            apu_sync,
+            render_threads,
+            sprite_limit,
+            skip_idle_loops,
+            color_correction,
+            interpolation,
+            video,
+            sync,
+            audio_latency,
+            show_fps,
+            show_input,
+            sram_flush_interval,
+            auto_resume,
         })
     }
+
+    fn load_video(map: &Table) -> Result<crate::video::VideoOptions, ConfigLoadError> {
+        use crate::video::{AspectRatio, Filter, VideoOptions};
+        let mut video = VideoOptions::default();
+        if let Some(filter) = map.get("filter") {
+            let filter = getval!(filter, String)?;
+            video.filter =
+                Filter::from_name(filter).ok_or_else(|| ConfigLoadError::UnknownValue {
+                    field: "filter",
+                    value: filter.clone(),
+                })?;
+        }
+        if let Some(aspect_ratio) = map.get("aspect-ratio") {
+            let aspect_ratio = getval!(aspect_ratio, String)?;
+            video.aspect_ratio = AspectRatio::from_name(aspect_ratio).ok_or_else(|| {
+                ConfigLoadError::UnknownValue {
+                    field: "aspect-ratio",
+                    value: aspect_ratio.clone(),
+                }
+            })?;
+        }
+        if let Some(integer_scaling) = map.get("integer-scaling") {
+            video.integer_scaling = *getval!(integer_scaling, Boolean)?;
+        }
+        Ok(video)
+    }
 }
 
 impl Default for Profile {
@@ -359,22 +647,143 @@ impl Default for Profile {
         Self {
             port1: Some(String::from("default")),
             port2: None,
+            devices: [None; 2],
             region: rsnes::cartridge::CountryFrameRate::Any,
             threaded: true,
+            apu_sync: rsnes::device::DEFAULT_APU_SYNC_CYCLES,
+            render_threads: 0,
+            sprite_limit: true,
+            skip_idle_loops: false,
+            color_correction: false,
+            interpolation: Default::default(),
+            video: Default::default(),
+            sync: crate::pacing::SyncMode::Timer,
+            audio_latency: DEFAULT_AUDIO_LATENCY,
+            show_fps: false,
+            show_input: false,
+            sram_flush_interval: crate::autosave::DEFAULT_SRAM_FLUSH_INTERVAL,
+            auto_resume: false,
+        }
+    }
+}
+
+/// Directories for the files created by the emulator
+#[derive(Debug, Clone, Default)]
+pub struct Paths {
+    /// Directory of SRAM files and save states,
+    /// by default they are stored next to the cartridge file
+    pub saves: Option<PathBuf>,
+    /// Directory with a directory per game, see [`crate::library`].
+    /// It takes precedence over `saves`.
+    pub library: Option<PathBuf>,
+}
+
+impl Paths {
+    fn load(map: &Table) -> Result<Self, ConfigLoadError> {
+        let mut paths = Self::default();
+        for (key, val) in map.iter() {
+            match key.as_str() {
+                "saves" => paths.saves = Some(expand_home(getval!(val, String)?)),
+                "library" => paths.library = Some(expand_home(getval!(val, String)?)),
+                _ => return Err(ConfigLoadError::UnknownField(format!("paths.{key}"))),
+            }
+        }
+        Ok(paths)
+    }
+
+    /// The path of the files belonging to a cartridge without the file extension
+    pub fn save_file_base(&self, rom_path: &Path) -> PathBuf {
+        match (&self.saves, rom_path.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _ => rom_path.to_owned(),
         }
     }
 }
 
+/// Settings of a game, which override the selected profile
+#[derive(Debug, Clone, Default)]
+pub struct GameSettings {
+    /// The profile used unless another one is selected on the command line
+    pub profile: Option<String>,
+    pub region: Option<rsnes::cartridge::CountryFrameRate>,
+    pub threaded: Option<bool>,
+    pub port1: Option<String>,
+    pub port2: Option<String>,
+}
+
+impl GameSettings {
+    fn load(map: &Table) -> Result<Self, ConfigLoadError> {
+        let mut settings = Self::default();
+        for (key, val) in map.iter() {
+            match key.as_str() {
+                "profile" => settings.profile = Some(getval!(val, String)?.clone()),
+                "region" => {
+                    let region = getval!(val, String)?;
+                    settings.region =
+                        Some(
+                            parse_region(region).ok_or_else(|| ConfigLoadError::UnknownValue {
+                                field: "region",
+                                value: region.clone(),
+                            })?,
+                        )
+                }
+                "threaded" => settings.threaded = Some(*getval!(val, Boolean)?),
+                "port1" => settings.port1 = Some(getval!(val, String)?.clone()),
+                "port2" => settings.port2 = Some(getval!(val, String)?.clone()),
+                _ => return Err(ConfigLoadError::UnknownField(key.clone())),
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigLoadError> {
+        let map: Table =
+            toml::de::from_str(&std::fs::read_to_string(path)?).map_err(ConfigLoadError::De)?;
+        Self::load(&map)
+    }
+
+    /// Override the settings of `profile` with the settings of the game
+    pub fn apply(&self, config: &Config, mut profile: Profile) -> Result<Profile, ConfigLoadError> {
+        for (port, name) in [
+            (&mut profile.port1, &self.port1),
+            (&mut profile.port2, &self.port2),
+        ] {
+            if let Some(name) = name {
+                if !config.controller_profiles.contains_key(name) {
+                    return Err(ConfigLoadError::UndefinedName {
+                        name: name.clone(),
+                        ty: "controller profile",
+                    });
+                }
+                *port = Some(name.clone())
+            }
+        }
+        profile.region = self.region.unwrap_or(profile.region);
+        profile.threaded = self.threaded.unwrap_or(profile.threaded);
+        Ok(profile)
+    }
+}
+
+/// Replace a leading `~` with the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     default_profile: String,
     profiles: HashMap<String, Profile>,
     controller_profiles: HashMap<String, ControllerProfile>,
+    pub paths: Paths,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            paths: Paths::default(),
             default_profile: String::from("default"),
             profiles: [(String::from("default"), Profile::default())].into(),
             controller_profiles: [(String::from("default"), ControllerProfile::default())].into(),
@@ -422,6 +831,7 @@ impl Config {
         let mut controller_profiles = Default::default();
         let mut profiles = Default::default();
         let mut default_profile = None;
+        let mut paths = Paths::default();
         for (key, val) in main.iter() {
             match key.as_str() {
                 "default-profile" => {
@@ -431,6 +841,7 @@ impl Config {
                 "controller-profiles" => {
                     controller_profiles = Self::load_controller_profiles(getval!(val, Table)?)?
                 }
+                "paths" => paths = Paths::load(getval!(val, Table)?)?,
                 _ => return Err(ConfigLoadError::UnknownField(key.clone())),
             }
         }
@@ -442,6 +853,7 @@ impl Config {
             default_profile,
             profiles,
             controller_profiles,
+            paths,
         };
         slf.validate_names()?;
         Ok(slf)
@@ -467,6 +879,25 @@ impl Config {
         Ok(())
     }
 
+    /// The path the default configuration gets written to
+    pub fn default_config_path() -> Option<PathBuf> {
+        let (_, path) = CONFIG_FILE_PATHS[0];
+        std::env::var_os("HOME").map(|home| Path::new(&home).join(path))
+    }
+
+    /// Write the documented default configuration to a file.
+    /// Existing files are not overwritten.
+    pub fn write_default(path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, DEFAULT_CONFIG.as_bytes()))
+    }
+
     pub fn seek_config_path() -> Option<PathBuf> {
         CONFIG_FILE_PATHS
             .iter()
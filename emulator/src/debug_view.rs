@@ -0,0 +1,336 @@
+//! Windows next to the game, which show the video memory and the sound chip
+//!
+//! Every view has its own window and surface. The views are drawn with the
+//! device, the shader and the bind group layout of the main window a few
+//! times per second, independently of the emulated frames.
+
+use crate::{create_render_pipeline, create_screen_texture, draw_picture, gpu, video};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    ppu::viewer::Image,
+};
+use std::time::{Duration, Instant};
+use winit::{
+    dpi::PhysicalSize,
+    event::WindowEvent,
+    event_loop::EventLoopWindowTarget,
+    window::{Window, WindowBuilder, WindowId},
+};
+
+/// The time between two updates of the views
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The width of the bars of a voice in the APU view
+const VOICE_WIDTH: u32 = 32;
+/// The height of the APU view, the bars are up to 128 pixels high
+const VOICE_HEIGHT: u32 = 128;
+const ENVELOPE_COLOR: [u8; 4] = [0x40, 0xc0, 0x40, 0xff];
+const OUTPUT_COLOR: [u8; 4] = [0x40, 0x80, 0xe0, 0xff];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewKind {
+    /// The VRAM as 4 bits per pixel tiles with the first palette
+    Vram,
+    /// The 256 colors of the CGRAM
+    Palette,
+    /// The envelope and output level of the eight voices of the DSP
+    Apu,
+}
+
+impl ViewKind {
+    pub const NAMES: [&'static str; 3] = ["vram", "palette", "apu"];
+    pub const ALL: [Self; 3] = [Self::Vram, Self::Palette, Self::Apu];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "vram" => Some(Self::Vram),
+            "palette" => Some(Self::Palette),
+            "apu" => Some(Self::Apu),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        Self::NAMES[self as usize]
+    }
+
+    const fn title(self) -> &'static str {
+        match self {
+            Self::Vram => "VRAM",
+            Self::Palette => "Palette",
+            Self::Apu => "APU",
+        }
+    }
+
+    /// The initial size of the window, a multiple of the image size
+    fn window_size(self) -> PhysicalSize<u32> {
+        match self {
+            Self::Vram => PhysicalSize::new(512, 1024),
+            Self::Palette => PhysicalSize::new(256, 256),
+            Self::Apu => PhysicalSize::new(8 * VOICE_WIDTH * 2, VOICE_HEIGHT * 2),
+        }
+    }
+
+    pub fn render<B: AudioBackend, FB: FrameBuffer>(self, snes: &Device<B, FB>) -> Image {
+        match self {
+            Self::Vram => snes.ppu.render_tiles(0, 4, 0, 32),
+            Self::Palette => snes.ppu.render_palette(16),
+            Self::Apu => render_voices(&snes.smp.dsp_registers()),
+        }
+    }
+}
+
+/// Draw a bar of the envelope (`ENVX`) and of the absolute
+/// output (`OUTX`) for every voice of the DSP registers `regs`
+fn render_voices(regs: &[u8; 0x80]) -> Image {
+    let mut image = Image::new(8 * VOICE_WIDTH, VOICE_HEIGHT);
+    for voice in 0..8 {
+        let envelope = u32::from(regs[voice << 4 | 8] & 0x7f);
+        let output = u32::from((regs[voice << 4 | 9] as i8).unsigned_abs());
+        for (i, (level, color)) in [(envelope, ENVELOPE_COLOR), (output, OUTPUT_COLOR)]
+            .into_iter()
+            .enumerate()
+        {
+            let left = voice as u32 * VOICE_WIDTH + 4 + i as u32 * 12;
+            for y in VOICE_HEIGHT - level.min(VOICE_HEIGHT)..VOICE_HEIGHT {
+                for x in left..left + 10 {
+                    image.pixels[(y * image.width + x) as usize] = color
+                }
+            }
+        }
+    }
+    image
+}
+
+/// The GPU resources of the main window, which the views share
+pub struct Renderer {
+    pub instance: wgpu::Instance,
+    pub adapter: wgpu::Adapter,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub pipeline_layout: wgpu::PipelineLayout,
+    pub sampler: wgpu::Sampler,
+    pub shader: wgpu::ShaderModule,
+}
+
+/// The open views and the views, which F10 opens
+pub struct DebugViews {
+    kinds: Vec<ViewKind>,
+    views: Vec<DebugView>,
+    next_update: Instant,
+}
+
+impl DebugViews {
+    /// Open the views named on the command line.
+    /// Without any names, F10 opens all views.
+    pub fn new(
+        names: &[String],
+        event_loop: &EventLoopWindowTarget<()>,
+        renderer: &Renderer,
+    ) -> Self {
+        let mut kinds: Vec<_> = names
+            .iter()
+            .filter_map(|name| ViewKind::from_name(name))
+            .collect();
+        let views = open_views(&kinds, event_loop, renderer);
+        if kinds.is_empty() {
+            kinds = ViewKind::ALL.to_vec()
+        }
+        Self {
+            kinds,
+            views,
+            next_update: Instant::now(),
+        }
+    }
+
+    /// Open the views, or close them if they are open
+    pub fn toggle(&mut self, event_loop: &EventLoopWindowTarget<()>, renderer: &Renderer) {
+        if self.views.is_empty() {
+            self.views = open_views(&self.kinds, event_loop, renderer)
+        } else {
+            self.views.clear()
+        }
+    }
+
+    /// Handle an event of the window of a view
+    pub fn window_event(&mut self, id: WindowId, event: WindowEvent, device: &wgpu::Device) {
+        match event {
+            WindowEvent::CloseRequested => self.views.retain(|view| view.id() != id),
+            WindowEvent::Resized(size) => {
+                if let Some(view) = self.views.iter_mut().find(|view| view.id() == id) {
+                    view.resize(device, size)
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Request a redraw of the views every [`UPDATE_INTERVAL`]
+    pub fn tick(&mut self, now: Instant) {
+        if !self.views.is_empty() && now >= self.next_update {
+            for view in &self.views {
+                view.request_redraw()
+            }
+            self.next_update = now + UPDATE_INTERVAL;
+        }
+    }
+
+    /// Draw the view with the window `id`
+    pub fn redraw<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        id: WindowId,
+        snes: &Device<B, FB>,
+        renderer: &Renderer,
+    ) -> Result<(), String> {
+        match self.views.iter_mut().find(|view| view.id() == id) {
+            Some(view) => {
+                let image = view.kind().render(snes);
+                view.draw(renderer, &image)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Open the views of `kinds`, views which can't be opened are skipped
+fn open_views(
+    kinds: &[ViewKind],
+    event_loop: &EventLoopWindowTarget<()>,
+    renderer: &Renderer,
+) -> Vec<DebugView> {
+    kinds
+        .iter()
+        .filter_map(|&kind| {
+            DebugView::open(kind, event_loop, renderer)
+                .map_err(|err| eprintln!("[error] {}", err))
+                .ok()
+        })
+        .collect()
+}
+
+struct DebugView {
+    kind: ViewKind,
+    window: Window,
+    target: gpu::Target,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    /// The texture of the image, recreated when the image size changes
+    screen: Option<(wgpu::Extent3d, wgpu::Texture, wgpu::BindGroup)>,
+}
+
+impl DebugView {
+    fn open(
+        kind: ViewKind,
+        event_loop: &EventLoopWindowTarget<()>,
+        renderer: &Renderer,
+    ) -> Result<Self, String> {
+        let window = WindowBuilder::new()
+            .with_title(format!("{} - {}", env!("CARGO_PKG_NAME"), kind.title()))
+            .with_inner_size(kind.window_size())
+            .build(event_loop)
+            .map_err(|err| format!("Could not open the {} view ({})", kind.name(), err))?;
+        let surface = unsafe { renderer.instance.create_surface(&window) };
+        if !renderer.adapter.is_surface_supported(&surface) {
+            return Err(format!(
+                "The graphics adapter can't draw the {} view",
+                kind.name()
+            ));
+        }
+        let size = window.inner_size();
+        let target = gpu::Target::window(
+            surface,
+            &renderer.adapter,
+            &renderer.device,
+            size.width,
+            size.height,
+        );
+        let pipeline = create_render_pipeline(
+            &renderer.device,
+            &renderer.pipeline_layout,
+            &renderer.shader,
+            target.format(),
+        );
+        let uniform_buffer = renderer.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: video::UNIFORM_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Ok(Self {
+            kind,
+            window,
+            target,
+            pipeline,
+            uniform_buffer,
+            screen: None,
+        })
+    }
+
+    const fn kind(&self) -> ViewKind {
+        self.kind
+    }
+
+    fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    fn request_redraw(&self) {
+        self.window.request_redraw()
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        self.target.resize(device, size.width, size.height)
+    }
+
+    fn draw(&mut self, renderer: &Renderer, image: &Image) -> Result<(), String> {
+        let extent = wgpu::Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1,
+        };
+        let (_, texture, bind_group) = match &mut self.screen {
+            Some(screen) if screen.0 == extent => screen,
+            screen => {
+                let (texture, bind_group) = create_screen_texture(
+                    &renderer.device,
+                    &renderer.bind_group_layout,
+                    &renderer.sampler,
+                    &self.uniform_buffer,
+                    extent,
+                );
+                screen.insert((extent, texture, bind_group))
+            }
+        };
+        renderer.queue.write_texture(
+            texture.as_image_copy(),
+            image.pixels.as_flattened(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: core::num::NonZeroU32::new(4 * extent.width),
+                rows_per_image: core::num::NonZeroU32::new(extent.height),
+            },
+            extent,
+        );
+        renderer.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            &video::image_uniform_data(self.target.size(), [image.width, image.height]),
+        );
+        let frame = self
+            .target
+            .acquire()
+            .map_err(|err| format!("Could not draw the {} view ({})", self.kind.name(), err))?;
+        draw_picture(
+            &renderer.device,
+            &renderer.queue,
+            &frame.view,
+            &self.pipeline,
+            bind_group,
+        );
+        self.target
+            .finish(&renderer.device, &renderer.queue, frame)
+            .map_err(|err| format!("Could not draw the {} view ({})", self.kind.name(), err))
+    }
+}
@@ -0,0 +1,289 @@
+//! Selection of the graphics backend and the target of the rendered picture
+//!
+//! The picture is presented in the window, if the graphics adapter can draw
+//! into it. Otherwise (or with `--headless`) it is rendered into a texture,
+//! whose frames can be written into a directory as PPM images.
+
+use pollster::FutureExt;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Any backend of the platform, prefers the backend
+    /// given by the `WGPU_BACKEND` environment variable
+    Auto,
+    Vulkan,
+    Metal,
+    Dx12,
+    Dx11,
+    Gl,
+}
+
+impl Backend {
+    pub const NAMES: [&'static str; 6] = ["auto", "vulkan", "metal", "dx12", "dx11", "gl"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Self::Auto),
+            "vulkan" => Some(Self::Vulkan),
+            "metal" => Some(Self::Metal),
+            "dx12" => Some(Self::Dx12),
+            "dx11" => Some(Self::Dx11),
+            "gl" => Some(Self::Gl),
+            _ => None,
+        }
+    }
+
+    pub fn backends(self) -> wgpu::Backends {
+        match self {
+            Self::Auto => wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all),
+            Self::Vulkan => wgpu::Backends::VULKAN,
+            Self::Metal => wgpu::Backends::METAL,
+            Self::Dx12 => wgpu::Backends::DX12,
+            Self::Dx11 => wgpu::Backends::DX11,
+            Self::Gl => wgpu::Backends::GL,
+        }
+    }
+}
+
+/// Find an adapter, which can draw into `surface`. If there is none,
+/// an adapter without a surface is returned for headless rendering.
+pub fn request_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<wgpu::Surface>,
+) -> Option<(wgpu::Adapter, Option<wgpu::Surface>)> {
+    let request = |compatible_surface: Option<&wgpu::Surface>| {
+        instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::LowPower,
+                compatible_surface,
+                force_fallback_adapter: false,
+            })
+            .block_on()
+    };
+    if let Some(surface) = surface {
+        if let Some(adapter) = request(Some(&surface)) {
+            return Some((adapter, Some(surface)));
+        }
+    }
+    request(None).map(|adapter| (adapter, None))
+}
+
+/// The texture format of headless rendering
+const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// A texture to draw the next picture into
+pub struct Frame {
+    surface_texture: Option<wgpu::SurfaceTexture>,
+    pub view: wgpu::TextureView,
+}
+
+pub struct Headless {
+    texture: wgpu::Texture,
+    extent: wgpu::Extent3d,
+    /// The directory the frames are written into
+    /// and the buffer the frames are copied into
+    dump: Option<(PathBuf, wgpu::Buffer)>,
+    frames: u64,
+}
+
+/// The bytes of a row in the buffer of a frame dump, which are aligned for copies
+fn padded_bytes_per_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (4 * width).div_ceil(align) * align
+}
+
+impl Headless {
+    fn new(device: &wgpu::Device, width: u32, height: u32, dump_dir: Option<PathBuf>) -> Self {
+        let extent = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("headless target"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HEADLESS_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let dump = dump_dir.map(|dir| {
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("frame dump"),
+                size: u64::from(padded_bytes_per_row(width) * height),
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            (dir, buffer)
+        });
+        Self {
+            texture,
+            extent,
+            dump,
+            frames: 0,
+        }
+    }
+
+    /// Write the rendered texture as a PPM image, if frames are dumped
+    fn dump(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> std::io::Result<()> {
+        let (dir, buffer) = match &self.dump {
+            Some(dump) => dump,
+            None => return Ok(()),
+        };
+        let bytes_per_row = padded_bytes_per_row(self.extent.width);
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            self.extent,
+        );
+        queue.submit(Some(encoder.finish()));
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        mapping.block_on().map_err(std::io::Error::other)?;
+        let path = dir.join(format!("frame{:06}.ppm", self.frames));
+        let result = write_ppm(&path, &slice.get_mapped_range(), bytes_per_row, self.extent);
+        buffer.unmap();
+        self.frames += 1;
+        result
+    }
+}
+
+/// Write the RGBA rows of `data` as a PPM image
+fn write_ppm(
+    path: &Path,
+    data: &[u8],
+    bytes_per_row: u32,
+    extent: wgpu::Extent3d,
+) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P6\n{} {}\n255\n", extent.width, extent.height)?;
+    for row in data.chunks(bytes_per_row as usize) {
+        for pixel in row[..4 * extent.width as usize].chunks(4) {
+            file.write_all(&pixel[..3])?;
+        }
+    }
+    file.flush()
+}
+
+pub enum Target {
+    Window {
+        surface: wgpu::Surface,
+        config: wgpu::SurfaceConfiguration,
+    },
+    Headless(Headless),
+}
+
+impl Target {
+    pub fn window(
+        surface: wgpu::Surface,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface.get_preferred_format(adapter).unwrap(),
+            width,
+            height,
+            // `Fifo` is always supported and synchronizes to the vertical blank
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        surface.configure(device, &config);
+        Self::Window { surface, config }
+    }
+
+    /// Render into a texture of a fixed size and
+    /// write every frame into `dump_dir`, if given
+    pub fn headless(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        dump_dir: Option<PathBuf>,
+    ) -> Self {
+        Self::Headless(Headless::new(device, width, height, dump_dir))
+    }
+
+    pub const fn is_headless(&self) -> bool {
+        matches!(self, Self::Headless(_))
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        match self {
+            Self::Window { config, .. } => config.format,
+            Self::Headless(_) => HEADLESS_FORMAT,
+        }
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        match self {
+            Self::Window { config, .. } => [config.width, config.height],
+            Self::Headless(headless) => [headless.extent.width, headless.extent.height],
+        }
+    }
+
+    /// Follow the size of the window, the headless texture keeps its size.
+    /// Minimized windows have no area and keep the previous size.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        if let Self::Window { surface, config } = self {
+            config.width = width;
+            config.height = height;
+            surface.configure(device, config);
+        }
+    }
+
+    pub fn acquire(&self) -> Result<Frame, wgpu::SurfaceError> {
+        let view_descriptor = wgpu::TextureViewDescriptor::default();
+        Ok(match self {
+            Self::Window { surface, .. } => {
+                let surface_texture = surface.get_current_texture()?;
+                Frame {
+                    view: surface_texture.texture.create_view(&view_descriptor),
+                    surface_texture: Some(surface_texture),
+                }
+            }
+            Self::Headless(headless) => Frame {
+                view: headless.texture.create_view(&view_descriptor),
+                surface_texture: None,
+            },
+        })
+    }
+
+    /// Present the drawn frame in the window or dump it
+    pub fn finish(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frame: Frame,
+    ) -> std::io::Result<()> {
+        match self {
+            Self::Window { .. } => {
+                if let Some(surface_texture) = frame.surface_texture {
+                    surface_texture.present()
+                }
+                Ok(())
+            }
+            Self::Headless(headless) => headless.dump(device, queue),
+        }
+    }
+}
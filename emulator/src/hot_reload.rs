@@ -0,0 +1,134 @@
+//! Reloading of the configuration and the shaders, while the emulator runs
+//!
+//! The modification times of the watched files are polled, which needs no
+//! file system notifications of the platform. Editors often replace a file
+//! in several steps, so a file, which fails to load, is loaded again after
+//! its next change.
+
+use crate::{
+    config::{self, ControllerProfile, GameSettings},
+    video::VideoOptions,
+};
+use rsnes::controller::DeviceKind;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The time between two checks of the modification time of a file
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The settings, which are taken from a reloaded configuration file
+pub struct ReloadedConfig {
+    pub video: VideoOptions,
+    /// The key bindings of both controller ports
+    pub controllers: [Option<ControllerProfile>; 2],
+}
+
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    next_check: Instant,
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+impl FileWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            modified: modification_time(&path),
+            path,
+            next_check: Instant::now() + POLL_INTERVAL,
+        }
+    }
+
+    /// Check if the file changed since the last call.
+    /// The file system is accessed at most every [`POLL_INTERVAL`].
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if now < self.next_check {
+            return false;
+        }
+        self.next_check = now + POLL_INTERVAL;
+        let modified = modification_time(&self.path);
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Load the configuration file again after it changed and select the
+    /// profile, which is named on the command line or by the game settings.
+    /// The connected `devices` stay the same, only their key bindings change.
+    pub fn reload_config(
+        &mut self,
+        now: Instant,
+        profile: Option<&str>,
+        game_settings: &GameSettings,
+        devices: [DeviceKind; 2],
+    ) -> Option<Result<ReloadedConfig, String>> {
+        if !self.poll(now) {
+            return None;
+        }
+        Some(
+            config::Config::load_from_file(&self.path)
+                .map_err(|err| format!("config: {err}"))
+                .and_then(|config| {
+                    let profile = crate::select_profile(&config, profile, game_settings)?;
+                    let [(_, port1), (_, port2)] = config::select_devices(
+                        config.get_controller_profiles(&profile),
+                        devices.map(Some),
+                        None,
+                    );
+                    Ok(ReloadedConfig {
+                        video: profile.video,
+                        controllers: [port1, port2],
+                    })
+                }),
+        )
+    }
+
+    /// Load the shader again after it changed and create the render
+    /// pipeline of the main window with it
+    #[cfg(feature = "dev-tools")]
+    pub fn reload_shader(
+        &mut self,
+        now: Instant,
+        renderer: &crate::debug_view::Renderer,
+        format: wgpu::TextureFormat,
+    ) -> Option<Result<wgpu::RenderPipeline, String>> {
+        if !self.poll(now) {
+            return None;
+        }
+        Some(load_wgsl(&self.path).map(|source| {
+            let shader = crate::shaders::create_wgsl(&renderer.device, &source);
+            crate::create_render_pipeline(
+                &renderer.device,
+                &renderer.pipeline_layout,
+                &shader,
+                format,
+            )
+        }))
+    }
+}
+
+/// Read a WGSL shader and check it, so that an invalid
+/// shader doesn't abort the emulator when it is compiled
+#[cfg(feature = "dev-tools")]
+pub fn load_wgsl(path: &Path) -> Result<String, String> {
+    use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+    let source = std::fs::read_to_string(path)
+        .map_err(|err| format!("Could not read shader \"{}\" ({})", path.display(), err))?;
+    let module =
+        naga::front::wgsl::parse_str(&source).map_err(|err| err.emit_to_string(&source))?;
+    Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .map_err(|err| format!("Invalid shader \"{}\" ({:?})", path.display(), err))?;
+    Ok(source)
+}
@@ -0,0 +1,161 @@
+//! The buttons held on the local input devices
+//!
+//! Key presses only change the held buttons. They reach the emulated
+//! controllers at the start of a frame after the turbo is applied,
+//! so that the turbo buttons toggle in step with the emulated frames.
+//! The keys, which aren't bound to a controller, are translated into
+//! [`Hotkey`]s of the emulator.
+
+use rsnes::controller::{buttons, ButtonState, ControllerPorts, InputFrame, Turbo};
+
+#[derive(Debug, Clone, Default)]
+pub struct LocalInput {
+    held: [ButtonState; 2],
+    turbo: [Turbo; 2],
+    /// The inputs of the last emulated frame
+    frame: InputFrame,
+}
+
+impl LocalInput {
+    pub fn new(turbo: [Turbo; 2]) -> Self {
+        Self {
+            held: Default::default(),
+            turbo,
+            frame: Default::default(),
+        }
+    }
+
+    /// The held buttons of the controller port `port`
+    pub fn held_mut(&mut self, port: usize) -> &mut ButtonState {
+        &mut self.held[port]
+    }
+
+    /// Toggle the turbo of the held buttons of the controller port `port`
+    /// and return the message for the overlay
+    pub fn toggle_turbo(&mut self, port: usize) -> String {
+        let turbo = self.turbo[port].toggle(self.held[port].0);
+        let names: Vec<_> = buttons::NAMES
+            .iter()
+            .filter(|(button, _)| turbo & button > 0)
+            .map(|(_, name)| *name)
+            .collect();
+        if names.is_empty() {
+            "Turbo off".to_owned()
+        } else {
+            format!("Turbo {}", names.join(", "))
+        }
+    }
+
+    /// Pass the buttons to the emulated controllers before a frame
+    pub fn before_frame(&self, ports: &mut ControllerPorts) {
+        for (port, (held, turbo)) in self.held.iter().zip(&self.turbo).enumerate() {
+            ports.set_buttons(port, 0, turbo.apply(*held));
+        }
+    }
+
+    /// Remember the inputs of the frame after movies, scripts and
+    /// netplay have changed them
+    pub fn capture(&mut self, ports: &ControllerPorts) {
+        self.frame = ports.input_frame(self.held)
+    }
+
+    pub const fn input_frame(&self) -> &InputFrame {
+        &self.frame
+    }
+
+    pub fn after_frame(&mut self) {
+        for turbo in &mut self.turbo {
+            turbo.next_frame()
+        }
+    }
+}
+
+/// An action of the emulator, which is triggered by the keyboard
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hotkey {
+    /// F1: toggle the frame rate display
+    ToggleStats,
+    /// F2: select the next filter
+    NextFilter,
+    /// F3: toggle the aspect ratio
+    ToggleAspectRatio,
+    /// F4: toggle the integer scaling
+    ToggleIntegerScaling,
+    /// F5: reset, Shift+F5: power cycle
+    Reset { power_cycle: bool },
+    /// F6: start or stop recording
+    ToggleRecording,
+    /// F7: toggle the turbo of the held buttons
+    ToggleTurbo,
+    /// F8: toggle the input display
+    ToggleInput,
+    /// F9: take a screenshot
+    Screenshot,
+    /// F10: open or close the debug views
+    ToggleDebugViews,
+    /// F11: toggle the borderless fullscreen
+    ToggleFullscreen,
+    /// P: pause or resume the emulation
+    Pause,
+    /// N: emulate a single frame while paused
+    NextFrame,
+    /// Tab: fast-forward, `: slow motion, with the speed to set
+    Speed(f32),
+    /// 1..0: store a save state
+    StoreState(usize),
+    /// Shift+1..0: load a save state
+    LoadState(usize),
+}
+
+/// Translates the keys into hotkeys and keeps track of the shift keys
+#[derive(Debug, Clone, Default)]
+pub struct Hotkeys {
+    shift: [bool; 2],
+}
+
+impl Hotkeys {
+    /// Handle a key press or release, which isn't bound to a controller
+    pub fn handle_scancode(&mut self, scancode: u32, pressed: bool) -> Option<Hotkey> {
+        let shift = self.shift[0] || self.shift[1];
+        Some(match scancode {
+            0x2a => return self.set_shift(0, pressed),
+            0x36 => return self.set_shift(1, pressed),
+            0x0f | 0x29 => Hotkey::Speed(match (scancode, pressed, shift) {
+                (_, false, _) => 1.0,
+                (0x0f, true, false) => 2.0,
+                (0x0f, true, true) => 4.0,
+                (_, true, false) => 0.5,
+                (_, true, true) => 0.25,
+            }),
+            _ if !pressed => return None,
+            0x3b => Hotkey::ToggleStats,
+            0x3c => Hotkey::NextFilter,
+            0x3d => Hotkey::ToggleAspectRatio,
+            0x3e => Hotkey::ToggleIntegerScaling,
+            0x3f => Hotkey::Reset { power_cycle: shift },
+            0x40 => Hotkey::ToggleRecording,
+            0x41 => Hotkey::ToggleTurbo,
+            0x42 => Hotkey::ToggleInput,
+            0x43 => Hotkey::Screenshot,
+            0x44 => Hotkey::ToggleDebugViews,
+            0x57 => Hotkey::ToggleFullscreen,
+            0x19 => Hotkey::Pause,
+            0x31 => Hotkey::NextFrame,
+            2..=11 => {
+                // the digit row starts with 1 and ends with 0
+                let id = (scancode - 1) as usize % 10;
+                if shift {
+                    Hotkey::LoadState(id)
+                } else {
+                    Hotkey::StoreState(id)
+                }
+            }
+            _ => return None,
+        })
+    }
+
+    fn set_shift(&mut self, key: usize, pressed: bool) -> Option<Hotkey> {
+        self.shift[key] = pressed;
+        None
+    }
+}
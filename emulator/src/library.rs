@@ -0,0 +1,152 @@
+//! The locations of the files belonging to a game
+//!
+//! Without a library directory in the configuration, the files are
+//! stored next to the cartridge file (or in the directory for saves)
+//! and named after it, e.g. `game.srm` and `game.ss0` for `game.sfc`.
+//!
+//! With a library directory, every game gets a directory named after
+//! its [`GameId`], which is independent of the name of the cartridge file:
+//!
+//! ```text
+//! <library>/<title>-<checksum>/
+//!     save.srm           battery backed SRAM
+//!     settings.toml      settings overriding the profile
+//!     states/            save state slots `state.ss0` - `state.ss9`
+//!     screenshots/       screenshots as PPM images
+//!     recordings/        recordings started by F6
+//! ```
+//!
+//! The settings may select the `profile`, the `region` (`"auto"`, `"pal"`
+//! or `"ntsc"`), `threaded` mode and the controller profiles of `port1`
+//! and `port2`. Options on the command line take precedence.
+
+use crate::config::{GameSettings, Paths};
+use rsnes::cartridge::Cartridge;
+use std::path::{Path, PathBuf};
+
+/// The name of the per-game settings file in the directory of a game
+pub const SETTINGS_NAME: &str = "settings.toml";
+
+/// Identifies a game independent of the name of its cartridge file
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GameId {
+    pub title: String,
+    /// The checksum in the cartridge header
+    pub checksum: u16,
+}
+
+impl GameId {
+    pub fn from_cartridge(cartridge: &Cartridge) -> Self {
+        Self {
+            title: cartridge.title().to_owned(),
+            checksum: cartridge.header().checksum(),
+        }
+    }
+
+    /// The name of the directory of the game in the library.
+    /// Characters, which may be invalid in file names, are replaced by `_`.
+    pub fn dir_name(&self) -> String {
+        let title: String = self
+            .title
+            .trim()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | ' ' | '-' | '_' | '.' | '!' | '&' => c,
+                _ => '_',
+            })
+            .collect();
+        let title = title.trim_start_matches('.');
+        let title = if title.is_empty() { "untitled" } else { title };
+        format!("{}-{:04x}", title, self.checksum)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum GamePaths {
+    /// The files are named after the cartridge file, `base` has no extension
+    Flat { base: PathBuf },
+    /// The files are stored in the directory of the game in the library
+    Library { dir: PathBuf },
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+impl GamePaths {
+    pub fn new(paths: &Paths, rom_path: &Path, id: &GameId) -> Self {
+        match &paths.library {
+            Some(library) => Self::Library {
+                dir: library.join(id.dir_name()),
+            },
+            None => Self::Flat {
+                base: paths.save_file_base(rom_path),
+            },
+        }
+    }
+
+    /// Create the directories of the game in the library
+    pub fn create_dirs(&self) -> std::io::Result<()> {
+        match self {
+            Self::Flat { .. } => Ok(()),
+            Self::Library { dir } => ["states", "screenshots", "recordings"]
+                .into_iter()
+                .try_for_each(|sub| std::fs::create_dir_all(dir.join(sub))),
+        }
+    }
+
+    pub fn sram(&self) -> PathBuf {
+        match self {
+            Self::Flat { base } => base.with_extension("srm"),
+            Self::Library { dir } => dir.join("save.srm"),
+        }
+    }
+
+    /// The path the save state slots are derived from, see [`crate::state_io::slot_path`]
+    pub fn states(&self) -> PathBuf {
+        match self {
+            Self::Flat { base } => base.clone(),
+            Self::Library { dir } => dir.join("states").join("state"),
+        }
+    }
+
+    /// A new path for a recording
+    pub fn recording(&self) -> PathBuf {
+        match self {
+            Self::Flat { base } => base.with_extension(format!("recording-{}", unix_time())),
+            Self::Library { dir } => dir.join("recordings").join(unix_time().to_string()),
+        }
+    }
+
+    /// A new path for a screenshot
+    pub fn screenshot(&self) -> PathBuf {
+        match self {
+            Self::Flat { base } => base.with_extension(format!("screenshot-{}.ppm", unix_time())),
+            Self::Library { dir } => dir.join("screenshots").join(format!("{}.ppm", unix_time())),
+        }
+    }
+
+    /// The per-game settings file, which only exists in the library
+    pub fn settings(&self) -> Option<PathBuf> {
+        match self {
+            Self::Flat { .. } => None,
+            Self::Library { dir } => Some(dir.join(SETTINGS_NAME)),
+        }
+    }
+
+    /// Load the per-game settings, a missing file contains no settings
+    pub fn load_settings(&self, verbose: bool) -> Result<GameSettings, String> {
+        match self.settings() {
+            Some(path) if path.is_file() => {
+                if verbose {
+                    println!("[info] Loading game settings \"{}\"", path.display());
+                }
+                GameSettings::load_from_file(&path)
+                    .map_err(|err| format!("{}: {}", path.display(), err))
+            }
+            _ => Ok(GameSettings::default()),
+        }
+    }
+}
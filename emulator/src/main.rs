@@ -1,33 +1,100 @@
+#[cfg(feature = "rom-archive")]
+mod archive;
 mod config;
 
-use clap::{ErrorKind, Parser};
+use clap::{Args, Parser, Subcommand};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
     Sample,
 };
+use input::Hotkey;
 use pollster::FutureExt;
-use rsnes::{backend::ArrayFrameBuffer, device::Device, spc700::StereoSample};
-use save_state::InSaveState;
+use rsnes::{
+    backend::ArrayFrameBuffer,
+    device::{Device, DeviceOptions, RamInit, Region},
+    spc700::{Interpolation, StereoSample},
+};
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 use winit::{
     event::{DeviceEvent, ElementState, Event, KeyboardInput, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Fullscreen, WindowBuilder},
 };
 
 const MASTER_CYCLES_PER_TICK: u16 = 2;
+/// The values of `--port1-device` and `--port2-device`
+const DEVICE_NAMES: &[&str] = &[
+    "auto",
+    "none",
+    "standard",
+    "mouse",
+    "super-scope",
+    "multitap",
+];
+/// The values of `--interpolation`
+const INTERPOLATION_NAMES: &[&str] = &["gaussian", "cubic"];
+/// The values of `--mapper`
+const MAPPER_NAMES: &[&str] = &["lorom", "hirom"];
 
 #[derive(Parser, Clone)]
 #[clap(
     version = clap::crate_version!(),
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
 )]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    options: Options,
+}
+
+// the options are only parsed once, so their size doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Clone)]
+enum Command {
+    /// Run a game cartridge in a window (the default without a subcommand)
+    Run(Options),
+    /// Print the information detected from the cartridge header
+    RomInfo(commands::RomInfoOptions),
+    /// Emulate frames without video and audio output and check the hash of the picture
+    Verify(commands::VerifyOptions),
+    /// Play a SPC music file without emulating the rest of the console
+    PlaySpc {
+        #[clap(parse(from_os_str))]
+        file: PathBuf,
+
+        /// Print the ID666 tag of the file
+        #[clap(short, long)]
+        verbose: bool,
+
+        /// The interpolation between the samples [default: gaussian]
+        #[clap(long, possible_values = INTERPOLATION_NAMES)]
+        interpolation: Option<String>,
+    },
+}
+
+#[derive(Args, Clone)]
 struct Options {
-    /// Game cartridge file to load (e.g. *.sfc and *.smc files)
-    #[clap(parse(from_os_str))]
-    input: PathBuf,
+    /// Game cartridge file to load (e.g. *.sfc and *.smc files,
+    /// or *.zip and *.gz files with the `rom-archive` feature)
+    #[clap(
+        parse(from_os_str),
+        required_unless_present_any = &["write-default-config", "recent", "list-recent"]
+    )]
+    input: Option<PathBuf>,
+
+    /// Load the n-th most recently played cartridge file (1 is the last one)
+    #[clap(long, value_name = "N", conflicts_with = "input")]
+    recent: Option<usize>,
+
+    /// Print the recently played cartridge files and exit
+    #[clap(long, exclusive = true)]
+    list_recent: bool,
 
     /// Print extra information that may spam your stdout
     #[clap(short, long)]
@@ -40,38 +107,325 @@ struct Options {
     /// Use a specified profile of your configuration
     #[clap(short, long)]
     profile: Option<String>,
+
+    /// Override the console region of the selected profile
+    #[clap(short, long, possible_values = ["auto", "ntsc", "pal"])]
+    region: Option<String>,
+
+    /// Contents of the work RAM at power-on:
+    /// `zeros`, `pattern` (0x55/0xAA) or `random[:SEED]` [default: zeros]
+    #[clap(long, value_name = "INIT")]
+    ram_init: Option<String>,
+
+    /// Master cycles the CPU may run ahead of the audio processor [default: 1364]
+    #[clap(long, value_name = "CYCLES")]
+    apu_sync: Option<u32>,
+
+    /// Device at controller port 1, `auto` uses the device the game
+    /// database knows for the game or the device of the profile
+    #[clap(long, value_name = "DEVICE", possible_values = DEVICE_NAMES)]
+    port1_device: Option<String>,
+
+    /// Device at controller port 2 (see `--port1-device`)
+    #[clap(long, value_name = "DEVICE", possible_values = DEVICE_NAMES)]
+    port2_device: Option<String>,
+
+    /// Apply an IPS or BPS patch to the cartridge file.
+    /// By default `game.bps` or `game.ips` next to `game.sfc` is applied.
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        requires = "input",
+        conflicts_with_all = &["sufami-a", "sufami-b"]
+    )]
+    patch: Option<PathBuf>,
+
+    /// Insert a flash memory pack into the BS-X Satellaview cartridge
+    #[clap(long, parse(from_os_str), value_name = "FILE", requires = "input")]
+    memory_pack: Option<PathBuf>,
+
+    /// Insert a mini-cartridge into slot A of the Sufami Turbo adapter.
+    /// The cartridge file is the BIOS of the adapter in this case.
+    #[clap(long, parse(from_os_str), value_name = "FILE", requires = "input")]
+    sufami_a: Option<PathBuf>,
+
+    /// Insert a mini-cartridge into slot B of the Sufami Turbo adapter
+    #[clap(long, parse(from_os_str), value_name = "FILE", requires = "input")]
+    sufami_b: Option<PathBuf>,
+
+    /// Load the cartridge file as a raw binary without a header, e.g. homebrew
+    /// during development. Problems of the binary are only printed as warnings.
+    #[clap(
+        long,
+        value_name = "MAPPING",
+        possible_values = MAPPER_NAMES,
+        requires = "input",
+        conflicts_with_all = &["sufami-a", "sufami-b"]
+    )]
+    mapper: Option<String>,
+
+    /// The reset vector of a raw binary loaded with `--mapper` [default: 0x8000]
+    #[clap(long, value_name = "ADDR", requires = "mapper", parse(try_from_str = parse_address))]
+    reset: Option<u16>,
+
+    /// Apply the Game Genie and Pro Action Replay codes of a file.
+    /// Every line starts with a code, the rest of the line is ignored.
+    #[clap(long, parse(from_os_str), value_name = "FILE", requires = "input")]
+    cheats: Option<PathBuf>,
+
+    /// Run a Lua script with hooks for frames and memory accesses
+    #[cfg(feature = "scripting")]
+    #[clap(long, parse(from_os_str), value_name = "FILE", requires = "input")]
+    script: Option<PathBuf>,
+
+    /// Select the clock source of the emulation speed [default: timer]
+    #[clap(long, possible_values = pacing::SyncMode::NAMES)]
+    sync: Option<String>,
+
+    /// Audio latency in milliseconds [default: 40]
+    #[clap(long, value_name = "MS")]
+    audio_latency: Option<u32>,
+
+    /// Draw all sprites of a scanline instead of the first 32 sprites and 34 tiles
+    #[clap(long)]
+    no_sprite_limit: bool,
+
+    /// Skip ahead to the next interrupt while a game waits for it in a loop.
+    /// This speed hack is ignored with movies and netplay.
+    #[clap(long)]
+    skip_idle_loops: bool,
+
+    /// Correct the oversaturated colors like a television
+    #[clap(long)]
+    color_correction: bool,
+
+    /// The interpolation between the audio samples. Other modes than the one
+    /// of the console are ignored with movies and netplay. [default: gaussian]
+    #[clap(long, possible_values = INTERPOLATION_NAMES)]
+    interpolation: Option<String>,
+
+    /// The graphics API used for drawing [default: auto]
+    #[clap(long, possible_values = gpu::Backend::NAMES)]
+    backend: Option<String>,
+
+    /// Draw the picture into a texture instead of the window,
+    /// which is also done if the window can't be drawn into
+    #[clap(long)]
+    headless: bool,
+
+    /// Render headless and write every drawn picture
+    /// into the directory as a PPM image
+    #[clap(long, parse(from_os_str), value_name = "DIR")]
+    dump_frames: Option<PathBuf>,
+
+    /// Draw the picture with a WGSL shader file instead of the built-in shader
+    /// and reload it when it changes. The shader needs the entry points
+    /// `vs_main` and `fs_main` and the bindings of `shaders/main.wgsl`.
+    #[cfg(feature = "dev-tools")]
+    #[clap(long, parse(from_os_str), value_name = "FILE")]
+    shader: Option<PathBuf>,
+
+    /// Open a window next to the game, which shows the VRAM, the palette
+    /// or the voices of the sound chip. F10 opens the given views
+    /// (by default all views) or closes the open views.
+    #[clap(long, possible_values = debug_view::ViewKind::NAMES)]
+    debug_view: Vec<String>,
+
+    /// Start in borderless fullscreen (toggle with F11)
+    #[clap(long)]
+    fullscreen: bool,
+
+    /// Show the frame rate and emulation speed (toggle with F1)
+    #[clap(long)]
+    show_fps: bool,
+
+    /// Show the pressed buttons of the controllers (toggle with F8)
+    #[clap(long)]
+    show_input: bool,
+
+    /// Record the inputs from power-on into a movie file
+    #[clap(long, parse(from_os_str), value_name = "FILE")]
+    record_movie: Option<PathBuf>,
+
+    /// Play back the inputs of a movie file
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        conflicts_with = "record-movie"
+    )]
+    play_movie: Option<PathBuf>,
+
+    /// Record the picture and sound from the start (toggle with F6).
+    /// Files with the extension of a video container are encoded with ffmpeg,
+    /// otherwise a directory with PPM images and a WAV file is written.
+    #[clap(long, parse(from_os_str), value_name = "PATH")]
+    record: Option<PathBuf>,
+
+    /// Write the documented default configuration to a file
+    /// (by default `~/.config/rsnes/config.toml`) and exit
+    #[clap(long, value_name = "FILE", exclusive = true)]
+    write_default_config: Option<Option<String>>,
+
+    #[cfg(feature = "netplay")]
+    #[clap(flatten)]
+    netplay: netplay::NetplayOptions,
 }
 
 macro_rules! error {
     ($($arg:tt)*) => {
-        clap::command!().error(ErrorKind::Io, format_args!($($arg)*)).exit()
+        clap::command!().error(clap::ErrorKind::Io, format_args!($($arg)*)).exit()
     };
 }
 
-fn cartridge_from_file(path: &std::path::Path) -> rsnes::cartridge::Cartridge {
-    let content = std::fs::read(path)
-        .unwrap_or_else(|err| error!("Could not read file \"{}\" ({})\n", path.display(), err));
-    rsnes::cartridge::Cartridge::from_bytes(&content).unwrap_or_else(|err| {
-        error!(
-            "Failure while reading cartridge file \"{}\" ({})\n",
+mod autosave;
+mod commands;
+mod debug_view;
+mod gpu;
+mod hot_reload;
+mod input;
+mod library;
+mod movie;
+#[cfg(feature = "netplay")]
+mod netplay;
+mod overlay;
+mod pacing;
+mod player;
+mod recent;
+mod recording;
+#[cfg(feature = "scripting")]
+mod script;
+mod state_io;
+mod video;
+
+/// Parse a 16-bit address in hexadecimal with an optional `0x` or `$` prefix
+fn parse_address(addr: &str) -> Result<u16, std::num::ParseIntError> {
+    let digits = addr
+        .strip_prefix("0x")
+        .or_else(|| addr.strip_prefix('$'))
+        .unwrap_or(addr);
+    u16::from_str_radix(digits, 16)
+}
+
+fn read_rom_file(path: &std::path::Path) -> Result<Vec<u8>, String> {
+    #[cfg(feature = "rom-archive")]
+    let content = archive::read_rom(path);
+    #[cfg(not(feature = "rom-archive"))]
+    let content = std::fs::read(path);
+    content.map_err(|err| format!("Could not read file \"{}\" ({})", path.display(), err))
+}
+
+/// Apply the given patch or a patch next to the cartridge file
+fn patch_rom(
+    content: Vec<u8>,
+    path: &std::path::Path,
+    patch: Option<&std::path::Path>,
+    verbose: bool,
+) -> Result<Vec<u8>, String> {
+    use rsnes::cartridge::patch::{self, PatchFormat};
+    let patch_path = patch.map(PathBuf::from).or_else(|| {
+        PatchFormat::EXTENSIONS
+            .iter()
+            .map(|ext| path.with_extension(ext))
+            .find(|path| path.is_file())
+    });
+    let patch_path = match patch_path {
+        Some(patch_path) => patch_path,
+        None => return Ok(content),
+    };
+    let patch_content = std::fs::read(&patch_path)
+        .map_err(|err| format!("Could not read file \"{}\" ({})", patch_path.display(), err))?;
+    let content = patch::apply(&content, &patch_content).map_err(|err| {
+        format!(
+            "Could not apply patch \"{}\" ({})",
+            patch_path.display(),
+            err
+        )
+    })?;
+    if verbose {
+        println!("[info] Applied patch \"{}\"", patch_path.display());
+    }
+    Ok(content)
+}
+
+/// Load a cartridge file, or a raw binary with the given mapping and reset vector
+fn cartridge_from_file(
+    path: &std::path::Path,
+    patch: Option<&std::path::Path>,
+    raw: Option<(rsnes::cartridge::RomType, u16)>,
+    verbose: bool,
+) -> Result<rsnes::cartridge::Cartridge, String> {
+    let content = patch_rom(read_rom_file(path)?, path, patch, verbose)?;
+    match raw {
+        Some((rom_type, reset_vector)) => {
+            rsnes::cartridge::Cartridge::from_raw(&content, rom_type, reset_vector)
+        }
+        None => rsnes::cartridge::Cartridge::from_bytes(&content),
+    }
+    .map_err(|err| {
+        format!(
+            "Failure while reading cartridge file \"{}\" ({})",
             path.display(),
             err
         )
     })
 }
 
+/// Load the SRAM file and attach the MSU-1 data of a cartridge
+fn attach_cartridge_files(
+    cartridge: &mut rsnes::cartridge::Cartridge,
+    rom_path: &std::path::Path,
+    sram_path: &std::path::Path,
+    verbose: bool,
+) {
+    if let Ok(content) = std::fs::read(sram_path) {
+        if verbose {
+            println!("[info] Loading SRAM file \"{}\"", sram_path.display());
+        }
+        cartridge.load_sram_file(&content);
+    }
+    let media = rsnes::backend::FileMedia::new(rom_path);
+    if media.is_present() {
+        if verbose {
+            println!(
+                "[info] Found MSU-1 data file \"{}\"",
+                media.data_path().display()
+            );
+        }
+        cartridge.attach_msu1(std::sync::Arc::new(media));
+    }
+}
+
 struct AudioBackend {
     producer: ringbuf::Producer<i16>,
+    fill: pacing::AudioFill,
+    /// Set while the emulation is paused
+    muted: std::sync::Arc<std::sync::atomic::AtomicBool>,
 }
 
 const SAMPLE_RATE: cpal::SampleRate = cpal::SampleRate(32000);
 const TIME_PER_GPU_FRAME: Duration = Duration::from_micros(8_333);
-const TIME_UNTIL_TIMER_RESET: Duration = Duration::from_millis(500);
 
 impl AudioBackend {
-    fn write_data<T: Sample>(data: &mut [T], consumer: &mut ringbuf::Consumer<i16>, channels: u16) {
+    /// Write the buffered samples to the output.
+    ///
+    /// While muted, only the samples exceeding `keep` are played, so that the
+    /// buffer neither runs empty nor grows, when frames are advanced one by one.
+    fn write_data<T: Sample>(
+        data: &mut [T],
+        consumer: &mut ringbuf::Consumer<i16>,
+        fill: &pacing::AudioFill,
+        channels: u16,
+        muted: bool,
+        keep: usize,
+    ) {
         for frame in data.chunks_exact_mut(channels.into()) {
-            let [l, r] = [(), ()].map(|_| T::from(&consumer.pop().unwrap_or(0)));
+            let [l, r] = if muted && consumer.len() <= keep {
+                [T::from(&0i16); 2]
+            } else {
+                [(), ()].map(|_| T::from(&consumer.pop().unwrap_or(0)))
+            };
             if channels == 2 {
                 frame[0] = l;
                 frame[1] = r;
@@ -82,39 +436,63 @@ impl AudioBackend {
                 }
             }
         }
+        fill.store(consumer.len());
     }
 
     fn create_stream<T: Sample>(
         device: &cpal::Device,
         cfg: &cpal::StreamConfig,
-    ) -> Result<
-        (
-            <cpal::Device as DeviceTrait>::Stream,
-            ringbuf::Producer<i16>,
-        ),
-        cpal::BuildStreamError,
-    > {
+        latency: Duration,
+    ) -> Result<(<cpal::Device as DeviceTrait>::Stream, Self), cpal::BuildStreamError> {
         let channels = cfg.channels;
-        let ringbuf_size = (match cfg.buffer_size {
+        let latency_size =
+            (latency.as_secs_f64() * f64::from(cfg.sample_rate.0)) as u32 * u32::from(channels);
+        let ringbuf_size = ((match cfg.buffer_size {
             cpal::BufferSize::Fixed(val) => val,
             cpal::BufferSize::Default => 1024,
         } + cfg.sample_rate.0 / 6)
-            * u32::from(channels);
+            * u32::from(channels))
+        .max(latency_size * 2);
         let (mut producer, mut consumer) = ringbuf::RingBuffer::new(ringbuf_size as usize).split();
-        // add a little latency
-        for _ in 0..ringbuf_size / 5 {
+        // the samples in the buffer delay the audio output
+        for _ in 0..latency_size {
             producer.push(0).unwrap();
         }
+        let fill = pacing::AudioFill::new(producer.capacity());
+        fill.store(producer.len());
+        let callback_fill = fill.clone();
+        let muted = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let callback_muted = muted.clone();
+        // samples are pushed in pairs of the left and right channel
+        let keep = latency_size as usize & !1;
         device
             .build_output_stream(
                 cfg,
-                move |data: &mut [T], _| Self::write_data::<T>(data, &mut consumer, channels),
+                move |data: &mut [T], _| {
+                    Self::write_data::<T>(
+                        data,
+                        &mut consumer,
+                        &callback_fill,
+                        channels,
+                        callback_muted.load(std::sync::atomic::Ordering::Relaxed),
+                        keep,
+                    )
+                },
                 |_| (),
             )
-            .map(|stream| (stream, producer))
+            .map(|stream| {
+                (
+                    stream,
+                    Self {
+                        producer,
+                        fill,
+                        muted,
+                    },
+                )
+            })
     }
 
-    fn new() -> Option<(Self, cpal::platform::Stream)> {
+    fn new(latency: Duration) -> Option<(Self, cpal::platform::Stream)> {
         let host = cpal::available_hosts()
             .into_iter()
             .find_map(|id| cpal::host_from_id(id).ok())
@@ -154,9 +532,9 @@ impl AudioBackend {
             cpal::SampleFormat::U16 => Self::create_stream::<u16>,
             cpal::SampleFormat::F32 => Self::create_stream::<f32>,
         };
-        let (stream, producer) = create_stream(&device, &cfg).ok()?;
+        let (stream, backend) = create_stream(&device, &cfg, latency).ok()?;
         stream.play().ok()?;
-        Some((Self { producer }, stream))
+        Some((backend, stream))
     }
 }
 
@@ -166,95 +544,635 @@ impl rsnes::backend::AudioBackend for AudioBackend {
             .producer
             .push(sample.l)
             .and_then(|()| self.producer.push(sample.r));
+        self.fill.store(self.producer.len());
+    }
+
+    fn fill_level(&self) -> Option<f32> {
+        Some(self.fill.level())
     }
 }
 
 mod shaders {
-    macro_rules! include_shader {
-        ($t:expr) => {
-            include_bytes!(concat!(env!("OUT_DIR"), "/", $t))
-        };
-    }
+    /// The built-in shader, it is compiled when the emulator starts
+    static SOURCE: &str = include_str!("../shaders/main.wgsl");
 
-    static VERTEX_SHADER: &[u8] = include_shader!("main.vertex.spirv");
-    static FRAGMENT_SHADER: &[u8] = include_shader!("main.fragment.spirv");
+    pub static VERTEX_ENTRY_POINT: &str = "vs_main";
+    pub static FRAGMENT_ENTRY_POINT: &str = "fs_main";
 
-    fn create_shader(device: &wgpu::Device, source: &[u8]) -> wgpu::ShaderModule {
+    /// Compile a WGSL shader. Invalid shaders abort the emulator,
+    /// so shader files are checked by [`crate::hot_reload::load_wgsl`].
+    pub fn create_wgsl(device: &wgpu::Device, source: &str) -> wgpu::ShaderModule {
         device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-            label: None, // TODO: label
-            source: wgpu::util::make_spirv(source),
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(source.into()),
         })
     }
 
-    static SHADER_ENTRY_POINT: &str = "main";
+    pub fn create(device: &wgpu::Device) -> wgpu::ShaderModule {
+        create_wgsl(device, SOURCE)
+    }
+}
 
-    pub fn create_vs(device: &wgpu::Device) -> (&str, wgpu::ShaderModule) {
-        (SHADER_ENTRY_POINT, create_shader(device, VERTEX_SHADER))
+/// Create the pipeline drawing the picture with the
+/// vertex and fragment shaders of the module `shader`
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: shaders::VERTEX_ENTRY_POINT,
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: shaders::FRAGMENT_ENTRY_POINT,
+            targets: &[format.into()],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+fn frame_size_to_extent(size: rsnes::backend::FrameSize) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: size.width,
+        height: size.height,
+        depth_or_array_layers: 1,
+    }
+}
+
+/// Create the texture holding the SNES picture and the bind group referencing it.
+///
+/// The texture needs to be recreated, when the output resolution changes
+/// (e.g. when the game switches to a high-resolution or interlaced mode).
+fn create_screen_texture(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    screen_size_buffer: &wgpu::Buffer,
+    extent: wgpu::Extent3d,
+) -> (wgpu::Texture, wgpu::BindGroup) {
+    let texture_format = wgpu::TextureFormat::Rgba8UnormSrgb;
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: texture_format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    });
+    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
+        label: None,
+        format: Some(texture_format),
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        aspect: wgpu::TextureAspect::All,
+        base_mip_level: 0,
+        mip_level_count: None,
+        base_array_layer: 0,
+        array_layer_count: None,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: screen_size_buffer.as_entire_binding(),
+            },
+        ],
+    });
+    (texture, bind_group)
+}
+
+/// Draw the texture of `bind_group` into `view`
+fn draw_picture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    view: &wgpu::TextureView,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: None,
+        color_attachments: &[wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        }],
+        depth_stencil_attachment: None,
+    });
+    rpass.set_pipeline(pipeline);
+    rpass.set_bind_group(0, bind_group, &[]);
+    rpass.draw(0..6, 0..1);
+    drop(rpass);
+    queue.submit(Some(encoder.finish()));
+}
+
+/// Everything besides the local input devices that controls the inputs
+#[derive(Default)]
+struct InputSessions {
+    movie: Option<movie::MovieSession>,
+    #[cfg(feature = "netplay")]
+    netplay: Option<netplay::NetplaySession>,
+    #[cfg(feature = "scripting")]
+    script: Option<script::Script>,
+}
+
+impl InputSessions {
+    /// Test if a netplay session runs. Both consoles must stay in sync,
+    /// so the local console must not load save states.
+    fn is_netplay(&self) -> bool {
+        #[cfg(feature = "netplay")]
+        return self.netplay.is_some();
+        #[cfg(not(feature = "netplay"))]
+        false
+    }
+
+    /// Prepare the inputs of the next frame.
+    /// Returns false if the frame must not be emulated yet.
+    fn before_frame<B: rsnes::backend::AudioBackend>(
+        &mut self,
+        snes: &mut Device<B, ArrayFrameBuffer>,
+    ) -> bool {
+        #[cfg(feature = "netplay")]
+        if let Some(netplay) = &mut self.netplay {
+            if !netplay.advance(snes) {
+                return false;
+            }
+        }
+        if let Some(movie) = &mut self.movie {
+            movie.before_frame(&mut snes.controllers);
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(script) = &mut self.script {
+            script.before_frame(&mut snes.controllers);
+        }
+        true
+    }
+
+    /// Run the hooks of the script after a frame got emulated
+    fn after_frame<B: rsnes::backend::AudioBackend>(
+        &mut self,
+        snes: &mut Device<B, ArrayFrameBuffer>,
+    ) {
+        #[cfg(feature = "scripting")]
+        if let Some(script) = &mut self.script {
+            if let Err(err) = script.after_frame(snes) {
+                eprintln!("[error] Script stopped ({})", err);
+                self.script = None;
+            }
+        }
+        #[cfg(not(feature = "scripting"))]
+        let _ = snes;
+    }
+}
+
+/// Prepare the inputs and emulate a frame. Returns the amount of
+/// master cycles it took or zero if the frame must not be emulated yet.
+fn run_frame<B: rsnes::backend::AudioBackend>(
+    snes: &mut Device<B, ArrayFrameBuffer>,
+    local_input: &mut input::LocalInput,
+    sessions: &mut InputSessions,
+    recorder: &mut Option<recording::Recorder>,
+) -> u64 {
+    local_input.before_frame(&mut snes.controllers);
+    if !sessions.before_frame(snes) {
+        return 0;
+    }
+    local_input.capture(&snes.controllers);
+    let cycles = emulate_frame(snes);
+    // take the newest frame of the render threads
+    snes.ppu.present();
+    local_input.after_frame();
+    sessions.after_frame(snes);
+    if let Some(rec) = recorder {
+        if let Err(err) = rec.frame(&snes.ppu.frame_buffer) {
+            eprintln!("[error] Recording stopped ({})", err);
+            snes.notify("Recording stopped");
+            *recorder = None;
+        }
+    }
+    cycles
+}
+
+/// Start a recording and return the message for the overlay
+fn start_recording(
+    path: &Path,
+    tap: &recording::SampleTap,
+    frame_size: rsnes::backend::FrameSize,
+    region: Region,
+) -> (Option<recording::Recorder>, String) {
+    match recording::Recorder::start(path, tap.clone(), frame_size, region, SAMPLE_RATE.0) {
+        Ok(rec) => (Some(rec), format!("Recording to {}", path.display())),
+        Err(err) => {
+            eprintln!("[error] Could not start recording ({})", err);
+            (None, "Could not start recording".to_owned())
+        }
+    }
+}
+
+/// Stop a recording and return the message for the overlay
+fn finish_recording(recorder: recording::Recorder) -> String {
+    let frames = recorder.frames();
+    match recorder.finish() {
+        Ok(path) => format!("Recorded {} frames to {}", frames, path.display()),
+        Err(err) => {
+            eprintln!("[error] Could not finish recording ({})", err);
+            "Could not finish recording".to_owned()
+        }
+    }
+}
+
+/// Write the picture to `path` and return the message for the overlay
+fn take_screenshot(path: &Path, frame_buffer: &ArrayFrameBuffer) -> String {
+    match recording::write_ppm(path, frame_buffer) {
+        Ok(()) => format!("Screenshot {}", path.display()),
+        Err(err) => {
+            eprintln!(
+                "[error] Could not write screenshot \"{}\" ({})",
+                path.display(),
+                err
+            );
+            "Could not write screenshot".to_owned()
+        }
+    }
+}
+
+/// Store a save state in the slot `id` and return the message for the overlay
+fn store_state<B: rsnes::backend::AudioBackend>(
+    snes: &mut Device<B, ArrayFrameBuffer>,
+    sessions: &InputSessions,
+    savestates: &mut state_io::Slots,
+    title: &str,
+    id: usize,
+) -> String {
+    let state = snes.serialize_at_frame_boundary();
+    let frame = sessions.movie.as_ref().map_or(0, |m| m.frame());
+    let thumbnail = state_io::Thumbnail::from_frame_buffer(&snes.ppu.frame_buffer);
+    savestates.store(id, state_io::SlotFile::new(title, frame, thumbnail, state));
+    format!("State {} saved", id)
+}
+
+/// Load the save state of the slot `id` and return the message for the overlay
+fn load_state<B: rsnes::backend::AudioBackend>(
+    snes: &mut Device<B, ArrayFrameBuffer>,
+    sessions: &mut InputSessions,
+    savestates: &state_io::Slots,
+    id: usize,
+) -> String {
+    if sessions.is_netplay() {
+        return format!("State {} not loaded during netplay", id);
+    }
+    let slot = match savestates.get(id) {
+        Some(slot) => slot,
+        None => return format!("State {} is empty", id),
+    };
+    if let Err(err) = save_state::deserialize_checked(snes, &slot.state) {
+        return format!("State {} not loaded: {}", id, err);
+    }
+    if let Some(movie) = &mut sessions.movie {
+        movie.on_load_state(slot.movie_frame as usize)
     }
+    format!("State {} loaded", id)
+}
 
-    pub fn create_fs(device: &wgpu::Device) -> (&str, wgpu::ShaderModule) {
-        (SHADER_ENTRY_POINT, create_shader(device, FRAGMENT_SHADER))
+/// Emulate a frame and return the amount of master cycles it took
+fn emulate_frame<B: rsnes::backend::AudioBackend, FB: rsnes::backend::FrameBuffer>(
+    snes: &mut Device<B, FB>,
+) -> u64 {
+    snes.run_cycle::<MASTER_CYCLES_PER_TICK>();
+    let mut cycle_count = u64::from(MASTER_CYCLES_PER_TICK);
+    while !snes.new_frame {
+        snes.run_cycle::<MASTER_CYCLES_PER_TICK>();
+        cycle_count += u64::from(MASTER_CYCLES_PER_TICK)
     }
+    cycle_count
+}
+
+/// The profile selected on the command line or by the game settings,
+/// with the settings of the game applied
+fn select_profile(
+    config: &config::Config,
+    name: Option<&str>,
+    game_settings: &config::GameSettings,
+) -> Result<config::Profile, String> {
+    let profile = match name.or(game_settings.profile.as_deref()) {
+        Some(name) => config
+            .get_profile(name)
+            .ok_or_else(|| format!("profile `{name}` is not defined"))?,
+        None => config.get_default_profile(),
+    };
+    game_settings
+        .apply(config, profile.clone())
+        .map_err(|err| format!("game settings: {err}"))
 }
 
 fn main() {
-    let options = Options::parse();
+    let cli = Cli::parse();
+    let options = match cli.command {
+        None => cli.options,
+        Some(Command::Run(options)) => options,
+        Some(Command::RomInfo(options)) => commands::rom_info(&options),
+        Some(Command::Verify(options)) => commands::verify(&options),
+        Some(Command::PlaySpc {
+            file,
+            verbose,
+            interpolation,
+        }) => player::play_spc(
+            &file,
+            verbose,
+            interpolation
+                .as_deref()
+                .and_then(Interpolation::from_name)
+                .unwrap_or_default(),
+        ),
+    };
+    if let Some(path) = &options.write_default_config {
+        let path = path
+            .as_ref()
+            .map(PathBuf::from)
+            .or_else(config::Config::default_config_path)
+            .unwrap_or_else(|| error!("Could not find the home directory\n"));
+        config::Config::write_default(&path).unwrap_or_else(|err| {
+            error!(
+                "Could not write configuration file \"{}\" ({})\n",
+                path.display(),
+                err
+            )
+        });
+        println!("Wrote the default configuration to \"{}\"", path.display());
+        return;
+    }
+    let mut recent_files = recent::RecentFiles::load();
+    if options.list_recent {
+        for (i, file) in recent_files.files().iter().enumerate() {
+            println!("{:2}: {}", i + 1, file.display());
+        }
+        return;
+    }
 
-    let config = config::Config::load(options.config, options.verbose)
+    let config_path = options
+        .config
+        .clone()
+        .or_else(config::Config::seek_config_path);
+    let config = config::Config::load(config_path.clone(), options.verbose)
         .unwrap_or_else(|err| error!("config: {err}"));
-    let profile = if let Some(name) = &options.profile {
-        config
-            .get_profile(name)
-            .unwrap_or_else(|| error!("profile `{name}` is not defined"))
+
+    let mut rom_path = match options.recent {
+        Some(n) => recent_files
+            .get(n)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| error!("There is no recent file number {}\n", n)),
+        None => options.input.clone().unwrap(),
+    };
+    let mut cartridge = if options.sufami_a.is_some() || options.sufami_b.is_some() {
+        let [base, slot_a, slot_b] = [
+            Some(&rom_path),
+            options.sufami_a.as_ref(),
+            options.sufami_b.as_ref(),
+        ]
+        .map(|path| path.map(|path| read_rom_file(path).unwrap_or_else(|err| error!("{}\n", err))));
+        // save states and SRAM belong to the inserted game instead of the BIOS
+        rom_path = options
+            .sufami_a
+            .clone()
+            .or_else(|| options.sufami_b.clone())
+            .unwrap();
+        rsnes::cartridge::Cartridge::from_sufami(
+            &base.unwrap(),
+            slot_a.as_deref(),
+            slot_b.as_deref(),
+        )
+        .unwrap_or_else(|err| error!("Failure while reading Sufami Turbo cartridges ({})\n", err))
     } else {
-        config.get_default_profile()
+        let raw = options.mapper.as_deref().map(|name| {
+            let rom_type = rsnes::cartridge::RomType::from_name(name).unwrap();
+            (rom_type, options.reset.unwrap_or(0x8000))
+        });
+        let cartridge =
+            cartridge_from_file(&rom_path, options.patch.as_deref(), raw, options.verbose)
+                .unwrap_or_else(|err| error!("{}\n", err));
+        // raw binaries can't be reopened without their mapping
+        if raw.is_none() {
+            recent_files.add(&rom_path);
+        }
+        cartridge
     };
-    let [port1_profile, port2_profile] =
-        config.get_controller_profiles(&profile).map(|p| p.cloned());
-
-    let cartridge = cartridge_from_file(&options.input);
-    let title = cartridge.title().to_owned();
+    if let Some(path) = &options.memory_pack {
+        let content = std::fs::read(path)
+            .unwrap_or_else(|err| error!("Could not read file \"{}\" ({})\n", path.display(), err));
+        cartridge
+            .insert_memory_pack(content)
+            .unwrap_or_else(|err| error!("Could not insert memory pack ({})\n", err));
+    }
+    // SRAM files and save states are stored next to the cartridge file,
+    // unless another directory or a library is configured
+    let mut game_paths = library::GamePaths::new(
+        &config.paths,
+        &rom_path,
+        &library::GameId::from_cartridge(&cartridge),
+    );
+    game_paths
+        .create_dirs()
+        .unwrap_or_else(|err| error!("Could not create the directories of the game ({})\n", err));
+    let sram_path = game_paths.sram();
+    attach_cartridge_files(&mut cartridge, &rom_path, &sram_path, options.verbose);
+    let game_settings = game_paths
+        .load_settings(options.verbose)
+        .unwrap_or_else(|err| error!("game settings: {err}"));
+    let profile = select_profile(&config, options.profile.as_deref(), &game_settings)
+        .unwrap_or_else(|err| error!("{err}"));
+    let device_options = [&options.port1_device, &options.port2_device];
+    let devices = [0, 1].map(|port| match device_options[port] {
+        Some(name) => config::parse_device(name).flatten(),
+        None => profile.devices[port],
+    });
+    let detected = cartridge.peripherals();
+    let [(port1_device, port1_profile), (port2_device, port2_profile)] =
+        config::select_devices(config.get_controller_profiles(&profile), devices, detected);
+    if options.verbose {
+        if let Some(detected) = detected {
+            println!(
+                "[info] The game database expects a {} at port 1 and a {} at port 2",
+                detected[0].name(),
+                detected[1].name()
+            );
+        }
+    }
+    let mut title = cartridge.title().to_owned();
     if options.verbose {
         println!(
             "[info] Cartridge header information: {:#?}",
             cartridge.header()
         );
     }
-    let is_pal = match profile.region {
-        rsnes::cartridge::CountryFrameRate::Any => matches!(
-            cartridge.get_country_frame_rate(),
-            rsnes::cartridge::CountryFrameRate::Pal
-        ),
-        rsnes::cartridge::CountryFrameRate::Pal => true,
-        rsnes::cartridge::CountryFrameRate::Ntsc => false,
+    let region = options
+        .region
+        .as_deref()
+        .and_then(config::parse_region)
+        .unwrap_or(profile.region);
+    let region = match region {
+        rsnes::cartridge::CountryFrameRate::Any => Region::from_cartridge(&cartridge),
+        rsnes::cartridge::CountryFrameRate::Pal => Region::Pal,
+        rsnes::cartridge::CountryFrameRate::Ntsc => Region::Ntsc,
     };
     if options.verbose {
         println!(
             "[info] Selected {} region",
-            if is_pal { "PAL" } else { "NTSC" }
+            if region.is_pal() { "PAL" } else { "NTSC" }
         );
     }
+    let audio_latency = options.audio_latency.unwrap_or(profile.audio_latency);
     let (audio_backend, _audio_stream) =
-        AudioBackend::new().unwrap_or_else(|| error!("Failed finding an audio output device"));
-    let mut snes = Device::new(
-        audio_backend,
-        ArrayFrameBuffer([[0; 4]; rsnes::backend::FRAME_BUFFER_SIZE], true),
-        is_pal,
+        AudioBackend::new(Duration::from_millis(audio_latency.into()))
+            .unwrap_or_else(|| error!("Failed finding an audio output device"));
+    let sync_mode = options
+        .sync
+        .as_deref()
+        .and_then(pacing::SyncMode::from_name)
+        .unwrap_or(profile.sync);
+    let mut pacer = pacing::Pacer::new(sync_mode, audio_backend.fill.clone(), region);
+    let audio_muted = audio_backend.muted.clone();
+    let ram_init = match options.ram_init.as_deref() {
+        Some(ram_init) => config::parse_ram_init(ram_init)
+            .unwrap_or_else(|| error!("Invalid RAM initialization \"{}\"\n", ram_init)),
+        None => RamInit::Zeros,
+    };
+    if let RamInit::Random(seed) = ram_init {
+        eprintln!("[info] Filling the work RAM with the seed {}", seed)
+    }
+    // movies and netplay rely on the exact timing
+    #[cfg(feature = "netplay")]
+    let netplay = options.netplay.is_active();
+    #[cfg(not(feature = "netplay"))]
+    let netplay = false;
+    let skip_idle_loops = (profile.skip_idle_loops || options.skip_idle_loops)
+        && options.record_movie.is_none()
+        && options.play_movie.is_none()
+        && !netplay;
+    let sample_tap = recording::SampleTap::default();
+    let mut snes = Device::new_with_options(
+        recording::TapBackend::new(
+            rsnes::sync::RateControl::new(audio_backend),
+            sample_tap.clone(),
+        ),
+        ArrayFrameBuffer::new(),
+        region,
         profile.threaded,
+        DeviceOptions {
+            ram_init,
+            apu_sync_cycles: options.apu_sync.unwrap_or(profile.apu_sync).max(1),
+            skip_idle_loops,
+        },
     );
-    snes.controllers.port1 = config::controller_profile_to_port(port1_profile.as_ref());
-    snes.controllers.port2 = config::controller_profile_to_port(port2_profile.as_ref());
+    snes.ppu.set_render_threads(profile.render_threads);
+    snes.ppu
+        .set_sprite_limits(profile.sprite_limit && !options.no_sprite_limit);
+    let interpolation = options
+        .interpolation
+        .as_deref()
+        .and_then(Interpolation::from_name)
+        .unwrap_or(profile.interpolation);
+    if options.record_movie.is_none() && options.play_movie.is_none() && !netplay {
+        snes.smp.set_interpolation(interpolation);
+    }
+    if profile.color_correction || options.color_correction {
+        snes.ppu
+            .set_color_correction(Some(rsnes::color::ColorCorrection::CRT));
+    }
+    snes.controllers.connect(0, port1_device);
+    snes.controllers.connect(1, port2_device);
     snes.load_cartridge(cartridge);
+    if let Some(path) = &options.cheats {
+        let content = std::fs::read_to_string(path)
+            .unwrap_or_else(|err| error!("Could not read file \"{}\" ({})\n", path.display(), err));
+        for (line, code) in content.lines().enumerate() {
+            let code = match code.split_whitespace().next() {
+                Some(code) if !code.starts_with('#') => code,
+                _ => continue,
+            };
+            snes.add_cheat(code).unwrap_or_else(|err| {
+                error!("Invalid cheat code in line {} ({})\n", line + 1, err)
+            });
+        }
+        if options.verbose {
+            println!(
+                "[info] Applied {} cheat codes",
+                snes.cheats().iter().count()
+            );
+        }
+    }
+    let mut sessions = InputSessions {
+        movie: if let Some(path) = options.record_movie.clone() {
+            Some(movie::MovieSession::record(path, &mut snes, &title))
+        } else {
+            options
+                .play_movie
+                .as_deref()
+                .map(|path| movie::MovieSession::play(path, &mut snes, options.verbose))
+        },
+        #[cfg(feature = "netplay")]
+        netplay: netplay::NetplaySession::start(&options.netplay, options.verbose),
+        #[cfg(feature = "scripting")]
+        script: options.script.as_deref().map(|path| {
+            script::Script::load(path, &mut snes).unwrap_or_else(|err| {
+                error!(
+                    "Failure while loading script \"{}\" ({})\n",
+                    path.display(),
+                    err
+                )
+            })
+        }),
+    };
+    // The first input profile drives this controller port.
+    // In netplay both players use their first profile on both ports.
+    #[cfg(feature = "netplay")]
+    let (local_port, port2_profile) = match &sessions.netplay {
+        Some(netplay) => {
+            snes.controllers.connect(1, port1_device);
+            (netplay.local_port(), None)
+        }
+        None => (0, port2_profile),
+    };
+    #[cfg(not(feature = "netplay"))]
+    let local_port = 0;
+    let mut turbo = [port1_profile.as_ref(), port2_profile.as_ref()]
+        .map(|profile| profile.map_or_else(Default::default, |profile| profile.turbo()));
+    turbo.rotate_left(local_port);
+    let mut local_input = input::LocalInput::new(turbo);
 
     let size = winit::dpi::PhysicalSize::new(
         rsnes::ppu::SCREEN_WIDTH * 4,
         rsnes::ppu::MAX_SCREEN_HEIGHT * 4,
     );
+    let headless = options.headless || options.dump_frames.is_some();
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_decorations(true)
-        .with_visible(true)
-        .with_fullscreen(None)
+        .with_visible(!headless)
+        .with_fullscreen(options.fullscreen.then(|| Fullscreen::Borderless(None)))
         .with_resizable(true)
         .with_maximized(false)
         .with_inner_size(size)
@@ -262,16 +1180,19 @@ fn main() {
         .build(&event_loop)
         .unwrap_or_else(|err| error!("Failure while creating window ({})", err));
 
-    let inst = wgpu::Instance::new(wgpu::Backends::VULKAN);
-    let surf = unsafe { inst.create_surface(&window) };
-    let adapter = inst
-        .request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::LowPower,
-            compatible_surface: Some(&surf),
-            force_fallback_adapter: false,
-        })
-        .block_on()
+    let backend = options
+        .backend
+        .as_deref()
+        .and_then(gpu::Backend::from_name)
+        .unwrap_or(gpu::Backend::Auto);
+    let inst = wgpu::Instance::new(backend.backends());
+    let surf = (!headless).then(|| unsafe { inst.create_surface(&window) });
+    let (adapter, surf) = gpu::request_adapter(&inst, surf)
         .unwrap_or_else(|| error!("Failure finding a graphics adapter"));
+    if options.verbose {
+        let info = adapter.get_info();
+        println!("[info] Graphics adapter {} ({:?})", info.name, info.backend)
+    }
     let (device, queue) = adapter
         .request_device(
             &wgpu::DeviceDescriptor {
@@ -283,8 +1204,6 @@ fn main() {
         )
         .block_on()
         .unwrap_or_else(|err| error!("Failure requesting a GPU command queue ({})", err));
-    let (vs_entry, vs_shader) = shaders::create_vs(&device);
-    let (fs_entry, fs_shader) = shaders::create_fs(&device);
 
     let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         label: None,
@@ -307,7 +1226,7 @@ fn main() {
             },
             wgpu::BindGroupLayoutEntry {
                 binding: 2,
-                visibility: wgpu::ShaderStages::VERTEX,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -322,31 +1241,6 @@ fn main() {
         bind_group_layouts: &[&bind_group_layout],
         push_constant_ranges: &[],
     });
-    let texture_extent = wgpu::Extent3d {
-        width: rsnes::ppu::SCREEN_WIDTH,
-        height: rsnes::ppu::MAX_SCREEN_HEIGHT,
-        depth_or_array_layers: 1,
-    };
-    let texture_format = wgpu::TextureFormat::Rgba8UnormSrgb;
-    let texture = device.create_texture(&wgpu::TextureDescriptor {
-        label: None,
-        size: texture_extent,
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format: texture_format,
-        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-    });
-    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
-        label: None,
-        format: Some(texture_format),
-        dimension: Some(wgpu::TextureViewDimension::D2),
-        aspect: wgpu::TextureAspect::All,
-        base_mip_level: 0,
-        mip_level_count: None,
-        base_array_layer: 0,
-        array_layer_count: None,
-    });
     let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
         label: None,
         address_mode_u: wgpu::AddressMode::MirrorRepeat,
@@ -363,65 +1257,106 @@ fn main() {
     });
     let screen_size_buffer = device.create_buffer(&wgpu::BufferDescriptor {
         label: None,
-        size: 4 * 4,
+        size: video::UNIFORM_SIZE,
         usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         mapped_at_creation: false,
     });
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&texture_view),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::Sampler(&sampler),
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: screen_size_buffer.as_entire_binding(),
-            },
-        ],
-    });
+    let mut texture_extent = frame_size_to_extent(snes.ppu.frame_buffer.size());
+    let (mut texture, mut bind_group) = create_screen_texture(
+        &device,
+        &bind_group_layout,
+        &sampler,
+        &screen_size_buffer,
+        texture_extent,
+    );
 
-    let swapchain_format = surf.get_preferred_format(&adapter).unwrap();
-    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: None,
-        layout: Some(&pipeline_layout),
-        vertex: wgpu::VertexState {
-            module: &vs_shader,
-            entry_point: vs_entry,
-            buffers: &[],
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &fs_shader,
-            entry_point: fs_entry,
-            targets: &[swapchain_format.into()],
-        }),
-        primitive: wgpu::PrimitiveState::default(),
-        depth_stencil: None,
-        multisample: wgpu::MultisampleState::default(),
-        multiview: None,
-    });
-    let mut surf_config = wgpu::SurfaceConfiguration {
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-        format: swapchain_format,
-        width: size.width as u32,
-        height: size.height as u32,
-        present_mode: wgpu::PresentMode::Fifo,
+    let mut target = match surf {
+        Some(surf) => {
+            // the window may be larger than requested, e.g. in fullscreen
+            let size = window.inner_size();
+            gpu::Target::window(surf, &adapter, &device, size.width, size.height)
+        }
+        None => {
+            if let Some(dir) = &options.dump_frames {
+                std::fs::create_dir_all(dir).unwrap_or_else(|err| {
+                    error!("Could not create directory \"{}\" ({})", dir.display(), err)
+                });
+            }
+            if !headless {
+                eprintln!(
+                    "[warning] The graphics adapter can't draw into the window, rendering headless"
+                )
+            }
+            gpu::Target::headless(
+                &device,
+                size.width,
+                size.height,
+                options.dump_frames.clone(),
+            )
+        }
+    };
+    // there is no vertical blank without a window to wait for
+    if target.is_headless() && pacer.mode() == pacing::SyncMode::Vsync {
+        pacer.set_mode(pacing::SyncMode::Timer)
+    }
+    #[cfg(feature = "dev-tools")]
+    let mut shader_watcher = options.shader.clone().map(hot_reload::FileWatcher::new);
+    #[cfg(feature = "dev-tools")]
+    let shader = match &options.shader {
+        Some(path) => {
+            let source = hot_reload::load_wgsl(path).unwrap_or_else(|err| error!("{}\n", err));
+            shaders::create_wgsl(&device, &source)
+        }
+        None => shaders::create(&device),
+    };
+    #[cfg(not(feature = "dev-tools"))]
+    let shader = shaders::create(&device);
+    #[allow(unused_mut)]
+    let mut render_pipeline =
+        create_render_pipeline(&device, &pipeline_layout, &shader, target.format());
+    let renderer = debug_view::Renderer {
+        instance: inst,
+        adapter,
+        device,
+        queue,
+        bind_group_layout,
+        pipeline_layout,
+        sampler,
+        shader,
     };
-    surf.configure(&device, &surf_config);
 
-    let mut shift = [false; 2];
-    let mut savestates: [Option<Vec<u8>>; 10] = [(); 10].map(|()| None);
+    let mut hotkeys = input::Hotkeys::default();
+    let mut savestates = state_io::Slots::load(&game_paths.states(), &title, options.verbose);
 
-    let mut next_device_update = Instant::now();
-    let mut next_graphics_update = next_device_update;
+    let mut next_graphics_update = Instant::now();
+    let mut overlay = overlay::Overlay::new(options.show_fps || profile.show_fps, region);
+    if options.show_input || profile.show_input {
+        overlay.toggle_input()
+    }
 
     let mut focused = true;
-    let mut update_screen_size = true;
+    let mut paused = false;
+    let mut video_options = profile.video;
+    let paths = config.paths.clone();
+    let mut recorder = None;
+    if let Some(path) = &options.record {
+        let message;
+        (recorder, message) =
+            start_recording(path, &sample_tap, snes.ppu.frame_buffer.size(), region);
+        overlay.show_message(message)
+    }
+
+    let mut sram_saver =
+        autosave::SramSaver::new(sram_path, profile.sram_flush_interval, options.verbose);
+    let mut resume_path = autosave::resume_path(&game_paths.states());
+    // movies and netplay sessions start at power-on
+    let can_resume = sessions.movie.is_none() && !sessions.is_netplay();
+    if profile.auto_resume
+        && can_resume
+        && autosave::load_resume_state(&mut snes, &resume_path, &title)
+    {
+        overlay.show_message(format!("Resumed {}", title))
+    }
 
     let has_mouse = [port1_profile.as_ref(), port2_profile.as_ref()]
         .into_iter()
@@ -436,19 +1371,82 @@ fn main() {
         window.set_cursor_visible(false);
     }
 
-    event_loop.run(move |ev, _, control_flow| {
+    // the key bindings are reloaded with the configuration file
+    let (mut port1_profile, mut port2_profile) = (port1_profile, port2_profile);
+    let mut config_watcher = config_path.map(hot_reload::FileWatcher::new);
+
+    let mut debug_views = debug_view::DebugViews::new(&options.debug_view, &event_loop, &renderer);
+
+    autosave::catch_interrupts();
+    event_loop.run(move |ev, event_loop, control_flow| {
         *control_flow = ControlFlow::Poll;
         match ev {
+            Event::WindowEvent { window_id, event } if window_id != window.id() => {
+                debug_views.window_event(window_id, event, &renderer.device)
+            }
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                WindowEvent::Resized(size) => {
-                    if surf_config.width != size.width || surf_config.height != size.height {
-                        update_screen_size = true;
+                WindowEvent::DroppedFile(path) => {
+                    if sessions.is_netplay() {
+                        overlay.show_message("Cannot change the cartridge in netplay");
+                        return;
+                    }
+                    let mut cartridge =
+                        match cartridge_from_file(&path, None, None, options.verbose) {
+                            Ok(cartridge) => cartridge,
+                            Err(err) => {
+                                eprintln!("[error] {}", err);
+                                overlay.show_message("Could not load the dropped file");
+                                return;
+                            }
+                        };
+                    sram_saver.flush(&snes);
+                    if profile.auto_resume {
+                        autosave::write_resume_state(&mut snes, &resume_path, &title)
+                    }
+                    if let Some(movie) = sessions.movie.take() {
+                        movie.finish()
+                    }
+                    // cheat codes belong to the previous game
+                    let cheats: Vec<_> = snes.cheats().iter().map(|(id, _)| id).collect();
+                    for id in cheats {
+                        snes.remove_cheat(id);
+                    }
+                    game_paths = library::GamePaths::new(
+                        &paths,
+                        &path,
+                        &library::GameId::from_cartridge(&cartridge),
+                    );
+                    if let Err(err) = game_paths.create_dirs() {
+                        eprintln!(
+                            "[warning] Could not create the directories of the game ({})",
+                            err
+                        )
+                    }
+                    let sram_path = game_paths.sram();
+                    attach_cartridge_files(&mut cartridge, &path, &sram_path, options.verbose);
+                    sram_saver.set_path(&snes, sram_path);
+                    resume_path = autosave::resume_path(&game_paths.states());
+                    title = cartridge.title().to_owned();
+                    savestates =
+                        state_io::Slots::load(&game_paths.states(), &title, options.verbose);
+                    window.set_title(&format!("{} - {}", env!("CARGO_PKG_NAME"), title));
+                    snes.load_cartridge(cartridge);
+                    snes.power_cycle();
+                    recent_files.add(&path);
+                    if profile.auto_resume
+                        && autosave::load_resume_state(&mut snes, &resume_path, &title)
+                    {
+                        overlay.show_message(format!("Resumed {}", title));
+                    } else {
+                        overlay.show_message(format!("Loaded {}", title));
                     }
-                    surf_config.width = size.width;
-                    surf_config.height = size.height;
-                    surf.configure(&device, &surf_config);
                 }
+                WindowEvent::Resized(size)
+                | WindowEvent::ScaleFactorChanged {
+                    new_inner_size: &mut size,
+                    ..
+                } => target.resize(&renderer.device, size.width, size.height),
                 WindowEvent::Focused(focus) => {
                     if has_mouse {
                         window.set_cursor_grab(true).unwrap_or_else(|err| {
@@ -467,7 +1465,7 @@ fn main() {
                         .enumerate()
                         .filter_map(|(i, p)| p.map(|p| (i, p)))
                     {
-                        let controller = &mut if port_nr == 0 {
+                        let controller = &mut if port_nr == local_port {
                             &mut snes.controllers.port1
                         } else {
                             &mut snes.controllers.port2
@@ -482,57 +1480,126 @@ fn main() {
                 DeviceEvent::Key(KeyboardInput {
                     scancode, state, ..
                 }) if focused => {
-                    let mut handled = false;
-                    for (port_nr, port_cfg) in [port1_profile.as_ref(), port2_profile.as_ref()]
+                    let pressed = state == ElementState::Pressed;
+                    let handled = [port1_profile.as_ref(), port2_profile.as_ref()]
                         .into_iter()
                         .enumerate()
                         .filter_map(|(i, p)| p.map(|p| (i, p)))
-                    {
-                        let controller = &mut if port_nr == 0 {
-                            &mut snes.controllers.port1
-                        } else {
-                            &mut snes.controllers.port2
+                        .any(|(port_nr, port_cfg)| {
+                            let port = usize::from(port_nr != local_port);
+                            port_cfg.handle_scancode(scancode, pressed, local_input.held_mut(port))
+                        });
+                    if handled {
+                        return;
+                    }
+                    match hotkeys.handle_scancode(scancode, pressed) {
+                        Some(Hotkey::ToggleStats) => overlay.toggle_stats(),
+                        Some(Hotkey::NextFilter) => {
+                            video_options.filter = video_options.filter.next();
+                            overlay.show_message(format!("Filter {}", video_options.filter.name()))
                         }
-                        .controller;
-                        if port_cfg.handle_scancode(
-                            scancode,
-                            matches!(state, ElementState::Pressed),
-                            controller,
-                        ) {
-                            handled = true;
-                            break;
+                        Some(Hotkey::ToggleAspectRatio) => {
+                            video_options.aspect_ratio = video_options.aspect_ratio.toggle();
+                            overlay.show_message(format!(
+                                "Aspect ratio {}",
+                                video_options.aspect_ratio.name()
+                            ))
                         }
-                    }
-                    if !handled {
-                        match scancode {
-                            _ => {
-                                match scancode {
-                                    0x2a => shift[0] = state == winit::event::ElementState::Pressed,
-                                    0x36 => shift[1] = state == winit::event::ElementState::Pressed,
-                                    2..=11 if state == winit::event::ElementState::Pressed => {
-                                        let id = if scancode == 11 { 0 } else { scancode - 1 };
-                                        let state = &mut savestates[id as usize];
-                                        if shift[0] || shift[1] {
-                                            if let Some(state) = state {
-                                                // load save state
-                                                let mut deserializer =
-                                                    save_state::SaveStateDeserializer {
-                                                        data: state.iter(),
-                                                    };
-                                                snes.deserialize(&mut deserializer);
-                                            }
-                                        } else {
-                                            // store save state
-                                            let mut serializer =
-                                                save_state::SaveStateSerializer { data: vec![] };
-                                            snes.serialize(&mut serializer);
-                                            *state = Some(serializer.data);
-                                        }
-                                    }
-                                    _ => (),
+                        Some(Hotkey::ToggleIntegerScaling) => {
+                            video_options.integer_scaling ^= true;
+                            overlay.show_message(format!(
+                                "Integer scaling {}",
+                                if video_options.integer_scaling {
+                                    "on"
+                                } else {
+                                    "off"
+                                }
+                            ))
+                        }
+                        Some(Hotkey::Reset { power_cycle }) => {
+                            if sessions.is_netplay() {
+                                overlay.show_message("Cannot reset in netplay")
+                            } else if sessions.movie.is_some() {
+                                overlay.show_message("Cannot reset during a movie")
+                            } else if power_cycle {
+                                snes.power_cycle();
+                                overlay.show_message("Power cycle")
+                            } else {
+                                snes.soft_reset();
+                                overlay.show_message("Reset")
+                            }
+                        }
+                        Some(Hotkey::ToggleRecording) => {
+                            let message = match recorder.take() {
+                                Some(rec) => finish_recording(rec),
+                                None => {
+                                    let path = options
+                                        .record
+                                        .clone()
+                                        .unwrap_or_else(|| game_paths.recording());
+                                    let message;
+                                    (recorder, message) = start_recording(
+                                        &path,
+                                        &sample_tap,
+                                        snes.ppu.frame_buffer.size(),
+                                        region,
+                                    );
+                                    message
                                 }
+                            };
+                            overlay.show_message(message)
+                        }
+                        Some(Hotkey::ToggleTurbo) => {
+                            overlay.show_message(local_input.toggle_turbo(local_port))
+                        }
+                        Some(Hotkey::ToggleInput) => overlay.toggle_input(),
+                        Some(Hotkey::Screenshot) => overlay.show_message(take_screenshot(
+                            &game_paths.screenshot(),
+                            &snes.ppu.frame_buffer,
+                        )),
+                        Some(Hotkey::ToggleDebugViews) => debug_views.toggle(event_loop, &renderer),
+                        Some(Hotkey::ToggleFullscreen) => {
+                            window.set_fullscreen(match window.fullscreen() {
+                                Some(_) => None,
+                                None => Some(Fullscreen::Borderless(None)),
+                            })
+                        }
+                        Some(Hotkey::Pause) => {
+                            if sessions.is_netplay() {
+                                overlay.show_message("Cannot pause in netplay");
+                                return;
                             }
+                            paused ^= true;
+                            audio_muted.store(paused, std::sync::atomic::Ordering::Relaxed);
+                            overlay.show_message(if paused { "Paused" } else { "Resumed" })
+                        }
+                        Some(Hotkey::NextFrame) if paused => {
+                            let cycles = run_frame(
+                                &mut snes,
+                                &mut local_input,
+                                &mut sessions,
+                                &mut recorder,
+                            );
+                            pacer.frame_done(cycles, snes.speed(), Instant::now());
+                            overlay.frame_done(cycles, Instant::now());
+                            window.request_redraw();
                         }
+                        // the speed is fixed while recording
+                        Some(Hotkey::Speed(speed)) if recorder.is_none() => snes.set_speed(speed),
+                        Some(Hotkey::StoreState(id)) => overlay.show_message(store_state(
+                            &mut snes,
+                            &sessions,
+                            &mut savestates,
+                            &title,
+                            id,
+                        )),
+                        Some(Hotkey::LoadState(id)) => overlay.show_message(load_state(
+                            &mut snes,
+                            &mut sessions,
+                            &savestates,
+                            id,
+                        )),
+                        _ => (),
                     }
                 }
                 DeviceEvent::MouseMotion { delta: (dx, dy) } if focused => {
@@ -541,7 +1608,7 @@ fn main() {
                         .enumerate()
                         .filter_map(|(i, p)| p.map(|p| (i, p)))
                     {
-                        let controller = &mut if port_nr == 0 {
+                        let controller = &mut if port_nr == local_port {
                             &mut snes.controllers.port1
                         } else {
                             &mut snes.controllers.port2
@@ -553,34 +1620,112 @@ fn main() {
                 _ => (),
             },
             Event::MainEventsCleared => {
-                let now = Instant::now();
-                if now >= next_device_update {
-                    snes.run_cycle::<MASTER_CYCLES_PER_TICK>();
-                    let mut cycle_count = u64::from(MASTER_CYCLES_PER_TICK);
-                    while !snes.new_frame {
-                        snes.run_cycle::<MASTER_CYCLES_PER_TICK>();
-                        cycle_count += u64::from(MASTER_CYCLES_PER_TICK)
+                if autosave::interrupted() {
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+                sram_saver.tick(&snes, Instant::now());
+                if let Some(result) = config_watcher.as_mut().and_then(|watcher| {
+                    watcher.reload_config(
+                        Instant::now(),
+                        options.profile.as_deref(),
+                        &game_settings,
+                        [port1_device, port2_device],
+                    )
+                }) {
+                    match result {
+                        Ok(reloaded) => {
+                            video_options = reloaded.video;
+                            let [port1, port2] = reloaded.controllers;
+                            port1_profile = port1;
+                            // in netplay the second port has no local player
+                            if !netplay {
+                                port2_profile = port2;
+                            }
+                            overlay.show_message("Reloaded the configuration")
+                        }
+                        Err(err) => {
+                            eprintln!("[error] {}", err);
+                            overlay.show_message("Invalid configuration, see the terminal")
+                        }
                     }
-                    // a more precise calculation is not possible by using floats
-                    next_device_update += Duration::from_nanos((8800 * cycle_count) / 189);
-                    // reset the next update timer if it fell to far behind
-                    if now > next_device_update + TIME_UNTIL_TIMER_RESET {
-                        next_device_update = now;
+                }
+                #[cfg(feature = "dev-tools")]
+                if let Some(result) = shader_watcher.as_mut().and_then(|watcher| {
+                    watcher.reload_shader(Instant::now(), &renderer, target.format())
+                }) {
+                    let message = match result {
+                        Ok(pipeline) => {
+                            render_pipeline = pipeline;
+                            "Reloaded the shader"
+                        }
+                        Err(err) => {
+                            eprintln!("[error] {}", err);
+                            "Invalid shader, see the terminal"
+                        }
+                    };
+                    overlay.show_message(message)
+                }
+                debug_views.tick(Instant::now());
+                if pacer.mode() == pacing::SyncMode::Vsync {
+                    window.request_redraw();
+                    return;
+                }
+                let now = Instant::now();
+                if !paused && pacer.is_frame_due(now) {
+                    let cycles =
+                        run_frame(&mut snes, &mut local_input, &mut sessions, &mut recorder);
+                    pacer.frame_done(cycles, snes.speed(), now);
+                    overlay.frame_done(cycles, now);
+                    // headless targets draw every emulated frame, e.g. for the frame dumps
+                    if target.is_headless() {
+                        window.request_redraw();
                     }
                 }
                 let now = Instant::now();
-                if now >= next_graphics_update {
+                if !target.is_headless() && now >= next_graphics_update {
                     window.request_redraw();
                     next_graphics_update = now + TIME_PER_GPU_FRAME;
                 }
             }
+            Event::RedrawRequested(window_id) if window_id != window.id() => {
+                if let Err(err) = debug_views.redraw(window_id, &snes, &renderer) {
+                    if options.verbose {
+                        eprintln!("[warning] {}", err)
+                    }
+                }
+            }
             Event::RedrawRequested(_) => {
-                match surf.get_current_texture() {
-                    Ok(surface_texture) => {
+                if !paused && pacer.mode() == pacing::SyncMode::Vsync {
+                    // the frame presentation below blocks until the next vertical blank
+                    let cycles =
+                        run_frame(&mut snes, &mut local_input, &mut sessions, &mut recorder);
+                    pacer.frame_done(cycles, snes.speed(), Instant::now());
+                    overlay.frame_done(cycles, Instant::now());
+                }
+                for message in snes.take_messages() {
+                    overlay.show_message(message)
+                }
+                match target.acquire() {
+                    Ok(frame) => {
                         if snes.ppu.frame_buffer.1 {
-                            queue.write_texture(
+                            overlay.set_input(*local_input.input_frame());
+                            let frame_buffer =
+                                overlay.compose(&snes.ppu.frame_buffer, Instant::now());
+                            let extent = frame_size_to_extent(frame_buffer.size());
+                            if extent != texture_extent {
+                                texture_extent = extent;
+                                (texture, bind_group) = create_screen_texture(
+                                    &renderer.device,
+                                    &renderer.bind_group_layout,
+                                    &renderer.sampler,
+                                    &screen_size_buffer,
+                                    texture_extent,
+                                );
+                            }
+                            renderer.queue.write_texture(
                                 texture.as_image_copy(),
-                                snes.ppu.frame_buffer.get_bytes(),
+                                frame_buffer.get_bytes(),
                                 wgpu::ImageDataLayout {
                                     offset: 0,
                                     bytes_per_row: core::num::NonZeroU32::new(
@@ -592,54 +1737,24 @@ fn main() {
                                 },
                                 texture_extent,
                             );
-                            if core::mem::take(&mut update_screen_size) {
-                                queue.write_buffer(
-                                    &screen_size_buffer,
-                                    0,
-                                    &surf_config.width.to_ne_bytes(),
-                                );
-                                queue.write_buffer(
-                                    &screen_size_buffer,
-                                    4,
-                                    &surf_config.height.to_ne_bytes(),
-                                );
-                                queue.write_buffer(
-                                    &screen_size_buffer,
-                                    8,
-                                    &u32::from(rsnes::ppu::MAX_SCREEN_HEIGHT).to_ne_bytes(),
-                                );
-                                queue.write_buffer(
-                                    &screen_size_buffer,
-                                    12,
-                                    &u32::from(snes.ppu.vend() - 1).to_ne_bytes(),
-                                );
-                            }
+                            renderer.queue.write_buffer(
+                                &screen_size_buffer,
+                                0,
+                                &video_options.uniform_data(target.size(), frame_buffer.size()),
+                            );
                         }
 
-                        let frame = &surface_texture.texture;
-                        let view = frame.create_view(&wgpu::TextureViewDescriptor::default());
-                        let mut encoder =
-                            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                                label: None,
-                            });
-                        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                            label: None,
-                            color_attachments: &[wgpu::RenderPassColorAttachment {
-                                view: &view,
-                                resolve_target: None,
-                                ops: wgpu::Operations {
-                                    load: wgpu::LoadOp::Load,
-                                    store: true,
-                                },
-                            }],
-                            depth_stencil_attachment: None,
-                        });
-                        rpass.set_pipeline(&render_pipeline);
-                        rpass.set_bind_group(0, &bind_group, &[]);
-                        rpass.draw(0..6, 0..1);
-                        drop(rpass);
-                        queue.submit(Some(encoder.finish()));
-                        surface_texture.present();
+                        draw_picture(
+                            &renderer.device,
+                            &renderer.queue,
+                            &frame.view,
+                            &render_pipeline,
+                            &bind_group,
+                        );
+                        if let Err(err) = target.finish(&renderer.device, &renderer.queue, frame) {
+                            eprintln!("[error] Could not write the frame ({})", err);
+                            *control_flow = ControlFlow::Exit;
+                        }
                     }
                     Err(wgpu::SurfaceError::Timeout) => {
                         if options.verbose {
@@ -649,6 +1764,18 @@ fn main() {
                     Err(err) => error!("Failed to acquire next swap chain texture ({})", err),
                 };
             }
+            Event::LoopDestroyed => {
+                if let Some(movie) = &sessions.movie {
+                    movie.finish()
+                }
+                if let Some(rec) = recorder.take() {
+                    println!("[info] {}", finish_recording(rec))
+                }
+                sram_saver.flush(&snes);
+                if profile.auto_resume {
+                    autosave::write_resume_state(&mut snes, &resume_path, &title)
+                }
+            }
             _ => (),
         }
     })
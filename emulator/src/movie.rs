@@ -0,0 +1,103 @@
+//! Recording and playback of movies from the command line
+
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    controller::ControllerPorts,
+    device::Device,
+    movie::{Movie, MovieMetadata, Player, Recorder, MOVIE_CLOCK},
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+pub enum MovieSession {
+    Recording { recorder: Recorder, path: PathBuf },
+    Playing(Player),
+}
+
+impl MovieSession {
+    /// Start recording from the power-on state
+    pub fn record<B: AudioBackend, FB: FrameBuffer>(
+        path: PathBuf,
+        device: &mut Device<B, FB>,
+        rom_title: &str,
+    ) -> Self {
+        device.set_clock_source(Arc::new(MOVIE_CLOCK));
+        let metadata = MovieMetadata {
+            rom_title: rom_title.to_owned(),
+            ..Default::default()
+        };
+        Self::Recording {
+            recorder: Recorder::from_power_on(metadata),
+            path,
+        }
+    }
+
+    pub fn play<B: AudioBackend, FB: FrameBuffer>(
+        path: &Path,
+        device: &mut Device<B, FB>,
+        verbose: bool,
+    ) -> Self {
+        let content = std::fs::read(path)
+            .unwrap_or_else(|err| error!("Could not read file \"{}\" ({})\n", path.display(), err));
+        let movie = Movie::from_bytes(&content).unwrap_or_else(|err| {
+            error!(
+                "Failure while reading movie file \"{}\" ({})\n",
+                path.display(),
+                err
+            )
+        });
+        if verbose {
+            println!(
+                "[info] Playing movie with {} frames and {} rerecords",
+                movie.frame_count(),
+                movie.rerecord_count
+            );
+        }
+        Self::Playing(Player::new(movie, device).unwrap_or_else(|err| {
+            error!(
+                "Failure while loading the start of movie file \"{}\" ({})\n",
+                path.display(),
+                err
+            )
+        }))
+    }
+
+    /// The index of the next frame
+    pub fn frame(&self) -> usize {
+        match self {
+            Self::Recording { recorder, .. } => recorder.frame(),
+            Self::Playing(player) => player.frame(),
+        }
+    }
+
+    pub fn before_frame(&mut self, ports: &mut ControllerPorts) {
+        match self {
+            Self::Recording { recorder, .. } => recorder.record_frame(ports),
+            Self::Playing(player) => {
+                player.play_frame(ports);
+            }
+        }
+    }
+
+    /// A save state of the given frame got loaded
+    pub fn on_load_state(&mut self, frame: usize) {
+        if let Self::Recording { recorder, .. } = self {
+            recorder.rerecord_from(frame)
+        }
+    }
+
+    /// Write the recorded movie to its file
+    pub fn finish(&self) {
+        if let Self::Recording { recorder, path } = self {
+            std::fs::write(path, recorder.movie().to_bytes()).unwrap_or_else(|err| {
+                eprintln!(
+                    "[error] Could not write movie file \"{}\" ({})",
+                    path.display(),
+                    err
+                )
+            })
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Netplay sessions from the command line
+
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    movie::PortInput,
+    netplay::{
+        rollback::{RollbackConfig, RollbackSession},
+        NetplayConfig, NetplayError, Role, Session, UdpTransport,
+    },
+};
+
+#[derive(clap::Args, Clone)]
+pub struct NetplayOptions {
+    /// Host a netplay session on the given address (e.g. `0.0.0.0:7845`)
+    #[clap(long, value_name = "ADDR", conflicts_with = "netplay-connect")]
+    netplay_host: Option<String>,
+
+    /// Join the netplay session hosted on the given address
+    #[clap(long, value_name = "ADDR")]
+    netplay_connect: Option<String>,
+
+    /// Delay of the local inputs in frames. It must be equal for both players.
+    #[clap(long, value_name = "FRAMES", default_value = "2")]
+    input_delay: u8,
+
+    /// Predict the inputs of the other player and roll back up to this amount
+    /// of frames on misprediction instead of waiting for the inputs
+    #[clap(long, value_name = "FRAMES")]
+    netplay_rollback: Option<u8>,
+}
+
+impl NetplayOptions {
+    /// Test if a netplay session is hosted or joined
+    pub fn is_active(&self) -> bool {
+        self.netplay_host.is_some() || self.netplay_connect.is_some()
+    }
+}
+
+enum Kind {
+    Lockstep(Session<UdpTransport>),
+    Rollback(RollbackSession<UdpTransport>),
+}
+
+pub struct NetplaySession {
+    session: Kind,
+    verbose: bool,
+    was_connected: bool,
+    correction_count: u64,
+}
+
+impl NetplaySession {
+    pub fn start(options: &NetplayOptions, verbose: bool) -> Option<Self> {
+        let (role, transport) = if let Some(addr) = &options.netplay_host {
+            (Role::Host, UdpTransport::listen(addr.as_str()))
+        } else if let Some(addr) = &options.netplay_connect {
+            (Role::Client, UdpTransport::connect(addr.as_str()))
+        } else {
+            return None;
+        };
+        let transport = transport.unwrap_or_else(|err| error!("netplay: {}", err));
+        if verbose {
+            match role {
+                Role::Host => println!("[info] Waiting for a netplay client"),
+                Role::Client => println!("[info] Connecting to the netplay host"),
+            }
+        }
+        let session = match options.netplay_rollback {
+            Some(max_rollback) => Kind::Rollback(RollbackSession::new(
+                transport,
+                RollbackConfig {
+                    input_delay: options.input_delay,
+                    max_rollback,
+                    ..RollbackConfig::new(role)
+                },
+            )),
+            None => Kind::Lockstep(Session::new(
+                transport,
+                NetplayConfig {
+                    input_delay: options.input_delay,
+                    ..NetplayConfig::new(role)
+                },
+            )),
+        };
+        Some(Self {
+            session,
+            verbose,
+            was_connected: false,
+            correction_count: 0,
+        })
+    }
+
+    /// The index of the controller port driven by the local player
+    pub fn local_port(&self) -> usize {
+        match &self.session {
+            Kind::Lockstep(session) => session.config().role.port(),
+            Kind::Rollback(session) => session.config().role.port(),
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        match &self.session {
+            Kind::Lockstep(session) => session.is_connected(),
+            Kind::Rollback(session) => session.is_connected(),
+        }
+    }
+
+    fn transport(&self) -> &UdpTransport {
+        match &self.session {
+            Kind::Lockstep(session) => session.transport(),
+            Kind::Rollback(session) => session.transport(),
+        }
+    }
+
+    /// Amount of resyncs in lockstep sessions, amount of rollbacks otherwise
+    fn correction_count(&self) -> u64 {
+        match &self.session {
+            Kind::Lockstep(session) => session.resync_count().into(),
+            Kind::Rollback(session) => session.rollback_count(),
+        }
+    }
+
+    /// Exchange the inputs of the next frame.
+    /// Returns false if the frame must not be emulated yet.
+    pub fn advance<B: AudioBackend, FB: FrameBuffer>(&mut self, snes: &mut Device<B, FB>) -> bool {
+        let local = match self.local_port() {
+            0 => &snes.controllers.port1,
+            _ => &snes.controllers.port2,
+        };
+        let local = PortInput::capture(&local.controller);
+        let ready: Result<bool, NetplayError> = match &mut self.session {
+            Kind::Lockstep(session) => session.advance(snes, local),
+            Kind::Rollback(session) => session.advance(snes, local, |snes| {
+                crate::emulate_frame(snes);
+            }),
+        };
+        let ready = ready.unwrap_or_else(|err| error!("netplay: {}", err));
+        if self.verbose {
+            if !self.was_connected && self.is_connected() {
+                let peer = self.transport().peer();
+                println!("[info] Netplay peer {} connected", peer.unwrap());
+            }
+            if let Kind::Lockstep(session) = &self.session {
+                if self.correction_count != self.correction_count() {
+                    println!(
+                        "[info] Netplay resynchronized after a desync at frame {}",
+                        session.frame()
+                    );
+                }
+            }
+        }
+        self.was_connected = self.is_connected();
+        self.correction_count = self.correction_count();
+        ready
+    }
+}
@@ -0,0 +1,253 @@
+//! Text drawn on top of the emulated picture
+//!
+//! Text is drawn with a 3x5 pixel font into a frame buffer.
+//! Lowercase letters are drawn as uppercase letters and unknown
+//! characters as `?`.
+//!
+//! Scripts draw directly into the frame buffer of the emulated picture,
+//! while the [`Overlay`] with the frame rate and messages is drawn
+//! into a copy right before the picture gets displayed.
+
+use rsnes::{
+    backend::{ArrayFrameBuffer, FrameBuffer, FRAME_BUFFER_SIZE},
+    controller::{buttons, ButtonState, InputFrame},
+    device::Region,
+    ppu::{MAX_SCREEN_HEIGHT, SCREEN_WIDTH},
+};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+pub const GLYPH_WIDTH: i32 = 3;
+pub const GLYPH_HEIGHT: i32 = 5;
+const TEXT_COLOR: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+const BACKGROUND_COLOR: [u8; 4] = [0, 0, 0, 0xff];
+
+/// Glyphs of the characters ` ` to `Z`, a row is stored in the lower 3 bits
+#[rustfmt::skip]
+const FONT: [[u8; 5]; 59] = [
+    [0, 0, 0, 0, 0], [2, 2, 2, 0, 2], [5, 5, 0, 0, 0], [5, 7, 5, 7, 5], // ␣!"#
+    [3, 6, 7, 3, 6], [5, 1, 2, 4, 5], [2, 5, 2, 5, 3], [2, 2, 0, 0, 0], // $%&'
+    [1, 2, 2, 2, 1], [4, 2, 2, 2, 4], [0, 5, 2, 5, 0], [0, 2, 7, 2, 0], // ()*+
+    [0, 0, 0, 2, 4], [0, 0, 7, 0, 0], [0, 0, 0, 0, 2], [1, 1, 2, 4, 4], // ,-./
+    [7, 5, 5, 5, 7], [2, 6, 2, 2, 7], [7, 1, 7, 4, 7], [7, 1, 7, 1, 7], // 0123
+    [5, 5, 7, 1, 1], [7, 4, 7, 1, 7], [7, 4, 7, 5, 7], [7, 1, 1, 1, 1], // 4567
+    [7, 5, 7, 5, 7], [7, 5, 7, 1, 7], [0, 2, 0, 2, 0], [0, 2, 0, 2, 4], // 89:;
+    [1, 2, 4, 2, 1], [0, 7, 0, 7, 0], [4, 2, 1, 2, 4], [7, 1, 3, 0, 2], // <=>?
+    [7, 5, 7, 4, 7], [2, 5, 7, 5, 5], [6, 5, 6, 5, 6], [3, 4, 4, 4, 3], // @ABC
+    [6, 5, 5, 5, 6], [7, 4, 6, 4, 7], [7, 4, 6, 4, 4], [3, 4, 5, 5, 3], // DEFG
+    [5, 5, 7, 5, 5], [7, 2, 2, 2, 7], [1, 1, 1, 5, 2], [5, 5, 6, 5, 5], // HIJK
+    [4, 4, 4, 4, 7], [5, 7, 7, 5, 5], [6, 5, 5, 5, 5], [2, 5, 5, 5, 2], // LMNO
+    [6, 5, 6, 4, 4], [2, 5, 5, 6, 3], [6, 5, 6, 5, 5], [3, 4, 2, 1, 6], // PQRS
+    [7, 2, 2, 2, 2], [5, 5, 5, 5, 7], [5, 5, 5, 5, 2], [5, 5, 7, 7, 5], // TUVW
+    [5, 5, 2, 5, 5], [5, 5, 2, 2, 2], [7, 1, 2, 4, 7],                  // XYZ
+];
+
+fn glyph(c: char) -> &'static [u8; 5] {
+    let index = (c.to_ascii_uppercase() as usize).wrapping_sub(' ' as usize);
+    FONT.get(index).unwrap_or(&FONT[usize::from(b'?' - b' ')])
+}
+
+/// Draw a line of text on a black background.
+///
+/// The coordinates are given in pixels of a 256x224 picture
+/// and get scaled in high-resolution and interlace modes.
+pub fn draw_text(frame_buffer: &mut ArrayFrameBuffer, x: i32, y: i32, text: &str) {
+    let size = frame_buffer.size();
+    let scale_x = (size.width / SCREEN_WIDTH).max(1) as i32;
+    let scale_y = (size.height / MAX_SCREEN_HEIGHT).max(1) as i32;
+    let (width, height) = (size.width as i32, size.height as i32);
+    let pixels = frame_buffer.mut_pixels();
+    let mut set_pixel = |px: i32, py: i32, color| {
+        for (dx, dy) in (0..scale_x).flat_map(|dx| (0..scale_y).map(move |dy| (dx, dy))) {
+            let (px, py) = (px * scale_x + dx, py * scale_y + dy);
+            if (0..width).contains(&px) && (0..height).contains(&py) {
+                pixels[(py * width + px) as usize] = color
+            }
+        }
+    };
+    for (i, c) in text.chars().enumerate() {
+        let left = x + i as i32 * (GLYPH_WIDTH + 1);
+        for row in -1..=GLYPH_HEIGHT {
+            for col in -1..=GLYPH_WIDTH {
+                let bits = usize::try_from(row)
+                    .ok()
+                    .and_then(|row| glyph(c).get(row))
+                    .copied()
+                    .unwrap_or(0);
+                let lit = (0..GLYPH_WIDTH).contains(&col) && bits & (4 >> col) != 0;
+                let color = if lit { TEXT_COLOR } else { BACKGROUND_COLOR };
+                set_pixel(left + col, y + row, color)
+            }
+        }
+    }
+}
+
+/// Time a message is shown
+const MESSAGE_DURATION: Duration = Duration::from_secs(3);
+/// The maximum amount of messages shown at once
+const MESSAGE_LINES: usize = 4;
+/// Time over which the frame rate and emulation speed are averaged
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The symbols of the buttons in the input display, the font has no arrows
+const BUTTON_SYMBOLS: [(u16, &str); 12] = [
+    (buttons::LEFT, "<"),
+    (buttons::RIGHT, ">"),
+    (buttons::UP, "U"),
+    (buttons::DOWN, "D"),
+    (buttons::L, "L"),
+    (buttons::R, "R"),
+    (buttons::Y, "Y"),
+    (buttons::B, "B"),
+    (buttons::X, "X"),
+    (buttons::A, "A"),
+    (buttons::SELECT, "SE"),
+    (buttons::START, "ST"),
+];
+
+/// A line of the input display, released buttons are shown as dots
+fn input_line(label: &str, state: ButtonState) -> String {
+    let mut line = label.to_owned();
+    for (button, symbol) in BUTTON_SYMBOLS {
+        line.push(' ');
+        if state.is_pressed(button) {
+            line.push_str(symbol)
+        } else {
+            line.extend(symbol.chars().map(|_| '.'))
+        }
+    }
+    line
+}
+
+/// Frame rate, emulation speed and messages drawn on top of the picture.
+///
+/// The text is drawn into a copy of the frame buffer, so that
+/// save state thumbnails and scripts only see the emulated picture.
+pub struct Overlay {
+    show_stats: bool,
+    region: Region,
+    messages: VecDeque<(String, Instant)>,
+    frames: u32,
+    cycles: u64,
+    interval_start: Instant,
+    /// Frames per second and emulation speed of the last interval
+    stats: Option<(f64, f64)>,
+    show_input: bool,
+    input: InputFrame,
+    buffer: Box<ArrayFrameBuffer>,
+}
+
+impl Overlay {
+    pub fn new(show_stats: bool, region: Region) -> Self {
+        Self {
+            show_stats,
+            region,
+            messages: VecDeque::new(),
+            frames: 0,
+            cycles: 0,
+            interval_start: Instant::now(),
+            stats: None,
+            show_input: false,
+            input: Default::default(),
+            buffer: Box::default(),
+        }
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.show_stats = !self.show_stats
+    }
+
+    pub fn toggle_input(&mut self) {
+        self.show_input = !self.show_input
+    }
+
+    /// Set the inputs shown by the input display
+    pub fn set_input(&mut self, input: InputFrame) {
+        self.input = input
+    }
+
+    /// Show a message for a few seconds
+    pub fn show_message(&mut self, message: impl Into<String>) {
+        if self.messages.len() >= MESSAGE_LINES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((message.into(), Instant::now()))
+    }
+
+    /// Count an emulated frame, which took `cycles` master cycles
+    pub fn frame_done(&mut self, cycles: u64, now: Instant) {
+        if cycles == 0 {
+            return;
+        }
+        self.frames += 1;
+        self.cycles += cycles;
+        let elapsed = now.saturating_duration_since(self.interval_start);
+        if elapsed >= STATS_INTERVAL {
+            let secs = elapsed.as_secs_f64();
+            let emulated = self.region.master_cycles_to_nanos(self.cycles) as f64 * 1e-9;
+            self.stats = Some((f64::from(self.frames) / secs, emulated / secs));
+            self.frames = 0;
+            self.cycles = 0;
+            self.interval_start = now;
+        }
+    }
+
+    /// Draw the overlay on top of `frame_buffer`.
+    /// Returns the frame buffer itself if there is nothing to draw.
+    pub fn compose<'a>(
+        &'a mut self,
+        frame_buffer: &'a ArrayFrameBuffer,
+        now: Instant,
+    ) -> &'a ArrayFrameBuffer {
+        while let Some((_, shown)) = self.messages.front() {
+            if now.saturating_duration_since(*shown) < MESSAGE_DURATION {
+                break;
+            }
+            self.messages.pop_front();
+        }
+        if !self.show_stats && !self.show_input && self.messages.is_empty() {
+            return frame_buffer;
+        }
+        let len = frame_buffer.size().pixel_count().min(FRAME_BUFFER_SIZE);
+        self.buffer.0[..len].copy_from_slice(&frame_buffer.0[..len]);
+        self.buffer.set_size(frame_buffer.size());
+        if self.show_stats {
+            let text = match self.stats {
+                Some((fps, speed)) => format!("{:.1} FPS {:.0}%", fps, speed * 100.0),
+                None => "- FPS".to_owned(),
+            };
+            draw_text(&mut self.buffer, 2, 2, &text);
+        }
+        let line_height = GLYPH_HEIGHT + 2;
+        if self.show_input {
+            // the held keys above the buttons seen by the console
+            // for every port with a standard controller
+            let lines = (self.input.raw.iter().zip(self.input.effective))
+                .enumerate()
+                .filter_map(|(port, (raw, effective))| Some((port, *raw, effective?)))
+                .flat_map(|(port, raw, effective)| {
+                    [
+                        input_line(&format!("{}IN ", port + 1), raw),
+                        input_line(&format!("{}OUT", port + 1), effective),
+                    ]
+                });
+            for (i, line) in lines.enumerate() {
+                let width = line.len() as i32 * (GLYPH_WIDTH + 1);
+                let x = SCREEN_WIDTH as i32 - 2 - width;
+                draw_text(&mut self.buffer, x, 2 + i as i32 * line_height, &line);
+            }
+        }
+        let bottom = MAX_SCREEN_HEIGHT as i32 - 2 - GLYPH_HEIGHT;
+        for (i, (message, _)) in self.messages.iter().rev().enumerate() {
+            draw_text(
+                &mut self.buffer,
+                2,
+                bottom - i as i32 * line_height,
+                message,
+            );
+        }
+        &self.buffer
+    }
+}
@@ -0,0 +1,145 @@
+//! Frame pacing of the emulation
+//!
+//! The emulation can be clocked by one of three sources:
+//!
+//! - `timer`: frames are emulated at the rate of the emulated console,
+//!   measured with the system clock
+//! - `audio`: a frame is emulated whenever the audio buffer runs
+//!   below half of its capacity, so the audio device is the master clock
+//! - `vsync`: exactly one frame is emulated per displayed frame,
+//!   the presentation of frames blocks until the next vertical blank
+
+use rsnes::device::Region;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// The timer gets reset, if the emulation falls behind by this amount of time
+const TIME_UNTIL_TIMER_RESET: Duration = Duration::from_millis(500);
+/// The time base is moved forward after this amount of master cycles
+/// to prevent an overflow in the conversion to nanoseconds
+const REBASE_CYCLES: u64 = 1 << 26;
+/// The audio buffer fill level the `audio` sync mode aims for
+const AUDIO_TARGET_FILL_LEVEL: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    Timer,
+    Audio,
+    Vsync,
+}
+
+impl SyncMode {
+    pub const NAMES: [&'static str; 3] = ["timer", "audio", "vsync"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "timer" => Some(Self::Timer),
+            "audio" => Some(Self::Audio),
+            "vsync" => Some(Self::Vsync),
+            _ => None,
+        }
+    }
+}
+
+/// Shared fill level of the audio ring buffer.
+///
+/// It is updated by both the emulation and the audio callback.
+#[derive(Debug, Clone)]
+pub struct AudioFill {
+    len: Arc<AtomicUsize>,
+    capacity: usize,
+}
+
+impl AudioFill {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            len: Arc::new(AtomicUsize::new(0)),
+            capacity,
+        }
+    }
+
+    pub fn store(&self, len: usize) {
+        self.len.store(len, Ordering::Relaxed)
+    }
+
+    /// Fill level in the range `0.0..=1.0`
+    pub fn level(&self) -> f32 {
+        self.len.load(Ordering::Relaxed) as f32 / self.capacity.max(1) as f32
+    }
+}
+
+#[derive(Debug)]
+pub struct Pacer {
+    mode: SyncMode,
+    audio_fill: AudioFill,
+    region: Region,
+    speed: f32,
+    /// Point in time, when `cycles` got reset
+    base: Instant,
+    /// Master cycles emulated since `base`
+    cycles: u64,
+}
+
+impl Pacer {
+    pub fn new(mode: SyncMode, audio_fill: AudioFill, region: Region) -> Self {
+        Self {
+            mode,
+            audio_fill,
+            region,
+            speed: 1.0,
+            base: Instant::now(),
+            cycles: 0,
+        }
+    }
+
+    pub const fn mode(&self) -> SyncMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: SyncMode) {
+        self.mode = mode;
+        self.base = Instant::now();
+        self.cycles = 0;
+    }
+
+    fn deadline(&self) -> Instant {
+        let nanos = self.region.master_cycles_to_nanos(self.cycles);
+        self.base + Duration::from_nanos(nanos).div_f32(self.speed)
+    }
+
+    /// Check if the next frame shall be emulated now.
+    ///
+    /// In `vsync` mode frames are emulated on redraw, so this always returns false.
+    pub fn is_frame_due(&self, now: Instant) -> bool {
+        match self.mode {
+            SyncMode::Timer => now >= self.deadline(),
+            SyncMode::Audio => self.audio_fill.level() < AUDIO_TARGET_FILL_LEVEL,
+            SyncMode::Vsync => false,
+        }
+    }
+
+    /// Notify the pacer about an emulated frame
+    pub fn frame_done(&mut self, cycles: u64, speed: f32, now: Instant) {
+        if speed != self.speed {
+            // start a new time base, so that previous frames
+            // are not rescaled with the new speed
+            self.base = self.deadline();
+            self.cycles = 0;
+            self.speed = speed;
+        }
+        self.cycles += cycles;
+        if self.cycles >= REBASE_CYCLES {
+            self.base = self.deadline();
+            self.cycles = 0;
+        }
+        if now > self.deadline() + TIME_UNTIL_TIMER_RESET {
+            self.base = now;
+            self.cycles = 0;
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Standalone playback of SPC music files
+//!
+//! Only the SPC700 and the audio backend are used, so
+//! there is neither a cartridge nor a video window.
+
+use crate::{config::DEFAULT_AUDIO_LATENCY, AudioBackend};
+use rsnes::{
+    backend::AudioBackend as _,
+    spc700::{Interpolation, Spc700},
+};
+use std::{path::Path, time::Duration};
+
+/// The SPC700 outputs one stereo sample every 32 cycles
+const SAMPLES_PER_SECOND: u64 = 32000;
+
+pub fn play_spc(path: &Path, verbose: bool, interpolation: Interpolation) -> ! {
+    let content = std::fs::read(path)
+        .unwrap_or_else(|err| error!("Could not read file \"{}\" ({})\n", path.display(), err));
+    let mut spc = Box::new(Spc700::default());
+    let tag = spc.load_spc(&content).unwrap_or_else(|err| {
+        error!(
+            "Failure while reading SPC file \"{}\" ({})\n",
+            path.display(),
+            err
+        )
+    });
+    spc.dsp_mut().set_interpolation(interpolation);
+    if let Some(tag) = &tag {
+        spc.dsp_mut().set_channel_mask(!tag.muted_channels);
+        println!("Playing \"{}\" from \"{}\"", tag.song_title, tag.game_title);
+        if verbose {
+            println!("[info] ID666 tag: {:#?}", tag);
+        }
+    }
+    // the song is played in an endless loop, if the tag does not specify a length
+    let (play_len, fade_len) = tag
+        .filter(|tag| tag.play_seconds > 0)
+        .map(|tag| {
+            (
+                u64::from(tag.play_seconds) * SAMPLES_PER_SECOND,
+                u64::from(tag.fade_millis) * SAMPLES_PER_SECOND / 1000,
+            )
+        })
+        .unwrap_or((u64::MAX, 0));
+
+    let (mut audio_backend, _audio_stream) =
+        AudioBackend::new(Duration::from_millis(DEFAULT_AUDIO_LATENCY.into()))
+            .unwrap_or_else(|| error!("Failed finding an audio output device"));
+    let mut sample_count = 0u64;
+    while sample_count < play_len.saturating_add(fade_len) {
+        let sample = match spc.run_cycle() {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let sample = if sample_count >= play_len {
+            let volume = (play_len + fade_len - sample_count) as i32;
+            sample.map(|s| (i32::from(s) * volume / fade_len as i32) as i16)
+        } else {
+            sample
+        };
+        while audio_backend.producer.remaining() < 2 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        audio_backend.push_sample(sample);
+        sample_count += 1;
+    }
+    // let the audio buffer run empty
+    while !audio_backend.producer.is_empty() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    std::process::exit(0)
+}
@@ -0,0 +1,77 @@
+//! The list of recently played cartridge files
+//!
+//! The list is stored next to the configuration file in
+//! `$HOME/.config/rsnes/recent.txt` with one path per line,
+//! the most recently played file comes first.
+
+use std::path::{Path, PathBuf};
+
+/// The maximum amount of files in the list
+pub const MAX_RECENT: usize = 10;
+
+#[derive(Debug, Clone, Default)]
+pub struct RecentFiles {
+    files: Vec<PathBuf>,
+}
+
+impl RecentFiles {
+    pub fn path() -> Option<PathBuf> {
+        crate::config::Config::default_config_path()
+            .and_then(|path| path.parent().map(|dir| dir.join("recent.txt")))
+    }
+
+    /// Load the list, a missing list is empty
+    pub fn load() -> Self {
+        let content = Self::path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .unwrap_or_default();
+        Self {
+            files: content
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(PathBuf::from)
+                .take(MAX_RECENT)
+                .collect(),
+        }
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The n-th most recently played file, starting with 1
+    pub fn get(&self, n: usize) -> Option<&Path> {
+        n.checked_sub(1)
+            .and_then(|i| self.files.get(i))
+            .map(PathBuf::as_path)
+    }
+
+    /// Move a file to the front of the list and write the list to disk
+    pub fn add(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        self.files.retain(|file| file != &path);
+        self.files.insert(0, path);
+        self.files.truncate(MAX_RECENT);
+        if let Err(err) = self.store() {
+            eprintln!("[warning] Could not store the recent files ({})", err)
+        }
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut content = String::new();
+        for file in &self.files {
+            if let Some(file) = file.to_str() {
+                content.push_str(file);
+                content.push('\n');
+            }
+        }
+        std::fs::write(path, content)
+    }
+}
@@ -0,0 +1,251 @@
+//! Lossless recording of the emulated picture and sound
+//!
+//! A recording is either written as a directory with one PPM image per
+//! emulated frame and the sound in `audio.wav`, or piped into an `ffmpeg`
+//! process if the output file has the extension of a video container.
+//! In the latter case the sound is written into a temporary WAV file,
+//! which is muxed with the video when the recording is stopped.
+
+use rsnes::{
+    backend::{ArrayFrameBuffer, AudioBackend, FrameSize, WavFileAudioBackend},
+    device::Region,
+    spc700::StereoSample,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{Arc, Mutex},
+};
+
+/// File extensions which are encoded with `ffmpeg`
+pub const VIDEO_EXTENSIONS: [&str; 5] = ["mkv", "mp4", "webm", "mov", "avi"];
+
+/// The samples pushed to the audio backend while a recording is running
+#[derive(Debug, Clone, Default)]
+pub struct SampleTap(Arc<Mutex<Option<Vec<i16>>>>);
+
+impl SampleTap {
+    fn start(&self) {
+        *self.0.lock().unwrap() = Some(Vec::new())
+    }
+
+    fn stop(&self) {
+        *self.0.lock().unwrap() = None
+    }
+
+    /// Take the interleaved stereo samples recorded since the last call
+    fn take(&self) -> Vec<i16> {
+        self.0
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map_or_else(Vec::new, core::mem::take)
+    }
+}
+
+/// An audio backend, which copies all samples into a [`SampleTap`]
+pub struct TapBackend<B: AudioBackend> {
+    pub backend: B,
+    tap: SampleTap,
+}
+
+impl<B: AudioBackend> TapBackend<B> {
+    pub fn new(backend: B, tap: SampleTap) -> Self {
+        Self { backend, tap }
+    }
+}
+
+impl<B: AudioBackend> AudioBackend for TapBackend<B> {
+    fn push_sample(&mut self, sample: StereoSample) {
+        if let Some(samples) = &mut *self.tap.0.lock().unwrap() {
+            samples.extend([sample.l, sample.r])
+        }
+        self.backend.push_sample(sample)
+    }
+
+    fn fill_level(&self) -> Option<f32> {
+        self.backend.fill_level()
+    }
+
+    fn push_voice_samples(&mut self, samples: [i16; 8]) {
+        self.backend.push_voice_samples(samples)
+    }
+}
+
+enum Output {
+    Images {
+        dir: PathBuf,
+    },
+    Ffmpeg {
+        child: Child,
+        stdin: BufWriter<ChildStdin>,
+        /// All frames are scaled to the size of the first frame
+        size: FrameSize,
+        video_path: PathBuf,
+        audio_path: PathBuf,
+        path: PathBuf,
+    },
+}
+
+/// Write the visible picture as a binary PPM image
+pub fn write_ppm(path: &Path, frame_buffer: &ArrayFrameBuffer) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let size = frame_buffer.size();
+    write!(file, "P6\n{} {}\n255\n", size.width, size.height)?;
+    for pixel in &frame_buffer.pixels()[..size.pixel_count()] {
+        file.write_all(&pixel[..3])?
+    }
+    file.flush()
+}
+
+pub struct Recorder {
+    output: Output,
+    audio: WavFileAudioBackend,
+    tap: SampleTap,
+    frames: u64,
+}
+
+impl Recorder {
+    /// Start a recording into `path`, which is either a video file
+    /// or a directory for images, see the module documentation
+    pub fn start(
+        path: &Path,
+        tap: SampleTap,
+        frame_size: FrameSize,
+        region: Region,
+        sample_rate: u32,
+    ) -> std::io::Result<Self> {
+        let is_video = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        let (output, audio_path) = if is_video {
+            // progressive pictures with the horizontal resolution of high resolution modes
+            let lines = if frame_size.height > 240 {
+                frame_size.height / 2
+            } else {
+                frame_size.height
+            };
+            let size = FrameSize {
+                width: 512,
+                height: lines * 2,
+            };
+            let video_path = path.with_extension("video.mkv");
+            let audio_path = path.with_extension("audio.wav");
+            let mut child = Command::new("ffmpeg")
+                .args(["-loglevel", "error", "-y", "-f", "rawvideo"])
+                .args(["-pixel_format", "rgba", "-video_size"])
+                .arg(format!("{}x{}", size.width, size.height))
+                .arg("-framerate")
+                .arg(format!("{:.6}", region.frame_rate()))
+                .args(["-i", "-", "-c:v", "ffv1"])
+                .arg(&video_path)
+                .stdin(Stdio::piped())
+                .spawn()?;
+            let stdin = BufWriter::new(child.stdin.take().unwrap());
+            let output = Output::Ffmpeg {
+                child,
+                stdin,
+                size,
+                video_path,
+                audio_path: audio_path.clone(),
+                path: path.to_owned(),
+            };
+            (output, audio_path)
+        } else {
+            std::fs::create_dir_all(path)?;
+            let dir = path.to_owned();
+            (Output::Images { dir }, path.join("audio.wav"))
+        };
+        let audio = WavFileAudioBackend::create(&audio_path, sample_rate)?;
+        tap.start();
+        Ok(Self {
+            output,
+            audio,
+            tap,
+            frames: 0,
+        })
+    }
+
+    pub const fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Write the samples recorded since the previous frame
+    fn write_audio(&mut self) -> std::io::Result<()> {
+        for sample in self.tap.take().chunks_exact(2) {
+            self.audio
+                .write_sample(StereoSample::<i16>::new(sample[0], sample[1]))?
+        }
+        Ok(())
+    }
+
+    /// Write an emulated frame and the sound since the previous frame
+    pub fn frame(&mut self, frame_buffer: &ArrayFrameBuffer) -> std::io::Result<()> {
+        self.write_audio()?;
+        match &mut self.output {
+            Output::Images { dir } => write_ppm(
+                &dir.join(format!("frame{:06}.ppm", self.frames)),
+                frame_buffer,
+            )?,
+            Output::Ffmpeg { stdin, size, .. } => {
+                let src_size = frame_buffer.size();
+                let pixels = frame_buffer.pixels();
+                for y in 0..size.height {
+                    let row = (y * src_size.height / size.height * src_size.width) as usize;
+                    for x in 0..size.width {
+                        let [r, g, b, _] = pixels[row + (x * src_size.width / size.width) as usize];
+                        stdin.write_all(&[r, g, b, 0xff])?
+                    }
+                }
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Stop the recording and return the path of the written video or directory
+    pub fn finish(mut self) -> std::io::Result<PathBuf> {
+        self.tap.stop();
+        self.write_audio()?;
+        self.audio.finish()?;
+        match self.output {
+            Output::Images { dir } => Ok(dir),
+            Output::Ffmpeg {
+                mut child,
+                mut stdin,
+                video_path,
+                audio_path,
+                path,
+                ..
+            } => {
+                stdin.flush()?;
+                drop(stdin);
+                let video = child.wait()?;
+                // the lossless video is copied if the container supports it,
+                // otherwise it is encoded with the default codec of the container
+                let is_mkv = path.extension().is_some_and(|ext| ext == "mkv");
+                let muxed = video.success()
+                    && Command::new("ffmpeg")
+                        .args(["-loglevel", "error", "-y", "-i"])
+                        .arg(&video_path)
+                        .arg("-i")
+                        .arg(&audio_path)
+                        .args(if is_mkv { &["-c:v", "copy"][..] } else { &[] })
+                        .arg(&path)
+                        .status()?
+                        .success();
+                if !muxed {
+                    return Err(std::io::Error::other(
+                        "ffmpeg failed to encode the recording",
+                    ));
+                }
+                std::fs::remove_file(video_path)?;
+                std::fs::remove_file(audio_path)?;
+                Ok(path)
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Lua scripts with hooks for frames and memory accesses
+//!
+//! Scripts use the functions of the global `emu` table:
+//!
+//! - `emu.on_frame(function() ... end)` registers a function called after every frame
+//! - `emu.on_memory_read(first, last, function(addr, value) ... end)` and
+//!   `emu.on_memory_write(...)` register functions called after every frame
+//!   for each access of the main CPU to an address in `first..=last`
+//! - `emu.read(addr)` and `emu.write(addr, value)` access the memory
+//!   without side effects
+//! - `emu.set_buttons(port, buttons)` overrides the pressed buttons of the
+//!   standard controller at port 1 or 2 in the next frame, the button bits
+//!   are found in `emu.buttons`
+//! - `emu.draw_text(x, y, text)` draws text on top of the current frame
+//! - `emu.frame()` returns the number of frames emulated since the script got loaded
+//!
+//! Addresses are 24-bit numbers like `0x7e0dbe`.
+
+use crate::overlay;
+use mlua::{Function, Lua, RegistryKey, Table};
+use rsnes::{
+    backend::{ArrayFrameBuffer, AudioBackend},
+    controller::{buttons, ButtonState, ControllerPorts},
+    device::{Addr24, Device},
+    watch::{AccessKind, WatchId},
+};
+use std::{cell::RefCell, collections::HashMap, ops::RangeInclusive, path::Path, rc::Rc};
+
+const BUTTONS: [(&str, u16); 12] = [
+    ("B", buttons::B),
+    ("Y", buttons::Y),
+    ("SELECT", buttons::SELECT),
+    ("START", buttons::START),
+    ("UP", buttons::UP),
+    ("DOWN", buttons::DOWN),
+    ("LEFT", buttons::LEFT),
+    ("RIGHT", buttons::RIGHT),
+    ("A", buttons::A),
+    ("X", buttons::X),
+    ("L", buttons::L),
+    ("R", buttons::R),
+];
+
+/// The state shared with the functions of the `emu` table
+#[derive(Default)]
+struct Hooks {
+    frame: Vec<RegistryKey>,
+    memory: HashMap<WatchId, RegistryKey>,
+    /// Memory hooks, whose address ranges are not watched by the device yet
+    pending: Vec<(AccessKind, RangeInclusive<Addr24>, RegistryKey)>,
+    buttons: [Option<u16>; 2],
+    texts: Vec<(i32, i32, String)>,
+}
+
+pub struct Script {
+    lua: Lua,
+    hooks: Rc<RefCell<Hooks>>,
+    frame: u64,
+}
+
+const fn to_addr(addr: u32) -> Addr24 {
+    Addr24::new((addr >> 16) as u8, addr as u16)
+}
+
+const fn from_addr(addr: Addr24) -> u32 {
+    ((addr.bank as u32) << 16) | addr.addr as u32
+}
+
+impl Script {
+    /// Load a script file and run it
+    pub fn load<B: AudioBackend>(
+        path: &Path,
+        snes: &mut Device<B, ArrayFrameBuffer>,
+    ) -> mlua::Result<Self> {
+        let source = std::fs::read_to_string(path).map_err(mlua::Error::external)?;
+        let lua = Lua::new();
+        let hooks = Rc::<RefCell<Hooks>>::default();
+        let emu = lua.create_table()?;
+        let hooks_ref = hooks.clone();
+        emu.set(
+            "on_frame",
+            lua.create_function(move |lua, f: Function| {
+                let key = lua.create_registry_value(f)?;
+                hooks_ref.borrow_mut().frame.push(key);
+                Ok(())
+            })?,
+        )?;
+        for (name, kind) in [
+            ("on_memory_read", AccessKind::Read),
+            ("on_memory_write", AccessKind::Write),
+        ] {
+            let hooks = hooks.clone();
+            emu.set(
+                name,
+                lua.create_function(move |lua, (first, last, f): (u32, u32, Function)| {
+                    let key = lua.create_registry_value(f)?;
+                    let range = to_addr(first)..=to_addr(last);
+                    hooks.borrow_mut().pending.push((kind, range, key));
+                    Ok(())
+                })?,
+            )?;
+        }
+        let hooks_ref = hooks.clone();
+        emu.set(
+            "set_buttons",
+            lua.create_function(move |_, (port, buttons): (usize, u16)| {
+                match hooks_ref.borrow_mut().buttons.get_mut(port.wrapping_sub(1)) {
+                    Some(pressed) => {
+                        *pressed = Some(buttons);
+                        Ok(())
+                    }
+                    None => Err(mlua::Error::RuntimeError(format!(
+                        "invalid controller port {}",
+                        port
+                    ))),
+                }
+            })?,
+        )?;
+        let hooks_ref = hooks.clone();
+        emu.set(
+            "draw_text",
+            lua.create_function(move |_, (x, y, text): (i32, i32, String)| {
+                hooks_ref.borrow_mut().texts.push((x, y, text));
+                Ok(())
+            })?,
+        )?;
+        emu.set("buttons", lua.create_table_from(BUTTONS)?)?;
+        lua.globals().set("emu", emu)?;
+
+        let mut slf = Self {
+            lua,
+            hooks,
+            frame: 0,
+        };
+        let name = path.display().to_string();
+        slf.with_device(snes, |lua| lua.load(&source).set_name(name).exec())?;
+        Ok(slf)
+    }
+
+    /// Run `f` while the memory of the device is accessible by the script
+    fn with_device<B: AudioBackend>(
+        &mut self,
+        snes: &mut Device<B, ArrayFrameBuffer>,
+        f: impl FnOnce(&Lua) -> mlua::Result<()>,
+    ) -> mlua::Result<()> {
+        let frame = self.frame;
+        let device = RefCell::new(&mut *snes);
+        let lua = &self.lua;
+        lua.scope(|scope| {
+            let emu: Table = lua.globals().get("emu")?;
+            emu.set(
+                "read",
+                scope.create_function(|_, addr: u32| Ok(device.borrow().peek(to_addr(addr))))?,
+            )?;
+            emu.set(
+                "write",
+                scope.create_function(|_, (addr, value): (u32, u8)| {
+                    device.borrow_mut().poke(to_addr(addr), value);
+                    Ok(())
+                })?,
+            )?;
+            emu.set("frame", scope.create_function(move |_, ()| Ok(frame))?)?;
+            f(lua)
+        })?;
+        let mut hooks = self.hooks.borrow_mut();
+        let pending = core::mem::take(&mut hooks.pending);
+        for (kind, range, key) in pending {
+            let id = snes.watches_mut().add(kind, range);
+            hooks.memory.insert(id, key);
+        }
+        Ok(())
+    }
+
+    /// Override the pressed buttons for the next frame
+    pub fn before_frame(&mut self, ports: &mut ControllerPorts) {
+        let buttons = core::mem::take(&mut self.hooks.borrow_mut().buttons);
+        for (port, buttons) in buttons.into_iter().enumerate() {
+            if let Some(buttons) = buttons {
+                ports.set_buttons(port, 0, ButtonState(buttons));
+            }
+        }
+    }
+
+    /// Call the hooks after a frame got emulated and draw the texts of the script
+    pub fn after_frame<B: AudioBackend>(
+        &mut self,
+        snes: &mut Device<B, ArrayFrameBuffer>,
+    ) -> mlua::Result<()> {
+        self.frame += 1;
+        let accesses = snes.watches_mut().take_accesses();
+        let hooks = self.hooks.clone();
+        self.with_device(snes, |lua| {
+            for (id, access) in accesses {
+                let f: Function = match hooks.borrow().memory.get(&id) {
+                    Some(key) => lua.registry_value(key)?,
+                    None => continue,
+                };
+                f.call::<_, ()>((from_addr(access.addr), access.value))?;
+            }
+            let frame_hooks = hooks
+                .borrow()
+                .frame
+                .iter()
+                .map(|key| lua.registry_value::<Function>(key))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            for f in frame_hooks {
+                f.call::<_, ()>(())?;
+            }
+            Ok(())
+        })?;
+        for (x, y, text) in self.hooks.borrow_mut().texts.drain(..) {
+            overlay::draw_text(&mut snes.ppu.frame_buffer, x, y, &text)
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+//! Save state slots stored in files next to the cartridge file
+//!
+//! The slot `N` of `game.sfc` is stored in `game.ssN`, or in the
+//! directory for saves of the configuration if one is set.
+//!
+//! # File format
+//!
+//! All numbers are stored in little endian.
+//!
+//! | Size      | Description                                            |
+//! |-----------|--------------------------------------------------------|
+//! | 8         | Magic bytes `RSNESSAV`                                 |
+//! | 2         | Format version (currently 2)                           |
+//! | 8         | Creation time in seconds since the unix epoch          |
+//! | 8 + n     | Length and UTF-8 bytes of the cartridge title          |
+//! | 8         | Movie frame the state was created at                   |
+//! | 2         | Thumbnail width                                        |
+//! | 2         | Thumbnail height                                       |
+//! | 4 * w * h | Thumbnail pixels in RGBA format                        |
+//! | 8 + n     | Length and bytes of the save state                     |
+//!
+//! The save state is stored in the format of the `save-state` crate, whose
+//! structs are blocks of tagged fields. Version 1 stored the fields of the
+//! save state without tags, these files are rejected as outdated.
+
+use rsnes::backend::ArrayFrameBuffer;
+use save_state::{InSaveState, SaveStateSerializer};
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const MAGIC: &[u8; 8] = b"RSNESSAV";
+pub const FORMAT_VERSION: u16 = 2;
+pub const SLOT_COUNT: usize = 10;
+
+#[derive(Debug)]
+pub enum StateFileError {
+    InvalidMagic,
+    OutdatedVersion(u16),
+    UnsupportedVersion(u16),
+    Truncated,
+}
+
+impl std::fmt::Display for StateFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "not a save state file (invalid magic bytes)"),
+            Self::OutdatedVersion(version) => {
+                write!(f, "outdated save state format version {}", version)
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state format version {}", version)
+            }
+            Self::Truncated => write!(f, "save state file is truncated"),
+        }
+    }
+}
+
+/// A downscaled screenshot
+#[derive(Debug, Clone, Default)]
+pub struct Thumbnail {
+    pub width: u16,
+    pub height: u16,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Thumbnail {
+    /// Downscale the visible picture by averaging blocks of pixels.
+    /// Every picture size results in a width of 64 pixels.
+    pub fn from_frame_buffer(frame_buffer: &ArrayFrameBuffer) -> Self {
+        let size = frame_buffer.size();
+        let pixels = frame_buffer.pixels();
+        // high resolution and interlaced pictures have twice the size
+        let scale = |len: u32| if len > 256 { 8 } else { 4 };
+        let (sx, sy) = (scale(size.width), scale(size.height));
+        let (width, height) = (size.width / sx, size.height / sy);
+        let mut thumbnail = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0u32; 4];
+                for py in y * sy..(y + 1) * sy {
+                    let row = (py * size.width) as usize;
+                    for px in x * sx..(x + 1) * sx {
+                        let pixel = pixels[row + px as usize];
+                        for (s, p) in sum.iter_mut().zip(pixel) {
+                            *s += u32::from(p)
+                        }
+                    }
+                }
+                thumbnail.push(sum.map(|s| (s / (sx * sy)) as u8));
+            }
+        }
+        Self {
+            width: width as u16,
+            height: height as u16,
+            pixels: thumbnail,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SlotFile {
+    /// Creation time in seconds since the unix epoch
+    pub timestamp: u64,
+    pub rom_title: String,
+    /// The movie frame the state was created at, see [`crate::movie::MovieSession::frame`]
+    pub movie_frame: u64,
+    pub thumbnail: Thumbnail,
+    pub state: Vec<u8>,
+}
+
+/// Bounds checked reader of a slot file
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], StateFileError> {
+        if self.data.len() < n {
+            return Err(StateFileError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, StateFileError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateFileError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn vec(&mut self) -> Result<&'a [u8], StateFileError> {
+        let len = self.u64()?;
+        self.bytes(len.try_into().map_err(|_| StateFileError::Truncated)?)
+    }
+}
+
+impl SlotFile {
+    pub fn new(rom_title: &str, movie_frame: usize, thumbnail: Thumbnail, state: Vec<u8>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        Self {
+            timestamp,
+            rom_title: rom_title.to_owned(),
+            movie_frame: movie_frame as u64,
+            thumbnail,
+            state,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = SaveStateSerializer::new();
+        out.write(MAGIC);
+        FORMAT_VERSION.serialize(&mut out);
+        self.timestamp.serialize(&mut out);
+        self.rom_title.serialize(&mut out);
+        self.movie_frame.serialize(&mut out);
+        self.thumbnail.width.serialize(&mut out);
+        self.thumbnail.height.serialize(&mut out);
+        out.write(self.thumbnail.pixels.as_flattened());
+        self.state.len().serialize(&mut out);
+        out.write(&self.state);
+        out.into_vec()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateFileError> {
+        let mut r = Reader { data: bytes };
+        if r.bytes(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(StateFileError::InvalidMagic);
+        }
+        let version = r.u16()?;
+        if version < FORMAT_VERSION {
+            return Err(StateFileError::OutdatedVersion(version));
+        } else if version > FORMAT_VERSION {
+            return Err(StateFileError::UnsupportedVersion(version));
+        }
+        let timestamp = r.u64()?;
+        let rom_title = String::from_utf8_lossy(r.vec()?).into_owned();
+        let movie_frame = r.u64()?;
+        let (width, height) = (r.u16()?, r.u16()?);
+        let pixels = r
+            .bytes(4 * usize::from(width) * usize::from(height))?
+            .chunks_exact(4)
+            .map(|p| [p[0], p[1], p[2], p[3]])
+            .collect();
+        let state = r.vec()?.to_vec();
+        Ok(Self {
+            timestamp,
+            rom_title,
+            movie_frame,
+            thumbnail: Thumbnail {
+                width,
+                height,
+                pixels,
+            },
+            state,
+        })
+    }
+}
+
+/// The save state slots of a cartridge
+pub struct Slots {
+    rom_path: PathBuf,
+    slots: [Option<SlotFile>; SLOT_COUNT],
+    verbose: bool,
+}
+
+impl Slots {
+    /// Load all existing slot files of the cartridge.
+    /// Files of other cartridges with the same file name are ignored.
+    pub fn load(rom_path: &Path, rom_title: &str, verbose: bool) -> Self {
+        let mut slots = [(); SLOT_COUNT].map(|()| None);
+        for (id, slot) in slots.iter_mut().enumerate() {
+            let path = slot_path(rom_path, id);
+            let content = match std::fs::read(&path) {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+            match SlotFile::from_bytes(&content) {
+                Ok(file) if file.rom_title == rom_title => *slot = Some(file),
+                Ok(_) => eprintln!(
+                    "[warning] ignoring \"{}\", it belongs to another cartridge",
+                    path.display()
+                ),
+                Err(err) => eprintln!("[warning] ignoring \"{}\" ({})", path.display(), err),
+            }
+        }
+        if verbose {
+            let count = slots.iter().flatten().count();
+            println!("[info] Loaded {} save state slots from disk", count);
+        }
+        Self {
+            rom_path: rom_path.to_owned(),
+            slots,
+            verbose,
+        }
+    }
+
+    pub fn get(&self, id: usize) -> Option<&SlotFile> {
+        self.slots[id].as_ref()
+    }
+
+    /// Store a slot and write it to disk
+    pub fn store(&mut self, id: usize, file: SlotFile) {
+        let path = slot_path(&self.rom_path, id);
+        match std::fs::write(&path, file.to_bytes()) {
+            Ok(()) if self.verbose => println!("[info] Saved state to \"{}\"", path.display()),
+            Ok(()) => (),
+            Err(err) => eprintln!(
+                "[error] Could not write save state \"{}\" ({})",
+                path.display(),
+                err
+            ),
+        }
+        self.slots[id] = Some(file);
+    }
+}
+
+/// The path of the file storing a slot of a cartridge
+pub fn slot_path(rom_path: &Path, id: usize) -> PathBuf {
+    rom_path.with_extension(format!("ss{}", id))
+}
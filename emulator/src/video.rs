@@ -0,0 +1,171 @@
+//! Post-processing of the emulated picture
+//!
+//! The picture is drawn by the fragment shader with one of several
+//! filters. The size of the picture on the screen depends on the
+//! selected aspect ratio and whether only integer multiples of the
+//! emulated resolution are allowed.
+
+use rsnes::{backend::FrameSize, ppu::SCREEN_WIDTH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Sharp pixels
+    Nearest,
+    /// Linear interpolation between the pixels
+    Bilinear,
+    /// Dark gaps between the scanlines
+    Scanlines,
+    /// An approximation of a CRT television with a curved screen,
+    /// scanlines and a shadow mask
+    Crt,
+}
+
+impl Filter {
+    pub const NAMES: [&'static str; 4] = ["nearest", "bilinear", "scanlines", "crt"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "nearest" => Some(Self::Nearest),
+            "bilinear" => Some(Self::Bilinear),
+            "scanlines" => Some(Self::Scanlines),
+            "crt" => Some(Self::Crt),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        Self::NAMES[self as usize]
+    }
+
+    /// The next filter, used to cycle through the filters with a hotkey
+    pub const fn next(self) -> Self {
+        match self {
+            Self::Nearest => Self::Bilinear,
+            Self::Bilinear => Self::Scanlines,
+            Self::Scanlines => Self::Crt,
+            Self::Crt => Self::Nearest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AspectRatio {
+    /// Square pixels, the 256x224 picture has an aspect ratio of 8:7
+    SquarePixels,
+    /// The picture is stretched to 4:3 like on a television
+    Television,
+}
+
+impl AspectRatio {
+    pub const NAMES: [&'static str; 2] = ["8:7", "4:3"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "8:7" => Some(Self::SquarePixels),
+            "4:3" => Some(Self::Television),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        Self::NAMES[self as usize]
+    }
+
+    pub const fn toggle(self) -> Self {
+        match self {
+            Self::SquarePixels => Self::Television,
+            Self::Television => Self::SquarePixels,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoOptions {
+    pub filter: Filter,
+    pub aspect_ratio: AspectRatio,
+    /// Only scale the picture by integer factors
+    pub integer_scaling: bool,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        Self {
+            filter: Filter::Nearest,
+            aspect_ratio: AspectRatio::SquarePixels,
+            integer_scaling: false,
+        }
+    }
+}
+
+/// Size of the uniform buffer read by the shaders
+pub const UNIFORM_SIZE: u64 = 32;
+
+impl VideoOptions {
+    /// The size of the picture in pixels of a non-interlaced,
+    /// low-resolution picture, corrected by the aspect ratio
+    fn picture_size(&self, frame_size: FrameSize) -> [f32; 2] {
+        let lines = if frame_size.height > 240 {
+            frame_size.height / 2
+        } else {
+            frame_size.height
+        } as f32;
+        let width = match self.aspect_ratio {
+            AspectRatio::SquarePixels => SCREEN_WIDTH as f32,
+            AspectRatio::Television => lines * 4.0 / 3.0,
+        };
+        [width, lines]
+    }
+
+    /// The size of the picture on the screen in pixels
+    pub fn output_size(&self, screen: [u32; 2], frame_size: FrameSize) -> [f32; 2] {
+        let [width, height] = self.picture_size(frame_size);
+        let [screen_width, screen_height] = screen.map(|v| v.max(1) as f32);
+        let mut scale = (screen_width / width).min(screen_height / height);
+        if self.integer_scaling && scale >= 1.0 {
+            scale = scale.floor()
+        }
+        [width * scale, height * scale]
+    }
+
+    /// The contents of the uniform buffer of the shaders, see [`uniform_bytes`]
+    pub fn uniform_data(&self, screen: [u32; 2], frame_size: FrameSize) -> [u8; 32] {
+        let output = self.output_size(screen, frame_size);
+        let source = [frame_size.width as f32, frame_size.height as f32];
+        uniform_bytes(screen, output, source, self.filter)
+    }
+}
+
+/// The contents of the uniform buffer of the shaders
+///
+/// | Type    | Description                                          |
+/// |---------|------------------------------------------------------|
+/// | `vec2`  | Size of the picture in normalized device coordinates |
+/// | `vec2`  | Size of the picture on the screen in pixels          |
+/// | `vec2`  | Size of the emulated picture in pixels               |
+/// | `uint`  | The selected [`Filter`]                              |
+fn uniform_bytes(screen: [u32; 2], output: [f32; 2], source: [f32; 2], filter: Filter) -> [u8; 32] {
+    let scale = [
+        output[0] / screen[0].max(1) as f32,
+        output[1] / screen[1].max(1) as f32,
+    ];
+    let mut data = [0; 32];
+    for (i, val) in scale.into_iter().chain(output).chain(source).enumerate() {
+        data[i * 4..i * 4 + 4].copy_from_slice(&val.to_ne_bytes())
+    }
+    data[24..28].copy_from_slice(&(filter as u32).to_ne_bytes());
+    data
+}
+
+/// The contents of the uniform buffer for an image of `size` pixels,
+/// which is scaled to fit the screen with sharp pixels
+pub fn image_uniform_data(screen: [u32; 2], size: [u32; 2]) -> [u8; 32] {
+    let [width, height] = size.map(|v| v.max(1) as f32);
+    let [screen_width, screen_height] = screen.map(|v| v.max(1) as f32);
+    let scale = (screen_width / width).min(screen_height / height);
+    uniform_bytes(
+        screen,
+        [width * scale, height * scale],
+        [width, height],
+        Filter::Nearest,
+    )
+}
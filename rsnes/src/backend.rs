@@ -3,6 +3,20 @@ mod audio {
 
     pub trait AudioBackend: Send + 'static {
         fn push_sample(&mut self, sample: StereoSample);
+
+        /// Fill level of the output buffer in the range `0.0..=1.0`, if it is known.
+        ///
+        /// This is used by [`crate::sync::RateControl`] to adjust the sample rate.
+        fn fill_level(&self) -> Option<f32> {
+            None
+        }
+
+        /// Receive the outputs of the 8 voices of the DSP after their envelopes
+        /// were applied and before they are mixed, once per emulated sample.
+        ///
+        /// This is meant for visualizations of the voices like oscilloscopes,
+        /// see [`crate::spc700::Dsp::voice_outputs`].
+        fn push_voice_samples(&mut self, _samples: [i16; 8]) {}
     }
     pub struct Dummy;
 
@@ -13,31 +27,392 @@ mod audio {
 
 pub use audio::{AudioBackend, Dummy as AudioDummy};
 
+mod wav {
+    use super::AudioBackend;
+    use crate::spc700::StereoSample;
+    use std::{
+        fs::File,
+        io::{self, BufWriter, Seek, SeekFrom, Write},
+        path::Path,
+    };
+
+    /// An audio backend, which writes the samples into a 16-bit stereo WAV file.
+    ///
+    /// The sizes in the header are written when the backend is finished or
+    /// dropped. Errors of [`AudioBackend::push_sample`] are kept and returned by
+    /// [`Self::finish`], the following samples are discarded.
+    #[derive(Debug)]
+    pub struct WavFileAudioBackend {
+        file: BufWriter<File>,
+        data_len: u32,
+        error: Option<io::Error>,
+        finished: bool,
+    }
+
+    impl WavFileAudioBackend {
+        /// Create the file `path` for samples at `sample_rate` samples per second.
+        /// The DSP outputs 32000 samples per second.
+        pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+            let mut file = BufWriter::new(File::create(path)?);
+            file.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+            for val in [
+                16u32,
+                0x0002_0001,
+                sample_rate,
+                sample_rate * 4,
+                0x0010_0004,
+            ] {
+                file.write_all(&val.to_le_bytes())?
+            }
+            file.write_all(b"data\0\0\0\0")?;
+            Ok(Self {
+                file,
+                data_len: 0,
+                error: None,
+                finished: false,
+            })
+        }
+
+        pub fn write_sample(&mut self, sample: StereoSample) -> io::Result<()> {
+            self.file.write_all(&sample.l.to_le_bytes())?;
+            self.file.write_all(&sample.r.to_le_bytes())?;
+            self.data_len += 4;
+            Ok(())
+        }
+
+        /// The number of stereo samples written
+        pub fn samples(&self) -> u32 {
+            self.data_len / 4
+        }
+
+        /// Write the sizes into the header and flush the file
+        pub fn finish(mut self) -> io::Result<()> {
+            self.finalize()
+        }
+
+        fn finalize(&mut self) -> io::Result<()> {
+            self.finished = true;
+            if let Some(err) = self.error.take() {
+                return Err(err);
+            }
+            self.file.seek(SeekFrom::Start(4))?;
+            self.file.write_all(&(self.data_len + 36).to_le_bytes())?;
+            self.file.seek(SeekFrom::Start(40))?;
+            self.file.write_all(&self.data_len.to_le_bytes())?;
+            self.file.flush()
+        }
+    }
+
+    impl AudioBackend for WavFileAudioBackend {
+        fn push_sample(&mut self, sample: StereoSample) {
+            if self.error.is_none() {
+                self.error = self.write_sample(sample).err()
+            }
+        }
+    }
+
+    impl Drop for WavFileAudioBackend {
+        fn drop(&mut self) {
+            if !self.finished {
+                let _ = self.finalize();
+            }
+        }
+    }
+}
+
+pub use wav::WavFileAudioBackend;
+
+mod media {
+    use std::{
+        fs::File,
+        io::{BufReader, Read, Seek},
+        path::{Path, PathBuf},
+    };
+
+    /// A seekable stream of bytes, e.g. a file
+    pub trait MediaStream: Read + Seek + Send {}
+
+    impl<T: Read + Seek + Send> MediaStream for T {}
+
+    /// Provides the streamed files of the MSU-1 expansion
+    pub trait MediaBackend: Send + Sync + 'static {
+        /// Open the data file
+        fn open_data(&self) -> Option<Box<dyn MediaStream>>;
+
+        /// Open the PCM file of an audio track
+        fn open_audio_track(&self, track: u16) -> Option<Box<dyn MediaStream>>;
+    }
+
+    /// Opens the files next to the cartridge file.
+    ///
+    /// For `game.sfc` the data file is `game.msu`
+    /// and the audio track `N` is `game-N.pcm`.
+    #[derive(Debug, Clone)]
+    pub struct FileMedia {
+        rom_path: PathBuf,
+    }
+
+    impl FileMedia {
+        pub fn new(rom_path: &Path) -> Self {
+            Self {
+                rom_path: rom_path.to_owned(),
+            }
+        }
+
+        pub fn data_path(&self) -> PathBuf {
+            self.rom_path.with_extension("msu")
+        }
+
+        pub fn audio_track_path(&self, track: u16) -> PathBuf {
+            let stem = self.rom_path.file_stem().unwrap_or_default();
+            let mut name = stem.to_owned();
+            name.push(format!("-{}.pcm", track));
+            self.rom_path.with_file_name(name)
+        }
+
+        /// Check if the cartridge uses the MSU-1, which is the case if the data file exists
+        pub fn is_present(&self) -> bool {
+            self.data_path().is_file()
+        }
+
+        fn open(path: &Path) -> Option<Box<dyn MediaStream>> {
+            Some(Box::new(BufReader::new(File::open(path).ok()?)))
+        }
+    }
+
+    impl MediaBackend for FileMedia {
+        fn open_data(&self) -> Option<Box<dyn MediaStream>> {
+            Self::open(&self.data_path())
+        }
+
+        fn open_audio_track(&self, track: u16) -> Option<Box<dyn MediaStream>> {
+            Self::open(&self.audio_track_path(track))
+        }
+    }
+}
+
+pub use media::{FileMedia, MediaBackend, MediaStream};
+
+mod clock {
+    /// Provides the wall clock time to the emulated hardware, e.g. the time
+    /// broadcast of the Satellaview or real-time clock chips.
+    ///
+    /// Replacing the system clock by a [`FixedClock`] makes runs reproducible,
+    /// which is required for movies and tests.
+    pub trait ClockSource: std::fmt::Debug + Send + Sync + 'static {
+        /// The current time in seconds since the unix epoch
+        fn unix_time(&self) -> u64;
+    }
+
+    /// The clock of the operating system
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct SystemClock;
+
+    impl ClockSource for SystemClock {
+        #[cfg(not(target_arch = "wasm32"))]
+        fn unix_time(&self) -> u64 {
+            use std::time::{SystemTime, UNIX_EPOCH};
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs())
+        }
+
+        /// There is no system clock on wasm32, so the epoch is used.
+        /// Frontends running in a browser should provide the time of the
+        /// JavaScript `Date` with [`crate::device::Device::set_clock_source`].
+        #[cfg(target_arch = "wasm32")]
+        fn unix_time(&self) -> u64 {
+            0
+        }
+    }
+
+    /// A clock, which always returns the same time in seconds since the unix epoch
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct FixedClock(pub u64);
+
+    impl ClockSource for FixedClock {
+        fn unix_time(&self) -> u64 {
+            self.0
+        }
+    }
+}
+
+pub use clock::{ClockSource, FixedClock, SystemClock};
+
+/// Dimensions of a picture in the frame buffer
+///
+/// The width is doubled in high-resolution modes (mode 5/6 or pseudo-hires)
+/// and the height is doubled in interlace mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FrameSize {
+    pub const DEFAULT: Self = Self {
+        width: ppu::SCREEN_WIDTH,
+        height: ppu::MAX_SCREEN_HEIGHT,
+    };
+
+    pub const fn pixel_count(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+}
+
+impl Default for FrameSize {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The format of the pixels, which the PPU writes into a [`FrameBuffer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelFormat {
+    /// Red, green and blue bytes followed by an opaque alpha byte.
+    /// Blank pixels are all zeros.
+    #[default]
+    Rgba8,
+    /// The 15-bit color of the PPU with the brightness applied,
+    /// red in the lowest five bits and blue in the highest ones (`0bbbbbgggggrrrrr`)
+    Bgr555,
+}
+
+/// The pixels of a scanline in one of the [`PixelFormat`]s
+#[derive(Debug, Clone, Copy)]
+pub enum Scanline<'a> {
+    Rgba8(&'a [[u8; 4]]),
+    Bgr555(&'a [u16]),
+}
+
+/// Convert a [`PixelFormat::Bgr555`] color to a [`PixelFormat::Rgba8`] pixel
+pub const fn bgr555_to_rgba8(color: u16) -> [u8; 4] {
+    const fn expand(c: u16) -> u8 {
+        let c = (c & 0x1f) as u8;
+        (c << 3) | (c >> 2)
+    }
+    [expand(color), expand(color >> 5), expand(color >> 10), 255]
+}
+
+/// The output of the PPU.
+///
+/// The PPU writes the picture scanline by scanline in the format the frame
+/// buffer asks for, so the frame buffer decides about the memory layout
+/// (e.g. the pitch of its rows) and doesn't need to convert the pixels.
 pub trait FrameBuffer {
-    fn pixels(&self) -> &[[u8; 4]];
-    fn mut_pixels(&mut self) -> &mut [[u8; 4]];
+    /// The format of the scanlines written by [`Self::write_scanline`].
+    /// It is requested for every scanline and should only change between frames.
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Rgba8
+    }
+    /// Notify the frame buffer about the dimensions of the picture being drawn.
+    ///
+    /// This is called at the start of every frame and when a frame becomes
+    /// high-resolution, see [`Self::widen_rows`].
+    fn set_size(&mut self, size: FrameSize);
+    /// Write the pixels of the row `row` of the picture, which are as many as
+    /// the width of the last [`Self::set_size`].
+    fn write_scanline(&mut self, row: usize, pixels: Scanline);
+    /// Double every pixel of the rows `0..rows`, which were written with the
+    /// low-resolution width, because the frame became high-resolution
+    fn widen_rows(&mut self, rows: usize);
     fn request_redraw(&mut self);
 }
 
-pub const FRAME_BUFFER_SIZE: usize = (ppu::MAX_SCREEN_HEIGHT_OVERSCAN * ppu::SCREEN_WIDTH) as usize;
+pub const FRAME_BUFFER_SIZE: usize = (ppu::MAX_FRAME_HEIGHT * ppu::MAX_FRAME_WIDTH) as usize;
 use crate::ppu;
+
+/// A frame buffer, which discards the picture, e.g. for tests or audio-only uses
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullFrameBuffer;
+
+impl FrameBuffer for NullFrameBuffer {
+    fn set_size(&mut self, _size: FrameSize) {}
+    fn write_scanline(&mut self, _row: usize, _pixels: Scanline) {}
+    fn widen_rows(&mut self, _rows: usize) {}
+    fn request_redraw(&mut self) {}
+}
+
+/// A frame buffer of RGBA pixels, which are stored row by row,
+/// so a row has a stride of `width` pixels of the [`FrameSize`]
 #[derive(Debug, Clone)]
-pub struct ArrayFrameBuffer(pub [[u8; 4]; FRAME_BUFFER_SIZE], pub bool);
+pub struct ArrayFrameBuffer(pub [[u8; 4]; FRAME_BUFFER_SIZE], pub bool, pub FrameSize);
 
 impl FrameBuffer for ArrayFrameBuffer {
-    fn pixels(&self) -> &[[u8; 4]] {
-        &self.0
+    fn set_size(&mut self, size: FrameSize) {
+        self.2 = size
     }
-    fn mut_pixels(&mut self) -> &mut [[u8; 4]] {
-        &mut self.0
+    fn write_scanline(&mut self, row: usize, pixels: Scanline) {
+        let n = row * self.2.width as usize;
+        match pixels {
+            Scanline::Rgba8(pixels) => self.0[n..n + pixels.len()].copy_from_slice(pixels),
+            Scanline::Bgr555(pixels) => {
+                for (dst, &color) in self.0[n..n + pixels.len()].iter_mut().zip(pixels) {
+                    *dst = bgr555_to_rgba8(color)
+                }
+            }
+        }
+    }
+    fn widen_rows(&mut self, rows: usize) {
+        let width = ppu::SCREEN_WIDTH as usize;
+        for row in (0..rows).rev() {
+            for x in (0..width).rev() {
+                let pixel = self.0[row * width + x];
+                let n = (row * width + x) << 1;
+                self.0[n..n + 2].fill(pixel);
+            }
+        }
     }
     fn request_redraw(&mut self) {
         self.1 = true
     }
 }
 
+impl Default for ArrayFrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ArrayFrameBuffer {
+    pub const fn new() -> Self {
+        Self([[0; 4]; FRAME_BUFFER_SIZE], true, FrameSize::DEFAULT)
+    }
+
+    pub const fn size(&self) -> FrameSize {
+        self.2
+    }
+
+    pub fn pixels(&self) -> &[[u8; 4]] {
+        &self.0
+    }
+
+    pub fn mut_pixels(&mut self) -> &mut [[u8; 4]] {
+        &mut self.0
+    }
+
+    /// Get the bytes of the currently visible picture
     pub fn get_bytes(&self) -> &[u8] {
-        unsafe { core::slice::from_raw_parts(self.0.as_ptr() as _, self.0.len() << 2) }
+        let len = self.2.pixel_count().min(self.0.len());
+        unsafe { core::slice::from_raw_parts(self.0.as_ptr() as _, len << 2) }
+    }
+
+    /// A 64-bit FNV-1a hash of the size and the colors of the visible picture.
+    ///
+    /// The hash doesn't depend on the platform, so it can be stored
+    /// to detect changes of the emulated picture.
+    pub fn hash(&self) -> u64 {
+        let size = self.2;
+        let pixels = self.0[..size.pixel_count().min(self.0.len())].iter();
+        let bytes = size.width.to_le_bytes().into_iter();
+        let bytes = bytes.chain(size.height.to_le_bytes());
+        bytes
+            .chain(pixels.flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]))
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            })
     }
 }
+
+#[cfg(test)]
+mod tests;
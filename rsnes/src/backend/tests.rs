@@ -0,0 +1,35 @@
+use super::*;
+use crate::spc700::StereoSample;
+
+#[test]
+fn bgr555_expansion() {
+    assert_eq!(bgr555_to_rgba8(0), [0, 0, 0, 255]);
+    assert_eq!(bgr555_to_rgba8(0x7fff), [255; 4]);
+    assert_eq!(bgr555_to_rgba8(0x7c10), [0x84, 0, 0xff, 255]);
+}
+
+#[test]
+fn wav_file() {
+    let path = std::env::temp_dir().join(format!("rsnes-wav-test-{}.wav", std::process::id()));
+    let mut backend = WavFileAudioBackend::create(&path, 32000).unwrap();
+    backend.push_sample(StereoSample::<i16>::new(1, -2));
+    backend.push_sample(StereoSample::<i16>::new(0x1234, -0x1234));
+    assert_eq!(backend.samples(), 2);
+    // the header gets completed when the backend is dropped
+    drop(backend);
+    let wav = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(wav.len(), 44 + 8);
+    assert_eq!(&wav[..4], b"RIFF");
+    assert_eq!(wav[4..8], 44u32.to_le_bytes());
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(wav[22..24], 2u16.to_le_bytes());
+    assert_eq!(wav[24..28], 32000u32.to_le_bytes());
+    assert_eq!(&wav[36..40], b"data");
+    assert_eq!(wav[40..44], 8u32.to_le_bytes());
+    let samples: Vec<i16> = wav[44..]
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    assert_eq!(samples, [1, -2, 0x1234, -0x1234]);
+}
@@ -0,0 +1,175 @@
+//! Breakpoints and watch expressions of the main CPU
+//!
+//! Both are checked after every instruction and every interrupt entry of the
+//! main CPU, i.e. before the instruction at the program counter is executed.
+//! A breakpoint hits if the program counter is at its address and its
+//! condition is not zero. Breakpoints without an address only test their
+//! condition, e.g. `[$7e0010] == 3`. The hits are recorded and taken by the
+//! frontend, which stops the emulation then, e.g. between calls of
+//! [`crate::device::Device::step_cpu_instruction`].
+//!
+//! Every change of the value of a watch expression is recorded as well.
+//! Breakpoints and watch expressions are not part of save states.
+
+pub mod expr;
+
+use crate::device::Addr24;
+use expr::{Context, Expr};
+
+/// Hits and changes beyond this count are dropped until they are taken
+pub(crate) const MAX_RECORDED_EVENTS: usize = 0x10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BreakpointId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchExprId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakpointHit {
+    pub id: BreakpointId,
+    /// The address of the next instruction
+    pub pc: Addr24,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchChange {
+    pub id: WatchExprId,
+    /// The address of the next instruction after the change
+    pub pc: Addr24,
+    pub old: u32,
+    pub new: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Breakpoint {
+    id: BreakpointId,
+    addr: Option<Addr24>,
+    condition: Option<Expr>,
+}
+
+#[derive(Debug, Clone)]
+struct WatchExpr {
+    id: WatchExprId,
+    expr: Expr,
+    value: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    breakpoints: Vec<Breakpoint>,
+    watch_exprs: Vec<WatchExpr>,
+    next_id: u32,
+    hits: Vec<BreakpointHit>,
+    changes: Vec<WatchChange>,
+}
+
+impl Breakpoints {
+    fn next_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// Break at the address `addr` if `condition` is not zero.
+    /// At least one of both should be given, otherwise every instruction hits.
+    pub fn add(&mut self, addr: Option<Addr24>, condition: Option<Expr>) -> BreakpointId {
+        let id = BreakpointId(self.next_id());
+        self.breakpoints.push(Breakpoint {
+            id,
+            addr,
+            condition,
+        });
+        id
+    }
+
+    /// Remove a breakpoint, `false` is returned if the breakpoint does not exist
+    pub fn remove(&mut self, id: BreakpointId) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.hits.retain(|hit| hit.id != id);
+        self.breakpoints.len() != len
+    }
+
+    /// Record the changes of the value of an expression
+    pub fn add_watch_expr(&mut self, expr: Expr) -> WatchExprId {
+        let id = WatchExprId(self.next_id());
+        self.watch_exprs.push(WatchExpr {
+            id,
+            expr,
+            value: None,
+        });
+        id
+    }
+
+    /// Remove a watch expression, `false` is returned if it does not exist
+    pub fn remove_watch_expr(&mut self, id: WatchExprId) -> bool {
+        let len = self.watch_exprs.len();
+        self.watch_exprs.retain(|watch| watch.id != id);
+        self.changes.retain(|change| change.id != id);
+        self.watch_exprs.len() != len
+    }
+
+    /// The value of a watch expression after the last instruction,
+    /// `None` if it wasn't evaluated yet or does not exist
+    pub fn watch_value(&self, id: WatchExprId) -> Option<u32> {
+        self.watch_exprs
+            .iter()
+            .find(|watch| watch.id == id)
+            .and_then(|watch| watch.value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty() && self.watch_exprs.is_empty()
+    }
+
+    /// Check if a breakpoint hit since the hits were taken the last time
+    pub fn has_hits(&self) -> bool {
+        !self.hits.is_empty()
+    }
+
+    /// Take the breakpoint hits in the order they happened
+    pub fn take_hits(&mut self) -> Vec<BreakpointHit> {
+        core::mem::take(&mut self.hits)
+    }
+
+    /// Take the changes of the watch expressions in the order they happened
+    pub fn take_changes(&mut self) -> Vec<WatchChange> {
+        core::mem::take(&mut self.changes)
+    }
+
+    /// Test the breakpoints and evaluate the watch expressions before
+    /// the instruction at `pc` is executed
+    pub(crate) fn check(&mut self, ctx: &impl Context, pc: Addr24) {
+        for breakpoint in &self.breakpoints {
+            if breakpoint.addr.is_some_and(|addr| addr != pc)
+                || self.hits.len() >= MAX_RECORDED_EVENTS
+            {
+                continue;
+            }
+            if breakpoint
+                .condition
+                .as_ref()
+                .is_none_or(|condition| condition.eval(ctx) != 0)
+            {
+                self.hits.push(BreakpointHit {
+                    id: breakpoint.id,
+                    pc,
+                })
+            }
+        }
+        for watch in &mut self.watch_exprs {
+            let new = watch.expr.eval(ctx);
+            match watch.value.replace(new) {
+                Some(old) if old != new && self.changes.len() < MAX_RECORDED_EVENTS => {
+                    self.changes.push(WatchChange {
+                        id: watch.id,
+                        pc,
+                        old,
+                        new,
+                    })
+                }
+                _ => (),
+            }
+        }
+    }
+}
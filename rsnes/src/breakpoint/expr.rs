@@ -0,0 +1,415 @@
+//! Expressions over the registers and the memory of the main CPU
+//!
+//! The syntax resembles C, e.g. `a == $42 && [$7e0010] != 0`:
+//!
+//! - numbers are decimal or hexadecimal with a `$` or `0x` prefix
+//! - the registers are `a`, `x`, `y`, `s`, `d`, `db`, `pb`, `pc` and `p`
+//! - `[addr]` reads the byte and `w[addr]` the little-endian word at the
+//!   24-bit address `addr`, without side effects like [`crate::device::Device::peek`]
+//! - the operators are, from the lowest to the highest precedence,
+//!   `||`, `&&`, `|`, `^`, `&`, `==` `!=`, `<` `<=` `>` `>=`, `+` `-`
+//!   and the unary `!`, `~` and `-`
+//!
+//! All values are 32-bit unsigned integers with wrapping arithmetic.
+//! Comparisons and logical operators result in `0` or `1`.
+//! Parentheses, brackets and unary operators nest at most [`MAX_DEPTH`] levels.
+
+use crate::{
+    backend::{AudioBackend, FrameBuffer},
+    device::{Addr24, Device},
+};
+
+/// The maximum nesting depth of an expression, which limits the recursion of the parser
+pub const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprError {
+    /// An unexpected character at the byte offset
+    UnexpectedChar(usize, char),
+    UnexpectedEnd,
+    UnknownIdentifier(String),
+    InvalidNumber(String),
+    /// The expression is nested deeper than [`MAX_DEPTH`]
+    TooDeep,
+}
+
+impl std::fmt::Display for ExprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedChar(pos, c) => write!(f, "unexpected '{}' at position {}", c, pos),
+            Self::UnexpectedEnd => write!(f, "unexpected end of the expression"),
+            Self::UnknownIdentifier(name) => write!(f, "unknown register \"{}\"", name),
+            Self::InvalidNumber(number) => write!(f, "invalid number \"{}\"", number),
+            Self::TooDeep => write!(f, "expression is nested deeper than {} levels", MAX_DEPTH),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    /// The stack pointer
+    S,
+    /// The direct page register
+    D,
+    /// The data bank register
+    Db,
+    /// The program bank register
+    Pb,
+    /// The program counter without the bank
+    Pc,
+    /// The processor status
+    P,
+}
+
+impl Register {
+    pub const NAMES: [(Self, &'static str); 9] = [
+        (Self::A, "a"),
+        (Self::X, "x"),
+        (Self::Y, "y"),
+        (Self::S, "s"),
+        (Self::D, "d"),
+        (Self::Db, "db"),
+        (Self::Pb, "pb"),
+        (Self::Pc, "pc"),
+        (Self::P, "p"),
+    ];
+
+    /// The register with the name `name` as used in [`Self::NAMES`], ignoring the case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, reg_name)| reg_name.eq_ignore_ascii_case(name))
+            .map(|(reg, _)| *reg)
+    }
+}
+
+/// The registers and the memory an expression is evaluated with
+pub trait Context {
+    fn register(&self, reg: Register) -> u32;
+
+    /// Read a byte without side effects
+    fn read(&self, addr: Addr24) -> u8;
+}
+
+impl<B: AudioBackend, FB: FrameBuffer> Context for Device<B, FB> {
+    fn register(&self, reg: Register) -> u32 {
+        let regs = &self.cpu.regs;
+        match reg {
+            Register::A => regs.a.into(),
+            Register::X => regs.x.into(),
+            Register::Y => regs.y.into(),
+            Register::S => regs.sp.into(),
+            Register::D => regs.dp.into(),
+            Register::Db => regs.db.into(),
+            Register::Pb => regs.pc.bank.into(),
+            Register::Pc => regs.pc.addr.into(),
+            Register::P => regs.status.0.into(),
+        }
+    }
+
+    fn read(&self, addr: Addr24) -> u8 {
+        self.peek(addr)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnaryOp {
+    Not,
+    Complement,
+    Negate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+}
+
+/// The binary operators from the lowest to the highest precedence.
+/// Longer operators precede their prefixes.
+const BINARY_OPS: [&[(&str, BinaryOp)]; 8] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(u32),
+    Register(Register),
+    Byte(Box<Node>),
+    Word(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+fn to_addr(addr: u32) -> Addr24 {
+    Addr24::new((addr >> 16) as u8, addr as u16)
+}
+
+impl Node {
+    fn eval(&self, ctx: &impl Context) -> u32 {
+        match self {
+            Self::Number(n) => *n,
+            Self::Register(reg) => ctx.register(*reg),
+            Self::Byte(addr) => {
+                let addr = addr.eval(ctx);
+                ctx.read(to_addr(addr)).into()
+            }
+            Self::Word(addr) => {
+                let addr = addr.eval(ctx);
+                let lo = ctx.read(to_addr(addr));
+                let hi = ctx.read(to_addr(addr.wrapping_add(1)));
+                u16::from_le_bytes([lo, hi]).into()
+            }
+            Self::Unary(op, val) => {
+                let val = val.eval(ctx);
+                match op {
+                    UnaryOp::Not => (val == 0).into(),
+                    UnaryOp::Complement => !val,
+                    UnaryOp::Negate => val.wrapping_neg(),
+                }
+            }
+            // the right side is not evaluated if the left side decides the result
+            Self::Binary(BinaryOp::Or, a, b) => (a.eval(ctx) != 0 || b.eval(ctx) != 0).into(),
+            Self::Binary(BinaryOp::And, a, b) => (a.eval(ctx) != 0 && b.eval(ctx) != 0).into(),
+            Self::Binary(op, a, b) => {
+                let (a, b) = (a.eval(ctx), b.eval(ctx));
+                match op {
+                    BinaryOp::Or | BinaryOp::And => unreachable!(),
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::Eq => (a == b).into(),
+                    BinaryOp::Ne => (a != b).into(),
+                    BinaryOp::Lt => (a < b).into(),
+                    BinaryOp::Le => (a <= b).into(),
+                    BinaryOp::Gt => (a > b).into(),
+                    BinaryOp::Ge => (a >= b).into(),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                }
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    /// The current nesting depth
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.rest().chars().next()
+    }
+
+    fn unexpected(&mut self) -> ExprError {
+        match self.peek_char() {
+            Some(c) => ExprError::UnexpectedChar(self.pos, c),
+            None => ExprError::UnexpectedEnd,
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ExprError> {
+        if self.peek_char() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /// Consume a binary operator, but not the prefix of `||` or `&&`
+    fn eat_op(&mut self, op: &str) -> bool {
+        self.skip_whitespace();
+        let rest = self.rest();
+        if !rest.starts_with(op) || (matches!(op, "|" | "&") && rest[1..].starts_with(op)) {
+            return false;
+        }
+        self.pos += op.len();
+        true
+    }
+
+    /// Consume a word of alphanumeric characters
+    fn word(&mut self) -> &'a str {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    /// Parse a nested part of the expression with `f`
+    fn nested<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, ExprError>,
+    ) -> Result<T, ExprError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(ExprError::TooDeep);
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Consume a binary operator of at least the precedence level `min_level`
+    fn binary_op(&mut self, min_level: usize) -> Option<(usize, BinaryOp)> {
+        (min_level..BINARY_OPS.len()).find_map(|level| {
+            BINARY_OPS[level]
+                .iter()
+                .find(|(name, _)| self.eat_op(name))
+                .map(|&(_, op)| (level, op))
+        })
+    }
+
+    /// Parse the operators of at least the precedence level `min_level`
+    /// by precedence climbing, so that the recursion only grows with the nesting
+    fn binary(&mut self, min_level: usize) -> Result<Node, ExprError> {
+        let mut node = self.unary()?;
+        while let Some((level, op)) = self.binary_op(min_level) {
+            let rhs = self.binary(level + 1)?;
+            node = Node::Binary(op, Box::new(node), Box::new(rhs));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        let op = match self.peek_char() {
+            Some('!') => UnaryOp::Not,
+            Some('~') => UnaryOp::Complement,
+            Some('-') => UnaryOp::Negate,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Node::Unary(op, Box::new(self.nested(Self::unary)?)))
+    }
+
+    fn memory(&mut self) -> Result<Node, ExprError> {
+        self.expect('[')?;
+        let addr = self.nested(|slf| slf.binary(0))?;
+        self.expect(']')?;
+        Ok(addr)
+    }
+
+    fn primary(&mut self) -> Result<Node, ExprError> {
+        match self.peek_char() {
+            Some('(') => {
+                self.pos += 1;
+                let node = self.nested(|slf| slf.binary(0))?;
+                self.expect(')')?;
+                Ok(node)
+            }
+            Some('[') => Ok(Node::Byte(Box::new(self.memory()?))),
+            Some('$') => {
+                self.pos += 1;
+                let digits = self.word();
+                u32::from_str_radix(digits, 16)
+                    .map(Node::Number)
+                    .map_err(|_| ExprError::InvalidNumber(format!("${}", digits)))
+            }
+            Some('0'..='9') => {
+                let word = self.word();
+                match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                    Some(digits) => u32::from_str_radix(digits, 16),
+                    None => word.parse(),
+                }
+                .map(Node::Number)
+                .map_err(|_| ExprError::InvalidNumber(word.into()))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let word = self.word();
+                if word.eq_ignore_ascii_case("w") && self.peek_char() == Some('[') {
+                    return Ok(Node::Word(Box::new(self.memory()?)));
+                }
+                Register::from_name(word)
+                    .map(Node::Register)
+                    .ok_or_else(|| ExprError::UnknownIdentifier(word.into()))
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+}
+
+/// A parsed expression, see the [module documentation](self) for the syntax
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, ExprError> {
+        let mut parser = Parser {
+            src: source,
+            pos: 0,
+            depth: 0,
+        };
+        let root = parser.binary(0)?;
+        if parser.peek_char().is_some() {
+            return Err(parser.unexpected());
+        }
+        Ok(Self {
+            source: source.trim().into(),
+            root,
+        })
+    }
+
+    /// The text the expression was parsed from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn eval(&self, ctx: &impl Context) -> u32 {
+        self.root.eval(ctx)
+    }
+}
+
+impl core::str::FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(source: &str) -> Result<Self, ExprError> {
+        Self::parse(source)
+    }
+}
+
+#[cfg(test)]
+mod tests;
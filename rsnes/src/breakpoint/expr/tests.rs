@@ -0,0 +1,72 @@
+use super::*;
+
+/// Registers with the value of their index in [`Register::NAMES`]
+/// and memory with the lower byte of the address
+struct TestContext;
+
+impl Context for TestContext {
+    fn register(&self, reg: Register) -> u32 {
+        Register::NAMES.iter().position(|(r, _)| *r == reg).unwrap() as u32
+    }
+
+    fn read(&self, addr: Addr24) -> u8 {
+        addr.addr as u8
+    }
+}
+
+fn eval(source: &str) -> u32 {
+    Expr::parse(source).unwrap().eval(&TestContext)
+}
+
+#[test]
+fn evaluate() {
+    assert_eq!(eval("42"), 42);
+    assert_eq!(eval("$ff + 0x10"), 0x10f);
+    assert_eq!(eval("y + DB - 5"), 2);
+    assert_eq!(eval("1 + 2 == 3 && pc == 7"), 1);
+    assert_eq!(eval("x == 1 || [0] == 99"), 1);
+    assert_eq!(eval("[$7e0012]"), 0x12);
+    assert_eq!(eval("w[$7e0012]"), 0x1312);
+    assert_eq!(eval("w[$00ffff]"), 0x00ff);
+    assert_eq!(eval("!(3 > 2) | ~0 & 4 ^ 1"), 5);
+    assert_eq!(eval("-1"), u32::MAX);
+    assert_eq!(eval("1 - 2 + 3"), 2);
+    assert_eq!(eval("a != 0 && a <= 0"), 0);
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!(Expr::parse("a =="), Err(ExprError::UnexpectedEnd));
+    assert_eq!(Expr::parse("a = 1"), Err(ExprError::UnexpectedChar(2, '=')));
+    assert_eq!(Expr::parse("[a"), Err(ExprError::UnexpectedEnd));
+    assert_eq!(Expr::parse("(1) 2"), Err(ExprError::UnexpectedChar(4, '2')));
+    assert_eq!(
+        Expr::parse("acc"),
+        Err(ExprError::UnknownIdentifier("acc".into()))
+    );
+    assert_eq!(
+        Expr::parse("$12g"),
+        Err(ExprError::InvalidNumber("$12g".into()))
+    );
+    assert_eq!(Expr::parse(" x == 1 ").unwrap().source(), "x == 1");
+}
+
+#[test]
+fn nesting_depth() {
+    let nested =
+        |open: &str, close: &str, depth| format!("{}1{}", open.repeat(depth), close.repeat(depth));
+    assert_eq!(eval(&nested("(", ")", MAX_DEPTH)), 1);
+    assert_eq!(eval(&nested("-", "", MAX_DEPTH - 1)), u32::MAX);
+    assert_eq!(eval(&nested("[", "]", MAX_DEPTH)), 1);
+    for (open, close) in [("(", ")"), ("!", ""), ("[", "]"), ("-(", ")")] {
+        assert_eq!(
+            Expr::parse(&nested(open, close, MAX_DEPTH + 1)),
+            Err(ExprError::TooDeep)
+        );
+        // the parser stops before it runs out of stack
+        assert_eq!(
+            Expr::parse(&nested(open, close, 100_000)),
+            Err(ExprError::TooDeep)
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Construction of a configured [`Device`]
+//!
+//! A device is set up in a certain order: the region has to be known before
+//! the device is created, while the clock source, the MSU-1 media and the
+//! controllers have to be attached before or after the cartridge is loaded.
+//! The [`EmulatorBuilder`] collects the settings and applies them in this order.
+
+use crate::{
+    backend::{AudioBackend, ClockSource, FrameBuffer, MediaBackend},
+    cartridge::Cartridge,
+    color::ColorCorrection,
+    controller::DeviceKind,
+    device::{Device, DeviceOptions, RamInit, Region},
+};
+use std::sync::Arc;
+
+/// Builder of a [`Device`] with a cartridge, backends and peripherals
+pub struct EmulatorBuilder<B: AudioBackend, FB: FrameBuffer> {
+    audio_backend: B,
+    frame_buffer: FB,
+    cartridge: Option<Cartridge>,
+    region: Option<Region>,
+    threaded: bool,
+    options: DeviceOptions,
+    render_threads: usize,
+    sprite_limit: bool,
+    color_correction: Option<ColorCorrection>,
+    controllers: [DeviceKind; 2],
+    clock: Option<Arc<dyn ClockSource>>,
+    msu1: Option<Arc<dyn MediaBackend>>,
+}
+
+impl<B: AudioBackend, FB: FrameBuffer> EmulatorBuilder<B, FB> {
+    /// Start building a device with the given audio and video backends.
+    ///
+    /// By default no cartridge is inserted, the region is NTSC, the audio
+    /// processor runs on the emulation thread and a standard controller is
+    /// connected to port 1.
+    pub fn new(audio_backend: B, frame_buffer: FB) -> Self {
+        Self {
+            audio_backend,
+            frame_buffer,
+            cartridge: None,
+            region: None,
+            threaded: false,
+            options: DeviceOptions::default(),
+            render_threads: 0,
+            sprite_limit: true,
+            color_correction: None,
+            controllers: [DeviceKind::Standard, DeviceKind::None],
+            clock: None,
+            msu1: None,
+        }
+    }
+
+    /// Insert a cartridge, which also selects the region unless
+    /// [`Self::region`] is given
+    pub fn cartridge(mut self, cartridge: Cartridge) -> Self {
+        self.cartridge = Some(cartridge);
+        self
+    }
+
+    /// Select the region instead of choosing it by the cartridge header,
+    /// see [`Region::from_cartridge`]
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// Run the audio processor on its own thread
+    pub fn threaded(mut self, threaded: bool) -> Self {
+        self.threaded = threaded;
+        self
+    }
+
+    /// Replace all console settings, see [`DeviceOptions`]
+    pub fn options(mut self, options: DeviceOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// See [`DeviceOptions::ram_init`]
+    pub fn ram_init(mut self, ram_init: RamInit) -> Self {
+        self.options.ram_init = ram_init;
+        self
+    }
+
+    /// See [`DeviceOptions::apu_sync_cycles`]
+    pub fn apu_sync_cycles(mut self, cycles: u32) -> Self {
+        self.options.apu_sync_cycles = cycles;
+        self
+    }
+
+    /// See [`DeviceOptions::skip_idle_loops`]
+    pub fn skip_idle_loops(mut self, enabled: bool) -> Self {
+        self.options.skip_idle_loops = enabled;
+        self
+    }
+
+    /// See [`crate::ppu::Ppu::set_render_threads`]
+    pub fn render_threads(mut self, threads: usize) -> Self {
+        self.render_threads = threads;
+        self
+    }
+
+    /// See [`crate::ppu::Ppu::set_sprite_limits`]
+    pub fn sprite_limit(mut self, enabled: bool) -> Self {
+        self.sprite_limit = enabled;
+        self
+    }
+
+    /// See [`crate::ppu::Ppu::set_color_correction`]
+    pub fn color_correction(mut self, correction: Option<ColorCorrection>) -> Self {
+        self.color_correction = correction;
+        self
+    }
+
+    /// Connect a device to the controller port `port`, which is `0` or `1`
+    pub fn controller(mut self, port: usize, device: DeviceKind) -> Self {
+        assert!(port < 2, "the controller port must be 0 or 1");
+        self.controllers[port] = device;
+        self
+    }
+
+    /// See [`Device::set_clock_source`]
+    pub fn clock_source(mut self, clock: Arc<dyn ClockSource>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Connect the MSU-1 expansion to the cartridge, see [`Cartridge::attach_msu1`]
+    pub fn msu1(mut self, media: Arc<dyn MediaBackend>) -> Self {
+        self.msu1 = Some(media);
+        self
+    }
+
+    /// Create the device and insert the cartridge
+    pub fn build(self) -> Device<B, FB> {
+        let region = match (self.region, &self.cartridge) {
+            (Some(region), _) => region,
+            (None, Some(cartridge)) => Region::from_cartridge(cartridge),
+            (None, None) => Region::Ntsc,
+        };
+        let mut device = Device::new_with_options(
+            self.audio_backend,
+            self.frame_buffer,
+            region,
+            self.threaded,
+            self.options,
+        );
+        device.ppu.set_render_threads(self.render_threads);
+        device.ppu.set_sprite_limits(self.sprite_limit);
+        device.ppu.set_color_correction(self.color_correction);
+        for (port, kind) in self.controllers.into_iter().enumerate() {
+            device.controllers.connect(port, kind);
+        }
+        if let Some(clock) = self.clock {
+            device.set_clock_source(clock);
+        }
+        if let Some(mut cartridge) = self.cartridge {
+            if let Some(media) = self.msu1 {
+                cartridge.attach_msu1(media);
+            }
+            device.load_cartridge(cartridge);
+        }
+        device
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,66 @@
+use super::*;
+use crate::{
+    backend::{AudioDummy, FixedClock, NullFrameBuffer},
+    device::Addr24,
+};
+
+fn new_builder() -> EmulatorBuilder<AudioDummy, NullFrameBuffer> {
+    EmulatorBuilder::new(AudioDummy, NullFrameBuffer)
+}
+
+/// A LoROM cartridge with the country code `country` and the reset vector `$8000`
+fn new_cartridge(country: u8) -> Cartridge {
+    let mut rom = vec![0xea; 0x8000];
+    let header = &mut rom[0x7fc0..];
+    header[..21].copy_from_slice(b"BUILDER TEST         ");
+    header[21] = 0x20;
+    header[23] = 5;
+    header[25] = country;
+    header[60..62].copy_from_slice(&0x8000u16.to_le_bytes());
+    Cartridge::from_bytes(&rom).unwrap()
+}
+
+#[test]
+fn region_from_cartridge() {
+    let device = new_builder().cartridge(new_cartridge(2)).build();
+    assert!(device.region().is_pal());
+    assert_eq!(device.cpu.regs.pc, Addr24::new(0, 0x8000));
+    let device = new_builder()
+        .cartridge(new_cartridge(2))
+        .region(Region::Ntsc)
+        .build();
+    assert!(!device.region().is_pal());
+    let device = new_builder().build();
+    assert!(!device.region().is_pal());
+    assert!(device.cartridge.is_none());
+}
+
+#[test]
+fn settings_are_applied() {
+    let device = new_builder()
+        .cartridge(new_cartridge(1))
+        .ram_init(RamInit::Pattern)
+        .apu_sync_cycles(32)
+        .skip_idle_loops(true)
+        .sprite_limit(false)
+        .color_correction(Some(ColorCorrection::CRT))
+        .controller(0, DeviceKind::Mouse)
+        .controller(1, DeviceKind::Multitap)
+        .clock_source(Arc::new(FixedClock(1234)))
+        .build();
+    let options = device.options();
+    assert_eq!(options.ram_init, RamInit::Pattern);
+    assert_eq!(options.apu_sync_cycles, 32);
+    assert!(options.skip_idle_loops);
+    assert!(!device.ppu.sprite_limits());
+    assert_eq!(device.ppu.color_correction(), Some(ColorCorrection::CRT));
+    assert_eq!(
+        device.controllers.port1.controller.kind(),
+        DeviceKind::Mouse
+    );
+    assert_eq!(
+        device.controllers.port2.controller.kind(),
+        DeviceKind::Multitap
+    );
+    assert_eq!(device.clock_source().unix_time(), 1234);
+}
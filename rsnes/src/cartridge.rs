@@ -5,17 +5,34 @@
 //! - the [super famicom wiki page](https://wiki.superfamicom.org/memory-mapping)
 //! - <http://patrickjohnston.org/ASM/ROM data/snestek.htm>
 
-use std::convert::TryInto;
+use std::{collections::HashMap, convert::TryInto, sync::Arc};
 
+#[cfg(feature = "sgb")]
+use crate::enhancement::sgb::{GameBoy, Icd2};
 use crate::{
+    backend::{ClockSource, MediaBackend, SystemClock},
+    controller::DeviceKind,
     device::{Addr24, Data},
-    enhancement::{sa1::Sa1, Dsp, DspVersion},
+    enhancement::{
+        bsx::{Bsx, MemoryPack},
+        msu1::{AudioOutput, Msu1},
+        sa1::Sa1,
+        srtc::{self, Srtc},
+        Dsp, DspVersion,
+    },
     timing::Cycles,
 };
 use save_state::{SaveStateDeserializer, SaveStateSerializer};
 use save_state_macro::*;
 
+mod database;
+pub mod patch;
+
 const MINIMUM_SIZE: usize = 0x8000;
+/// The largest ROM size a header can announce (size byte `0x0d`)
+const MAXIMUM_ROM_SIZE: u32 = 0x80_0000;
+/// Magic bytes at the start of Sufami Turbo mini-cartridges
+const SUFAMI_MAGIC: &[u8; 14] = b"BANDAI SFC-ADX";
 
 fn split_byte(byte: u8) -> (u8, u8) {
     (byte >> 4, byte & 15)
@@ -26,6 +43,11 @@ pub enum ReadRomError {
     TooSmall(usize),
     AlignError(usize),
     NoSuitableHeader,
+    NoMemoryPackSlot,
+    NoSufamiTurboCartridge,
+    #[cfg(feature = "sgb")]
+    NoGameBoySlot,
+    UnsupportedRawMapping(RomType),
 }
 
 impl std::fmt::Display for ReadRomError {
@@ -36,13 +58,21 @@ impl std::fmt::Display for ReadRomError {
                 write!(f, "file must be a multiple of 512 in length (got {})", size)
             }
             Self::NoSuitableHeader => write!(f, "no suitable header found"),
+            Self::NoMemoryPackSlot => write!(f, "the cartridge has no memory pack slot"),
+            Self::NoSufamiTurboCartridge => write!(f, "not a Sufami Turbo cartridge"),
+            #[cfg(feature = "sgb")]
+            Self::NoGameBoySlot => write!(f, "the cartridge has no Game Boy slot"),
+            Self::UnsupportedRawMapping(rom_type) => {
+                write!(f, "raw binaries can't be mapped as {:?}", rom_type)
+            }
         }
     }
 }
 
+/// The memory mapping of the ROM given by the map mode of the header
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
-enum RomType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomType {
     LoRom = 0,
     HiRom = 1,
     LoRomSDD1 = 2,
@@ -66,6 +96,26 @@ impl RomType {
             _ => return None,
         })
     }
+
+    /// The mappings a raw binary can be loaded with, see [`Cartridge::from_raw`]
+    pub const NAMES: [(Self, &'static str); 2] = [(Self::LoRom, "lorom"), (Self::HiRom, "hirom")];
+
+    /// The mapping with the name `name` as used in [`Self::NAMES`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, mapping_name)| *mapping_name == name)
+            .map(|(rom_type, _)| *rom_type)
+    }
+
+    /// The address of the header in the file, if this mapping is used
+    const fn header_address(&self) -> usize {
+        match self {
+            Self::LoRom | Self::LoRomSDD1 | Self::LoRomSA1 => 0x7fb0,
+            Self::HiRom | Self::HiRomSPC7110 => 0xffb0,
+            Self::ExHiRom => 0x40ffb0,
+        }
+    }
 }
 
 impl save_state::InSaveState for RomType {
@@ -76,7 +126,10 @@ impl save_state::InSaveState for RomType {
     fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
         let mut i: u8 = 0;
         i.deserialize(state);
-        *self = Self::from_byte(i).unwrap_or_else(|| panic!("unknown enum discriminant {}", i))
+        match Self::from_byte(i) {
+            Some(rom_type) => *self = rom_type,
+            None => state.fail(save_state::DeserializeError::InvalidValue),
+        }
     }
 }
 
@@ -137,7 +190,7 @@ impl save_state::InSaveState for OptExtendedHeader {
                 header.deserialize(state);
                 Self::Later { subtype, header }
             }
-            _ => panic!("unknown enum discriminant {}", i),
+            _ => return state.fail(save_state::DeserializeError::InvalidValue),
         }
     }
 }
@@ -149,7 +202,7 @@ impl Default for OptExtendedHeader {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Coprocessor {
     Dsp = 0,
     Gsu = 1,
@@ -161,6 +214,10 @@ pub enum Coprocessor {
     St01x = 7,
     St018 = 8,
     Cx4 = 9,
+    /// BS-X Satellaview base cartridge
+    Bsx = 10,
+    /// Super Game Boy, whose ICD2 connects a Game Boy
+    Sgb = 11,
     Unknown = 0xff,
 }
 
@@ -182,7 +239,7 @@ impl save_state::InSaveState for Coprocessor {
                 }
             }};
         }
-        *self = deser!(Dsp, Gsu, Obc1, Sa1, Sdd1, Srtc, Spc7110, St01x, St018, Cx4)
+        *self = deser!(Dsp, Gsu, Obc1, Sa1, Sdd1, Srtc, Spc7110, St01x, St018, Cx4, Bsx, Sgb)
     }
 }
 
@@ -192,6 +249,15 @@ impl Default for Coprocessor {
     }
 }
 
+/// Transformations applied to the ROM file while loading
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, InSaveState)]
+pub struct ImageFormat {
+    /// A 512 byte header of a copier device was stripped
+    pub copier_header: bool,
+    /// The 32 KiB blocks were reordered from an interleaved HiROM dump
+    pub interleaved: bool,
+}
+
 #[derive(Debug, Default, Clone, InSaveState)]
 pub struct Header {
     name: String,
@@ -205,7 +271,10 @@ pub struct Header {
     ram_size: u32,
     country: u8,
     checksum: u16,
+    checksum_complement: u16,
     version: u8,
+    reset_vector: u16,
+    image_format: ImageFormat,
 }
 
 impl Header {
@@ -214,6 +283,7 @@ impl Header {
         const VALID_CHECKSUM_COMPLEMENT: u16 = 32;
         const VALID_SPEED_INDICATION: u16 = 24;
         const KNOWN_COUNTRY: u16 = 10;
+        const VALID_RESET_VECTOR: u16 = 20;
         assert_eq!(full_bytes.len(), 80);
 
         let bytes = &full_bytes[16..];
@@ -230,7 +300,8 @@ impl Header {
         }
         score += name.len() as u16 * VALID_CHAR;
         let (speed, rom_type) = split_byte(bytes[21]);
-        if speed & !1 == 1 {
+        // the upper nibble of the map mode is either 2 or 3
+        if speed & !1 == 2 {
             score += VALID_SPEED_INDICATION
         }
         let is_fast = speed & 1 == 1;
@@ -250,6 +321,11 @@ impl Header {
         if checksum_complement == !checksum {
             score += VALID_CHECKSUM_COMPLEMENT
         }
+        // the reset vector of the emulation mode must point into the ROM area
+        let reset_vector = u16::from_le_bytes(full_bytes[0x4c..0x4e].try_into().unwrap());
+        if reset_vector >= 0x8000 {
+            score += VALID_RESET_VECTOR
+        }
         let extended = if developer_id == 51 {
             // later Extended Header
             OptExtendedHeader::Later {
@@ -287,6 +363,8 @@ impl Header {
             (_, 3, _) => Some(Coprocessor::Sa1),
             (_, 4, _) => Some(Coprocessor::Sdd1),
             (_, 5, _) => Some(Coprocessor::Srtc),
+            // chip byte 0xe3
+            (3, 14, _) => Some(Coprocessor::Sgb),
             (_, 15, 0) => Some(Coprocessor::Spc7110),
             (_, 15, 1) => Some(Coprocessor::St01x),
             (_, 15, 2) => Some(Coprocessor::St018),
@@ -306,22 +384,61 @@ impl Header {
                 ram_size,
                 country,
                 checksum,
+                checksum_complement,
                 version,
+                reset_vector,
+                image_format: ImageFormat::default(),
             },
             score,
         ))
     }
 
+    pub const fn image_format(&self) -> ImageFormat {
+        self.image_format
+    }
+
+    /// The checksum of the ROM stored in the header
+    pub const fn checksum(&self) -> u16 {
+        self.checksum
+    }
+
+    pub const fn rom_type(&self) -> RomType {
+        self.rom_type
+    }
+
+    /// The coprocessor announced by the chipset byte
+    pub const fn coprocessor(&self) -> Option<Coprocessor> {
+        self.coprocessor
+    }
+
+    /// Check if the cartridge announces fast ROM accesses
+    pub const fn is_fast(&self) -> bool {
+        self.is_fast
+    }
+
+    /// The size of the ROM in bytes announced by the header
+    pub const fn rom_size(&self) -> u32 {
+        self.rom_size
+    }
+
+    /// The size of the SRAM in bytes announced by the header
+    pub const fn ram_size(&self) -> u32 {
+        self.ram_size
+    }
+
+    /// The destination code, e.g. `0` for Japan and `1` for North America
+    pub const fn country(&self) -> u8 {
+        self.country
+    }
+
+    pub const fn version(&self) -> u8 {
+        self.version
+    }
+
     pub fn find_dsp_version(&self, rom_size: u32, ram_size: u32) -> Option<DspVersion> {
         let ver = match self.rom_type {
             RomType::LoRom => match (rom_size >> 20, ram_size >> 10) {
-                (1, 0) => {
-                    if self.name == "TOP GEAR 3000" {
-                        DspVersion::Dsp4
-                    } else {
-                        DspVersion::Dsp1
-                    }
-                }
+                (1, 0) => DspVersion::Dsp1,
                 (1, 32) => DspVersion::Dsp2,
                 (1, 8) => DspVersion::Dsp3,
                 (2, 8) => DspVersion::Dsp1,
@@ -399,7 +516,7 @@ impl save_state::InSaveState for ReadFunction {
             1 => Self::Sram,
             2 => Self::DspDr,
             3 => Self::DspSr,
-            _ => panic!("unknown enum discriminant {}", i),
+            _ => return state.fail(save_state::DeserializeError::InvalidValue),
         }
     }
 }
@@ -437,7 +554,7 @@ impl save_state::InSaveState for WriteFunction {
             0 => Self::Ignore,
             1 => Self::Sram,
             2 => Self::DspDr,
-            _ => panic!("unknown enum discriminant {}", i),
+            _ => return state.fail(save_state::DeserializeError::InvalidValue),
         }
     }
 }
@@ -447,12 +564,14 @@ struct MapFunction {
     bank_mask: u8,
     bank_lshift: u8,
     addr_mask: u16,
+    offset: u32,
 }
 
 impl MapFunction {
     pub fn run(&self, addr: Addr24) -> u32 {
-        (u32::from(addr.bank & self.bank_mask) << self.bank_lshift)
-            | u32::from(addr.addr & self.addr_mask)
+        ((u32::from(addr.bank & self.bank_mask) << self.bank_lshift)
+            | u32::from(addr.addr & self.addr_mask))
+            + self.offset
     }
 }
 
@@ -481,19 +600,80 @@ impl Default for MappingEntry {
     }
 }
 
-#[derive(Debug, Default, Clone, InSaveState)]
+/// Number of 8 KiB pages in the 24-bit address space
+pub(crate) const PAGE_COUNT: usize = 0x800;
+
+pub(crate) const fn page_index(addr: Addr24) -> usize {
+    ((addr.bank as usize) << 3) | (addr.addr as usize >> 13)
+}
+
+/// A precomputed lookup of the mapping entry of an 8 KiB page
+#[derive(Debug, Clone, Copy)]
+enum Page {
+    /// No entry is mapped into this page
+    Unmapped,
+    /// The whole page is mapped by a single entry
+    Mapped {
+        entry: u16,
+        /// The mapped address of the page without the offset
+        prefix: u32,
+        /// The part of the address mask inside of the page
+        addr_mask: u16,
+        offset: u32,
+        /// The memory, if the entry only reads and writes the ROM or the RAM
+        plain: Option<PlainMemory>,
+    },
+    /// Several entries share this page, so the entries have to be searched
+    Mixed,
+}
+
+/// The memory of a page, which can be accessed without a function of the entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlainMemory {
+    Rom,
+    Sram,
+}
+
+#[derive(Debug, Clone)]
 pub struct MemoryMapping {
     areas: Vec<MappingEntry>,
+    /// The page table, which must be rebuilt with [`MemoryMapping::build_pages`]
+    /// whenever `areas` changes
+    pages: Box<[Page]>,
+}
+
+impl Default for MemoryMapping {
+    fn default() -> Self {
+        Self {
+            areas: vec![],
+            pages: vec![Page::Unmapped; PAGE_COUNT].into_boxed_slice(),
+        }
+    }
+}
+
+impl save_state::InSaveState for MemoryMapping {
+    fn serialize(&self, state: &mut SaveStateSerializer) {
+        self.areas.serialize(state)
+    }
+
+    fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+        self.areas.deserialize(state);
+        self.build_pages()
+    }
 }
 
 macro_rules! map {
     ($slf:ident @ $sb:literal:$sa:literal .. $eb:literal:$ea:literal => $r:ident | $w:ident [$bmask:literal << $bls:literal : $amask:literal]) => {
+        map!($slf @ $sb:$sa .. $eb:$ea => $r | $w [$bmask << $bls : $amask] + 0)
+    };
+    ($slf:ident @ $sb:literal:$sa:literal .. $eb:literal:$ea:literal => $r:ident | $w:ident [$bmask:literal << $bls:literal : $amask:literal] + $offset:literal) => {
         $slf.areas.push(MappingEntry {
             area: Area::new(Addr24::new($sb, $sa), Addr24::new($eb, $ea)),
             map: MapFunction {
                 bank_mask: $bmask,
                 bank_lshift: $bls,
                 addr_mask: $amask,
+                offset: $offset,
             },
             read: ReadFunction::$r,
             write: WriteFunction::$w,
@@ -502,7 +682,40 @@ macro_rules! map {
 }
 
 impl MemoryMapping {
+    /// Find the mapping entry of an address and the mapped address
     pub fn find(&self, addr: Addr24) -> Option<(u32, &MappingEntry)> {
+        match self.pages[page_index(addr)] {
+            Page::Unmapped => None,
+            Page::Mapped {
+                entry,
+                prefix,
+                addr_mask,
+                offset,
+                ..
+            } => Some((
+                (prefix | u32::from(addr.addr & addr_mask)) + offset,
+                &self.areas[usize::from(entry)],
+            )),
+            Page::Mixed => self.search(addr),
+        }
+    }
+
+    /// Find the mapped address of an address in a page, which only maps the ROM or the RAM
+    fn find_plain(&self, addr: Addr24) -> Option<(PlainMemory, u32)> {
+        match self.pages[page_index(addr)] {
+            Page::Mapped {
+                prefix,
+                addr_mask,
+                offset,
+                plain: Some(memory),
+                ..
+            } => Some((memory, (prefix | u32::from(addr.addr & addr_mask)) + offset)),
+            _ => None,
+        }
+    }
+
+    /// Find the mapping entry of an address by searching all entries
+    fn search(&self, addr: Addr24) -> Option<(u32, &MappingEntry)> {
         self.areas.iter().find_map(|entry| {
             if entry.area.find(addr) {
                 Some((entry.map.run(addr), entry))
@@ -511,6 +724,47 @@ impl MemoryMapping {
             }
         })
     }
+
+    /// Build the page table from the mapping entries.
+    ///
+    /// The first entry containing an address is used, so a page is only
+    /// looked up directly if the first entry touching it covers the whole page.
+    fn build_pages(&mut self) {
+        for (index, page) in self.pages.iter_mut().enumerate() {
+            let bank = (index >> 3) as u8;
+            let (start, end) = (
+                ((index & 7) << 13) as u16,
+                ((index & 7) << 13 | 0x1fff) as u16,
+            );
+            let first = self.areas.iter().enumerate().find(|(_, entry)| {
+                let area = &entry.area;
+                (area.start.bank..=area.end.bank).contains(&bank)
+                    && area.start.addr <= end
+                    && area.end.addr >= start
+            });
+            *page = match first {
+                None => Page::Unmapped,
+                Some((i, entry))
+                    if entry.area.start.addr <= start && entry.area.end.addr >= end =>
+                {
+                    let map = &entry.map;
+                    Page::Mapped {
+                        entry: i as u16,
+                        prefix: (u32::from(bank & map.bank_mask) << map.bank_lshift)
+                            | u32::from(start & map.addr_mask),
+                        addr_mask: map.addr_mask & 0x1fff,
+                        offset: map.offset,
+                        plain: match (entry.read, entry.write) {
+                            (ReadFunction::Rom, WriteFunction::Ignore) => Some(PlainMemory::Rom),
+                            (ReadFunction::Sram, WriteFunction::Sram) => Some(PlainMemory::Sram),
+                            _ => None,
+                        },
+                    }
+                }
+                Some(_) => Page::Mixed,
+            }
+        }
+    }
 }
 
 fn copy_rom(dst: &mut [u8], src: &[u8]) {
@@ -535,6 +789,142 @@ fn copy_rom(dst: &mut [u8], src: &[u8]) {
     }
 }
 
+/// Check the size of a ROM file and remove the header of a copier device
+fn strip_copier_header<'a>(
+    bytes: &'a [u8],
+    image_format: &mut ImageFormat,
+) -> Result<&'a [u8], ReadRomError> {
+    if bytes.len() < MINIMUM_SIZE {
+        return Err(ReadRomError::TooSmall(bytes.len()));
+    }
+    if bytes.len() & 0x1ff != 0 {
+        return Err(ReadRomError::AlignError(bytes.len()));
+    }
+    if bytes.len() & 0x3ff == 0 {
+        Ok(bytes)
+    } else {
+        image_format.copier_header = true;
+        Ok(&bytes[512..])
+    }
+}
+
+/// Sum of all bytes of the ROM
+fn calculate_checksum(rom: &[u8]) -> u16 {
+    use core::num::Wrapping;
+    let Wrapping(checksum): Wrapping<u16> = rom.iter().copied().map(Into::into).map(Wrapping).sum();
+    checksum
+}
+
+/// The checksum of `dst_len` bytes filled by [`copy_rom`] from `src`
+fn mirrored_checksum(dst_len: usize, src: &[u8]) -> u16 {
+    if dst_len <= src.len() {
+        calculate_checksum(&src[..dst_len])
+    } else if src.len().is_power_of_two() {
+        let copies = (dst_len / src.len()) as u16;
+        calculate_checksum(src)
+            .wrapping_mul(copies)
+            .wrapping_add(calculate_checksum(&src[..dst_len % src.len()]))
+    } else {
+        let left_part = src.len().next_power_of_two() >> 1;
+        let right_part = (src.len() - left_part).next_power_of_two();
+        let copies = ((dst_len - left_part) / right_part) as u16;
+        calculate_checksum(&src[..left_part])
+            .wrapping_add(mirrored_checksum(right_part, &src[left_part..]).wrapping_mul(copies))
+    }
+}
+
+/// The checksum of the ROM created by [`create_rom`], without creating it
+fn rom_checksum(content: &[u8], size: u32) -> u16 {
+    let size = size as usize;
+    let len = if content.len() > size {
+        content.len().next_power_of_two()
+    } else {
+        size
+    };
+    mirrored_checksum(len, content)
+}
+
+/// Find the most plausible header and its address in a ROM file without a copier header.
+///
+/// Every possible header location gets scored by the header contents,
+/// by the checksum and by the instruction at the reset vector.
+fn find_header(bytes: &[u8]) -> Option<(Header, usize)> {
+    const MATCHING_LOCATION: u16 = 16;
+    const VALID_CHECKSUM: u16 = 48;
+    const PLAUSIBLE_FIRST_OPCODE: u16 = 8;
+    const IMPLAUSIBLE_FIRST_OPCODE: u16 = 16;
+    const IMPLAUSIBLE_ROM_SIZE: u16 = 32;
+
+    let mut header: Option<(Header, u16, usize)> = None;
+    for addr in [0x7fb0, 0xffb0, 0x40ffb0] {
+        if bytes.len() < addr + 80 {
+            continue;
+        }
+        let (mut new, mut score) = match Header::from_bytes(&bytes[addr..addr + 80]) {
+            Some(header) => header,
+            None => continue,
+        };
+        if database::find(&new).is_some() {
+            return Some((new, addr));
+        }
+        if new.rom_type.header_address() == addr {
+            score += MATCHING_LOCATION
+        }
+        // the size byte of a garbage header may announce gigabytes
+        if !(0x400..=MAXIMUM_ROM_SIZE).contains(&new.rom_size) {
+            new.rom_size = bytes.len().next_power_of_two() as u32;
+            score = score.saturating_sub(IMPLAUSIBLE_ROM_SIZE)
+        }
+        if rom_checksum(bytes, new.rom_size) == new.checksum {
+            score += VALID_CHECKSUM
+        }
+        if new.reset_vector >= 0x8000 {
+            let reset = usize::from(new.reset_vector);
+            let entry = if addr == 0x7fb0 {
+                reset & 0x7fff
+            } else {
+                (addr & !0xffff) | reset
+            };
+            match bytes.get(entry) {
+                // sei, clc, sec, rep, sep, stz, jmp, jml
+                Some(0x78 | 0x18 | 0x38 | 0xc2 | 0xe2 | 0x9c | 0x4c | 0x5c) => {
+                    score += PLAUSIBLE_FIRST_OPCODE
+                }
+                // brk, cop, wdm, stp, sbc long
+                Some(0x00 | 0x02 | 0x42 | 0xdb | 0xff) => {
+                    score = score.saturating_sub(IMPLAUSIBLE_FIRST_OPCODE)
+                }
+                _ => (),
+            }
+        }
+        if header.as_ref().is_none_or(|(_, s, _)| score > *s) {
+            header = Some((new, score, addr));
+        }
+    }
+    header.map(|(header, _, addr)| (header, addr))
+}
+
+/// Check if the file looks like an interleaved HiROM dump.
+///
+/// Those dumps store the upper 32 KiB halves of all banks before the lower
+/// halves, so that the HiROM header is found at the LoROM header location.
+fn is_interleaved(bytes: &[u8], header: &Header, addr: usize) -> bool {
+    addr == 0x7fb0
+        && matches!(header.rom_type, RomType::HiRom | RomType::HiRomSPC7110)
+        && bytes.len() & 0xffff == 0
+}
+
+/// Reorder the 32 KiB blocks of an interleaved dump
+fn deinterleave(bytes: &[u8]) -> Vec<u8> {
+    let (upper, lower) = bytes.split_at(bytes.len() >> 1);
+    lower
+        .chunks(0x8000)
+        .zip(upper.chunks(0x8000))
+        .flat_map(|(lower, upper)| lower.iter().chain(upper))
+        .copied()
+        .collect()
+}
+
 fn create_rom(content: &[u8], size: u32) -> Vec<u8> {
     let size = size as usize;
     let mut rom = if content.len() > size {
@@ -553,40 +943,44 @@ pub struct Cartridge {
     ram: Vec<u8>,
     dsp: Option<Dsp>,
     sa1: Option<Sa1>,
+    bsx: Option<Bsx>,
+    msu1: Option<Msu1>,
+    srtc: Option<Srtc>,
+    #[cfg(feature = "sgb")]
+    sgb: Option<Icd2>,
     mapping: MemoryMapping,
+    /// Values of cheat codes, which replace the values read from an address
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    read_patches: HashMap<Addr24, u8>,
+    /// The wall clock used by the cartridge, the system clock if it is `None`
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    clock: Option<Arc<dyn ClockSource>>,
 }
 
 impl Cartridge {
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReadRomError> {
-        if bytes.len() < MINIMUM_SIZE {
-            return Err(ReadRomError::TooSmall(bytes.len()));
-        }
-        if bytes.len() & 0x1ff != 0 {
-            return Err(ReadRomError::AlignError(bytes.len()));
-        }
-        let bytes = if bytes.len() & 0x3ff == 0 {
-            bytes
-        } else {
-            &bytes[512..]
-        };
-
-        let mut header = None;
-        for addr in [0x7fb0, 0xffb0, 0x40ffb0] {
-            if bytes.len() >= addr + 80 {
-                if let Some((new, score)) = Header::from_bytes(&bytes[addr..addr + 80]) {
-                    if header.as_ref().map(|(_, s)| score > *s).unwrap_or(true) {
-                        header = Some((new, score));
-                    }
-                }
+        let mut image_format = ImageFormat::default();
+        let mut bytes = strip_copier_header(bytes, &mut image_format)?;
+
+        let (mut header, addr) = find_header(bytes).ok_or(ReadRomError::NoSuitableHeader)?;
+        let deinterleaved;
+        if is_interleaved(bytes, &header, addr) {
+            deinterleaved = deinterleave(bytes);
+            if let Some((new, 0xffb0)) = find_header(&deinterleaved) {
+                image_format.interleaved = true;
+                bytes = &deinterleaved;
+                header = new;
             }
         }
-        let (header, _score) = header.ok_or(ReadRomError::NoSuitableHeader)?;
+        header.image_format = image_format;
+        let db_entry = database::find(&header);
+        if let Some(entry) = db_entry {
+            entry.apply(&mut header)
+        }
 
         let rom = create_rom(bytes, header.rom_size);
 
-        use core::num::Wrapping;
-        let Wrapping(checksum): Wrapping<u16> =
-            rom.iter().copied().map(Into::into).map(Wrapping).sum();
+        let checksum = calculate_checksum(&rom);
         if checksum != header.checksum {
             eprintln!("warning: checksum did not match! Checksum in ROM is {:04x}; Calculated checksum is {:04x}", header.checksum, checksum);
         }
@@ -594,8 +988,9 @@ impl Cartridge {
         let ram_size = header.ram_size;
 
         let dsp = if let Some(Coprocessor::Dsp) = header.coprocessor {
-            let ver = header
-                .find_dsp_version(rom.len() as u32, ram_size)
+            let ver = db_entry
+                .and_then(|entry| entry.dsp_version)
+                .or_else(|| header.find_dsp_version(rom.len() as u32, ram_size))
                 .unwrap_or_else(|| panic!("could not select a NEC-DSP version for this game"));
             Some(Dsp::new(ver))
         } else {
@@ -608,12 +1003,38 @@ impl Cartridge {
             None
         };
 
+        let bsx = if let Some(Coprocessor::Bsx) = header.coprocessor {
+            Some(Bsx::new())
+        } else {
+            None
+        };
+
+        let srtc = if let Some(Coprocessor::Srtc) = header.coprocessor {
+            Some(Srtc::new())
+        } else {
+            None
+        };
+
+        #[cfg(feature = "sgb")]
+        let sgb = if let Some(Coprocessor::Sgb) = header.coprocessor {
+            Some(Icd2::new(header.name.contains("GAMEBOY2")))
+        } else {
+            None
+        };
+
         let mut slf = Self {
             rom,
             ram: vec![0xff; ram_size as usize],
             mapping: MemoryMapping::default(),
             dsp,
             sa1,
+            bsx,
+            msu1: None,
+            srtc,
+            #[cfg(feature = "sgb")]
+            sgb,
+            read_patches: HashMap::new(),
+            clock: None,
             header,
         };
 
@@ -622,6 +1043,134 @@ impl Cartridge {
         Ok(slf)
     }
 
+    /// Load a raw binary without a header, e.g. the output of a homebrew assembler.
+    ///
+    /// The binary is mapped as `rom_type`, which is one of [`RomType::NAMES`],
+    /// and the reset vector at `$00:FFFC` is replaced by `reset_vector`.
+    /// The cartridge has no SRAM and no coprocessor. Implausible binaries are
+    /// loaded anyway, only a warning is printed.
+    pub fn from_raw(
+        bytes: &[u8],
+        rom_type: RomType,
+        reset_vector: u16,
+    ) -> Result<Self, ReadRomError> {
+        let vector_addr = match rom_type {
+            RomType::LoRom => 0x7ffc,
+            RomType::HiRom => 0xfffc,
+            _ => return Err(ReadRomError::UnsupportedRawMapping(rom_type)),
+        };
+        if bytes.is_empty() {
+            return Err(ReadRomError::TooSmall(0));
+        }
+        if bytes.len() % MINIMUM_SIZE != 0 {
+            eprintln!(
+                "warning: the size of the binary ({} bytes) is no multiple of 32 KiB",
+                bytes.len()
+            );
+        }
+        if reset_vector < 0x8000 {
+            eprintln!(
+                "warning: the reset vector ${:04x} does not point into the ROM",
+                reset_vector
+            );
+        }
+        let size = (vector_addr + 4).max(bytes.len()).next_power_of_two();
+        let mut rom = create_rom(bytes, size as u32);
+        let vector = &mut rom[vector_addr..vector_addr + 2];
+        let old_vector = u16::from_le_bytes([vector[0], vector[1]]);
+        if bytes.len() > vector_addr
+            && !matches!(old_vector, 0 | 0xffff)
+            && old_vector != reset_vector
+        {
+            eprintln!(
+                "warning: the reset vector ${:04x} of the binary is replaced by ${:04x}",
+                old_vector, reset_vector
+            );
+        }
+        vector.copy_from_slice(&reset_vector.to_le_bytes());
+
+        let header = Header {
+            rom_type,
+            rom_size: rom.len() as u32,
+            checksum: calculate_checksum(&rom),
+            reset_vector,
+            ..Header::default()
+        };
+        let mut slf = Self {
+            header,
+            rom,
+            ..Self::default()
+        };
+        slf.setup_memory_mappings();
+        Ok(slf)
+    }
+
+    /// Create a cartridge of the Sufami Turbo adapter.
+    ///
+    /// `base` is the BIOS of the adapter, `slot_a` and `slot_b`
+    /// are the mini-cartridges inserted into the slots.
+    /// The title of the cartridge is the title of the first mini-cartridge.
+    pub fn from_sufami(
+        base: &[u8],
+        slot_a: Option<&[u8]>,
+        slot_b: Option<&[u8]>,
+    ) -> Result<Self, ReadRomError> {
+        let mut slf = Self::from_bytes(base)?;
+        let slots = [slot_a, slot_b].map(|slot| {
+            slot.map(|bytes| {
+                let bytes = strip_copier_header(bytes, &mut ImageFormat::default())?;
+                if bytes.starts_with(SUFAMI_MAGIC) {
+                    Ok(bytes)
+                } else {
+                    Err(ReadRomError::NoSufamiTurboCartridge)
+                }
+            })
+            .transpose()
+        });
+        let [slot_a, slot_b] = slots;
+        let (slot_a, slot_b) = (slot_a?, slot_b?);
+
+        // the BIOS and both slots get 1 MiB of ROM and 128 KiB of SRAM each
+        let mut rom = vec![0; 0x400000];
+        copy_rom(&mut rom[..0x100000], &slf.rom);
+        for (i, slot) in [slot_a, slot_b].into_iter().enumerate() {
+            if let Some(slot) = slot {
+                copy_rom(&mut rom[(i + 1) << 20..(i + 2) << 20], slot);
+            }
+        }
+        if let Some(slot) = slot_a.or(slot_b) {
+            // the title is stored after the magic bytes
+            slf.header.name = slot[0x10..0x24]
+                .iter()
+                .filter(|c| (b' '..=b'~').contains(c))
+                .map(|&c| char::from(c))
+                .collect::<String>()
+                .trim()
+                .to_string();
+        }
+        slf.rom = rom;
+        slf.ram = vec![0xff; 0x40000];
+
+        let map = &mut slf.mapping;
+        map.areas.clear();
+        map!(map @ 0x00:0x8000 .. 0x1f:0xffff => Rom | Ignore [0x1f<<15:0x7fff]);
+        map!(map @ 0x80:0x8000 .. 0x9f:0xffff => Rom | Ignore [0x1f<<15:0x7fff]);
+        if slot_a.is_some() {
+            map!(map @ 0x20:0x8000 .. 0x3f:0xffff => Rom | Ignore [0x1f<<15:0x7fff] + 0x100000);
+            map!(map @ 0xa0:0x8000 .. 0xbf:0xffff => Rom | Ignore [0x1f<<15:0x7fff] + 0x100000);
+            map!(map @ 0x60:0x8000 .. 0x63:0xffff => Sram | Sram [0x3<<15:0x7fff]);
+            map!(map @ 0xe0:0x8000 .. 0xe3:0xffff => Sram | Sram [0x3<<15:0x7fff]);
+        }
+        if slot_b.is_some() {
+            map!(map @ 0x40:0x8000 .. 0x5f:0xffff => Rom | Ignore [0x1f<<15:0x7fff] + 0x200000);
+            map!(map @ 0xc0:0x8000 .. 0xdf:0xffff => Rom | Ignore [0x1f<<15:0x7fff] + 0x200000);
+            map!(map @ 0x70:0x8000 .. 0x73:0xffff => Sram | Sram [0x3<<15:0x7fff] + 0x20000);
+            map!(map @ 0xf0:0x8000 .. 0xf3:0xffff => Sram | Sram [0x3<<15:0x7fff] + 0x20000);
+        }
+        map.build_pages();
+        Ok(slf)
+    }
+
     fn setup_memory_mappings(&mut self) {
         let map = &mut self.mapping;
         match self.header.rom_type {
@@ -685,12 +1234,38 @@ impl Cartridge {
                     }
                 }
             }
+            RomType::ExHiRom => {
+                map!(map @ 0x00:0x8000 .. 0x3f:0xffff => Rom | Ignore [0x3f<<16:0xffff] + 0x400000);
+                map!(map @ 0x40:0x0000 .. 0x7d:0xffff => Rom | Ignore [0x3f<<16:0xffff] + 0x400000);
+                map!(map @ 0x80:0x8000 .. 0xbf:0xffff => Rom | Ignore [0x3f<<16:0xffff]);
+                map!(map @ 0xc0:0x0000 .. 0xff:0xffff => Rom | Ignore [0x3f<<16:0xffff]);
+                if !self.ram.is_empty() {
+                    map!(map @ 0x20:0x6000 .. 0x3f:0x7fff => Sram | Sram [0x3f<<13:0x1fff]);
+                    map!(map @ 0xa0:0x6000 .. 0xbf:0x7fff => Sram | Sram [0x3f<<13:0x1fff]);
+                }
+            }
             ty => todo!("unsupported rom type {:?}", ty),
         }
+        map.build_pages();
     }
 
     pub fn read_byte(&mut self, addr: Addr24) -> Option<u8> {
-        if self.has_sa1() {
+        let val = self.read_mapped_byte(addr);
+        self.read_patches.get(&addr).copied().or(val)
+    }
+
+    fn read_mapped_byte(&mut self, addr: Addr24) -> Option<u8> {
+        #[cfg(feature = "sgb")]
+        if let Some(val) = self.sgb.as_mut().and_then(|sgb| sgb.read(addr)) {
+            return Some(val);
+        }
+        if let Some(val) = self.msu1.as_mut().and_then(|msu1| msu1.read(addr)) {
+            Some(val)
+        } else if let Some(val) = self.srtc.as_mut().and_then(|srtc| srtc.read(addr)) {
+            Some(val)
+        } else if let Some(bsx) = &self.bsx {
+            bsx.read(&self.rom, addr)
+        } else if self.has_sa1() {
             self.sa1_read::<false>(addr)
         } else {
             if let Some((index, MappingEntry { read, .. })) = self.mapping.find(addr) {
@@ -702,7 +1277,17 @@ impl Cartridge {
     }
 
     pub fn write_byte(&mut self, addr: Addr24, val: u8) {
-        if self.has_sa1() {
+        #[cfg(feature = "sgb")]
+        if self.sgb.as_mut().is_some_and(|sgb| sgb.write(addr, val)) {
+            return;
+        }
+        if self.msu1.as_mut().is_some_and(|msu1| msu1.write(addr, val)) {
+            // handled by the MSU-1
+        } else if self.srtc.as_mut().is_some_and(|srtc| srtc.write(addr, val)) {
+            // handled by the S-RTC
+        } else if let Some(bsx) = &mut self.bsx {
+            bsx.write(addr, val)
+        } else if self.has_sa1() {
             self.sa1_write::<false>(addr, val)
         } else {
             if let Some((index, MappingEntry { write, .. })) = self.mapping.find(addr) {
@@ -711,6 +1296,75 @@ impl Cartridge {
         }
     }
 
+    /// Read a byte of the ROM or the RAM without side effects.
+    ///
+    /// Registers of coprocessors are not read, `None` is returned instead.
+    pub fn peek_byte(&self, addr: Addr24) -> Option<u8> {
+        let val = if let Some(bsx) = &self.bsx {
+            bsx.read(&self.rom, addr)
+        } else if self.has_sa1() {
+            self.sa1_peek(addr)
+        } else {
+            match self.mapping.find(addr) {
+                Some((index, MappingEntry { read, .. })) => match read {
+                    ReadFunction::Rom => Some(self.read_rom(index)),
+                    ReadFunction::Sram => Some(self.ram[self.get_sram_addr(index)]),
+                    ReadFunction::DspDr | ReadFunction::DspSr => None,
+                },
+                None => None,
+            }
+        };
+        self.read_patches.get(&addr).copied().or(val)
+    }
+
+    /// Test if the CPU accesses the cartridge only through the mapping,
+    /// i.e. there are no coprocessors or expansions intercepting accesses
+    fn is_only_mapped(&self) -> bool {
+        #[cfg(feature = "sgb")]
+        if self.sgb.is_some() {
+            return false;
+        }
+        !(self.msu1.is_some() || self.srtc.is_some() || self.bsx.is_some() || self.has_sa1())
+    }
+
+    /// Test if `addr` is mapped to the ROM or the RAM, so that reading it
+    /// has no side effects and only writes of the CPU change the value.
+    ///
+    /// Addresses of cartridges with coprocessors or expansions are never plain.
+    pub fn is_plain_memory(&self, addr: Addr24) -> bool {
+        self.is_only_mapped()
+            && matches!(
+                self.mapping.find(addr),
+                Some((
+                    _,
+                    MappingEntry {
+                        read: ReadFunction::Rom | ReadFunction::Sram,
+                        ..
+                    }
+                ))
+            )
+    }
+
+    /// Write a byte into the ROM or the RAM, e.g. by a debugger.
+    ///
+    /// Registers of coprocessors and the memory of the BS-X cartridge are not written.
+    pub fn poke_byte(&mut self, addr: Addr24, val: u8) {
+        if self.bsx.is_some() {
+            // flash memory is only written by commands
+        } else if self.has_sa1() {
+            self.sa1_poke(addr, val)
+        } else if let Some((index, MappingEntry { read, .. })) = self.mapping.find(addr) {
+            match read {
+                ReadFunction::Rom => {
+                    let index = self.get_rom_addr(index);
+                    self.rom[index] = val
+                }
+                ReadFunction::Sram => self.write_sram(index, val),
+                ReadFunction::DspDr | ReadFunction::DspSr => (),
+            }
+        }
+    }
+
     pub const fn get_country_frame_rate(&self) -> CountryFrameRate {
         use CountryFrameRate::*;
         match self.header.country {
@@ -732,6 +1386,56 @@ impl Cartridge {
         &self.header.name
     }
 
+    /// The battery backed RAM of the cartridge
+    pub fn sram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// The lowest CPU address of a byte of the battery backed RAM.
+    ///
+    /// `None` is returned if the offset is out of range or the RAM
+    /// is not mapped, e.g. by the SA-1 or the BS-X cartridge.
+    pub fn sram_addr(&self, offset: u32) -> Option<Addr24> {
+        if offset as usize >= self.ram.len() || self.bsx.is_some() || self.has_sa1() {
+            return None;
+        }
+        let mask = self.ram.len() as u32 - 1;
+        let entries = self.mapping.areas.iter().filter(|entry| {
+            matches!(
+                entry,
+                MappingEntry {
+                    read: ReadFunction::Sram,
+                    write: WriteFunction::Sram,
+                    ..
+                }
+            )
+        });
+        entries.flat_map(|entry| {
+            let Area { start, end } = entry.area;
+            (start.bank..=end.bank).filter_map(move |bank| {
+                // invert the mapping function, the result is checked below
+                let MapFunction {
+                    bank_mask,
+                    bank_lshift,
+                    addr_mask,
+                    offset: base,
+                } = entry.map;
+                let high = u32::from(bank & bank_mask) << bank_lshift;
+                let low = offset.wrapping_sub(base).wrapping_sub(high) & mask;
+                let addr = (start.addr & !addr_mask) | (low as u16 & addr_mask);
+                (start.addr..=end.addr)
+                    .contains(&addr)
+                    .then_some(Addr24::new(bank, addr))
+            })
+        })
+        .find(|&addr| {
+            matches!(
+                self.mapping.find(addr),
+                Some((index, MappingEntry { read: ReadFunction::Sram, .. })) if index & mask == offset
+            )
+        })
+    }
+
     fn get_sram_addr(&self, addr: u32) -> usize {
         addr as usize & (self.ram.len() - 1)
     }
@@ -779,6 +1483,9 @@ impl Cartridge {
 
     /// Read from the cartridge
     pub fn read<D: Data>(&mut self, mut addr: Addr24) -> Option<D> {
+        if let Some(value) = self.read_plain(addr) {
+            return Some(value);
+        }
         let mut arr: D::Arr = Default::default();
         let mut open_bus = None;
         for v in arr.as_mut() {
@@ -791,12 +1498,55 @@ impl Cartridge {
 
     /// Write to the cartridge
     pub fn write<D: Data>(&mut self, mut addr: Addr24, value: D) {
+        if self.write_plain(addr, value) {
+            return;
+        }
         for &v in value.to_bytes().as_ref().iter() {
             self.write_byte(addr, v);
             addr.addr = addr.addr.wrapping_add(1);
         }
     }
 
+    /// Read the ROM or the RAM directly through the page table.
+    /// Returns `None`, if a byte isn't plain memory or is patched by a cheat code.
+    fn read_plain<D: Data>(&self, mut addr: Addr24) -> Option<D> {
+        if !self.read_patches.is_empty() || !self.is_only_mapped() {
+            return None;
+        }
+        let mut arr: D::Arr = Default::default();
+        for v in arr.as_mut() {
+            *v = match self.mapping.find_plain(addr)? {
+                (PlainMemory::Rom, index) => self.read_rom(index),
+                (PlainMemory::Sram, index) => self.ram[self.get_sram_addr(index)],
+            };
+            addr.addr = addr.addr.wrapping_add(1);
+        }
+        Some(D::from_bytes(&arr))
+    }
+
+    /// Write the RAM directly through the page table, writes of the ROM are ignored.
+    /// Returns false without writing anything, if a byte isn't plain memory.
+    fn write_plain<D: Data>(&mut self, addr: Addr24, value: D) -> bool {
+        if !self.is_only_mapped() {
+            return false;
+        }
+        let bytes = value.to_bytes();
+        let targets = (0..bytes.as_ref().len() as u16).map(|i| {
+            self.mapping
+                .find_plain(Addr24::new(addr.bank, addr.addr.wrapping_add(i)))
+        });
+        if targets.clone().any(|target| target.is_none()) {
+            return false;
+        }
+        for (target, &v) in targets.flatten().zip(bytes.as_ref()) {
+            if let (PlainMemory::Sram, index) = target {
+                let index = self.get_sram_addr(index);
+                self.ram[index] = v
+            }
+        }
+        true
+    }
+
     pub fn set_region(&mut self, pal: bool) {
         if let Some(dsp) = &mut self.dsp {
             dsp.set_timing_proportion(if pal {
@@ -808,18 +1558,165 @@ impl Cartridge {
         if let Some(sa1) = &mut self.sa1 {
             sa1.set_region(pal)
         }
+        if let Some(msu1) = &mut self.msu1 {
+            msu1.set_region(pal)
+        }
+        if let Some(srtc) = &mut self.srtc {
+            srtc.set_region(pal)
+        }
+        #[cfg(feature = "sgb")]
+        if let Some(sgb) = &mut self.sgb {
+            sgb.set_region(pal)
+        }
+    }
+
+    /// Reset the coprocessors, the cartridge RAM keeps its contents
+    pub fn reset(&mut self) {
+        if let Some(dsp) = &mut self.dsp {
+            dsp.reset()
+        }
+        if let Some(sa1) = &mut self.sa1 {
+            sa1.reset()
+        }
+        #[cfg(feature = "sgb")]
+        if let Some(sgb) = &mut self.sgb {
+            sgb.reset()
+        }
     }
 
     pub fn tick(&mut self, n: Cycles) {
         if let Some(dsp) = &mut self.dsp {
             dsp.tick(n)
         }
+        if let Some(msu1) = &mut self.msu1 {
+            msu1.tick(n)
+        }
+        if let Some(srtc) = &mut self.srtc {
+            srtc.tick(n)
+        }
+        #[cfg(feature = "sgb")]
+        if let Some(sgb) = &mut self.sgb {
+            sgb.tick(n.into())
+        }
+    }
+
+    /// Replace the values read from the given addresses, e.g. by cheat codes
+    pub fn set_read_patches(&mut self, patches: impl IntoIterator<Item = (Addr24, u8)>) {
+        self.read_patches = patches.into_iter().collect()
+    }
+
+    /// Connect the MSU-1 expansion, which streams the files provided by `media`
+    pub fn attach_msu1(&mut self, media: Arc<dyn MediaBackend>) {
+        self.msu1 = Some(Msu1::new(media))
+    }
+
+    pub fn has_msu1(&self) -> bool {
+        self.msu1.is_some()
+    }
+
+    /// The audio output of the expansion chips, which gets mixed into the DSP output
+    pub fn expansion_audio(&self) -> Option<AudioOutput> {
+        #[cfg(feature = "sgb")]
+        if let Some(sgb) = &self.sgb {
+            return Some(sgb.output().clone());
+        }
+        self.msu1.as_ref().map(|msu1| msu1.output().clone())
     }
 
     pub fn refresh_coprocessors(&mut self) {
         if let Some(dsp) = &mut self.dsp {
             dsp.refresh()
         }
+        #[cfg(feature = "sgb")]
+        if let Some(sgb) = &mut self.sgb {
+            sgb.refresh()
+        }
+    }
+
+    /// Read from a register on address bus B, which belongs to the expansion port
+    pub fn read_bus_b(&mut self, addr: u8) -> Option<u8> {
+        self.bsx.as_mut()?.read_bus_b(addr)
+    }
+
+    /// Write to a register on address bus B, which belongs to the expansion port
+    pub fn write_bus_b(&mut self, addr: u8, val: u8) {
+        if let Some(bsx) = &mut self.bsx {
+            let clock = self.clock.as_deref().unwrap_or(&SystemClock);
+            bsx.write_bus_b(addr, val, clock)
+        }
+    }
+
+    /// Set the wall clock used by the cartridge, e.g. for the time broadcast of the BS-X.
+    ///
+    /// A real-time clock, which was not restored from an SRAM file, is set to its time.
+    pub fn set_clock_source(&mut self, clock: Arc<dyn ClockSource>) {
+        if let Some(srtc) = &mut self.srtc {
+            srtc.synchronize(clock.unix_time())
+        }
+        self.clock = Some(clock)
+    }
+
+    fn unix_time(&self) -> u64 {
+        self.clock.as_deref().unwrap_or(&SystemClock).unix_time()
+    }
+
+    /// The contents of an SRAM file: the battery backed RAM followed by the
+    /// state of the real-time clock, if the cartridge has one
+    pub fn sram_file(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(srtc) = &self.srtc {
+            data.extend_from_slice(&srtc.battery_data(self.unix_time()))
+        }
+        data
+    }
+
+    /// Restore the contents of an SRAM file written by [`Self::sram_file`].
+    /// Missing bytes are left unchanged and surplus bytes are ignored.
+    pub fn load_sram_file(&mut self, data: &[u8]) {
+        let len = self.ram.len().min(data.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+        let unix_time = self.unix_time();
+        if let (Some(srtc), Some(Ok(rtc))) = (
+            &mut self.srtc,
+            data.get(self.ram.len()..self.ram.len() + srtc::BATTERY_SIZE)
+                .map(TryInto::try_into),
+        ) {
+            srtc.load_battery_data(rtc, unix_time)
+        }
+    }
+
+    /// The devices the game expects at the controller ports 1 and 2,
+    /// if they are known by the game database
+    pub fn peripherals(&self) -> Option<[DeviceKind; 2]> {
+        database::find(&self.header).and_then(|entry| entry.ports)
+    }
+
+    pub fn has_srtc(&self) -> bool {
+        self.srtc.is_some()
+    }
+
+    /// Insert a flash memory pack into the slot of the cartridge
+    pub fn insert_memory_pack(&mut self, data: Vec<u8>) -> Result<(), ReadRomError> {
+        let bsx = self.bsx.as_mut().ok_or(ReadRomError::NoMemoryPackSlot)?;
+        bsx.insert_memory_pack(MemoryPack::new(data));
+        Ok(())
+    }
+
+    /// Connect the Game Boy to the ICD2 of a Super Game Boy cartridge
+    #[cfg(feature = "sgb")]
+    pub fn insert_game_boy(&mut self, game_boy: Box<dyn GameBoy>) -> Result<(), ReadRomError> {
+        let sgb = self.sgb.as_mut().ok_or(ReadRomError::NoGameBoySlot)?;
+        sgb.connect(game_boy);
+        Ok(())
+    }
+
+    #[cfg(feature = "sgb")]
+    pub fn has_sgb(&self) -> bool {
+        self.sgb.is_some()
+    }
+
+    pub fn has_bsx(&self) -> bool {
+        self.bsx.is_some()
     }
 
     pub fn has_sa1(&self) -> bool {
@@ -838,3 +1735,6 @@ impl Cartridge {
             .expect("unexpectedly queried sa1-chip in a non-sa1 cartridge")
     }
 }
+
+#[cfg(test)]
+mod tests;
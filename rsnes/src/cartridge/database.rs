@@ -0,0 +1,106 @@
+//! Cartridges which can not be detected correctly by their header
+//!
+//! Entries are keyed by the cartridge title and by the checksum and its
+//! complement stored in the cartridge header, if only some revisions of
+//! the game need the entry. They override the detected memory mapping and coprocessor
+//! and name the devices, which the game expects at the controller ports.
+//! The database is only used with the `game-db` feature.
+
+use super::{Coprocessor, Header, RomType};
+use crate::{
+    controller::DeviceKind::{self, Mouse, Multitap, Standard, SuperScope},
+    enhancement::DspVersion,
+};
+
+pub(super) struct Entry {
+    name: &'static str,
+    /// The checksum and its complement in the header,
+    /// `None` matches every revision of the game
+    checksum: Option<[u16; 2]>,
+    rom_type: Option<RomType>,
+    coprocessor: Option<Option<Coprocessor>>,
+    pub(super) dsp_version: Option<DspVersion>,
+    /// The devices at the controller ports 1 and 2
+    pub(super) ports: Option<[DeviceKind; 2]>,
+}
+
+/// An entry of a game, which is only played with special devices
+const fn peripherals(name: &'static str, ports: [DeviceKind; 2]) -> Entry {
+    Entry {
+        name,
+        checksum: None,
+        rom_type: None,
+        coprocessor: None,
+        dsp_version: None,
+        ports: Some(ports),
+    }
+}
+
+const ENTRIES: &[Entry] = &[
+    // the header does not distinguish the DSP-4 from the DSP-1
+    Entry {
+        name: "TOP GEAR 3000",
+        checksum: None,
+        rom_type: Some(RomType::LoRom),
+        coprocessor: Some(Some(Coprocessor::Dsp)),
+        dsp_version: Some(DspVersion::Dsp4),
+        ports: None,
+    },
+    // the BS-X BIOS has a plain LoROM header
+    Entry {
+        name: "Satellaview BS-X",
+        checksum: None,
+        rom_type: Some(RomType::LoRom),
+        coprocessor: Some(Some(Coprocessor::Bsx)),
+        dsp_version: None,
+        ports: None,
+    },
+    // games drawn with the SNES Mouse
+    peripherals("MARIO PAINT", [Mouse, Standard]),
+    peripherals("MARIOPAINT", [Mouse, Standard]),
+    // Super Scope games
+    peripherals("SUPER SCOPE 6", [Standard, SuperScope]),
+    peripherals("SUPERSCOPE 6", [Standard, SuperScope]),
+    peripherals("BATTLE CLASH", [Standard, SuperScope]),
+    peripherals("METAL COMBAT", [Standard, SuperScope]),
+    peripherals("YOSHI'S SAFARI", [Standard, SuperScope]),
+    // games for up to five players
+    peripherals("SUPER BOMBERMAN", [Standard, Multitap]),
+    peripherals("SUPER BOMBERMAN2", [Standard, Multitap]),
+    peripherals("SUPER BOMBERMAN 3", [Standard, Multitap]),
+    peripherals("SUPER BOMBERMAN 4", [Standard, Multitap]),
+    peripherals("SUPER BOMBERMAN 5", [Standard, Multitap]),
+    peripherals("NBA JAM", [Standard, Multitap]),
+    peripherals("NBA JAM TE", [Standard, Multitap]),
+    peripherals("SECRET OF MANA", [Standard, Multitap]),
+];
+
+impl Entry {
+    fn matches(&self, header: &Header) -> bool {
+        self.checksum
+            .is_none_or(|checksum| checksum == [header.checksum, header.checksum_complement])
+            && self.name == header.name
+    }
+
+    /// Override the detected values of the header
+    pub(super) fn apply(&self, header: &mut Header) {
+        if let Some(rom_type) = self.rom_type {
+            header.rom_type = rom_type
+        }
+        if let Some(coprocessor) = self.coprocessor {
+            header.coprocessor = coprocessor
+        }
+    }
+}
+
+/// Find the database entry of a cartridge
+pub(super) fn find(header: &Header) -> Option<&'static Entry> {
+    if cfg!(feature = "game-db") {
+        ENTRIES.iter().find(|entry| entry.matches(header))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,40 @@
+use super::*;
+
+fn header(name: &str, checksum: u16) -> Header {
+    Header {
+        name: name.to_owned(),
+        checksum,
+        checksum_complement: !checksum,
+        ..Header::default()
+    }
+}
+
+#[test]
+fn checksum_selects_the_revision() {
+    let entry = Entry {
+        name: "TEST GAME",
+        checksum: Some([0x1234, !0x1234]),
+        rom_type: Some(RomType::HiRom),
+        coprocessor: None,
+        dsp_version: None,
+        ports: None,
+    };
+    assert!(entry.matches(&header("TEST GAME", 0x1234)));
+    assert!(!entry.matches(&header("TEST GAME", 0x1235)));
+    assert!(!entry.matches(&header("OTHER GAME", 0x1234)));
+    // the complement is part of the key
+    let mut corrupted = header("TEST GAME", 0x1234);
+    corrupted.checksum_complement = 0;
+    assert!(!entry.matches(&corrupted));
+
+    let mut header = header("TEST GAME", 0x1234);
+    entry.apply(&mut header);
+    assert_eq!(header.rom_type, RomType::HiRom);
+}
+
+#[test]
+fn title_entries_match_every_revision() {
+    let entry = peripherals("TEST GAME", [Mouse, Standard]);
+    assert!(entry.matches(&header("TEST GAME", 0x1234)));
+    assert!(entry.matches(&header("TEST GAME", 0x4321)));
+}
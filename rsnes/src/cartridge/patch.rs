@@ -0,0 +1,293 @@
+//! Soft-patching of cartridge files with IPS and BPS patches
+//!
+//! Patches are applied to the file contents before the header gets parsed,
+//! so that translations and ROM hacks can be played without modifying the
+//! original file.
+//!
+//! # Literature
+//!
+//! - <https://zerosoft.zophar.net/ips.php>
+//! - <https://github.com/blakesmith/rombp/blob/master/docs/bps_spec.md>
+
+use super::MAXIMUM_ROM_SIZE;
+
+const IPS_MAGIC: &[u8; 5] = b"PATCH";
+const IPS_EOF: &[u8; 3] = b"EOF";
+const BPS_MAGIC: &[u8; 4] = b"BPS1";
+/// Size of the three checksums at the end of BPS patches
+const BPS_FOOTER_SIZE: usize = 12;
+/// Size of the header of copier devices
+const COPIER_HEADER_SIZE: usize = 512;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    UnknownFormat,
+    UnexpectedEnd,
+    InvalidOffset,
+    /// The BPS patch was created for another file
+    SourceChecksum {
+        expected: u32,
+        found: u32,
+    },
+    TargetChecksum {
+        expected: u32,
+        found: u32,
+    },
+    PatchChecksum {
+        expected: u32,
+        found: u32,
+    },
+}
+
+impl std::fmt::Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(f, "neither an IPS nor a BPS patch"),
+            Self::UnexpectedEnd => write!(f, "unexpected end of the patch"),
+            Self::InvalidOffset => write!(f, "patch refers to data outside of the file"),
+            Self::SourceChecksum { expected, found } => write!(
+                f,
+                "patch does not belong to this file (crc32 {:08x} instead of {:08x})",
+                found, expected
+            ),
+            Self::TargetChecksum { expected, found } => write!(
+                f,
+                "patched file is corrupted (crc32 {:08x} instead of {:08x})",
+                found, expected
+            ),
+            Self::PatchChecksum { expected, found } => write!(
+                f,
+                "patch is corrupted (crc32 {:08x} instead of {:08x})",
+                found, expected
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchFormat {
+    Ips,
+    Bps,
+}
+
+impl PatchFormat {
+    /// Detect the format by the magic bytes of the patch
+    pub fn detect(patch: &[u8]) -> Option<Self> {
+        if patch.starts_with(IPS_MAGIC) {
+            Some(Self::Ips)
+        } else if patch.starts_with(BPS_MAGIC) {
+            Some(Self::Bps)
+        } else {
+            None
+        }
+    }
+
+    /// File extensions of the patch formats in the order they are searched for
+    pub const EXTENSIONS: [&'static str; 2] = ["bps", "ips"];
+}
+
+/// The CRC-32 checksum used by BPS patches
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self
+            .pos
+            .checked_add(n)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or(PatchError::UnexpectedEnd)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn be(&mut self, n: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(n)?
+            .iter()
+            .fold(0, |val, &b| (val << 8) | usize::from(b)))
+    }
+
+    /// Read a variable length number of a BPS patch
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut data = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.byte()?;
+            data = usize::from(byte & 0x7f)
+                .checked_mul(shift)
+                .and_then(|val| data.checked_add(val))
+                .ok_or(PatchError::InvalidOffset)?;
+            if byte & 0x80 != 0 {
+                return Ok(data);
+            }
+            shift = shift.checked_shl(7).ok_or(PatchError::InvalidOffset)?;
+            data = data.checked_add(shift).ok_or(PatchError::InvalidOffset)?;
+        }
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut target = rom.to_vec();
+    let mut reader = Reader {
+        data: patch,
+        pos: IPS_MAGIC.len(),
+    };
+    loop {
+        if reader.data.get(reader.pos..reader.pos + 3) == Some(IPS_EOF) {
+            reader.pos += 3;
+            break;
+        }
+        let offset = reader.be(3)?;
+        let (len, data) = match reader.be(2)? {
+            // run-length encoded record
+            0 => (reader.be(2)?, None),
+            len => (len, Some(reader.bytes(len)?)),
+        };
+        if target.len() < offset + len {
+            target.resize(offset + len, 0)
+        }
+        let dst = &mut target[offset..offset + len];
+        match data {
+            Some(data) => dst.copy_from_slice(data),
+            None => dst.fill(reader.byte()?),
+        }
+    }
+    // an optional extension truncates the file
+    if let Ok(size) = reader.be(3) {
+        target.truncate(size)
+    }
+    Ok(target)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = patch
+        .len()
+        .checked_sub(BPS_FOOTER_SIZE)
+        .filter(|&footer| footer >= BPS_MAGIC.len())
+        .ok_or(PatchError::UnexpectedEnd)?;
+    let checksum = |i: usize| {
+        let pos = footer + i * 4;
+        u32::from_le_bytes(patch[pos..pos + 4].try_into().unwrap())
+    };
+    let found = crc32(&patch[..patch.len() - 4]);
+    if found != checksum(2) {
+        return Err(PatchError::PatchChecksum {
+            expected: checksum(2),
+            found,
+        });
+    }
+    let found = crc32(rom);
+    if found != checksum(0) {
+        return Err(PatchError::SourceChecksum {
+            expected: checksum(0),
+            found,
+        });
+    }
+
+    let mut reader = Reader {
+        data: &patch[..footer],
+        pos: BPS_MAGIC.len(),
+    };
+    let _source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+
+    // the size is only trusted after the checksum of the target was checked
+    let mut target = Vec::with_capacity(target_size.min(MAXIMUM_ROM_SIZE as usize));
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+    let relative = |offset: &mut usize, data: usize| {
+        let delta = data >> 1;
+        *offset = if data & 1 == 0 {
+            offset.checked_add(delta)
+        } else {
+            offset.checked_sub(delta)
+        }
+        .ok_or(PatchError::InvalidOffset)?;
+        Ok(())
+    };
+    while reader.pos < reader.data.len() {
+        let data = reader.number()?;
+        let len = (data >> 2) + 1;
+        if target_size - target.len() < len {
+            return Err(PatchError::InvalidOffset);
+        }
+        match data & 3 {
+            0 => {
+                let pos = target.len();
+                let src = pos
+                    .checked_add(len)
+                    .and_then(|end| rom.get(pos..end))
+                    .ok_or(PatchError::InvalidOffset)?;
+                target.extend_from_slice(src)
+            }
+            1 => target.extend_from_slice(reader.bytes(len)?),
+            2 => {
+                relative(&mut source_offset, reader.number()?)?;
+                let src = source_offset
+                    .checked_add(len)
+                    .and_then(|end| rom.get(source_offset..end))
+                    .ok_or(PatchError::InvalidOffset)?;
+                target.extend_from_slice(src);
+                source_offset += len;
+            }
+            _ => {
+                relative(&mut target_offset, reader.number()?)?;
+                if target_offset >= target.len() {
+                    return Err(PatchError::InvalidOffset);
+                }
+                // the copied range may overlap with the written bytes
+                for _ in 0..len {
+                    target.push(target[target_offset]);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if target.len() != target_size {
+        return Err(PatchError::InvalidOffset);
+    }
+    let found = crc32(&target);
+    if found != checksum(1) {
+        return Err(PatchError::TargetChecksum {
+            expected: checksum(1),
+            found,
+        });
+    }
+    Ok(target)
+}
+
+/// Apply an IPS or BPS patch to the contents of a cartridge file.
+///
+/// If a BPS patch does not match the file, because the file has a
+/// copier header, the patch is applied to the file without the header.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match PatchFormat::detect(patch).ok_or(PatchError::UnknownFormat)? {
+        PatchFormat::Ips => apply_ips(rom, patch),
+        PatchFormat::Bps => match apply_bps(rom, patch) {
+            Err(PatchError::SourceChecksum { .. }) if rom.len() % 0x400 == COPIER_HEADER_SIZE => {
+                apply_bps(&rom[COPIER_HEADER_SIZE..], patch)
+            }
+            result => result,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests;
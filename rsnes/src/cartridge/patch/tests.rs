@@ -0,0 +1,126 @@
+use super::*;
+
+fn encode_number(mut data: usize, out: &mut Vec<u8>) {
+    loop {
+        let x = (data & 0x7f) as u8;
+        data >>= 7;
+        if data == 0 {
+            out.push(0x80 | x);
+            break;
+        }
+        out.push(x);
+        data -= 1;
+    }
+}
+
+/// Create a BPS patch of the `actions` (command, length, argument)
+fn new_bps(source: &[u8], target: &[u8], actions: &[(usize, usize, &[u8], isize)]) -> Vec<u8> {
+    let mut patch = BPS_MAGIC.to_vec();
+    encode_number(source.len(), &mut patch);
+    encode_number(target.len(), &mut patch);
+    encode_number(0, &mut patch);
+    for &(command, len, data, offset) in actions {
+        encode_number(((len - 1) << 2) | command, &mut patch);
+        match command {
+            1 => patch.extend_from_slice(data),
+            2 | 3 => encode_number(
+                (offset.unsigned_abs() << 1) | usize::from(offset < 0),
+                &mut patch,
+            ),
+            _ => (),
+        }
+    }
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+    patch
+}
+
+#[test]
+fn checksum() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(b""), 0);
+}
+
+#[test]
+fn ips() {
+    let rom = [0u8; 8];
+    let mut patch = IPS_MAGIC.to_vec();
+    // record at offset 2
+    patch.extend_from_slice(&[0, 0, 2, 0, 2, 0xaa, 0xbb]);
+    // run-length encoded record, which extends the file
+    patch.extend_from_slice(&[0, 0, 6, 0, 0, 0, 4, 0xcc]);
+    patch.extend_from_slice(IPS_EOF);
+    assert_eq!(
+        apply(&rom, &patch).unwrap(),
+        [0, 0, 0xaa, 0xbb, 0, 0, 0xcc, 0xcc, 0xcc, 0xcc]
+    );
+    patch.extend_from_slice(&[0, 0, 4]);
+    assert_eq!(apply(&rom, &patch).unwrap(), [0, 0, 0xaa, 0xbb]);
+    assert_eq!(
+        apply(&rom, &patch[..patch.len() - 6]),
+        Err(PatchError::UnexpectedEnd)
+    );
+    assert_eq!(apply(&rom, b"NOPATCH"), Err(PatchError::UnknownFormat));
+}
+
+#[test]
+fn bps() {
+    let source = b"abcdefgh";
+    let target = b"xyabcdabcdab-gh";
+    let patch = new_bps(
+        source,
+        target,
+        &[
+            (1, 2, b"xy", 0),
+            (2, 4, &[], 0),
+            (3, 6, &[], 2),
+            (1, 1, b"-", 0),
+            (2, 2, &[], 2),
+        ],
+    );
+    assert_eq!(apply(source, &patch).unwrap(), target);
+
+    // the copier header is skipped, if the patch does not match
+    let mut headered = vec![0; COPIER_HEADER_SIZE];
+    headered.extend_from_slice(&[0xea; 0x400]);
+    let patch = new_bps(&headered[COPIER_HEADER_SIZE..], b"\xea", &[(0, 1, &[], 0)]);
+    assert_eq!(apply(&headered, &patch).unwrap(), [0xea]);
+
+    assert!(matches!(
+        apply(b"abcdefgi", &new_bps(source, target, &[])),
+        Err(PatchError::SourceChecksum { .. })
+    ));
+    let mut patch = new_bps(source, target, &[]);
+    patch[5] ^= 1;
+    assert!(matches!(
+        apply(source, &patch),
+        Err(PatchError::PatchChecksum { .. })
+    ));
+}
+
+/// Create a BPS patch of `source`, whose header and commands are `numbers`
+fn new_raw_bps(source: &[u8], numbers: &[usize]) -> Vec<u8> {
+    let mut patch = BPS_MAGIC.to_vec();
+    for &number in numbers {
+        encode_number(number, &mut patch);
+    }
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(b"").to_le_bytes());
+    patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+    patch
+}
+
+#[test]
+fn bps_overflows() {
+    let source = b"abcdefgh";
+    // metadata, which ends beyond the end of the address space
+    let patch = new_raw_bps(source, &[8, 8, usize::MAX]);
+    assert_eq!(apply(source, &patch), Err(PatchError::UnexpectedEnd));
+    // a huge target size is not reserved
+    let patch = new_raw_bps(source, &[8, usize::MAX >> 1, 0]);
+    assert_eq!(apply(source, &patch), Err(PatchError::InvalidOffset));
+    // the target must not grow beyond its size
+    let patch = new_raw_bps(source, &[8, 4, 0, (7 << 2) | 3, 0]);
+    assert_eq!(apply(source, &patch), Err(PatchError::InvalidOffset));
+}
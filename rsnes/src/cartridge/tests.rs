@@ -0,0 +1,449 @@
+use super::*;
+
+/// Create a ROM image with a valid header at `addr`
+fn new_rom(size: usize, addr: usize, name: &str, map_mode: u8, chips: u8) -> Vec<u8> {
+    let mut rom = vec![0xea; size];
+    let header = &mut rom[addr + 16..addr + 80];
+    header[..21].fill(b' ');
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[21] = map_mode;
+    header[22] = chips;
+    header[23] = (size.next_power_of_two() >> 10).trailing_zeros() as u8;
+    header[24] = 0;
+    header[25] = 1;
+    header[26] = 0;
+    header[28..32].copy_from_slice(&[0xff, 0xff, 0, 0]);
+    // reset vector pointing to a `sei` instruction
+    header[60..62].copy_from_slice(&0x8000u16.to_le_bytes());
+    let entry = if addr == 0x7fb0 {
+        0
+    } else {
+        (addr & !0xffff) | 0x8000
+    };
+    rom[entry] = 0x78;
+    let checksum = calculate_checksum(&create_rom(&rom, size as u32));
+    rom[addr + 44..addr + 46].copy_from_slice(&(!checksum).to_le_bytes());
+    rom[addr + 46..addr + 48].copy_from_slice(&checksum.to_le_bytes());
+    rom
+}
+
+#[test]
+fn detect_lorom() {
+    let rom = new_rom(0x100000, 0x7fb0, "LOROM TEST", 0x20, 0);
+    let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(cartridge.header.rom_type, RomType::LoRom);
+    assert_eq!(cartridge.title(), "LOROM TEST");
+    assert_eq!(cartridge.read_byte(Addr24::new(0x00, 0x8000)), Some(0x78));
+    assert_eq!(cartridge.read_byte(Addr24::new(0x00, 0xffc0)), Some(b'L'));
+}
+
+#[test]
+fn detect_hirom_with_plausible_lorom_header() {
+    let mut rom = new_rom(0x100000, 0xffb0, "HIROM TEST", 0x21, 0);
+    // a LoROM header with a wrong checksum and reset vector
+    let fake = &mut rom[0x7fc0..0x8000];
+    fake[..21].copy_from_slice(b"NOT THE REAL HEADER  ");
+    fake[21] = 0x20;
+    fake[28..32].copy_from_slice(&[0x34, 0x12, 0xcb, 0xed]);
+    fake[60..62].copy_from_slice(&0x0000u16.to_le_bytes());
+    let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(cartridge.header.rom_type, RomType::HiRom);
+    assert_eq!(cartridge.title(), "HIROM TEST");
+    assert_eq!(cartridge.read_byte(Addr24::new(0x00, 0x8000)), Some(0x78));
+    assert_eq!(cartridge.read_byte(Addr24::new(0xc0, 0x7fc0)), Some(b'N'));
+}
+
+#[test]
+fn exhirom_mapping() {
+    let mut rom = new_rom(0x600000, 0x40ffb0, "EXHIROM TEST", 0x35, 0);
+    rom[0] = 0x12;
+    rom[0x3f_ffff] = 0x34;
+    rom[0x41_0000] = 0x56;
+    rom[0x5f_ffff] = 0x78;
+    let checksum = calculate_checksum(&create_rom(&rom, 0x800000));
+    rom[0x40ffdc..0x40ffde].copy_from_slice(&(!checksum).to_le_bytes());
+    rom[0x40ffde..0x40ffe0].copy_from_slice(&checksum.to_le_bytes());
+    let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(cartridge.header.rom_type, RomType::ExHiRom);
+    assert_eq!(cartridge.title(), "EXHIROM TEST");
+    let mut read = |bank, addr| cartridge.read_byte(Addr24::new(bank, addr));
+    assert_eq!(read(0xc0, 0x0000), Some(0x12));
+    assert_eq!(read(0xff, 0xffff), Some(0x34));
+    assert_eq!(read(0x41, 0x0000), Some(0x56));
+    assert_eq!(read(0x00, 0x8000), Some(0x78));
+    assert_eq!(read(0x00, 0xffc0), Some(b'E'));
+    assert_eq!(read(0x5f, 0xffff), Some(0x78));
+}
+
+#[test]
+fn rom_checksum_matches_mirrored_rom() {
+    let content: Vec<u8> = (0..0x28_0000u32)
+        .map(|i| (i * 7 + (i >> 9)) as u8)
+        .collect();
+    for len in [0x8000, 0x10000, 0x18000, 0x20_0000, 0x28_0000] {
+        for size in [0, 0x400, 0x20_0000, 0x40_0000, 0x80_0000] {
+            let content = &content[..len];
+            assert_eq!(
+                rom_checksum(content, size),
+                calculate_checksum(&create_rom(content, size)),
+                "{len:#x} bytes mirrored to {size:#x} bytes"
+            );
+        }
+    }
+}
+
+#[test]
+fn implausible_rom_size() {
+    let mut rom = new_rom(0x10000, 0x7fb0, "HUGE SIZE TEST", 0x20, 0);
+    // 2 GiB
+    rom[0x7fd7] = 21;
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(cartridge.header.rom_size, 0x10000);
+    assert_eq!(cartridge.rom.len(), 0x10000);
+}
+
+#[test]
+fn peek_and_poke() {
+    let mut rom = new_rom(0x100000, 0x7fb0, "PEEK TEST", 0x20, 2);
+    // 8 KiB of SRAM
+    rom[0x7fb0 + 40] = 3;
+    let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+    cartridge.poke_byte(Addr24::new(0x00, 0x8000), 0x18);
+    cartridge.poke_byte(Addr24::new(0x70, 0x0001), 0x42);
+    assert_eq!(cartridge.peek_byte(Addr24::new(0x80, 0x8000)), Some(0x18));
+    assert_eq!(cartridge.peek_byte(Addr24::new(0x71, 0x2001)), Some(0x42));
+    assert_eq!(cartridge.sram()[1], 0x42);
+    cartridge.set_read_patches([(Addr24::new(0x00, 0x8000), 0xea)]);
+    assert_eq!(cartridge.peek_byte(Addr24::new(0x00, 0x8000)), Some(0xea));
+    assert_eq!(cartridge.peek_byte(Addr24::new(0x80, 0x8000)), Some(0x18));
+}
+
+#[test]
+fn sram_addresses() {
+    let mut rom = new_rom(0x100000, 0x7fb0, "LOROM SRAM", 0x20, 2);
+    // 64 KiB of SRAM
+    rom[0x7fb0 + 40] = 6;
+    let lorom = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(lorom.sram_addr(0x0123), Some(Addr24::new(0x70, 0x0123)));
+    assert_eq!(lorom.sram_addr(0x8123), Some(Addr24::new(0x71, 0x0123)));
+    assert_eq!(lorom.sram_addr(0x10000), None);
+
+    let mut rom = new_rom(0x100000, 0xffb0, "HIROM SRAM", 0x21, 2);
+    // 8 KiB of SRAM
+    rom[0xffb0 + 40] = 3;
+    let mut hirom = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(hirom.header.rom_type, RomType::HiRom);
+    assert_eq!(hirom.sram_addr(0x1234), Some(Addr24::new(0x20, 0x7234)));
+    for offset in 0..hirom.sram().len() as u32 {
+        let addr = hirom.sram_addr(offset).unwrap();
+        hirom.poke_byte(addr, offset as u8 ^ 0x5a);
+        assert_eq!(hirom.sram()[offset as usize], offset as u8 ^ 0x5a);
+    }
+
+    let rom = new_rom(0x100000, 0x7fb0, "NO SRAM", 0x20, 0);
+    assert_eq!(Cartridge::from_bytes(&rom).unwrap().sram_addr(0), None);
+}
+
+#[cfg(feature = "game-db")]
+#[test]
+fn database_overrides_dsp_version() {
+    let rom = new_rom(0x100000, 0x7fb0, "TOP GEAR 3000", 0x30, 3);
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+    let dsp = cartridge.dsp.as_ref().unwrap();
+    assert!(matches!(dsp.version(), DspVersion::Dsp4));
+    assert_eq!(cartridge.peripherals(), None);
+}
+
+#[cfg(feature = "game-db")]
+#[test]
+fn database_peripherals() {
+    use crate::controller::DeviceKind;
+    let rom = new_rom(0x100000, 0x7fb0, "MARIO PAINT", 0x20, 0);
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(
+        cartridge.peripherals(),
+        Some([DeviceKind::Mouse, DeviceKind::Standard])
+    );
+    let rom = new_rom(0x100000, 0x7fb0, "SUPER BOMBERMAN2", 0x20, 0);
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(
+        cartridge.peripherals(),
+        Some([DeviceKind::Standard, DeviceKind::Multitap])
+    );
+}
+
+#[test]
+fn strip_copier_header() {
+    let rom = new_rom(0x80000, 0x7fb0, "HEADERED", 0x20, 0);
+    let mut file = vec![0; 512];
+    file.extend_from_slice(&rom);
+    let mut cartridge = Cartridge::from_bytes(&file).unwrap();
+    assert_eq!(
+        cartridge.header().image_format(),
+        ImageFormat {
+            copier_header: true,
+            interleaved: false,
+        }
+    );
+    assert_eq!(cartridge.title(), "HEADERED");
+    assert_eq!(cartridge.read_byte(Addr24::new(0x00, 0x8000)), Some(0x78));
+}
+
+#[test]
+fn deinterleave_hirom() {
+    let mut rom = new_rom(0x100000, 0xffb0, "INTERLEAVED", 0x21, 0);
+    rom[0x10000] = 0x12;
+    rom[0x18000] = 0x34;
+    let (lower, upper): (Vec<_>, Vec<_>) = rom
+        .chunks(0x8000)
+        .enumerate()
+        .partition(|(i, _)| i % 2 == 0);
+    let file: Vec<u8> = upper
+        .into_iter()
+        .chain(lower)
+        .flat_map(|(_, block)| block)
+        .copied()
+        .collect();
+    let mut cartridge = Cartridge::from_bytes(&file).unwrap();
+    assert_eq!(
+        cartridge.header().image_format(),
+        ImageFormat {
+            copier_header: false,
+            interleaved: true,
+        }
+    );
+    assert_eq!(cartridge.header.rom_type, RomType::HiRom);
+    assert_eq!(cartridge.title(), "INTERLEAVED");
+    assert_eq!(cartridge.read_byte(Addr24::new(0xc1, 0x0000)), Some(0x12));
+    assert_eq!(cartridge.read_byte(Addr24::new(0xc1, 0x8000)), Some(0x34));
+    assert_eq!(cartridge.read_byte(Addr24::new(0x00, 0x8000)), Some(0x78));
+}
+
+fn new_sufami_cartridge(title: &str, size: usize, fill: u8) -> Vec<u8> {
+    let mut rom = vec![fill; size];
+    rom[..14].copy_from_slice(SUFAMI_MAGIC);
+    rom[14..0x24].fill(0);
+    rom[0x10..0x10 + title.len()].copy_from_slice(title.as_bytes());
+    rom
+}
+
+#[test]
+fn sufami_turbo_slots() {
+    let base = new_rom(0x40000, 0x7fb0, "ADD-ON BASE CASSETE", 0x20, 0);
+    let slot_a = new_sufami_cartridge("SLOT A GAME", 0x80000, 0xaa);
+    let slot_b = new_sufami_cartridge("SLOT B GAME", 0x40000, 0xbb);
+    let mut cartridge = Cartridge::from_sufami(&base, Some(&slot_a), Some(&slot_b)).unwrap();
+    assert_eq!(cartridge.title(), "SLOT A GAME");
+    let mut read = |bank, addr| cartridge.read_byte(Addr24::new(bank, addr));
+    assert_eq!(read(0x00, 0x8000), Some(0x78));
+    assert_eq!(read(0x20, 0x8000), Some(b'B'));
+    assert_eq!(read(0xa0, 0x9000), Some(0xaa));
+    assert_eq!(read(0x40, 0x8000), Some(b'B'));
+    assert_eq!(read(0xc0, 0x9000), Some(0xbb));
+    cartridge.write_byte(Addr24::new(0x60, 0x8000), 0x12);
+    cartridge.write_byte(Addr24::new(0x70, 0x8000), 0x34);
+    assert_eq!(cartridge.read_byte(Addr24::new(0xe0, 0x8000)), Some(0x12));
+    assert_eq!(cartridge.read_byte(Addr24::new(0xf0, 0x8000)), Some(0x34));
+}
+
+#[test]
+fn sufami_turbo_empty_slot() {
+    let base = new_rom(0x40000, 0x7fb0, "ADD-ON BASE CASSETE", 0x20, 0);
+    let slot_b = new_sufami_cartridge("SLOT B GAME", 0x40000, 0xbb);
+    let mut cartridge = Cartridge::from_sufami(&base, None, Some(&slot_b)).unwrap();
+    assert_eq!(cartridge.title(), "SLOT B GAME");
+    assert_eq!(cartridge.read_byte(Addr24::new(0x20, 0x8000)), None);
+    assert_eq!(cartridge.read_byte(Addr24::new(0x60, 0x8000)), None);
+    assert!(matches!(
+        Cartridge::from_sufami(&base, Some(&base), None),
+        Err(ReadRomError::NoSufamiTurboCartridge)
+    ));
+}
+
+fn assert_pages_match_search(mapping: &MemoryMapping) {
+    for bank in 0..=0xff {
+        for addr in (0..=0xffff)
+            .step_by(0x7f)
+            .chain([0x1fff, 0x6fff, 0x7000, 0xffff])
+        {
+            let addr = Addr24::new(bank, addr);
+            let paged = mapping.find(addr).map(|(i, e)| (i, e as *const _));
+            let searched = mapping.search(addr).map(|(i, e)| (i, e as *const _));
+            assert_eq!(paged, searched, "mismatch at {addr}");
+        }
+    }
+}
+
+#[test]
+fn page_table_matches_mapping() {
+    let mut rom = new_rom(0x100000, 0x7fb0, "LOROM SRAM", 0x20, 2);
+    rom[0x7fb0 + 40] = 3;
+    assert_pages_match_search(&Cartridge::from_bytes(&rom).unwrap().mapping);
+    let mut rom = new_rom(0x100000, 0xffb0, "HIROM SRAM", 0x21, 2);
+    rom[0xffb0 + 40] = 3;
+    assert_pages_match_search(&Cartridge::from_bytes(&rom).unwrap().mapping);
+    let rom = new_rom(0x400000, 0x7fb0, "LOROM NO SRAM", 0x20, 0);
+    assert_pages_match_search(&Cartridge::from_bytes(&rom).unwrap().mapping);
+
+    // entries sharing pages, like the NEC-DSP registers of HiROM cartridges
+    let mut map = MemoryMapping::default();
+    map!(map @ 0x00:0x6000 .. 0x1f:0x6fff => DspDr | DspDr [0<<0:0]);
+    map!(map @ 0x00:0x7000 .. 0x1f:0x7fff => DspSr | Ignore [0<<0:0]);
+    map!(map @ 0x00:0x0000 .. 0x3f:0xffff => Rom | Ignore [0x3f<<16:0xffff]);
+    map!(map @ 0x20:0x6000 .. 0x3f:0x7fff => Sram | Sram [0x3f<<13:0x1fff]);
+    map.build_pages();
+    assert!(matches!(
+        map.pages[page_index(Addr24::new(0, 0x6000))],
+        Page::Mixed
+    ));
+    assert!(matches!(
+        map.pages[page_index(Addr24::new(0, 0x8000))],
+        Page::Mapped {
+            plain: Some(PlainMemory::Rom),
+            ..
+        }
+    ));
+    assert!(matches!(
+        map.pages[page_index(Addr24::new(0x40, 0))],
+        Page::Unmapped
+    ));
+    assert_pages_match_search(&map);
+}
+
+#[test]
+fn plain_accesses_match_mapping() {
+    let mut rom = new_rom(0x100000, 0x7fb0, "PLAIN TEST", 0x20, 2);
+    rom[0x7fb0 + 40] = 3;
+    for (i, byte) in rom[..0x7000].iter_mut().enumerate() {
+        *byte ^= i as u8
+    }
+    let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+    for addr in [Addr24::new(0x00, 0x8000), Addr24::new(0x70, 0x0000)] {
+        assert!(cartridge.mapping.find_plain(addr).is_some());
+    }
+    cartridge.write(Addr24::new(0x70, 0x1fff), 0x1234u16);
+    assert_eq!(
+        (cartridge.sram()[0x1fff], cartridge.sram()[0]),
+        (0x34, 0x12)
+    );
+    cartridge.write(Addr24::new(0x00, 0x8000), 0u8);
+    assert_eq!(cartridge.peek_byte(Addr24::new(0x00, 0x8000)), Some(rom[0]));
+    for bank in (0..=0xff).step_by(7) {
+        for addr in (0..=0xffff).step_by(0x3ff).chain([0x1fff, 0x7fff, 0xffff]) {
+            let addr = Addr24::new(bank, addr);
+            let next = Addr24::new(bank, addr.addr.wrapping_add(1));
+            let expected = cartridge
+                .read_byte(addr)
+                .map(|low| u16::from_le_bytes([low, cartridge.read_byte(next).unwrap_or(low)]));
+            assert_eq!(cartridge.read::<u16>(addr), expected, "mismatch at {addr}");
+        }
+    }
+    cartridge.set_read_patches([(Addr24::new(0x00, 0x8001), 0x55)]);
+    assert_eq!(
+        cartridge.read::<u16>(Addr24::new(0x00, 0x8000)),
+        Some(u16::from_le_bytes([rom[0], 0x55]))
+    );
+}
+
+#[test]
+fn srtc_sram_file() {
+    use crate::backend::FixedClock;
+    let mut rom = new_rom(0x100000, 0xffb0, "SRTC TEST", 0x21, 0x55);
+    // 8 KiB of SRAM
+    rom[0xffb0 + 40] = 3;
+    let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert!(cartridge.has_srtc());
+    cartridge.set_clock_source(Arc::new(FixedClock(1614834367)));
+    cartridge.write_byte(Addr24::new(0x20, 0x6000), 0x42);
+    let file = cartridge.sram_file();
+    assert_eq!(file.len(), 0x2000 + srtc::BATTERY_SIZE);
+
+    let mut restored = Cartridge::from_bytes(&rom).unwrap();
+    restored.set_clock_source(Arc::new(FixedClock(1614834367 + 13)));
+    restored.load_sram_file(&file);
+    // the restored time is kept
+    restored.set_clock_source(Arc::new(FixedClock(0)));
+    assert_eq!(restored.read_byte(Addr24::new(0x20, 0x6000)), Some(0x42));
+    restored.write_byte(Addr24::new(0x00, 0x2801), 0xd);
+    let digits: Vec<_> = (0..3)
+        .map(|_| restored.read_byte(Addr24::new(0x00, 0x2800)).unwrap())
+        .collect();
+    assert_eq!(digits, [15, 0, 2]);
+}
+
+#[test]
+fn sa1_write_protection() {
+    let rom = new_rom(0x100000, 0x7fb0, "SA1 TEST", 0x23, 0x35);
+    let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert!(cartridge.has_sa1());
+
+    // the I-RAM is write-protected page by page for each CPU
+    cartridge.write_byte(Addr24::new(0x00, 0x3000), 0x12);
+    assert_eq!(cartridge.read_byte(Addr24::new(0x00, 0x3000)), Some(0));
+    cartridge.write_byte(Addr24::new(0x00, 0x2229), 0x01);
+    cartridge.write_byte(Addr24::new(0x00, 0x3000), 0x12);
+    cartridge.write_byte(Addr24::new(0x00, 0x3100), 0x34);
+    assert_eq!(
+        cartridge.sa1_read::<true>(Addr24::new(0x00, 0x0000)),
+        Some(0x12)
+    );
+    assert_eq!(
+        cartridge.sa1_read::<true>(Addr24::new(0x00, 0x0100)),
+        Some(0)
+    );
+    cartridge.sa1_write::<true>(Addr24::new(0x00, 0x222a), 0x02);
+    cartridge.sa1_write::<true>(Addr24::new(0x00, 0x0100), 0x34);
+    assert_eq!(cartridge.read_byte(Addr24::new(0x80, 0x3100)), Some(0x34));
+
+    // the first 256 bytes of the BW-RAM are protected until either CPU enables writes
+    cartridge.write_byte(Addr24::new(0x00, 0x2228), 0x00);
+    cartridge.write_byte(Addr24::new(0x40, 0x00ff), 0x56);
+    cartridge.write_byte(Addr24::new(0x40, 0x0100), 0x78);
+    assert_eq!(cartridge.read_byte(Addr24::new(0x40, 0x00ff)), Some(0));
+    assert_eq!(cartridge.read_byte(Addr24::new(0x40, 0x0100)), Some(0x78));
+    cartridge.sa1_write::<true>(Addr24::new(0x00, 0x2227), 0x80);
+    cartridge.write_byte(Addr24::new(0x40, 0x00ff), 0x56);
+    assert_eq!(cartridge.read_byte(Addr24::new(0x40, 0x00ff)), Some(0x56));
+}
+
+#[test]
+fn sa1_bitmap_projection() {
+    let rom = new_rom(0x100000, 0x7fb0, "SA1 TEST", 0x23, 0x35);
+    let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+    // 2 bits per pixel
+    cartridge.sa1_write::<true>(Addr24::new(0x00, 0x223f), 0x80);
+    cartridge.sa1_write::<true>(Addr24::new(0x60, 0x0105), 0x03);
+    assert_eq!(cartridge.read_byte(Addr24::new(0x40, 0x0041)), Some(0x0c));
+    assert_eq!(
+        cartridge.sa1_read::<true>(Addr24::new(0x60, 0x0105)),
+        Some(0x03)
+    );
+    // the projection isn't visible to the SNES
+    assert_eq!(cartridge.read_byte(Addr24::new(0x60, 0x0105)), None);
+    // 4 bits per pixel in the bitmap window of bank 0
+    cartridge.sa1_write::<true>(Addr24::new(0x00, 0x223f), 0x00);
+    cartridge.sa1_write::<true>(Addr24::new(0x00, 0x2225), 0x80);
+    cartridge.sa1_write::<true>(Addr24::new(0x00, 0x6003), 0x0a);
+    assert_eq!(cartridge.read_byte(Addr24::new(0x40, 0x0001)), Some(0xa0));
+}
+
+#[test]
+fn raw_binary() {
+    let mut binary = vec![0xea; 0x8000];
+    binary[0x10] = 0x78;
+    let mut cartridge = Cartridge::from_raw(&binary, RomType::LoRom, 0x8010).unwrap();
+    assert_eq!(cartridge.header.rom_type, RomType::LoRom);
+    assert_eq!(cartridge.read_byte(Addr24::new(0x00, 0x8010)), Some(0x78));
+    assert_eq!(cartridge.read_byte(Addr24::new(0x00, 0xfffc)), Some(0x10));
+    assert_eq!(cartridge.read_byte(Addr24::new(0x00, 0xfffd)), Some(0x80));
+
+    // a HiROM binary of 32 KiB is mirrored into the upper half of the bank
+    let mut cartridge = Cartridge::from_raw(&binary, RomType::HiRom, 0x8010).unwrap();
+    assert_eq!(cartridge.read_byte(Addr24::new(0xc0, 0x0010)), Some(0x78));
+    assert_eq!(cartridge.read_byte(Addr24::new(0x00, 0x8010)), Some(0x78));
+    assert_eq!(cartridge.read_byte(Addr24::new(0x00, 0xfffc)), Some(0x10));
+
+    assert!(Cartridge::from_raw(&binary, RomType::LoRomSA1, 0x8000).is_err());
+    assert!(Cartridge::from_raw(&[], RomType::LoRom, 0x8000).is_err());
+    assert_eq!(RomType::from_name("hirom"), Some(RomType::HiRom));
+}
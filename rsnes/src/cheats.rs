@@ -0,0 +1,204 @@
+//! Cheat codes of the Game Genie and the Pro Action Replay
+//!
+//! Game Genie codes (e.g. `C879-D96B`) replace the value read from a
+//! cartridge address. Pro Action Replay codes (e.g. `7E0DBE05`) write their
+//! value into the work RAM once per frame. Pro Action Replay codes outside
+//! of the work RAM replace cartridge reads like Game Genie codes.
+//!
+//! # Literature
+//!
+//! - <https://gamehacking.org/faqs/hackv500c.html>
+
+use crate::device::Addr24;
+
+pub mod search;
+
+/// The Game Genie substitutes hexadecimal digits by these characters
+const GAME_GENIE_DIGITS: &[u8; 16] = b"DF4709156BC8A23E";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheatError {
+    InvalidLength(usize),
+    InvalidDigit(char),
+}
+
+impl std::fmt::Display for CheatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidLength(len) => write!(f, "cheat code has an invalid length ({})", len),
+            Self::InvalidDigit(c) => write!(f, "invalid digit '{}' in cheat code", c),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatFormat {
+    GameGenie,
+    ProActionReplay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheatCode {
+    pub format: CheatFormat,
+    pub addr: Addr24,
+    pub value: u8,
+}
+
+fn parse_digits(code: &str, digit: impl Fn(u8) -> Option<u8>) -> Result<u32, CheatError> {
+    code.chars().try_fold(0, |data, c| {
+        u8::try_from(c)
+            .ok()
+            .and_then(|c| digit(c.to_ascii_uppercase()))
+            .map(|d| (data << 4) | u32::from(d))
+            .ok_or(CheatError::InvalidDigit(c))
+    })
+}
+
+impl CheatCode {
+    /// Parse a Game Genie code (`XXXX-XXXX`) or a
+    /// Pro Action Replay code (`AAAAAADD` or `AAAAAA:DD`)
+    pub fn parse(code: &str) -> Result<Self, CheatError> {
+        let code = code.trim();
+        match code.len() {
+            9 if code.as_bytes()[4] == b'-' => {
+                let data = parse_digits(&code.replace('-', ""), |c| {
+                    GAME_GENIE_DIGITS
+                        .iter()
+                        .position(|&d| d == c)
+                        .map(|d| d as u8)
+                })?;
+                // the address bits are transposed
+                let addr = ((data & 0x003c00) << 10)
+                    | ((data & 0x00003c) << 14)
+                    | ((data & 0xf00000) >> 8)
+                    | ((data & 0x000003) << 10)
+                    | ((data & 0x00c000) >> 6)
+                    | ((data & 0x0f0000) >> 12)
+                    | ((data & 0x0003c0) >> 6);
+                Ok(Self::new(CheatFormat::GameGenie, addr, (data >> 24) as u8))
+            }
+            8 | 9 => {
+                let code = match code.split_once(':') {
+                    Some((addr, value)) if addr.len() == 6 => [addr, value].concat(),
+                    Some(_) => return Err(CheatError::InvalidDigit(':')),
+                    None => code.to_owned(),
+                };
+                if code.len() != 8 {
+                    return Err(CheatError::InvalidLength(code.len()));
+                }
+                let data = parse_digits(&code, |c| (c as char).to_digit(16).map(|d| d as u8))?;
+                Ok(Self::new(
+                    CheatFormat::ProActionReplay,
+                    data >> 8,
+                    data as u8,
+                ))
+            }
+            len => Err(CheatError::InvalidLength(len)),
+        }
+    }
+
+    fn new(format: CheatFormat, addr: u32, value: u8) -> Self {
+        Self {
+            format,
+            addr: Addr24::new((addr >> 16) as u8, addr as u16),
+            value,
+        }
+    }
+
+    /// Check if the code writes into the work RAM instead of replacing cartridge reads
+    pub fn is_ram_write(&self) -> bool {
+        self.format == CheatFormat::ProActionReplay
+            && ((0x7e..=0x7f).contains(&self.addr.bank)
+                || (self.addr.bank & 0x40 == 0 && self.addr.addr < 0x2000))
+    }
+}
+
+impl std::str::FromStr for CheatCode {
+    type Err = CheatError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        Self::parse(code)
+    }
+}
+
+/// Identifies a cheat of a [`Cheats`] list, even after other cheats got removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CheatId(u32);
+
+#[derive(Debug, Clone)]
+pub struct Cheat {
+    pub code: CheatCode,
+    pub enabled: bool,
+}
+
+/// The cheats of a device, they are not part of save states
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    cheats: Vec<(CheatId, Cheat)>,
+    next_id: u32,
+}
+
+impl Cheats {
+    /// Add an enabled cheat
+    pub fn add(&mut self, code: CheatCode) -> CheatId {
+        let id = CheatId(self.next_id);
+        self.next_id += 1;
+        self.cheats.push((
+            id,
+            Cheat {
+                code,
+                enabled: true,
+            },
+        ));
+        id
+    }
+
+    pub fn remove(&mut self, id: CheatId) -> Option<Cheat> {
+        let index = self.cheats.iter().position(|(i, _)| *i == id)?;
+        Some(self.cheats.remove(index).1)
+    }
+
+    pub fn get(&self, id: CheatId) -> Option<&Cheat> {
+        self.iter()
+            .find_map(|(i, cheat)| (i == id).then_some(cheat))
+    }
+
+    /// Enable or disable a cheat, `false` is returned if the cheat does not exist
+    pub fn set_enabled(&mut self, id: CheatId, enabled: bool) -> bool {
+        match self.cheats.iter_mut().find(|(i, _)| *i == id) {
+            Some((_, cheat)) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (CheatId, &Cheat)> {
+        self.cheats.iter().map(|(id, cheat)| (*id, cheat))
+    }
+
+    fn enabled_codes(&self) -> impl Iterator<Item = &CheatCode> {
+        self.cheats
+            .iter()
+            .filter(|(_, cheat)| cheat.enabled)
+            .map(|(_, cheat)| &cheat.code)
+    }
+
+    /// Enabled codes, which replace reads of the cartridge
+    pub(crate) fn read_patches(&self) -> impl Iterator<Item = (Addr24, u8)> + '_ {
+        self.enabled_codes()
+            .filter(|code| !code.is_ram_write())
+            .map(|code| (code.addr, code.value))
+    }
+
+    /// Enabled codes, which get written into the work RAM every frame
+    pub(crate) fn ram_writes(&self) -> impl Iterator<Item = (Addr24, u8)> + '_ {
+        self.enabled_codes()
+            .filter(|code| code.is_ram_write())
+            .map(|code| (code.addr, code.value))
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,133 @@
+//! Searching the memory for the addresses of game values
+//!
+//! A search starts with a snapshot of a memory region, in which every address
+//! is a candidate. Every further snapshot is compared to the previous one and
+//! only the addresses whose values changed as expected remain candidates.
+
+/// Memory regions, which contain the values of a game.
+///
+/// The CPU address of an offset is given by [`crate::device::Device::memory_addr`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryRegion {
+    WorkRam,
+    /// The battery backed RAM of the cartridge
+    Sram,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchError {
+    /// The snapshot was taken from another memory region than the search
+    RegionMismatch {
+        expected: MemoryRegion,
+        found: MemoryRegion,
+    },
+}
+
+impl std::fmt::Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::RegionMismatch { expected, found } => write!(
+                f,
+                "snapshot of {:?} does not match the search in {:?}",
+                found, expected
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemorySnapshot {
+    pub region: MemoryRegion,
+    pub data: Vec<u8>,
+}
+
+/// Condition of a value compared to its value in the previous snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+    IncreasedBy(u8),
+    DecreasedBy(u8),
+    /// The current value equals the given value
+    EqualTo(u8),
+}
+
+impl Comparison {
+    pub fn matches(self, previous: u8, current: u8) -> bool {
+        match self {
+            Self::Unchanged => current == previous,
+            Self::Changed => current != previous,
+            Self::Increased => current > previous,
+            Self::Decreased => current < previous,
+            Self::IncreasedBy(n) => current == previous.wrapping_add(n),
+            Self::DecreasedBy(n) => current == previous.wrapping_sub(n),
+            Self::EqualTo(n) => current == n,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemorySearch {
+    previous: MemorySnapshot,
+    /// Offsets of the addresses, which matched all comparisons
+    candidates: Vec<u32>,
+}
+
+impl MemorySearch {
+    /// Start a search, in which every address is a candidate
+    pub fn new(snapshot: MemorySnapshot) -> Self {
+        Self {
+            candidates: (0..snapshot.data.len() as u32).collect(),
+            previous: snapshot,
+        }
+    }
+
+    /// Keep the candidates, which match the comparison with the previous snapshot.
+    ///
+    /// The snapshot must be taken from the same memory region as the previous one.
+    pub fn refine(
+        &mut self,
+        snapshot: MemorySnapshot,
+        comparison: Comparison,
+    ) -> Result<(), SearchError> {
+        if snapshot.region != self.previous.region {
+            return Err(SearchError::RegionMismatch {
+                expected: self.previous.region,
+                found: snapshot.region,
+            });
+        }
+        let (previous, current) = (&self.previous.data, &snapshot.data);
+        self.candidates.retain(|&offset| {
+            let offset = offset as usize;
+            current
+                .get(offset)
+                .is_some_and(|&current| comparison.matches(previous[offset], current))
+        });
+        self.previous = snapshot;
+        Ok(())
+    }
+
+    pub fn region(&self) -> MemoryRegion {
+        self.previous.region
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// The offsets of the remaining candidates and their values of the last snapshot
+    pub fn candidates(&self) -> impl Iterator<Item = (u32, u8)> + '_ {
+        self.candidates
+            .iter()
+            .map(|&offset| (offset, self.previous.data[offset as usize]))
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,56 @@
+use super::*;
+
+fn snapshot(data: &[u8]) -> MemorySnapshot {
+    MemorySnapshot {
+        region: MemoryRegion::WorkRam,
+        data: data.to_vec(),
+    }
+}
+
+#[test]
+fn refine() {
+    let mut search = MemorySearch::new(snapshot(&[3, 3, 3, 3, 3]));
+    assert_eq!(search.len(), 5);
+    search
+        .refine(snapshot(&[3, 2, 4, 5, 3]), Comparison::Increased)
+        .unwrap();
+    assert_eq!(search.candidates().collect::<Vec<_>>(), [(2, 4), (3, 5)]);
+    search
+        .refine(snapshot(&[3, 2, 3, 3, 3]), Comparison::DecreasedBy(1))
+        .unwrap();
+    assert_eq!(search.candidates().collect::<Vec<_>>(), [(2, 3)]);
+    search
+        .refine(snapshot(&[0, 0, 3, 0, 0]), Comparison::Unchanged)
+        .unwrap();
+    assert_eq!(search.len(), 1);
+    search
+        .refine(snapshot(&[0, 0, 9, 0, 0]), Comparison::EqualTo(8))
+        .unwrap();
+    assert!(search.is_empty());
+}
+
+#[test]
+fn comparisons() {
+    assert!(Comparison::IncreasedBy(2).matches(0xff, 1));
+    assert!(!Comparison::IncreasedBy(2).matches(1, 2));
+    assert!(Comparison::Changed.matches(1, 2));
+    assert!(Comparison::Decreased.matches(2, 1));
+    assert!(!Comparison::Decreased.matches(1, 1));
+}
+
+#[test]
+fn refine_other_region() {
+    let mut search = MemorySearch::new(snapshot(&[1, 2]));
+    let sram = MemorySnapshot {
+        region: MemoryRegion::Sram,
+        data: vec![1, 2],
+    };
+    assert_eq!(
+        search.refine(sram, Comparison::Unchanged),
+        Err(SearchError::RegionMismatch {
+            expected: MemoryRegion::WorkRam,
+            found: MemoryRegion::Sram,
+        })
+    );
+    assert_eq!(search.len(), 2);
+}
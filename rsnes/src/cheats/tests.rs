@@ -0,0 +1,90 @@
+use super::*;
+use crate::test_utils::{new_cartridge, new_device};
+
+#[test]
+fn parse_game_genie() {
+    let code = CheatCode::parse("C879-D96B").unwrap();
+    assert_eq!(code.format, CheatFormat::GameGenie);
+    assert_eq!(code.addr, Addr24::new(0x12, 0x3456));
+    assert_eq!(code.value, 0xab);
+    assert!(!code.is_ram_write());
+    assert_eq!(CheatCode::parse("c879-d96b"), Ok(code));
+    assert_eq!(
+        CheatCode::parse("C879-D96G"),
+        Err(CheatError::InvalidDigit('G'))
+    );
+}
+
+#[test]
+fn parse_pro_action_replay() {
+    let code: CheatCode = "7E0DBE05".parse().unwrap();
+    assert_eq!(code.format, CheatFormat::ProActionReplay);
+    assert_eq!(code.addr, Addr24::new(0x7e, 0x0dbe));
+    assert_eq!(code.value, 0x05);
+    assert!(code.is_ram_write());
+    assert_eq!(CheatCode::parse("7E0DBE:05"), Ok(code));
+    let code = CheatCode::parse("00812Aea").unwrap();
+    assert_eq!((code.addr, code.value), (Addr24::new(0, 0x812a), 0xea));
+    assert!(!code.is_ram_write());
+    assert!(CheatCode::parse("80:1fff05").is_err());
+    assert_eq!(
+        CheatCode::parse("7E0DBE0"),
+        Err(CheatError::InvalidLength(7))
+    );
+}
+
+#[test]
+fn cheat_list() {
+    let mut cheats = Cheats::default();
+    let write = cheats.add(CheatCode::parse("7E0DBE05").unwrap());
+    let patch = cheats.add(CheatCode::parse("C879-D96B").unwrap());
+    assert_eq!(
+        cheats.ram_writes().collect::<Vec<_>>(),
+        [(Addr24::new(0x7e, 0x0dbe), 5)]
+    );
+    assert_eq!(
+        cheats.read_patches().collect::<Vec<_>>(),
+        [(Addr24::new(0x12, 0x3456), 0xab)]
+    );
+    assert!(cheats.set_enabled(patch, false));
+    assert_eq!(cheats.read_patches().count(), 0);
+    assert!(cheats.remove(write).is_some());
+    assert!(cheats.remove(write).is_none());
+    assert!(!cheats.set_enabled(write, true));
+    assert!(!cheats.get(patch).unwrap().enabled);
+    assert_eq!(cheats.iter().count(), 1);
+}
+
+#[test]
+fn rom_read_patch() {
+    let mut device = new_device();
+    // `lda $8010`, `sta $0010`, `stp`
+    device.load_cartridge(new_cartridge(
+        &[0xad, 0x10, 0x80, 0x8d, 0x10, 0x00, 0xdb],
+        &[],
+    ));
+    let id = device.add_cheat("00801042").unwrap();
+    assert_eq!(device.peek(Addr24::new(0x00, 0x8010)), 0x42);
+    while device.step_cpu_instruction().is_some() {}
+    assert_eq!(device.peek(Addr24::new(0x7e, 0x0010)), 0x42);
+    assert!(device.set_cheat_enabled(id, false));
+    assert_eq!(device.peek(Addr24::new(0x00, 0x8010)), 0xea);
+}
+
+#[test]
+fn ram_write_every_frame() {
+    let mut device = new_device();
+    device.load_cartridge(new_cartridge(&[0x80, 0xfe], &[]));
+    device.add_cheat("7E001005").unwrap();
+    device.add_cheat("001FFF06").unwrap();
+    for _ in 0..2 {
+        device.poke(Addr24::new(0x7e, 0x0010), 0);
+        device.poke(Addr24::new(0x7e, 0x1fff), 0);
+        device.new_frame = false;
+        while !device.new_frame {
+            device.run_cycle::<2>();
+        }
+        assert_eq!(device.peek(Addr24::new(0x7e, 0x0010)), 5);
+        assert_eq!(device.peek(Addr24::new(0x00, 0x1fff)), 6);
+    }
+}
@@ -0,0 +1,83 @@
+//! Correction of the 15-bit colors of the PPU
+//!
+//! The PPU outputs 5 bits per color channel, which look oversaturated and too
+//! bright in the dark tones, when they are shown on a computer display as is.
+//! A television decodes the signal with a different gamma and mixes the
+//! channels slightly. [`ColorCorrection`] approximates this in three steps:
+//! the channels are linearized with the gamma of the television, mixed with a
+//! matrix and encoded with the gamma of the computer display.
+//!
+//! The correction is applied by the PPU with [`crate::ppu::Ppu::set_color_correction`].
+//! Frontends which draw with shaders can instead request the native colors with
+//! [`crate::backend::PixelFormat::Bgr555`] and apply the constants themselves.
+
+/// The parameters of a color correction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorCorrection {
+    /// The gamma the channels are linearized with
+    pub input_gamma: f32,
+    /// The linear output channels (rows) as a mix of the input channels
+    /// (columns), both in the order red, green and blue
+    pub matrix: [[f32; 3]; 3],
+    /// The gamma the mixed channels are encoded with
+    pub output_gamma: f32,
+}
+
+impl ColorCorrection {
+    /// Output the colors unchanged
+    pub const IDENTITY: Self = Self {
+        input_gamma: 1.0,
+        matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        output_gamma: 1.0,
+    };
+
+    /// An approximation of a television connected by composite video.
+    ///
+    /// Every row of the matrix sums up to one, so gray stays gray.
+    pub const CRT: Self = Self {
+        input_gamma: 2.5,
+        matrix: [[0.82, 0.24, -0.06], [0.10, 0.76, 0.14], [0.04, 0.14, 0.82]],
+        output_gamma: 2.2,
+    };
+
+    /// Correct a color in the [`crate::backend::PixelFormat::Bgr555`] format
+    pub fn apply(&self, color: u16) -> [u8; 4] {
+        let linear = [0, 5, 10].map(|shift| self.linearize(color >> shift));
+        let [r, g, b] = self.matrix.map(|row| {
+            let v: f32 = row.iter().zip(&linear).map(|(f, c)| f * c).sum();
+            (v.clamp(0.0, 1.0).powf(self.output_gamma.recip()) * 255.0).round() as u8
+        });
+        [r, g, b, 255]
+    }
+
+    fn linearize(&self, channel: u16) -> f32 {
+        (f32::from(channel & 0x1f) / 31.0).powf(self.input_gamma)
+    }
+
+    /// Precompute the corrected colors of all 15-bit colors
+    pub fn table(&self) -> ColorTable {
+        let mut table: Box<[[u8; 4]]> = (0..0x8000).map(|color| self.apply(color)).collect();
+        // blank pixels are rendered as zero and stay all zeros like uncorrected ones
+        table[0] = [0; 4];
+        ColorTable(table)
+    }
+}
+
+impl Default for ColorCorrection {
+    fn default() -> Self {
+        Self::CRT
+    }
+}
+
+/// The corrected RGBA colors of all 15-bit colors, see [`ColorCorrection::table`]
+#[derive(Debug, Clone)]
+pub struct ColorTable(Box<[[u8; 4]]>);
+
+impl ColorTable {
+    pub fn get(&self, color: u16) -> [u8; 4] {
+        self.0[usize::from(color & 0x7fff)]
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,26 @@
+use super::*;
+use crate::backend::bgr555_to_rgba8;
+
+#[test]
+fn identity() {
+    for color in [0x0000, 0x001f, 0x03e0, 0x7c00, 0x1234, 0x7fff] {
+        let expanded = bgr555_to_rgba8(color);
+        let corrected = ColorCorrection::IDENTITY.apply(color);
+        assert!((0..3).all(|i| expanded[i].abs_diff(corrected[i]) <= 1));
+        assert_eq!(corrected[3], 255);
+    }
+}
+
+#[test]
+fn crt_keeps_gray() {
+    let table = ColorCorrection::CRT.table();
+    assert_eq!(table.get(0), [0; 4]);
+    assert_eq!(table.get(0x7fff), [255; 4]);
+    for c in 1..0x1f {
+        let [r, g, b, _] = table.get(c | (c << 5) | (c << 10));
+        assert!(r.abs_diff(g) <= 1 && g.abs_diff(b) <= 1);
+    }
+    // pure red loses saturation
+    let [r, g, b, _] = table.get(0x001f);
+    assert!(r < 255 && g > 0 && b > 0);
+}
@@ -14,6 +14,172 @@ pub mod buttons {
     pub const X: u16 = 0x200;
     pub const L: u16 = 0x400;
     pub const R: u16 = 0x800;
+
+    /// The buttons in the order of their bits together with their names
+    pub const NAMES: [(u16, &str); 12] = [
+        (B, "B"),
+        (Y, "Y"),
+        (SELECT, "Select"),
+        (START, "Start"),
+        (UP, "Up"),
+        (DOWN, "Down"),
+        (LEFT, "Left"),
+        (RIGHT, "Right"),
+        (A, "A"),
+        (X, "X"),
+        (L, "L"),
+        (R, "R"),
+    ];
+
+    /// The button with the name `name` as used in [`NAMES`]
+    pub fn from_name(name: &str) -> Option<u16> {
+        NAMES
+            .iter()
+            .find(|(_, button_name)| *button_name == name)
+            .map(|(button, _)| *button)
+    }
+}
+
+/// The pressed buttons of a standard controller as a combination of [`buttons`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ButtonState(pub u16);
+
+impl ButtonState {
+    pub const fn is_pressed(self, button: u16) -> bool {
+        self.0 & button > 0
+    }
+
+    pub fn set(&mut self, button: u16, pressed: bool) {
+        if pressed {
+            self.0 |= button
+        } else {
+            self.0 &= !button
+        }
+    }
+}
+
+/// The buttons of both controller ports in a frame, e.g. for an input display
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct InputFrame {
+    /// The buttons held on the input devices of the frontend,
+    /// before turbo, movies or scripts change them
+    pub raw: [ButtonState; 2],
+    /// The buttons seen by the console, `None` without a standard controller
+    pub effective: [Option<ButtonState>; 2],
+}
+
+/// Autofire of the buttons of a standard controller.
+///
+/// While a turbo button is held, it is alternately pressed and
+/// released, each for `period` frames. The layer sits between the
+/// held buttons and [`ControllerPorts::set_buttons`], so the emulated
+/// controller only ever sees the resulting button state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Turbo {
+    /// The turbo buttons as a combination of [`buttons`]
+    pub buttons: u16,
+    period: u16,
+    frame: u16,
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Self::new(0, Self::DEFAULT_PERIOD)
+    }
+}
+
+impl Turbo {
+    pub const DEFAULT_PERIOD: u16 = 2;
+
+    pub const fn new(buttons: u16, period: u16) -> Self {
+        Self {
+            buttons,
+            period: if period > 0 { period } else { 1 },
+            frame: 0,
+        }
+    }
+
+    /// The amount of frames the turbo buttons stay pressed or released
+    pub const fn period(&self) -> u16 {
+        self.period
+    }
+
+    pub fn set_period(&mut self, period: u16) {
+        *self = Self::new(self.buttons, period)
+    }
+
+    /// Toggle the turbo of `buttons` and return the turbo buttons
+    pub fn toggle(&mut self, buttons: u16) -> u16 {
+        self.buttons ^= buttons;
+        self.buttons
+    }
+
+    /// Check if the turbo buttons are released in the current frame
+    pub const fn is_released(&self) -> bool {
+        self.frame >= self.period
+    }
+
+    /// The buttons seen by the console in the current frame if `held` are held
+    pub const fn apply(&self, held: ButtonState) -> ButtonState {
+        if self.is_released() {
+            ButtonState(held.0 & !self.buttons)
+        } else {
+            held
+        }
+    }
+
+    /// Advance the turbo by one emulated frame
+    pub fn next_frame(&mut self) {
+        self.frame = ((u32::from(self.frame) + 1) % (u32::from(self.period) << 1)) as u16
+    }
+}
+
+/// The kind of device connected to a controller port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceKind {
+    None,
+    Standard,
+    Mouse,
+    SuperScope,
+    Multitap,
+}
+
+impl DeviceKind {
+    /// The devices together with their names
+    pub const NAMES: [(Self, &'static str); 5] = [
+        (Self::None, "none"),
+        (Self::Standard, "standard"),
+        (Self::Mouse, "mouse"),
+        (Self::SuperScope, "super-scope"),
+        (Self::Multitap, "multitap"),
+    ];
+
+    /// The device with the name `name` as used in [`Self::NAMES`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, device_name)| *device_name == name)
+            .map(|(device, _)| *device)
+    }
+
+    pub fn name(self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(device, _)| *device == self)
+            .map(|(_, name)| *name)
+            .unwrap()
+    }
+
+    /// Create a device of this kind in its initial state
+    pub fn create(self) -> Controller {
+        match self {
+            Self::None => Controller::None,
+            Self::Standard => Controller::Standard(StandardController::new()),
+            Self::Mouse => Controller::Mouse(Mouse::default()),
+            Self::SuperScope => Controller::SuperScope(SuperScope::default()),
+            Self::Multitap => Controller::Multitap(Multitap::default()),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -21,9 +187,21 @@ pub enum Controller {
     None,
     Standard(StandardController),
     Mouse(Mouse),
+    SuperScope(SuperScope),
+    Multitap(Multitap),
 }
 
 impl Controller {
+    pub const fn kind(&self) -> DeviceKind {
+        match self {
+            Self::None => DeviceKind::None,
+            Self::Standard(_) => DeviceKind::Standard,
+            Self::Mouse(_) => DeviceKind::Mouse,
+            Self::SuperScope(_) => DeviceKind::SuperScope,
+            Self::Multitap(_) => DeviceKind::Multitap,
+        }
+    }
+
     pub fn poll_bit_data1(&self) -> bool {
         match self {
             Self::None => false,
@@ -31,12 +209,22 @@ impl Controller {
                 shift_register.get() & 1 > 0
             }
             Self::Mouse(Mouse { shift_register, .. }) => shift_register.get() & 1 > 0,
+            Self::SuperScope(SuperScope { shift_register, .. }) => shift_register.get() & 1 > 0,
+            Self::Multitap(multitap) => multitap.pair()[0].shift_register.get() & 1 > 0,
         }
     }
 
     pub fn poll_bit_data2(&self) -> bool {
         match self {
-            Self::None | Self::Standard(_) | Self::Mouse(_) => false,
+            Self::None | Self::Standard(_) | Self::Mouse(_) | Self::SuperScope(_) => false,
+            Self::Multitap(multitap) => multitap.pair()[1].shift_register.get() & 1 > 0,
+        }
+    }
+
+    /// Set the I/O bit of the programmable I/O-port belonging to the port
+    fn set_io_bit(&mut self, bit: bool) {
+        if let Self::Multitap(multitap) = self {
+            multitap.io_bit = bit
         }
     }
 
@@ -58,6 +246,12 @@ impl Controller {
                         | ((dx as u32) << 24),
                 );
             }
+            Self::SuperScope(scope) => scope.shift_register.set(scope.get_report()),
+            Self::Multitap(multitap) => {
+                for cntrl in &multitap.controllers {
+                    cntrl.shift_register.set(cntrl.pressed_buttons)
+                }
+            }
             Self::None => (),
         }
     }
@@ -71,6 +265,14 @@ impl Controller {
             Self::Mouse(Mouse { shift_register, .. }) => {
                 shift_register.set((shift_register.get() >> 1) | 0x8000_0000)
             }
+            Self::SuperScope(SuperScope { shift_register, .. }) => {
+                shift_register.set((shift_register.get() >> 1) | 0x8000)
+            }
+            Self::Multitap(multitap) => {
+                for StandardController { shift_register, .. } in multitap.pair() {
+                    shift_register.set((shift_register.get() >> 1) | 0x8000)
+                }
+            }
         }
     }
 
@@ -93,12 +295,16 @@ impl save_state::InSaveState for Controller {
             Self::None => 0,
             Self::Standard(..) => 1,
             Self::Mouse(..) => 2,
+            Self::SuperScope(..) => 3,
+            Self::Multitap(..) => 4,
         };
         n.serialize(state);
         match self {
             Self::None => (),
             Self::Standard(v) => v.serialize(state),
             Self::Mouse(v) => v.serialize(state),
+            Self::SuperScope(v) => v.serialize(state),
+            Self::Multitap(v) => v.serialize(state),
         }
     }
 
@@ -117,7 +323,17 @@ impl save_state::InSaveState for Controller {
                 mouse.deserialize(state);
                 Self::Mouse(mouse)
             }
-            _ => panic!("unexpected discriminant value {}", n),
+            3 => {
+                let mut scope = SuperScope::default();
+                scope.deserialize(state);
+                Self::SuperScope(scope)
+            }
+            4 => {
+                let mut multitap = Multitap::default();
+                multitap.deserialize(state);
+                Self::Multitap(multitap)
+            }
+            _ => return state.fail(save_state::DeserializeError::InvalidValue),
         }
     }
 }
@@ -144,6 +360,34 @@ impl Mouse {
     }
 }
 
+/// The Super Scope light gun
+///
+/// The light gun is connected to controller port 2. Whenever the
+/// CRT beam passes the aimed position the PPU counters get latched.
+#[derive(Debug, Clone, Default, InSaveState)]
+pub struct SuperScope {
+    shift_register: Cell<u16>,
+    pub fire: bool,
+    pub cursor: bool,
+    pub turbo: bool,
+    pub pause: bool,
+    /// The screen position in pixels the gun is aimed at, `None` if it
+    /// points out of the screen
+    pub position: Option<[u16; 2]>,
+}
+
+impl SuperScope {
+    fn get_report(&self) -> u16 {
+        // bits 9-16 are the device signature which is all ones
+        0xff00
+            | (self.fire as u16)
+            | ((self.cursor as u16) << 1)
+            | ((self.turbo as u16) << 2)
+            | ((self.pause as u16) << 3)
+            | ((self.position.is_none() as u16) << 6)
+    }
+}
+
 /// The standard SNES-Controller with A,B,X,Y,Left,Right,Up,Down,
 /// L,R,Start,Select buttons
 #[derive(Debug, Default, Clone, InSaveState)]
@@ -161,6 +405,29 @@ impl StandardController {
     }
 }
 
+/// The Super Multitap, which connects four standard controllers to a port
+///
+/// The I/O bit of the port (bit 6 of the programmable I/O-port for port 1,
+/// bit 7 for port 2) selects, which pair of controllers is read from the
+/// data lines 1 and 2. While the controllers are latched, data line 2
+/// reads one, which lets games detect the Multitap.
+#[derive(Debug, Clone, Default, InSaveState)]
+pub struct Multitap {
+    pub controllers: [StandardController; 4],
+    io_bit: bool,
+}
+
+impl Multitap {
+    /// The controllers connected to the data lines 1 and 2
+    fn pair(&self) -> &[StandardController] {
+        if self.io_bit {
+            &self.controllers[..2]
+        } else {
+            &self.controllers[2..]
+        }
+    }
+}
+
 #[derive(Debug, Clone, InSaveState)]
 pub struct ControllerPort {
     pub controller: Controller,
@@ -186,8 +453,17 @@ impl ControllerPort {
     }
 
     pub fn read_port_data(&mut self) -> u8 {
+        self.clock_port_data(self.strobe)
+    }
+
+    /// Read the data lines and clock the device. The automatic joypad read
+    /// releases the latch before the first bit, so `latched` is `false` then.
+    fn clock_port_data(&mut self, latched: bool) -> u8 {
         let bit1 = self.controller.poll_bit_data1();
-        let bit2 = self.controller.poll_bit_data2();
+        let bit2 = match &self.controller {
+            Controller::Multitap(_) if latched => true,
+            controller => controller.poll_bit_data2(),
+        };
         if !self.strobe {
             self.controller.on_strobe_clock();
         }
@@ -196,12 +472,19 @@ impl ControllerPort {
     }
 }
 
+/// Master cycles of the automatic joypad read, see <https://wiki.superfamicom.org/timing>
+const AUTO_JOYPAD_CYCLES: u16 = 4224;
+/// Master cycles from the start of the automatic joypad read until the latch is released
+const AUTO_JOYPAD_LATCH_CYCLES: u16 = 128;
+/// Master cycles of reading one bit of all controllers
+const AUTO_JOYPAD_BIT_CYCLES: u16 = 256;
+
 #[derive(Debug, Clone, InSaveState)]
 pub struct ControllerPorts {
     pub port1: ControllerPort,
     pub port2: ControllerPort,
     pio: u8,
-    pub(crate) auto_joypad_timer: u16,
+    auto_joypad_timer: u16,
 }
 
 impl ControllerPorts {
@@ -214,37 +497,166 @@ impl ControllerPorts {
         }
     }
 
+    /// Reset the I/O-port and abort an automatic joypad read
+    pub fn reset(&mut self) {
+        self.pio = 0;
+        self.auto_joypad_timer = 0;
+        self.update_io_bits();
+    }
+
     /// Write to the programmable I/O-port.
     /// Returns if EXTLATCH shall be triggered.
     pub fn set_pio(&mut self, val: u8) -> bool {
-        (replace(&mut self.pio, val) & !val) & 0x80 > 0
+        let latch = (replace(&mut self.pio, val) & !val) & 0x80 > 0;
+        self.update_io_bits();
+        latch
+    }
+
+    fn update_io_bits(&mut self) {
+        self.port1.controller.set_io_bit(self.pio & 0x40 > 0);
+        self.port2.controller.set_io_bit(self.pio & 0x80 > 0);
+    }
+
+    /// Connect a device of the kind `device` to the port `port`
+    pub fn connect(&mut self, port: usize, device: DeviceKind) {
+        if let Some(port) = self.port_mut(port) {
+            *port = ControllerPort::new(device.create())
+        }
+        self.update_io_bits()
     }
 
     pub const fn get_pio(&self) -> u8 {
         self.pio
     }
 
+    /// The controller port `port`, which is `0` or `1`
+    pub const fn port(&self, port: usize) -> Option<&ControllerPort> {
+        match port {
+            0 => Some(&self.port1),
+            1 => Some(&self.port2),
+            _ => None,
+        }
+    }
+
+    pub fn port_mut(&mut self, port: usize) -> Option<&mut ControllerPort> {
+        match port {
+            0 => Some(&mut self.port1),
+            1 => Some(&mut self.port2),
+            _ => None,
+        }
+    }
+
+    /// The buttons of the standard controller of `player` at the port `port`.
+    /// Returns `None` if there is no standard controller.
+    ///
+    /// A Multitap has the players `0..4`, other devices only the player `0`.
+    pub fn buttons(&self, port: usize, player: usize) -> Option<ButtonState> {
+        match (&self.port(port)?.controller, player) {
+            (Controller::Standard(controller), 0) => Some(ButtonState(controller.pressed_buttons)),
+            (Controller::Multitap(multitap), _) => multitap
+                .controllers
+                .get(player)
+                .map(|controller| ButtonState(controller.pressed_buttons)),
+            _ => None,
+        }
+    }
+
+    /// Combine the buttons held on the input devices with the buttons
+    /// of the standard controllers, which the console is going to see.
+    ///
+    /// This is meant to be called right before a frame is emulated,
+    /// after all inputs have been set.
+    pub fn input_frame(&self, raw: [ButtonState; 2]) -> InputFrame {
+        InputFrame {
+            raw,
+            effective: [0, 1].map(|port| self.buttons(port, 0)),
+        }
+    }
+
+    /// Set the buttons of the standard controller of `player` at the port `port`.
+    /// The buttons are latched by the next strobe of the controller.
+    /// Returns `false` if there is no standard controller.
+    pub fn set_buttons(&mut self, port: usize, player: usize, state: ButtonState) -> bool {
+        match self
+            .port_mut(port)
+            .map(|port| (&mut port.controller, player))
+        {
+            Some((Controller::Standard(controller), 0)) => {
+                controller.pressed_buttons = state.0;
+                true
+            }
+            Some((Controller::Multitap(multitap), player)) => {
+                match multitap.controllers.get_mut(player) {
+                    Some(controller) => {
+                        controller.pressed_buttons = state.0;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Get the screen position a light gun on port 2 is aimed at.
+    ///
+    /// The light gun can only trigger a counter latch if
+    /// bit 7 of the programmable I/O-port is set.
+    pub fn lightgun_position(&self) -> Option<[u16; 2]> {
+        match &self.port2.controller {
+            Controller::SuperScope(scope) if self.pio & 0x80 > 0 => scope.position,
+            _ => None,
+        }
+    }
+
     pub fn set_strobe(&mut self, bit: bool) {
         self.port1.set_strobe(bit);
         self.port2.set_strobe(bit);
     }
 
-    pub fn auto_joypad(&mut self) {
+    /// Start the automatic joypad read, which shifts the controller
+    /// data into the registers $4218-$421F over 4224 master cycles
+    pub fn start_auto_joypad(&mut self) {
         for port in [&mut self.port1, &mut self.port2] {
             port.set_strobe(false);
             port.set_strobe(true);
             port.data1 = 0;
             port.data2 = 0;
-            for _ in 0..16 {
-                port.data1 <<= 1;
-                port.data2 <<= 1;
-                let data = port.read_port_data();
-                port.data1 |= u16::from(data & 1);
-                port.data2 |= u16::from(data >> 1);
+        }
+        self.auto_joypad_timer = AUTO_JOYPAD_CYCLES;
+    }
+
+    /// The amount of bits read by the automatic joypad read after `elapsed` master cycles
+    const fn auto_joypad_bits(elapsed: u16) -> u16 {
+        let bits = elapsed.saturating_sub(AUTO_JOYPAD_LATCH_CYCLES) / AUTO_JOYPAD_BIT_CYCLES;
+        if bits > 16 {
+            16
+        } else {
+            bits
+        }
+    }
+
+    /// Advance the automatic joypad read by `cycles` master cycles
+    pub fn tick_auto_joypad(&mut self, cycles: u16) {
+        if self.auto_joypad_timer == 0 {
+            return;
+        }
+        let before = Self::auto_joypad_bits(AUTO_JOYPAD_CYCLES - self.auto_joypad_timer);
+        self.auto_joypad_timer -= self.auto_joypad_timer.min(cycles);
+        let after = Self::auto_joypad_bits(AUTO_JOYPAD_CYCLES - self.auto_joypad_timer);
+        for _ in before..after {
+            for port in [&mut self.port1, &mut self.port2] {
+                let data = port.clock_port_data(false);
+                port.data1 = (port.data1 << 1) | u16::from(data & 1);
+                port.data2 = (port.data2 << 1) | u16::from(data >> 1);
             }
         }
     }
 
+    pub const fn is_auto_joypad_busy(&self) -> bool {
+        self.auto_joypad_timer > 0
+    }
+
     pub(crate) fn access(&self, id: u16) -> u8 {
         let port = if id & 2 > 0 { &self.port2 } else { &self.port1 };
         let data = if id & 4 > 0 { port.data2 } else { port.data1 };
@@ -255,3 +667,6 @@ impl ControllerPorts {
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
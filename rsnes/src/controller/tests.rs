@@ -0,0 +1,116 @@
+use super::*;
+
+#[test]
+fn button_names() {
+    for (button, name) in buttons::NAMES {
+        assert_eq!(buttons::from_name(name), Some(button));
+    }
+    let all = buttons::NAMES
+        .iter()
+        .fold(0, |all, (button, _)| all | button);
+    assert_eq!(all, 0xfff);
+    assert_eq!(buttons::from_name("a"), None);
+}
+
+#[test]
+fn turbo() {
+    let mut turbo = Turbo::new(buttons::A, 2);
+    let held = ButtonState(buttons::A | buttons::B);
+    let pressed: Vec<_> = (0..8)
+        .map(|_| {
+            let pressed = turbo.apply(held);
+            turbo.next_frame();
+            pressed.is_pressed(buttons::A)
+        })
+        .collect();
+    assert_eq!(
+        pressed,
+        [true, true, false, false, true, true, false, false]
+    );
+    assert!(turbo.apply(held).is_pressed(buttons::B));
+
+    turbo.next_frame();
+    turbo.next_frame();
+    assert!(turbo.is_released());
+    assert_eq!(turbo.toggle(buttons::A | buttons::B), buttons::B);
+    assert_eq!(turbo.apply(held), ButtonState(buttons::A));
+    turbo.set_period(0);
+    assert_eq!(turbo.period(), 1);
+    assert!(!turbo.is_released());
+}
+
+#[test]
+fn input_frame() {
+    let mut ports = ControllerPorts::new();
+    let raw = [ButtonState(buttons::A), ButtonState(buttons::B)];
+    ports.set_buttons(0, 0, Turbo::new(buttons::A, 1).apply(raw[0]));
+    assert_eq!(ports.input_frame(raw).effective, [Some(raw[0]), None]);
+    let mut turbo = Turbo::new(buttons::A, 1);
+    turbo.next_frame();
+    ports.set_buttons(0, 0, turbo.apply(raw[0]));
+    let frame = ports.input_frame(raw);
+    assert_eq!(frame.raw, raw);
+    assert_eq!(frame.effective, [Some(ButtonState(0)), None]);
+}
+
+#[test]
+fn device_names() {
+    for (device, name) in DeviceKind::NAMES {
+        assert_eq!(DeviceKind::from_name(name), Some(device));
+        assert_eq!(device.name(), name);
+        assert_eq!(device.create().kind(), device);
+    }
+    assert_eq!(DeviceKind::from_name("scope"), None);
+}
+
+#[test]
+fn multitap() {
+    let mut ports = ControllerPorts::new();
+    ports.connect(1, DeviceKind::Multitap);
+    for (player, button) in [buttons::B, buttons::Y, buttons::SELECT, buttons::START]
+        .into_iter()
+        .enumerate()
+    {
+        assert!(ports.set_buttons(1, player, ButtonState(button)));
+    }
+    assert!(!ports.set_buttons(1, 4, ButtonState(0)));
+    assert_eq!(ports.buttons(1, 3), Some(ButtonState(buttons::START)));
+
+    // the Multitap is detected by data line 2 while latched
+    ports.set_strobe(true);
+    assert_eq!(ports.port2.read_port_data(), 2);
+    ports.set_strobe(false);
+    ports.set_strobe(true);
+    ports.set_strobe(false);
+
+    let read = |ports: &mut ControllerPorts| -> [u16; 2] {
+        (0..16).fold([0, 0], |[d1, d2], _| {
+            let data = ports.port2.read_port_data();
+            [
+                (d1 << 1) | u16::from(data & 1),
+                (d2 << 1) | u16::from(data >> 1),
+            ]
+        })
+    };
+    // the first two controllers are selected by the I/O bit
+    ports.set_pio(0x80);
+    assert_eq!(read(&mut ports), [0x8000, 0x4000]);
+    ports.set_pio(0x00);
+    assert_eq!(read(&mut ports), [0x2000, 0x1000]);
+    // the first two controllers were shifted out before
+    ports.set_pio(0x80);
+    assert_eq!(read(&mut ports), [0xffff, 0xffff]);
+
+    // the automatic joypad read fills the registers of data line 2
+    ports.set_pio(0x80);
+    ports.start_auto_joypad();
+    ports.tick_auto_joypad(AUTO_JOYPAD_CYCLES);
+    assert_eq!(
+        ports.access(2) as u16 | (u16::from(ports.access(3)) << 8),
+        0x8000
+    );
+    assert_eq!(
+        ports.access(6) as u16 | (u16::from(ports.access(7)) << 8),
+        0x4000
+    );
+}
@@ -14,7 +14,7 @@ use save_state_macro::*;
 pub const RESET_VECTOR_ADDR: Addr24 = Addr24::new(0, 0xfffc);
 
 /// Structure containing the processor registers
-#[derive(Debug, Clone, InSaveState)]
+#[derive(Debug, Clone, PartialEq, Eq, InSaveState)]
 pub struct Regs {
     /// The accumulator register
     pub a: u16,
@@ -64,7 +64,7 @@ impl Regs {
 
 /// Processor status flags
 #[repr(transparent)]
-#[derive(Debug, Clone, Copy, InSaveState)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, InSaveState)]
 pub struct Status(pub u8);
 
 macro_rules! bitor { ($t:ident, $($vs:ident)|*) => { $t($(<$t>::$vs.0)|*) }; }
@@ -155,6 +155,143 @@ impl Not for Status {
     }
 }
 
+/// An interrupt serviced by the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+}
+
+/// The result of one step of the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuStep {
+    /// Master cycles used by this step
+    pub cycles: crate::timing::Cycles,
+    /// The interrupt, which got serviced before the instruction was executed
+    pub interrupt: Option<Interrupt>,
+}
+
+/// The maximum depth of the [`CallStack`], the outermost frames are dropped beyond it
+#[cfg(feature = "call-stack")]
+const MAX_CALL_DEPTH: usize = 0x400;
+
+/// The way a [`CallFrame`] was entered
+#[cfg(feature = "call-stack")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    /// JSR, returned from with RTS
+    Jsr,
+    /// JSL, returned from with RTL
+    Jsl,
+    /// The instructions BRK and COP and the interrupts, returned from with RTI
+    Brk,
+    Cop,
+    Nmi,
+    Irq,
+}
+
+#[cfg(feature = "call-stack")]
+impl CallKind {
+    const fn is_interrupt(self) -> bool {
+        matches!(self, Self::Brk | Self::Cop | Self::Nmi | Self::Irq)
+    }
+}
+
+#[cfg(feature = "call-stack")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    pub kind: CallKind,
+    /// The address of the call instruction or of the interrupted instruction
+    pub call_site: Addr24,
+    /// The address of the subroutine or of the interrupt handler
+    pub target: Addr24,
+    /// The stack pointer after the return address was pushed
+    pub sp: u16,
+    /// The return address may have been discarded, because the stack pointer
+    /// was moved above it or the returns didn't match the calls
+    pub reliable: bool,
+}
+
+/// A shadow stack of the subroutine calls and interrupts for debuggers.
+///
+/// The frames are matched to the returns by the stack pointer, so that
+/// frames left without a return (e.g. by moving the stack pointer) are
+/// dropped, while returns to manually pushed addresses don't drop frames.
+#[cfg(feature = "call-stack")]
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+}
+
+#[cfg(feature = "call-stack")]
+impl CallStack {
+    pub const fn new() -> Self {
+        Self { frames: Vec::new() }
+    }
+
+    /// The frames, the outermost first
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear()
+    }
+
+    pub(crate) fn enter(&mut self, kind: CallKind, call_site: Addr24, target: Addr24, sp: u16) {
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(CallFrame {
+            kind,
+            call_site,
+            target,
+            sp,
+            reliable: true,
+        })
+    }
+
+    /// Return from the frame, whose return address is at the stack pointer `sp`
+    pub(crate) fn leave(&mut self, kind: CallKind, sp: u16) {
+        let matches_kind = |frame: &CallFrame| {
+            frame.kind == kind || (frame.kind.is_interrupt() && kind.is_interrupt())
+        };
+        match self.frames.iter().rposition(|frame| frame.sp == sp) {
+            Some(index) => {
+                let consistent =
+                    index + 1 == self.frames.len() && matches_kind(&self.frames[index]);
+                self.frames.truncate(index);
+                if !consistent {
+                    self.mark_unreliable(0)
+                }
+            }
+            None => {
+                // frames below the stack pointer were left without a return,
+                // frames above it were not returned from yet
+                let len = self.frames.len();
+                self.frames.retain(|frame| frame.sp > sp);
+                if self.frames.len() != len {
+                    self.mark_unreliable(0)
+                }
+            }
+        }
+    }
+
+    /// The stack pointer was set to `sp`, which discards the return addresses below it
+    pub(crate) fn set_stack_pointer(&mut self, sp: u16) {
+        self.mark_unreliable(sp)
+    }
+
+    /// Mark the frames with a stack pointer below `sp` as unreliable, or all with `0`
+    fn mark_unreliable(&mut self, sp: u16) {
+        for frame in &mut self.frames {
+            if sp == 0 || frame.sp < sp {
+                frame.reliable = false
+            }
+        }
+    }
+}
+
 /// Structure for emulating the 65816 Processor
 #[derive(Debug, Clone, InSaveState)]
 pub struct Cpu {
@@ -163,8 +300,20 @@ pub struct Cpu {
     pub(crate) access_speed: bool,
     pub(crate) in_nmi: bool,
     pub(crate) irq_bit: u8,
+    /// The IRQ disable flag used to poll the IRQ line before the next instruction.
+    ///
+    /// CLI, SEI, PLP, REP and SEP change the flag in their last cycle after
+    /// the interrupts were polled, so the change only applies one instruction later.
+    pub(crate) irq_disable_polled: bool,
     pub wait_mode: bool,
     pub active: bool,
+    /// The last executed step, used to implement single stepping
+    #[except((|_v, _s| ()), (|v: &mut Option<CpuStep>, _s| *v = None))]
+    pub(crate) last_step: Option<CpuStep>,
+    /// The frames of a loaded state are unknown
+    #[cfg(feature = "call-stack")]
+    #[except((|_v, _s| ()), (|v: &mut CallStack, _s| v.clear()))]
+    pub(crate) call_stack: CallStack,
 }
 
 impl Cpu {
@@ -185,11 +334,22 @@ impl Cpu {
             access_speed: false,
             in_nmi: false,
             irq_bit: 0,
+            irq_disable_polled: true,
             wait_mode: false,
             active: true,
+            last_step: None,
+            #[cfg(feature = "call-stack")]
+            call_stack: CallStack::new(),
         }
     }
 
+    /// The subroutine calls and interrupts, which were not returned from yet,
+    /// the outermost first
+    #[cfg(feature = "call-stack")]
+    pub fn call_stack(&self) -> &[CallFrame] {
+        self.call_stack.frames()
+    }
+
     /// Indicate if the A register is in 8-bit mode
     pub const fn is_reg8(&self) -> bool {
         self.regs.status.has(Status::ACCUMULATION) || self.regs.is_emulation
@@ -233,9 +393,17 @@ impl Cpu {
     pub fn update_emulation(&mut self) {
         self.regs.status |= Status::INDEX_REGISTER_SIZE | Status::ACCUMULATION;
         self.regs.sp = (self.regs.sp & 0xff) | 0x100;
+        #[cfg(feature = "call-stack")]
+        self.update_call_stack();
         self.update_status();
     }
 
+    /// Mark the frames of the call stack above the changed stack pointer as unreliable
+    #[cfg(feature = "call-stack")]
+    pub(crate) fn update_call_stack(&mut self) {
+        self.call_stack.set_stack_pointer(self.regs.sp)
+    }
+
     pub fn update_status(&mut self) {
         if self.is_idx8() {
             self.regs.x &= 0xff;
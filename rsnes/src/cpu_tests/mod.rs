@@ -0,0 +1,187 @@
+//! Single instruction test harness for the 65816 CPU
+//!
+//! This module loads JSON test vectors, where every test case describes
+//! the processor state and memory before and after executing one instruction.
+//! The instruction is executed on a flat 24-bit memory without any
+//! memory mapped I/O, so only the CPU itself is tested.
+//! The harness for the SPC700 lives in [`spc700`].
+//!
+//! # Literature
+//!
+//! - <https://github.com/TomHarte/ProcessorTests/tree/main/65816>
+
+use crate::{
+    backend::{AudioDummy, FrameBuffer, NullFrameBuffer},
+    cpu::{Cpu, Status},
+    device::{Addr24, Data, Device, Region},
+    instr::AccessType,
+};
+use serde::Deserialize;
+
+pub mod spc700;
+
+/// The processor and memory state of a test case
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CpuState {
+    pub pc: u16,
+    pub s: u16,
+    pub p: u8,
+    pub a: u16,
+    pub x: u16,
+    pub y: u16,
+    pub dbr: u8,
+    pub d: u16,
+    pub pbr: u8,
+    pub e: u8,
+    /// List of `[address, value]` pairs
+    pub ram: Vec<(u32, u8)>,
+}
+
+/// A single instruction test case
+#[derive(Debug, Clone, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    pub initial: CpuState,
+    #[serde(rename = "final")]
+    pub expected: CpuState,
+    /// The bus activity for every CPU cycle. Only the amount of cycles is compared.
+    pub cycles: Vec<serde_json::Value>,
+}
+
+/// Parse a JSON array of test cases
+pub fn load_tests(json: &str) -> Result<Vec<TestCase>, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+/// A mismatch between the expected and the emulated state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub what: String,
+    pub expected: u32,
+    pub got: u32,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: expected {:#x}, got {:#x}",
+            self.what, self.expected, self.got
+        )
+    }
+}
+
+/// Access type, which maps the whole 24-bit address space to flat memory
+pub struct AccessTypeFlat;
+
+impl<B: crate::backend::AudioBackend, FB: FrameBuffer> AccessType<B, FB> for AccessTypeFlat {
+    fn read<D: Data>(device: &mut Device<B, FB>, addr: Addr24) -> D {
+        let mut data = <D::Arr as Default>::default();
+        let mut addr = (u32::from(addr.bank) << 16) | u32::from(addr.addr);
+        for d in data.as_mut() {
+            *d = device.flat_memory.get(&addr).copied().unwrap_or(0);
+            addr = (addr + 1) & 0xffffff;
+        }
+        D::from_bytes(&data)
+    }
+
+    fn write<D: Data>(device: &mut Device<B, FB>, addr: Addr24, val: D) {
+        let mut addr = (u32::from(addr.bank) << 16) | u32::from(addr.addr);
+        for &d in val.to_bytes().as_ref() {
+            device.flat_memory.insert(addr, d);
+            addr = (addr + 1) & 0xffffff;
+        }
+    }
+
+    fn cpu(device: &Device<B, FB>) -> &Cpu {
+        &device.cpu
+    }
+
+    fn cpu_mut(device: &mut Device<B, FB>) -> &mut Cpu {
+        &mut device.cpu
+    }
+
+    fn is_main() -> bool {
+        true
+    }
+}
+
+/// Runner for single instruction test cases
+pub struct TestRunner {
+    device: Box<Device<AudioDummy, NullFrameBuffer>>,
+}
+
+impl Default for TestRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestRunner {
+    pub fn new() -> Self {
+        Self {
+            device: Box::new(Device::new(
+                AudioDummy,
+                NullFrameBuffer,
+                Region::Ntsc,
+                false,
+            )),
+        }
+    }
+
+    fn set_state(&mut self, state: &CpuState) {
+        let cpu = &mut self.device.cpu;
+        *cpu = Cpu::new();
+        cpu.regs.pc = Addr24::new(state.pbr, state.pc);
+        cpu.regs.sp = state.s;
+        cpu.regs.status = Status(state.p);
+        cpu.regs.a = state.a;
+        cpu.regs.x = state.x;
+        cpu.regs.y = state.y;
+        cpu.regs.db = state.dbr;
+        cpu.regs.dp = state.d;
+        cpu.regs.is_emulation = state.e != 0;
+        self.device.flat_memory.clear();
+        self.device
+            .flat_memory
+            .extend(state.ram.iter().map(|&(addr, val)| (addr & 0xffffff, val)));
+    }
+
+    /// Execute a test case and return all differences to the expected state
+    pub fn run(&mut self, test: &TestCase) -> Vec<Mismatch> {
+        self.set_state(&test.initial);
+        let cycles = crate::instr::create_device_access::<AccessTypeFlat, _, _>(&mut self.device)
+            .dispatch_instruction();
+
+        let mut mismatches = vec![];
+        let mut check = |what: &str, expected: u32, got: u32| {
+            if expected != got {
+                mismatches.push(Mismatch {
+                    what: what.to_string(),
+                    expected,
+                    got,
+                })
+            }
+        };
+        let (exp, regs) = (&test.expected, &self.device.cpu.regs);
+        check("pc", exp.pc.into(), regs.pc.addr.into());
+        check("pbr", exp.pbr.into(), regs.pc.bank.into());
+        check("s", exp.s.into(), regs.sp.into());
+        check("p", exp.p.into(), regs.status.0.into());
+        check("a", exp.a.into(), regs.a.into());
+        check("x", exp.x.into(), regs.x.into());
+        check("y", exp.y.into(), regs.y.into());
+        check("dbr", exp.dbr.into(), regs.db.into());
+        check("d", exp.d.into(), regs.dp.into());
+        check("e", exp.e.into(), regs.is_emulation.into());
+        for &(addr, val) in &exp.ram {
+            let got = self.device.flat_memory.get(&addr).copied().unwrap_or(0);
+            check(&format!("ram[{:06x}]", addr), val.into(), got.into());
+        }
+        check("cycles", test.cycles.len() as u32, cycles);
+        mismatches
+    }
+}
+
+#[cfg(test)]
+mod tests;
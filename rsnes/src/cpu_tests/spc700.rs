@@ -0,0 +1,134 @@
+//! Single instruction test harness for the SPC700
+//!
+//! The instruction is executed on a flat 16-bit memory, so neither the
+//! I/O registers at `$F0-$FF` nor the IPL ROM are visible to the processor.
+//!
+//! # Literature
+//!
+//! - <https://github.com/TomHarte/ProcessorTests/tree/main/spc700>
+
+use super::Mismatch;
+use crate::spc700::Spc700;
+use serde::Deserialize;
+
+/// The processor and memory state of a test case
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CpuState {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub psw: u8,
+    /// List of `[address, value]` pairs
+    pub ram: Vec<(u16, u8)>,
+}
+
+/// A single instruction test case
+#[derive(Debug, Clone, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    pub initial: CpuState,
+    #[serde(rename = "final")]
+    pub expected: CpuState,
+    /// The bus activity for every CPU cycle. Only the amount of cycles is compared.
+    pub cycles: Vec<serde_json::Value>,
+}
+
+/// Parse a JSON array of test cases
+pub fn load_tests(json: &str) -> Result<Vec<TestCase>, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+/// Runner for single instruction test cases
+#[derive(Debug, Clone)]
+pub struct TestRunner {
+    spc: Box<Spc700>,
+    cycle_stepped: bool,
+}
+
+impl Default for TestRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestRunner {
+    pub fn new() -> Self {
+        let mut spc = Box::new(Spc700::default());
+        spc.flat_memory = true;
+        Self {
+            spc,
+            cycle_stepped: false,
+        }
+    }
+
+    /// Run the test cases cycle by cycle in the cycle-stepped mode
+    pub fn new_cycle_stepped() -> Self {
+        Self {
+            cycle_stepped: true,
+            ..Self::new()
+        }
+    }
+
+    fn set_state(&mut self, state: &CpuState) {
+        let spc = &mut self.spc;
+        spc.reset();
+        spc.cycles_ahead = 0;
+        spc.mem.fill(0);
+        spc.pc = state.pc;
+        spc.a = state.a;
+        spc.x = state.x;
+        spc.y = state.y;
+        spc.sp = state.sp;
+        spc.status = state.psw;
+        for &(addr, val) in &state.ram {
+            spc.mem[usize::from(addr)] = val;
+        }
+    }
+
+    /// Execute the instruction and return the amount of cycles it took
+    fn execute(&mut self) -> u32 {
+        if !self.cycle_stepped {
+            return self.spc.dispatch_instruction();
+        }
+        self.spc.set_cycle_stepped(true);
+        let mut cycles = 0;
+        while {
+            self.spc.run_cycle();
+            cycles += 1;
+            self.spc.instruction_pending || self.spc.cycles_ahead > 0
+        } {}
+        cycles
+    }
+
+    /// Execute a test case and return all differences to the expected state
+    pub fn run(&mut self, test: &TestCase) -> Vec<Mismatch> {
+        self.set_state(&test.initial);
+        let cycles = self.execute();
+
+        let mut mismatches = vec![];
+        let mut check = |what: &str, expected: u32, got: u32| {
+            if expected != got {
+                mismatches.push(Mismatch {
+                    what: what.to_string(),
+                    expected,
+                    got,
+                })
+            }
+        };
+        let (exp, spc) = (&test.expected, &self.spc);
+        check("pc", exp.pc.into(), spc.pc.into());
+        check("a", exp.a.into(), spc.a.into());
+        check("x", exp.x.into(), spc.x.into());
+        check("y", exp.y.into(), spc.y.into());
+        check("sp", exp.sp.into(), spc.sp.into());
+        check("psw", exp.psw.into(), spc.status.into());
+        for &(addr, val) in &exp.ram {
+            let got = spc.mem[usize::from(addr)];
+            check(&format!("ram[{:04x}]", addr), val.into(), got.into());
+        }
+        check("cycles", test.cycles.len() as u32, cycles);
+        mismatches
+    }
+}
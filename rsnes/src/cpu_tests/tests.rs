@@ -0,0 +1,292 @@
+use super::*;
+
+fn run_json(json: &str) {
+    let mut runner = TestRunner::new();
+    for test in load_tests(json).unwrap() {
+        let mismatches = runner.run(&test);
+        assert!(
+            mismatches.is_empty(),
+            "test `{}` failed:\n{}",
+            test.name,
+            mismatches
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+}
+
+#[test]
+fn clear_carry_emulation() {
+    run_json(
+        r#"[{
+            "name": "18 e 1",
+            "initial": {"pc": 4096, "s": 511, "p": 53, "a": 0, "x": 0, "y": 0, "dbr": 0,
+                        "d": 0, "pbr": 0, "e": 1, "ram": [[4096, 24]]},
+            "final": {"pc": 4097, "s": 511, "p": 52, "a": 0, "x": 0, "y": 0, "dbr": 0,
+                      "d": 0, "pbr": 0, "e": 1, "ram": [[4096, 24]]},
+            "cycles": [[4096, 24, "dp-remxPR"], [4097, null, "-p-remxPR"]]
+        }]"#,
+    )
+}
+
+#[test]
+fn load_accumulator_immediate16() {
+    run_json(
+        r#"[{
+            "name": "a9 n 1",
+            "initial": {"pc": 4096, "s": 8191, "p": 0, "a": 0, "x": 0, "y": 0, "dbr": 0,
+                        "d": 0, "pbr": 1, "e": 0,
+                        "ram": [[69632, 169], [69633, 52], [69634, 18]]},
+            "final": {"pc": 4099, "s": 8191, "p": 0, "a": 4660, "x": 0, "y": 0, "dbr": 0,
+                      "d": 0, "pbr": 1, "e": 0,
+                      "ram": [[69632, 169], [69633, 52], [69634, 18]]},
+            "cycles": [[69632, 169, "dp-remxPR"], [69633, 52, "-p-remxPR"],
+                       [69634, 18, "-p-remxPR"]]
+        }]"#,
+    )
+}
+
+#[test]
+fn store_accumulator_absolute8() {
+    run_json(
+        r#"[{
+            "name": "8d n 1",
+            "initial": {"pc": 4096, "s": 8191, "p": 32, "a": 66, "x": 0, "y": 0, "dbr": 126,
+                        "d": 0, "pbr": 0, "e": 0,
+                        "ram": [[4096, 141], [4097, 0], [4098, 32]]},
+            "final": {"pc": 4099, "s": 8191, "p": 32, "a": 66, "x": 0, "y": 0, "dbr": 126,
+                      "d": 0, "pbr": 0, "e": 0,
+                      "ram": [[4096, 141], [4097, 0], [4098, 32], [8265728, 66]]},
+            "cycles": [[4096, 141, "dp-remxPR"], [4097, 0, "-p-remxPR"],
+                       [4098, 32, "-p-remxPR"], [8265728, 66, "d--remxPW"]]
+        }]"#,
+    )
+}
+
+#[test]
+fn direct_page_wrapping_emulation() {
+    // lda $f8,x wraps around within the direct page
+    run_json(
+        r#"[{
+            "name": "b5 e 1",
+            "initial": {"pc": 4096, "s": 511, "p": 52, "a": 0, "x": 16, "y": 0, "dbr": 0,
+                        "d": 0, "pbr": 0, "e": 1, "ram": [[4096, 181], [4097, 248], [8, 66]]},
+            "final": {"pc": 4098, "s": 511, "p": 52, "a": 66, "x": 16, "y": 0, "dbr": 0,
+                      "d": 0, "pbr": 0, "e": 1, "ram": [[4096, 181], [4097, 248], [8, 66]]},
+            "cycles": [[4096, 181, "dp-remxPR"], [4097, 248, "-p-remxPR"],
+                       [4097, null, "-p-remxPR"], [8, 66, "d--remxPR"]]
+        }]"#,
+    )
+}
+
+#[test]
+fn push_effective_address_emulation() {
+    // pea $1234 pushes below page 1, the stack pointer is confined to page 1 afterwards
+    run_json(
+        r#"[{
+            "name": "f4 e 1",
+            "initial": {"pc": 4096, "s": 256, "p": 52, "a": 0, "x": 0, "y": 0, "dbr": 0,
+                        "d": 0, "pbr": 0, "e": 1,
+                        "ram": [[4096, 244], [4097, 52], [4098, 18]]},
+            "final": {"pc": 4099, "s": 510, "p": 52, "a": 0, "x": 0, "y": 0, "dbr": 0,
+                      "d": 0, "pbr": 0, "e": 1,
+                      "ram": [[4096, 244], [4097, 52], [4098, 18], [255, 52], [256, 18]]},
+            "cycles": [[4096, 244, "dp-remxPR"], [4097, 52, "-p-remxPR"],
+                       [4098, 18, "-p-remxPR"], [256, 18, "--e--xPW"], [255, 52, "--e--xPW"]]
+        }]"#,
+    )
+}
+
+#[test]
+fn block_move_negative() {
+    // mvn $7e,$01 moves one byte and returns to itself
+    run_json(
+        r#"[{
+            "name": "54 n 1",
+            "initial": {"pc": 4096, "s": 8191, "p": 0, "a": 1, "x": 8192, "y": 12288,
+                        "dbr": 0, "d": 0, "pbr": 0, "e": 0,
+                        "ram": [[4096, 84], [4097, 126], [4098, 1], [73728, 66]]},
+            "final": {"pc": 4096, "s": 8191, "p": 0, "a": 0, "x": 8193, "y": 12289,
+                      "dbr": 126, "d": 0, "pbr": 0, "e": 0,
+                      "ram": [[4096, 84], [4097, 126], [4098, 1], [73728, 66],
+                              [8269824, 66]]},
+            "cycles": [[4096, 84, "dp-remxPR"], [4097, 126, "-p-remxPR"],
+                       [4098, 1, "-p-remxPR"], [73728, 66, "d--remxPR"],
+                       [8269824, 66, "d--remxPW"], [8269824, null, "---remxPR"],
+                       [8269824, null, "---remxPR"]]
+        }]"#,
+    )
+}
+
+/// Run all test vector files (`*.json`) in the directory given by
+/// the environment variable `RSNES_CPU_TESTS_DIR`.
+#[test]
+#[ignore]
+fn external_test_vectors() {
+    let dir = std::env::var("RSNES_CPU_TESTS_DIR")
+        .expect("the environment variable `RSNES_CPU_TESTS_DIR` is not set");
+    let mut runner = TestRunner::new();
+    let (mut total, mut failed) = (0usize, 0usize);
+    let mut paths: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let tests = load_tests(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        for test in tests {
+            total += 1;
+            let mismatches = runner.run(&test);
+            if let Some(first) = mismatches.first() {
+                failed += 1;
+                println!("{}: {}", test.name, first);
+            }
+        }
+    }
+    assert_eq!(failed, 0, "{} of {} tests failed", failed, total);
+}
+
+fn run_spc700_json(json: &str) {
+    let runners = [
+        spc700::TestRunner::new(),
+        spc700::TestRunner::new_cycle_stepped(),
+    ];
+    for mut runner in runners {
+        for test in spc700::load_tests(json).unwrap() {
+            let mismatches = runner.run(&test);
+            assert!(
+                mismatches.is_empty(),
+                "test `{}` failed:\n{}",
+                test.name,
+                mismatches
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+    }
+}
+
+#[test]
+fn spc700_load_accumulator_immediate() {
+    run_spc700_json(
+        r#"[{
+            "name": "e8 0000",
+            "initial": {"pc": 4096, "a": 0, "x": 0, "y": 0, "sp": 239, "psw": 2,
+                        "ram": [[4096, 232], [4097, 66]]},
+            "final": {"pc": 4098, "a": 66, "x": 0, "y": 0, "sp": 239, "psw": 0,
+                      "ram": [[4096, 232], [4097, 66]]},
+            "cycles": [[4096, 232, "read"], [4097, 66, "read"]]
+        }]"#,
+    )
+}
+
+#[test]
+fn spc700_store_immediate_direct_page() {
+    run_spc700_json(
+        r#"[{
+            "name": "8f 0000",
+            "initial": {"pc": 4096, "a": 0, "x": 0, "y": 0, "sp": 239, "psw": 0,
+                        "ram": [[4096, 143], [4097, 66], [4098, 16]]},
+            "final": {"pc": 4099, "a": 0, "x": 0, "y": 0, "sp": 239, "psw": 0,
+                      "ram": [[4096, 143], [4097, 66], [4098, 16], [16, 66]]},
+            "cycles": [[4096, 143, "read"], [4097, 66, "read"], [4098, 16, "read"],
+                       [16, 0, "read"], [16, 66, "write"]]
+        }]"#,
+    )
+}
+
+#[test]
+fn spc700_branch_taken() {
+    run_spc700_json(
+        r#"[{
+            "name": "d0 0000",
+            "initial": {"pc": 4096, "a": 0, "x": 0, "y": 0, "sp": 239, "psw": 0,
+                        "ram": [[4096, 208], [4097, 4]]},
+            "final": {"pc": 4102, "a": 0, "x": 0, "y": 0, "sp": 239, "psw": 0,
+                      "ram": [[4096, 208], [4097, 4]]},
+            "cycles": [[4096, 208, "read"], [4097, 4, "read"],
+                       [null, null, "wait"], [null, null, "wait"]]
+        }]"#,
+    )
+}
+
+#[test]
+fn spc700_write_test_register() {
+    run_spc700_json(
+        r#"[{
+            "name": "c4 0000",
+            "initial": {"pc": 4096, "a": 128, "x": 0, "y": 0, "sp": 239, "psw": 0,
+                        "ram": [[4096, 196], [4097, 240]]},
+            "final": {"pc": 4098, "a": 128, "x": 0, "y": 0, "sp": 239, "psw": 0,
+                      "ram": [[4096, 196], [4097, 240], [240, 128]]},
+            "cycles": [[4096, 196, "read"], [4097, 240, "read"], [240, 0, "read"],
+                       [240, 128, "write"]]
+        }]"#,
+    )
+}
+
+#[test]
+fn spc700_cycle_stepped_write_timing() {
+    let mut spc = crate::spc700::Spc700::default();
+    spc.flat_memory = true;
+    spc.cycles_ahead = 0;
+    spc.pc = 0x1000;
+    spc.mem[0x1000..0x1003].copy_from_slice(&[0x8f, 0x42, 0x10]);
+    spc.set_cycle_stepped(true);
+    for _ in 0..4 {
+        spc.run_cycle();
+        assert_eq!(spc.mem[0x10], 0);
+    }
+    spc.run_cycle();
+    assert_eq!(spc.mem[0x10], 0x42);
+    assert_eq!(spc.pc, 0x1003);
+}
+
+#[test]
+fn spc700_test_register() {
+    use crate::spc700::{flags, test_flags};
+    let mut spc = crate::spc700::Spc700::default();
+    spc.write(0x1234, 0x11);
+    assert_eq!(spc.read(0x1234), 0x11);
+    spc.write(0xf0, test_flags::TIMERS_ENABLE);
+    assert_eq!(spc.test_register(), test_flags::TIMERS_ENABLE);
+    spc.write(0x1234, 0x22);
+    assert_eq!(spc.read(0x1234), 0x11);
+    spc.status |= flags::ZERO_PAGE;
+    spc.write(0xf0, test_flags::TIMERS_ENABLE | test_flags::RAM_DISABLE);
+    assert_eq!(spc.test_register(), test_flags::TIMERS_ENABLE);
+}
+
+/// Run all SPC700 test vector files (`*.json`) in the directory given by
+/// the environment variable `RSNES_SPC700_TESTS_DIR`.
+#[test]
+#[ignore]
+fn external_spc700_test_vectors() {
+    let dir = std::env::var("RSNES_SPC700_TESTS_DIR")
+        .expect("the environment variable `RSNES_SPC700_TESTS_DIR` is not set");
+    let mut runner = spc700::TestRunner::new();
+    let (mut total, mut failed) = (0usize, 0usize);
+    let mut paths: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let tests = spc700::load_tests(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        for test in tests {
+            total += 1;
+            let mismatches = runner.run(&test);
+            if let Some(first) = mismatches.first() {
+                failed += 1;
+                println!("{}: {}", test.name, first);
+            }
+        }
+    }
+    assert_eq!(failed, 0, "{} of {} tests failed", failed, total);
+}
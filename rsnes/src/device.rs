@@ -1,21 +1,202 @@
 //! The SNES/Famicom device
 
 use crate::{
-    backend::{AudioBackend, FrameBuffer},
-    cartridge::Cartridge,
+    backend::{AudioBackend, ClockSource, FrameBuffer, SystemClock},
+    breakpoint::{expr::Expr, Breakpoints},
+    cartridge::{page_index, Cartridge, PAGE_COUNT},
+    cheats::{
+        search::{MemoryRegion, MemorySnapshot},
+        CheatCode, CheatError, CheatId, Cheats,
+    },
     controller::ControllerPorts,
     cpu::Cpu,
     dma::Dma,
+    heatmap::Heatmap,
+    idle_loop::IdleLoop,
     ppu::Ppu,
     registers::MathRegisters,
     smp::Smp,
     timing::Cycles,
+    watch::{AccessKind, Watches},
 };
 use core::cell::Cell;
 use save_state_macro::*;
+use std::sync::Arc;
 
 const RAM_SIZE: usize = 0x20000;
 
+/// How the CPU accesses an 8 KiB page of the address bus A
+#[derive(Debug, Clone, Copy)]
+enum BusPage {
+    /// The work RAM starting at the index
+    Wram(u32),
+    /// The cartridge, see [`Cartridge::read`]
+    Cartridge,
+    /// The registers of the bus B and the CPU, mixed with the cartridge
+    Io,
+}
+
+/// The pages of the bus A, which are the same for every cartridge
+const BUS_PAGES: [BusPage; PAGE_COUNT] = {
+    let mut pages = [BusPage::Cartridge; PAGE_COUNT];
+    let mut index = 0;
+    while index < PAGE_COUNT {
+        let (bank, page) = ((index >> 3) as u8, index as u32 & 7);
+        pages[index] = if bank & 0xfe == 0x7e {
+            BusPage::Wram((bank as u32 & 1) << 16 | page << 13)
+        } else if bank & 0x40 == 0 {
+            match page {
+                0 => BusPage::Wram(0),
+                1 | 2 => BusPage::Io,
+                _ => BusPage::Cartridge,
+            }
+        } else {
+            BusPage::Cartridge
+        };
+        index += 1;
+    }
+    pages
+};
+/// The maximum amount of queued messages, see [`Device::notify`]
+pub const MAX_MESSAGES: usize = 16;
+
+/// The console region, which determines the video standard and clock speeds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Region {
+    /// 60 Hz region with 262 scanlines per frame
+    Ntsc = 0,
+    /// 50 Hz region with 312 scanlines per frame
+    Pal = 1,
+}
+
+impl Region {
+    /// Choose a region depending on the country code in the cartridge header.
+    /// Cartridges without a known country fall back to NTSC.
+    pub const fn from_cartridge(cartridge: &Cartridge) -> Self {
+        match cartridge.get_country_frame_rate() {
+            crate::cartridge::CountryFrameRate::Pal => Self::Pal,
+            _ => Self::Ntsc,
+        }
+    }
+
+    pub const fn is_pal(&self) -> bool {
+        matches!(self, Self::Pal)
+    }
+
+    /// The master clock frequency as a fraction in Hz
+    pub const fn master_clock(&self) -> (u64, u64) {
+        match self {
+            // (945/44) MHz
+            Self::Ntsc => (236_250_000, 11),
+            Self::Pal => (21_281_370, 1),
+        }
+    }
+
+    /// Convert an amount of master cycles into real time nanoseconds
+    pub const fn master_cycles_to_nanos(&self, cycles: u64) -> u64 {
+        let (num, den) = self.master_clock();
+        (cycles * den * 1_000_000_000) / num
+    }
+
+    /// The approximate amount of frames per second in non-interlaced mode
+    pub fn frame_rate(&self) -> f64 {
+        let (num, den) = self.master_clock();
+        let (lines, dots) = match self {
+            Self::Ntsc => (262.0, 1364.0),
+            Self::Pal => (312.0, 1364.0),
+        };
+        num as f64 / (den as f64 * lines * dots)
+    }
+}
+
+impl save_state::InSaveState for Region {
+    fn serialize(&self, state: &mut save_state::SaveStateSerializer) {
+        (*self as u8).serialize(state)
+    }
+
+    fn deserialize(&mut self, state: &mut save_state::SaveStateDeserializer) {
+        let mut i: u8 = 0;
+        i.deserialize(state);
+        *self = match i {
+            0 => Self::Ntsc,
+            1 => Self::Pal,
+            _ => return state.fail(save_state::DeserializeError::InvalidValue),
+        }
+    }
+}
+
+/// The contents of the work RAM at power-on.
+///
+/// The work RAM of a real console contains a pattern which depends on the
+/// individual RAM chip, and some games behave differently depending on it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RamInit {
+    /// All bytes are zero
+    #[default]
+    Zeros,
+    /// Alternating `0x55` and `0xAA` bytes
+    Pattern,
+    /// Pseudo-random bytes, which are the same for the same seed
+    Random(u64),
+}
+
+impl RamInit {
+    pub fn fill(&self, ram: &mut [u8]) {
+        match self {
+            Self::Zeros => ram.fill(0),
+            Self::Pattern => {
+                for (i, byte) in ram.iter_mut().enumerate() {
+                    *byte = if i & 1 == 0 { 0x55 } else { 0xaa }
+                }
+            }
+            Self::Random(seed) => {
+                // SplitMix64
+                let mut state = *seed;
+                for chunk in ram.chunks_mut(8) {
+                    state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                    let mut z = state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                    z ^= z >> 31;
+                    chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+/// The default of [`DeviceOptions::apu_sync_cycles`], the length of a scanline
+pub const DEFAULT_APU_SYNC_CYCLES: u32 = 1364;
+
+/// The slowest emulation speed, see [`Device::set_speed`]
+pub const MIN_SPEED: f32 = 1.0 / 256.0;
+
+/// Settings of the emulated console, which are not part of the emulated state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceOptions {
+    /// The contents of the work RAM at power-on, see [`Device::power_cycle`]
+    pub ram_init: RamInit,
+    /// Skip the cycles of loops, which wait for an interrupt by polling the
+    /// work RAM. This speed hack changes the timing of the interrupts, so it
+    /// shouldn't be used with movies or netplay.
+    pub skip_idle_loops: bool,
+    /// Master cycles the main CPU may run ahead of the S-SMP, before the S-SMP
+    /// catches up. The S-SMP always catches up before an APU port is accessed,
+    /// so this only trades the overhead of catching up (more noticeable in
+    /// threaded mode) for a more steady audio output.
+    pub apu_sync_cycles: u32,
+}
+
+impl Default for DeviceOptions {
+    fn default() -> Self {
+        Self {
+            ram_init: RamInit::default(),
+            apu_sync_cycles: DEFAULT_APU_SYNC_CYCLES,
+            skip_idle_loops: false,
+        }
+    }
+}
+
 /// The 24-bit address type used
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Addr24 {
@@ -139,6 +320,20 @@ impl Data for Addr24 {
     }
 }
 
+/// A function called at the start of every scanline, see [`Device::set_scanline_hook`]
+pub type ScanlineHook = Box<dyn FnMut(u16) + Send>;
+
+#[derive(Default)]
+struct ScanlineHookSlot(Option<ScanlineHook>);
+
+impl core::fmt::Debug for ScanlineHookSlot {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("ScanlineHookSlot")
+            .field(&self.0.is_some())
+            .finish()
+    }
+}
+
 #[derive(Debug, InSaveState)]
 pub struct Device<B: AudioBackend, FB: FrameBuffer> {
     pub(crate) cpu: Cpu,
@@ -152,23 +347,83 @@ pub struct Device<B: AudioBackend, FB: FrameBuffer> {
     ram: [u8; RAM_SIZE],
     wram_addr: Cell<u32>,
     pub(crate) memory_cycles: Cycles,
+    /// Master cycles elapsed since power on
+    pub(crate) master_cycles: u64,
     pub(crate) cpu_ahead_cycles: i32,
     pub(crate) new_scanline: bool,
     pub(crate) scanline_drawn: bool,
     pub new_frame: bool,
     pub(crate) do_hdma: bool,
-    // multiplied by 4
+    /// HTIME in dots
     pub(crate) irq_time_h: u16,
+    /// VTIME in scanlines
     pub(crate) irq_time_v: u16,
-    pub(crate) shall_irq: bool,
     pub(crate) shall_nmi: bool,
+    /// The next vertical blanking NMI was already serviced by hijacking BRK or COP
+    pub(crate) nmi_hijacked: bool,
     pub(crate) nmi_vblank_bit: Cell<bool>,
     pub(crate) math_registers: MathRegisters,
-    pub(crate) is_pal: bool,
+    pub(crate) region: Region,
+    /// Emulation speed relative to real time, this is a frontend setting
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    speed: f32,
+    /// Cheat codes are not part of the emulated state
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    cheats: Cheats,
+    /// Watched address ranges are not part of the emulated state
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    watches: Watches,
+    /// Breakpoints are not part of the emulated state
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    breakpoints: Breakpoints,
+    /// The memory access statistics are a frontend setting
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) heatmap: Option<Box<Heatmap>>,
+    /// The wall clock is provided by the frontend
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    clock: Arc<dyn ClockSource>,
+    /// Messages about emulation events for the user
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    messages: Vec<String>,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    options: DeviceOptions,
+    /// The scanline hook is provided by the frontend
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    scanline_hook: ScanlineHookSlot,
+    /// The idle loop detection is a speed hack, see [`crate::idle_loop`]
+    #[except((|_v, _s| ()), (|v: &mut IdleLoop, _s| v.reset()))]
+    pub(crate) idle_loop: IdleLoop,
+    /// Hardware events recorded for debuggers
+    #[cfg(feature = "trace")]
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) trace: crate::trace::EventTrace,
+    /// Flat 24-bit memory used by the CPU test harness instead of the memory map
+    #[cfg(feature = "cpu-tests")]
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) flat_memory: std::collections::HashMap<u32, u8>,
 }
 
 impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
-    pub fn new(audio_backend: B, frame_buffer: FB, is_pal: bool, is_threaded: bool) -> Self {
+    pub fn new(audio_backend: B, frame_buffer: FB, region: Region, is_threaded: bool) -> Self {
+        Self::new_with_options(
+            audio_backend,
+            frame_buffer,
+            region,
+            is_threaded,
+            DeviceOptions::default(),
+        )
+    }
+
+    pub fn new_with_options(
+        audio_backend: B,
+        frame_buffer: FB,
+        region: Region,
+        is_threaded: bool,
+        options: DeviceOptions,
+    ) -> Self {
+        let is_pal = region.is_pal();
+        let mut ram = [0; RAM_SIZE];
+        options.ram_init.fill(&mut ram);
         Self {
             cpu: Cpu::new(),
             smp: Smp::new(audio_backend, is_pal, is_threaded),
@@ -177,21 +432,227 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
             controllers: ControllerPorts::new(),
             cartridge: None,
             open_bus: 0,
-            ram: [0; RAM_SIZE],
+            ram,
             wram_addr: Cell::new(0),
             memory_cycles: 0,
+            master_cycles: 0,
             cpu_ahead_cycles: 186,
             new_scanline: true,
             new_frame: true,
             scanline_drawn: false,
             do_hdma: true,
-            irq_time_h: 0x7fc,
+            irq_time_h: 0x1ff,
             irq_time_v: 0x1ff,
-            shall_irq: false,
             shall_nmi: false,
+            nmi_hijacked: false,
             nmi_vblank_bit: Cell::new(false),
             math_registers: MathRegisters::new(),
-            is_pal,
+            region,
+            speed: 1.0,
+            cheats: Cheats::default(),
+            watches: Watches::default(),
+            breakpoints: Breakpoints::default(),
+            heatmap: None,
+            clock: Arc::new(SystemClock),
+            messages: Vec::new(),
+            options,
+            scanline_hook: Default::default(),
+            idle_loop: Default::default(),
+            #[cfg(feature = "trace")]
+            trace: Default::default(),
+            #[cfg(feature = "cpu-tests")]
+            flat_memory: Default::default(),
+        }
+    }
+
+    pub const fn region(&self) -> Region {
+        self.region
+    }
+
+    pub const fn options(&self) -> &DeviceOptions {
+        &self.options
+    }
+
+    /// Set the source of the wall clock time, which is the system clock by default.
+    ///
+    /// Use a [`crate::backend::FixedClock`] to make the emulation independent
+    /// of the time it is run at, e.g. for movies and tests.
+    pub fn set_clock_source(&mut self, clock: Arc<dyn ClockSource>) {
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.set_clock_source(clock.clone())
+        }
+        self.clock = clock
+    }
+
+    pub fn clock_source(&self) -> &dyn ClockSource {
+        &*self.clock
+    }
+
+    /// Set a function, which is called at the start of every scanline with
+    /// the number of the scanline, or remove it with `None`.
+    ///
+    /// The scanline 0 starts together with a new frame. The hook is called
+    /// from within [`Self::run_cycle`], so it should return quickly; it is
+    /// meant for effects, latency measurements or captures of the frontend.
+    /// The hook is not part of save states.
+    pub fn set_scanline_hook(&mut self, hook: Option<ScanlineHook>) {
+        self.scanline_hook = ScanlineHookSlot(hook)
+    }
+
+    /// Set the emulation speed relative to real time (e.g. `2.0` for fast-forward).
+    ///
+    /// The frontend is responsible for running the emulation at this speed,
+    /// see [`Region::master_cycles_to_nanos`] and [`Self::speed`].
+    /// The audio output is adjusted by dropping or repeating samples,
+    /// so that it keeps running in real time.
+    ///
+    /// Speeds below [`MIN_SPEED`] and NaN are raised to [`MIN_SPEED`].
+    /// An infinite speed drops all samples.
+    pub fn set_speed(&mut self, speed: f32) {
+        let speed = speed.max(MIN_SPEED);
+        self.speed = speed;
+        self.smp.set_speed(speed);
+    }
+
+    pub const fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub(crate) fn call_scanline_hook(&mut self) {
+        if let Some(hook) = &mut self.scanline_hook.0 {
+            hook(self.ppu.get_pos().y)
+        }
+    }
+
+    /// Queue a message about an emulation event for the user.
+    ///
+    /// Frontends can show the messages e.g. in an on-screen overlay,
+    /// see [`Self::take_messages`]. Only the last [`MAX_MESSAGES`]
+    /// messages are kept.
+    pub fn notify(&mut self, message: impl Into<String>) {
+        if self.messages.len() >= MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+        self.messages.push(message.into())
+    }
+
+    /// Take the queued messages, the oldest message comes first
+    pub fn take_messages(&mut self) -> Vec<String> {
+        core::mem::take(&mut self.messages)
+    }
+
+    pub const fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    /// Add and enable a Game Genie or Pro Action Replay code
+    pub fn add_cheat(&mut self, code: &str) -> Result<CheatId, CheatError> {
+        let id = self.cheats.add(CheatCode::parse(code)?);
+        self.update_read_patches();
+        Ok(id)
+    }
+
+    /// Remove a cheat, `false` is returned if the cheat does not exist
+    pub fn remove_cheat(&mut self, id: CheatId) -> bool {
+        let removed = self.cheats.remove(id).is_some();
+        self.update_read_patches();
+        removed
+    }
+
+    /// Enable or disable a cheat, `false` is returned if the cheat does not exist
+    pub fn set_cheat_enabled(&mut self, id: CheatId, enabled: bool) -> bool {
+        let found = self.cheats.set_enabled(id, enabled);
+        self.update_read_patches();
+        found
+    }
+
+    fn update_read_patches(&mut self) {
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.set_read_patches(self.cheats.read_patches())
+        }
+    }
+
+    /// The address ranges, in which the accesses of [`Self::read`] and [`Self::write`] are recorded
+    pub fn watches_mut(&mut self) -> &mut Watches {
+        &mut self.watches
+    }
+
+    /// The breakpoints and watch expressions of the main CPU, see [`crate::breakpoint`]
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// Evaluate an expression with the current registers and memory
+    pub fn evaluate(&mut self, expr: &Expr) -> u32 {
+        expr.eval(self)
+    }
+
+    pub(crate) fn check_breakpoints(&mut self) {
+        if !self.breakpoints.is_empty() {
+            let pc = self.cpu.regs.pc;
+            let mut breakpoints = core::mem::take(&mut self.breakpoints);
+            breakpoints.check(self, pc);
+            self.breakpoints = breakpoints;
+        }
+    }
+
+    /// Start counting the memory accesses from zero, see [`crate::heatmap`]
+    pub fn enable_heatmap(&mut self) {
+        self.heatmap = Some(Box::default())
+    }
+
+    /// The memory accesses counted since [`Self::enable_heatmap`]
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_deref()
+    }
+
+    /// Stop counting the memory accesses and return the counts
+    pub fn take_heatmap(&mut self) -> Option<Heatmap> {
+        self.heatmap.take().map(|heatmap| *heatmap)
+    }
+
+    /// The contents of a memory region, it is empty if there is no such memory
+    pub fn memory(&self, region: MemoryRegion) -> &[u8] {
+        match region {
+            MemoryRegion::WorkRam => &self.ram,
+            MemoryRegion::Sram => self.cartridge.as_ref().map_or(&[], |cart| cart.sram()),
+        }
+    }
+
+    /// The CPU address of an offset in a memory region, as used by cheat codes.
+    ///
+    /// `None` is returned if the offset is out of range or the memory is not mapped.
+    pub fn memory_addr(&self, region: MemoryRegion, offset: u32) -> Option<Addr24> {
+        match region {
+            MemoryRegion::WorkRam => {
+                (offset < 0x20000).then(|| Addr24::new(0x7e + (offset >> 16) as u8, offset as u16))
+            }
+            MemoryRegion::Sram => self.cartridge.as_ref()?.sram_addr(offset),
+        }
+    }
+
+    /// Copy the contents of a memory region, e.g. for a [`crate::cheats::search::MemorySearch`]
+    pub fn snapshot(&self, region: MemoryRegion) -> MemorySnapshot {
+        MemorySnapshot {
+            region,
+            data: self.memory(region).to_vec(),
+        }
+    }
+
+    /// Write the values of the work RAM cheats, this is done once per frame
+    pub(crate) fn apply_ram_cheats(&mut self) {
+        for (addr, value) in self.cheats.ram_writes() {
+            // the RAM writes are in the banks $7e-$7f or the low 8 KiB of the work RAM
+            let index = if (0x7e..=0x7f).contains(&addr.bank) {
+                ((addr.bank as usize & 1) << 16) | addr.addr as usize
+            } else {
+                addr.addr as usize
+            };
+            self.ram[index] = value
         }
     }
 
@@ -201,6 +662,13 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
         crate::instr::create_device_access(self)
     }
 
+    /// Access the memory like the main CPU, but without side effects
+    pub fn with_debug_access<'a>(
+        &'a mut self,
+    ) -> crate::instr::DeviceAccess<'a, crate::instr::AccessTypeDebug, B, FB> {
+        crate::instr::create_device_access(self)
+    }
+
     pub fn with_sa1_cpu<'a>(
         &'a mut self,
     ) -> crate::instr::DeviceAccess<'a, crate::enhancement::sa1::AccessTypeSa1, B, FB> {
@@ -214,13 +682,99 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
         }
     }
 
+    /// The recorded hardware events, see [`crate::trace`]
+    #[cfg(feature = "trace")]
+    pub fn trace(&self) -> &crate::trace::EventTrace {
+        &self.trace
+    }
+
+    #[cfg(feature = "trace")]
+    pub fn trace_mut(&mut self) -> &mut crate::trace::EventTrace {
+        &mut self.trace
+    }
+
+    #[cfg(feature = "trace")]
+    pub(crate) fn trace_event(&mut self, kind: crate::trace::EventKind) {
+        let pos = self.ppu.get_pos();
+        self.trace.push(pos.y, pos.x >> 2, kind)
+    }
+
     pub fn load_cartridge(&mut self, mut cartridge: Cartridge) {
-        cartridge.set_region(self.is_pal);
+        cartridge.set_region(self.region.is_pal());
+        self.smp.set_expansion_audio(cartridge.expansion_audio());
+        cartridge.set_read_patches(self.cheats.read_patches());
+        cartridge.set_clock_source(self.clock.clone());
         self.cartridge = Some(cartridge);
         self.cpu = Cpu::new();
         self.reset_program_counter();
     }
 
+    /// Reset the console like the reset button does.
+    ///
+    /// The CPU restarts at the reset vector and the registers of all chips
+    /// return to their reset state, but the work RAM, the video RAM and the
+    /// audio RAM keep their contents, which is used by some games to detect
+    /// a reset. The cartridge RAM is always kept.
+    pub fn soft_reset(&mut self) {
+        self.cpu = Cpu::new();
+        self.dma.reset();
+        self.ppu.reset();
+        self.smp.reset();
+        self.controllers.reset();
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.reset();
+            cartridge.set_region(self.region.is_pal());
+        }
+        self.cpu_ahead_cycles = 186;
+        self.shall_nmi = false;
+        self.nmi_hijacked = false;
+        self.nmi_vblank_bit.set(false);
+        self.math_registers = MathRegisters::new();
+        self.idle_loop.reset();
+        if self.cartridge.is_some() {
+            self.reset_program_counter();
+        }
+    }
+
+    /// Switch the console off and on again.
+    ///
+    /// In addition to [`Self::soft_reset`] all memories except the
+    /// cartridge RAM are cleared and the video timing starts at the
+    /// beginning of a frame. The work RAM is filled according to
+    /// [`DeviceOptions::ram_init`].
+    pub fn power_cycle(&mut self) {
+        self.ppu.power_cycle();
+        self.smp.power_cycle();
+        self.dma = Dma::new();
+        self.options.ram_init.fill(&mut self.ram);
+        self.wram_addr.set(0);
+        self.open_bus = 0;
+        self.memory_cycles = 0;
+        self.master_cycles = 0;
+        self.new_scanline = true;
+        self.new_frame = true;
+        self.scanline_drawn = false;
+        self.do_hdma = true;
+        self.irq_time_h = 0x1ff;
+        self.irq_time_v = 0x1ff;
+        self.soft_reset();
+    }
+
+    /// The last value transferred on the data bus (MDR).
+    ///
+    /// Reads of addresses, which are not driven by any chip,
+    /// return this value (open bus).
+    pub const fn last_bus_value(&self) -> u8 {
+        self.open_bus
+    }
+
+    /// The contents of the SRAM file of the cartridge, see [`Cartridge::sram_file`].
+    ///
+    /// Returns `None` if there is no cartridge or it has no battery backed memory.
+    pub fn sram_file(&self) -> Option<Vec<u8>> {
+        Some(self.cartridge.as_ref()?.sram_file()).filter(|data| !data.is_empty())
+    }
+
     pub fn reset_program_counter(&mut self) {
         let addr = crate::cpu::RESET_VECTOR_ADDR;
         self.cpu.regs.pc = Addr24::new(0, self.read::<u16>(addr));
@@ -230,33 +784,131 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
         }
     }
 
+    /// Read a byte of the memory map without side effects, e.g. for debuggers.
+    ///
+    /// I/O registers, which hold a value, like the DMA channel registers,
+    /// the joypad registers or the latched PPU counters, return their value
+    /// as the CPU would read it, but flags are not acknowledged and latches
+    /// are not toggled. The other I/O registers and the registers of
+    /// coprocessors return the open bus value. Neither the open bus value
+    /// nor the master cycles are modified.
+    pub fn peek(&self, addr: Addr24) -> u8 {
+        let value = if (0x7e..=0x7f).contains(&addr.bank) {
+            Some(self.ram[((addr.bank as usize & 1) << 16) | addr.addr as usize])
+        } else if addr.bank & 0x40 == 0 {
+            match addr.addr {
+                0x0000..=0x1fff => Some(self.ram[addr.addr as usize]),
+                0x2100..=0x21ff => self.peek_bus_b(addr.addr as u8),
+                0x4000..=0x43ff => self.peek_internal_register(addr.addr),
+                _ => self.peek_cartridge(addr),
+            }
+        } else {
+            self.peek_cartridge(addr)
+        };
+        value.unwrap_or(self.open_bus)
+    }
+
+    /// Read a register on address bus B without side effects
+    fn peek_bus_b(&self, addr: u8) -> Option<u8> {
+        match addr {
+            0x34..=0x3f => self.ppu.peek_register(addr),
+            // WMDATA without incrementing the WRAM address
+            0x80 => Some(self.ram[self.wram_addr.get() as usize]),
+            // the APU ports are only known after running the APU
+            _ => None,
+        }
+    }
+
+    fn peek_cartridge(&self, addr: Addr24) -> Option<u8> {
+        self.cartridge.as_ref()?.peek_byte(addr)
+    }
+
+    /// Write a byte into the memory map, e.g. for debuggers.
+    ///
+    /// The work RAM and the ROM and RAM of the cartridge are written directly.
+    /// Writes to the I/O registers are ignored, because writing them starts
+    /// transfers or changes state, which is not stored in the register.
+    /// Neither the open bus value nor the master cycles are modified.
+    pub fn poke(&mut self, addr: Addr24, value: u8) {
+        if (0x7e..=0x7f).contains(&addr.bank) {
+            self.ram[((addr.bank as usize & 1) << 16) | addr.addr as usize] = value
+        } else if addr.bank & 0x40 == 0 {
+            match addr.addr {
+                0x0000..=0x1fff => self.ram[addr.addr as usize] = value,
+                0x2100..=0x21ff | 0x4000..=0x43ff => (),
+                _ => self.poke_cartridge(addr, value),
+            }
+        } else {
+            self.poke_cartridge(addr, value)
+        }
+    }
+
+    fn poke_cartridge(&mut self, addr: Addr24, value: u8) {
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.poke_byte(addr, value)
+        }
+    }
+
     /// Read a value from the mapped memory at the specified address.
     /// This method also updates open bus.
     pub fn read<D: Data>(&mut self, addr: Addr24) -> D {
+        self.sync_apu_port_access(addr);
         let value = self.read_data::<D>(addr);
+        if !self.watches.is_empty() {
+            self.watches.record(AccessKind::Read, addr, value)
+        }
+        self.record_heatmap_access::<D>(false, addr);
         self.open_bus = value.to_open_bus();
-        self.memory_cycles +=
-            (self.get_memory_cycle(addr) - 6) * core::mem::size_of::<D::Arr>() as u32;
+        self.memory_cycles += self.get_access_cycles::<D>(addr);
         value
     }
 
     /// Write a value to the mapped memory at the specified address.
     /// This method also updates open bus.
     pub fn write<D: Data>(&mut self, addr: Addr24, value: D) {
+        if !self.watches.is_empty() {
+            self.watches.record(AccessKind::Write, addr, value)
+        }
+        self.record_heatmap_access::<D>(true, addr);
         self.open_bus = value.to_open_bus();
+        self.sync_apu_port_access(addr);
         self.write_data(addr, value);
-        self.memory_cycles +=
-            (self.get_memory_cycle(addr) - 6) * core::mem::size_of::<D::Arr>() as u32;
+        self.memory_cycles += self.get_access_cycles::<D>(addr);
+    }
+
+    fn record_heatmap_access<D: Data>(&mut self, write: bool, addr: Addr24) {
+        if let Some(mut heatmap) = self.heatmap.take() {
+            heatmap.record::<D>(write, addr, |addr| self.get_memory_cycle(addr));
+            self.heatmap = Some(heatmap);
+        }
+    }
+
+    /// The CPU executes an instruction as a whole at its first master cycle,
+    /// so before it accesses an APU port the S-SMP catches up to the master
+    /// cycle of the access within the instruction
+    fn sync_apu_port_access(&mut self, addr: Addr24) {
+        if (addr.bank & 0x40 == 0) && (0x2140..=0x217f).contains(&addr.addr) {
+            self.smp.catch_up(self.memory_cycles)
+        }
     }
 }
 
 impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
     pub fn read_bus_b<D: Data>(&mut self, addr: u8) -> D {
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record_bus_b::<D>(false, addr)
+        }
         let mut data = <D::Arr as Default>::default();
+        // bytes not driven by a register read the previous byte of this access
+        let open_bus = self.open_bus;
 
         for (i, d) in data.as_mut().iter_mut().enumerate() {
             let addr = addr.wrapping_add(i as u8);
             *d = match addr {
+                0x37 if self.controllers.get_pio() & 0x80 == 0 => {
+                    // SLHV only latches if bit 7 of WRIO is set
+                    self.open_bus
+                }
                 0x34..=0x3f => {
                     let val = self.ppu.read_register(addr).unwrap_or(self.open_bus);
                     if addr < 0x3b || addr == 0x3e {
@@ -268,16 +920,29 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
                 }
                 0x40..=0x7f => {
                     // APU Ports 2140h-2143h are mirrored to 2144h..217Fh
-                    self.smp.read_output_port(addr)
+                    let value = self.smp.read_output_port(addr);
+                    #[cfg(feature = "trace")]
+                    self.trace_event(crate::trace::EventKind::ApuRead {
+                        port: addr & 3,
+                        value,
+                    });
+                    value
                 }
                 0x80 => {
                     let res = self.ram[self.wram_addr.get() as usize];
                     self.increment_wram_addr();
                     res
                 }
-                0x00..=0x33 | 0x81..=0xff => self.open_bus,
-            }
+                0x88..=0x9f => self
+                    .cartridge
+                    .as_mut()
+                    .and_then(|cartridge| cartridge.read_bus_b(addr))
+                    .unwrap_or(self.open_bus),
+                0x00..=0x33 | 0x81..=0x87 | 0xa0..=0xff => self.open_bus,
+            };
+            self.open_bus = *d;
         }
+        self.open_bus = open_bus;
         D::from_bytes(&data)
     }
 
@@ -288,18 +953,16 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
     /// This method does not modify open bus.
     /// The master cycles aren't touched either.
     pub fn read_data<D: Data>(&mut self, addr: Addr24) -> D {
-        if (0x7e..=0x7f).contains(&addr.bank) {
-            // address bus A + /WRAM
-            D::parse(
-                &self.ram,
-                ((addr.bank as usize & 1) << 16) | addr.addr as usize,
-            )
-        } else if addr.bank & 0xc0 == 0 || addr.bank & 0xc0 == 0x80 {
-            match addr.addr {
-                0x0000..=0x1fff => {
-                    // address bus A + /WRAM
-                    D::parse(&self.ram, addr.addr as usize)
-                }
+        match BUS_PAGES[page_index(addr)] {
+            BusPage::Wram(start) => {
+                // address bus A + /WRAM
+                D::parse(&self.ram, start as usize | (addr.addr & 0x1fff) as usize)
+            }
+            BusPage::Cartridge => {
+                // cartridge read of $xy:$6000-$FFFF or bank $40-$7D or $C0-$FF
+                self.read_cartridge(addr)
+            }
+            BusPage::Io => match addr.addr {
                 (0x2000..=0x20ff) | (0x2200..=0x3fff) | (0x4400..=0x5fff) => {
                     // address bus A
                     // TODO: should there always be a cartridge access done?
@@ -313,29 +976,25 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
                     // internal CPU registers
                     // see https://wiki.superfamicom.org/registers
                     let mut data = <D::Arr as Default>::default();
+                    let open_bus = self.open_bus;
                     for (i, d) in data.as_mut().iter_mut().enumerate() {
                         *d = self
                             .read_internal_register(addr.addr.wrapping_add(i as u16))
-                            .unwrap_or(self.open_bus)
+                            .unwrap_or(self.open_bus);
+                        self.open_bus = *d;
                     }
+                    self.open_bus = open_bus;
                     D::from_bytes(&data)
                 }
-                0x6000..=0xffff => {
-                    // cartridge read on region ($30-$3f):$6000-$7fff or $xy:$8000-$FFFF
-                    self.read_cartridge(addr)
-                }
-            }
-        } else {
-            // cartridge read of bank $40-$7D or $C0-$FF
-            self.read_cartridge(addr)
+                0x0000..=0x1fff | 0x6000..=0xffff => unreachable!(),
+            },
         }
     }
 
     fn read_cartridge<D: Data>(&mut self, addr: Addr24) -> D {
         self.cartridge
             .as_mut()
-            .unwrap()
-            .read(addr)
+            .and_then(|cartridge| cartridge.read(addr))
             .unwrap_or_else(|| D::from_open_bus(self.open_bus))
     }
 
@@ -344,11 +1003,25 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
     }
 
     pub fn write_bus_b<D: Data>(&mut self, addr: u8, value: D) {
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record_bus_b::<D>(true, addr)
+        }
         for (i, d) in value.to_bytes().as_ref().iter().enumerate() {
             let addr = addr.wrapping_add(i as u8);
             match addr {
-                0x00..=0x33 => self.ppu.write_register(addr, *d),
-                0x40..=0x7f => self.smp.write_input_port(addr, *d),
+                0x00..=0x33 => {
+                    #[cfg(feature = "trace")]
+                    self.trace_event(crate::trace::EventKind::PpuWrite { addr, value: *d });
+                    self.ppu.write_register(addr, *d)
+                }
+                0x40..=0x7f => {
+                    #[cfg(feature = "trace")]
+                    self.trace_event(crate::trace::EventKind::ApuWrite {
+                        port: addr & 3,
+                        value: *d,
+                    });
+                    self.smp.write_input_port(addr, *d)
+                }
                 0x80 => {
                     self.ram[(self.wram_addr.get() & 0x1ffff) as usize] = *d;
                     self.increment_wram_addr();
@@ -362,7 +1035,12 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
                 0x83 => self
                     .wram_addr
                     .set((self.wram_addr.get() & 0xffff) | (u32::from(*d & 1) << 16)),
-                0x34..=0x3f | 0x84..=0xff => (),
+                0x88..=0x9f => {
+                    if let Some(cartridge) = &mut self.cartridge {
+                        cartridge.write_bus_b(addr, *d)
+                    }
+                }
+                0x34..=0x3f | 0x84..=0x87 | 0xa0..=0xff => (),
             }
         }
     }
@@ -374,18 +1052,19 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
     /// This method does not modify open bus
     /// The master cycles aren't touched either.
     pub fn write_data<D: Data>(&mut self, addr: Addr24, value: D) {
-        if (0x7e..=0x7f).contains(&addr.bank) {
-            // address bus A + /WRAM
-            value.write_to(
-                &mut self.ram,
-                ((addr.bank as usize & 1) << 16) | addr.addr as usize,
-            )
-        } else if addr.bank & 0xc0 == 0 || addr.bank & 0xc0 == 0x80 {
-            match addr.addr {
-                0x0000..=0x1fff => {
-                    // address bus A + /WRAM
-                    value.write_to(&mut self.ram, addr.addr as usize)
-                }
+        match BUS_PAGES[page_index(addr)] {
+            BusPage::Wram(start) => {
+                // address bus A + /WRAM
+                value.write_to(
+                    &mut self.ram,
+                    start as usize | (addr.addr & 0x1fff) as usize,
+                )
+            }
+            BusPage::Cartridge => {
+                // cartridge write of $xy:$6000-$FFFF or bank $40-$7D or $C0-$FF
+                self.write_cartridge(addr, value)
+            }
+            BusPage::Io => match addr.addr {
                 (0x2000..=0x20ff) | (0x2200..=0x3fff) | (0x4400..=0x5fff) => {
                     // address bus A
                     // TODO: should there always be a cartridge access done?
@@ -402,18 +1081,17 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
                         self.write_internal_register(addr.addr.wrapping_add(i as u16), *d)
                     }
                 }
-                0x6000..=0xffff => {
-                    // cartridge read of bank $40-$7D or $C0-$FF
-                    self.write_cartridge(addr, value)
-                }
-            }
-        } else {
-            // cartridge read of bank $40-$7D or $C0-$FF
-            self.write_cartridge(addr, value)
+                0x0000..=0x1fff | 0x6000..=0xffff => unreachable!(),
+            },
         }
     }
 
     fn write_cartridge<D: Data>(&mut self, addr: Addr24, value: D) {
-        self.cartridge.as_mut().unwrap().write(addr, value)
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.write(addr, value)
+        }
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,824 @@
+use super::*;
+use crate::breakpoint::{BreakpointHit, WatchChange};
+use crate::test_utils::{
+    new_cartridge, new_cartridge_with_vectors, new_device, new_device_with_options, TestDevice,
+};
+
+/// Put a value on the data bus by reading it from the work RAM
+fn drive_bus(device: &mut TestDevice, value: u8) {
+    device.poke(Addr24::new(0x7e, 0x1234), value);
+    assert_eq!(device.read::<u8>(Addr24::new(0x7e, 0x1234)), value);
+}
+
+#[test]
+fn unmapped_reads_return_bus_value() {
+    let mut device = new_device();
+    drive_bus(&mut device, 0xa5);
+    assert_eq!(device.last_bus_value(), 0xa5);
+    for addr in [0x2000, 0x2100, 0x2181, 0x4000, 0x4100, 0x5000, 0x8000] {
+        assert_eq!(device.read::<u8>(Addr24::new(0x00, addr)), 0xa5);
+    }
+    assert_eq!(device.read::<u8>(Addr24::new(0x40, 0x0000)), 0xa5);
+    assert_eq!(device.read::<u16>(Addr24::new(0x00, 0x4000)), 0xa5a5);
+}
+
+#[test]
+fn dma_registers_are_not_mirrored() {
+    let mut device = new_device();
+    device.write::<u8>(Addr24::new(0x00, 0x4300), 0x12);
+    device.write::<u8>(Addr24::new(0x00, 0x430b), 0x77);
+    drive_bus(&mut device, 0x34);
+    assert_eq!(device.read::<u8>(Addr24::new(0x00, 0x4380)), 0x34);
+    assert_eq!(device.read::<u8>(Addr24::new(0x00, 0x4300)), 0x12);
+    // the undriven second byte keeps the first byte on the bus
+    drive_bus(&mut device, 0x34);
+    assert_eq!(device.read::<u16>(Addr24::new(0x00, 0x430b)), 0x7777);
+    assert_eq!(device.last_bus_value(), 0x77);
+}
+
+#[test]
+fn partially_driven_registers() {
+    let mut device = new_device();
+    drive_bus(&mut device, 0xff);
+    assert_eq!(device.read::<u8>(Addr24::new(0x00, 0x4017)) & 0xfc, 0xfc);
+    drive_bus(&mut device, 0x00);
+    assert_eq!(device.read::<u8>(Addr24::new(0x00, 0x4017)) & 0xfc, 0x1c);
+    drive_bus(&mut device, 0xff);
+    assert_eq!(device.read::<u8>(Addr24::new(0x00, 0x4211)) & 0x7f, 0x7f);
+}
+
+#[test]
+fn memory_addresses() {
+    let device = new_device();
+    assert_eq!(
+        device.memory_addr(MemoryRegion::WorkRam, 0x1_0dbe),
+        Some(Addr24::new(0x7f, 0x0dbe))
+    );
+    assert_eq!(device.memory_addr(MemoryRegion::WorkRam, 0x2_0000), None);
+    assert_eq!(device.memory_addr(MemoryRegion::Sram, 0), None);
+}
+
+#[test]
+fn extreme_speeds() {
+    let mut device = new_device();
+    device.load_cartridge(new_cartridge(&[0x80, 0xfe], &[]));
+    for speed in [f32::MIN_POSITIVE, 0.0, -1.0, f32::NAN] {
+        device.set_speed(speed);
+        assert_eq!(device.speed(), MIN_SPEED);
+        device.run_cycle::<{ 1364 * 8 }>();
+    }
+    device.set_speed(f32::INFINITY);
+    assert_eq!(device.speed(), f32::INFINITY);
+    device.run_cycle::<{ 1364 * 8 }>();
+}
+
+#[test]
+fn peek_registers_without_side_effects() {
+    let mut device = new_device();
+    device.write::<u8>(Addr24::new(0x00, 0x4352), 0x12);
+    assert_eq!(device.peek(Addr24::new(0x00, 0x4352)), 0x12);
+    device.poke(Addr24::new(0x00, 0x4352), 0x34);
+    assert_eq!(device.peek(Addr24::new(0x00, 0x4352)), 0x12);
+
+    // the NMI flag is only acknowledged by reading it
+    device.nmi_vblank_bit.set(true);
+    assert_eq!(device.peek(Addr24::new(0x00, 0x4210)) & 0x80, 0x80);
+    assert_eq!(device.peek(Addr24::new(0x00, 0x4210)) & 0x80, 0x80);
+    assert_eq!(device.read::<u8>(Addr24::new(0x00, 0x4210)) & 0x80, 0x80);
+    assert_eq!(device.peek(Addr24::new(0x00, 0x4210)) & 0x80, 0);
+
+    // peeking the latched counter doesn't toggle between its bytes
+    device.write::<u8>(Addr24::new(0x00, 0x4201), 0x80);
+    device.read::<u8>(Addr24::new(0x00, 0x2137));
+    let low = device.peek(Addr24::new(0x00, 0x213c));
+    assert_eq!(device.peek(Addr24::new(0x00, 0x213c)), low);
+    assert_eq!(device.read::<u8>(Addr24::new(0x00, 0x213c)), low);
+    let high = device.peek(Addr24::new(0x00, 0x213c));
+    assert_eq!(device.read::<u8>(Addr24::new(0x00, 0x213c)), high);
+    assert_eq!(device.peek(Addr24::new(0x00, 0x213f)) & 0x40, 0x40);
+    assert_eq!(device.peek(Addr24::new(0x00, 0x213f)) & 0x40, 0x40);
+}
+
+#[test]
+fn memory_access_speeds() {
+    let mut device = new_device();
+    device.load_cartridge(new_cartridge(
+        &[
+            0xea, // nop
+            0xa9, 0x01, // lda #$01
+            0x8d, 0x0d, 0x42, // sta $420d
+            0x5c, 0x00, 0x90, 0x80, // jml $809000
+        ],
+        &[
+            0xea, // nop
+            0xad, 0x16, 0x40, // lda $4016
+            0xaf, 0x00, 0x00, 0x7e, // lda $7e0000
+            0xad, 0x00, 0x21, // lda $2100
+        ],
+    ));
+    let cycles: Vec<_> = (0..8)
+        .map(|_| device.step_cpu_instruction().unwrap().cycles)
+        .collect();
+    assert_eq!(cycles, [14, 16, 30, 32, 12, 30, 32, 24]);
+}
+
+#[test]
+fn wram_refresh_pauses_cpu() {
+    let mut device = new_device();
+    // `bra` to itself
+    device.load_cartridge(new_cartridge(&[0x80, 0xfe], &[]));
+    // the CPU starts after the reset delay
+    device.step_cpu_instruction();
+    let start = (device.master_cycles, device.ppu.get_pos().y);
+    let mut cpu_cycles = 0;
+    while device.ppu.get_pos().y < start.1 + 10 {
+        cpu_cycles += u64::from(device.step_cpu_instruction().unwrap().cycles);
+    }
+    let paused = device.master_cycles - start.0 - cpu_cycles;
+    assert!((9 * 40..=11 * 40).contains(&paused), "paused {}", paused);
+}
+
+#[test]
+fn auto_joypad_read_timing() {
+    use crate::controller::ButtonState;
+    let mut device = new_device();
+    device.load_cartridge(new_cartridge(&[0x80, 0xfe], &[]));
+    assert!(device
+        .controllers
+        .set_buttons(0, 0, ButtonState(0xc0f0u16.reverse_bits())));
+    // enable the automatic joypad read
+    device.write::<u8>(Addr24::new(0x00, 0x4200), 0x01);
+    let busy = |device: &mut TestDevice| device.read::<u8>(Addr24::new(0x00, 0x4212)) & 1 > 0;
+    while !busy(&mut device) {
+        device.step_cpu_instruction().unwrap();
+    }
+    assert_eq!(device.ppu.get_pos().y, device.ppu.vend());
+    let start = device.master_cycles;
+    let mut partial = false;
+    while busy(&mut device) {
+        let data = device.read::<u16>(Addr24::new(0x00, 0x4218));
+        partial |= data != 0 && data != 0xc0f0;
+        device.step_cpu_instruction().unwrap();
+    }
+    assert!(partial);
+    // the busy flag is only sampled between two instructions
+    let cycles = device.master_cycles - start;
+    assert!((4224 - 40..4224 + 40).contains(&cycles), "took {}", cycles);
+    assert_eq!(device.read::<u16>(Addr24::new(0x00, 0x4218)), 0xc0f0);
+    assert_eq!(device.peek(Addr24::new(0x00, 0x4218)), 0xf0);
+    assert_eq!(device.peek(Addr24::new(0x80, 0x4219)), 0xc0);
+}
+
+#[test]
+fn scanline_hook() {
+    use std::sync::{Arc, Mutex};
+    let mut device = new_device();
+    device.load_cartridge(new_cartridge(&[0x80, 0xfe], &[]));
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let lines_ref = lines.clone();
+    device.set_scanline_hook(Some(Box::new(move |y| lines_ref.lock().unwrap().push(y))));
+    device.run_cycle::<2>();
+    while !device.is_at_frame_boundary() {
+        device.run_cycle::<2>();
+    }
+    let expected: Vec<u16> = (1..262).chain([0]).collect();
+    assert_eq!(*lines.lock().unwrap(), expected);
+    device.set_scanline_hook(None);
+    device.run_cycle::<2>();
+    while !device.is_at_frame_boundary() {
+        device.run_cycle::<2>();
+    }
+    assert_eq!(lines.lock().unwrap().len(), 262);
+}
+
+#[test]
+fn interrupt_flag_delay() {
+    use crate::cpu::{Interrupt, Status};
+    let mut device = new_device();
+    // cli, sei
+    device.load_cartridge(new_cartridge_with_vectors(
+        &[0x58, 0x78],
+        &[],
+        [0xa000, 0xb000],
+    ));
+    let step = |device: &mut TestDevice| device.step_cpu_instruction().unwrap().interrupt;
+    assert_eq!(step(&mut device), None);
+    device.cpu.irq_bit = 0x80;
+    // the IRQ is polled before CLI and SEI change the flag
+    assert_eq!(step(&mut device), None);
+    assert_eq!(step(&mut device), Some(Interrupt::Irq));
+    assert!(device.cpu.regs.status.has(Status::IRQ_DISABLE));
+    assert_eq!(device.cpu.regs.pc.addr, 0xb001);
+    // in emulation mode the pushed status tells the IRQ apart from BRK
+    let status = device.peek(Addr24::new(0, device.cpu.regs.sp.wrapping_add(1)));
+    assert_eq!(status & Status::BREAK.0, 0);
+}
+
+#[test]
+fn nmi_hijacks_brk() {
+    use crate::cpu::Interrupt;
+    let mut device = new_device();
+    // lda #$80, sta $4200 and a brk at $00:9000
+    device.load_cartridge(new_cartridge_with_vectors(
+        &[0xa9, 0x80, 0x8d, 0x00, 0x42],
+        &[0x00, 0x00],
+        [0xa000, 0xb000],
+    ));
+    let vend = device.ppu.vend();
+    loop {
+        device.step_cpu_instruction().unwrap();
+        let pos = *device.ppu.get_pos();
+        if pos.y + 1 == vend && pos.x + 20 >= device.ppu.get_scanline_cycles() {
+            break;
+        }
+    }
+    device.cpu.regs.pc = Addr24::new(0, 0x9000);
+    // the NMI is requested while the BRK pushes the status
+    let brk = device.step_cpu_instruction().unwrap();
+    assert_eq!(brk.interrupt, None);
+    assert_eq!(device.cpu.regs.pc.addr, 0xa000);
+    assert!(device.cpu.in_nmi);
+    // the hijacking NMI is not serviced again
+    assert_ne!(
+        device.step_cpu_instruction().unwrap().interrupt,
+        Some(Interrupt::Nmi)
+    );
+    assert_eq!(device.cpu.regs.pc.addr, 0xa001);
+}
+
+#[test]
+fn interruptible_block_move() {
+    use crate::cpu::Interrupt;
+    let mut device = new_device();
+    device.load_cartridge(new_cartridge(
+        &[
+            0x18, 0xfb, // clc, xce
+            0xc2, 0x30, // rep #$30
+            0x58, // cli
+            0xa2, 0x00, 0x00, // ldx #$0000
+            0xa0, 0x00, 0x01, // ldy #$0100
+            0xa9, 0x03, 0x00, // lda #$0003
+            0x54, 0x7e, 0x7e, // mvn $7e,$7e
+        ],
+        &[],
+    ));
+    for _ in 0..7 {
+        device.step_cpu_instruction().unwrap();
+    }
+    // a byte costs 7 cycles, the ROM and work RAM accesses take 2 more master cycles
+    let step = device.step_cpu_instruction().unwrap();
+    assert_eq!(step.cycles, 7 * 6 + 5 * 2);
+    assert_eq!(device.cpu.regs.pc.addr, 0x800e);
+    assert_eq!(device.cpu.regs.a, 2);
+    assert_eq!(device.cpu.regs.db, 0x7e);
+    // an IRQ is serviced between two bytes and returns to the block move
+    device.cpu.irq_bit = 0x80;
+    let step = device.step_cpu_instruction().unwrap();
+    assert_eq!(step.interrupt, Some(Interrupt::Irq));
+    let sp = device.cpu.regs.sp;
+    let ret = [2, 3].map(|i| device.peek(Addr24::new(0, sp.wrapping_add(i))));
+    assert_eq!(u16::from_le_bytes(ret), 0x800e);
+    assert_eq!(device.cpu.regs.a, 2);
+}
+
+#[test]
+fn idle_cycles_are_skipped() {
+    use crate::cpu::Interrupt;
+    let mut device = new_device();
+    // lda #$80, sta $4200, wai, stp and a rti at $00:9000
+    device.load_cartridge(new_cartridge_with_vectors(
+        &[0xa9, 0x80, 0x8d, 0x00, 0x42, 0xcb, 0xdb],
+        &[0x40],
+        [0x9000, 0xeaea],
+    ));
+    for _ in 0..3 {
+        device.step_cpu_instruction().unwrap();
+    }
+    assert!(device.cpu.wait_mode);
+    let start = device.master_cycles;
+    let mut calls = 0;
+    while device.cpu.wait_mode {
+        device.run_cycle::<2>();
+        calls += 1;
+    }
+    // the CPU wakes up at the start of the vertical blank
+    assert_eq!(device.ppu.get_pos().y, device.ppu.vend());
+    assert!(calls * 2 * 16 < device.master_cycles - start);
+    let step = device.step_cpu_instruction().unwrap();
+    assert_eq!(step.interrupt, Some(Interrupt::Nmi));
+    assert_eq!(device.cpu.regs.pc.addr, 0x8006);
+    device.step_cpu_instruction();
+    assert!(!device.cpu.active);
+    // a stopped CPU skips until the next event as well
+    let start = device.master_cycles;
+    let mut calls = 0;
+    device.run_cycle::<2>();
+    while !device.is_at_frame_boundary() {
+        device.run_cycle::<2>();
+        calls += 1;
+    }
+    assert!(calls * 2 * 16 < device.master_cycles - start);
+    assert!(!device.cpu.active);
+}
+
+#[test]
+fn upcoming_events() {
+    use crate::timing::Event;
+    let mut device = new_device();
+    device.load_cartridge(new_cartridge_with_vectors(
+        &[0xdb],
+        &[0x40],
+        [0x9000, 0x9000],
+    ));
+    device.step_cpu_instruction();
+    // an H-IRQ at the dot $40 of every scanline
+    device.cpu.nmitimen = 0x10;
+    device.irq_time_h = 0x40;
+    device.run_cycle::<2>();
+    while !device.new_scanline {
+        device.run_cycle::<2>();
+    }
+    device.cpu.irq_bit = 0;
+    let scheduler = device.upcoming_events::<2>();
+    let first = scheduler.peek().unwrap();
+    let events = scheduler.into_sorted_vec();
+    assert_eq!(events[0], first);
+    assert!(events.windows(2).all(|pair| pair[0] <= pair[1]));
+    let start = device.master_cycles();
+    let x = u64::from(device.ppu.get_pos().x);
+    let line_end = events
+        .iter()
+        .find(|event| event.event == Event::ScanlineEnd)
+        .unwrap();
+    let line_length = u64::from(device.ppu.get_scanline_cycles());
+    assert_eq!(line_end.master_cycle, start + line_length - 2 - x);
+    let timer_irq = *events
+        .iter()
+        .find(|event| event.event == Event::TimerIrq)
+        .unwrap();
+    assert_eq!(timer_irq.master_cycle, start + 0x100 + 14 - x);
+    // the IRQ is raised by the cycles containing the scheduled master cycle
+    while device.cpu.irq_bit == 0 {
+        assert!(device.master_cycles() <= timer_irq.master_cycle);
+        device.run_cycle::<2>();
+    }
+    assert!(device.master_cycles() > timer_irq.master_cycle);
+    assert!(device
+        .upcoming_events::<2>()
+        .into_sorted_vec()
+        .iter()
+        .all(|event| event.event != Event::TimerIrq));
+}
+
+#[test]
+fn conditional_breakpoints() {
+    use crate::breakpoint::expr::Expr;
+    let mut device = new_device();
+    device.load_cartridge(new_cartridge(
+        &[
+            0xe6, 0x10, // inc $10
+            0xa5, 0x10, // lda $10
+            0x80, 0xfa, // bra $8000
+        ],
+        &[],
+    ));
+    let breakpoints = device.breakpoints_mut();
+    let hit = breakpoints.add(
+        Some(Addr24::new(0, 0x8004)),
+        Some(Expr::parse("a == 3").unwrap()),
+    );
+    let watch = breakpoints.add_watch_expr(Expr::parse("[$7e0010]").unwrap());
+    let mut instructions = 0;
+    while !device.breakpoints().has_hits() {
+        device.step_cpu_instruction().unwrap();
+        instructions += 1;
+    }
+    assert_eq!(instructions, 8);
+    let pc = Addr24::new(0, 0x8004);
+    assert_eq!(
+        device.breakpoints_mut().take_hits(),
+        [BreakpointHit { id: hit, pc }]
+    );
+    assert_eq!(device.evaluate(&Expr::parse("a + x").unwrap()), 3);
+    let changes = device.breakpoints_mut().take_changes();
+    let change = |old, new| WatchChange {
+        id: watch,
+        pc: Addr24::new(0, 0x8002),
+        old,
+        new,
+    };
+    // the first value after the first instruction is no change
+    assert_eq!(changes, [change(1, 2), change(2, 3)]);
+    assert_eq!(device.breakpoints().watch_value(watch), Some(3));
+    // the emulation continues after the hit without hitting again immediately
+    device.step_cpu_instruction().unwrap();
+    assert!(!device.breakpoints().has_hits());
+}
+
+#[cfg(feature = "call-stack")]
+#[test]
+fn call_stack() {
+    use crate::cpu::{CallFrame, CallKind};
+    let mut device = new_device();
+    device.load_cartridge(new_cartridge(
+        &[
+            0x18, // clc
+            0xfb, // xce
+            0xc2, 0x10, // rep #$10
+            0x20, 0x00, 0x90, // jsr $9000
+            0xdb, // stp
+        ],
+        &[
+            0x22, 0x10, 0x90, 0x00, // jsl $009010
+            0x60, // rts
+            0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, // nop
+            0xba, // tsx
+            0xe8, 0xe8, 0xe8, // inx
+            0x9a, // txs
+            0x60, // rts
+        ],
+    ));
+    let mut step = |count| {
+        for _ in 0..count {
+            device.step_cpu_instruction().unwrap();
+        }
+        device.cpu.call_stack().to_vec()
+    };
+    let sp = 0x100;
+    let jsr = CallFrame {
+        kind: CallKind::Jsr,
+        call_site: Addr24::new(0, 0x8004),
+        target: Addr24::new(0, 0x9000),
+        sp: sp - 2,
+        reliable: true,
+    };
+    let jsl = CallFrame {
+        kind: CallKind::Jsl,
+        call_site: Addr24::new(0, 0x9000),
+        target: Addr24::new(0, 0x9010),
+        sp: sp - 5,
+        reliable: true,
+    };
+    assert_eq!(step(4), [jsr]);
+    assert_eq!(step(1), [jsr, jsl]);
+    // the return address of JSL is discarded by moving the stack pointer
+    let discarded = CallFrame {
+        reliable: false,
+        ..jsl
+    };
+    assert_eq!(step(5), [jsr, discarded]);
+    // RTS returns from both
+    assert_eq!(step(1), []);
+    assert_eq!(device.cpu.regs.pc, Addr24::new(0, 0x8007));
+}
+
+#[test]
+fn idle_loop_skipping() {
+    let run = |skip_idle_loops| {
+        let options = DeviceOptions {
+            skip_idle_loops,
+            ..Default::default()
+        };
+        let mut device = new_device_with_options(options);
+        // wait in a loop until the NMI handler at $00:9000 increments $10
+        device.load_cartridge(new_cartridge_with_vectors(
+            &[
+                0xa9, 0x80, // lda #$80
+                0x8d, 0x00, 0x42, // sta $4200
+                0xa5, 0x10, // lda $10
+                0xf0, 0xfc, // beq $8005
+                0xdb, // stp
+            ],
+            &[
+                0xe6, 0x10, // inc $10
+                0x40, // rti
+            ],
+            [0x9000, 0xeaea],
+        ));
+        let mut calls = 0u64;
+        while device.cpu.active {
+            device.run_cycle::<2>();
+            calls += 1;
+        }
+        (calls, device.ppu.get_pos().y)
+    };
+    let (accurate_calls, accurate_line) = run(false);
+    let (calls, line) = run(true);
+    // the loop ends in the same scanline, but most of its cycles are skipped
+    assert_eq!(line, accurate_line);
+    assert!(calls * 16 < accurate_calls);
+}
+
+#[test]
+fn save_state_at_frame_boundary() {
+    use crate::controller::ButtonState;
+    let mut device = new_device();
+    device.load_cartridge(new_cartridge(&[0x80, 0xfe], &[]));
+    assert!(device.is_at_frame_boundary());
+    device
+        .controllers
+        .set_buttons(0, 0, ButtonState(0x8000u16.reverse_bits()));
+    device.write::<u8>(Addr24::new(0x00, 0x4200), 0x01);
+    // stop in the middle of the automatic joypad read
+    while device.read::<u8>(Addr24::new(0x00, 0x4212)) & 1 == 0 {
+        device.step_cpu_instruction().unwrap();
+    }
+    assert!(!device.is_at_frame_boundary());
+    let state = device.serialize_at_frame_boundary();
+    assert!(device.is_at_frame_boundary());
+    assert_eq!(device.ppu.get_pos().y, 0);
+
+    let mut restored = new_device();
+    restored.load_cartridge(new_cartridge(&[0x80, 0xfe], &[]));
+    save_state::deserialize_checked(&mut *restored, &state).unwrap();
+    assert!(restored.is_at_frame_boundary());
+    assert_eq!(restored.master_cycles, device.master_cycles);
+    assert_eq!(restored.read::<u16>(Addr24::new(0x00, 0x4218)), 0x8000);
+}
+
+#[test]
+fn stopped_cpu_message() {
+    let mut device = new_device();
+    // `stp`
+    device.load_cartridge(new_cartridge(&[0xdb], &[]));
+    for i in 0..MAX_MESSAGES + 1 {
+        device.notify(format!("message {}", i));
+    }
+    assert_eq!(device.take_messages().first().unwrap(), "message 1");
+    assert!(device.step_cpu_instruction().is_some());
+    assert!(device.step_cpu_instruction().is_none());
+    assert_eq!(device.take_messages(), ["CPU stopped"]);
+    assert!(device.take_messages().is_empty());
+}
+
+#[test]
+fn soft_reset_and_power_cycle() {
+    let mut device = new_device();
+    // `lda #$42`, `sta $0010`, `stp`
+    device.load_cartridge(new_cartridge(&[0xa9, 0x42, 0x8d, 0x10, 0x00, 0xdb], &[]));
+    while device.step_cpu_instruction().is_some() {}
+    assert_eq!(device.peek(Addr24::new(0x7e, 0x0010)), 0x42);
+    device.poke(Addr24::new(0x7e, 0x0010), 0);
+
+    device.soft_reset();
+    assert_eq!(device.cpu.regs.pc, Addr24::new(0, 0x8000));
+    assert!(device.step_cpu_instruction().is_some());
+    device.poke(Addr24::new(0x7f, 0x1234), 0x55);
+    while device.step_cpu_instruction().is_some() {}
+    assert_eq!(device.peek(Addr24::new(0x7e, 0x0010)), 0x42);
+
+    device.soft_reset();
+    assert_eq!(device.peek(Addr24::new(0x7f, 0x1234)), 0x55);
+    device.power_cycle();
+    assert_eq!(device.peek(Addr24::new(0x7e, 0x0010)), 0);
+    assert_eq!(device.peek(Addr24::new(0x7f, 0x1234)), 0);
+    assert_eq!(device.cpu.regs.pc, Addr24::new(0, 0x8000));
+    assert_eq!(device.master_cycles, 0);
+}
+
+#[test]
+fn ram_init_policies() {
+    let new_device = |ram_init| {
+        let options = DeviceOptions {
+            ram_init,
+            ..Default::default()
+        };
+        new_device_with_options(options)
+    };
+    let dump = |device: &mut TestDevice| -> Vec<u8> {
+        (0..0x100)
+            .map(|i| device.peek(Addr24::new(0x7e, i)))
+            .collect()
+    };
+
+    let mut device = new_device(RamInit::Pattern);
+    assert_eq!(dump(&mut device)[..4], [0x55, 0xaa, 0x55, 0xaa]);
+    assert_eq!(device.peek(Addr24::new(0x7f, 0xffff)), 0xaa);
+
+    let first = dump(&mut new_device(RamInit::Random(1)));
+    let mut device = new_device(RamInit::Random(1));
+    assert_eq!(dump(&mut device), first);
+    assert_ne!(dump(&mut new_device(RamInit::Random(2))), first);
+    assert!(first.iter().any(|&byte| byte != first[0]));
+
+    device.load_cartridge(new_cartridge(&[0xdb], &[]));
+    device.poke(Addr24::new(0x7e, 0), !first[0]);
+    device.power_cycle();
+    assert_eq!(dump(&mut device), first);
+}
+
+#[test]
+fn apu_sync_cycles() {
+    let stop_cycles = |apu_sync_cycles| {
+        let options = DeviceOptions {
+            apu_sync_cycles,
+            ..Default::default()
+        };
+        let mut device = new_device_with_options(options);
+        // wait for the IPL ROM to be ready
+        device.load_cartridge(new_cartridge(
+            &[
+                0xad, 0x40, 0x21, // lda $2140
+                0xc9, 0xaa, // cmp #$aa
+                0xd0, 0xf9, // bne $8000
+                0xdb, // stp
+            ],
+            &[],
+        ));
+        while device.step_cpu_instruction().is_some() {}
+        assert!(!device.cpu.active);
+        device.master_cycles
+    };
+    let cycles = stop_cycles(DEFAULT_APU_SYNC_CYCLES);
+    // the S-SMP catches up on port accesses, so the timing is the same
+    for apu_sync_cycles in [1, 32, 512, 1 << 20] {
+        assert_eq!(stop_cycles(apu_sync_cycles), cycles);
+    }
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn trace_events() {
+    use crate::trace::{Category, EventKind};
+    let mut device = new_device();
+    // `lda #$0f`, `sta $2100`, `lda #$81`, `sta $4200`, `wai`
+    let code = [
+        0xa9, 0x0f, 0x8d, 0x00, 0x21, 0xa9, 0x81, 0x8d, 0x00, 0x42, 0xcb,
+    ];
+    device.load_cartridge(new_cartridge(&code, &[]));
+    for category in Category::ALL {
+        device.trace_mut().set_enabled(category, true)
+    }
+    for _ in 0..6 {
+        device.step_cpu_instruction();
+    }
+    let events = device.trace_mut().take_events();
+    assert_eq!(
+        events[0].kind,
+        EventKind::PpuWrite {
+            addr: 0,
+            value: 0x0f
+        }
+    );
+    let nmi = events.iter().find(|event| event.kind == EventKind::Nmi);
+    assert_eq!(nmi.unwrap().time.scanline, 225);
+}
+
+#[test]
+fn invalid_save_state() {
+    let mut device = new_device();
+    device.load_cartridge(new_cartridge(&[0x80, 0xfe], &[]));
+    device.poke(Addr24::new(0x7e, 0x0010), 0x11);
+    let mut state = vec![];
+    save_state::serialize_in_place(&*device, &mut state);
+    device.poke(Addr24::new(0x7e, 0x0010), 0x22);
+    let result = save_state::deserialize_checked(&mut *device, &state[..state.len() / 2]);
+    assert_eq!(result, Err(save_state::DeserializeError::NotEnoughData));
+    assert_eq!(device.peek(Addr24::new(0x7e, 0x0010)), 0x22);
+    assert_eq!(
+        save_state::deserialize_checked(&mut *device, &state),
+        Ok(())
+    );
+    assert_eq!(device.peek(Addr24::new(0x7e, 0x0010)), 0x11);
+}
+
+#[test]
+fn set_buttons() {
+    use crate::controller::{buttons, ButtonState, Controller, ControllerPort, StandardController};
+    let mut device = new_device();
+    let mut state = ButtonState::default();
+    state.set(buttons::A | buttons::START, true);
+    state.set(buttons::START, false);
+    assert!(state.is_pressed(buttons::A) && !state.is_pressed(buttons::START));
+    assert!(device.controllers.set_buttons(0, 0, state));
+    assert_eq!(device.controllers.buttons(0, 0), Some(state));
+    assert!(!device.controllers.set_buttons(0, 1, state));
+    assert!(!device.controllers.set_buttons(2, 0, state));
+    // the second port is empty until a controller is plugged in
+    assert!(!device.controllers.set_buttons(1, 0, state));
+    device.controllers.port2 = ControllerPort::new(Controller::Standard(StandardController::new()));
+    assert_eq!(device.controllers.buttons(1, 0), Some(ButtonState(0)));
+    assert!(device.controllers.set_buttons(1, 0, state));
+    assert_eq!(device.controllers.buttons(1, 0), Some(state));
+}
+
+/// The code, which writes the values into the registers with `lda #value`, `sta register`
+fn register_writes(writes: &[(u16, u8)]) -> Vec<u8> {
+    writes
+        .iter()
+        .flat_map(|&(reg, value)| [0xa9, value, 0x8d, reg as u8, (reg >> 8) as u8])
+        .collect()
+}
+
+/// The master cycle at which the last executed instruction started
+fn instruction_start(device: &TestDevice) -> u64 {
+    // the instruction is executed as a whole in the first cycles of the step
+    device.master_cycles - 2
+}
+
+#[test]
+fn dma_stalls_cpu() {
+    // the CPU resumes after the alignment to the DMA clock, the overhead
+    // of the transfer and of every channel and the transferred bytes
+    for padding in 0..4 {
+        // `nop`s moving the write after the WRAM refresh and shifting it
+        // by 6 master cycles each
+        let mut code = vec![0xea; 6 + padding];
+        code.extend(register_writes(&[
+            // channel 0: three bytes from $7e:0000 to VMDATAL/H
+            (0x4300, 1),
+            (0x4301, 0x18),
+            (0x4302, 0),
+            (0x4303, 0),
+            (0x4304, 0x7e),
+            (0x4305, 3),
+            (0x4306, 0),
+            // channel 1: two bytes from $7e:0000 to CGDATA
+            (0x4310, 0),
+            (0x4311, 0x22),
+            (0x4312, 0),
+            (0x4313, 0),
+            (0x4314, 0x7e),
+            (0x4315, 2),
+            (0x4316, 0),
+            (0x420b, 3),
+        ]));
+        code.extend([0xea, 0xea]);
+        let mut device = new_device();
+        device.load_cartridge(new_cartridge(&code, &[]));
+        let mut write = None;
+        while device.cpu.regs.pc.addr != 0x8000 + code.len() as u16 - 2 {
+            let step = device.step_cpu_instruction().unwrap();
+            write = Some((instruction_start(&device), step.cycles));
+        }
+        let (start, cycles) = write.unwrap();
+        assert!(start > 576, "the DMA overlaps the WRAM refresh");
+        device.step_cpu_instruction().unwrap();
+        // the CPU executes the write as a whole at its first cycle,
+        // so the alignment starts there
+        let align = (8 - start % 8) % 8;
+        assert_eq!(
+            instruction_start(&device),
+            start + u64::from(cycles) + align + 8 + 2 * 8 + 5 * 8,
+            "padding {}",
+            padding
+        );
+        assert!(!device.dma.is_dma_running());
+        assert_eq!(device.dma.ahead_cycles, 0);
+    }
+}
+
+#[test]
+fn hdma_stalls_cpu() {
+    let mut code = register_writes(&[
+        // channel 0: one byte per line to INIDISP with the table at $00:9000
+        (0x4300, 0),
+        (0x4301, 0x00),
+        (0x4302, 0x00),
+        (0x4303, 0x90),
+        (0x4304, 0x00),
+        (0x420c, 1),
+    ]);
+    // `bra` to itself
+    code.extend([0x80, 0xfe]);
+    let mut device = new_device();
+    // one line with the value $0f, then the end of the table
+    device.load_cartridge(new_cartridge(&code, &[0x01, 0x0f, 0x00]));
+    for _ in 0..12 {
+        device.step_cpu_instruction().unwrap();
+    }
+    assert!(device.dma.is_hdma_running());
+    while !device.new_frame {
+        device.run_cycle::<2>();
+    }
+    // the channels are set up at the start of the frame, which stalls the CPU
+    // by 18 cycles of overhead and 8 cycles to load the first entry
+    device.run_cycle::<2>();
+    assert_eq!(device.dma.hdma_ahead_cycles, 18 + 8);
+    let cpu_ahead_cycles = device.cpu_ahead_cycles;
+    for _ in 0..13 {
+        assert_eq!(device.cpu_ahead_cycles, cpu_ahead_cycles);
+        device.run_cycle::<2>();
+    }
+    assert_eq!(device.dma.hdma_ahead_cycles, 0);
+    device.run_cycle::<2>();
+    assert_eq!(device.cpu_ahead_cycles, cpu_ahead_cycles - 2);
+
+    // the transfer of the first line: 18 cycles of overhead, 8 for the
+    // channel, 8 for the byte and 8 to load the terminating entry
+    while device.ppu.get_pos().x < 1024 {
+        device.run_cycle::<2>();
+    }
+    device.run_cycle::<2>();
+    assert_eq!(device.ppu.get_pos().y, 0);
+    assert_eq!(device.dma.hdma_ahead_cycles, 18 + 8 + 8 + 8);
+    // the channel is done for the rest of the frame
+    while device.ppu.get_pos().y == 0 {
+        device.run_cycle::<2>();
+    }
+    while device.ppu.get_pos().x < 1024 {
+        device.run_cycle::<2>();
+    }
+    device.run_cycle::<2>();
+    assert_eq!(device.dma.hdma_ahead_cycles, 0);
+}
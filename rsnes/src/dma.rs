@@ -9,6 +9,11 @@ pub mod flags {
     pub const PPU_TO_CPU: u8 = 0x80;
 }
 
+/// Master cycles of DMA overhead for starting a transfer and for each channel
+const DMA_OVERHEAD_CYCLES: i32 = 8;
+/// Master cycles a DMA or HDMA needs to transfer one byte
+const DMA_BYTE_CYCLES: i32 = 8;
+
 #[derive(Debug, Clone, Copy, InSaveState)]
 pub struct Channel {
     a_bus: Addr24,
@@ -88,7 +93,9 @@ pub struct Dma {
     hdma_enabled: u8,
     cancelled: u8,
     do_transfer: u8,
+    /// Master cycles the CPU and DMA are stalled by a HDMA transfer
     pub(crate) hdma_ahead_cycles: i32,
+    /// Master cycles the CPU is stalled by the running DMA transfer
     pub(crate) ahead_cycles: i32,
 }
 
@@ -106,6 +113,14 @@ impl Dma {
         }
     }
 
+    /// Stop all transfers, the channel registers keep their values
+    pub fn reset(&mut self) {
+        *self = Self {
+            channels: self.channels,
+            ..Self::new()
+        }
+    }
+
     /// Read 8-bit from channel transfer values
     pub fn read(&self, addr: u16) -> Option<u8> {
         let channel = (addr >> 4) & 0b111;
@@ -132,15 +147,26 @@ impl Dma {
         self.hdma_enabled > 0
     }
 
-    pub fn enable_dma(&mut self, value: u8) {
+    /// Write MDMAEN to start a general purpose DMA.
+    ///
+    /// `master_cycles` is the current master clock counter which is
+    /// needed to align the transfer to the 8 cycle DMA clock.
+    pub fn enable_dma(&mut self, value: u8, master_cycles: u64) {
         let activated = value & !self.dma_enabled;
         self.dma_enabled = value;
         self.running = self.dma_enabled > 0;
         if activated > 0 {
-            self.ahead_cycles += 18 + activated.count_ones() as i32 * 8
+            // > [...] the CPU is paused, then it waits until the master clock is
+            // > a multiple of 8 cycles, followed by 8 cycles of DMA overhead
+            // > and 8 more cycles of overhead for each active channel
+            // source: FullSNES
+            let align = (8 - (master_cycles & 7) as i32) & 7;
+            self.ahead_cycles += align + DMA_OVERHEAD_CYCLES * (1 + activated.count_ones() as i32)
         }
     }
 
+    /// Write HDMAEN. Disabling a channel mid-frame stops it immediately,
+    /// re-enabling it continues with the current table state.
     pub fn enable_hdma(&mut self, value: u8) {
         self.hdma_enabled = value;
     }
@@ -216,15 +242,7 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
         // Maybe FIXED mode writes always the same data even if two reads
         // would result in different data
         let channel = self.dma.channels.get(channel_id).unwrap();
-        let offsets: &[u8] = match channel.control & flags::MODE {
-            0b000 => &[0],
-            0b001 => &[0, 1],
-            0b010 | 0b110 => &[0, 0],
-            0b011 | 0b111 => &[0, 0, 1, 1],
-            0b100 => &[0, 1, 2, 3],
-            0b101 => &[0, 1, 0, 1],
-            0b1000..=u8::MAX => unreachable!(),
-        };
+        let offsets = Self::get_transfer_offsets(channel.control);
         let delta = if channel.control & flags::FIXED == 0 {
             if channel.control & flags::DECREMENT > 0 {
                 u16::MAX
@@ -239,7 +257,7 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             let channel = self.dma.channels.get_mut(channel_id).unwrap();
             channel.a_bus.addr = channel.a_bus.addr.wrapping_add(delta);
             channel.size = channel.size.wrapping_sub(1);
-            self.dma.ahead_cycles += 6;
+            self.dma.ahead_cycles += DMA_BYTE_CYCLES;
             if channel.size == 0 {
                 self.dma.dma_enabled &= !(1 << channel_id);
                 break;
@@ -251,93 +269,109 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
         if let Some(channel) = self.dma.get_first_dma_channel_id() {
             self.do_dma(channel)
         } else {
-            self.dma.running = false
+            self.dma.running = false;
+            #[cfg(feature = "trace")]
+            self.trace_event(crate::trace::EventKind::DmaEnd)
         }
     }
 
+    /// Load the next HDMA table entry of a channel.
+    /// This loads the line counter and, in indirect mode, the indirect address.
+    /// A line counter of zero terminates the channel for the rest of the frame.
+    ///
+    /// Returns the amount of master cycles the CPU is stalled.
+    fn load_hdma_entry(&mut self, channel_id: usize) -> i32 {
+        let channel = self.dma.channels.get_mut(channel_id).unwrap();
+        let addr = Addr24::new(channel.a_bus.bank, channel.table);
+        channel.table = channel.table.wrapping_add(1);
+        let line_counter = self.read::<u8>(addr);
+        let channel = self.dma.channels.get_mut(channel_id).unwrap();
+        channel.line_counter = line_counter;
+        self.dma.do_transfer |= 1 << channel_id;
+        let mut cycles = 8;
+        if channel.control & flags::INDIRECT > 0 {
+            let addr = Addr24::new(channel.a_bus.bank, channel.table);
+            channel.table = channel.table.wrapping_add(2);
+            cycles += 16;
+            let indirect = self.read::<u16>(addr);
+            self.dma.channels.get_mut(channel_id).unwrap().size = indirect;
+        }
+        if line_counter == 0 {
+            self.dma.cancelled |= 1 << channel_id
+        }
+        cycles
+    }
+
+    /// Run one HDMA transfer step, which is done once every visible scanline.
+    ///
+    /// Returns the amount of master cycles the CPU is stalled.
     pub fn do_hdma(&mut self) -> i32 {
-        let mut cycles = 0;
         let hdma_running = self.dma.hdma_enabled & !self.dma.cancelled;
+        if hdma_running == 0 {
+            return 0;
+        }
+        // > If a DMA and HDMA are running on the same channel,
+        // > the DMA will be terminated
+        // source: FullSNES
+        self.dma.dma_enabled &= !hdma_running;
+        // HDMA has a fixed overhead of 18 cycles per scanline
+        let mut cycles = 18;
         for channel_id in 0..8 {
-            if hdma_running & (1 << channel_id) > 0 {
-                if cycles == 0 {
-                    cycles = 24
-                } else {
-                    cycles += 8
-                }
-                let channel = self.dma.channels.get(channel_id).unwrap();
-                let offsets: &[u8] = match channel.control & flags::MODE {
-                    0b000 => &[0],
-                    0b001 => &[0, 1],
-                    0b010 | 0b110 => &[0, 0],
-                    0b011 | 0b111 => &[0, 0, 1, 1],
-                    0b100 => &[0, 1, 2, 3],
-                    0b101 => &[0, 1, 0, 1],
-                    0b1000..=u8::MAX => unreachable!(),
-                };
-                if self.dma.do_transfer & (1 << channel_id) > 0 {
-                    for &i in offsets {
-                        cycles += 8;
-                        self.transfer_hdma_byte(channel_id, i)
-                    }
-                }
-                let channel = self.dma.channels.get_mut(channel_id).unwrap();
-                channel.line_counter = channel.line_counter.wrapping_sub(1);
-                self.dma.do_transfer |= 1 << channel_id;
-                if channel.line_counter == 0 || channel.line_counter == 0x80 {
-                    let addr = Addr24::new(channel.a_bus.bank, channel.table);
-                    let val = self.read::<u8>(addr);
-                    let channel = self.dma.channels.get_mut(channel_id).unwrap();
-                    channel.line_counter = val;
-                    channel.table = channel.table.wrapping_add(1);
-                    if channel.line_counter == 0 {
-                        self.dma.cancelled |= 1 << channel_id
-                    }
-                    if channel.control & flags::INDIRECT > 0 {
-                        cycles += 16;
-                        let addr = Addr24::new(channel.a_bus.bank, channel.table);
-                        let val = self.read::<u16>(addr);
-                        let channel = self.dma.channels.get_mut(channel_id).unwrap();
-                        channel.table = channel.table.wrapping_add(2);
-                        channel.size = val;
-                    }
-                } else if channel.line_counter < 0x80 {
-                    self.dma.do_transfer &= !(1 << channel_id)
+            if hdma_running & (1 << channel_id) == 0 {
+                continue;
+            }
+            cycles += 8;
+            let channel = self.dma.channels.get(channel_id).unwrap();
+            let offsets = Self::get_transfer_offsets(channel.control);
+            if self.dma.do_transfer & (1 << channel_id) > 0 {
+                for &i in offsets {
+                    cycles += DMA_BYTE_CYCLES;
+                    self.transfer_hdma_byte(channel_id, i)
                 }
             }
+            let channel = self.dma.channels.get_mut(channel_id).unwrap();
+            channel.line_counter = channel.line_counter.wrapping_sub(1);
+            // only continue transferring in repeat mode
+            if channel.line_counter & 0x80 == 0 {
+                self.dma.do_transfer &= !(1 << channel_id)
+            }
+            if channel.line_counter & 0x7f == 0 {
+                cycles += self.load_hdma_entry(channel_id);
+            }
         }
         cycles
     }
 
+    /// Initialize all enabled HDMA channels, which is done at the start of each frame.
+    ///
+    /// Returns the amount of master cycles the CPU is stalled.
     pub fn reset_hdma(&mut self) -> i32 {
-        let mut cycles = 0;
-        self.dma.dma_enabled &= !self.dma.hdma_enabled;
         self.dma.cancelled = 0;
-        self.dma.do_transfer = self.dma.hdma_enabled;
+        self.dma.do_transfer = 0;
+        if self.dma.hdma_enabled == 0 {
+            return 0;
+        }
+        self.dma.dma_enabled &= !self.dma.hdma_enabled;
+        let mut cycles = 18;
         for channel_id in 0..8 {
-            let channel = self.dma.channels.get_mut(channel_id).unwrap();
             if self.dma.hdma_enabled & (1 << channel_id) > 0 {
-                if cycles == 0 {
-                    cycles = 24
-                } else {
-                    cycles += 8
-                }
-                channel.table = channel.a_bus.addr;
-                let read_addr1 = Addr24::new(channel.a_bus.bank, channel.table);
-                channel.table = channel.table.wrapping_add(1);
-                let read_addr2 = Addr24::new(channel.a_bus.bank, channel.table);
-                let line_counter = self.read(read_addr1);
                 let channel = self.dma.channels.get_mut(channel_id).unwrap();
-                channel.line_counter = line_counter;
-                if channel.control & flags::INDIRECT > 0 {
-                    cycles += 16;
-                    let new_size = self.read::<u16>(read_addr2);
-                    let channel = self.dma.channels.get_mut(channel_id).unwrap();
-                    channel.table = channel.table.wrapping_add(2);
-                    channel.size = new_size;
-                }
+                channel.table = channel.a_bus.addr;
+                cycles += self.load_hdma_entry(channel_id);
             }
         }
         cycles
     }
+
+    const fn get_transfer_offsets(control: u8) -> &'static [u8] {
+        match control & flags::MODE {
+            0b000 => &[0],
+            0b001 => &[0, 1],
+            0b010 | 0b110 => &[0, 0],
+            0b011 | 0b111 => &[0, 0, 1, 1],
+            0b100 => &[0, 1, 2, 3],
+            0b101 => &[0, 1, 0, 1],
+            0b1000..=u8::MAX => unreachable!(),
+        }
+    }
 }
@@ -0,0 +1,409 @@
+//! BS-X Satellaview handling types
+//!
+//! The BS-X base cartridge contains a memory controller (MCC), 512 KiB of
+//! PSRAM, 32 KiB of battery backed SRAM and a slot for a flash memory pack.
+//! The Satellaview base unit is connected to the expansion port and
+//! receives the data streams of the satellite broadcasts.
+//!
+//! The broadcasts ended in 2000, so the only stream provided is the time
+//! channel, which is generated from the [`ClockSource`] of the cartridge.
+//! Only the MCC mappings used by the BIOS to start memory pack
+//! and downloaded games are emulated.
+//!
+//! # Literature
+//!
+//! - <https://problemkaputt.de/fullsnes.htm>
+
+use super::calendar;
+use crate::{backend::ClockSource, device::Addr24};
+use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
+use save_state_macro::*;
+
+const PSRAM_SIZE: usize = 0x80000;
+const SRAM_SIZE: usize = 0x8000;
+const FLASH_BLOCK_SIZE: usize = 0x10000;
+/// Size of a data frame of a broadcast stream
+pub const FRAME_SIZE: usize = 22;
+/// The broadcast channel, which transmits the current time
+pub const TIME_CHANNEL: u16 = 0x0121;
+/// The broadcasts used japan standard time (UTC+9)
+const TIME_OFFSET: u64 = 9 * 60 * 60;
+
+/// Indices of the MCC registers at `$00-0F:5000`
+mod mcc {
+    /// map PSRAM and the memory pack in HiROM instead of LoROM layout
+    pub const HIROM: usize = 0x02;
+    /// enable PSRAM at banks $60-$6F
+    pub const PSRAM_60: usize = 0x03;
+    /// enable PSRAM at banks $70-$77
+    pub const PSRAM_70: usize = 0x04;
+    /// enable the memory pack at banks $40-$5F
+    pub const PACK_40: usize = 0x05;
+    /// map PSRAM instead of the memory pack at banks $20-$3F and $A0-$BF
+    pub const PSRAM_20: usize = 0x06;
+    /// enable the BIOS at banks $00-$1F
+    pub const BIOS_00: usize = 0x07;
+    /// enable the BIOS at banks $80-$9F
+    pub const BIOS_80: usize = 0x08;
+    /// allow writes to the memory pack
+    pub const PACK_WRITE: usize = 0x0c;
+    /// apply all written registers
+    pub const COMMIT: usize = 0x0e;
+}
+
+mod flash_modes {
+    pub const READ_ARRAY: u8 = 0;
+    pub const READ_STATUS: u8 = 1;
+    pub const READ_ID: u8 = 2;
+    pub const PROGRAM: u8 = 3;
+    pub const ERASE_BLOCK: u8 = 4;
+    pub const ERASE_CHIP: u8 = 5;
+}
+
+/// Status register value of an idle flash chip
+const FLASH_READY: u8 = 0x80;
+
+/// A flash memory pack inserted into the BS-X cartridge.
+///
+/// The size is always a power of two of at least one flash block.
+#[derive(Debug, Clone)]
+pub struct MemoryPack {
+    data: Vec<u8>,
+    mode: u8,
+    status: u8,
+}
+
+impl MemoryPack {
+    /// Create a memory pack from a dump.
+    /// The size gets rounded up to a power of two with erased memory.
+    pub fn new(mut data: Vec<u8>) -> Self {
+        data.resize(data.len().next_power_of_two().max(FLASH_BLOCK_SIZE), 0xff);
+        Self {
+            data,
+            mode: flash_modes::READ_ARRAY,
+            status: FLASH_READY,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn has_valid_size(&self) -> bool {
+        self.data.len().is_power_of_two() && self.data.len() >= FLASH_BLOCK_SIZE
+    }
+
+    fn index(&self, addr: u32) -> usize {
+        addr as usize & (self.data.len() - 1)
+    }
+
+    pub fn read(&self, addr: u32) -> u8 {
+        match self.mode {
+            flash_modes::READ_ARRAY => self.data[self.index(addr)],
+            flash_modes::READ_ID => {
+                // vendor signature, followed by the type and size of the pack
+                let size = (self.data.len() >> 17).trailing_zeros() as u8;
+                match addr & 0xff {
+                    0 => b'M',
+                    2 => b'P',
+                    6 => 0x10 | size,
+                    _ => 0,
+                }
+            }
+            _ => self.status,
+        }
+    }
+
+    pub fn write(&mut self, addr: u32, val: u8) {
+        use flash_modes::*;
+        self.mode = match (self.mode, val) {
+            (PROGRAM, _) => {
+                // flash cells can only be programmed from 1 to 0
+                let i = self.index(addr);
+                self.data[i] &= val;
+                READ_STATUS
+            }
+            (ERASE_BLOCK, 0xd0) => {
+                let start = self.index(addr) & !(FLASH_BLOCK_SIZE - 1);
+                self.data[start..start + FLASH_BLOCK_SIZE].fill(0xff);
+                READ_STATUS
+            }
+            (ERASE_CHIP, 0xd0) => {
+                self.data.fill(0xff);
+                READ_STATUS
+            }
+            (ERASE_BLOCK | ERASE_CHIP, _) => READ_STATUS,
+            (_, 0x00 | 0xff) => READ_ARRAY,
+            (_, 0x70 | 0x71) => READ_STATUS,
+            (_, 0x75 | 0x90) => READ_ID,
+            (_, 0x10 | 0x40) => PROGRAM,
+            (_, 0x20) => ERASE_BLOCK,
+            (_, 0xa7) => ERASE_CHIP,
+            (mode, 0x50) => {
+                self.status = FLASH_READY;
+                mode
+            }
+            (mode, _) => mode,
+        }
+    }
+}
+
+/// An erased memory pack of a single flash block
+impl Default for MemoryPack {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl InSaveState for MemoryPack {
+    fn serialize(&self, state: &mut SaveStateSerializer) {
+        self.data.serialize(state);
+        self.mode.serialize(state);
+        self.status.serialize(state);
+    }
+
+    fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+        self.data.deserialize(state);
+        self.mode.deserialize(state);
+        self.status.deserialize(state);
+        if !self.has_valid_size() {
+            state.fail(save_state::DeserializeError::InvalidValue)
+        }
+    }
+}
+
+/// A data stream of the Satellaview base unit
+#[derive(Debug, Default, Clone, InSaveState)]
+struct Stream {
+    channel: u16,
+    /// Received frames, which were not read yet
+    queue: Vec<[u8; FRAME_SIZE]>,
+    frame: [u8; FRAME_SIZE],
+    pos: usize,
+}
+
+impl Stream {
+    fn set_channel(&mut self, channel: u16, clock: &dyn ClockSource) {
+        self.channel = channel;
+        self.queue.clear();
+        self.pos = FRAME_SIZE;
+        if channel == TIME_CHANNEL {
+            self.queue.push(time_frame(clock.unix_time()))
+        }
+    }
+
+    fn read(&mut self, reg: u8) -> u8 {
+        match reg {
+            0 => self.channel as u8,
+            1 => (self.channel >> 8) as u8,
+            // amount of frames ready to be read
+            2 => self.queue.len().min(0x7f) as u8,
+            3 => {
+                // latch the next frame and return its prefix
+                if self.queue.is_empty() {
+                    return 0;
+                }
+                self.frame = self.queue.remove(0);
+                self.pos = 0;
+                // every stream only consists of a single frame
+                0x90
+            }
+            4 => match self.frame.get(self.pos) {
+                Some(&val) => {
+                    self.pos += 1;
+                    val
+                }
+                None => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, reg: u8, val: u8, clock: &dyn ClockSource) {
+        match reg {
+            0 => self.set_channel((self.channel & 0xff00) | u16::from(val), clock),
+            1 => self.set_channel((self.channel & 0xff) | (u16::from(val) << 8), clock),
+            _ => (),
+        }
+    }
+}
+
+/// Create the frame of the time channel.
+///
+/// The frame starts with a 10 byte header, followed by an unknown byte,
+/// the second, minute, hour, day of the week (1 = sunday), day, month and year.
+pub fn time_frame(unix_time: u64) -> [u8; FRAME_SIZE] {
+    let time = unix_time + TIME_OFFSET;
+    let days = (time / 86400) as i64;
+    let secs = time % 86400;
+    let (year, month, day) = calendar::civil_from_days(days);
+    let mut frame = [0; FRAME_SIZE];
+    frame[5] = 1;
+    frame[6] = 1;
+    frame[11] = (secs % 60) as u8;
+    frame[12] = (secs / 60 % 60) as u8;
+    frame[13] = (secs / 3600) as u8;
+    frame[14] = calendar::weekday(days) + 1;
+    frame[15] = day;
+    frame[16] = month;
+    frame[17..19].copy_from_slice(&(year as u16).to_le_bytes());
+    frame
+}
+
+#[derive(Debug, Clone, InSaveState)]
+pub struct Bsx {
+    /// Written MCC registers
+    pending: [bool; 16],
+    /// Applied MCC registers
+    mcc: [bool; 16],
+    psram: Vec<u8>,
+    sram: Vec<u8>,
+    pack: Option<MemoryPack>,
+    streams: [Stream; 2],
+    /// Registers `$2194-$219F` of the base unit
+    unit_regs: [u8; 12],
+}
+
+impl Default for Bsx {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bsx {
+    pub fn new() -> Self {
+        let mut mcc = [false; 16];
+        mcc[mcc::BIOS_00] = true;
+        mcc[mcc::BIOS_80] = true;
+        Self {
+            pending: mcc,
+            mcc,
+            psram: vec![0; PSRAM_SIZE],
+            sram: vec![0xff; SRAM_SIZE],
+            pack: None,
+            streams: Default::default(),
+            unit_regs: [0; 12],
+        }
+    }
+
+    pub fn insert_memory_pack(&mut self, pack: MemoryPack) {
+        self.pack = Some(pack)
+    }
+
+    pub fn memory_pack(&self) -> Option<&MemoryPack> {
+        self.pack.as_ref()
+    }
+
+    pub fn sram(&self) -> &[u8] {
+        &self.sram
+    }
+
+    fn pack_addr(&self, addr: Addr24) -> u32 {
+        if self.mcc[mcc::HIROM] {
+            (u32::from(addr.bank & 0x3f) << 16) | u32::from(addr.addr)
+        } else {
+            (u32::from(addr.bank & 0x3f) << 15) | u32::from(addr.addr & 0x7fff)
+        }
+    }
+
+    fn psram_addr(addr: Addr24) -> usize {
+        ((usize::from(addr.bank & 7) << 16) | usize::from(addr.addr)) & (PSRAM_SIZE - 1)
+    }
+
+    /// Find the memory, which is mapped to an address
+    fn map(&self, addr: Addr24) -> Option<Target> {
+        let (bank, a) = (addr.bank, addr.addr);
+        Some(match bank {
+            0x00..=0x0f if a == 0x5000 => Target::Mcc(usize::from(bank)),
+            0x10..=0x17 if (0x5000..=0x5fff).contains(&a) => {
+                Target::Sram((usize::from(bank & 7) << 12) | usize::from(a & 0xfff))
+            }
+            0x00..=0x3f | 0x80..=0xbf if a >= 0x8000 => {
+                let bios = if bank < 0x80 {
+                    self.mcc[mcc::BIOS_00]
+                } else {
+                    self.mcc[mcc::BIOS_80]
+                };
+                if bank & 0x60 == 0 && bios {
+                    Target::Bios((u32::from(bank & 0x1f) << 15) | u32::from(a & 0x7fff))
+                } else if bank & 0x20 != 0 && self.mcc[mcc::PSRAM_20] {
+                    Target::Psram(
+                        ((usize::from(bank & 0xf) << 15) | usize::from(a & 0x7fff))
+                            & (PSRAM_SIZE - 1),
+                    )
+                } else {
+                    Target::Pack(self.pack_addr(addr))
+                }
+            }
+            0x40..=0x5f if self.mcc[mcc::PACK_40] => Target::Pack(self.pack_addr(addr)),
+            0x60..=0x6f if self.mcc[mcc::PSRAM_60] => Target::Psram(Self::psram_addr(addr)),
+            0x70..=0x77 if self.mcc[mcc::PSRAM_70] => Target::Psram(Self::psram_addr(addr)),
+            0xc0..=0xff => Target::Pack((u32::from(bank & 0x3f) << 16) | u32::from(a)),
+            _ => return None,
+        })
+    }
+
+    /// Read from the cartridge. `bios` is the ROM of the base cartridge.
+    pub fn read(&self, bios: &[u8], addr: Addr24) -> Option<u8> {
+        match self.map(addr)? {
+            Target::Mcc(reg) => Some(if self.pending[reg] { 0x80 } else { 0 }),
+            Target::Sram(i) => Some(self.sram[i]),
+            Target::Psram(i) => Some(self.psram[i]),
+            Target::Bios(i) => Some(bios[i as usize & (bios.len() - 1)]),
+            Target::Pack(i) => self.pack.as_ref().map(|pack| pack.read(i)),
+        }
+    }
+
+    /// Write to the cartridge
+    pub fn write(&mut self, addr: Addr24, val: u8) {
+        match self.map(addr) {
+            Some(Target::Mcc(reg)) => {
+                self.pending[reg] = val & 0x80 != 0;
+                if reg == mcc::COMMIT && self.pending[reg] {
+                    self.mcc = self.pending
+                }
+            }
+            Some(Target::Sram(i)) => self.sram[i] = val,
+            Some(Target::Psram(i)) => self.psram[i] = val,
+            Some(Target::Pack(i)) if self.mcc[mcc::PACK_WRITE] => {
+                if let Some(pack) = &mut self.pack {
+                    pack.write(i, val)
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Read the registers `$2188-$219F` of the base unit
+    pub fn read_bus_b(&mut self, addr: u8) -> Option<u8> {
+        match addr {
+            0x88..=0x8d => Some(self.streams[0].read(addr - 0x88)),
+            0x8e..=0x93 => Some(self.streams[1].read(addr - 0x8e)),
+            0x94..=0x9f => Some(self.unit_regs[usize::from(addr - 0x94)]),
+            _ => None,
+        }
+    }
+
+    /// Write the registers `$2188-$219F` of the base unit.
+    ///
+    /// Selecting the time channel reads the current time from `clock`.
+    pub fn write_bus_b(&mut self, addr: u8, val: u8, clock: &dyn ClockSource) {
+        match addr {
+            0x88..=0x8d => self.streams[0].write(addr - 0x88, val, clock),
+            0x8e..=0x93 => self.streams[1].write(addr - 0x8e, val, clock),
+            0x94..=0x9f => self.unit_regs[usize::from(addr - 0x94)] = val,
+            _ => (),
+        }
+    }
+}
+
+enum Target {
+    Mcc(usize),
+    Sram(usize),
+    Psram(usize),
+    Bios(u32),
+    Pack(u32),
+}
+
+#[cfg(test)]
+mod tests;
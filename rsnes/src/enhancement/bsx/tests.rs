@@ -0,0 +1,103 @@
+use super::*;
+
+#[test]
+fn time_frame_date() {
+    // 2000-01-01 00:00:00 in japan standard time
+    let frame = time_frame(946684800 - TIME_OFFSET);
+    assert_eq!(frame[11..19], [0, 0, 0, 7, 1, 1, 0xd0, 0x07]);
+    // 2021-03-04 05:06:07 UTC
+    let frame = time_frame(1614834367);
+    assert_eq!(frame[11..19], [7, 6, 14, 5, 4, 3, 0xe5, 0x07]);
+}
+
+#[test]
+fn flash_commands() {
+    let mut pack = MemoryPack::new(vec![0x5a; 0x80000]);
+    assert_eq!(pack.read(0x1234), 0x5a);
+    pack.write(0, 0x75);
+    assert_eq!(
+        [pack.read(0), pack.read(2), pack.read(6)],
+        [b'M', b'P', 0x12]
+    );
+    pack.write(0, 0xff);
+    pack.write(0x1234, 0x10);
+    pack.write(0x1234, 0x0f);
+    assert_eq!(pack.read(0x1234), FLASH_READY);
+    pack.write(0, 0xff);
+    assert_eq!(pack.read(0x1234), 0x0a);
+    pack.write(0x10000, 0x20);
+    pack.write(0x10000, 0xd0);
+    pack.write(0, 0xff);
+    assert_eq!(pack.read(0x1234), 0x0a);
+    assert_eq!(pack.read(0x1ffff), 0xff);
+    assert_eq!(pack.read(0x20000), 0x5a);
+}
+
+#[test]
+fn memory_pack_sizes() {
+    let mut pack = MemoryPack::default();
+    assert_eq!(pack.data().len(), FLASH_BLOCK_SIZE);
+    assert_eq!(pack.read(0x1234), 0xff);
+    pack.write(0, 0xa7);
+    pack.write(0, 0xd0);
+    assert_eq!(MemoryPack::new(vec![0; 0x30000]).data().len(), 0x40000);
+
+    for len in [0, 0x100, 0x30000] {
+        let mut ser = SaveStateSerializer::new();
+        MemoryPack {
+            data: vec![0; len],
+            mode: flash_modes::READ_ARRAY,
+            status: FLASH_READY,
+        }
+        .serialize(&mut ser);
+        let mut restored = MemoryPack::new(vec![0x5a; 0x20000]);
+        assert_eq!(
+            save_state::deserialize_checked(&mut restored, ser.data()),
+            Err(save_state::DeserializeError::InvalidValue)
+        );
+        assert_eq!(restored.data(), [0x5a; 0x20000]);
+    }
+}
+
+#[test]
+fn mcc_commit() {
+    let bios: Vec<u8> = (0..=255).cycle().take(0x100000).collect();
+    let mut bsx = Bsx::new();
+    bsx.insert_memory_pack(MemoryPack::new(vec![0xaa; 0x100000]));
+    assert_eq!(bsx.read(&bios, Addr24::new(0x00, 0x8012)), Some(0x12));
+    assert_eq!(bsx.read(&bios, Addr24::new(0x60, 0x0000)), None);
+    assert_eq!(bsx.read(&bios, Addr24::new(0xc0, 0x0000)), Some(0xaa));
+    // pending registers get applied on commit only
+    bsx.write(Addr24::new(mcc::PSRAM_60 as u8, 0x5000), 0x80);
+    bsx.write(Addr24::new(mcc::BIOS_00 as u8, 0x5000), 0);
+    assert_eq!(
+        bsx.read(&bios, Addr24::new(mcc::PSRAM_60 as u8, 0x5000)),
+        Some(0x80)
+    );
+    assert_eq!(bsx.read(&bios, Addr24::new(0x60, 0x0000)), None);
+    bsx.write(Addr24::new(mcc::COMMIT as u8, 0x5000), 0x80);
+    bsx.write(Addr24::new(0x61, 0x0002), 0x34);
+    assert_eq!(bsx.read(&bios, Addr24::new(0x61, 0x0002)), Some(0x34));
+    assert_eq!(bsx.read(&bios, Addr24::new(0x00, 0x8012)), Some(0xaa));
+    // the memory pack is write protected
+    bsx.write(Addr24::new(0xc0, 0x0000), 0x40);
+    assert_eq!(bsx.read(&bios, Addr24::new(0xc0, 0x0000)), Some(0xaa));
+}
+
+#[test]
+fn time_channel_stream() {
+    use crate::backend::FixedClock;
+    let clock = FixedClock(1614834367);
+    let mut bsx = Bsx::new();
+    assert_eq!(bsx.read_bus_b(0x8a), Some(0));
+    bsx.write_bus_b(0x88, 0x21, &clock);
+    bsx.write_bus_b(0x89, 0x01, &clock);
+    assert_eq!(bsx.read_bus_b(0x8a), Some(1));
+    assert_eq!(bsx.read_bus_b(0x8b), Some(0x90));
+    assert_eq!(bsx.read_bus_b(0x8a), Some(0));
+    let frame: Vec<u8> = (0..FRAME_SIZE)
+        .map(|_| bsx.read_bus_b(0x8c).unwrap())
+        .collect();
+    assert_eq!(frame, time_frame(clock.0));
+    assert_eq!(bsx.read_bus_b(0x8c), Some(0));
+}
@@ -0,0 +1,52 @@
+//! Conversion between days since the unix epoch and dates of the
+//! proleptic gregorian calendar, used by the real-time clocks.
+//!
+//! See <https://howardhinnant.github.io/date_algorithms.html>
+
+/// The date of the day with the given number of days since 1970-01-01
+/// as year, month (1-12) and day (1-31)
+pub fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (
+        yoe + era * 400 + i64::from(month <= 2),
+        month as u8,
+        day as u8,
+    )
+}
+
+/// The number of days since 1970-01-01 of a date
+pub fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let (month, day) = (i64::from(month), i64::from(day));
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The day of the week (0 = sunday) of the day with the given number of days since 1970-01-01
+pub fn weekday(days: i64) -> u8 {
+    // 1970-01-01 was a thursday
+    (days + 4).rem_euclid(7) as u8
+}
+
+pub const fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+pub const fn days_in_month(year: i64, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
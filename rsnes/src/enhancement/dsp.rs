@@ -147,6 +147,14 @@ impl Dsp {
         }
     }
 
+    /// Reset the DSP, the clock speed is kept
+    pub fn reset(&mut self) {
+        *self = Self {
+            timing_proportion: self.timing_proportion,
+            ..Self::new(self.ver)
+        }
+    }
+
     pub const fn version(&self) -> DspVersion {
         self.ver
     }
@@ -467,7 +475,7 @@ impl InSaveState for DspVersion {
             2 => Self::Dsp2,
             3 => Self::Dsp3,
             4 => Self::Dsp4,
-            _ => panic!("unknown enum discriminant {}", i),
+            _ => return state.fail(save_state::DeserializeError::InvalidValue),
         }
     }
 }
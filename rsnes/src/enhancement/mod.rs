@@ -1,5 +1,11 @@
+pub mod bsx;
+mod calendar;
 mod dsp;
+pub mod msu1;
 pub mod sa1;
+#[cfg(feature = "sgb")]
+pub mod sgb;
+pub mod srtc;
 
 #[doc(inline)]
 pub use dsp::{Dsp, DspVersion};
@@ -0,0 +1,375 @@
+//! MSU-1 expansion handling types
+//!
+//! The MSU-1 streams a data file and CD quality audio tracks from the
+//! files provided by a [`MediaBackend`]. Its registers are mapped to
+//! `$2000-$2007` of the banks `$00-$3F` and `$80-$BF`.
+//!
+//! Audio tracks start with the magic bytes `MSU1` and the loop point in
+//! samples, followed by 16-bit signed stereo samples at 44.1 kHz.
+//!
+//! # Literature
+//!
+//! - <https://snes.nesdev.org/wiki/MSU-1>
+
+use crate::{
+    backend::{MediaBackend, MediaStream},
+    device::Addr24,
+    spc700::StereoSample,
+};
+use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
+use std::{
+    collections::VecDeque,
+    io::{Read, Seek, SeekFrom},
+    sync::{Arc, Mutex},
+};
+
+const IDENTIFIER: &[u8; 6] = b"S-MSU1";
+const REVISION: u8 = 1;
+const TRACK_MAGIC: &[u8; 4] = b"MSU1";
+/// The magic bytes followed by the loop point
+const TRACK_HEADER_SIZE: u64 = 8;
+const SAMPLE_RATE: u32 = 44100;
+const DSP_SAMPLE_RATE: u32 = 32000;
+/// Queued samples are limited to prevent a growing latency
+const MAX_QUEUED_SAMPLES: usize = 4096;
+
+mod status {
+    pub const AUDIO_REPEAT: u8 = 0x20;
+    pub const AUDIO_PLAYING: u8 = 0x10;
+    pub const TRACK_MISSING: u8 = 0x08;
+}
+
+/// Master clock frequency multiplied by 44
+const fn master_clock_44(pal: bool) -> u64 {
+    if pal {
+        21_281_370 * 44
+    } else {
+        945_000_000
+    }
+}
+
+/// Audio samples of the expansion port, which get mixed into the DSP output.
+///
+/// The samples are resampled to the DSP sample rate by linear interpolation.
+/// Clones share the queued samples, but resample independently.
+#[derive(Debug, Clone, Default)]
+pub struct AudioOutput {
+    queue: Arc<Mutex<VecDeque<StereoSample>>>,
+    /// Position between `previous` and `next` in units of `1 / DSP_SAMPLE_RATE`
+    acc: u32,
+    previous: StereoSample,
+    next: StereoSample,
+}
+
+impl AudioOutput {
+    pub(crate) fn push(&self, sample: StereoSample) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.len() >= MAX_QUEUED_SAMPLES {
+            queue.pop_front();
+        }
+        queue.push_back(sample)
+    }
+
+    /// Mix the expansion audio into a sample of the DSP
+    pub fn mix(&mut self, sample: StereoSample) -> StereoSample {
+        self.acc += SAMPLE_RATE;
+        let mut queue = self.queue.lock().unwrap();
+        while self.acc >= DSP_SAMPLE_RATE {
+            self.acc -= DSP_SAMPLE_RATE;
+            self.previous = self.next;
+            self.next = queue.pop_front().unwrap_or_default();
+        }
+        let (acc, rate) = (i64::from(self.acc), i64::from(DSP_SAMPLE_RATE));
+        let interpolated = self.previous.zip_with(self.next, |a, b| {
+            ((i64::from(a) * (rate - acc) + i64::from(b) * acc) / rate) as i16
+        });
+        sample + interpolated
+    }
+}
+
+pub struct Msu1 {
+    media: Option<Arc<dyn MediaBackend>>,
+    output: AudioOutput,
+    data: Option<Box<dyn MediaStream>>,
+    audio: Option<Box<dyn MediaStream>>,
+    pal: bool,
+
+    data_seek: u32,
+    data_offset: u32,
+    track_latch: u16,
+    track: Option<u16>,
+    track_missing: bool,
+    loop_point: u32,
+    /// Position in the audio track in samples
+    position: u32,
+    volume: u8,
+    playing: bool,
+    repeat: bool,
+    /// Master cycles multiplied by the sample rate and 44
+    cycles: u64,
+    /// The streams were not opened at their positions yet,
+    /// e.g. after cloning or loading a save state
+    stale: bool,
+}
+
+impl Msu1 {
+    pub fn new(media: Arc<dyn MediaBackend>) -> Self {
+        Self {
+            media: Some(media),
+            stale: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn output(&self) -> &AudioOutput {
+        &self.output
+    }
+
+    pub fn set_region(&mut self, pal: bool) {
+        self.pal = pal
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = REVISION;
+        if self.repeat {
+            status |= status::AUDIO_REPEAT
+        }
+        if self.playing {
+            status |= status::AUDIO_PLAYING
+        }
+        if self.track_missing {
+            status |= status::TRACK_MISSING
+        }
+        status
+    }
+
+    fn seek_data(&mut self) {
+        if let Some(data) = &mut self.data {
+            let _ = data.seek(SeekFrom::Start(self.data_offset.into()));
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let mut buf = [0];
+        if let Some(data) = &mut self.data {
+            if data.read_exact(&mut buf).is_ok() {
+                self.data_offset = self.data_offset.wrapping_add(1);
+            }
+        }
+        buf[0]
+    }
+
+    /// Open the audio track and read its header
+    fn open_track(&mut self, track: u16) -> Option<()> {
+        let mut audio = self.media.as_ref()?.open_audio_track(track)?;
+        let mut header = [0; TRACK_HEADER_SIZE as usize];
+        audio.read_exact(&mut header).ok()?;
+        if !header.starts_with(TRACK_MAGIC) {
+            return None;
+        }
+        self.loop_point = u32::from_le_bytes(header[4..].try_into().unwrap());
+        self.audio = Some(audio);
+        Some(())
+    }
+
+    fn load_track(&mut self, track: u16) {
+        self.track = Some(track);
+        self.audio = None;
+        self.position = 0;
+        self.playing = false;
+        self.repeat = false;
+        self.track_missing = self.open_track(track).is_none();
+    }
+
+    fn seek_audio(&mut self, position: u32) -> bool {
+        self.position = position;
+        let offset = TRACK_HEADER_SIZE + u64::from(position) * 4;
+        self.audio
+            .as_mut()
+            .is_some_and(|audio| audio.seek(SeekFrom::Start(offset)).is_ok())
+    }
+
+    fn read_sample(&mut self) -> Option<StereoSample> {
+        let mut buf = [0; 4];
+        self.audio.as_mut()?.read_exact(&mut buf).ok()?;
+        self.position += 1;
+        Some(StereoSample::<i16>::new(
+            i16::from_le_bytes([buf[0], buf[1]]),
+            i16::from_le_bytes([buf[2], buf[3]]),
+        ))
+    }
+
+    fn next_sample(&mut self) -> StereoSample {
+        let sample = match self.read_sample() {
+            Some(sample) => Some(sample),
+            None if self.repeat && self.seek_audio(self.loop_point) => self.read_sample(),
+            None => None,
+        };
+        match sample {
+            Some(sample) => {
+                let volume = i32::from(self.volume);
+                sample.map(|s| (i32::from(s) * volume / 255) as i16)
+            }
+            None => {
+                self.playing = false;
+                StereoSample::default()
+            }
+        }
+    }
+
+    /// Reopen the streams if they are stale, e.g. after loading a save state.
+    /// This is done lazily so that clones, which are never run, do not open any files.
+    fn reopen(&mut self) {
+        if !core::mem::take(&mut self.stale) {
+            return;
+        }
+        self.data = self.media.as_ref().and_then(|media| media.open_data());
+        self.seek_data();
+        self.audio = None;
+        if let Some(track) = self.track {
+            if self.open_track(track).is_some() {
+                self.seek_audio(self.position);
+            }
+        }
+    }
+
+    /// Read a register, `None` is returned if the address does not belong to the MSU-1
+    pub fn read(&mut self, addr: Addr24) -> Option<u8> {
+        if addr.bank & 0x40 != 0 || !(0x2000..=0x2007).contains(&addr.addr) {
+            return None;
+        }
+        self.reopen();
+        Some(match addr.addr & 7 {
+            0 => self.status(),
+            1 => self.read_data(),
+            n => IDENTIFIER[usize::from(n) - 2],
+        })
+    }
+
+    /// Write a register, `false` is returned if the address does not belong to the MSU-1
+    pub fn write(&mut self, addr: Addr24, val: u8) -> bool {
+        if addr.bank & 0x40 != 0 || !(0x2000..=0x2007).contains(&addr.addr) {
+            return false;
+        }
+        self.reopen();
+        match addr.addr & 7 {
+            n @ 0..=3 => {
+                let shift = n * 8;
+                self.data_seek = (self.data_seek & !(0xff << shift)) | (u32::from(val) << shift);
+                if n == 3 {
+                    self.data_offset = self.data_seek;
+                    self.seek_data()
+                }
+            }
+            4 => self.track_latch = (self.track_latch & 0xff00) | u16::from(val),
+            5 => {
+                self.track_latch = (self.track_latch & 0xff) | (u16::from(val) << 8);
+                self.load_track(self.track_latch)
+            }
+            6 => self.volume = val,
+            _ => {
+                self.playing = val & 1 != 0 && !self.track_missing && self.audio.is_some();
+                self.repeat = val & 2 != 0;
+            }
+        }
+        true
+    }
+
+    /// Tick in main CPU master cycles
+    pub fn tick(&mut self, n: u32) {
+        if !self.playing {
+            self.cycles = 0;
+            return;
+        }
+        self.reopen();
+        let clock = master_clock_44(self.pal);
+        self.cycles += u64::from(n) * u64::from(SAMPLE_RATE) * 44;
+        while self.cycles >= clock && self.playing {
+            self.cycles -= clock;
+            let sample = self.next_sample();
+            self.output.push(sample)
+        }
+    }
+}
+
+impl Default for Msu1 {
+    fn default() -> Self {
+        Self {
+            media: None,
+            output: AudioOutput::default(),
+            data: None,
+            audio: None,
+            pal: false,
+            data_seek: 0,
+            data_offset: 0,
+            track_latch: 0,
+            track: None,
+            track_missing: false,
+            loop_point: 0,
+            position: 0,
+            volume: 0xff,
+            playing: false,
+            repeat: false,
+            cycles: 0,
+            stale: false,
+        }
+    }
+}
+
+impl Clone for Msu1 {
+    fn clone(&self) -> Self {
+        Self {
+            media: self.media.clone(),
+            output: self.output.clone(),
+            data: None,
+            audio: None,
+            stale: true,
+            ..*self
+        }
+    }
+}
+
+impl std::fmt::Debug for Msu1 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Msu1")
+            .field("data_offset", &self.data_offset)
+            .field("track", &self.track)
+            .field("position", &self.position)
+            .field("status", &self.status())
+            .finish_non_exhaustive()
+    }
+}
+
+impl InSaveState for Msu1 {
+    fn serialize(&self, state: &mut SaveStateSerializer) {
+        self.data_seek.serialize(state);
+        self.data_offset.serialize(state);
+        self.track_latch.serialize(state);
+        self.track.serialize(state);
+        self.track_missing.serialize(state);
+        self.position.serialize(state);
+        self.volume.serialize(state);
+        self.playing.serialize(state);
+        self.repeat.serialize(state);
+        self.cycles.serialize(state);
+    }
+
+    fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+        self.data_seek.deserialize(state);
+        self.data_offset.deserialize(state);
+        self.track_latch.deserialize(state);
+        self.track.deserialize(state);
+        self.track_missing.deserialize(state);
+        self.position.deserialize(state);
+        self.volume.deserialize(state);
+        self.playing.deserialize(state);
+        self.repeat.deserialize(state);
+        self.cycles.deserialize(state);
+        self.data = None;
+        self.audio = None;
+        self.stale = true;
+    }
+}
+
+#[cfg(test)]
+mod tests;
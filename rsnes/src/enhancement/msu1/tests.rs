@@ -0,0 +1,176 @@
+use super::*;
+use std::{
+    io::Cursor,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Media stored in memory, the audio tracks are numbered by their index
+struct MemoryMedia {
+    data: Vec<u8>,
+    tracks: Vec<Vec<u8>>,
+    /// The amount of opened streams
+    opened: Arc<AtomicUsize>,
+}
+
+impl MediaBackend for MemoryMedia {
+    fn open_data(&self) -> Option<Box<dyn MediaStream>> {
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        Some(Box::new(Cursor::new(self.data.clone())))
+    }
+
+    fn open_audio_track(&self, track: u16) -> Option<Box<dyn MediaStream>> {
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        let track = self.tracks.get(usize::from(track))?;
+        Some(Box::new(Cursor::new(track.clone())))
+    }
+}
+
+fn new_track(loop_point: u32, samples: impl IntoIterator<Item = i16>) -> Vec<u8> {
+    let mut track = TRACK_MAGIC.to_vec();
+    track.extend_from_slice(&loop_point.to_le_bytes());
+    for sample in samples {
+        track.extend_from_slice(&sample.to_le_bytes());
+        track.extend_from_slice(&(-sample).to_le_bytes());
+    }
+    track
+}
+
+fn new_msu1_with_counter(opened: Arc<AtomicUsize>) -> Msu1 {
+    Msu1::new(Arc::new(MemoryMedia {
+        data: (0..=255).collect(),
+        tracks: vec![new_track(0, 1..=4), new_track(2, 1..=4)],
+        opened,
+    }))
+}
+
+fn new_msu1() -> Msu1 {
+    new_msu1_with_counter(Arc::default())
+}
+
+fn write(msu1: &mut Msu1, reg: u16, val: u8) {
+    assert!(msu1.write(Addr24::new(0x80, 0x2000 | reg), val))
+}
+
+fn read(msu1: &mut Msu1, reg: u16) -> u8 {
+    msu1.read(Addr24::new(0x00, 0x2000 | reg)).unwrap()
+}
+
+/// Emulate the master cycles of `n` samples
+fn tick_samples(msu1: &mut Msu1, n: u64) {
+    let cycles = n * master_clock_44(false) / (u64::from(SAMPLE_RATE) * 44);
+    msu1.tick(cycles as u32 + 1)
+}
+
+fn queued(msu1: &Msu1) -> Vec<i16> {
+    let queue = msu1.output.queue.lock().unwrap();
+    queue.iter().map(|sample| sample.l).collect()
+}
+
+#[test]
+fn registers() {
+    let mut msu1 = new_msu1();
+    let id: Vec<u8> = (2..8).map(|reg| read(&mut msu1, reg)).collect();
+    assert_eq!(id, IDENTIFIER);
+    assert_eq!(read(&mut msu1, 0), REVISION);
+    assert_eq!(msu1.read(Addr24::new(0x40, 0x2000)), None);
+    assert_eq!(msu1.read(Addr24::new(0x00, 0x2008)), None);
+    for (reg, val) in [0x34u8, 0, 0, 0].into_iter().enumerate() {
+        write(&mut msu1, reg as u16, val);
+    }
+    assert_eq!([read(&mut msu1, 1), read(&mut msu1, 1)], [0x34, 0x35]);
+    // a seek is only done by writing the last byte of the offset
+    write(&mut msu1, 0, 0x80);
+    assert_eq!(read(&mut msu1, 1), 0x36);
+    write(&mut msu1, 3, 0);
+    assert_eq!(read(&mut msu1, 1), 0x80);
+}
+
+#[test]
+fn audio_playback() {
+    let mut msu1 = new_msu1();
+    write(&mut msu1, 4, 5);
+    write(&mut msu1, 5, 0);
+    assert_eq!(read(&mut msu1, 0), REVISION | status::TRACK_MISSING);
+    write(&mut msu1, 7, 1);
+    assert_eq!(read(&mut msu1, 0) & status::AUDIO_PLAYING, 0);
+
+    write(&mut msu1, 4, 0);
+    write(&mut msu1, 5, 0);
+    write(&mut msu1, 7, 1);
+    assert_eq!(read(&mut msu1, 0), REVISION | status::AUDIO_PLAYING);
+    tick_samples(&mut msu1, 6);
+    assert_eq!(queued(&msu1), [1, 2, 3, 4, 0]);
+    assert_eq!(read(&mut msu1, 0), REVISION);
+}
+
+#[test]
+fn audio_repeat_and_save_state() {
+    let mut msu1 = new_msu1();
+    write(&mut msu1, 4, 1);
+    write(&mut msu1, 5, 0);
+    write(&mut msu1, 6, 0x7f);
+    write(&mut msu1, 7, 3);
+    tick_samples(&mut msu1, 6);
+    assert_eq!(queued(&msu1), [0, 0, 1, 1, 1, 1]);
+    write(&mut msu1, 6, 0xff);
+
+    let mut state = SaveStateSerializer::new();
+    msu1.serialize(&mut state);
+    tick_samples(&mut msu1, 2);
+    let mut copy = new_msu1();
+    copy.deserialize(&mut SaveStateDeserializer::new(state.data()));
+    tick_samples(&mut copy, 2);
+    assert_eq!(queued(&copy), [3, 4]);
+    assert_eq!(queued(&msu1)[6..], queued(&copy));
+    assert_eq!(
+        read(&mut copy, 0),
+        REVISION | status::AUDIO_PLAYING | status::AUDIO_REPEAT
+    );
+}
+
+#[test]
+fn clones_open_streams_lazily() {
+    let opened = Arc::new(AtomicUsize::new(0));
+    let mut msu1 = new_msu1_with_counter(opened.clone());
+    assert_eq!(opened.load(Ordering::Relaxed), 0);
+    write(&mut msu1, 4, 1);
+    write(&mut msu1, 5, 0);
+    write(&mut msu1, 7, 3);
+    tick_samples(&mut msu1, 1);
+    assert_eq!(opened.load(Ordering::Relaxed), 2);
+
+    let clones = vec![msu1.clone(); 4];
+    assert_eq!(opened.load(Ordering::Relaxed), 2);
+    let mut clone = clones[0].clone();
+    tick_samples(&mut clone, 2);
+    tick_samples(&mut msu1, 2);
+    assert_eq!(opened.load(Ordering::Relaxed), 4);
+    assert_eq!(queued(&clone), queued(&msu1)[..queued(&clone).len()]);
+}
+
+#[test]
+fn audio_output_interpolation() {
+    let mut output = AudioOutput::default();
+    for i in 0..32 {
+        output.push(StereoSample::<i16>::new(i * 320, -i * 320));
+    }
+    // a linear ramp is reproduced exactly with a latency of two samples
+    for k in 1..=20 {
+        let sample = output.mix(StereoSample::default());
+        if k >= 2 {
+            assert_eq!(
+                sample,
+                StereoSample::<i16>::new(k * 441 - 640, 640 - k * 441)
+            );
+        }
+    }
+    // the DSP sample is mixed in
+    let mut output = AudioOutput::default();
+    output.push(StereoSample::<i16>::new2(1000));
+    output.push(StereoSample::<i16>::new2(1000));
+    output.mix(StereoSample::default());
+    assert_eq!(
+        output.mix(StereoSample::<i16>::new(10, -10)),
+        StereoSample::<i16>::new(1010, 990)
+    );
+}
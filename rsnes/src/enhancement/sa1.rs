@@ -9,7 +9,7 @@
 
 use crate::{
     cartridge::Cartridge,
-    cpu::{Cpu, Status},
+    cpu::Cpu,
     device::{Addr24, Data, Device},
     instr::{AccessType, DeviceAccess},
 };
@@ -367,7 +367,7 @@ impl VarLen {
 #[derive(Debug, DefaultByNew, Clone, InSaveState)]
 pub struct Sa1 {
     iram: [u8; IRAM_SIZE],
-    bwram: [u8; BWRAM_SIZE],
+    bwram: Vec<u8>,
     blocks: [Block; 4],
     cpu: Cpu,
     ahead_cycles: i32,
@@ -377,6 +377,12 @@ pub struct Sa1 {
     bwram_map: [u8; 2],
     bwram_map_bits: bool,
     bwram_2bits: bool,
+    // SBWE/CBWE: the SNES or the SA-1 enabled writes to the protected area
+    bwram_write_enable: [bool; 2],
+    // BWPA: the size of the write-protected area at the start of the BW-RAM
+    bwram_protected: u32,
+    // SIWP/CIWP: every bit enables the writes to a 256-byte page of the I-RAM
+    iram_write_enable: [u8; 2],
     dma: DmaInfo,
     varlen: VarLen,
     timer: Timer,
@@ -402,10 +408,10 @@ pub struct Sa1 {
 }
 
 impl Sa1 {
-    pub const fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             iram: [0; IRAM_SIZE],
-            bwram: [0; BWRAM_SIZE],
+            bwram: vec![0; BWRAM_SIZE],
             blocks: [
                 Block::new(0, 0), // Set Super MMC Bank C
                 Block::new(1, 1), // Set Super MMC Bank D
@@ -420,6 +426,9 @@ impl Sa1 {
             bwram_map: [0; 2],
             bwram_map_bits: false,
             bwram_2bits: false,
+            bwram_write_enable: [false; 2],
+            bwram_protected: 0,
+            iram_write_enable: [0; 2],
             dma: DmaInfo::new(),
             varlen: VarLen::new(),
             timer: Timer::new(),
@@ -441,9 +450,15 @@ impl Sa1 {
         self.timer.set_region(is_pal)
     }
 
+    /// Reset the SA-1 and its registers, the I-RAM and BW-RAM keep their contents
     pub fn reset(&mut self) {
-        // TODO: correctly implement resetting
-        *self = Self::new()
+        let iram = self.iram;
+        let bwram = core::mem::take(&mut self.bwram);
+        *self = Self {
+            iram,
+            bwram,
+            ..Self::new()
+        }
     }
 
     pub fn cpu_mut(&mut self) -> &mut Cpu {
@@ -488,7 +503,7 @@ impl Sa1 {
     }
 
     pub fn shall_irq(&mut self) -> bool {
-        if self.cpu.regs.status.has(Status::IRQ_DISABLE) {
+        if self.cpu.irq_disable_polled {
             false
         } else {
             let irq = 0xe0 & self.sa1_interrupt_enable & !self.sa1_interrupt_acknowledge;
@@ -517,11 +532,35 @@ impl Sa1 {
         }
     }
 
+    /// Test if the protected area doesn't cover the BW-RAM address `addr`
+    /// or the protection got disabled by either CPU
+    fn is_bwram_writable(&self, addr: u32) -> bool {
+        addr >= self.bwram_protected || self.bwram_write_enable.contains(&true)
+    }
+
+    fn write_bwram(&mut self, addr: u32, val: u8) {
+        let addr = addr & 0x3_ffff;
+        if self.is_bwram_writable(addr) {
+            self.bwram[addr as usize] = val
+        }
+    }
+
+    fn read_iram(&self, addr: u16) -> u8 {
+        self.iram[usize::from(addr) & (IRAM_SIZE - 1)]
+    }
+
+    fn write_iram<const INTERNAL: bool>(&mut self, addr: u16, val: u8) {
+        let addr = usize::from(addr) & (IRAM_SIZE - 1);
+        if (self.iram_write_enable[INTERNAL as usize] >> (addr >> 8)) & 1 > 0 {
+            self.iram[addr] = val
+        }
+    }
+
     fn read_bwram_bits_with<const A1: u8, const A2: u8, const M1: u32, const M2: u8>(
         &self,
         addr: u32,
     ) -> u8 {
-        let val = self.bwram[(addr >> A2) as usize];
+        let val = self.bwram[((addr >> A2) & 0x3_ffff) as usize];
         (val >> ((addr & M1) << A1)) & M2
     }
 
@@ -530,7 +569,11 @@ impl Sa1 {
         addr: u32,
         val: u8,
     ) {
-        let r = &mut self.bwram[(addr >> A2) as usize];
+        let index = (addr >> A2) & 0x3_ffff;
+        if !self.is_bwram_writable(index) {
+            return;
+        }
+        let r = &mut self.bwram[index as usize];
         let s = (addr & M1) << A1;
         *r = (*r & !(M2 << s)) | ((val & M2) << s)
     }
@@ -568,7 +611,7 @@ impl Sa1 {
         if INTERNAL && self.bwram_map_bits {
             return self.write_bwram_bits(addr, val);
         }
-        self.bwram[(addr & 0x3_ffff) as usize] = val
+        self.write_bwram(addr, val)
     }
 }
 
@@ -679,9 +722,7 @@ impl Cartridge {
         const FALLBACK: u8 = 0xff;
         if addr.bank & 0x40 == 0 {
             match addr.addr {
-                0x0000..=0x07ff | 0x3000..=0x37ff => {
-                    self.sa1_ref().iram[usize::from(addr.addr) & (IRAM_SIZE - 1)]
-                }
+                0x0000..=0x07ff | 0x3000..=0x37ff => self.sa1_ref().read_iram(addr.addr),
                 0x6000..=0x7fff => self.sa1_ref().read_bwram_small::<true>(addr),
                 0x8000..=0xffff => self.read_rom(self.sa1_ref().lorom_addr(addr)),
                 _ => FALLBACK,
@@ -851,16 +892,16 @@ impl Cartridge {
                 sa1.bwram_map_bits = val & 0x80 > 0;
             }
             (0x2226, SNES) | (0x2227, SA1) => {
-                // BW-Ram Write Protection enable
-                // TODO: no emulator known to me is implementing this. Check why
+                // SBWE/CBWE - BW-Ram Write Protection enable
+                sa1.bwram_write_enable[INTERNAL as usize] = val & 0x80 > 0;
             }
             (0x2228, SNES) => {
-                // BW-Ram Write Protection area
-                // TODO: no emulator known to me is implementing this. Check why
+                // BWPA - BW-Ram Write Protection area
+                sa1.bwram_protected = 0x100 << (val & 0xf);
             }
             (0x2229, SNES) | (0x222a, SA1) => {
-                // I-Ram Write Protection
-                // TODO: no emulator known to me is implementing this. Check why
+                // SIWP/CIWP - I-Ram Write Protection
+                sa1.iram_write_enable[INTERNAL as usize] = val;
             }
             (0x2230, SA1) => {
                 // DCNT - DMA Control
@@ -967,14 +1008,12 @@ impl Cartridge {
         sa1.memory_cycles += 12;
         if addr.bank & 0x40 == 0 {
             match addr.addr {
-                0x0000..=0x07ff if INTERNAL => {
-                    Some(sa1.iram[usize::from(addr.addr) & (IRAM_SIZE - 1)])
-                }
+                0x0000..=0x07ff if INTERNAL => Some(sa1.read_iram(addr.addr)),
                 0x2200..=0x23ff => {
                     sa1.memory_cycles -= 6;
                     self.sa1_read_io::<INTERNAL>(addr.addr)
                 }
-                0x3000..=0x37ff => Some(sa1.iram[usize::from(addr.addr) & (IRAM_SIZE - 1)]),
+                0x3000..=0x37ff => Some(sa1.read_iram(addr.addr)),
                 0x6000..=0x7fff => Some(sa1.read_bwram_small::<INTERNAL>(addr)),
                 0x8000..=0xffff => {
                     sa1.memory_cycles -= 6;
@@ -988,8 +1027,9 @@ impl Cartridge {
                 0x00 => {
                     Some(sa1.bwram[(usize::from(addr.bank & 3) << 16) | usize::from(addr.addr)])
                 }
-                0x20 => Some(
-                    sa1.read_bwram_bits((u32::from(addr.bank & 15) << 16) | u32::from(addr.bank)),
+                // the bitmap projection of the BW-RAM is only visible to the SA-1
+                0x20 if INTERNAL => Some(
+                    sa1.read_bwram_bits((u32::from(addr.bank & 15) << 16) | u32::from(addr.addr)),
                 ),
                 _ => None,
             }
@@ -1000,29 +1040,59 @@ impl Cartridge {
         }
     }
 
+    /// Read the memory like the SNES CPU does, but without touching the I/O ports
+    pub fn sa1_peek(&self, addr: Addr24) -> Option<u8> {
+        let sa1 = self.sa1_ref();
+        if addr.bank & 0x40 == 0 {
+            match addr.addr {
+                0x3000..=0x37ff => Some(sa1.read_iram(addr.addr)),
+                0x6000..=0x7fff => Some(sa1.read_bwram_small::<false>(addr)),
+                0x8000..=0xffff => Some(self.read_rom(sa1.lorom_addr(addr))),
+                _ => None,
+            }
+        } else if addr.bank & 0x80 == 0 {
+            match addr.bank & 0x30 {
+                0x00 => {
+                    Some(sa1.bwram[(usize::from(addr.bank & 3) << 16) | usize::from(addr.addr)])
+                }
+                _ => None,
+            }
+        } else {
+            Some(self.read_rom(sa1.hirom_addr(addr)))
+        }
+    }
+
+    /// Write the memory like the SNES CPU does, but without touching the I/O ports
+    pub fn sa1_poke(&mut self, addr: Addr24, val: u8) {
+        if addr.bank & 0x40 == 0 && (0x2200..=0x23ff).contains(&addr.addr) {
+            return;
+        }
+        let memory_cycles = self.sa1_ref().memory_cycles;
+        self.sa1_write::<false>(addr, val);
+        self.sa1_mut().memory_cycles = memory_cycles;
+    }
+
     pub fn sa1_write<const INTERNAL: bool>(&mut self, addr: Addr24, val: u8) {
         let sa1 = self.sa1_mut();
         sa1.memory_cycles += 12;
         if addr.bank & 0x40 == 0 {
             match addr.addr {
-                0x0000..=0x07ff if INTERNAL => {
-                    sa1.iram[usize::from(addr.addr) & (IRAM_SIZE - 1)] = val
-                }
+                0x0000..=0x07ff if INTERNAL => sa1.write_iram::<INTERNAL>(addr.addr, val),
                 0x2200..=0x23ff => {
                     sa1.memory_cycles -= 6;
                     self.sa1_write_io::<INTERNAL>(addr.addr, val)
                 }
-                0x3000..=0x37ff => sa1.iram[usize::from(addr.addr) & (IRAM_SIZE - 1)] = val,
+                0x3000..=0x37ff => sa1.write_iram::<INTERNAL>(addr.addr, val),
                 0x6000..=0x7fff => sa1.write_bwram_small::<INTERNAL>(addr, val),
                 _ => (),
             }
         } else if addr.bank & 0x80 == 0 {
             match addr.bank & 0x30 {
                 0x00 => {
-                    sa1.bwram[(usize::from(addr.bank & 3) << 16) | usize::from(addr.addr)] = val
+                    sa1.write_bwram((u32::from(addr.bank & 3) << 16) | u32::from(addr.addr), val)
                 }
-                0x20 => sa1.write_bwram_bits(
-                    (u32::from(addr.bank & 15) << 16) | u32::from(addr.bank),
+                0x20 if INTERNAL => sa1.write_bwram_bits(
+                    (u32::from(addr.bank & 15) << 16) | u32::from(addr.addr),
                     val,
                 ),
                 _ => (),
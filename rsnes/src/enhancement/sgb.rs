@@ -0,0 +1,575 @@
+//! Super Game Boy handling types
+//!
+//! The Super Game Boy cartridges contain a complete Game Boy, which is
+//! connected to the SNES by the ICD2 chip. The Game Boy itself is not
+//! emulated by this crate, but provided by an implementation of [`GameBoy`].
+//!
+//! The ICD2 converts the LCD output of the Game Boy into SNES tiles, passes
+//! the joypads of the SNES to the Game Boy and receives the command packets,
+//! which the Game Boy sends by writing to its joypad register. The packets
+//! are interpreted by the BIOS in the ROM of the cartridge, which draws the
+//! border and applies the palettes with the SNES PPU.
+//!
+//! The registers of the ICD2 are mapped to `$6000-$7FFF` of the banks
+//! `$00-$3F` and `$80-$BF`:
+//!
+//! | Address         | Access | Description                                   |
+//! |-----------------|--------|-----------------------------------------------|
+//! | `$6000`         | R      | LCD character row (bits 7-3), write buffer    |
+//! | `$6001`         | W      | Select the character buffer to read           |
+//! | `$6002`         | R      | A command packet is available (bit 0)         |
+//! | `$6003`         | W      | Reset (bit 7), joypad count, clock divider    |
+//! | `$6004-$6007`   | W      | Joypads 1-4                                   |
+//! | `$600F`         | R      | Chip version                                  |
+//! | `$7000-$700F`   | R      | The received command packet                   |
+//! | `$7800`         | R      | Read the selected character buffer            |
+//!
+//! # Literature
+//!
+//! - <https://problemkaputt.de/fullsnes.htm>
+//! - <https://gbdev.io/pandocs/SGB_Functions.html>
+
+use crate::{
+    device::{Addr24, Region},
+    enhancement::msu1::AudioOutput,
+    spc700::StereoSample,
+};
+use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
+use save_state_macro::*;
+use std::collections::VecDeque;
+
+/// The width of the Game Boy LCD in pixels
+pub const LCD_WIDTH: usize = 160;
+/// The height of the Game Boy LCD in pixels
+pub const LCD_HEIGHT: u8 = 144;
+/// The size of a command packet in bytes
+pub const PACKET_SIZE: usize = 16;
+/// A row of 20 tiles with two bits per pixel
+const ROW_SIZE: usize = LCD_WIDTH / 8 * 16;
+/// The amount of character rows buffered by the ICD2
+const ROW_COUNT: usize = 4;
+/// Received packets are limited, if the BIOS does not fetch them
+const MAX_QUEUED_PACKETS: usize = 64;
+/// The frequency of the oscillator of the Super Game Boy 2 in Hz
+const SGB2_OSCILLATOR: u64 = 20_971_520;
+/// The master clock divider selected by bits 1-0 of `$6003`
+const DIVIDERS: [u64; 4] = [4, 5, 7, 9];
+/// The Game Boy runs, when this amount of master cycles is pending,
+/// which is a little more than a scanline of the Game Boy
+const RUN_THRESHOLD: u64 = 2400;
+const VERSION: u8 = 0x21;
+
+/// A Game Boy, which is connected to the ICD2 of a Super Game Boy cartridge.
+///
+/// The implementation contains the Game Boy cartridge.
+pub trait GameBoy: Send + 'static {
+    /// Reset the CPU and the LCD, while the ICD2 holds the reset line
+    fn reset(&mut self);
+
+    /// Run for about `cycles` clock cycles and return the cycles actually run
+    fn run(&mut self, cycles: u32, icd2: &mut dyn Icd2Port) -> u32;
+
+    /// Create an independent copy of the Game Boy
+    fn boxed_clone(&self) -> Box<dyn GameBoy>;
+
+    /// The state of the Game Boy, which gets stored in save states
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore a state returned by [`Self::save_state`]
+    fn load_state(&mut self, _data: &[u8]) {}
+}
+
+/// The pins of the Game Boy, which are connected to the ICD2
+pub trait Icd2Port {
+    /// The Game Boy wrote the bits 5-4 (P15 and P14) of its joypad register
+    fn write_joypad(&mut self, p1: u8);
+
+    /// The low nibble of the joypad register read by the Game Boy
+    fn read_joypad(&mut self) -> u8;
+
+    /// The LCD finished the line `y` with a shade of `0..=3` per pixel
+    fn draw_line(&mut self, y: u8, pixels: &[u8; LCD_WIDTH]);
+
+    /// Output an audio sample with a sample rate of 44.1 kHz
+    fn push_sample(&mut self, sample: StereoSample);
+}
+
+/// The commands sent by the Game Boy in the first byte of a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Pal01,
+    Pal23,
+    Pal03,
+    Pal12,
+    AttrBlk,
+    AttrLin,
+    AttrDiv,
+    AttrChr,
+    Sound,
+    SouTrn,
+    PalSet,
+    PalTrn,
+    AtrcEn,
+    TestEn,
+    IconEn,
+    DataSnd,
+    DataTrn,
+    MltReq,
+    Jump,
+    ChrTrn,
+    PctTrn,
+    AttrTrn,
+    AttrSet,
+    MaskEn,
+    ObjTrn,
+    PalPri,
+}
+
+impl Command {
+    const ALL: [Self; 26] = [
+        Self::Pal01,
+        Self::Pal23,
+        Self::Pal03,
+        Self::Pal12,
+        Self::AttrBlk,
+        Self::AttrLin,
+        Self::AttrDiv,
+        Self::AttrChr,
+        Self::Sound,
+        Self::SouTrn,
+        Self::PalSet,
+        Self::PalTrn,
+        Self::AtrcEn,
+        Self::TestEn,
+        Self::IconEn,
+        Self::DataSnd,
+        Self::DataTrn,
+        Self::MltReq,
+        Self::Jump,
+        Self::ChrTrn,
+        Self::PctTrn,
+        Self::AttrTrn,
+        Self::AttrSet,
+        Self::MaskEn,
+        Self::ObjTrn,
+        Self::PalPri,
+    ];
+
+    /// Decode the command of the first packet of a transfer.
+    ///
+    /// The packet count in bits 2-0 is ignored, it is `None` for
+    /// unknown commands.
+    pub fn from_header(header: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(header >> 3)).copied()
+    }
+}
+
+/// The state of the ICD2, which is visible to the Game Boy
+#[derive(Debug, Clone, InSaveState)]
+struct Port {
+    /// The character rows in the tile format with two bits per pixel
+    rows: [[u8; ROW_SIZE]; ROW_COUNT],
+    /// The LCD line, which gets drawn next
+    line: u8,
+    write_row: u8,
+    joypads: [u8; 4],
+    /// The selected joypad, which gets incremented in multiplayer mode
+    joypad_id: u8,
+    /// The mask of the joypad id: 0 for one joypad, 1 for two and 3 for four
+    joypad_mask: u8,
+    /// P15 and P14 were high since the joypad id was incremented
+    joypad_lock: bool,
+    p1: u8,
+    packet: [u8; PACKET_SIZE],
+    /// The received bits of the current packet, `None` if no packet
+    /// transfer was started by a reset pulse
+    packet_bit: Option<u8>,
+    /// A bit was received, both lines must be high before the next one
+    strobe_lock: bool,
+    packets: VecDeque<[u8; PACKET_SIZE]>,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    audio: AudioOutput,
+}
+
+impl Port {
+    fn new() -> Self {
+        Self {
+            rows: [[0; ROW_SIZE]; ROW_COUNT],
+            line: 0,
+            write_row: 0,
+            joypads: [0xff; 4],
+            joypad_id: 0,
+            joypad_mask: 0,
+            joypad_lock: false,
+            p1: 0x30,
+            packet: [0; PACKET_SIZE],
+            packet_bit: None,
+            strobe_lock: false,
+            packets: VecDeque::new(),
+            audio: AudioOutput::default(),
+        }
+    }
+
+    /// Reset the state, but keep the audio output connected
+    fn reset(&mut self) {
+        let audio = core::mem::take(&mut self.audio);
+        *self = Self {
+            audio,
+            ..Self::new()
+        }
+    }
+
+    /// Test if the indices restored from a save state are in range
+    fn is_valid(&self) -> bool {
+        usize::from(self.write_row) < ROW_COUNT
+            && matches!(self.joypad_mask, 0 | 1 | 3)
+            && self.joypad_id & !self.joypad_mask == 0
+            && self
+                .packet_bit
+                .is_none_or(|bit| usize::from(bit) <= PACKET_SIZE * 8)
+    }
+
+    fn receive_bit(&mut self, bit: bool) {
+        let Some(index) = self.packet_bit else {
+            return;
+        };
+        self.strobe_lock = true;
+        if usize::from(index) < PACKET_SIZE * 8 {
+            let byte = &mut self.packet[usize::from(index >> 3)];
+            *byte = (*byte >> 1) | (u8::from(bit) << 7);
+            self.packet_bit = Some(index + 1);
+        } else {
+            // the stop bit must be zero
+            if !bit {
+                if self.packets.len() >= MAX_QUEUED_PACKETS {
+                    self.packets.pop_front();
+                }
+                self.packets.push_back(self.packet);
+            }
+            self.packet_bit = None
+        }
+    }
+}
+
+impl Icd2Port for Port {
+    fn write_joypad(&mut self, p1: u8) {
+        let p1 = p1 & 0x30;
+        if p1 == 0x30 {
+            if !self.joypad_lock {
+                self.joypad_lock = true;
+                self.joypad_id = (self.joypad_id + 1) & self.joypad_mask;
+            }
+            self.strobe_lock = false;
+        } else {
+            if p1 & 0x20 == 0 {
+                self.joypad_lock = false
+            }
+            if p1 == 0 {
+                // a reset pulse starts a packet
+                self.packet_bit = Some(0);
+                self.strobe_lock = true;
+            } else if !self.strobe_lock {
+                // a low P14 transfers a zero, a low P15 a one
+                self.receive_bit(p1 == 0x10)
+            }
+        }
+        self.p1 = p1
+    }
+
+    fn read_joypad(&mut self) -> u8 {
+        let pad = self.joypads[usize::from(self.joypad_id)];
+        match self.p1 {
+            0x30 => 0xf - self.joypad_id,
+            p1 => {
+                let mut val = 0xf;
+                if p1 & 0x10 == 0 {
+                    val &= pad
+                }
+                if p1 & 0x20 == 0 {
+                    val &= pad >> 4
+                }
+                val & 0xf
+            }
+        }
+    }
+
+    fn draw_line(&mut self, y: u8, pixels: &[u8; LCD_WIDTH]) {
+        if y >= LCD_HEIGHT {
+            return;
+        }
+        let row = &mut self.rows[usize::from(self.write_row)];
+        let offset = usize::from(y & 7) * 2;
+        for (tile, pixels) in row.chunks_exact_mut(16).zip(pixels.chunks_exact(8)) {
+            let (mut low, mut high) = (0, 0);
+            for &shade in pixels {
+                low = (low << 1) | (shade & 1);
+                high = (high << 1) | ((shade >> 1) & 1);
+            }
+            tile[offset] = low;
+            tile[offset + 1] = high;
+        }
+        self.line = y + 1;
+        if y & 7 == 7 {
+            self.write_row = (self.write_row + 1) & 3
+        }
+        if self.line == LCD_HEIGHT {
+            self.line = 0
+        }
+    }
+
+    fn push_sample(&mut self, sample: StereoSample) {
+        self.audio.push(sample)
+    }
+}
+
+/// The ICD2 chip and the connected Game Boy
+pub struct Icd2 {
+    game_boy: Option<Box<dyn GameBoy>>,
+    port: Port,
+    /// The oscillator of the Super Game Boy 2 is independent of the SNES
+    sgb2: bool,
+    pal: bool,
+    /// The Game Boy is held in reset while bit 7 of `$6003` is cleared
+    control: u8,
+    read_row: u8,
+    read_index: u16,
+    /// The packet latched by reading `$6002`
+    packet: [u8; PACKET_SIZE],
+    /// Master cycles not yet run by the Game Boy
+    pending: u64,
+    /// The fraction of a Game Boy cycle, which was not yet run
+    phase: u64,
+    /// Game Boy cycles run ahead of the ICD2
+    overrun: u64,
+}
+
+impl Icd2 {
+    /// Create the ICD2 of a Super Game Boy or, if `sgb2` is set,
+    /// a Super Game Boy 2 without a connected Game Boy
+    pub fn new(sgb2: bool) -> Self {
+        Self {
+            game_boy: None,
+            port: Port::new(),
+            sgb2,
+            pal: false,
+            control: 0,
+            read_row: 0,
+            read_index: 0,
+            packet: [0; PACKET_SIZE],
+            pending: 0,
+            phase: 0,
+            overrun: 0,
+        }
+    }
+
+    /// Connect a Game Boy, which is reset by the ICD2
+    pub fn connect(&mut self, mut game_boy: Box<dyn GameBoy>) {
+        game_boy.reset();
+        self.game_boy = Some(game_boy)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.game_boy.is_some()
+    }
+
+    pub fn is_sgb2(&self) -> bool {
+        self.sgb2
+    }
+
+    /// The audio output of the Game Boy, which gets mixed into the DSP output
+    pub fn output(&self) -> &AudioOutput {
+        &self.port.audio
+    }
+
+    pub fn set_region(&mut self, pal: bool) {
+        self.pal = pal
+    }
+
+    /// Reset the registers, which holds the Game Boy in reset
+    pub fn reset(&mut self) {
+        self.refresh();
+        self.control = 0;
+        self.read_row = 0;
+        self.read_index = 0;
+        self.packet = [0; PACKET_SIZE];
+        self.port.reset();
+        if let Some(game_boy) = &mut self.game_boy {
+            game_boy.reset()
+        }
+    }
+
+    const fn is_register(addr: Addr24) -> bool {
+        addr.bank & 0x40 == 0 && addr.addr & 0xe000 == 0x6000
+    }
+
+    pub fn read(&mut self, addr: Addr24) -> Option<u8> {
+        if !Self::is_register(addr) {
+            return None;
+        }
+        self.refresh();
+        match addr.addr {
+            0x6000 => Some(((self.port.line >> 3) << 3) | self.port.write_row),
+            0x6002 => {
+                let packet = self.port.packets.pop_front();
+                if let Some(packet) = packet {
+                    self.packet = packet
+                }
+                Some(packet.is_some().into())
+            }
+            0x600f => Some(VERSION),
+            0x7000..=0x700f => Some(self.packet[usize::from(addr.addr & 0xf)]),
+            0x7800 => {
+                let row = &self.port.rows[usize::from(self.read_row)];
+                let val = row.get(usize::from(self.read_index)).copied();
+                self.read_index = (self.read_index + 1).min(ROW_SIZE as u16);
+                Some(val.unwrap_or(0))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, addr: Addr24, val: u8) -> bool {
+        if !Self::is_register(addr) {
+            return false;
+        }
+        self.refresh();
+        match addr.addr {
+            0x6001 => {
+                self.read_row = val & 3;
+                self.read_index = 0
+            }
+            0x6003 => {
+                if self.control & 0x80 != 0 && val & 0x80 == 0 {
+                    self.port.reset();
+                    if let Some(game_boy) = &mut self.game_boy {
+                        game_boy.reset()
+                    }
+                }
+                self.control = val;
+                self.port.joypad_mask = match (val >> 4) & 3 {
+                    0 => 0,
+                    1 => 1,
+                    _ => 3,
+                };
+                self.port.joypad_id &= self.port.joypad_mask
+            }
+            0x6004..=0x6007 => self.port.joypads[usize::from(addr.addr & 3)] = val,
+            _ => (),
+        }
+        true
+    }
+
+    /// The Game Boy clock relative to the master clock
+    fn clock_ratio(&self) -> (u64, u64) {
+        let divider = DIVIDERS[usize::from(self.control & 3)];
+        if self.sgb2 {
+            let region = if self.pal { Region::Pal } else { Region::Ntsc };
+            let (num, den) = region.master_clock();
+            (SGB2_OSCILLATOR * den, num * divider)
+        } else {
+            (1, divider)
+        }
+    }
+
+    pub fn tick(&mut self, n: u64) {
+        self.pending += n;
+        if self.pending >= RUN_THRESHOLD {
+            self.refresh()
+        }
+    }
+
+    /// Run the Game Boy up to the current master cycle
+    pub fn refresh(&mut self) {
+        let (num, den) = self.clock_ratio();
+        self.phase += core::mem::take(&mut self.pending) * num;
+        let mut cycles = self.phase / den;
+        self.phase %= den;
+        let Some(game_boy) = &mut self.game_boy else {
+            return;
+        };
+        if self.control & 0x80 == 0 {
+            return;
+        }
+        let skip = cycles.min(self.overrun);
+        self.overrun -= skip;
+        cycles -= skip;
+        if cycles > 0 {
+            let cycles = cycles.min(u32::MAX.into()) as u32;
+            let run = game_boy.run(cycles, &mut self.port);
+            self.overrun += u64::from(run.saturating_sub(cycles))
+        }
+    }
+}
+
+impl Clone for Icd2 {
+    fn clone(&self) -> Self {
+        Self {
+            game_boy: self
+                .game_boy
+                .as_ref()
+                .map(|game_boy| game_boy.boxed_clone()),
+            port: self.port.clone(),
+            ..*self
+        }
+    }
+}
+
+impl Default for Icd2 {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl std::fmt::Debug for Icd2 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Icd2")
+            .field("connected", &self.is_connected())
+            .field("sgb2", &self.sgb2)
+            .field("control", &self.control)
+            .field("line", &self.port.line)
+            .field("command", &Command::from_header(self.packet[0]))
+            .finish_non_exhaustive()
+    }
+}
+
+impl InSaveState for Icd2 {
+    fn serialize(&self, state: &mut SaveStateSerializer) {
+        self.port.serialize(state);
+        self.control.serialize(state);
+        self.read_row.serialize(state);
+        self.read_index.serialize(state);
+        self.packet.serialize(state);
+        self.pending.serialize(state);
+        self.phase.serialize(state);
+        self.overrun.serialize(state);
+        let data = self.game_boy.as_ref().map(|game_boy| game_boy.save_state());
+        data.unwrap_or_default().serialize(state);
+    }
+
+    fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+        self.port.deserialize(state);
+        self.control.deserialize(state);
+        self.read_row.deserialize(state);
+        self.read_index.deserialize(state);
+        self.packet.deserialize(state);
+        self.pending.deserialize(state);
+        self.phase.deserialize(state);
+        self.overrun.deserialize(state);
+        if !self.port.is_valid()
+            || usize::from(self.read_row) >= ROW_COUNT
+            || usize::from(self.read_index) > ROW_SIZE
+        {
+            return state.fail(save_state::DeserializeError::InvalidValue);
+        }
+        let mut data: Vec<u8> = Vec::new();
+        data.deserialize(state);
+        if let Some(game_boy) = &mut self.game_boy {
+            game_boy.load_state(&data)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,190 @@
+use super::*;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default)]
+struct Log {
+    cycles: u64,
+    resets: u32,
+}
+
+/// A Game Boy, which sends packets and draws lines when it runs
+#[derive(Clone, Default)]
+struct TestGameBoy {
+    log: Arc<Mutex<Log>>,
+    packets: Vec<([u8; PACKET_SIZE], bool)>,
+    lines: u8,
+}
+
+impl TestGameBoy {
+    fn send_packet(port: &mut dyn Icd2Port, packet: &[u8; PACKET_SIZE], stop: bool) {
+        port.write_joypad(0x00);
+        port.write_joypad(0x30);
+        let bits = (0..PACKET_SIZE * 8).map(|i| packet[i >> 3] & (1 << (i & 7)) != 0);
+        for bit in bits.chain([stop]) {
+            port.write_joypad(if bit { 0x10 } else { 0x20 });
+            port.write_joypad(0x30);
+        }
+    }
+}
+
+impl GameBoy for TestGameBoy {
+    fn reset(&mut self) {
+        self.log.lock().unwrap().resets += 1
+    }
+
+    fn run(&mut self, cycles: u32, icd2: &mut dyn Icd2Port) -> u32 {
+        self.log.lock().unwrap().cycles += u64::from(cycles);
+        for (packet, stop) in self.packets.drain(..) {
+            Self::send_packet(icd2, &packet, stop)
+        }
+        for y in 0..core::mem::take(&mut self.lines) {
+            let pixels = core::array::from_fn(|x| (x as u8).wrapping_add(y) & 3);
+            icd2.draw_line(y, &pixels)
+        }
+        cycles
+    }
+
+    fn boxed_clone(&self) -> Box<dyn GameBoy> {
+        Box::new(self.clone())
+    }
+}
+
+fn reg(addr: u16) -> Addr24 {
+    Addr24::new(0x00, addr)
+}
+
+fn connect(icd2: &mut Icd2, game_boy: TestGameBoy) -> Arc<Mutex<Log>> {
+    let log = game_boy.log.clone();
+    icd2.connect(Box::new(game_boy));
+    icd2.write(reg(0x6003), 0x81);
+    log
+}
+
+#[test]
+fn command_packets() {
+    let mut pal01 = [0; PACKET_SIZE];
+    pal01[0] = 0x01;
+    pal01[1..5].copy_from_slice(&[0xff, 0x7f, 0x1f, 0x00]);
+    let mut icd2 = Icd2::new(false);
+    connect(
+        &mut icd2,
+        TestGameBoy {
+            // the second packet has an invalid stop bit
+            packets: vec![(pal01, false), ([0x89; PACKET_SIZE], true)],
+            ..Default::default()
+        },
+    );
+    icd2.tick(RUN_THRESHOLD);
+    assert_eq!(icd2.read(reg(0x6002)), Some(1));
+    let packet: Vec<u8> = (0x7000..0x7010)
+        .map(|a| icd2.read(reg(a)).unwrap())
+        .collect();
+    assert_eq!(packet, pal01);
+    assert_eq!(Command::from_header(packet[0]), Some(Command::Pal01));
+    assert_eq!(icd2.read(reg(0x6002)), Some(0));
+    assert_eq!(Command::from_header(0x89), Some(Command::MltReq));
+    assert_eq!(Command::from_header(0xd1), None);
+}
+
+#[test]
+fn character_rows() {
+    let mut icd2 = Icd2::new(false);
+    connect(
+        &mut icd2,
+        TestGameBoy {
+            lines: 9,
+            ..Default::default()
+        },
+    );
+    assert_eq!(icd2.read(reg(0x6000)), Some(0));
+    icd2.refresh();
+    icd2.tick(RUN_THRESHOLD);
+    // the ninth line is drawn into the second buffer
+    assert_eq!(icd2.read(reg(0x6000)), Some(0x09));
+    icd2.write(reg(0x6001), 0);
+    let row: Vec<u8> = (0..ROW_SIZE + 1)
+        .map(|_| icd2.read(reg(0x7800)).unwrap())
+        .collect();
+    // the shades 0, 1, 2, 3 and 1, 2, 3, 0 repeat in the first two lines
+    assert_eq!(row[..4], [0x55, 0x33, 0xaa, 0x66]);
+    assert_eq!(row[16..20], row[..4]);
+    assert_eq!(row[ROW_SIZE], 0);
+    icd2.write(reg(0x6001), 1);
+    assert_eq!(icd2.read(reg(0x7800)), Some(0x55));
+    assert_eq!(icd2.read(reg(0x600f)), Some(VERSION));
+    assert_eq!(icd2.read(Addr24::new(0x40, 0x6000)), None);
+}
+
+#[test]
+fn joypads() {
+    let mut icd2 = Icd2::new(false);
+    // right and A are pressed
+    icd2.write(reg(0x6004), 0xee);
+    // start is pressed on the second joypad
+    icd2.write(reg(0x6005), 0x7f);
+    let read = |icd2: &mut Icd2, p1| {
+        icd2.port.write_joypad(p1);
+        icd2.port.read_joypad()
+    };
+    assert_eq!(read(&mut icd2, 0x20), 0xe);
+    assert_eq!(read(&mut icd2, 0x10), 0xe);
+    assert_eq!(read(&mut icd2, 0x00), 0xe);
+    assert_eq!(read(&mut icd2, 0x30), 0xf);
+    // two joypads are read alternately
+    icd2.write(reg(0x6003), 0x90);
+    assert_eq!(read(&mut icd2, 0x10), 0xe);
+    assert_eq!(read(&mut icd2, 0x30), 0xe);
+    assert_eq!(read(&mut icd2, 0x30), 0xe);
+    assert_eq!(read(&mut icd2, 0x10), 0x7);
+    assert_eq!(read(&mut icd2, 0x30), 0xf);
+}
+
+#[test]
+fn clock_and_reset() {
+    let mut icd2 = Icd2::new(false);
+    let log = connect(&mut icd2, TestGameBoy::default());
+    icd2.tick(5000);
+    assert_eq!(log.lock().unwrap().cycles, 1000);
+    // the Game Boy is held in reset
+    icd2.write(reg(0x6003), 0x00);
+    icd2.tick(10000);
+    assert_eq!(log.lock().unwrap().resets, 2);
+    assert_eq!(log.lock().unwrap().cycles, 1000);
+    icd2.write(reg(0x6003), 0x83);
+    icd2.tick(9000);
+    assert_eq!(log.lock().unwrap().cycles, 2000);
+
+    // the Super Game Boy 2 has its own oscillator
+    let mut icd2 = Icd2::new(true);
+    let log = connect(&mut icd2, TestGameBoy::default());
+    icd2.tick(21_477_272);
+    assert_eq!(log.lock().unwrap().cycles, 4_194_303);
+}
+
+#[test]
+fn save_state() {
+    let mut icd2 = Icd2::new(false);
+    icd2.write(reg(0x6004), 0x12);
+    icd2.write(reg(0x6003), 0x81);
+    let mut ser = SaveStateSerializer::new();
+    icd2.serialize(&mut ser);
+    let mut restored = Icd2::new(false);
+    restored.deserialize(&mut SaveStateDeserializer::new(ser.data()));
+    assert_eq!(restored.port.joypads, icd2.port.joypads);
+    assert_eq!(restored.control, 0x81);
+}
+
+#[test]
+fn invalid_save_state() {
+    let mut icd2 = Icd2::new(false);
+    icd2.port.joypad_id = 0xff;
+    let mut ser = SaveStateSerializer::new();
+    icd2.serialize(&mut ser);
+    let mut restored = Icd2::new(false);
+    assert_eq!(
+        save_state::deserialize_checked(&mut restored, ser.data()),
+        Err(save_state::DeserializeError::InvalidValue)
+    );
+    assert_eq!(restored.port.joypad_id, 0);
+    assert_eq!(restored.port.read_joypad(), 0xf);
+}
@@ -0,0 +1,295 @@
+//! Sharp S-RTC real-time clock handling types
+//!
+//! The S-RTC is only used by Daikaijuu Monogatari II. Its registers are
+//! mapped to `$00-3F:2800-2801` and `$80-BF:2800-2801`. The time is
+//! transferred as 13 BCD digits: the second, minute, hour and day
+//! (two digits each), the month, the year (three digits, counted from 1000)
+//! and the day of the week.
+//!
+//! The clock runs in emulated time. When the battery backed state gets
+//! restored, the wall clock time passed since it was stored is added.
+//!
+//! # Literature
+//!
+//! - <https://problemkaputt.de/fullsnes.htm>
+
+use super::calendar;
+use crate::device::{Addr24, Region};
+use save_state_macro::*;
+
+/// Size of the battery backed state, which is appended to the SRAM in SRAM files
+pub const BATTERY_SIZE: usize = 16;
+/// The amount of digits of the time
+const DIGIT_COUNT: i8 = 13;
+
+mod modes {
+    pub const READY: u8 = 0;
+    pub const COMMAND: u8 = 1;
+    pub const READ: u8 = 2;
+    pub const WRITE: u8 = 3;
+}
+
+#[derive(Debug, Clone, InSaveState)]
+pub struct Srtc {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    /// Years since 1000, the chip counts up to 12 bits
+    year: u16,
+    /// Day of the week (0 = sunday)
+    weekday: u8,
+    mode: u8,
+    /// The transferred digit, -1 is the start marker
+    index: i8,
+    /// Master cycles multiplied by the denominator of the master clock
+    cycles: u64,
+    region: Region,
+    /// The time was restored from the battery backed state, so it must not
+    /// be replaced by the wall clock time
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    restored: bool,
+}
+
+impl Default for Srtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Srtc {
+    pub const fn new() -> Self {
+        Self {
+            second: 0,
+            minute: 0,
+            hour: 0,
+            day: 1,
+            month: 1,
+            year: 0,
+            weekday: 0,
+            mode: modes::READ,
+            index: -1,
+            cycles: 0,
+            region: Region::Ntsc,
+            restored: false,
+        }
+    }
+
+    pub fn set_region(&mut self, pal: bool) {
+        self.region = if pal { Region::Pal } else { Region::Ntsc }
+    }
+
+    const fn is_register(addr: Addr24) -> bool {
+        addr.bank & 0x40 == 0 && addr.addr & 0xfffe == 0x2800
+    }
+
+    /// The date and time in seconds since the unix epoch
+    fn unix_time(&self) -> i64 {
+        let year = 1000 + i64::from(self.year);
+        let days = calendar::days_from_civil(year, self.month, self.day);
+        let secs =
+            u32::from(self.hour) * 3600 + u32::from(self.minute) * 60 + u32::from(self.second);
+        days * 86400 + i64::from(secs)
+    }
+
+    /// Set the date and time from seconds since the unix epoch
+    fn set_unix_time(&mut self, time: i64) {
+        let days = time.div_euclid(86400);
+        let secs = time.rem_euclid(86400);
+        let (year, month, day) = calendar::civil_from_days(days);
+        self.second = (secs % 60) as u8;
+        self.minute = (secs / 60 % 60) as u8;
+        self.hour = (secs / 3600) as u8;
+        self.day = day;
+        self.month = month;
+        self.year = (year - 1000).clamp(0, 0xfff) as u16;
+        self.weekday = calendar::weekday(days);
+    }
+
+    /// Set the clock to the wall clock time, unless it was restored
+    /// from the battery backed state
+    pub fn synchronize(&mut self, unix_time: u64) {
+        if !self.restored {
+            self.set_unix_time(unix_time as i64)
+        }
+    }
+
+    /// The battery backed state, which is stored together with the wall clock time
+    pub fn battery_data(&self, unix_time: u64) -> [u8; BATTERY_SIZE] {
+        let mut data = [0; BATTERY_SIZE];
+        data[..6].copy_from_slice(&[
+            self.second,
+            self.minute,
+            self.hour,
+            self.day,
+            self.month,
+            self.weekday,
+        ]);
+        data[6..8].copy_from_slice(&self.year.to_le_bytes());
+        data[8..].copy_from_slice(&unix_time.to_le_bytes());
+        data
+    }
+
+    /// Restore the battery backed state and add the wall clock time passed since it was stored
+    pub fn load_battery_data(&mut self, data: &[u8; BATTERY_SIZE], unix_time: u64) {
+        [
+            self.second,
+            self.minute,
+            self.hour,
+            self.day,
+            self.month,
+            self.weekday,
+        ] = data[..6].try_into().unwrap();
+        self.year = u16::from_le_bytes([data[6], data[7]]);
+        let stored = u64::from_le_bytes(data[8..].try_into().unwrap());
+        let passed = unix_time.saturating_sub(stored);
+        if passed > 0 {
+            self.set_unix_time(self.unix_time() + passed as i64)
+        }
+        self.restored = true;
+    }
+
+    fn read_digit(&self, index: i8) -> u8 {
+        match index {
+            0 => self.second % 10,
+            1 => self.second / 10,
+            2 => self.minute % 10,
+            3 => self.minute / 10,
+            4 => self.hour % 10,
+            5 => self.hour / 10,
+            6 => self.day % 10,
+            7 => self.day / 10,
+            8 => self.month,
+            9 => (self.year % 10) as u8,
+            10 => (self.year / 10 % 10) as u8,
+            11 => (self.year / 100) as u8,
+            _ => self.weekday,
+        }
+    }
+
+    fn write_digit(&mut self, index: i8, val: u8) {
+        let set_lo = |v: u8| v - v % 10 + val;
+        let set_hi = |v: u8| val * 10 + v % 10;
+        let year = self.year;
+        match index {
+            0 => self.second = set_lo(self.second),
+            1 => self.second = set_hi(self.second),
+            2 => self.minute = set_lo(self.minute),
+            3 => self.minute = set_hi(self.minute),
+            4 => self.hour = set_lo(self.hour),
+            5 => self.hour = set_hi(self.hour),
+            6 => self.day = set_lo(self.day),
+            7 => self.day = set_hi(self.day),
+            8 => self.month = val,
+            9 => self.year = year - year % 10 + u16::from(val),
+            10 => self.year = year / 100 * 100 + u16::from(val) * 10 + year % 10,
+            _ => self.year = u16::from(val) * 100 + year % 100,
+        }
+    }
+
+    /// Read the register at `$2800`
+    pub fn read(&mut self, addr: Addr24) -> Option<u8> {
+        if !Self::is_register(addr) {
+            return None;
+        }
+        Some(if addr.addr & 1 == 1 || self.mode != modes::READ {
+            0
+        } else if self.index < 0 {
+            self.index += 1;
+            15
+        } else if self.index >= DIGIT_COUNT {
+            self.index = -1;
+            15
+        } else {
+            self.index += 1;
+            self.read_digit(self.index - 1)
+        })
+    }
+
+    /// Write the register at `$2801`, returns false if `addr` is not a register
+    pub fn write(&mut self, addr: Addr24, val: u8) -> bool {
+        if !Self::is_register(addr) {
+            return false;
+        }
+        if addr.addr & 1 == 0 {
+            return true;
+        }
+        match (self.mode, val & 0xf) {
+            (_, 0xd) => {
+                self.mode = modes::READ;
+                self.index = -1;
+            }
+            (_, 0xe) => self.mode = modes::COMMAND,
+            (_, 0xf) => (),
+            (modes::COMMAND, 0) => {
+                self.mode = modes::WRITE;
+                self.index = 0;
+            }
+            (modes::COMMAND, 4) => {
+                self.mode = modes::READY;
+                self.index = -1;
+                [self.second, self.minute, self.hour, self.day, self.month] = [0; 5];
+                self.year = 0;
+                self.weekday = 0;
+            }
+            (modes::COMMAND, _) => self.mode = modes::READY,
+            (modes::WRITE, val) if (0..DIGIT_COUNT - 1).contains(&self.index) => {
+                self.write_digit(self.index, val);
+                self.index += 1;
+                if self.index == DIGIT_COUNT - 1 {
+                    // the day of the week gets calculated from the date
+                    let year = 1000 + i64::from(self.year);
+                    let days = calendar::days_from_civil(year, self.month, self.day);
+                    self.weekday = calendar::weekday(days);
+                }
+            }
+            _ => (),
+        }
+        true
+    }
+
+    fn tick_second(&mut self) {
+        self.second += 1;
+        if self.second < 60 {
+            return;
+        }
+        self.second = 0;
+        self.minute += 1;
+        if self.minute < 60 {
+            return;
+        }
+        self.minute = 0;
+        self.hour += 1;
+        if self.hour < 24 {
+            return;
+        }
+        self.hour = 0;
+        self.weekday = (self.weekday + 1) % 7;
+        let year = 1000 + i64::from(self.year);
+        if self.day < calendar::days_in_month(year, self.month) {
+            self.day += 1;
+            return;
+        }
+        self.day = 1;
+        if self.month < 12 {
+            self.month += 1;
+            return;
+        }
+        self.month = 1;
+        self.year = (self.year + 1) & 0xfff;
+    }
+
+    /// Tick in main CPU master cycles
+    pub fn tick(&mut self, n: u32) {
+        let (num, den) = self.region.master_clock();
+        self.cycles += u64::from(n) * den;
+        while self.cycles >= num {
+            self.cycles -= num;
+            self.tick_second()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,60 @@
+use super::*;
+
+const REG: Addr24 = Addr24::new(0x00, 0x2800);
+const CMD: Addr24 = Addr24::new(0x80, 0x2801);
+
+fn read_time(srtc: &mut Srtc) -> Vec<u8> {
+    srtc.write(CMD, 0xd);
+    (0..15).map(|_| srtc.read(REG).unwrap()).collect()
+}
+
+#[test]
+fn read_digits() {
+    let mut srtc = Srtc::new();
+    // 2021-03-04 05:06:07 UTC, a thursday
+    srtc.synchronize(1614834367);
+    assert_eq!(
+        read_time(&mut srtc),
+        [15, 7, 0, 6, 0, 5, 0, 4, 0, 3, 1, 2, 10, 4, 15]
+    );
+    assert_eq!(srtc.read(Addr24::new(0x40, 0x2800)), None);
+}
+
+#[test]
+fn write_digits() {
+    let mut srtc = Srtc::new();
+    srtc.write(CMD, 0xe);
+    srtc.write(CMD, 0);
+    // 1999-12-31 23:59:59
+    for digit in [9, 5, 9, 5, 3, 2, 1, 3, 12, 9, 9, 9] {
+        srtc.write(CMD, digit);
+    }
+    assert_eq!(
+        read_time(&mut srtc),
+        [15, 9, 5, 9, 5, 3, 2, 1, 3, 12, 9, 9, 9, 5, 15]
+    );
+    srtc.tick(236_250_000 / 11 + 1);
+    assert_eq!(
+        read_time(&mut srtc),
+        [15, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 10, 6, 15]
+    );
+    // reset the time
+    srtc.write(CMD, 0xe);
+    srtc.write(CMD, 4);
+    assert_eq!(srtc.read(REG), Some(0));
+}
+
+#[test]
+fn battery_data() {
+    let mut srtc = Srtc::new();
+    srtc.synchronize(1614834367);
+    let data = srtc.battery_data(1614834367);
+    let mut restored = Srtc::new();
+    restored.load_battery_data(&data, 1614834367 + 86400 + 61);
+    restored.synchronize(0);
+    // 2021-03-05 05:07:08, a friday
+    assert_eq!(
+        read_time(&mut restored),
+        [15, 8, 0, 7, 0, 5, 0, 5, 0, 3, 1, 2, 10, 5, 15]
+    );
+}
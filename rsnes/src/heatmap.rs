@@ -0,0 +1,203 @@
+//! Statistics of the memory accesses for profiling
+//!
+//! While a heatmap is enabled with [`crate::device::Device::enable_heatmap`],
+//! the reads and writes of the main CPU and the DMA are counted per 8 KiB page
+//! of the address space together with the master cycles they took. The
+//! instructions executed by the main CPU are counted at the page of their
+//! opcode and the accesses of the PPU registers `$2100-$213F` are counted per
+//! register. [`Heatmap::report`] summarizes the counts after a run.
+//! Heatmaps are not part of save states.
+
+use crate::device::{Addr24, Data};
+use crate::timing::Cycles;
+
+/// The size of a page in bytes
+pub const PAGE_SIZE: u16 = 0x2000;
+const PAGES: usize = 0x100 * (0x10000 / PAGE_SIZE as usize);
+/// The PPU registers `$2100 + n` with `n < PPU_REGISTERS` are counted
+pub const PPU_REGISTERS: u8 = 0x40;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AccessCounts {
+    pub reads: u64,
+    pub writes: u64,
+    /// Instructions, whose opcode was fetched from the page
+    pub executes: u64,
+    /// Master cycles of the reads and writes
+    pub cycles: u64,
+}
+
+impl AccessCounts {
+    pub const fn total(&self) -> u64 {
+        self.reads + self.writes + self.executes
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    pages: Box<[AccessCounts]>,
+    ppu_registers: [AccessCounts; PPU_REGISTERS as usize],
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const fn page_index(addr: Addr24) -> usize {
+    ((addr.bank as usize) << 3) | (addr.addr / PAGE_SIZE) as usize
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Self {
+            pages: vec![AccessCounts::default(); PAGES].into_boxed_slice(),
+            ppu_registers: [AccessCounts::default(); PPU_REGISTERS as usize],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new()
+    }
+
+    /// The counts of the page containing `addr`
+    pub fn page(&self, addr: Addr24) -> &AccessCounts {
+        &self.pages[page_index(addr)]
+    }
+
+    /// The counts of the PPU register `$2100 + reg`
+    pub fn ppu_register(&self, reg: u8) -> Option<&AccessCounts> {
+        self.ppu_registers.get(usize::from(reg))
+    }
+
+    /// Count the access of the bytes of `D` at `addr`, which take
+    /// `cycles(addr)` master cycles each
+    pub(crate) fn record<D: Data>(
+        &mut self,
+        write: bool,
+        mut addr: Addr24,
+        cycles: impl Fn(Addr24) -> Cycles,
+    ) {
+        for _ in 0..core::mem::size_of::<D::Arr>() {
+            let page = &mut self.pages[page_index(addr)];
+            if write {
+                page.writes += 1
+            } else {
+                page.reads += 1
+            }
+            page.cycles += u64::from(cycles(addr));
+            addr.addr = addr.addr.wrapping_add(1);
+        }
+    }
+
+    pub(crate) fn record_execute(&mut self, pc: Addr24) {
+        self.pages[page_index(pc)].executes += 1
+    }
+
+    /// Count an access of the B bus address `reg`
+    pub(crate) fn record_bus_b<D: Data>(&mut self, write: bool, reg: u8) {
+        for i in 0..core::mem::size_of::<D::Arr>() as u8 {
+            if let Some(counts) = self.ppu_registers.get_mut(usize::from(reg.wrapping_add(i))) {
+                if write {
+                    counts.writes += 1
+                } else {
+                    counts.reads += 1
+                }
+            }
+        }
+    }
+
+    /// Summarize the accessed pages and PPU registers
+    pub fn report(&self) -> HeatmapReport {
+        let pages = (0..PAGES)
+            .filter(|&i| !self.pages[i].is_empty())
+            .map(|i| PageReport {
+                start: Addr24::new((i >> 3) as u8, (i as u16 & 7) * PAGE_SIZE),
+                counts: self.pages[i],
+            })
+            .collect();
+        let ppu_registers = (0..PPU_REGISTERS)
+            .filter(|&reg| !self.ppu_registers[usize::from(reg)].is_empty())
+            .map(|reg| RegisterReport {
+                addr: 0x2100 | u16::from(reg),
+                counts: self.ppu_registers[usize::from(reg)],
+            })
+            .collect();
+        HeatmapReport {
+            pages,
+            ppu_registers,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageReport {
+    /// The first address of the page
+    pub start: Addr24,
+    pub counts: AccessCounts,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterReport {
+    pub addr: u16,
+    pub counts: AccessCounts,
+}
+
+/// The accessed pages and PPU registers of a [`Heatmap`] in the order of their addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeatmapReport {
+    pub pages: Vec<PageReport>,
+    pub ppu_registers: Vec<RegisterReport>,
+}
+
+impl HeatmapReport {
+    /// The `count` pages, in which the most master cycles were spent
+    pub fn busiest_pages(&self, count: usize) -> Vec<PageReport> {
+        let mut pages = self.pages.clone();
+        pages.sort_by_key(|page| core::cmp::Reverse(page.counts.cycles));
+        pages.truncate(count);
+        pages
+    }
+}
+
+impl std::fmt::Display for HeatmapReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let row = |f: &mut std::fmt::Formatter, columns: &[&dyn std::fmt::Display]| {
+            write!(f, "{:<8}", columns[0].to_string())?;
+            for column in &columns[1..] {
+                write!(f, "  {:>10}", column.to_string())?;
+            }
+            writeln!(f)
+        };
+        row(f, &[&"page", &"reads", &"writes", &"executes", &"cycles"])?;
+        for PageReport { start, counts } in &self.pages {
+            let c = counts;
+            row(
+                f,
+                &[
+                    &format!("${}", start),
+                    &c.reads,
+                    &c.writes,
+                    &c.executes,
+                    &c.cycles,
+                ],
+            )?;
+        }
+        row(f, &[&"register", &"reads", &"writes"])?;
+        for RegisterReport { addr, counts } in &self.ppu_registers {
+            row(
+                f,
+                &[&format!("${:04x}", addr), &counts.reads, &counts.writes],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
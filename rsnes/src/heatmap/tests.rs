@@ -0,0 +1,39 @@
+use super::*;
+
+#[test]
+fn count_accesses() {
+    let mut heatmap = Heatmap::new();
+    let cycles = |addr: Addr24| if addr.bank == 0x7e { 8 } else { 6 };
+    heatmap.record::<u16>(false, Addr24::new(0x7e, 0x1fff), cycles);
+    heatmap.record::<u8>(true, Addr24::new(0x7e, 0x0000), cycles);
+    heatmap.record_execute(Addr24::new(0x80, 0x8000));
+    heatmap.record_bus_b::<u16>(true, 0x18);
+    heatmap.record_bus_b::<u8>(false, 0x40);
+    assert_eq!(
+        *heatmap.page(Addr24::new(0x7e, 0x1234)),
+        AccessCounts {
+            reads: 1,
+            writes: 1,
+            executes: 0,
+            cycles: 16,
+        }
+    );
+    assert_eq!(heatmap.page(Addr24::new(0x7e, 0x2000)).reads, 1);
+    assert_eq!(heatmap.ppu_register(0x19).unwrap().writes, 1);
+    assert_eq!(heatmap.ppu_register(0x40), None);
+
+    let report = heatmap.report();
+    let starts: Vec<_> = report.pages.iter().map(|page| page.start).collect();
+    assert_eq!(
+        starts,
+        [
+            Addr24::new(0x7e, 0x0000),
+            Addr24::new(0x7e, 0x2000),
+            Addr24::new(0x80, 0x8000)
+        ]
+    );
+    assert_eq!(report.busiest_pages(1)[0].start, Addr24::new(0x7e, 0x0000));
+    let registers: Vec<_> = report.ppu_registers.iter().map(|reg| reg.addr).collect();
+    assert_eq!(registers, [0x2118, 0x2119]);
+    assert!(report.to_string().contains("$7e:2000"));
+}
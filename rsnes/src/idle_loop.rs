@@ -0,0 +1,78 @@
+//! Idle loop detection, a speed hack
+//!
+//! Many games wait for the NMI in a loop, which polls a variable in the work
+//! RAM (e.g. `loop: lda $10; beq loop`) or just branches to itself. If the
+//! registers are the same after two iterations and the loop only read the
+//! work RAM and the cartridge memory, the loop can't end before an interrupt
+//! changes the memory. The main CPU is treated like waiting after WAI then,
+//! so the cycles until the next event get skipped.
+//!
+//! The skipped cycles change the timing of the interrupts relative to the
+//! loop, so the detection is disabled by default,
+//! see [`crate::device::DeviceOptions::skip_idle_loops`].
+
+use crate::{
+    backend::{AudioBackend, FrameBuffer},
+    cpu::Regs,
+    device::{Addr24, Device},
+};
+
+/// The maximum length of an idle loop in bytes
+const MAX_LOOP_LENGTH: u16 = 16;
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct IdleLoop {
+    /// The address of the jump back to the loop start and the registers after the jump
+    iteration: Option<(Addr24, Regs)>,
+    /// The main CPU only read plain memory since the last jump back
+    pure: bool,
+    /// The main CPU spins in the loop until an interrupt occurs
+    pub(crate) detected: bool,
+}
+
+impl IdleLoop {
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default()
+    }
+}
+
+impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
+    /// Record a memory access of the main CPU, which ends the current
+    /// iteration of an idle loop unless it reads plain memory
+    pub(crate) fn record_idle_loop_access(&mut self, addr: Addr24, write: bool) {
+        if self.idle_loop.pure && (write || !self.is_plain_memory(addr)) {
+            self.idle_loop.pure = false;
+        }
+    }
+
+    /// Test if reading `addr` has no side effects and returns a value, which
+    /// only changes by writes of the CPU or DMA, i.e. the work RAM, ROM or SRAM
+    fn is_plain_memory(&self, addr: Addr24) -> bool {
+        if (0x7e..=0x7f).contains(&addr.bank) {
+            return true;
+        }
+        let system_bank = addr.bank & 0x40 == 0;
+        if system_bank && addr.addr < 0x2000 {
+            return true;
+        }
+        if system_bank && matches!(addr.addr, 0x2100..=0x21ff | 0x4000..=0x43ff) {
+            return false;
+        }
+        self.cartridge
+            .as_ref()
+            .is_some_and(|cartridge| cartridge.is_plain_memory(addr))
+    }
+
+    /// Check if the instruction at `pc`, which was just executed,
+    /// jumped back to the start of an idle loop
+    pub(crate) fn detect_idle_loop(&mut self, pc: Addr24) {
+        let next = self.cpu.regs.pc;
+        if next.bank != pc.bank || next.addr > pc.addr || pc.addr - next.addr >= MAX_LOOP_LENGTH {
+            return;
+        }
+        let iteration = Some((pc, self.cpu.regs.clone()));
+        self.idle_loop.detected = self.idle_loop.pure && self.idle_loop.iteration == iteration;
+        self.idle_loop.iteration = iteration;
+        self.idle_loop.pure = true;
+    }
+}
@@ -1,4 +1,6 @@
-use crate::cpu::{Cpu, Status};
+#[cfg(feature = "call-stack")]
+use crate::cpu::CallKind;
+use crate::cpu::{Cpu, Interrupt, Status};
 use crate::device::{Addr24, Data, Device};
 use crate::timing::Cycles;
 
@@ -11,8 +13,8 @@ static CYCLES: [Cycles; 256] = [
        2, 5, 5, 7, 5, 4, 6, 6,   2, 4, 2, 2, 6, 4, 7, 5,  // 1^
        6, 6, 8, 4, 3, 3, 5, 6,   4, 2, 2, 5, 4, 4, 6, 5,  // 2^
        2, 5, 5, 7, 4, 4, 6, 6,   2, 4, 2, 2, 4, 4, 7, 5,  // 3^
-       6, 6, 2, 4, 1, 3, 5, 6,   3, 2, 2, 3, 3, 4, 6, 5,  // 4^
-       2, 5, 5, 7, 1, 4, 6, 6,   2, 4, 3, 2, 4, 4, 7, 5,  // 5^
+       6, 6, 2, 4, 7, 3, 5, 6,   3, 2, 2, 3, 3, 4, 6, 5,  // 4^
+       2, 5, 5, 7, 7, 4, 6, 6,   2, 4, 3, 2, 4, 4, 7, 5,  // 5^
        6, 6, 6, 4, 3, 3, 5, 6,   4, 2, 2, 6, 5, 4, 6, 5,  // 6^
        2, 5, 5, 7, 4, 4, 6, 6,   2, 4, 4, 2, 6, 4, 7, 5,  // 7^
        2, 6, 4, 4, 3, 3, 3, 6,   2, 2, 2, 3, 4, 4, 4, 5,  // 8^
@@ -30,7 +32,7 @@ macro_rules! compare_memory {
     (CPX: $($t:tt)*) => {compare_memory!([x, x8, is_idx8]: $($t)*)};
     (CPY: $($t:tt)*) => {compare_memory!([y, y8, is_idx8]: $($t)*)};
     ([$r:ident, $r8:ident, $is8:ident]: $self:ident, $addr:expr, $cycles:expr) => {{
-        // this will also work with decimal mode (TODO: check this fact)
+        // comparisons are binary, even in decimal mode
         if $self.cpu().$is8() {
             let val = $self.read::<u8>($addr);
             $self.compare8($self.cpu().regs.$r8() as u8, val);
@@ -42,6 +44,23 @@ macro_rules! compare_memory {
     }};
 }
 
+/// Adjust the decimal digit at bit `shift` of a sum of BCD numbers, whose lower
+/// digits are adjusted already. The digit of a difference is adjusted downwards.
+fn decimal_adjust<const SUB: bool>(res: i32, shift: u32) -> i32 {
+    let low = (1 << shift) - 1;
+    if SUB {
+        if res <= (0xf << shift) | low {
+            res - (6 << shift)
+        } else {
+            res
+        }
+    } else if res > (9 << shift) | low {
+        res + (6 << shift)
+    } else {
+        res
+    }
+}
+
 pub trait AccessType<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> {
     fn read<D: Data>(device: &mut Device<B, FB>, addr: Addr24) -> D;
     fn write<D: Data>(device: &mut Device<B, FB>, addr: Addr24, val: D);
@@ -58,10 +77,16 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> AccessTyp
     for AccessTypeMain
 {
     fn read<D: Data>(device: &mut Device<B, FB>, addr: Addr24) -> D {
+        if device.options().skip_idle_loops {
+            device.record_idle_loop_access(addr, false)
+        }
         device.read::<D>(addr)
     }
 
     fn write<D: Data>(device: &mut Device<B, FB>, addr: Addr24, val: D) {
+        if device.options().skip_idle_loops {
+            device.record_idle_loop_access(addr, true)
+        }
         device.write::<D>(addr, val)
     }
 
@@ -78,6 +103,38 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> AccessTyp
     }
 }
 
+/// Access type of debuggers, which accesses the memory without side effects
+/// by [`Device::peek`] and [`Device::poke`]
+pub struct AccessTypeDebug;
+
+impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> AccessType<B, FB>
+    for AccessTypeDebug
+{
+    fn read<D: Data>(device: &mut Device<B, FB>, mut addr: Addr24) -> D {
+        let mut arr: D::Arr = Default::default();
+        for v in arr.as_mut() {
+            *v = device.peek(addr);
+            addr.addr = addr.addr.wrapping_add(1);
+        }
+        D::from_bytes(&arr)
+    }
+
+    fn write<D: Data>(device: &mut Device<B, FB>, mut addr: Addr24, val: D) {
+        for &v in val.to_bytes().as_ref() {
+            device.poke(addr, v);
+            addr.addr = addr.addr.wrapping_add(1);
+        }
+    }
+
+    fn cpu(device: &Device<B, FB>) -> &Cpu {
+        &device.cpu
+    }
+
+    fn cpu_mut(device: &mut Device<B, FB>) -> &mut Cpu {
+        &mut device.cpu
+    }
+}
+
 pub(crate) fn create_device_access<
     'a,
     T: AccessType<B, FB>,
@@ -151,6 +208,59 @@ impl<
         }
         D::from_bytes(&arr)
     }
+
+    /// Push data on the stack like the instructions new to the 65816 do.
+    ///
+    /// In emulation mode they may access the stack outside of page 1,
+    /// which is only enforced after the instruction, see [`Self::confine_stack`].
+    pub fn push_unconfined<D: Data>(&mut self, val: D) {
+        for d in val.to_bytes().as_ref().iter().rev() {
+            self.write(Addr24::new(0, self.cpu().regs.sp), *d);
+            self.cpu_mut().regs.sp = self.cpu().regs.sp.wrapping_sub(1);
+        }
+    }
+
+    /// Pull data from the stack like the instructions new to the 65816 do,
+    /// see [`Self::push_unconfined`]
+    pub fn pull_unconfined<D: Data>(&mut self) -> D {
+        let mut arr = D::Arr::default();
+        for d in arr.as_mut() {
+            self.cpu_mut().regs.sp = self.cpu().regs.sp.wrapping_add(1);
+            *d = self.read(Addr24::new(0, self.cpu().regs.sp));
+        }
+        D::from_bytes(&arr)
+    }
+
+    /// Move the stack pointer back to page 1 in emulation mode
+    pub fn confine_stack(&mut self) {
+        if self.cpu().regs.is_emulation {
+            self.cpu_mut().regs.sp = (self.cpu().regs.sp & 0xff) | 0x100
+        }
+    }
+
+    /// The address of the byte `offset` (including an index) in the direct page.
+    ///
+    /// In emulation mode with the low byte of D being zero, the address wraps
+    /// around within the direct page like in the zero page of the 6502.
+    pub fn direct_addr(&self, offset: u16) -> Addr24 {
+        let dp = self.cpu().regs.dp;
+        Addr24::new(
+            0,
+            if self.cpu().regs.is_emulation && dp & 0xff == 0 {
+                dp | (offset & 0xff)
+            } else {
+                dp.wrapping_add(offset)
+            },
+        )
+    }
+
+    /// Read the 16-bit pointer at the byte `offset` of the direct page,
+    /// whose bytes wrap around like in [`Self::direct_addr`]
+    fn read_direct_pointer(&mut self, offset: u16) -> u16 {
+        let low = self.read::<u8>(self.direct_addr(offset));
+        let high = self.read::<u8>(self.direct_addr(offset.wrapping_add(1)));
+        u16::from_le_bytes([low, high])
+    }
 }
 
 impl<
@@ -199,7 +309,7 @@ impl<
         if self.cpu().regs.dp & 0xff > 0 {
             *cycles += 1
         }
-        let addr = self.read(Addr24::new(0, self.cpu().regs.dp.wrapping_add(addr.into())));
+        let addr = self.read_direct_pointer(addr.into());
         self.cpu().get_data_addr(addr)
     }
 
@@ -217,14 +327,7 @@ impl<
         if self.cpu().regs.dp & 0xff > 0 {
             *cycles += 1
         }
-        Addr24::new(
-            0,
-            self.cpu()
-                .regs
-                .dp
-                .wrapping_add(addr.into())
-                .wrapping_add(val),
-        )
+        self.direct_addr(u16::from(addr).wrapping_add(val))
     }
 
     /// DP Indexed, X
@@ -272,7 +375,7 @@ impl<
         if self.cpu().regs.dp & 0xff > 0 {
             *cycles += 1
         }
-        Addr24::new(0, self.cpu().regs.dp.wrapping_add(val.into()))
+        self.direct_addr(val.into())
     }
 
     /// DP Indexed Indirect, X
@@ -281,17 +384,12 @@ impl<
         if self.cpu().regs.dp & 0xff > 0 {
             *cycles += 1
         }
-        let addr = self
-            .cpu()
-            .regs
-            .dp
-            .wrapping_add(if self.cpu().is_idx8() {
-                self.cpu().regs.x8().into()
-            } else {
-                self.cpu().regs.x
-            })
-            .wrapping_add(val.into());
-        let addr = self.read(Addr24::new(0, addr));
+        let x = if self.cpu().is_idx8() {
+            self.cpu().regs.x8().into()
+        } else {
+            self.cpu().regs.x
+        };
+        let addr = self.read_direct_pointer(x.wrapping_add(val.into()));
         self.cpu().get_data_addr(addr)
     }
 
@@ -322,8 +420,7 @@ impl<
         if self.cpu().regs.dp & 0xff > 0 {
             *cycles += 1
         }
-        let addr = addr.wrapping_add(self.cpu().regs.dp);
-        let addr = self.read::<u16>(Addr24::new(0, addr));
+        let addr = self.read_direct_pointer(addr);
         let y = if self.cpu().is_idx8() {
             self.cpu().regs.y & 0xff
         } else {
@@ -374,6 +471,11 @@ impl<
         &mut self,
         cycles: &mut Cycles,
     ) {
+        #[cfg(feature = "call-stack")]
+        let call_site = {
+            let pc = self.cpu().regs.pc;
+            Addr24::new(pc.bank, pc.addr.wrapping_sub(1))
+        };
         let _ = self.load::<u8>();
         let (pushed_status, vector) = if !self.cpu().regs.is_emulation {
             *cycles += 1;
@@ -386,13 +488,72 @@ impl<
         };
         self.push(self.cpu().regs.pc.addr);
         self.push(self.cpu().regs.status.0 | pushed_status);
+        let irq_disabled = self.cpu().regs.status.has(Status::IRQ_DISABLE);
         let s = (self.cpu().regs.status | Status::IRQ_DISABLE) & !Status::DECIMAL;
         self.cpu_mut().regs.status = s;
-        self.cpu_mut().regs.pc = Addr24::new(0, self.read(Addr24::new(0, vector)));
+        let interrupt = self.hijacking_interrupt(*cycles, irq_disabled);
+        let vector = match interrupt {
+            Some(Interrupt::Nmi) => {
+                self.cpu_mut().in_nmi = true;
+                self.get_nmi_vector()
+            }
+            Some(Interrupt::Irq) => self.get_irq_vector(),
+            None => self.read(Addr24::new(0, vector)),
+        };
+        self.cpu_mut().regs.pc = Addr24::new(0, vector);
+        #[cfg(feature = "call-stack")]
+        self.enter_call(
+            match interrupt {
+                Some(Interrupt::Nmi) => CallKind::Nmi,
+                Some(Interrupt::Irq) => CallKind::Irq,
+                None if BREAK_FLAG => CallKind::Brk,
+                None => CallKind::Cop,
+            },
+            call_site,
+        );
+    }
+
+    /// Record the call, which just jumped to the program counter
+    #[cfg(feature = "call-stack")]
+    fn enter_call(&mut self, kind: CallKind, call_site: Addr24) {
+        let cpu = self.cpu_mut();
+        let (target, sp) = (cpu.regs.pc, cpu.regs.sp);
+        cpu.call_stack.enter(kind, call_site, target, sp)
+    }
+
+    /// Record the return, which is about to pull the return address
+    #[cfg(feature = "call-stack")]
+    fn leave_call(&mut self, kind: CallKind) {
+        let cpu = self.cpu_mut();
+        let sp = cpu.regs.sp;
+        cpu.call_stack.leave(kind, sp)
+    }
+
+    /// The interrupt, which gets requested while BRK or COP push the return
+    /// address and status, before they fetch their vector.
+    ///
+    /// Like on the real 65816 the interrupt hijacks the instruction: the
+    /// vector of the interrupt is used instead and the interrupt is not
+    /// serviced another time afterwards.
+    fn hijacking_interrupt(&mut self, cycles: Cycles, irq_disabled: bool) -> Option<Interrupt> {
+        if !T::is_main() {
+            return None;
+        }
+        // the vector is fetched in the last two cycles
+        let elapsed = (cycles - 2) * 6 + self.0.memory_cycles;
+        if self.0.nmi_within(elapsed) {
+            self.0.nmi_hijacked = true;
+            Some(Interrupt::Nmi)
+        } else if !irq_disabled && self.0.timer_irq_within(elapsed) {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        }
     }
 
     pub fn dispatch_instruction_with(&mut self, start_addr: Addr24, op: u8) -> Cycles {
         let mut cycles = CYCLES[op as usize];
+        let irq_disabled = self.cpu().regs.status.has(Status::IRQ_DISABLE);
         match op {
             0x00 => {
                 // BRK - Break
@@ -509,7 +670,8 @@ impl<
             }
             0x0b => {
                 // PHD - Push Direct Page
-                self.push(self.cpu().regs.dp)
+                self.push_unconfined(self.cpu().regs.dp);
+                self.confine_stack()
             }
             0x0c => {
                 // TSB - Test and set Bits from Absolute
@@ -668,7 +830,9 @@ impl<
             }
             0x1b => {
                 // TCS - Transfer A to SP
-                self.cpu_mut().regs.sp = self.cpu().regs.a
+                self.cpu_mut().regs.sp = self.cpu().regs.a;
+                #[cfg(feature = "call-stack")]
+                self.cpu_mut().update_call_stack();
             }
             0x1c => {
                 // TRB - Test and Reset Bits from Absolute in A
@@ -732,6 +896,8 @@ impl<
                 self.push(start_addr.addr.wrapping_add(2));
                 let new_addr = self.load::<u16>();
                 self.cpu_mut().regs.pc.addr = new_addr;
+                #[cfg(feature = "call-stack")]
+                self.enter_call(CallKind::Jsr, start_addr);
             }
             0x21 => {
                 // AND - And A with DP Indexed Indirect, X
@@ -740,10 +906,13 @@ impl<
             }
             0x22 => {
                 // JSR/JSL - Jump to Subroutine Long
-                self.push(start_addr.bank);
-                self.push(start_addr.addr.wrapping_add(3));
+                self.push_unconfined(start_addr.bank);
+                self.push_unconfined(start_addr.addr.wrapping_add(3));
+                self.confine_stack();
                 let new_addr = self.load::<Addr24>();
                 self.cpu_mut().regs.pc = new_addr;
+                #[cfg(feature = "call-stack")]
+                self.enter_call(CallKind::Jsl, start_addr);
             }
             0x23 => {
                 // AND - And A with Stack Relative
@@ -810,7 +979,8 @@ impl<
             }
             0x2b => {
                 // PLD - Pull Direct Page Register
-                let dp = self.pull();
+                let dp = self.pull_unconfined();
+                self.confine_stack();
                 self.cpu_mut().regs.dp = dp;
                 self.cpu_mut().update_nz16(dp);
             }
@@ -925,6 +1095,8 @@ impl<
             }
             0x40 => {
                 // RTI - Return from interrupt
+                #[cfg(feature = "call-stack")]
+                self.leave_call(CallKind::Irq);
                 self.cpu_mut().in_nmi = false;
                 self.cpu_mut().regs.status.0 = self.pull();
                 self.cpu_mut().update_status();
@@ -1192,6 +1364,8 @@ impl<
             }
             0x60 => {
                 // RTS - Return from subroutine
+                #[cfg(feature = "call-stack")]
+                self.leave_call(CallKind::Jsr);
                 self.cpu_mut().regs.pc.addr = 1u16.wrapping_add(self.pull());
             }
             0x61 => {
@@ -1203,7 +1377,8 @@ impl<
                 // PER - Push PC + imm
                 let val = self.load::<u16>();
                 let val = self.cpu().regs.pc.addr.wrapping_add(val);
-                self.push(val)
+                self.push_unconfined(val);
+                self.confine_stack()
             }
             0x63 => {
                 // ADC - Stack Relative Add with Carry
@@ -1296,7 +1471,10 @@ impl<
             }
             0x6b => {
                 // RTL - Return from subroutine long
-                self.cpu_mut().regs.pc = self.pull();
+                #[cfg(feature = "call-stack")]
+                self.leave_call(CallKind::Jsl);
+                self.cpu_mut().regs.pc = self.pull_unconfined();
+                self.confine_stack();
                 self.cpu_mut().regs.pc.addr = self.cpu().regs.pc.addr.wrapping_add(1);
             }
             0x6c => {
@@ -1724,7 +1902,9 @@ impl<
             }
             0x9a => {
                 // TXS - Transfer X to SP
-                self.cpu_mut().regs.sp = self.cpu().regs.x
+                self.cpu_mut().regs.sp = self.cpu().regs.x;
+                #[cfg(feature = "call-stack")]
+                self.cpu_mut().update_call_stack();
             }
             0x9b => {
                 // TXY - Transfer X to Y
@@ -2304,7 +2484,7 @@ impl<
             }
             0xcf => {
                 // CMP - Compare A with Absolute Long
-                // this will also work with decimal mode (TODO: check this fact)
+                // comparisons are binary, even in decimal mode
                 let addr = self.load::<Addr24>();
                 compare_memory!(CMP: self, addr, &mut cycles)
             }
@@ -2314,7 +2494,7 @@ impl<
             }
             0xd1 => {
                 // CMP - Compare A with DP Indirect Indexed, Y
-                // this will also work with decimal mode (TODO: check this fact)
+                // comparisons are binary, even in decimal mode
                 let addr = self.load_indirect_indexed_y::<true>(&mut cycles);
                 compare_memory!(CMP: self, addr, &mut cycles)
             }
@@ -2332,7 +2512,8 @@ impl<
                 // PEI - Push 16-bit value from DP
                 let addr = self.load_direct(&mut cycles);
                 let val = self.read::<u16>(addr);
-                self.push(val);
+                self.push_unconfined(val);
+                self.confine_stack()
             }
             0xd5 => {
                 // CMP - Compare A with DP Indexed, X
@@ -2550,7 +2731,8 @@ impl<
             0xf4 => {
                 // PEA - Push absolute value
                 let addr = self.load::<u16>();
-                self.push(addr)
+                self.push_unconfined(addr);
+                self.confine_stack()
             }
             0xf5 => {
                 // SBC - Subtract DP Indexed, X with carry
@@ -2608,8 +2790,11 @@ impl<
             0xfc => {
                 // JSR - Jump to Subroutine
                 let addr = self.load_indexed_indirect();
-                self.push(start_addr.addr.wrapping_add(2));
+                self.push_unconfined(start_addr.addr.wrapping_add(2));
+                self.confine_stack();
                 self.cpu_mut().regs.pc = addr;
+                #[cfg(feature = "call-stack")]
+                self.enter_call(CallKind::Jsr, start_addr);
             }
             0xfd => {
                 // SBC - Subtract Absolute Indexed, X with carry
@@ -2636,9 +2821,22 @@ impl<
                 self.sub_carry_memory(addr, &mut cycles)
             }
         };
+        // CLI, SEI, PLP, REP and SEP change the flag after the interrupts were polled,
+        // while RTI restores it before
+        let irq_disabled = if matches!(op, 0x28 | 0x58 | 0x78 | 0xc2 | 0xe2) {
+            irq_disabled
+        } else {
+            self.cpu().regs.status.has(Status::IRQ_DISABLE)
+        };
+        self.cpu_mut().irq_disable_polled = irq_disabled;
         cycles
     }
 
+    /// Move a single byte of MVN or MVP, which costs 7 cycles.
+    ///
+    /// The instruction is executed again until all bytes are moved, because
+    /// the program counter is moved back to it. So the interrupts are polled
+    /// after every byte and the return address of an interrupt is the block move.
     fn block_move<const DELTA: u16>(&mut self) {
         let [dst, src] = self.load::<u16>().to_bytes();
         self.cpu_mut().regs.db = dst;
@@ -2728,149 +2926,84 @@ impl<
         }
     }
 
-    fn generic_add_carry8<const GT1: u8, const GT2: u16>(
-        &mut self,
-        op1: u8,
-        fu8: fn(u8, u8) -> u8,
-        gt8: fn(&u8, &u8) -> bool,
-        fu16: fn(u16, u16) -> u16,
-        gt16: fn(&u16, &u16) -> bool,
-    ) {
+    /// Add `op1` and the carry to the 8-bit accumulator.
+    ///
+    /// In decimal mode the digits are added one after another and adjusted
+    /// like on the 65816: the overflow flag is computed before the high digit
+    /// is adjusted, while the other flags result from the adjusted value.
+    /// SBC adds the inverted operand and adjusts the digits downwards.
+    fn generic_add_carry8<const SUB: bool>(&mut self, op1: u8) {
         let op2 = self.cpu().regs.a8();
-        if self.cpu().regs.status.has(Status::DECIMAL) {
-            let res = (op1 & 0xf)
-                .wrapping_add(op2 & 0xf)
-                .wrapping_add(self.cpu().regs.status.has(Status::CARRY) as _);
-            let res = if gt8(&res, &GT1) { fu8(res, 6) } else { res };
-            let carry = (res > 0xf) as u16;
-            let res = u16::from(op1 & 0xf0)
-                .wrapping_add((op2 & 0xf0).into())
-                .wrapping_add(carry << 4)
-                .wrapping_add((res & 0xf).into());
-            self.cpu_mut().regs.status.set_if(
-                Status::OVERFLOW,
-                !(u16::from(op1) ^ u16::from(op2)) & (u16::from(op2) ^ res) & 0x80 > 0,
-            );
-            let res = if gt16(&res, &GT2) {
-                fu16(res, 0x60)
-            } else {
-                res
-            };
-            self.cpu_mut().regs.status.set_if(Status::CARRY, res > 0xff);
-            let res = (res & 0xff) as u8;
-            self.cpu_mut().update_nz8(res);
-            self.cpu_mut().regs.set_a8(res);
+        let (a, b) = (i32::from(op2), i32::from(op1));
+        let mut carry = i32::from(self.cpu().regs.status.has(Status::CARRY));
+        let decimal = self.cpu().regs.status.has(Status::DECIMAL);
+        let mut res = if decimal {
+            let mut res = (a & 0xf) + (b & 0xf) + carry;
+            res = decimal_adjust::<SUB>(res, 0);
+            carry = i32::from(res > 0xf);
+            (a & 0xf0) + (b & 0xf0) + (carry << 4) + (res & 0xf)
         } else {
-            let (new, nc) = op1.overflowing_add(op2);
-            let (new, nc2) = new.overflowing_add(self.cpu().regs.status.has(Status::CARRY) as _);
-            let nc = nc ^ nc2;
-            self.cpu_mut().regs.status.set_if(Status::CARRY, nc);
-            let op1v = op1 & 128;
-            let v = op1v == (op2 & 128) && op1v != (new & 128);
-            self.cpu_mut().regs.status.set_if(Status::OVERFLOW, v);
-            self.cpu_mut().update_nz8(new);
-            self.cpu_mut().regs.set_a8(new);
+            a + b + carry
+        };
+        let v = !(a ^ b) & (a ^ res) & 0x80 != 0;
+        if decimal {
+            res = decimal_adjust::<SUB>(res, 4);
         }
+        let status = &mut self.cpu_mut().regs.status;
+        status.set_if(Status::OVERFLOW, v);
+        status.set_if(Status::CARRY, res > 0xff);
+        let res = res as u8;
+        self.cpu_mut().update_nz8(res);
+        self.cpu_mut().regs.set_a8(res);
     }
 
     pub fn add_carry8(&mut self, op1: u8) {
-        self.generic_add_carry8::<9, 0x9f>(
-            op1,
-            u8::wrapping_add,
-            u8::gt,
-            u16::wrapping_add,
-            u16::gt,
-        )
+        self.generic_add_carry8::<false>(op1)
     }
 
     pub fn sub_carry8(&mut self, op1: u8) {
-        self.generic_add_carry8::<0xf, 0xff>(
-            !op1,
-            u8::wrapping_sub,
-            u8::le,
-            u16::wrapping_sub,
-            u16::le,
-        )
+        self.generic_add_carry8::<true>(!op1)
     }
 
-    fn generic_add_carry16<const GT1: u16, const GT2: u16, const GT3: u16, const GT4: u32>(
-        &mut self,
-        op1: u16,
-        f: fn(u16, u16) -> u16,
-        gt: fn(&u16, &u16) -> bool,
-        fu32: fn(u32, u32) -> u32,
-        gt32: fn(&u32, &u32) -> bool,
-    ) {
+    /// Add `op1` and the carry to the 16-bit accumulator,
+    /// see [`Self::generic_add_carry8`]
+    fn generic_add_carry16<const SUB: bool>(&mut self, op1: u16) {
         let op2 = self.cpu().regs.a;
-        if self.cpu().regs.status.has(Status::DECIMAL) {
-            let res = (op1 & 0xf)
-                .wrapping_add(op2 & 0xf)
-                .wrapping_add(self.cpu().regs.status.has(Status::CARRY) as _);
-            let res = if gt(&res, &GT1) { f(res, 6) } else { res };
-            let carry = (res > 0xf) as u16;
-            let res = (op1 & 0xf0)
-                .wrapping_add(op2 & 0xf0)
-                .wrapping_add(carry << 4)
-                .wrapping_add(res & 0xf);
-            let res = if gt(&res, &GT2) { f(res, 0x60) } else { res };
-            let carry = (res > 0xff) as u16;
-            let res = (op1 & 0xf00)
-                .wrapping_add(op2 & 0xf00)
-                .wrapping_add(carry << 8)
-                .wrapping_add(res & 0xff);
-            let res = if gt(&res, &GT3) { f(res, 0x600) } else { res };
-            let carry = (res > 0xfff) as u32;
-            let res = u32::from(op1 & 0xf000)
-                .wrapping_add((op2 & 0xf000).into())
-                .wrapping_add(carry << 12)
-                .wrapping_add((res & 0xfff).into());
-            self.cpu_mut().regs.status.set_if(
-                Status::OVERFLOW,
-                !(u32::from(op1) ^ u32::from(op2)) & (u32::from(op2) ^ res) & 0x8000 > 0,
-            );
-            let res = if gt32(&res, &GT4) {
-                fu32(res, 0x6000)
-            } else {
-                res
-            };
-            self.cpu_mut()
-                .regs
-                .status
-                .set_if(Status::CARRY, res > 0xffff);
-            let res = (res & 0xffff) as u16;
-            self.cpu_mut().update_nz16(res);
-            self.cpu_mut().regs.a = res
+        let (a, b) = (i32::from(op2), i32::from(op1));
+        let mut carry = i32::from(self.cpu().regs.status.has(Status::CARRY));
+        let decimal = self.cpu().regs.status.has(Status::DECIMAL);
+        let mut res = if decimal {
+            let mut res = 0;
+            for shift in [0, 4, 8, 12] {
+                let digit = 0xf << shift;
+                res = (a & digit) + (b & digit) + (carry << shift) + (res & ((1 << shift) - 1));
+                if shift < 12 {
+                    res = decimal_adjust::<SUB>(res, shift);
+                    carry = i32::from(res > (0x10 << shift) - 1);
+                }
+            }
+            res
         } else {
-            let (new, nc) = op1.overflowing_add(op2);
-            let (new, nc2) = new.overflowing_add(self.cpu().regs.status.has(Status::CARRY) as _);
-            let nc = nc ^ nc2;
-            self.cpu_mut().regs.status.set_if(Status::CARRY, nc);
-            let op1v = op1 & 0x8000;
-            let v = op1v == (op2 & 0x8000) && op1v != (new & 0x8000);
-            self.cpu_mut().regs.status.set_if(Status::OVERFLOW, v);
-            self.cpu_mut().update_nz16(new);
-            self.cpu_mut().regs.a = new;
+            a + b + carry
+        };
+        let v = !(a ^ b) & (a ^ res) & 0x8000 != 0;
+        if decimal {
+            res = decimal_adjust::<SUB>(res, 12);
         }
+        let status = &mut self.cpu_mut().regs.status;
+        status.set_if(Status::OVERFLOW, v);
+        status.set_if(Status::CARRY, res > 0xffff);
+        let res = res as u16;
+        self.cpu_mut().update_nz16(res);
+        self.cpu_mut().regs.a = res
     }
 
     pub fn add_carry16(&mut self, op1: u16) {
-        self.generic_add_carry16::<9, 0x9f, 0x9ff, 0x9fff>(
-            op1,
-            u16::wrapping_add,
-            u16::gt,
-            u32::wrapping_add,
-            u32::gt,
-        )
+        self.generic_add_carry16::<false>(op1)
     }
 
     pub fn sub_carry16(&mut self, op1: u16) {
-        self.generic_add_carry16::<0xf, 0xff, 0xfff, 0xffff>(
-            !op1,
-            u16::wrapping_sub,
-            u16::le,
-            u32::wrapping_sub,
-            u32::le,
-        )
+        self.generic_add_carry16::<true>(!op1)
     }
 
     pub fn branch_near(&mut self, cond: bool, cycles: &mut Cycles) {
@@ -3004,27 +3137,49 @@ impl<
     }
 
     pub fn nmi(&mut self) -> u32 {
+        #[cfg(feature = "call-stack")]
+        let call_site = self.cpu().regs.pc;
         self.cpu_mut().in_nmi = true;
         let vector = self.get_nmi_vector();
-        self.interrupt(vector)
+        let cycles = self.interrupt(vector);
+        #[cfg(feature = "call-stack")]
+        self.enter_call(CallKind::Nmi, call_site);
+        cycles
     }
 
     pub fn irq(&mut self) -> u32 {
-        self.cpu_mut().irq_bit = 0x80;
+        #[cfg(feature = "call-stack")]
+        let call_site = self.cpu().regs.pc;
         let vector = self.get_irq_vector();
-        self.interrupt(vector)
+        let cycles = self.interrupt(vector);
+        #[cfg(feature = "call-stack")]
+        self.enter_call(CallKind::Irq, call_site);
+        cycles
     }
 
+    /// Enter the handler of a hardware interrupt at `vector`
+    /// and return the master cycles this took.
+    ///
+    /// In emulation mode the program bank is not pushed and the pushed status
+    /// has the break flag cleared, which tells interrupts apart from BRK,
+    /// because both share the same vector.
     pub fn interrupt(&mut self, vector: u16) -> u32 {
-        if self.cpu().regs.is_emulation {
-            self.push(self.cpu().regs.pc.addr)
+        let cycles = if self.cpu().regs.is_emulation {
+            self.push(self.cpu().regs.pc.addr);
+            self.push(self.cpu().regs.status.0 & !Status::BREAK.0);
+            7
         } else {
-            self.push(self.cpu().regs.pc)
-        }
-        self.push(self.cpu().regs.status.0);
+            self.push(self.cpu().regs.pc);
+            self.push(self.cpu().regs.status.0);
+            8
+        };
         self.cpu_mut().regs.status |= Status::IRQ_DISABLE;
         self.cpu_mut().regs.status &= !Status::DECIMAL;
+        self.cpu_mut().irq_disable_polled = true;
         self.cpu_mut().regs.pc = Addr24::new(0, vector);
-        48
+        cycles * 6
     }
 }
+
+#[cfg(test)]
+mod tests;
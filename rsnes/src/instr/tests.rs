@@ -0,0 +1,171 @@
+use super::*;
+use crate::test_utils::{new_device, TestDevice};
+
+/// The result and the flags N, V, Z and C of ADC or SBC in decimal mode
+type Outcome = (u32, [bool; 4]);
+
+/// A model of ADC and SBC in decimal mode with `digits` BCD digits,
+/// which works digit by digit instead of on the whole sum.
+///
+/// Every digit is adjusted before its carry is taken to the next digit.
+/// The overflow flag is taken from the most significant digit before its adjustment.
+fn reference(a: u32, b: u32, carry: bool, sub: bool, digits: u32) -> Outcome {
+    let b = if sub { !b } else { b };
+    let (mut res, mut carry, mut overflow) = (0, i32::from(carry), false);
+    for i in 0..digits {
+        let digit = |v: u32| ((v >> (i * 4)) & 0xf) as i32;
+        let mut sum = digit(a) + digit(b) + carry;
+        if i + 1 == digits {
+            let sign = 1 << (i * 4 + 3);
+            let unadjusted = (sum as u32) << (i * 4);
+            overflow = !(a ^ b) & (a ^ unadjusted) & sign != 0;
+        }
+        if sub && sum <= 0xf {
+            sum -= 6
+        } else if !sub && sum > 9 {
+            sum += 6
+        }
+        carry = i32::from(sum > 0xf);
+        res |= ((sum & 0xf) as u32) << (i * 4);
+    }
+    let negative = res >> (digits * 4 - 1) != 0;
+    (res, [negative, overflow, res == 0, carry != 0])
+}
+
+fn emulate(device: &mut TestDevice, a: u32, b: u32, carry: bool, sub: bool, wide: bool) -> Outcome {
+    let mut status = Status::DECIMAL;
+    status.set_if(Status::CARRY, carry);
+    status.set_if(Status::ACCUMULATION, !wide);
+    device.cpu.regs.status = status;
+    device.cpu.regs.is_emulation = false;
+    device.cpu.regs.a = a as u16;
+    let mut cpu = device.with_main_cpu();
+    match (sub, wide) {
+        (false, false) => cpu.add_carry8(b as u8),
+        (true, false) => cpu.sub_carry8(b as u8),
+        (false, true) => cpu.add_carry16(b as u16),
+        (true, true) => cpu.sub_carry16(b as u16),
+    }
+    let status = device.cpu.regs.status;
+    let res = if wide {
+        device.cpu.regs.a.into()
+    } else {
+        device.cpu.regs.a8().into()
+    };
+    let flags = [
+        Status::NEGATIVE,
+        Status::OVERFLOW,
+        Status::ZERO,
+        Status::CARRY,
+    ];
+    (res, flags.map(|flag| status.has(flag)))
+}
+
+/// Convert a BCD number to binary
+fn from_bcd(v: u32) -> u32 {
+    (0..8)
+        .rev()
+        .fold(0, |acc, i| acc * 10 + ((v >> (i * 4)) & 0xf))
+}
+
+const fn is_bcd(v: u32) -> bool {
+    let mut i = 0;
+    while i < 8 {
+        if (v >> (i * 4)) & 0xf > 9 {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+#[test]
+fn decimal_known_results() {
+    let mut device = new_device();
+    let mut run = |a, b, carry, sub| emulate(&mut device, a, b, carry, sub, false);
+    // 99 + 1 = 100
+    assert_eq!(
+        run(0x99, 0x01, false, false),
+        (0x00, [false, false, true, true])
+    );
+    // 79 + 0 + 1 = 80, which overflows like the binary sum
+    assert_eq!(
+        run(0x79, 0x00, true, false),
+        (0x80, [true, true, false, false])
+    );
+    // 0 - 1 = -1
+    assert_eq!(
+        run(0x00, 0x01, true, true),
+        (0x99, [true, false, false, false])
+    );
+    // the invalid digit F borrows from the high digit, which is adjusted downwards
+    assert_eq!(
+        run(0x00, 0x0f, false, true),
+        (0x9a, [true, false, false, false])
+    );
+    assert_eq!(
+        emulate(&mut device, 0x1234, 0x8766, false, false, true),
+        (0x0000, [false, false, true, true])
+    );
+    assert_eq!(
+        emulate(&mut device, 0x0000, 0x0001, true, true, true),
+        (0x9999, [true, false, false, false])
+    );
+}
+
+#[test]
+fn decimal_all_8bit_operands() {
+    let mut device = new_device();
+    for a in 0..0x100 {
+        for b in 0..0x100 {
+            for (carry, sub) in [(false, false), (true, false), (false, true), (true, true)] {
+                let outcome = emulate(&mut device, a, b, carry, sub, false);
+                assert_eq!(
+                    outcome,
+                    reference(a, b, carry, sub, 2),
+                    "{:02x} {} {:02x} carry {}",
+                    a,
+                    if sub { '-' } else { '+' },
+                    b,
+                    carry
+                );
+                if is_bcd(a) && is_bcd(b) {
+                    let (a, b) = (from_bcd(a) as i32, from_bcd(b) as i32);
+                    let value = if sub {
+                        a - b - 1 + i32::from(carry)
+                    } else {
+                        a + b + i32::from(carry)
+                    };
+                    assert_eq!(from_bcd(outcome.0) as i32, value.rem_euclid(100));
+                    assert_eq!(outcome.1[3], if sub { value >= 0 } else { value >= 100 });
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn decimal_random_16bit_operands() {
+    let mut device = new_device();
+    let mut seed = 0x2545_f491u32;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+    for _ in 0..100_000 {
+        let [a, b] = [next() & 0xffff, next() & 0xffff];
+        let bits = next();
+        let (carry, sub) = (bits & 1 > 0, bits & 2 > 0);
+        assert_eq!(
+            emulate(&mut device, a, b, carry, sub, true),
+            reference(a, b, carry, sub, 4),
+            "{:04x} {} {:04x} carry {}",
+            a,
+            if sub { '-' } else { '+' },
+            b,
+            carry
+        );
+    }
+}
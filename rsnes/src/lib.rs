@@ -1,14 +1,37 @@
 pub mod backend;
+pub mod breakpoint;
+pub mod builder;
 pub mod cartridge;
+pub mod cheats;
+pub mod color;
 pub mod controller;
 pub mod cpu;
+#[cfg(feature = "cpu-tests")]
+pub mod cpu_tests;
 pub mod device;
 pub mod dma;
 pub mod enhancement;
+pub mod heatmap;
+mod idle_loop;
 mod instr;
+pub mod movie;
+#[cfg(feature = "netplay")]
+pub mod netplay;
 pub mod oam;
 pub mod ppu;
 mod registers;
 pub mod smp;
 pub mod spc700;
-mod timing;
+pub mod spc_file;
+pub mod sync;
+pub mod timing;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod watch;
+
+pub use builder::EmulatorBuilder;
+
+#[cfg(test)]
+mod test_utils;
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,492 @@
+//! Movie recording and playback
+//!
+//! A movie consists of the controller inputs of every frame and the state
+//! the recording was started from (either power-on or a save state).
+//! Replaying the inputs on the same state deterministically reproduces
+//! the recording.
+//!
+//! # File format
+//!
+//! All numbers are stored in little endian.
+//!
+//! | Offset | Size | Description                                 |
+//! |--------|------|---------------------------------------------|
+//! | 0      | 8    | Magic bytes `RSNESMOV`                      |
+//! | 8      | 2    | Format version (currently 2)                |
+//! | 10     | ...  | Movie encoded in the save state format      |
+//!
+//! The encoded movie is a block of tagged fields: the length of the block
+//! (`u32`) followed by every field as its tag (`u32`), the length of its
+//! data (`u32`) and the data. The tag is the [`save_state::field_tag`] of
+//! the field name. Fields with unknown tags are skipped. The fields are:
+//!
+//! - `rerecord_count`: the rerecord count (`u32`)
+//! - `metadata`: a block with the tagged fields `author`, `description` and
+//!   `rom_title`, which are strings encoded as their length (`u64`) followed
+//!   by their UTF-8 bytes
+//! - `start`: a byte which is 0 for power-on, otherwise it is followed
+//!   by the length (`u64`) and the bytes of a save state
+//! - `frames`: the amount of frames (`u64`) followed by two port inputs per frame
+//!
+//! A port input starts with one byte for the type of the controller:
+//!
+//! - 0: no controller
+//! - 1: standard controller, followed by the pressed buttons (`u16`)
+//! - 2: mouse, followed by the offset (`[i32; 2]`) and the left and right button
+//! - 3: Super Scope, followed by fire, cursor, turbo and pause buttons and the
+//!   aimed position (`Option<[u16; 2]>`)
+//! - 4: Multitap, followed by the pressed buttons of its four controllers (`[u16; 4]`)
+//!
+//! Booleans are stored as one byte, which is `0x00` for false and `0xff` for true.
+//!
+//! Version 1 stored the fields of the movie without tags and lengths,
+//! these files are rejected with [`MovieError::OutdatedVersion`].
+//!
+//! The wall clock is not recorded, so both the recording and the playback
+//! must use [`MOVIE_CLOCK`] as the clock source of the device.
+
+use crate::{
+    backend::{AudioBackend, FixedClock, FrameBuffer},
+    controller::{Controller, ControllerPorts},
+    device::Device,
+};
+use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
+use save_state_macro::InSaveState;
+
+pub const MAGIC: &[u8; 8] = b"RSNESMOV";
+pub const FORMAT_VERSION: u16 = 2;
+/// The wall clock seen by the emulated system during movies (2000-01-01 UTC)
+pub const MOVIE_CLOCK: FixedClock = FixedClock(946684800);
+
+#[derive(Debug)]
+pub enum MovieError {
+    InvalidMagic,
+    /// The file was written in the format of an older version, which can't be read anymore
+    OutdatedVersion(u16),
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidState(save_state::DeserializeError),
+}
+
+impl std::fmt::Display for MovieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "not a movie file (invalid magic bytes)"),
+            Self::OutdatedVersion(version) => {
+                write!(f, "outdated movie format version {}", version)
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported movie format version {}", version)
+            }
+            Self::Truncated => write!(f, "movie file is truncated"),
+            Self::InvalidState(err) => write!(f, "invalid movie file: {}", err),
+        }
+    }
+}
+
+/// The input of a single controller port during one frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PortInput {
+    #[default]
+    None,
+    Standard {
+        buttons: u16,
+    },
+    Mouse {
+        offset: [i32; 2],
+        left: bool,
+        right: bool,
+    },
+    SuperScope {
+        fire: bool,
+        cursor: bool,
+        turbo: bool,
+        pause: bool,
+        position: Option<[u16; 2]>,
+    },
+    Multitap {
+        buttons: [u16; 4],
+    },
+}
+
+impl PortInput {
+    /// Get the current input of a controller
+    pub fn capture(controller: &Controller) -> Self {
+        match controller {
+            Controller::None => Self::None,
+            Controller::Standard(c) => Self::Standard {
+                buttons: c.pressed_buttons,
+            },
+            Controller::Mouse(m) => Self::Mouse {
+                offset: m.internal_offset,
+                left: m.left_button,
+                right: m.right_button,
+            },
+            Controller::SuperScope(s) => Self::SuperScope {
+                fire: s.fire,
+                cursor: s.cursor,
+                turbo: s.turbo,
+                pause: s.pause,
+                position: s.position,
+            },
+            Controller::Multitap(m) => Self::Multitap {
+                buttons: m.controllers.each_ref().map(|c| c.pressed_buttons),
+            },
+        }
+    }
+
+    /// Set the input of a controller.
+    /// Nothing happens if the type of the controller does not match.
+    pub fn apply(&self, controller: &mut Controller) {
+        match (self, controller) {
+            (Self::Standard { buttons }, Controller::Standard(c)) => c.pressed_buttons = *buttons,
+            (
+                Self::Mouse {
+                    offset,
+                    left,
+                    right,
+                },
+                Controller::Mouse(m),
+            ) => {
+                m.internal_offset = *offset;
+                m.left_button = *left;
+                m.right_button = *right;
+            }
+            (
+                Self::SuperScope {
+                    fire,
+                    cursor,
+                    turbo,
+                    pause,
+                    position,
+                },
+                Controller::SuperScope(s),
+            ) => {
+                s.fire = *fire;
+                s.cursor = *cursor;
+                s.turbo = *turbo;
+                s.pause = *pause;
+                s.position = *position;
+            }
+            (Self::Multitap { buttons }, Controller::Multitap(m)) => {
+                for (c, buttons) in m.controllers.iter_mut().zip(buttons) {
+                    c.pressed_buttons = *buttons
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl InSaveState for PortInput {
+    fn serialize(&self, state: &mut SaveStateSerializer) {
+        match self {
+            Self::None => 0u8.serialize(state),
+            Self::Standard { buttons } => {
+                1u8.serialize(state);
+                buttons.serialize(state);
+            }
+            Self::Mouse {
+                offset,
+                left,
+                right,
+            } => {
+                2u8.serialize(state);
+                offset.serialize(state);
+                left.serialize(state);
+                right.serialize(state);
+            }
+            Self::SuperScope {
+                fire,
+                cursor,
+                turbo,
+                pause,
+                position,
+            } => {
+                3u8.serialize(state);
+                fire.serialize(state);
+                cursor.serialize(state);
+                turbo.serialize(state);
+                pause.serialize(state);
+                position.serialize(state);
+            }
+            Self::Multitap { buttons } => {
+                4u8.serialize(state);
+                buttons.serialize(state);
+            }
+        }
+    }
+
+    fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+        let mut i: u8 = 0;
+        i.deserialize(state);
+        *self = match i {
+            0 => Self::None,
+            1 => {
+                let mut buttons = 0;
+                buttons.deserialize(state);
+                Self::Standard { buttons }
+            }
+            2 => {
+                let (mut offset, mut left, mut right) = ([0; 2], false, false);
+                offset.deserialize(state);
+                left.deserialize(state);
+                right.deserialize(state);
+                Self::Mouse {
+                    offset,
+                    left,
+                    right,
+                }
+            }
+            3 => {
+                let mut buttons = [false; 4];
+                for button in &mut buttons {
+                    button.deserialize(state);
+                }
+                let mut position = None;
+                position.deserialize(state);
+                let [fire, cursor, turbo, pause] = buttons;
+                Self::SuperScope {
+                    fire,
+                    cursor,
+                    turbo,
+                    pause,
+                    position,
+                }
+            }
+            4 => {
+                let mut buttons = [0; 4];
+                buttons.deserialize(state);
+                Self::Multitap { buttons }
+            }
+            _ => return state.fail(save_state::DeserializeError::InvalidValue),
+        }
+    }
+}
+
+/// The inputs of both controller ports during one frame
+pub type FrameInput = [PortInput; 2];
+
+fn capture_frame(ports: &ControllerPorts) -> FrameInput {
+    [
+        PortInput::capture(&ports.port1.controller),
+        PortInput::capture(&ports.port2.controller),
+    ]
+}
+
+fn apply_frame(input: &FrameInput, ports: &mut ControllerPorts) {
+    input[0].apply(&mut ports.port1.controller);
+    input[1].apply(&mut ports.port2.controller);
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, InSaveState)]
+pub struct MovieMetadata {
+    pub author: String,
+    pub description: String,
+    /// Title of the cartridge the movie was recorded with
+    pub rom_title: String,
+}
+
+/// The state, from which a movie starts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MovieStart {
+    /// The console was powered on with the cartridge inserted
+    #[default]
+    PowerOn,
+    SaveState(Vec<u8>),
+}
+
+impl InSaveState for MovieStart {
+    fn serialize(&self, state: &mut SaveStateSerializer) {
+        match self {
+            Self::PowerOn => 0u8.serialize(state),
+            Self::SaveState(data) => {
+                1u8.serialize(state);
+                data.serialize(state);
+            }
+        }
+    }
+
+    fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+        let mut i: u8 = 0;
+        i.deserialize(state);
+        *self = match i {
+            0 => Self::PowerOn,
+            1 => {
+                let mut data = vec![];
+                data.deserialize(state);
+                Self::SaveState(data)
+            }
+            _ => return state.fail(save_state::DeserializeError::InvalidValue),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, InSaveState)]
+pub struct Movie {
+    /// How often parts of the movie got recorded again
+    pub rerecord_count: u32,
+    pub metadata: MovieMetadata,
+    pub start: MovieStart,
+    pub frames: Vec<FrameInput>,
+}
+
+impl Movie {
+    pub fn new(metadata: MovieMetadata, start: MovieStart) -> Self {
+        Self {
+            rerecord_count: 0,
+            metadata,
+            start,
+            frames: vec![],
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Encode the movie in the movie file format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ser = SaveStateSerializer::new();
+        ser.write(MAGIC);
+        FORMAT_VERSION.serialize(&mut ser);
+        self.serialize(&mut ser);
+        ser.into_vec()
+    }
+
+    /// Decode a movie file.
+    /// Truncated or otherwise invalid movie data is returned as an error.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MovieError> {
+        let payload = bytes
+            .strip_prefix(MAGIC.as_slice())
+            .ok_or(MovieError::InvalidMagic)?;
+        let version = payload
+            .get(..2)
+            .map(|v| u16::from_le_bytes([v[0], v[1]]))
+            .ok_or(MovieError::Truncated)?;
+        if version < FORMAT_VERSION {
+            return Err(MovieError::OutdatedVersion(version));
+        } else if version > FORMAT_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let mut movie = Self::default();
+        let mut state = SaveStateDeserializer::new(&payload[2..]);
+        movie.deserialize(&mut state);
+        state.finish().map_err(MovieError::InvalidState)?;
+        Ok(movie)
+    }
+}
+
+/// Records the inputs of a running device
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    movie: Movie,
+}
+
+impl Recorder {
+    /// Start a recording from the power-on state.
+    /// The device must have been created and loaded with a cartridge just now.
+    pub fn from_power_on(metadata: MovieMetadata) -> Self {
+        Self {
+            movie: Movie::new(metadata, MovieStart::PowerOn),
+        }
+    }
+
+    /// Start a recording from the current state of the device.
+    /// A frame in progress is finished first.
+    pub fn from_save_state<B: AudioBackend, FB: FrameBuffer>(
+        device: &mut Device<B, FB>,
+        metadata: MovieMetadata,
+    ) -> Self {
+        let start = MovieStart::SaveState(device.serialize_at_frame_boundary());
+        Self {
+            movie: Movie::new(metadata, start),
+        }
+    }
+
+    /// Continue recording an existing movie after its last frame
+    pub fn from_movie(movie: Movie) -> Self {
+        Self { movie }
+    }
+
+    /// The index of the next recorded frame
+    pub fn frame(&self) -> usize {
+        self.movie.frames.len()
+    }
+
+    /// Record the current inputs. This must be called before every emulated frame.
+    pub fn record_frame(&mut self, ports: &ControllerPorts) {
+        self.movie.frames.push(capture_frame(ports))
+    }
+
+    /// Discard all frames starting at `frame`, e.g. after a
+    /// save state of that frame got loaded, and count a rerecord
+    pub fn rerecord_from(&mut self, frame: usize) {
+        self.movie.frames.truncate(frame);
+        self.movie.rerecord_count = self.movie.rerecord_count.saturating_add(1);
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Replays the inputs of a movie
+#[derive(Debug, Clone)]
+pub struct Player {
+    movie: Movie,
+    frame: usize,
+}
+
+impl Player {
+    /// Start the playback. If the movie starts from a save state, it
+    /// gets loaded, otherwise the device must have been created and
+    /// loaded with a cartridge just now.
+    /// The clock source of the device is set to [`MOVIE_CLOCK`].
+    ///
+    /// If the save state is invalid, the device is left unchanged
+    /// and the error is returned.
+    pub fn new<B: AudioBackend, FB: FrameBuffer>(
+        movie: Movie,
+        device: &mut Device<B, FB>,
+    ) -> Result<Self, MovieError> {
+        if let MovieStart::SaveState(data) = &movie.start {
+            save_state::deserialize_checked(device, data).map_err(MovieError::InvalidState)?;
+        }
+        device.set_clock_source(std::sync::Arc::new(MOVIE_CLOCK));
+        Ok(Self { movie, frame: 0 })
+    }
+
+    /// The index of the next played frame
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    /// Apply the inputs of the next frame. This must be called before every emulated frame.
+    /// Returns false, if the movie has already ended.
+    pub fn play_frame(&mut self, ports: &mut ControllerPorts) -> bool {
+        match self.movie.frames.get(self.frame) {
+            Some(input) => {
+                apply_frame(input, ports);
+                self.frame += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,119 @@
+use super::*;
+use crate::controller::{Mouse, StandardController, SuperScope};
+use crate::test_utils::new_device;
+
+fn example_movie() -> Movie {
+    let mut movie = Movie::new(
+        MovieMetadata {
+            author: "author".to_string(),
+            description: "a description".to_string(),
+            rom_title: "TEST ROM".to_string(),
+        },
+        MovieStart::SaveState(vec![1, 2, 3]),
+    );
+    movie.rerecord_count = 42;
+    movie.frames = vec![
+        [PortInput::None, PortInput::None],
+        [
+            PortInput::Standard { buttons: 0x0fff },
+            PortInput::Mouse {
+                offset: [-5, 7],
+                left: true,
+                right: false,
+            },
+        ],
+        [
+            PortInput::Standard { buttons: 0 },
+            PortInput::SuperScope {
+                fire: true,
+                cursor: false,
+                turbo: true,
+                pause: false,
+                position: Some([100, 50]),
+            },
+        ],
+        [
+            PortInput::None,
+            PortInput::Multitap {
+                buttons: [0x0001, 0x0800, 0, 0x0fff],
+            },
+        ],
+    ];
+    movie
+}
+
+#[test]
+fn file_roundtrip() {
+    let movie = example_movie();
+    let bytes = movie.to_bytes();
+    assert!(bytes.starts_with(MAGIC));
+    assert_eq!(Movie::from_bytes(&bytes).unwrap(), movie);
+}
+
+#[test]
+fn invalid_files() {
+    assert!(matches!(
+        Movie::from_bytes(b"NOTAMOVIE"),
+        Err(MovieError::InvalidMagic)
+    ));
+    assert!(matches!(
+        Movie::from_bytes(b"RSNESMOV"),
+        Err(MovieError::Truncated)
+    ));
+    assert!(matches!(
+        Movie::from_bytes(b"RSNESMOV\x01\x00"),
+        Err(MovieError::OutdatedVersion(1))
+    ));
+    assert!(matches!(
+        Movie::from_bytes(b"RSNESMOV\x03\x00"),
+        Err(MovieError::UnsupportedVersion(3))
+    ));
+}
+
+#[test]
+fn record_and_play() {
+    let mut ports = ControllerPorts::new();
+    ports.port2.controller = Controller::Mouse(Mouse::default());
+    let mut recorder = Recorder::from_power_on(MovieMetadata::default());
+    for i in 0..10 {
+        if let Controller::Standard(c) = &mut ports.port1.controller {
+            c.pressed_buttons = i
+        }
+        if let Controller::Mouse(m) = &mut ports.port2.controller {
+            m.internal_offset = [i.into(), -i32::from(i)]
+        }
+        recorder.record_frame(&ports);
+    }
+    recorder.rerecord_from(5);
+    let movie = recorder.finish();
+    assert_eq!(movie.frame_count(), 5);
+    assert_eq!(movie.rerecord_count, 1);
+
+    let mut ports = ControllerPorts::new();
+    ports.port2.controller = Controller::SuperScope(SuperScope::default());
+    let mut player = Player { movie, frame: 0 };
+    for i in 0..5 {
+        assert!(player.play_frame(&mut ports));
+        match &ports.port1.controller {
+            Controller::Standard(StandardController {
+                pressed_buttons, ..
+            }) => assert_eq!(*pressed_buttons, i),
+            _ => unreachable!(),
+        }
+    }
+    assert!(player.is_finished());
+    assert!(!player.play_frame(&mut ports));
+}
+
+#[test]
+fn play_invalid_save_state() {
+    let mut device = new_device();
+    let movie = Movie::new(
+        MovieMetadata::default(),
+        MovieStart::SaveState(vec![1, 2, 3]),
+    );
+    assert!(matches!(
+        Player::new(movie, &mut *device),
+        Err(MovieError::InvalidState(_))
+    ));
+}
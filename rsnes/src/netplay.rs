@@ -0,0 +1,853 @@
+//! Deterministic lockstep netplay
+//!
+//! Both peers run the same emulation and only exchange their controller
+//! inputs. The local input of a frame is scheduled `input_delay` frames
+//! in the future, so that it usually arrives at the peer before it is
+//! needed. A frame is only emulated when the inputs of both peers are known.
+//!
+//! Every `checksum_interval` frames both peers hash their save state and
+//! compare the checksums. On a mismatch the host sends its save state of
+//! that frame to the client, which loads it and emulates the following
+//! frames again with the already known inputs.
+//!
+//! The host drives controller port 1 and the client drives port 2.
+//! See [`rollback`] for sessions, which do not wait for the inputs of the peer.
+//!
+//! # Packet format
+//!
+//! All numbers are stored in little endian. Every packet starts with a
+//! byte describing its type:
+//!
+//! - 0 (hello): protocol version (`u16`), input delay (`u8`) and
+//!   whether rollback is used (`0x00` or `0xff`)
+//! - 1 (frame): resync epoch (`u16`), next expected peer frame (`u32`),
+//!   next unverified checksum frame (`u32`), first input frame (`u32`),
+//!   amount of inputs (`u8`), the inputs encoded like in movie files,
+//!   amount of checksums (`u8`) and pairs of frame (`u32`) and checksum (`u64`)
+//! - 2 (state chunk): resync epoch (`u16`), frame (`u32`), chunk index (`u16`),
+//!   amount of chunks (`u16`) and the chunk data
+//! - 3 (state acknowledgement): the new resync epoch (`u16`)
+
+use crate::{
+    backend::{AudioBackend, FrameBuffer},
+    controller::ControllerPorts,
+    device::Device,
+    movie::PortInput,
+};
+use save_state::{serialize_in_place, InSaveState, SaveStateDeserializer, SaveStateSerializer};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+pub mod rollback;
+
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// The maximum amount of inputs sent in one packet
+const MAX_INPUTS_PER_PACKET: usize = 32;
+/// The maximum size of a received packet
+const MAX_PACKET_SIZE: usize = 2048;
+const CHUNK_SIZE: usize = 1024;
+/// The amount of state chunks sent per poll during a resync
+const CHUNKS_PER_POLL: usize = 16;
+/// Packets are sent at least this often, even if there is no new input
+const RESEND_INTERVAL: Duration = Duration::from_millis(10);
+
+const HELLO: u8 = 0;
+const FRAME: u8 = 1;
+const STATE_CHUNK: u8 = 2;
+const STATE_ACK: u8 = 3;
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(io::Error),
+    VersionMismatch(u16),
+    InputDelayMismatch {
+        local: u8,
+        remote: u8,
+    },
+    /// Only one of the peers uses rollback
+    RollbackMismatch,
+    Timeout,
+    /// The state sent by the host for a resync is invalid
+    InvalidState(save_state::DeserializeError),
+}
+
+impl std::fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "network error ({})", err),
+            Self::VersionMismatch(version) => write!(
+                f,
+                "peer uses protocol version {} instead of {}",
+                version, PROTOCOL_VERSION
+            ),
+            Self::InputDelayMismatch { local, remote } => write!(
+                f,
+                "peer uses an input delay of {} frames instead of {}",
+                remote, local
+            ),
+            Self::RollbackMismatch => write!(f, "only one of the peers uses rollback"),
+            Self::Timeout => write!(f, "connection to peer timed out"),
+            Self::InvalidState(err) => write!(f, "invalid state received from peer ({})", err),
+        }
+    }
+}
+
+impl From<io::Error> for NetplayError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Host,
+    Client,
+}
+
+impl Role {
+    /// The index of the controller port driven by this role
+    pub const fn port(self) -> usize {
+        match self {
+            Self::Host => 0,
+            Self::Client => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NetplayConfig {
+    pub role: Role,
+    /// Amount of frames the local inputs get delayed. Must be equal on both peers.
+    pub input_delay: u8,
+    /// Amount of frames between two state checksums
+    pub checksum_interval: u32,
+    /// The session fails, if the peer does not answer for this time
+    pub timeout: Duration,
+}
+
+impl NetplayConfig {
+    pub const fn new(role: Role) -> Self {
+        Self {
+            role,
+            input_delay: 2,
+            checksum_interval: 60,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// An unreliable, unordered packet transport
+pub trait Transport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()>;
+
+    /// Receive a packet without blocking.
+    /// Returns `None` if no packet is available.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>>;
+}
+
+/// Packet transport over UDP
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+    peer: Option<SocketAddr>,
+}
+
+impl UdpTransport {
+    /// Listen on `addr`. The first peer sending a packet becomes the remote peer.
+    pub fn listen(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, peer: None })
+    }
+
+    /// Connect to the peer listening on `addr`
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let peer = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address given"))?;
+        let local: SocketAddr = if peer.is_ipv4() {
+            ([0; 4], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            peer: Some(peer),
+        })
+    }
+
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        match self.peer {
+            Some(peer) => self.socket.send_to(packet, peer).map(drop),
+            None => Ok(()),
+        }
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        loop {
+            match self.socket.recv_from(buf) {
+                Ok((size, from)) => match self.peer {
+                    Some(peer) if peer != from => continue,
+                    _ => {
+                        self.peer = Some(from);
+                        return Ok(Some(size));
+                    }
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// FNV-1a hash of a save state
+pub fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Packet {
+    Hello {
+        version: u16,
+        input_delay: u8,
+        rollback: bool,
+    },
+    Frame {
+        epoch: u16,
+        ack: u32,
+        verified: u32,
+        start: u32,
+        inputs: Vec<PortInput>,
+        checksums: Vec<(u32, u64)>,
+    },
+    StateChunk {
+        epoch: u16,
+        frame: u32,
+        index: u16,
+        count: u16,
+        data: Vec<u8>,
+    },
+    StateAck {
+        epoch: u16,
+    },
+}
+
+/// Bounds checked reader of received packets
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.data.len() < n {
+            return None;
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    /// Read a port input, which is encoded in the save state format
+    fn port_input(&mut self) -> Option<PortInput> {
+        let size = match *self.data.first()? {
+            0 => 1,
+            1 => 3,
+            2 => 11,
+            3 => match self.data.get(5)?.count_ones() >= 4 {
+                true => 10,
+                false => 6,
+            },
+            4 => 9,
+            _ => return None,
+        };
+        let mut input = PortInput::None;
+        input.deserialize(&mut SaveStateDeserializer::new(self.bytes(size)?));
+        Some(input)
+    }
+}
+
+impl Packet {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = SaveStateSerializer::new();
+        match self {
+            Self::Hello {
+                version,
+                input_delay,
+                rollback,
+            } => {
+                HELLO.serialize(&mut out);
+                version.serialize(&mut out);
+                input_delay.serialize(&mut out);
+                rollback.serialize(&mut out);
+            }
+            Self::Frame {
+                epoch,
+                ack,
+                verified,
+                start,
+                inputs,
+                checksums,
+            } => {
+                FRAME.serialize(&mut out);
+                epoch.serialize(&mut out);
+                ack.serialize(&mut out);
+                verified.serialize(&mut out);
+                start.serialize(&mut out);
+                (inputs.len() as u8).serialize(&mut out);
+                for input in inputs {
+                    input.serialize(&mut out);
+                }
+                (checksums.len() as u8).serialize(&mut out);
+                for (frame, checksum) in checksums {
+                    frame.serialize(&mut out);
+                    checksum.serialize(&mut out);
+                }
+            }
+            Self::StateChunk {
+                epoch,
+                frame,
+                index,
+                count,
+                data,
+            } => {
+                STATE_CHUNK.serialize(&mut out);
+                epoch.serialize(&mut out);
+                frame.serialize(&mut out);
+                index.serialize(&mut out);
+                count.serialize(&mut out);
+                out.write(data);
+            }
+            Self::StateAck { epoch } => {
+                STATE_ACK.serialize(&mut out);
+                epoch.serialize(&mut out);
+            }
+        }
+        out.into_vec()
+    }
+
+    /// Check if the hello of the peer is compatible to the own hello
+    fn check_hello(&self, own: &Self) -> Result<(), NetplayError> {
+        match (self, own) {
+            (
+                Self::Hello {
+                    version,
+                    input_delay,
+                    rollback,
+                },
+                Self::Hello {
+                    input_delay: own_delay,
+                    rollback: own_rollback,
+                    ..
+                },
+            ) => {
+                if *version != PROTOCOL_VERSION {
+                    Err(NetplayError::VersionMismatch(*version))
+                } else if input_delay != own_delay {
+                    Err(NetplayError::InputDelayMismatch {
+                        local: *own_delay,
+                        remote: *input_delay,
+                    })
+                } else if rollback != own_rollback {
+                    Err(NetplayError::RollbackMismatch)
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Decode a packet. Returns `None` for malformed packets.
+    fn decode(data: &[u8]) -> Option<Self> {
+        let mut r = Reader { data };
+        Some(match r.u8()? {
+            HELLO => Self::Hello {
+                version: r.u16()?,
+                input_delay: r.u8()?,
+                rollback: r.u8()?.count_ones() >= 4,
+            },
+            FRAME => {
+                let (epoch, ack, verified, start) = (r.u16()?, r.u32()?, r.u32()?, r.u32()?);
+                let inputs = (0..r.u8()?)
+                    .map(|_| r.port_input())
+                    .collect::<Option<_>>()?;
+                let checksums = (0..r.u8()?)
+                    .map(|_| Some((r.u32()?, r.u64()?)))
+                    .collect::<Option<_>>()?;
+                Self::Frame {
+                    epoch,
+                    ack,
+                    verified,
+                    start,
+                    inputs,
+                    checksums,
+                }
+            }
+            STATE_CHUNK => Self::StateChunk {
+                epoch: r.u16()?,
+                frame: r.u32()?,
+                index: r.u16()?,
+                count: r.u16()?,
+                data: r.data.to_vec(),
+            },
+            STATE_ACK => Self::StateAck { epoch: r.u16()? },
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Resync {
+    /// The host sends its state of `frame` until the client acknowledges it
+    Sending {
+        frame: u32,
+        state: Vec<u8>,
+        next_chunk: usize,
+    },
+    /// The client waits for the state of the host
+    Receiving {
+        frame: Option<u32>,
+        chunks: Vec<Option<Vec<u8>>>,
+    },
+}
+
+/// A lockstep session with a remote peer
+#[derive(Debug)]
+pub struct Session<T: Transport> {
+    transport: T,
+    config: NetplayConfig,
+    connected: bool,
+    /// The next frame to be emulated
+    frame: u32,
+    /// The first frame of which the inputs are stored
+    history_start: u32,
+    local_inputs: VecDeque<PortInput>,
+    /// Inputs of the peer without gaps
+    remote_inputs: VecDeque<PortInput>,
+    /// The first frame of which the peer does not have the local input
+    remote_ack: u32,
+    /// Checksums of the local states, which the peer has not verified yet
+    checksums: BTreeMap<u32, u64>,
+    /// States kept by the host for a possible resync
+    snapshots: BTreeMap<u32, Vec<u8>>,
+    /// Reused for the states, whose checksums are taken
+    state_buffer: Vec<u8>,
+    remote_checksums: BTreeMap<u32, u64>,
+    /// All checksums before this frame have been verified
+    verified: u32,
+    remote_verified: u32,
+    /// Amount of completed resyncs. Checksums of another epoch are ignored.
+    epoch: u16,
+    resync: Option<Resync>,
+    last_received: Instant,
+    last_sent: Option<Instant>,
+}
+
+impl<T: Transport> Session<T> {
+    pub fn new(transport: T, config: NetplayConfig) -> Self {
+        let delay = usize::from(config.input_delay);
+        Self {
+            transport,
+            config,
+            connected: false,
+            frame: 0,
+            history_start: 0,
+            local_inputs: vec![PortInput::None; delay].into(),
+            remote_inputs: VecDeque::new(),
+            remote_ack: 0,
+            checksums: BTreeMap::new(),
+            snapshots: BTreeMap::new(),
+            state_buffer: vec![],
+            remote_checksums: BTreeMap::new(),
+            verified: 0,
+            remote_verified: 0,
+            epoch: 0,
+            resync: None,
+            last_received: Instant::now(),
+            last_sent: None,
+        }
+    }
+
+    pub fn config(&self) -> &NetplayConfig {
+        &self.config
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// The index of the next frame
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Check if the client currently waits for the state of the host
+    pub fn is_resyncing(&self) -> bool {
+        matches!(self.resync, Some(Resync::Receiving { .. }))
+    }
+
+    /// The amount of completed resyncs after a desync
+    pub fn resync_count(&self) -> u16 {
+        self.epoch
+    }
+
+    fn local_end(&self) -> u32 {
+        self.history_start + self.local_inputs.len() as u32
+    }
+
+    fn remote_end(&self) -> u32 {
+        self.history_start + self.remote_inputs.len() as u32
+    }
+
+    fn send(&mut self, packet: &Packet) -> Result<(), NetplayError> {
+        Ok(self.transport.send(&packet.encode())?)
+    }
+
+    fn send_frame_packet(&mut self, now: Instant) -> Result<(), NetplayError> {
+        let start = self.remote_ack.max(self.history_start);
+        let inputs = self
+            .local_inputs
+            .iter()
+            .skip((start - self.history_start) as usize)
+            .take(MAX_INPUTS_PER_PACKET)
+            .copied()
+            .collect();
+        let checksums = self
+            .checksums
+            .iter()
+            .take(u8::MAX.into())
+            .map(|(&frame, &checksum)| (frame, checksum))
+            .collect();
+        self.send(&Packet::Frame {
+            epoch: self.epoch,
+            ack: self.remote_end(),
+            verified: self.verified,
+            start,
+            inputs,
+            checksums,
+        })?;
+        self.last_sent = Some(now);
+        Ok(())
+    }
+
+    fn send_state_chunks(&mut self) -> Result<(), NetplayError> {
+        let (frame, packets) = match &mut self.resync {
+            Some(Resync::Sending {
+                frame,
+                state,
+                next_chunk,
+            }) => {
+                let count = state.len().div_ceil(CHUNK_SIZE);
+                let packets: Vec<_> = (0..CHUNKS_PER_POLL.min(count))
+                    .map(|i| {
+                        let index = (*next_chunk + i) % count;
+                        let start = index * CHUNK_SIZE;
+                        let end = (start + CHUNK_SIZE).min(state.len());
+                        (index, count, state[start..end].to_vec())
+                    })
+                    .collect();
+                *next_chunk = (*next_chunk + packets.len()) % count.max(1);
+                (*frame, packets)
+            }
+            _ => return Ok(()),
+        };
+        for (index, count, data) in packets {
+            self.send(&Packet::StateChunk {
+                epoch: self.epoch,
+                frame,
+                index: index as u16,
+                count: count as u16,
+                data,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn on_inputs(&mut self, start: u32, inputs: Vec<PortInput>) {
+        let end = self.remote_end();
+        if start > end {
+            return;
+        }
+        let skip = (end - start) as usize;
+        self.remote_inputs.extend(inputs.into_iter().skip(skip));
+    }
+
+    /// Compare the checksums, which are known by both peers
+    fn verify_checksums(&mut self) {
+        let frames: Vec<u32> = self
+            .remote_checksums
+            .keys()
+            .copied()
+            .filter(|frame| self.checksums.contains_key(frame))
+            .collect();
+        for frame in frames {
+            let remote = self.remote_checksums.remove(&frame).unwrap();
+            if self.checksums[&frame] == remote {
+                self.verified = self.verified.max(frame + 1);
+                self.snapshots.retain(|&f, _| f > frame);
+            } else if self.resync.is_none() {
+                self.resync = match self.config.role {
+                    Role::Host => self.snapshots.remove(&frame).map(|state| Resync::Sending {
+                        frame,
+                        state,
+                        next_chunk: 0,
+                    }),
+                    Role::Client => Some(Resync::Receiving {
+                        frame: None,
+                        chunks: vec![],
+                    }),
+                };
+            }
+        }
+    }
+
+    fn on_state_chunk<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        (epoch, frame, index, count): (u16, u32, u16, u16),
+        data: Vec<u8>,
+    ) -> Result<(), NetplayError> {
+        if self.config.role != Role::Client {
+            return Ok(());
+        }
+        if epoch < self.epoch {
+            // the acknowledgement got lost
+            return self.send(&Packet::StateAck { epoch: self.epoch });
+        }
+        if epoch > self.epoch || frame < self.history_start || frame > self.frame {
+            return Ok(());
+        }
+        if !matches!(&self.resync, Some(Resync::Receiving { frame: Some(f), chunks }) if *f == frame && chunks.len() == usize::from(count))
+        {
+            self.resync = Some(Resync::Receiving {
+                frame: Some(frame),
+                chunks: vec![None; usize::from(count)],
+            });
+        }
+        let chunks = match &mut self.resync {
+            Some(Resync::Receiving { chunks, .. }) => chunks,
+            _ => unreachable!(),
+        };
+        if let Some(chunk) = chunks.get_mut(usize::from(index)) {
+            *chunk = Some(data);
+        }
+        if chunks.iter().all(Option::is_some) {
+            let state: Vec<u8> = chunks.drain(..).flatten().flatten().collect();
+            save_state::deserialize_checked(device, &state).map_err(NetplayError::InvalidState)?;
+            self.resync = None;
+            self.epoch = self.epoch.wrapping_add(1);
+            self.frame = frame;
+            self.checksums.clear();
+            self.remote_checksums.clear();
+            self.send(&Packet::StateAck { epoch: self.epoch })?;
+        }
+        Ok(())
+    }
+
+    fn handle_packet<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        packet: Packet,
+    ) -> Result<(), NetplayError> {
+        match packet {
+            Packet::Hello { .. } => {
+                packet.check_hello(&self.hello())?;
+                if !self.connected {
+                    // answer, so that the peer does not need to wait for the next hello
+                    self.send(&self.hello())?;
+                }
+                self.connected = true;
+            }
+            Packet::Frame {
+                epoch,
+                ack,
+                verified,
+                start,
+                inputs,
+                checksums,
+            } => {
+                self.connected = true;
+                self.remote_ack = self.remote_ack.max(ack);
+                self.on_inputs(start, inputs);
+                if epoch == self.epoch {
+                    self.remote_verified = self.remote_verified.max(verified);
+                    let min_frame = self.verified;
+                    self.remote_checksums.extend(
+                        checksums
+                            .into_iter()
+                            .filter(|(frame, _)| *frame >= min_frame),
+                    );
+                }
+            }
+            Packet::StateChunk {
+                epoch,
+                frame,
+                index,
+                count,
+                data,
+            } => self.on_state_chunk(device, (epoch, frame, index, count), data)?,
+            Packet::StateAck { epoch } => {
+                if self.config.role == Role::Host
+                    && epoch == self.epoch.wrapping_add(1)
+                    && matches!(self.resync, Some(Resync::Sending { .. }))
+                {
+                    // the own checksums stay valid, the client emulates these frames again
+                    self.resync = None;
+                    self.epoch = epoch;
+                    self.remote_checksums.clear();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn hello(&self) -> Packet {
+        Packet::Hello {
+            version: PROTOCOL_VERSION,
+            input_delay: self.config.input_delay,
+            rollback: false,
+        }
+    }
+
+    /// Drop all inputs and checksums, which can not be needed anymore
+    fn prune(&mut self) {
+        let mut keep_from = self
+            .frame
+            .min(self.remote_ack)
+            .min(self.verified)
+            .min(self.remote_verified)
+            .min(self.remote_end());
+        if let Some(Resync::Sending { frame, .. }) = &self.resync {
+            keep_from = keep_from.min(*frame)
+        }
+        if keep_from > self.history_start {
+            let n = (keep_from - self.history_start) as usize;
+            self.local_inputs.drain(..n);
+            self.remote_inputs.drain(..n);
+            self.history_start = keep_from;
+        }
+        let remote_verified = self.remote_verified;
+        self.checksums.retain(|&frame, _| frame >= remote_verified);
+    }
+
+    /// Send and receive packets
+    pub fn poll<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+    ) -> Result<(), NetplayError> {
+        let now = Instant::now();
+        let mut buf = [0; MAX_PACKET_SIZE];
+        while let Some(size) = self.transport.recv(&mut buf)? {
+            self.last_received = now;
+            if let Some(packet) = Packet::decode(&buf[..size]) {
+                self.handle_packet(device, packet)?;
+            }
+        }
+        if self.connected && now.duration_since(self.last_received) > self.config.timeout {
+            return Err(NetplayError::Timeout);
+        }
+        self.verify_checksums();
+        self.prune();
+        if self
+            .last_sent
+            .is_none_or(|last| now.duration_since(last) >= RESEND_INTERVAL)
+        {
+            if self.connected {
+                self.send_frame_packet(now)?;
+            } else {
+                self.send(&self.hello())?;
+                self.last_sent = Some(now);
+            }
+        }
+        self.send_state_chunks()
+    }
+
+    /// Prepare the next frame.
+    ///
+    /// `local` is the current input of the local player. If the inputs of both
+    /// players are known, they are applied to the controller ports and `true`
+    /// is returned. The caller must then emulate exactly one frame.
+    pub fn advance<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        local: PortInput,
+    ) -> Result<bool, NetplayError> {
+        self.poll(device)?;
+        if !self.connected || self.is_resyncing() {
+            return Ok(false);
+        }
+        if self.local_end() == self.frame + u32::from(self.config.input_delay) {
+            self.local_inputs.push_back(local);
+            self.send_frame_packet(Instant::now())?;
+        }
+        if self.frame >= self.remote_end() {
+            return Ok(false);
+        }
+        if self.frame % self.config.checksum_interval.max(1) == 0
+            && self.frame >= self.verified
+            && !self.checksums.contains_key(&self.frame)
+        {
+            serialize_in_place(device, &mut self.state_buffer);
+            self.checksums
+                .insert(self.frame, checksum(&self.state_buffer));
+            if self.config.role == Role::Host {
+                self.snapshots.insert(self.frame, self.state_buffer.clone());
+            }
+        }
+        let i = (self.frame - self.history_start) as usize;
+        let (local, remote) = (self.local_inputs[i], self.remote_inputs[i]);
+        let [port1, port2] = match self.config.role {
+            Role::Host => [local, remote],
+            Role::Client => [remote, local],
+        };
+        apply_inputs(&mut device.controllers, [port1, port2]);
+        self.frame += 1;
+        Ok(true)
+    }
+}
+
+fn apply_inputs(ports: &mut ControllerPorts, [port1, port2]: [PortInput; 2]) {
+    port1.apply(&mut ports.port1.controller);
+    port2.apply(&mut ports.port2.controller);
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,368 @@
+//! Rollback netplay
+//!
+//! Instead of waiting for the input of the peer, the emulation continues
+//! with a predicted input, which is the last known input of the peer.
+//! The state before every frame is saved. When the actual input arrives
+//! and differs from the prediction, the state before the first mispredicted
+//! frame is restored and all following frames are emulated again.
+//!
+//! The emulation only waits for the peer, if it would otherwise run more
+//! than `max_rollback` frames ahead of the last confirmed input.
+//! A `max_rollback` of zero behaves like a lockstep session.
+//! It uses the same packets as the lockstep sessions without checksums.
+
+use super::{
+    apply_inputs, NetplayError, Packet, Role, Transport, MAX_INPUTS_PER_PACKET, MAX_PACKET_SIZE,
+    PROTOCOL_VERSION, RESEND_INTERVAL,
+};
+use crate::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    movie::PortInput,
+};
+use save_state::{serialize_in_place, InSaveState, SaveStateDeserializer};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct RollbackConfig {
+    pub role: Role,
+    /// Amount of frames the local inputs get delayed. Must be equal on both peers.
+    pub input_delay: u8,
+    /// The maximum amount of frames, which get emulated again after a misprediction
+    pub max_rollback: u8,
+    /// The session fails, if the peer does not answer for this time
+    pub timeout: Duration,
+}
+
+impl RollbackConfig {
+    pub const fn new(role: Role) -> Self {
+        Self {
+            role,
+            input_delay: 1,
+            max_rollback: 8,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A rollback session with a remote peer
+#[derive(Debug)]
+pub struct RollbackSession<T: Transport> {
+    transport: T,
+    config: RollbackConfig,
+    connected: bool,
+    /// The next frame to be emulated
+    frame: u32,
+    /// The first frame of which the inputs are stored
+    history_start: u32,
+    local_inputs: VecDeque<PortInput>,
+    /// Confirmed inputs of the peer without gaps
+    remote_inputs: VecDeque<PortInput>,
+    /// The last confirmed input of the peer, which got dropped from `remote_inputs`
+    last_remote_input: PortInput,
+    /// The peer inputs the emulated frames were emulated with
+    used_inputs: VecDeque<PortInput>,
+    /// All frames before this one were emulated with confirmed inputs
+    confirmed_frame: u32,
+    /// The first frame of which the peer does not have the local input
+    remote_ack: u32,
+    /// States before the frames, indexed by the frame modulo the length
+    snapshots: Vec<Vec<u8>>,
+    rollback_count: u64,
+    resimulated_frames: u64,
+    last_received: Instant,
+    last_sent: Option<Instant>,
+}
+
+impl<T: Transport> RollbackSession<T> {
+    pub fn new(transport: T, config: RollbackConfig) -> Self {
+        let delay = usize::from(config.input_delay);
+        let snapshot_count = usize::from(config.max_rollback) + 1;
+        Self {
+            transport,
+            config,
+            connected: false,
+            frame: 0,
+            history_start: 0,
+            local_inputs: vec![PortInput::None; delay].into(),
+            remote_inputs: VecDeque::new(),
+            last_remote_input: PortInput::None,
+            used_inputs: VecDeque::new(),
+            confirmed_frame: 0,
+            remote_ack: 0,
+            snapshots: vec![vec![]; snapshot_count],
+            rollback_count: 0,
+            resimulated_frames: 0,
+            last_received: Instant::now(),
+            last_sent: None,
+        }
+    }
+
+    pub fn config(&self) -> &RollbackConfig {
+        &self.config
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// The index of the next frame
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// The amount of frames emulated with predicted inputs
+    pub fn predicted_frames(&self) -> u32 {
+        self.frame - self.confirmed_frame
+    }
+
+    /// The amount of mispredictions, which caused a rollback
+    pub fn rollback_count(&self) -> u64 {
+        self.rollback_count
+    }
+
+    /// The amount of frames, which were emulated again after a rollback
+    pub fn resimulated_frames(&self) -> u64 {
+        self.resimulated_frames
+    }
+
+    fn local_end(&self) -> u32 {
+        self.history_start + self.local_inputs.len() as u32
+    }
+
+    fn remote_end(&self) -> u32 {
+        self.history_start + self.remote_inputs.len() as u32
+    }
+
+    fn hello(&self) -> Packet {
+        Packet::Hello {
+            version: PROTOCOL_VERSION,
+            input_delay: self.config.input_delay,
+            rollback: true,
+        }
+    }
+
+    fn send(&mut self, packet: &Packet) -> Result<(), NetplayError> {
+        Ok(self.transport.send(&packet.encode())?)
+    }
+
+    fn send_frame_packet(&mut self, now: Instant) -> Result<(), NetplayError> {
+        let start = self.remote_ack.max(self.history_start);
+        let inputs = self
+            .local_inputs
+            .iter()
+            .skip((start - self.history_start) as usize)
+            .take(MAX_INPUTS_PER_PACKET)
+            .copied()
+            .collect();
+        self.send(&Packet::Frame {
+            epoch: 0,
+            ack: self.remote_end(),
+            verified: 0,
+            start,
+            inputs,
+            checksums: vec![],
+        })?;
+        self.last_sent = Some(now);
+        Ok(())
+    }
+
+    fn handle_packet(&mut self, packet: Packet) -> Result<(), NetplayError> {
+        match packet {
+            Packet::Hello { .. } => {
+                packet.check_hello(&self.hello())?;
+                if !self.connected {
+                    self.send(&self.hello())?;
+                }
+                self.connected = true;
+            }
+            Packet::Frame {
+                ack, start, inputs, ..
+            } => {
+                self.connected = true;
+                self.remote_ack = self.remote_ack.max(ack);
+                let end = self.remote_end();
+                if start <= end {
+                    let skip = (end - start) as usize;
+                    self.remote_inputs.extend(inputs.into_iter().skip(skip));
+                }
+            }
+            Packet::StateChunk { .. } | Packet::StateAck { .. } => (),
+        }
+        Ok(())
+    }
+
+    /// Drop all inputs, which can not be needed anymore
+    fn prune(&mut self) {
+        let keep_from = self
+            .confirmed_frame
+            .min(self.remote_ack)
+            .min(self.remote_end());
+        if keep_from > self.history_start {
+            let n = (keep_from - self.history_start) as usize;
+            self.last_remote_input = self.remote_inputs[n - 1];
+            self.local_inputs.drain(..n);
+            self.remote_inputs.drain(..n);
+            self.used_inputs.drain(..n);
+            self.history_start = keep_from;
+        }
+    }
+
+    /// Send and receive packets
+    pub fn poll(&mut self) -> Result<(), NetplayError> {
+        let now = Instant::now();
+        let mut buf = [0; MAX_PACKET_SIZE];
+        while let Some(size) = self.transport.recv(&mut buf)? {
+            self.last_received = now;
+            if let Some(packet) = Packet::decode(&buf[..size]) {
+                self.handle_packet(packet)?;
+            }
+        }
+        if self.connected && now.duration_since(self.last_received) > self.config.timeout {
+            return Err(NetplayError::Timeout);
+        }
+        if self
+            .last_sent
+            .is_none_or(|last| now.duration_since(last) >= RESEND_INTERVAL)
+        {
+            if self.connected {
+                self.send_frame_packet(now)?;
+            } else {
+                self.send(&self.hello())?;
+                self.last_sent = Some(now);
+            }
+        }
+        Ok(())
+    }
+
+    /// The confirmed input of the peer or a prediction
+    fn remote_input(&self, frame: u32) -> PortInput {
+        let i = (frame - self.history_start) as usize;
+        match self.remote_inputs.get(i) {
+            Some(input) => *input,
+            None => *self.remote_inputs.back().unwrap_or(&self.last_remote_input),
+        }
+    }
+
+    fn save_snapshot<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &Device<B, FB>,
+        frame: u32,
+    ) {
+        let i = frame as usize % self.snapshots.len();
+        serialize_in_place(device, &mut self.snapshots[i]);
+    }
+
+    fn prepare_frame<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        frame: u32,
+    ) {
+        let i = (frame - self.history_start) as usize;
+        let remote = self.remote_input(frame);
+        if let Some(used) = self.used_inputs.get_mut(i) {
+            *used = remote
+        } else {
+            self.used_inputs.push_back(remote)
+        }
+        let local = self.local_inputs[i];
+        apply_inputs(
+            &mut device.controllers,
+            match self.config.role {
+                Role::Host => [local, remote],
+                Role::Client => [remote, local],
+            },
+        );
+    }
+
+    /// Restore the state before the first mispredicted frame
+    /// and emulate all following frames again
+    fn rollback<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        run_frame: &mut impl FnMut(&mut Device<B, FB>),
+    ) {
+        let end = self.remote_end().min(self.frame);
+        let mispredicted = (self.confirmed_frame..end).find(|&frame| {
+            let i = (frame - self.history_start) as usize;
+            self.remote_inputs[i] != self.used_inputs[i]
+        });
+        if let Some(first) = mispredicted {
+            let snapshot = &self.snapshots[first as usize % self.snapshots.len()];
+            device.deserialize(&mut SaveStateDeserializer::new(snapshot));
+            // an infinite speed mutes the audio of the frames emulated again
+            let speed = device.speed();
+            device.set_speed(f32::INFINITY);
+            for frame in first..self.frame {
+                if frame > first {
+                    self.save_snapshot(device, frame);
+                }
+                self.prepare_frame(device, frame);
+                run_frame(device);
+            }
+            device.set_speed(speed);
+            self.rollback_count += 1;
+            self.resimulated_frames += u64::from(self.frame - first);
+        }
+        self.confirmed_frame = end;
+    }
+
+    /// Exchange packets with the peer and roll back, if inputs were mispredicted.
+    ///
+    /// `run_frame` is used to emulate the frames again. It must emulate exactly one frame.
+    pub fn update<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        mut run_frame: impl FnMut(&mut Device<B, FB>),
+    ) -> Result<(), NetplayError> {
+        self.poll()?;
+        self.rollback(device, &mut run_frame);
+        self.prune();
+        Ok(())
+    }
+
+    /// Prepare the next frame.
+    ///
+    /// `local` is the current input of the local player.
+    /// See [`Self::update`] for the meaning of `run_frame`.
+    ///
+    /// If `true` is returned, the inputs of the next frame have been applied
+    /// to the controller ports and the caller must emulate exactly one frame.
+    pub fn advance<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        local: PortInput,
+        run_frame: impl FnMut(&mut Device<B, FB>),
+    ) -> Result<bool, NetplayError> {
+        self.update(device, run_frame)?;
+        if !self.connected {
+            return Ok(false);
+        }
+        if self.local_end() == self.frame + u32::from(self.config.input_delay) {
+            self.local_inputs.push_back(local);
+            self.send_frame_packet(Instant::now())?;
+        }
+        if self.frame >= self.remote_end()
+            && self.predicted_frames() >= u32::from(self.config.max_rollback)
+        {
+            return Ok(false);
+        }
+        self.save_snapshot(device, self.frame);
+        self.prepare_frame(device, self.frame);
+        self.frame += 1;
+        if self.confirmed_frame + 1 == self.frame && self.frame <= self.remote_end() {
+            self.confirmed_frame = self.frame
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests;
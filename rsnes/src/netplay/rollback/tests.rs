@@ -0,0 +1,89 @@
+use super::*;
+use crate::netplay::tests::{buttons, memory_pair, new_device};
+use crate::test_utils::TestDevice;
+
+/// A fake frame, which mixes the controller inputs into the state
+fn step(state: u8, [port1, port2]: [u16; 2]) -> u8 {
+    state.rotate_left(3) ^ (port1 as u8).wrapping_add((port2 as u8).wrapping_mul(7))
+}
+
+fn run_frame(device: &mut TestDevice) {
+    device.open_bus = step(device.open_bus, buttons(device));
+}
+
+fn input(role: Role, frame: u32) -> u16 {
+    match role {
+        Role::Host => (frame / 3) as u16,
+        Role::Client => (frame / 5) as u16 | 0x80,
+    }
+}
+
+#[test]
+fn rollback_on_misprediction() {
+    const FRAMES: u32 = 60;
+    let (a, b) = memory_pair();
+    let mut sessions = [(a, Role::Host), (b, Role::Client)]
+        .map(|(transport, role)| RollbackSession::new(transport, RollbackConfig::new(role)));
+    let mut devices = [new_device(), new_device()];
+    let delay = u32::from(sessions[0].config().input_delay);
+
+    for iteration in 0.. {
+        for (i, (session, device)) in sessions.iter_mut().zip(&mut devices).enumerate() {
+            // the client runs at half the speed, so that the host has to predict
+            if i == 1 && iteration % 2 == 1 || session.frame() >= FRAMES {
+                session.update(device, run_frame).unwrap();
+                continue;
+            }
+            let local = PortInput::Standard {
+                buttons: input(session.config().role, session.frame()),
+            };
+            if session.advance(device, local, run_frame).unwrap() {
+                run_frame(device);
+            }
+        }
+        if sessions
+            .iter()
+            .all(|s| s.frame() == FRAMES && s.predicted_frames() == 0)
+        {
+            break;
+        }
+        assert!(iteration < 10_000);
+    }
+
+    let (mut state, mut ports) = (0, [0, 0]);
+    for frame in 0..FRAMES {
+        if frame >= delay {
+            ports = [Role::Host, Role::Client].map(|role| input(role, frame - delay));
+        }
+        state = step(state, ports);
+    }
+    assert!(sessions[0].rollback_count() > 0);
+    assert_eq!(devices[0].open_bus, state);
+    assert_eq!(devices[1].open_bus, state);
+}
+
+#[test]
+fn zero_rollback_is_lockstep() {
+    let (a, b) = memory_pair();
+    let mut sessions = [(a, Role::Host), (b, Role::Client)].map(|(transport, role)| {
+        let config = RollbackConfig {
+            max_rollback: 0,
+            ..RollbackConfig::new(role)
+        };
+        RollbackSession::new(transport, config)
+    });
+    let mut devices = [new_device(), new_device()];
+    for iteration in 0..60 {
+        for (i, (session, device)) in sessions.iter_mut().zip(&mut devices).enumerate() {
+            if i == 1 && iteration % 2 == 1 {
+                continue;
+            }
+            let local = PortInput::Standard { buttons: 1 };
+            if session.advance(device, local, run_frame).unwrap() {
+                run_frame(device);
+            }
+            assert_eq!(session.predicted_frames(), 0);
+        }
+    }
+    assert_eq!(sessions[0].rollback_count(), 0);
+}
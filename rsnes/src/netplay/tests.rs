@@ -0,0 +1,198 @@
+use super::*;
+use crate::{
+    controller::Controller,
+    test_utils::{self, TestDevice},
+};
+use std::{cell::RefCell, rc::Rc};
+
+/// One end of a lossless in-memory connection
+pub(super) struct MemoryTransport {
+    incoming: Rc<RefCell<VecDeque<Vec<u8>>>>,
+    outgoing: Rc<RefCell<VecDeque<Vec<u8>>>>,
+}
+
+pub(super) fn memory_pair() -> (MemoryTransport, MemoryTransport) {
+    let (a, b) = (Rc::default(), Rc::default());
+    (
+        MemoryTransport {
+            incoming: Rc::clone(&a),
+            outgoing: Rc::clone(&b),
+        },
+        MemoryTransport {
+            incoming: b,
+            outgoing: a,
+        },
+    )
+}
+
+impl Transport for MemoryTransport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.outgoing.borrow_mut().push_back(packet.to_vec());
+        Ok(())
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        Ok(self.incoming.borrow_mut().pop_front().map(|packet| {
+            buf[..packet.len()].copy_from_slice(&packet);
+            packet.len()
+        }))
+    }
+}
+
+/// Create a console with a standard controller on both ports
+pub(super) fn new_device() -> Box<TestDevice> {
+    let mut device = test_utils::new_device();
+    device.controllers.port2.controller = device.controllers.port1.controller.clone();
+    device
+}
+
+pub(super) fn buttons(device: &TestDevice) -> [u16; 2] {
+    [&device.controllers.port1, &device.controllers.port2].map(|port| match &port.controller {
+        Controller::Standard(c) => c.pressed_buttons,
+        _ => unreachable!(),
+    })
+}
+
+fn new_sessions(interval: u32) -> [Session<MemoryTransport>; 2] {
+    let (a, b) = memory_pair();
+    [(a, Role::Host), (b, Role::Client)].map(|(transport, role)| {
+        let config = NetplayConfig {
+            checksum_interval: interval,
+            ..NetplayConfig::new(role)
+        };
+        Session::new(transport, config)
+    })
+}
+
+#[test]
+fn packet_roundtrip() {
+    let packets = [
+        Packet::Hello {
+            version: PROTOCOL_VERSION,
+            input_delay: 3,
+            rollback: true,
+        },
+        Packet::Frame {
+            epoch: 1,
+            ack: 20,
+            verified: 16,
+            start: 18,
+            inputs: vec![
+                PortInput::Standard { buttons: 0x0123 },
+                PortInput::Mouse {
+                    offset: [-3, 4],
+                    left: true,
+                    right: false,
+                },
+                PortInput::SuperScope {
+                    fire: false,
+                    cursor: true,
+                    turbo: false,
+                    pause: true,
+                    position: Some([17, 42]),
+                },
+                PortInput::Multitap {
+                    buttons: [1, 2, 3, 0x0800],
+                },
+                PortInput::None,
+            ],
+            checksums: vec![(0, 0x0123456789abcdef), (60, 1)],
+        },
+        Packet::StateChunk {
+            epoch: 2,
+            frame: 120,
+            index: 3,
+            count: 300,
+            data: vec![1, 2, 3, 4],
+        },
+        Packet::StateAck { epoch: 3 },
+    ];
+    for packet in packets {
+        let bytes = packet.encode();
+        assert_eq!(Packet::decode(&bytes).as_ref(), Some(&packet));
+        if !matches!(packet, Packet::StateChunk { .. }) {
+            assert_eq!(Packet::decode(&bytes[..bytes.len() - 1]), None);
+        }
+    }
+    assert_eq!(Packet::decode(&[]), None);
+    assert_eq!(
+        Packet::decode(&[FRAME, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 7]),
+        None
+    );
+}
+
+#[test]
+fn lockstep_inputs() {
+    let mut sessions = new_sessions(60);
+    let mut devices = [new_device(), new_device()];
+    let delay = u32::from(sessions[0].config().input_delay);
+    let mut frames = [0u32; 2];
+    while frames.iter().any(|&f| f < 100) {
+        for i in 0..2 {
+            let local = PortInput::Standard {
+                buttons: (frames[i] as u16) << (i * 8),
+            };
+            if sessions[i].advance(&mut devices[i], local).unwrap() {
+                let frame = frames[i];
+                let expected = frame.saturating_sub(delay) as u16;
+                let expected = if frame < delay {
+                    [0, 0]
+                } else {
+                    [expected, expected << 8]
+                };
+                assert_eq!(buttons(&devices[i]), expected, "frame {}", frame);
+                frames[i] += 1;
+            }
+        }
+        // the peers never drift apart by more than the input delay
+        assert!(frames[0].abs_diff(frames[1]) <= delay + 1);
+    }
+    assert_eq!(sessions[0].resync_count(), 0);
+    assert_eq!(sessions[1].resync_count(), 0);
+}
+
+#[test]
+fn resync_after_desync() {
+    let mut sessions = new_sessions(4);
+    let mut devices = [new_device(), new_device()];
+    let mut desynced = false;
+    for _ in 0..1000 {
+        for i in 0..2 {
+            let local = PortInput::Standard { buttons: 0 };
+            sessions[i].advance(&mut devices[i], local).unwrap();
+        }
+        if !desynced && sessions[1].frame() == 10 {
+            devices[1].open_bus = 0x55;
+            desynced = true;
+        }
+        if sessions[1].resync_count() > 0 && sessions[0].resync_count() > 0 {
+            break;
+        }
+    }
+    assert!(desynced);
+    assert_eq!(sessions[1].resync_count(), 1);
+    assert_eq!(sessions[0].resync_count(), 1);
+    assert_eq!(devices[1].open_bus, devices[0].open_bus);
+}
+
+#[test]
+fn mismatching_input_delay() {
+    let (a, b) = memory_pair();
+    let mut host = Session::new(a, NetplayConfig::new(Role::Host));
+    let mut client = Session::new(
+        b,
+        NetplayConfig {
+            input_delay: 5,
+            ..NetplayConfig::new(Role::Client)
+        },
+    );
+    let mut device = new_device();
+    host.poll(&mut device).unwrap();
+    assert!(matches!(
+        client.poll(&mut device),
+        Err(NetplayError::InputDelayMismatch {
+            local: 5,
+            remote: 2
+        })
+    ));
+}
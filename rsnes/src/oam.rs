@@ -1,4 +1,5 @@
 use save_state_macro::*;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Copy, InSaveState)]
 pub struct Object {
@@ -159,16 +160,17 @@ impl Oam {
 
 #[derive(Debug, Clone, InSaveState)]
 pub struct CgRam {
-    data: [u8; 512],
+    /// Shared with the render threads like the VRAM
+    data: Arc<[u8; 512]>,
     // 9-bit value
     addr: u16,
     stashed_write: u8,
 }
 
 impl CgRam {
-    pub const fn new() -> Self {
+    pub fn new() -> Self {
         Self {
-            data: [0; 512],
+            data: Arc::new([0; 512]),
             addr: 0,
             stashed_write: 0,
         }
@@ -182,8 +184,9 @@ impl CgRam {
         if self.addr & 1 == 0 {
             self.stashed_write = value
         } else {
-            self.data[usize::from(self.addr & 0x1fe)] = self.stashed_write;
-            self.data[usize::from(self.addr & 0x1ff)] = value
+            let data = Arc::make_mut(&mut self.data);
+            data[usize::from(self.addr & 0x1fe)] = self.stashed_write;
+            data[usize::from(self.addr & 0x1ff)] = value
         }
         self.addr = self.addr.wrapping_add(1)
     }
@@ -203,7 +206,7 @@ impl CgRam {
         val
     }
 
-    pub const fn main_screen_backdrop(&self) -> u16 {
+    pub fn main_screen_backdrop(&self) -> u16 {
         u16::from_le_bytes([self.data[0], self.data[1]])
     }
 }
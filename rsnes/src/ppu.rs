@@ -1,14 +1,32 @@
-use crate::oam::{CgRam, Oam, Object};
+pub mod debug;
+mod render_pool;
+pub mod viewer;
+
+use crate::{
+    backend::{FrameBuffer, FrameSize, PixelFormat, Scanline},
+    color::{ColorCorrection, ColorTable},
+    oam::{CgRam, Oam, Object},
+};
 use core::mem::{replace, take};
+use render_pool::RenderPool;
 use save_state::{SaveStateDeserializer, SaveStateSerializer};
 use save_state_macro::*;
+use std::sync::Arc;
 
 pub const VRAM_SIZE: usize = 0x8000;
 pub const SCREEN_WIDTH: u32 = 256;
 pub const MAX_SCREEN_HEIGHT: u32 = 224;
 pub const MAX_SCREEN_HEIGHT_OVERSCAN: u32 = 239;
+/// Maximum width of the output picture in high-resolution modes
+pub const MAX_FRAME_WIDTH: u32 = SCREEN_WIDTH * 2;
+/// Maximum height of the output picture in interlace mode
+pub const MAX_FRAME_HEIGHT: u32 = MAX_SCREEN_HEIGHT_OVERSCAN * 2;
 pub const CHIP_5C77_VERSION: u8 = 1;
 pub const CHIP_5C78_VERSION: u8 = 3;
+/// Sprites per scanline, further sprites set the range overflow flag
+pub const MAX_OBJS_PER_LINE: u8 = 32;
+/// 8x8 sprite tiles per scanline, further tiles set the time overflow flag
+pub const MAX_OBJ_TILES_PER_LINE: u8 = 34;
 
 // TODO: Check the exact value of this.
 // wiki.superfamicom.org/timing states that
@@ -16,6 +34,9 @@ pub const CHIP_5C78_VERSION: u8 = 3;
 // there is garbage for about 16-24 pixels.
 pub const RAY_AHEAD_CYCLES: u16 = 20 * 4;
 
+/// The dot at which the first pixel of a scanline is output
+const FIRST_PIXEL_DOT: u16 = 22;
+
 static OBJ_SIZES: [[[u8; 2]; 2]; 8] = [
     [[8, 8], [16, 16]],
     [[8, 8], [32, 32]],
@@ -43,6 +64,15 @@ impl RayPos {
     }
 }
 
+/// The INIDISP ($2100) settings before a change in the middle of a scanline
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, InSaveState)]
+struct InidispChange {
+    /// The first pixel, which is output with the new settings
+    x: u16,
+    force_blank: bool,
+    brightness: u8,
+}
+
 #[derive(Debug, Default, Clone, Copy, InSaveState)]
 pub struct LatchedState {
     pos: RayPos,
@@ -61,6 +91,16 @@ impl LatchedState {
         }
     }
 
+    /// The byte [`Self::get`] returns next, without toggling the flip-flop
+    pub fn peek<const N: u8>(&self, open_bus: u8) -> u8 {
+        let c = self.pos.get::<N>();
+        if self.flip[N as usize] {
+            ((c >> 8) & 1) as u8 | (open_bus & 0xfe)
+        } else {
+            (c & 0xff) as u8
+        }
+    }
+
     pub fn reset_flipflops(&mut self) -> bool {
         self.flip = [false; 2];
         take(&mut self.latched)
@@ -69,7 +109,9 @@ impl LatchedState {
 
 #[derive(Debug, Clone, InSaveState)]
 pub struct Vram {
-    vram: [u16; VRAM_SIZE],
+    /// Shared with the scanlines being rendered by render threads
+    /// and only copied when written during rendering
+    vram: Arc<[u16; VRAM_SIZE]>,
     unmapped_addr: u16,
     mapped_addr: u16,
     increment_first: bool,
@@ -81,7 +123,7 @@ pub struct Vram {
 impl Vram {
     pub fn new() -> Self {
         Self {
-            vram: [0; VRAM_SIZE],
+            vram: Arc::new([0; VRAM_SIZE]),
             unmapped_addr: 0,
             mapped_addr: 0,
             increment_first: false,
@@ -105,7 +147,7 @@ impl Vram {
     }
 
     pub fn get_mut(&mut self) -> &mut u16 {
-        &mut self.vram[usize::from(self.mapped_addr) & (VRAM_SIZE - 1)]
+        &mut Arc::make_mut(&mut self.vram)[usize::from(self.mapped_addr) & (VRAM_SIZE - 1)]
     }
 
     pub fn read(&self, addr: u16) -> u16 {
@@ -142,6 +184,14 @@ impl Color {
         }
     }
 
+    /// The 15-bit color in the [`PixelFormat::Bgr555`] format
+    pub fn to_bgr555_with_brightness(self, brightness: u8) -> u16 {
+        let b = u16::from(brightness.clamp(0, 15));
+        let [r, g, b] =
+            [self.r, self.g, self.b].map(|c| (u16::from(c.clamp(0, 0x1f)) * b + 7) / 15);
+        r | (g << 5) | (b << 10)
+    }
+
     pub fn map<F: FnMut(u8) -> u8>(self, mut f: F) -> Self {
         Self {
             r: f(self.r),
@@ -309,7 +359,9 @@ const fn sign_extend<const B: u16>(n: u16) -> u16 {
 struct Mode7Settings {
     x_mirror: bool,
     y_mirror: bool,
+    /// Outside of the 1024x1024 playing field the tilemap repeats (M7SEL bit 7 clear)
     wrap: bool,
+    /// Outside of the playing field the character 0 is drawn instead of nothing
     fill: bool,
     prev_m7old: u8,
     // 13-bit signed
@@ -533,11 +585,11 @@ impl Layers {
                 BG!(nr 1, colors 4),
                 BG!(nr 2, colors 4),
                 S!(1),
-                BG!(nr 1, colors 4),
-                BG!(nr 2, colors 4),
+                BG!(nr 3, colors 4, prio),
+                BG!(nr 4, colors 4, prio),
                 S!(0),
-                BG!(nr 1, colors 4),
-                BG!(nr 2, colors 4),
+                BG!(nr 3, colors 4),
+                BG!(nr 4, colors 4),
             ],
             (1, false, _) => to_list![
                 S!(3),
@@ -551,6 +603,7 @@ impl Layers {
                 S!(0),
                 BG!(nr 3, colors 4),
             ],
+            // the BG3 priority bit moves the high priority tiles of BG3 in front
             (1, true, _) => to_list![
                 BG!(nr 3, colors 4, prio),
                 S!(3),
@@ -621,10 +674,105 @@ impl BgMode {
     }
 }
 
+/// A pixel of a scanline in one of the [`PixelFormat`]s.
+/// The default value is a blank pixel.
+pub trait OutputPixel: Copy + Default + Send + 'static {
+    fn from_color(color: Color, brightness: u8) -> Self;
+
+    fn scanline(pixels: &[Self]) -> Scanline<'_>;
+}
+
+impl OutputPixel for [u8; 4] {
+    fn from_color(color: Color, brightness: u8) -> Self {
+        color.to_rgba8_with_brightness(brightness)
+    }
+
+    fn scanline(pixels: &[Self]) -> Scanline<'_> {
+        Scanline::Rgba8(pixels)
+    }
+}
+
+impl OutputPixel for u16 {
+    fn from_color(color: Color, brightness: u8) -> Self {
+        color.to_bgr555_with_brightness(brightness)
+    }
+
+    fn scanline(pixels: &[Self]) -> Scanline<'_> {
+        Scanline::Bgr555(pixels)
+    }
+}
+
+/// The frame buffer of the PPU of a render thread, which renders single scanlines
+#[derive(Debug, Clone, Copy)]
+struct Detached;
+
+impl FrameBuffer for Detached {
+    fn set_size(&mut self, _size: FrameSize) {}
+    fn write_scanline(&mut self, _row: usize, _pixels: Scanline) {}
+    fn widen_rows(&mut self, _rows: usize) {}
+    fn request_redraw(&mut self) {}
+}
+
+/// The state a scanline is rendered from on a render thread.
+///
+/// The registers are copied for every scanline. The VRAM and the CGRAM are
+/// shared and only copied when they are written while a scanline is rendered.
+/// The objects of the scanline are already looked up in the OAM.
+#[derive(Debug, Clone)]
+struct LineState {
+    vram: Arc<[u16; VRAM_SIZE]>,
+    cgram: CgRam,
+    bgs: [Bg; 4],
+    bg_mode: BgMode,
+    bg3_prio: bool,
+    pos: RayPos,
+    brightness: u8,
+    draw_layers: Layers,
+    obj_tile_addr: [u16; 2],
+    obj_layer: Layer,
+    obj_cache: [ObjCacheEntry; 256],
+    color_math: ColorMath,
+    direct_color_mode: bool,
+    interlace_active: bool,
+    window_positions: [[u8; 2]; 2],
+    overscan: bool,
+    pseudo512: bool,
+    mosaic_size: u8,
+    mode7_settings: Mode7Settings,
+    field: bool,
+    force_blank: bool,
+    inidisp_change: Option<InidispChange>,
+    is_pal: bool,
+}
+
+/// Write a rendered scanline into the row `row` of the frame buffer
+/// and apply the color correction
+fn output_scanline<FB: FrameBuffer>(
+    frame_buffer: &mut FB,
+    color_correction: Option<&(ColorCorrection, ColorTable)>,
+    row: usize,
+    pixels: Scanline,
+) {
+    match (pixels, color_correction) {
+        (Scanline::Bgr555(pixels), Some((_, table)))
+            if frame_buffer.pixel_format() == PixelFormat::Rgba8 =>
+        {
+            let mut line = [[0; 4]; MAX_FRAME_WIDTH as usize];
+            for (dst, &color) in line.iter_mut().zip(pixels) {
+                *dst = table.get(color)
+            }
+            frame_buffer.write_scanline(row, Scanline::Rgba8(&line[..pixels.len()]))
+        }
+        (pixels, _) => frame_buffer.write_scanline(row, pixels),
+    }
+}
+
 #[derive(Debug, Clone, InSaveState)]
 pub struct Ppu<FB: crate::backend::FrameBuffer> {
     #[except((|_v, _s| ()), (|_v, _s| ()))]
     pub frame_buffer: FB,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    render_pool: Option<RenderPool>,
     oam: Oam,
     cgram: CgRam,
     vram: Vram,
@@ -643,6 +791,13 @@ pub struct Ppu<FB: crate::backend::FrameBuffer> {
     obj_layer: Layer,
     obj_cache: [ObjCacheEntry; 256],
     overflow_flags: u8,
+    /// Drop the sprites and tiles exceeding the limits per scanline,
+    /// the overflow flags are set either way
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    sprite_limits: bool,
+    /// The color correction of RGBA output and its precomputed colors
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    color_correction: Option<(ColorCorrection, ColorTable)>,
     color_math: ColorMath,
     direct_color_mode: bool,
     object_interlace: bool,
@@ -654,7 +809,13 @@ pub struct Ppu<FB: crate::backend::FrameBuffer> {
     mode7_settings: Mode7Settings,
     field: bool,
     force_blank: bool,
+    /// The settings before INIDISP was written while the scanline was output
+    inidisp_change: Option<InidispChange>,
     is_pal: bool,
+    /// The current frame is output with 512 pixels per scanline
+    frame_hires: bool,
+    /// The current frame is output with both fields interleaved
+    frame_interlace: bool,
     pub(crate) open_bus1: u8,
     pub(crate) open_bus2: u8,
 }
@@ -664,6 +825,7 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         let bg_mode = BgMode::new(0, false, false);
         Self {
             frame_buffer,
+            render_pool: None,
             oam: Oam::new(),
             cgram: CgRam::new(),
             vram: Vram::new(),
@@ -679,6 +841,8 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             obj_layer: Layer::new(),
             obj_cache: [ObjCacheEntry::EMPTY; 256],
             overflow_flags: 0,
+            sprite_limits: true,
+            color_correction: None,
             color_math: ColorMath::new(),
             direct_color_mode: false,
             object_interlace: false,
@@ -690,7 +854,10 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             mode7_settings: Mode7Settings::new(),
             field: false,
             force_blank: true,
+            inidisp_change: None,
             is_pal,
+            frame_hires: false,
+            frame_interlace: false,
             open_bus1: 0,
             open_bus2: 0,
         }
@@ -700,12 +867,6 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
     pub fn read_register(&mut self, addr: u8) -> Option<u8> {
         assert!(addr >= 0x34 && addr <= 0x3f);
         match addr {
-            0x34..=0x36 => {
-                // MPYL/M/H
-                let x = self.mode7_settings.params[0] as i16 as i32;
-                let y = (self.mode7_settings.params[1] >> 8) as i8 as i32;
-                Some(((x * y) as u32).to_le_bytes()[usize::from(addr & 3)])
-            }
             0x37 => {
                 // SLHV - Software Latch for H/V Counter
                 self.latch();
@@ -724,18 +885,44 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             }
             0x3b => Some(self.cgram.read(self.open_bus2)), // RDCGRAM
             0x3c => Some(self.latched.get::<0>(self.open_bus2)), // OPHCT
-            0x3d => Some(self.latched.get::<1>(self.open_bus2)), // OPHCT
-            0x3e => Some(self.overflow_flags | (self.open_bus1 & 0x10) | CHIP_5C77_VERSION), // STAT77
+            0x3d => Some(self.latched.get::<1>(self.open_bus2)), // OPVCT
             0x3f => {
                 // STAT78
-                self.latched.flip = [false; 2];
-                Some(
-                    ((take(&mut self.latched.latched) as u8) << 6)
-                        | (self.open_bus2 & 0x20)
-                        | CHIP_5C78_VERSION
-                        | ((self.is_pal as u8) << 4),
-                )
+                let val = self.peek_register(addr);
+                self.latched.reset_flipflops();
+                val
             }
+            _ => self.peek_register(addr),
+        }
+    }
+
+    /// Read a register (2134 - 213f) without side effects.
+    ///
+    /// The latched values are returned as the next read would return them.
+    /// `None` is returned for the registers, whose value can't be read
+    /// without side effects.
+    pub fn peek_register(&self, addr: u8) -> Option<u8> {
+        assert!((0x34..=0x3f).contains(&addr));
+        match addr {
+            0x34..=0x36 => {
+                // MPYL/M/H
+                let x = self.mode7_settings.params[0] as i16 as i32;
+                let y = (self.mode7_settings.params[1] >> 8) as i8 as i32;
+                Some(((x * y) as u32).to_le_bytes()[usize::from(addr & 3)])
+            }
+            // RDVRAML/H
+            0x39 | 0x3a => Some(self.vram.buffered.to_le_bytes()[usize::from(addr == 0x3a)]),
+            0x3c => Some(self.latched.peek::<0>(self.open_bus2)), // OPHCT
+            0x3d => Some(self.latched.peek::<1>(self.open_bus2)), // OPVCT
+            0x3e => Some(self.overflow_flags | (self.open_bus1 & 0x10) | CHIP_5C77_VERSION), // STAT77
+            0x3f => Some(
+                // STAT78
+                ((self.latched.latched as u8) << 6)
+                    | (self.open_bus2 & 0x20)
+                    | CHIP_5C78_VERSION
+                    | ((self.is_pal as u8) << 4),
+            ),
+            0x37 | 0x38 | 0x3b => None,
             _ => unreachable!(),
         }
     }
@@ -746,6 +933,7 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         match addr {
             0x00 => {
                 // INIDISP
+                self.split_inidisp();
                 self.force_blank = val & 0x80 > 0;
                 self.brightness = val & 15;
             }
@@ -1057,10 +1245,20 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             .wrapping_add(v[0] & 7)
             .wrapping_add((v[1] & 7) << 3);
         let cgram_addr = self.vram.read(pixel_addr).to_le_bytes()[1];
-        if cgram_addr == 0 || (nr == 1 && (cgram_addr & 0x80 == 0) == prio) {
+        // with EXTBG the upper bit of the pixels of BG2 selects the priority
+        let cgram_addr = if nr == 1 {
+            if (cgram_addr & 0x80 > 0) != prio {
+                return None;
+            }
+            cgram_addr & 0x7f
+        } else {
+            cgram_addr
+        };
+        if cgram_addr == 0 {
             None
         } else {
-            Some(if self.direct_color_mode {
+            // direct color only applies to the 256 colors of BG1
+            Some(if self.direct_color_mode && nr == 0 {
                 Color {
                     r: (cgram_addr & 7) << 2,
                     g: (cgram_addr & 0x38) >> 1,
@@ -1072,9 +1270,9 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         }
     }
 
-    pub fn fetch_bg_tile(&mut self, x: u8, y: u16, nr: u8, bits: u8, prio: bool) -> Option<Color> {
+    pub fn fetch_bg_tile(&mut self, x: u16, y: u16, nr: u8, bits: u8, prio: bool) -> Option<Color> {
         if self.bg_mode.num == 7 {
-            return self.fetch_bg7_tile(x, nr, prio);
+            return self.fetch_bg7_tile(x as u8, nr, prio);
         }
         // TODO: implement offset-per-tile
         let bg = &self.bgs[usize::from(nr)];
@@ -1154,9 +1352,23 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         Some(color)
     }
 
+    /// Get the background x coordinate for a pixel on the screen.
+    ///
+    /// In mode 5 and 6 the backgrounds are 512 pixels wide, where the
+    /// even pixels are part of the subscreen and the odd pixels are part
+    /// of the main screen.
+    fn bg_x(&self, x: u8, odd: bool) -> u16 {
+        if matches!(self.bg_mode.num, 5 | 6) {
+            (u16::from(x) << 1) | u16::from(odd)
+        } else {
+            x.into()
+        }
+    }
+
     pub fn fetch_screen(
         &mut self,
         x: u8,
+        bg_x: u16,
         y: u16,
         mainscreen: bool,
         subscreen: bool,
@@ -1183,7 +1395,7 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             }
             let mut layer_color_math_ = ly.color_math;
             if let Some(color) = match draw_ly {
-                &DrawLayer::Bg { nr, bits, prio } => self.fetch_bg_tile(x, y, nr, bits, prio),
+                &DrawLayer::Bg { nr, bits, prio } => self.fetch_bg_tile(bg_x, y, nr, bits, prio),
                 &DrawLayer::Sprite { prio } => {
                     let entry = self.obj_cache[usize::from(x)];
                     if prio == entry.prio && entry.palette_addr != 0 {
@@ -1221,7 +1433,11 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         )
     }
 
-    pub fn draw_pixel(&mut self, x: u8, y: u16) -> [u8; 4] {
+    pub fn draw_pixel<P: OutputPixel>(&mut self, x: u8, y: u16) -> P {
+        let (force_blank, brightness) = self.inidisp_at(x);
+        if force_blank {
+            return P::default();
+        }
         let mut lazy_in_window = None;
         let mut in_window = || {
             if let Some(iw) = lazy_in_window {
@@ -1242,6 +1458,7 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         });
         let (main, sub, color_math) = self.fetch_screen(
             x,
+            self.bg_x(x, true),
             y,
             main_enable,
             color_enable && self.color_math.add_subscreen,
@@ -1260,7 +1477,45 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         } else {
             main
         };
-        color.to_rgba8_with_brightness(self.brightness)
+        P::from_color(color, brightness)
+    }
+
+    /// Draw the left half of a high-resolution pixel, which is taken from the subscreen
+    pub fn draw_subscreen_pixel<P: OutputPixel>(&mut self, x: u8, y: u16) -> P {
+        let (force_blank, brightness) = self.inidisp_at(x);
+        if force_blank {
+            return P::default();
+        }
+        let (_, sub, _) = self.fetch_screen(x, self.bg_x(x, false), y, false, true);
+        P::from_color(sub.unwrap_or(self.color_math.color), brightness)
+    }
+
+    /// The forced blank and the brightness the pixel `x` of the current scanline is output with
+    fn inidisp_at(&self, x: u8) -> (bool, u8) {
+        match self.inidisp_change {
+            Some(change) if u16::from(x) < change.x => (change.force_blank, change.brightness),
+            _ => (self.force_blank, self.brightness),
+        }
+    }
+
+    /// Keep the INIDISP settings before a write while the current scanline is output.
+    ///
+    /// Scanlines are drawn at once shortly before their end, so without this
+    /// a fade would change the whole scanline, even the pixels which were
+    /// output before the write.
+    fn split_inidisp(&mut self) {
+        let drawn = self.pos.x + RAY_AHEAD_CYCLES >= self.get_scanline_cycles();
+        if self.pos.y + 1 >= self.vend() || drawn || self.inidisp_change.is_some() {
+            return;
+        }
+        let x = (self.pos.x >> 2).saturating_sub(FIRST_PIXEL_DOT).min(256);
+        if x > 0 {
+            self.inidisp_change = Some(InidispChange {
+                x,
+                force_blank: self.force_blank,
+                brightness: self.brightness,
+            })
+        }
     }
 
     fn draw_obj_8x8_tile(&mut self, obj: &Object, row: u8, tile_x: u8, tile_y: u8, size: [u8; 2]) {
@@ -1292,14 +1547,20 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         }
     }
 
+    /// Evaluate the sprites of a scanline and draw them into the sprite cache.
+    ///
+    /// The PPU selects up to 32 sprites per scanline starting with the first sprite
+    /// and fetches up to 34 tiles of them starting with the last selected sprite,
+    /// so the tiles of the sprites with the highest priority get dropped first.
+    /// Exceeding the limits sets the range and time overflow flags of $213E.
     fn refill_obj_cache(&mut self, y: u16) {
         self.obj_cache.fill(ObjCacheEntry::EMPTY);
         let y = (y & 0xff) as u8;
         let mut objs_in_line = 0;
         let mut tiles_in_line = 0;
-        let firstsprite = self.oam.get_first_sprite();
+        let firstsprite = usize::from(self.oam.get_first_sprite());
         for obj_id in 0..128 {
-            let obj = &mut self.oam.objs[usize::from(obj_id + firstsprite) & 0x7f];
+            let obj = &mut self.oam.objs[(obj_id + firstsprite) & 0x7f];
             obj.used = false;
             let size = self.obj_size[usize::from(obj.is_large)];
             if (-i16::from(size[0]) >= obj.x && obj.x != -256)
@@ -1308,19 +1569,21 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             {
                 continue;
             }
-            if objs_in_line >= 32 {
+            if objs_in_line >= MAX_OBJS_PER_LINE {
                 self.overflow_flags |= 0x40;
-                break;
+                if self.sprite_limits {
+                    break;
+                }
             }
+            objs_in_line += 1;
             obj.used = true;
         }
-        'obj_loop: for obj in 0..128 {
-            let obj = self.oam.objs[!usize::from(obj + firstsprite) & 0x7f];
+        'obj_loop: for obj_id in 0..128 {
+            let obj = self.oam.objs[(firstsprite + 127 - obj_id) & 0x7f];
             if !obj.used {
                 continue;
             }
             let size = self.obj_size[usize::from(obj.is_large)];
-            objs_in_line += 1;
             let y = y.wrapping_sub(obj.y);
             let y = if obj.is_yflip() { size[1] - y - 1 } else { y };
             'tile_loop: for tile_id in 0..size[0] >> 3 {
@@ -1328,9 +1591,11 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
                 if left < -7 || left >= 256 {
                     continue 'tile_loop;
                 }
-                if tiles_in_line >= 34 {
+                if tiles_in_line >= MAX_OBJ_TILES_PER_LINE {
                     self.overflow_flags |= 0x80;
-                    break 'obj_loop;
+                    if self.sprite_limits {
+                        break 'obj_loop;
+                    }
                 }
                 tiles_in_line += 1;
                 self.draw_obj_8x8_tile(&obj, y, tile_id, y >> 3, size);
@@ -1338,9 +1603,64 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         }
     }
 
+    /// Enable or disable the limits of 32 sprites and 34 sprite tiles per scanline.
+    ///
+    /// Without the limits sprites don't flicker or vanish, but a few games rely on
+    /// the limits to hide sprites. The overflow flags of $213E are set either way.
+    pub fn set_sprite_limits(&mut self, enabled: bool) {
+        self.sprite_limits = enabled
+    }
+
+    pub const fn sprite_limits(&self) -> bool {
+        self.sprite_limits
+    }
+
+    /// Test if the current scanline is drawn with 512 pixels
+    pub fn is_hires(&self) -> bool {
+        self.pseudo512 || matches!(self.bg_mode.num, 5 | 6)
+    }
+
+    /// Get the dimensions of the frame, which is currently drawn
+    pub fn frame_size(&self) -> FrameSize {
+        FrameSize {
+            width: SCREEN_WIDTH << u8::from(self.frame_hires),
+            height: u32::from(self.vend() - 1) << u8::from(self.frame_interlace),
+        }
+    }
+
+    /// Convert the already drawn low-resolution scanlines of this frame
+    /// into high-resolution scanlines by doubling every pixel.
+    fn widen_frame(&mut self) {
+        self.frame_hires = true;
+        let size = self.frame_size();
+        self.frame_buffer.set_size(size);
+        self.frame_buffer.widen_rows(size.height as usize);
+    }
+
     pub fn draw_scanline(&mut self) {
         let y = self.pos.y + 1;
-        let mut n = usize::from(self.pos.y) * 256;
+        if self.pos.y == 0 {
+            self.end_frame();
+            self.frame_hires = self.is_hires();
+            self.frame_interlace = self.interlace_active;
+            if self.render_pool.is_none() {
+                let size = self.frame_size();
+                self.frame_buffer.set_size(size);
+            }
+        } else if !self.frame_hires && self.is_hires() {
+            if self.render_pool.is_some() {
+                // the scanlines get widened when they are composited
+                self.frame_hires = true;
+            } else {
+                self.widen_frame();
+            }
+        }
+        let width = usize::from(256u16 << u8::from(self.frame_hires));
+        let row = usize::from(if self.frame_interlace {
+            (self.pos.y << 1) | u16::from(self.field)
+        } else {
+            self.pos.y
+        });
         for bg in &mut self.bgs {
             bg.cached_tile = None;
         }
@@ -1349,8 +1669,10 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
                 bg.mosaic_start = Some(y);
             }
         }
-        if self.force_blank {
-            self.frame_buffer.mut_pixels()[n..n + 256].fill([0; 4])
+        let force_blank =
+            self.force_blank && self.inidisp_change.is_none_or(|change| change.force_blank);
+        let bg_y = if force_blank {
+            None
         } else {
             self.refill_obj_cache(y - 1);
             self.mode7_settings.tmpy = (y & 0xff) as u8;
@@ -1359,13 +1681,217 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             }
             self.mode7_settings.update_tmp3::<0>();
             self.mode7_settings.update_tmp3::<1>();
-            for x in 0u8..=255 {
-                self.frame_buffer.mut_pixels()[n] = self.draw_pixel(x, y);
-                n += 1;
+            // in interlaced mode 5 and 6 both fields show different background lines
+            Some(
+                if self.frame_interlace && matches!(self.bg_mode.num, 5 | 6) {
+                    (y << 1) | u16::from(self.field)
+                } else {
+                    y
+                },
+            )
+        };
+        let format = self.render_format();
+        if self.render_pool.is_some() {
+            let job = bg_y.map(|bg_y| (bg_y, self.line_state()));
+            if let Some(pool) = &mut self.render_pool {
+                pool.dispatch(row, width, format, job)
+            }
+        } else {
+            match format {
+                PixelFormat::Rgba8 => self.write_line::<[u8; 4]>(row, width, bg_y),
+                PixelFormat::Bgr555 => self.write_line::<u16>(row, width, bg_y),
+            }
+        }
+        self.inidisp_change = None;
+    }
+
+    /// Draw a scanline with `width` pixels into the row `row` of the frame buffer,
+    /// the scanline is blank if `bg_y` is `None`
+    fn write_line<P: OutputPixel>(&mut self, row: usize, width: usize, bg_y: Option<u16>) {
+        let mut line = [P::default(); MAX_FRAME_WIDTH as usize];
+        if let Some(bg_y) = bg_y {
+            self.render_line(bg_y, &mut line[..width]);
+        }
+        self.output_scanline(row, P::scanline(&line[..width]));
+    }
+
+    /// The format the scanlines are rendered in, which is the native format
+    /// if the colors get corrected
+    fn render_format(&self) -> PixelFormat {
+        match self.frame_buffer.pixel_format() {
+            PixelFormat::Rgba8 if self.color_correction.is_some() => PixelFormat::Bgr555,
+            format => format,
+        }
+    }
+
+    fn output_scanline(&mut self, row: usize, pixels: Scanline) {
+        output_scanline(
+            &mut self.frame_buffer,
+            self.color_correction.as_ref(),
+            row,
+            pixels,
+        )
+    }
+
+    /// Correct the colors of frame buffers with the [`PixelFormat::Rgba8`] format,
+    /// `None` outputs them unchanged. See [`crate::color`] for details.
+    pub fn set_color_correction(&mut self, correction: Option<ColorCorrection>) {
+        self.color_correction = correction.map(|correction| (correction, correction.table()));
+    }
+
+    pub fn color_correction(&self) -> Option<ColorCorrection> {
+        self.color_correction
+            .as_ref()
+            .map(|(correction, _)| *correction)
+    }
+
+    /// Draw the pixels of a scanline, a high-resolution scanline has 512 pixels
+    fn render_line<P: OutputPixel>(&mut self, bg_y: u16, line: &mut [P]) {
+        if line.len() > SCREEN_WIDTH as usize {
+            let hires = self.is_hires();
+            for (x, pixels) in (0u8..=255).zip(line.chunks_exact_mut(2)) {
+                let main = self.draw_pixel(x, bg_y);
+                let sub = if hires {
+                    self.draw_subscreen_pixel(x, bg_y)
+                } else {
+                    main
+                };
+                pixels.copy_from_slice(&[sub, main]);
+            }
+        } else {
+            for (x, pixel) in (0u8..=255).zip(line) {
+                *pixel = self.draw_pixel(x, bg_y)
             }
         }
     }
 
+    /// Render scanlines on `threads` worker threads, zero disables the render threads.
+    ///
+    /// The scanlines are rendered while the emulation continues. The finished
+    /// frames only reach the frame buffer with [`Self::present`].
+    /// WebAssembly has no threads, so the scanlines are always rendered directly there.
+    pub fn set_render_threads(&mut self, threads: usize) {
+        let threads = if cfg!(target_arch = "wasm32") {
+            0
+        } else {
+            threads
+        };
+        self.render_pool = (threads > 0).then(|| RenderPool::new(threads));
+    }
+
+    pub fn render_threads(&self) -> usize {
+        self.render_pool.as_ref().map_or(0, RenderPool::threads)
+    }
+
+    /// Hand the dispatched scanlines of the frame to the render threads,
+    /// which composite the frame without the emulation waiting for them
+    fn end_frame(&mut self) {
+        let size = self.frame_size();
+        if let Some(pool) = &mut self.render_pool {
+            pool.end_frame(size)
+        }
+    }
+
+    /// Write the newest frame finished by the render threads into the frame buffer.
+    ///
+    /// Neither the emulation nor this waits for the render threads, so this
+    /// is usually the frame before the last emulated frame. Returns false if
+    /// no new frame is finished or if there are no render threads, which
+    /// write the scanlines into the frame buffer while they are emulated.
+    pub fn present(&mut self) -> bool {
+        let Some(frame) = self.render_pool.as_mut().and_then(RenderPool::latest_frame) else {
+            return false;
+        };
+        self.frame_buffer.set_size(frame.size);
+        for (row, pixels) in &frame.lines {
+            output_scanline(
+                &mut self.frame_buffer,
+                self.color_correction.as_ref(),
+                *row,
+                pixels.scanline(),
+            );
+        }
+        true
+    }
+
+    /// Copy the state needed to render the current scanline
+    fn line_state(&self) -> LineState {
+        LineState {
+            vram: Arc::clone(&self.vram.vram),
+            cgram: self.cgram.clone(),
+            bgs: self.bgs,
+            bg_mode: self.bg_mode,
+            bg3_prio: self.bg3_prio,
+            pos: self.pos,
+            brightness: self.brightness,
+            draw_layers: self.draw_layers.clone(),
+            obj_tile_addr: self.obj_tile_addr,
+            obj_layer: self.obj_layer,
+            obj_cache: self.obj_cache,
+            color_math: self.color_math,
+            direct_color_mode: self.direct_color_mode,
+            interlace_active: self.interlace_active,
+            window_positions: self.window_positions,
+            overscan: self.overscan,
+            pseudo512: self.pseudo512,
+            mosaic_size: self.mosaic_size,
+            mode7_settings: self.mode7_settings.clone(),
+            field: self.field,
+            force_blank: self.force_blank,
+            inidisp_change: self.inidisp_change,
+            is_pal: self.is_pal,
+        }
+    }
+
+    /// Reset the PPU with the reset line, which only enables forced blanking.
+    /// The video memory and all other registers keep their values.
+    pub(crate) fn reset(&mut self) {
+        self.force_blank = true
+    }
+
+    /// Return the PPU to its power-on state, the frame buffer, the render
+    /// threads, the sprite limits and the color correction are kept
+    pub(crate) fn power_cycle(&mut self) {
+        Ppu {
+            frame_buffer: _,
+            render_pool: _,
+            oam: self.oam,
+            cgram: self.cgram,
+            vram: self.vram,
+            bgs: self.bgs,
+            bg_mode: self.bg_mode,
+            bg3_prio: self.bg3_prio,
+            pos: self.pos,
+            latched: self.latched,
+            brightness: self.brightness,
+            draw_layers: self.draw_layers,
+            obj_size: self.obj_size,
+            obj_tile_addr: self.obj_tile_addr,
+            obj_layer: self.obj_layer,
+            obj_cache: self.obj_cache,
+            overflow_flags: self.overflow_flags,
+            sprite_limits: _,
+            color_correction: _,
+            color_math: self.color_math,
+            direct_color_mode: self.direct_color_mode,
+            object_interlace: self.object_interlace,
+            interlace_active: self.interlace_active,
+            window_positions: self.window_positions,
+            overscan: self.overscan,
+            pseudo512: self.pseudo512,
+            mosaic_size: self.mosaic_size,
+            mode7_settings: self.mode7_settings,
+            field: self.field,
+            force_blank: self.force_blank,
+            inidisp_change: self.inidisp_change,
+            is_pal: self.is_pal,
+            frame_hires: self.frame_hires,
+            frame_interlace: self.frame_interlace,
+            open_bus1: self.open_bus1,
+            open_bus2: self.open_bus2,
+        } = Ppu::new(Detached, self.is_pal);
+    }
+
     pub fn is_in_window(&self, x: u8, window: &Window) -> bool {
         let window_n = |n: usize| {
             (self.window_positions[n][0]..=self.window_positions[n][1]).contains(&x)
@@ -1399,8 +1925,12 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         &mut self.pos
     }
 
+    /// Latch the H/V counters, the horizontal counter is latched in dots
     pub fn latch(&mut self) {
-        self.latched.pos = self.pos;
+        self.latched.pos = RayPos {
+            x: self.pos.x >> 2,
+            y: self.pos.y,
+        };
         self.latched.latched = true
     }
 
@@ -1442,10 +1972,6 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         self.pos.y >= self.vend()
     }
 
-    pub fn is_cpu_active(&self) -> bool {
-        !(536..536 + 40).contains(&self.pos.x)
-    }
-
     pub fn end_vblank(&mut self) {
         self.field ^= true;
         self.bgs.iter_mut().for_each(|bg| bg.mosaic_start = None);
@@ -1463,12 +1989,67 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
     }
 
     pub fn vblank(&mut self) {
+        self.end_frame();
         if !self.force_blank {
             self.oam.oam_reset();
         }
     }
 }
 
+impl Ppu<Detached> {
+    /// Take over the state of a scanline to render it
+    fn load_line_state(&mut self, state: LineState) {
+        let LineState {
+            vram,
+            cgram,
+            bgs,
+            bg_mode,
+            bg3_prio,
+            pos,
+            brightness,
+            draw_layers,
+            obj_tile_addr,
+            obj_layer,
+            obj_cache,
+            color_math,
+            direct_color_mode,
+            interlace_active,
+            window_positions,
+            overscan,
+            pseudo512,
+            mosaic_size,
+            mode7_settings,
+            field,
+            force_blank,
+            inidisp_change,
+            is_pal,
+        } = state;
+        self.vram.vram = vram;
+        self.cgram = cgram;
+        self.bgs = bgs;
+        self.bg_mode = bg_mode;
+        self.bg3_prio = bg3_prio;
+        self.pos = pos;
+        self.brightness = brightness;
+        self.draw_layers = draw_layers;
+        self.obj_tile_addr = obj_tile_addr;
+        self.obj_layer = obj_layer;
+        self.obj_cache = obj_cache;
+        self.color_math = color_math;
+        self.direct_color_mode = direct_color_mode;
+        self.interlace_active = interlace_active;
+        self.window_positions = window_positions;
+        self.overscan = overscan;
+        self.pseudo512 = pseudo512;
+        self.mosaic_size = mosaic_size;
+        self.mode7_settings = mode7_settings;
+        self.field = field;
+        self.force_blank = force_blank;
+        self.inidisp_change = inidisp_change;
+        self.is_pal = is_pal;
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, InSaveState)]
 pub struct RemapMode {
     mask: u16,
@@ -1491,3 +2072,6 @@ impl RemapMode {
         (((rest_part >> self.shift) | (rest_part << 3)) & self.mask) | addr_part
     }
 }
+
+#[cfg(test)]
+mod tests;
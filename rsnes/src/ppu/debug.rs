@@ -0,0 +1,138 @@
+//! Snapshots of the PPU state for debugger user interfaces
+//!
+//! The snapshots are decoded from the registers and memories, so that
+//! VRAM, palette and sprite viewers don't need to know the register layout.
+
+use super::{Ppu, VRAM_SIZE};
+use crate::backend::FrameBuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BgDebugState {
+    /// Horizontal and vertical scroll offset
+    pub scroll: [u16; 2],
+    /// Word address of the tilemap in the VRAM
+    pub map_base_addr: u16,
+    /// Word address of the character data in the VRAM
+    pub tile_base_addr: u16,
+    /// Width and height of the tilemap in tiles
+    pub map_size: [u8; 2],
+    /// Width and height of a tile in pixels
+    pub tile_size: [u8; 2],
+    pub mosaic: bool,
+    /// The layer is enabled on the main screen (TM)
+    pub main_screen: bool,
+    /// The layer is enabled on the sub screen (TS)
+    pub sub_screen: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectDebugState {
+    /// The horizontal position in the range `-256..256`
+    pub x: i16,
+    pub y: u8,
+    /// The 9-bit number of the first tile including the name table select bit
+    pub tile: u16,
+    /// The sprite palette `0..8`, which starts at CGRAM entry `128 + 16 * palette`
+    pub palette: u8,
+    pub priority: u8,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    /// Width and height in pixels
+    pub size: [u8; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode7DebugState {
+    /// The matrix parameters A, B, C and D in 8.8 fixed point
+    pub matrix: [i16; 4],
+    /// The center of the rotation
+    pub center: [i16; 2],
+    /// The horizontal and vertical scroll offset
+    pub offset: [i16; 2],
+    pub x_mirror: bool,
+    pub y_mirror: bool,
+    /// The tilemap repeats outside of the 1024x1024 pixels
+    pub wrap: bool,
+    /// Tile 0 is shown outside of the tilemap instead of transparent pixels
+    pub fill: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PpuDebugState {
+    /// The background mode `0..8`
+    pub bg_mode: u8,
+    /// The high priority of BG3 in mode 1
+    pub bg3_priority: bool,
+    /// The EXTBG bit of SETINI
+    pub extbg: bool,
+    pub bgs: [BgDebugState; 4],
+    pub mode7: Mode7DebugState,
+    pub brightness: u8,
+    pub force_blank: bool,
+    /// Sprites are enabled on the main and the sub screen
+    pub obj_screens: [bool; 2],
+    /// Width and height of the small and the large sprites in pixels
+    pub obj_sizes: [[u8; 2]; 2],
+    /// Word addresses of the character data of the two sprite name tables
+    pub obj_tile_addr: [u16; 2],
+    /// The 256 colors of the CGRAM in BGR555 format
+    pub palette: [u16; 256],
+    pub objects: [ObjectDebugState; 128],
+}
+
+/// Sign-extend a 13-bit value of the mode 7 registers
+const fn sign_extend13(val: u16) -> i16 {
+    ((val << 3) as i16) >> 3
+}
+
+impl<FB: FrameBuffer> Ppu<FB> {
+    /// Take a snapshot of the registers, the palette and the sprites
+    pub fn debug_state(&self) -> PpuDebugState {
+        let mode7 = &self.mode7_settings;
+        PpuDebugState {
+            bg_mode: self.bg_mode.num,
+            bg3_priority: self.bg_mode.bg3_prio,
+            extbg: self.bg_mode.extbg,
+            bgs: self.bgs.map(|bg| BgDebugState {
+                scroll: bg.scroll,
+                map_base_addr: bg.map_base_addr,
+                tile_base_addr: bg.tile_base_addr,
+                map_size: bg.size,
+                tile_size: bg.tile_size,
+                mosaic: bg.mosaic,
+                main_screen: bg.layer.main_screen,
+                sub_screen: bg.layer.sub_screen,
+            }),
+            mode7: Mode7DebugState {
+                matrix: mode7.params.map(|param| param as i16),
+                center: mode7.center.map(sign_extend13),
+                offset: mode7.offset.map(sign_extend13),
+                x_mirror: mode7.x_mirror,
+                y_mirror: mode7.y_mirror,
+                wrap: mode7.wrap,
+                fill: mode7.fill,
+            },
+            brightness: self.brightness,
+            force_blank: self.force_blank,
+            obj_screens: [self.obj_layer.main_screen, self.obj_layer.sub_screen],
+            obj_sizes: self.obj_size,
+            obj_tile_addr: self.obj_tile_addr,
+            palette: core::array::from_fn(|i| self.cgram.read16(i as u8)),
+            objects: self.oam.objs.map(|obj| ObjectDebugState {
+                x: obj.x,
+                y: obj.y,
+                tile: u16::from(obj.tile_nr) | (u16::from(obj.attrs & 1) << 8),
+                palette: obj.get_palette_nr(),
+                priority: obj.get_priority(),
+                x_flip: obj.is_xflip(),
+                y_flip: obj.is_yflip(),
+                size: self.obj_size[usize::from(obj.is_large)],
+            }),
+        }
+    }
+
+    /// The contents of the VRAM as 16-bit words
+    pub fn vram(&self) -> &[u16; VRAM_SIZE] {
+        &self.vram.vram
+    }
+}
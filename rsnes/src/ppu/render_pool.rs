@@ -0,0 +1,372 @@
+//! Rendering of scanlines on worker threads
+//!
+//! Every scanline is rendered from a [`LineState`] taken when the scanline
+//! is due, so the emulation continues while the scanline is rendered.
+//! Every worker keeps its own PPU, which takes over the state of a scanline.
+//!
+//! A compositor thread collects the rendered scanlines. When all scanlines
+//! of a frame are rendered, it hands the frame over through a triple buffer:
+//! the compositor and the frontend each own a frame and only swap it with
+//! the frame in the middle, so neither of them waits for the other.
+//! The picture is the same as without render threads.
+
+use super::{Detached, LineState, OutputPixel, Ppu};
+use crate::backend::{FrameSize, PixelFormat, Scanline};
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex, PoisonError,
+    },
+    thread::JoinHandle,
+};
+
+/// The pixels of a scanline in the format of the frame buffer
+#[derive(Debug)]
+pub(super) enum Pixels {
+    Rgba8(Vec<[u8; 4]>),
+    Bgr555(Vec<u16>),
+}
+
+impl Pixels {
+    fn render(ppu: &mut Ppu<Detached>, bg_y: u16, width: usize, format: PixelFormat) -> Self {
+        fn render<P: OutputPixel>(ppu: &mut Ppu<Detached>, bg_y: u16, width: usize) -> Vec<P> {
+            let mut line = vec![P::default(); width];
+            ppu.render_line(bg_y, &mut line);
+            line
+        }
+        match format {
+            PixelFormat::Rgba8 => Self::Rgba8(render(ppu, bg_y, width)),
+            PixelFormat::Bgr555 => Self::Bgr555(render(ppu, bg_y, width)),
+        }
+    }
+
+    /// A blank scanline
+    fn blank(width: usize, format: PixelFormat) -> Self {
+        match format {
+            PixelFormat::Rgba8 => Self::Rgba8(vec![Default::default(); width]),
+            PixelFormat::Bgr555 => Self::Bgr555(vec![Default::default(); width]),
+        }
+    }
+
+    /// Double every pixel, if the scanline is narrower than `width`
+    fn widen(self, width: usize) -> Self {
+        fn widen<P: Copy>(pixels: Vec<P>, width: usize) -> Vec<P> {
+            if pixels.len() < width {
+                pixels.into_iter().flat_map(|pixel| [pixel; 2]).collect()
+            } else {
+                pixels
+            }
+        }
+        match self {
+            Self::Rgba8(pixels) => Self::Rgba8(widen(pixels, width)),
+            Self::Bgr555(pixels) => Self::Bgr555(widen(pixels, width)),
+        }
+    }
+
+    pub fn scanline(&self) -> Scanline<'_> {
+        match self {
+            Self::Rgba8(pixels) => Scanline::Rgba8(pixels),
+            Self::Bgr555(pixels) => Scanline::Bgr555(pixels),
+        }
+    }
+}
+
+/// A frame composited from the rendered scanlines
+#[derive(Debug, Default)]
+pub(super) struct Frame {
+    pub size: FrameSize,
+    /// The rows and their pixels
+    pub lines: Vec<(usize, Pixels)>,
+}
+
+/// The frame in the middle of the triple buffer and whether it is newer
+/// than the frame of the frontend
+#[derive(Debug, Default)]
+struct TripleBuffer {
+    middle: Mutex<(Box<Frame>, bool)>,
+}
+
+impl TripleBuffer {
+    /// Swap the finished `frame` of the compositor with the middle frame
+    fn publish(&self, frame: &mut Box<Frame>) {
+        let mut middle = self.middle.lock().unwrap_or_else(PoisonError::into_inner);
+        core::mem::swap(&mut middle.0, frame);
+        middle.1 = true;
+    }
+
+    /// Swap the `frame` of the frontend with the middle frame, if it is newer
+    fn take(&self, frame: &mut Box<Frame>) -> bool {
+        let mut middle = self.middle.lock().unwrap_or_else(PoisonError::into_inner);
+        let fresh = core::mem::take(&mut middle.1);
+        if fresh {
+            core::mem::swap(&mut middle.0, frame);
+        }
+        fresh
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    frame: u64,
+    index: usize,
+    width: usize,
+    format: PixelFormat,
+    bg_y: u16,
+    state: LineState,
+}
+
+#[derive(Debug)]
+enum Message {
+    /// The next scanline of the current frame, which is rendered by a worker if `rendered`
+    Line {
+        row: usize,
+        format: PixelFormat,
+        rendered: bool,
+    },
+    /// A worker rendered the scanline `index` of the frame `frame`
+    Rendered {
+        frame: u64,
+        index: usize,
+        pixels: Pixels,
+    },
+    /// All scanlines of the current frame are dispatched
+    EndFrame(FrameSize),
+}
+
+#[derive(Debug)]
+struct RenderedLine {
+    row: usize,
+    format: PixelFormat,
+    /// The pixels of the scanline or `None` if it is blank
+    pixels: Option<Pixels>,
+}
+
+/// A frame, whose scanlines are still being rendered
+#[derive(Debug, Default)]
+struct PendingFrame {
+    lines: Vec<RenderedLine>,
+    pending: usize,
+    /// The size of the frame, once all scanlines are dispatched
+    size: Option<FrameSize>,
+}
+
+impl PendingFrame {
+    fn is_finished(&self) -> bool {
+        self.size.is_some() && self.pending == 0
+    }
+
+    /// Composite the scanlines into `frame`, scanlines drawn
+    /// before the frame became high-resolution get widened
+    fn composite(self, frame: &mut Frame) {
+        let size = self.size.unwrap_or_default();
+        let width = size.width as usize;
+        frame.size = size;
+        frame.lines.clear();
+        frame.lines.extend(self.lines.into_iter().map(|line| {
+            let pixels = match line.pixels {
+                Some(pixels) => pixels.widen(width),
+                None => Pixels::blank(width, line.format),
+            };
+            (line.row, pixels)
+        }));
+    }
+}
+
+fn worker(jobs: Arc<Mutex<Receiver<Job>>>, results: Sender<Message>) {
+    let mut ppu = Ppu::new(Detached, false);
+    loop {
+        let job = match jobs.lock().map(|jobs| jobs.recv()) {
+            Ok(Ok(job)) => job,
+            _ => break,
+        };
+        ppu.load_line_state(job.state);
+        let pixels = Pixels::render(&mut ppu, job.bg_y, job.width, job.format);
+        let message = Message::Rendered {
+            frame: job.frame,
+            index: job.index,
+            pixels,
+        };
+        if results.send(message).is_err() {
+            break;
+        }
+    }
+}
+
+fn compositor(messages: Receiver<Message>, frames: Arc<TripleBuffer>) {
+    let mut back = Box::<Frame>::default();
+    let mut pending = VecDeque::<PendingFrame>::new();
+    // the number of the oldest pending frame
+    let mut first = 0u64;
+    for message in messages {
+        match message {
+            Message::Line {
+                row,
+                format,
+                rendered,
+            } => {
+                if pending.back().is_none_or(|frame| frame.size.is_some()) {
+                    pending.push_back(PendingFrame::default())
+                }
+                if let Some(frame) = pending.back_mut() {
+                    frame.lines.push(RenderedLine {
+                        row,
+                        format,
+                        pixels: None,
+                    });
+                    frame.pending += usize::from(rendered);
+                }
+            }
+            Message::Rendered {
+                frame,
+                index,
+                pixels,
+            } => {
+                let frame = usize::try_from(frame - first)
+                    .ok()
+                    .and_then(|frame| pending.get_mut(frame));
+                if let Some(frame) = frame {
+                    frame.lines[index].pixels = Some(pixels);
+                    frame.pending -= 1;
+                }
+            }
+            Message::EndFrame(size) => {
+                if let Some(frame) = pending.back_mut() {
+                    frame.size = Some(size)
+                }
+            }
+        }
+        while pending.front().is_some_and(PendingFrame::is_finished) {
+            if let Some(frame) = pending.pop_front() {
+                frame.composite(&mut back);
+                frames.publish(&mut back);
+                first += 1;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct RenderPool {
+    jobs: Option<Sender<Job>>,
+    messages: Option<Sender<Message>>,
+    workers: Vec<JoinHandle<()>>,
+    compositor: Option<JoinHandle<()>>,
+    frames: Arc<TripleBuffer>,
+    /// The last frame taken by the frontend
+    front: Box<Frame>,
+    /// The number of the current frame and its dispatched scanlines
+    frame: u64,
+    lines: usize,
+}
+
+impl RenderPool {
+    pub fn new(threads: usize) -> Self {
+        let (jobs, job_recv) = channel();
+        let (messages, message_recv) = channel();
+        let job_recv = Arc::new(Mutex::new(job_recv));
+        let workers = (0..threads)
+            .map(|_| {
+                let (jobs, messages) = (job_recv.clone(), messages.clone());
+                std::thread::spawn(move || worker(jobs, messages))
+            })
+            .collect();
+        let frames = Arc::<TripleBuffer>::default();
+        let compositor = {
+            let frames = Arc::clone(&frames);
+            std::thread::spawn(move || compositor(message_recv, frames))
+        };
+        Self {
+            jobs: Some(jobs),
+            messages: Some(messages),
+            workers,
+            compositor: Some(compositor),
+            frames,
+            front: Box::default(),
+            frame: 0,
+            lines: 0,
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Render a scanline, which is blank if there is no job
+    pub fn dispatch(
+        &mut self,
+        row: usize,
+        width: usize,
+        format: PixelFormat,
+        job: Option<(u16, LineState)>,
+    ) {
+        let (Some(jobs), Some(messages)) = (&self.jobs, &self.messages) else {
+            return;
+        };
+        let index = self.lines;
+        self.lines += 1;
+        let rendered = job.is_some();
+        // the scanline reaches the compositor before its pixels
+        let _ = messages.send(Message::Line {
+            row,
+            format,
+            rendered,
+        });
+        if let Some((bg_y, state)) = job {
+            let job = Job {
+                frame: self.frame,
+                index,
+                width,
+                format,
+                bg_y,
+                state,
+            };
+            if jobs.send(job).is_err() {
+                // no worker renders the scanline, so it stays blank
+                let _ = messages.send(Message::Rendered {
+                    frame: self.frame,
+                    index,
+                    pixels: Pixels::blank(width, format),
+                });
+            }
+        }
+    }
+
+    /// Let the compositor finish the frame with the size `size`
+    pub fn end_frame(&mut self, size: FrameSize) {
+        if self.lines == 0 {
+            return;
+        }
+        if let Some(messages) = &self.messages {
+            let _ = messages.send(Message::EndFrame(size));
+        }
+        self.frame += 1;
+        self.lines = 0;
+    }
+
+    /// The newest finished frame, if it wasn't taken yet
+    pub fn latest_frame(&mut self) -> Option<&Frame> {
+        self.frames.take(&mut self.front).then_some(&*self.front)
+    }
+}
+
+impl Clone for RenderPool {
+    fn clone(&self) -> Self {
+        Self::new(self.threads())
+    }
+}
+
+impl Drop for RenderPool {
+    fn drop(&mut self) {
+        // the workers stop when the job channel is closed
+        // and the compositor stops after the workers
+        drop(self.jobs.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        drop(self.messages.take());
+        if let Some(compositor) = self.compositor.take() {
+            let _ = compositor.join();
+        }
+    }
+}
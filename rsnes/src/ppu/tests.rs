@@ -0,0 +1,526 @@
+use super::*;
+use crate::{
+    backend::{bgr555_to_rgba8, FRAME_BUFFER_SIZE},
+    color::ColorCorrection,
+};
+
+#[derive(Debug, Clone)]
+struct VecFrameBuffer(Vec<[u8; 4]>, FrameSize);
+
+impl FrameBuffer for VecFrameBuffer {
+    fn set_size(&mut self, size: FrameSize) {
+        self.1 = size
+    }
+    fn write_scanline(&mut self, row: usize, pixels: Scanline) {
+        let Scanline::Rgba8(pixels) = pixels else {
+            panic!("the pixels are not in the requested format")
+        };
+        let n = row * self.1.width as usize;
+        self.0[n..n + pixels.len()].copy_from_slice(pixels)
+    }
+    fn widen_rows(&mut self, rows: usize) {
+        let width = SCREEN_WIDTH as usize;
+        for i in (0..rows * width).rev() {
+            let pixel = self.0[i];
+            self.0[i << 1..(i << 1) + 2].fill(pixel);
+        }
+    }
+    fn request_redraw(&mut self) {}
+}
+
+/// A frame buffer, which asks for [`PixelFormat::Bgr555`] pixels
+#[derive(Debug, Clone)]
+struct Bgr555FrameBuffer(Vec<u16>, FrameSize);
+
+impl FrameBuffer for Bgr555FrameBuffer {
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Bgr555
+    }
+    fn set_size(&mut self, size: FrameSize) {
+        self.1 = size
+    }
+    fn write_scanline(&mut self, row: usize, pixels: Scanline) {
+        let Scanline::Bgr555(pixels) = pixels else {
+            panic!("the pixels are not in the requested format")
+        };
+        let n = row * self.1.width as usize;
+        self.0[n..n + pixels.len()].copy_from_slice(pixels)
+    }
+    fn widen_rows(&mut self, rows: usize) {
+        let width = SCREEN_WIDTH as usize;
+        for i in (0..rows * width).rev() {
+            let pixel = self.0[i];
+            self.0[i << 1..(i << 1) + 2].fill(pixel);
+        }
+    }
+    fn request_redraw(&mut self) {}
+}
+
+fn write_vram<FB: FrameBuffer>(ppu: &mut Ppu<FB>, addr: u16, words: &[u16]) {
+    ppu.write_register(0x16, addr as u8);
+    ppu.write_register(0x17, (addr >> 8) as u8);
+    for word in words {
+        ppu.write_register(0x18, *word as u8);
+        ppu.write_register(0x19, (word >> 8) as u8);
+    }
+}
+
+fn rgba_frame_buffer() -> VecFrameBuffer {
+    VecFrameBuffer(vec![[0; 4]; FRAME_BUFFER_SIZE], FrameSize::DEFAULT)
+}
+
+/// Draw a frame of a mode 1 background, which is changed mid-frame
+fn draw_frame<FB: FrameBuffer>(
+    frame_buffer: FB,
+    render_threads: usize,
+    correction: Option<ColorCorrection>,
+) -> FB {
+    let mut ppu = Ppu::new(frame_buffer, false);
+    ppu.set_render_threads(render_threads);
+    ppu.set_color_correction(correction);
+    ppu.write_register(0x00, 0x0f);
+    ppu.write_register(0x05, 0x01);
+    ppu.write_register(0x07, 0x00);
+    ppu.write_register(0x0b, 0x01);
+    ppu.write_register(0x2c, 0x01);
+    ppu.write_register(0x15, 0x80);
+    ppu.write_register(0x21, 0);
+    for i in 0u16..32 {
+        let color = i.wrapping_mul(0x0c63);
+        ppu.write_register(0x22, color as u8);
+        ppu.write_register(0x22, (color >> 8) as u8);
+    }
+    let tilemap: Vec<u16> = (0..0x400).map(|i| (i * 7) & 0x3f).collect();
+    write_vram(&mut ppu, 0, &tilemap);
+    let tiles: Vec<u16> = (0u16..0x400).map(|i| i.wrapping_mul(0x9e37)).collect();
+    write_vram(&mut ppu, 0x1000, &tiles);
+
+    for y in 0..224 {
+        match y {
+            60 => write_vram(&mut ppu, 0x1000, &[0xffff; 64]),
+            100 => {
+                ppu.write_register(0x0d, 3);
+                ppu.write_register(0x0d, 0);
+            }
+            120 => {
+                ppu.write_register(0x21, 3);
+                ppu.write_register(0x22, 0x1f);
+                ppu.write_register(0x22, 0x7c);
+            }
+            150 => ppu.write_register(0x05, 0x05),
+            200 => ppu.write_register(0x00, 0x8f),
+            _ => (),
+        }
+        ppu.mut_pos().y = y;
+        ppu.draw_scanline();
+    }
+    ppu.vblank();
+    wait_for_frame(&mut ppu);
+    ppu.frame_buffer
+}
+
+/// Wait for the render threads to finish the frame and present it
+fn wait_for_frame<FB: FrameBuffer>(ppu: &mut Ppu<FB>) {
+    if ppu.render_threads() > 0 {
+        while !ppu.present() {
+            std::thread::yield_now()
+        }
+    }
+}
+
+#[test]
+fn render_threads() {
+    let frame = draw_frame(rgba_frame_buffer(), 0, None);
+    assert_eq!(frame.1.width, 512);
+    let pixels = frame.1.pixel_count();
+    assert!(frame.0[..pixels].iter().any(|pixel| *pixel != [0; 4]));
+    let threaded = draw_frame(rgba_frame_buffer(), 3, None);
+    assert_eq!(threaded.1, frame.1);
+    assert!(threaded.0[..pixels] == frame.0[..pixels]);
+}
+
+#[test]
+fn present_finished_frames() {
+    let mut ppu = Ppu::new(rgba_frame_buffer(), false);
+    assert!(!ppu.present());
+    ppu.set_render_threads(2);
+    ppu.write_register(0x00, 0x0f);
+    for color in [0x001f, 0x03e0] {
+        ppu.write_register(0x21, 0);
+        ppu.write_register(0x22, color as u8);
+        ppu.write_register(0x22, (color >> 8) as u8);
+        for y in 0..224 {
+            ppu.mut_pos().y = y;
+            ppu.draw_scanline();
+        }
+        // the frame is only handed over after all its scanlines are dispatched
+        assert!(!ppu.present());
+        ppu.vblank();
+        wait_for_frame(&mut ppu);
+        // a frame is only presented once
+        assert!(!ppu.present());
+        let backdrop = Color::from(color).to_rgba8_with_brightness(15);
+        assert_eq!(ppu.frame_buffer.0[100 * 256], backdrop);
+    }
+}
+
+#[test]
+fn bgr555_pixel_format() {
+    let rgba = draw_frame(rgba_frame_buffer(), 0, None);
+    let pixels = rgba.1.pixel_count();
+    for threads in [0, 3] {
+        let frame = draw_frame(
+            Bgr555FrameBuffer(vec![0; FRAME_BUFFER_SIZE], rgba.1),
+            threads,
+            None,
+        );
+        assert_eq!(frame.1, rgba.1);
+        // the colors agree up to the rounding of the expansion to 8 bits
+        for (color, pixel) in frame.0[..pixels].iter().zip(&rgba.0[..pixels]) {
+            let expanded = bgr555_to_rgba8(*color);
+            if *pixel == [0; 4] {
+                assert_eq!(*color, 0);
+            } else {
+                assert!((0..3).all(|i| expanded[i].abs_diff(pixel[i]) <= 1));
+            }
+        }
+    }
+}
+
+#[test]
+fn color_correction() {
+    let native = draw_frame(
+        Bgr555FrameBuffer(vec![0; FRAME_BUFFER_SIZE], FrameSize::DEFAULT),
+        0,
+        None,
+    );
+    // the native colors are output, if the frame buffer requests them
+    let corrected = draw_frame(
+        Bgr555FrameBuffer(vec![0; FRAME_BUFFER_SIZE], FrameSize::DEFAULT),
+        0,
+        Some(ColorCorrection::CRT),
+    );
+    assert!(corrected.0 == native.0);
+    let table = ColorCorrection::CRT.table();
+    let pixels = native.1.pixel_count();
+    for threads in [0, 3] {
+        let frame = draw_frame(rgba_frame_buffer(), threads, Some(ColorCorrection::CRT));
+        assert_eq!(frame.1, native.1);
+        assert!(frame.0[..pixels]
+            .iter()
+            .zip(&native.0[..pixels])
+            .all(|(pixel, color)| *pixel == table.get(*color)));
+    }
+}
+
+#[test]
+fn debug_state() {
+    let frame_buffer = VecFrameBuffer(vec![], FrameSize::DEFAULT);
+    let mut ppu = Ppu::new(frame_buffer, false);
+    ppu.write_register(0x01, 0xc0);
+    ppu.write_register(0x05, 0x09);
+    ppu.write_register(0x07, 0x7d);
+    ppu.write_register(0x0b, 0x21);
+    ppu.write_register(0x2c, 0x11);
+    ppu.write_register(0x21, 3);
+    ppu.write_register(0x22, 0xff);
+    ppu.write_register(0x22, 0x7f);
+    ppu.write_register(0x02, 0x00);
+    ppu.write_register(0x03, 0x00);
+    for val in [0x10, 0x20, 0x30, 0xcb] {
+        ppu.write_register(0x04, val);
+    }
+    ppu.write_register(0x02, 0x00);
+    ppu.write_register(0x03, 0x01);
+    ppu.write_register(0x04, 0x03);
+    write_vram(&mut ppu, 0x1234, &[0xabcd]);
+
+    let state = ppu.debug_state();
+    assert_eq!(state.bg_mode, 1);
+    assert!(state.bg3_priority);
+    assert_eq!(state.bgs[0].map_base_addr, 0x7c00);
+    assert_eq!(state.bgs[0].map_size, [64, 32]);
+    assert_eq!(state.bgs[0].tile_base_addr, 0x1000);
+    assert_eq!(state.bgs[1].tile_base_addr, 0x2000);
+    assert!(state.bgs[0].main_screen && !state.bgs[1].main_screen);
+    assert_eq!(state.obj_screens, [true, false]);
+    assert_eq!(state.palette[..4], [0, 0, 0, 0x7fff]);
+    let obj = state.objects[0];
+    assert_eq!((obj.x, obj.y, obj.tile), (-240, 0x20, 0x130));
+    assert_eq!((obj.palette, obj.priority), (5, 0));
+    assert!(obj.x_flip && obj.y_flip);
+    assert_eq!(obj.size, [32, 64]);
+    assert_eq!(state.objects[1].size, [16, 32]);
+    assert_eq!(ppu.vram()[0x1234], 0xabcd);
+}
+
+#[test]
+fn viewer_images() {
+    let frame_buffer = VecFrameBuffer(vec![], FrameSize::DEFAULT);
+    let mut ppu = Ppu::new(frame_buffer, false);
+    ppu.write_register(0x01, 0x02);
+    ppu.write_register(0x05, 0x01);
+    ppu.write_register(0x07, 0x00);
+    ppu.write_register(0x0b, 0x01);
+    for (idx, color) in [(37, 0x001f), (146, 0x03e0)] {
+        ppu.write_register(0x21, idx);
+        ppu.write_register(0x22, color as u8);
+        ppu.write_register(0x22, (color >> 8) as u8);
+    }
+    // tile 1 with the color 5 at the top left pixel
+    write_vram(&mut ppu, 0x1010, &[0x0080]);
+    write_vram(&mut ppu, 0x1018, &[0x0080]);
+    // tile 1 with palette 2 mirrored horizontally
+    write_vram(&mut ppu, 0x0001, &[0x4801]);
+    // sprite tile 0 with the color 2 at the top left pixel
+    write_vram(&mut ppu, 0x4000, &[0x8000]);
+    ppu.write_register(0x02, 0x00);
+    ppu.write_register(0x03, 0x00);
+    for val in [0, 0, 0, 0x02] {
+        ppu.write_register(0x04, val);
+    }
+
+    let (red, green) = ([0xff, 0, 0, 0xff], [0, 0xff, 0, 0xff]);
+    let tiles = ppu.render_tiles(0x1000, 4, 2, 16);
+    assert_eq!((tiles.width, tiles.height), (128, 8 * 128));
+    assert_eq!(tiles.get(8, 0), red);
+    assert_eq!(tiles.get(9, 0), [0; 4]);
+
+    let tilemap = ppu.render_tilemap(0).unwrap();
+    assert_eq!((tilemap.width, tilemap.height), (256, 256));
+    assert_eq!(tilemap.get(15, 0), red);
+    assert_eq!(tilemap.get(8, 0), [0; 4]);
+    assert!(ppu.render_tilemap(3).is_none());
+
+    let palette = ppu.render_palette(2);
+    assert_eq!(palette.get(10, 4), red);
+    assert_eq!(palette.get(4, 18), green);
+
+    let sprites = ppu.render_sprites();
+    assert_eq!(sprites.get(0, 0), green);
+    assert_eq!(sprites.get(1, 0), [0; 4]);
+}
+
+/// Draw a scanline through `count` sprites at y=10, which are `step` pixels apart.
+/// Returns the overflow flags and which pixels of the line are covered by sprites.
+fn sprite_line(count: u8, step: u8, large: bool, sprite_limits: bool) -> (u8, Vec<bool>) {
+    let frame_buffer = VecFrameBuffer(vec![[0; 4]; FRAME_BUFFER_SIZE], FrameSize::DEFAULT);
+    let mut ppu = Ppu::new(frame_buffer, false);
+    ppu.set_sprite_limits(sprite_limits);
+    ppu.write_register(0x00, 0x0f);
+    // small sprites 8x8, large sprites 16x16
+    ppu.write_register(0x01, 0x00);
+    // sprite tiles 0 and 1 with the color 1 in every pixel
+    write_vram(&mut ppu, 0x0000, &[0x00ff; 16]);
+    ppu.write_register(0x02, 0x00);
+    ppu.write_register(0x03, 0x00);
+    for i in 0..128 {
+        let [x, y] = if i < count { [i * step, 10] } else { [0, 0xf0] };
+        for val in [x, y, 0, 0] {
+            ppu.write_register(0x04, val);
+        }
+    }
+    for _ in 0..32 {
+        ppu.write_register(0x04, if large { 0xaa } else { 0 });
+    }
+    ppu.mut_pos().y = 12;
+    ppu.draw_scanline();
+    let covered = ppu
+        .obj_cache
+        .iter()
+        .map(|entry| entry.palette_addr != 0)
+        .collect();
+    (ppu.read_register(0x3e).unwrap() & 0xc0, covered)
+}
+
+#[test]
+fn sprite_limits() {
+    let (flags, covered) = sprite_line(32, 7, false, true);
+    assert_eq!(flags, 0);
+    assert!(covered[..224].iter().all(|c| *c));
+    // the 33rd sprite is dropped
+    let (flags, covered) = sprite_line(33, 7, false, true);
+    assert_eq!(flags, 0x40);
+    assert!(covered[223] && !covered[225]);
+    let (flags, covered) = sprite_line(33, 7, false, false);
+    assert_eq!(flags, 0x40);
+    assert!(covered[..231].iter().all(|c| *c));
+
+    // 17 sprites with two tiles each stay below the tile limit
+    let (flags, covered) = sprite_line(17, 14, true, true);
+    assert_eq!(flags, 0);
+    assert!(covered[0]);
+    // the tiles of the first sprite are fetched last and dropped
+    let (flags, covered) = sprite_line(18, 14, true, true);
+    assert_eq!(flags, 0x80);
+    assert!(!covered[0] && covered[14]);
+    let (flags, covered) = sprite_line(18, 14, true, false);
+    assert_eq!(flags, 0x80);
+    assert!(covered[0]);
+}
+
+fn write_cgram(ppu: &mut Ppu<VecFrameBuffer>, idx: u8, color: u16) {
+    ppu.write_register(0x21, idx);
+    ppu.write_register(0x22, color as u8);
+    ppu.write_register(0x22, (color >> 8) as u8);
+}
+
+/// The color of the main screen at the top left pixel
+fn top_left_color(ppu: &mut Ppu<VecFrameBuffer>) -> Color {
+    for bg in &mut ppu.bgs {
+        bg.cached_tile = None;
+    }
+    ppu.fetch_screen(0, 0, 0, true, false).0
+}
+
+#[test]
+fn direct_color() {
+    let mut ppu = Ppu::new(VecFrameBuffer(vec![], FrameSize::DEFAULT), false);
+    ppu.write_register(0x05, 0x03);
+    ppu.write_register(0x07, 0x00);
+    ppu.write_register(0x0b, 0x01);
+    ppu.write_register(0x2c, 0x01);
+    // tile 1 with the palette 5
+    write_vram(&mut ppu, 0x0000, &[0x1401]);
+    // 8bpp tile 1 with the color 0xff at the top left pixel
+    for plane in 0..4 {
+        write_vram(&mut ppu, 0x1020 + plane * 8, &[0x8080]);
+    }
+    write_cgram(&mut ppu, 0xff, 0x03e0);
+    assert_eq!(top_left_color(&mut ppu), Color::new(0, 31, 0));
+    ppu.write_register(0x30, 0x01);
+    assert_eq!(top_left_color(&mut ppu), Color::new(30, 28, 28));
+}
+
+#[test]
+fn bg3_priority() {
+    let mut ppu = Ppu::new(VecFrameBuffer(vec![], FrameSize::DEFAULT), false);
+    ppu.write_register(0x07, 0x00);
+    ppu.write_register(0x09, 0x04);
+    ppu.write_register(0x0b, 0x01);
+    ppu.write_register(0x0c, 0x02);
+    ppu.write_register(0x2c, 0x05);
+    // BG1: tile 1 with low priority and the color 1 at the top left pixel
+    write_vram(&mut ppu, 0x0000, &[0x0001]);
+    write_vram(&mut ppu, 0x1010, &[0x0080]);
+    // BG3: tile 1 with high priority and the color 2 at the top left pixel
+    write_vram(&mut ppu, 0x0400, &[0x2001]);
+    write_vram(&mut ppu, 0x2008, &[0x8000]);
+    write_cgram(&mut ppu, 1, 0x001f);
+    write_cgram(&mut ppu, 2, 0x03e0);
+    write_cgram(&mut ppu, 0x42, 0x7c00);
+
+    ppu.write_register(0x05, 0x01);
+    assert_eq!(top_left_color(&mut ppu), Color::new(31, 0, 0));
+    ppu.write_register(0x05, 0x09);
+    assert_eq!(top_left_color(&mut ppu), Color::new(0, 31, 0));
+    // in mode 0 the priority bit has no effect and BG3 uses its own palettes
+    ppu.write_register(0x05, 0x08);
+    ppu.write_register(0x2c, 0x04);
+    assert_eq!(top_left_color(&mut ppu), Color::new(0, 0, 31));
+}
+
+fn write_twice(ppu: &mut Ppu<VecFrameBuffer>, addr: u8, val: u16) {
+    ppu.write_register(addr, val as u8);
+    ppu.write_register(addr, (val >> 8) as u8);
+}
+
+#[test]
+fn mode7_multiplication() {
+    let mut ppu = Ppu::new(VecFrameBuffer(vec![], FrameSize::DEFAULT), false);
+    let mut product = |a: u16, b: u8| {
+        write_twice(&mut ppu, 0x1b, a);
+        // only the last written byte of M7B is used
+        ppu.write_register(0x1c, 0x55);
+        ppu.write_register(0x1c, b);
+        [0x34, 0x35, 0x36].map(|addr| ppu.read_register(addr).unwrap())
+    };
+    assert_eq!(product(0x0102, 0x03), [0x06, 0x03, 0x00]);
+    assert_eq!(product(0xfffe, 0x03), [0xfa, 0xff, 0xff]);
+    assert_eq!(product(0x7fff, 0x80), [0x80, 0x00, 0xc0]);
+    assert_eq!(product(0x8000, 0x80), [0x00, 0x00, 0x40]);
+}
+
+#[test]
+fn mode7_repetition() {
+    let mut ppu = Ppu::new(VecFrameBuffer(vec![], FrameSize::DEFAULT), false);
+    ppu.write_register(0x05, 0x07);
+    ppu.write_register(0x2c, 0x01);
+    // the tiles 0 and 127 of the first row use the character 1,
+    // the character 0 has the color 6 and the character 1 the color 5
+    write_vram(&mut ppu, 0, &[0x0601]);
+    write_vram(&mut ppu, 1, &[0x0600; 63]);
+    write_vram(&mut ppu, 64, &[0x0500; 63]);
+    write_vram(&mut ppu, 127, &[0x0501]);
+    for (idx, color) in [(0, 0x7c00), (5, 0x001f), (6, 0x03e0)] {
+        write_cgram(&mut ppu, idx, color);
+    }
+    let [backdrop, char1, char0] = [
+        Color::new(0, 0, 31),
+        Color::new(31, 0, 0),
+        Color::new(0, 31, 0),
+    ];
+    write_twice(&mut ppu, 0x1b, 0x0100);
+    write_twice(&mut ppu, 0x1e, 0x0100);
+    assert_eq!(top_left_color(&mut ppu), char1);
+
+    // scrolled 8 pixels to the left of the playing field
+    write_twice(&mut ppu, 0x0d, 0x1ff8);
+    assert_eq!(ppu.mode7_settings.offset[0], 0xfff8);
+    assert_eq!(top_left_color(&mut ppu), char1);
+    ppu.write_register(0x1a, 0x80);
+    assert_eq!(top_left_color(&mut ppu), backdrop);
+    ppu.write_register(0x1a, 0xc0);
+    assert_eq!(top_left_color(&mut ppu), char0);
+
+    // offsets beyond the playing field wrap around before the transformation
+    write_twice(&mut ppu, 0x0d, 0x0400);
+    assert_eq!(ppu.mode7_settings.tmp1[0], 0);
+    assert_eq!(top_left_color(&mut ppu), char1);
+    // the center is a 13-bit signed value
+    ppu.write_register(0x1a, 0x80);
+    write_twice(&mut ppu, 0x0d, 0);
+    write_twice(&mut ppu, 0x1f, 0x1000);
+    assert_eq!(ppu.mode7_settings.center[0], 0xf000);
+    assert_eq!(top_left_color(&mut ppu), backdrop);
+}
+
+/// Write INIDISP at the pixel `x` of the scanline `y` and draw the scanline
+fn inidisp_line(ppu: &mut Ppu<VecFrameBuffer>, y: u16, x: u16, val: u8) -> Vec<[u8; 4]> {
+    *ppu.mut_pos() = RayPos {
+        x: (x + FIRST_PIXEL_DOT) << 2,
+        y,
+    };
+    ppu.write_register(0x00, val);
+    ppu.draw_scanline();
+    ppu.end_frame();
+    wait_for_frame(ppu);
+    let n = usize::from(y) * 256;
+    ppu.frame_buffer.0[n..n + 256].to_vec()
+}
+
+#[test]
+fn inidisp_mid_scanline() {
+    let full = Color::new(31, 31, 31).to_rgba8_with_brightness(15);
+    let half = Color::new(31, 31, 31).to_rgba8_with_brightness(7);
+    for threads in [0, 2] {
+        let frame_buffer = VecFrameBuffer(vec![[0; 4]; FRAME_BUFFER_SIZE], FrameSize::DEFAULT);
+        let mut ppu = Ppu::new(frame_buffer, false);
+        ppu.set_render_threads(threads);
+        write_cgram(&mut ppu, 0, 0x7fff);
+        ppu.write_register(0x00, 0x0f);
+        // forced blank from the pixel 100 on
+        let line = inidisp_line(&mut ppu, 10, 100, 0x80);
+        assert!(line[..100].iter().all(|p| *p == full));
+        assert!(line[100..].iter().all(|p| *p == [0; 4]));
+        // the fade continues on the next scanline at the pixel 50
+        let line = inidisp_line(&mut ppu, 11, 50, 0x07);
+        assert!(line[..50].iter().all(|p| *p == [0; 4]));
+        assert!(line[50..].iter().all(|p| *p == half));
+        // a write in the horizontal blanking period changes the next scanline
+        let line = inidisp_line(&mut ppu, 12, 270, 0x0f);
+        assert!(line.iter().all(|p| *p == half));
+        let line = inidisp_line(&mut ppu, 13, 0, 0x0f);
+        assert!(line.iter().all(|p| *p == full));
+    }
+}
@@ -0,0 +1,196 @@
+//! Rendering of the video memory for graphics debuggers
+//!
+//! The images show the tiles, tilemaps, palette and sprites independently
+//! of the picture on the screen. Pixels with the color index 0 are
+//! transparent, all other colors are shown at full brightness.
+
+use super::{Color, Ppu, VRAM_SIZE};
+use crate::backend::FrameBuffer;
+
+/// An image with RGBA pixels stored row by row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0; 4]; (width * height) as usize],
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> [u8; 4] {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn set(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        self.pixels[(y * self.width + x) as usize] = pixel
+    }
+}
+
+/// Bits per pixel of the background layer `bg` (`0..4`) in the background mode `mode`.
+/// Returns `None` if the mode has no such layer.
+pub const fn bg_bit_depth(mode: u8, bg: usize) -> Option<u8> {
+    match (mode, bg) {
+        (0, 0..=3) | (1, 2) | (4, 1) | (5, 1) => Some(2),
+        (1 | 2, 0 | 1) | (3, 1) | (5 | 6, 0) => Some(4),
+        (3 | 4, 0) | (7, 0 | 1) => Some(8),
+        _ => None,
+    }
+}
+
+fn to_rgba(color: u16) -> [u8; 4] {
+    Color::from(color).to_rgba8_with_brightness(15)
+}
+
+impl<FB: FrameBuffer> Ppu<FB> {
+    /// The color index of a pixel of a tile with `bits` bits per pixel
+    fn tile_pixel(&self, tile_base: u16, tile_nr: u16, bits: u8, x: u8, y: u8) -> u8 {
+        let addr = tile_base
+            .wrapping_add(tile_nr << (2 + bits.trailing_zeros()))
+            .wrapping_add(u16::from(y & 7));
+        (0..bits >> 1).fold(0, |idx, i| {
+            let plane = self.vram.read(addr.wrapping_add(u16::from(i) << 3));
+            let [low, high] = plane.to_le_bytes().map(|p| (p >> (7 - (x & 7))) & 1);
+            idx | ((low | (high << 1)) << (i << 1))
+        })
+    }
+
+    /// The color of a color index in a palette of `1 << bits` colors
+    fn palette_color(&self, palette: u8, bits: u8, idx: u8) -> [u8; 4] {
+        if idx == 0 {
+            return [0; 4];
+        }
+        let addr = if bits == 8 {
+            idx
+        } else {
+            (palette << bits) | idx
+        };
+        to_rgba(self.cgram.read16(addr))
+    }
+
+    /// Render the tiles of the VRAM beginning at the word address `tile_base_addr`
+    /// with `columns` tiles per row. `palette` selects one of the palettes with
+    /// `1 << bits` colors, which is ignored for 8 bits per pixel.
+    pub fn render_tiles(&self, tile_base_addr: u16, bits: u8, palette: u8, columns: u32) -> Image {
+        let count = (VRAM_SIZE >> (2 + bits.trailing_zeros())) as u32;
+        let rows = count.div_ceil(columns);
+        let mut image = Image::new(columns * 8, rows * 8);
+        for nr in 0..count {
+            let (tx, ty) = ((nr % columns) * 8, (nr / columns) * 8);
+            for y in 0..8 {
+                for x in 0..8 {
+                    let idx = self.tile_pixel(tile_base_addr, nr as u16, bits, x, y);
+                    let color = self.palette_color(palette, bits, idx);
+                    image.set(tx + u32::from(x), ty + u32::from(y), color)
+                }
+            }
+        }
+        image
+    }
+
+    /// Render the whole tilemap of the background layer `bg` (`0..4`)
+    /// in the current background mode, without scrolling.
+    /// Returns `None` if the current mode has no such layer.
+    pub fn render_tilemap(&self, bg: usize) -> Option<Image> {
+        let bits = bg_bit_depth(self.bg_mode.num, bg)?;
+        if self.bg_mode.num == 7 {
+            return Some(self.render_mode7_tilemap());
+        }
+        let layer = &self.bgs[bg];
+        let [tw, th] = layer.tile_size.map(u32::from);
+        let [mw, mh] = layer.size.map(u32::from);
+        let mut image = Image::new(mw * tw, mh * th);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let (tile_x, tile_y) = ((x / tw) as u16, (y / th) as u16);
+                let map_nr = match layer.size {
+                    [64, 32] => (tile_x << 5) & 0x400,
+                    [32, 64] => (tile_y << 5) & 0x400,
+                    [64, 64] => ((tile_x << 5) | ((tile_y & 0x20) << 6)) & 0xc00,
+                    _ => 0,
+                };
+                let map_val = self.vram.read(
+                    layer
+                        .map_base_addr
+                        .wrapping_add((tile_x & 0x1f) | ((tile_y & 0x1f) << 5))
+                        .wrapping_add(map_nr),
+                );
+                let (mut px, mut py) = (x % tw, y % th);
+                if map_val & 0x4000 > 0 {
+                    px = tw - 1 - px
+                }
+                if map_val & 0x8000 > 0 {
+                    py = th - 1 - py
+                }
+                let tile_nr = (map_val & 0x3ff)
+                    .wrapping_add((px >> 3) as u16)
+                    .wrapping_add(((py >> 3) as u16) << 4);
+                let idx = self.tile_pixel(layer.tile_base_addr, tile_nr, bits, px as u8, py as u8);
+                let palette = ((map_val >> 10) & 7) as u8;
+                let color = if self.bg_mode.num == 0 && idx > 0 {
+                    to_rgba(self.cgram.read16((palette << 2) | idx | (bg << 5) as u8))
+                } else {
+                    self.palette_color(palette, bits, idx)
+                };
+                image.set(x, y, color)
+            }
+        }
+        Some(image)
+    }
+
+    /// Render the 1024x1024 pixels of the mode 7 tilemap
+    fn render_mode7_tilemap(&self) -> Image {
+        let mut image = Image::new(1024, 1024);
+        for y in 0..1024 {
+            for x in 0..1024 {
+                let tile_nr = (x >> 3) + ((y >> 3) << 7);
+                let char_nr = self.vram.read(tile_nr as u16).to_le_bytes()[0];
+                let pixel_addr = (u16::from(char_nr) << 6) | (x & 7) as u16 | ((y & 7) << 3) as u16;
+                let idx = self.vram.read(pixel_addr).to_le_bytes()[1];
+                image.set(x, y, self.palette_color(0, 8, idx))
+            }
+        }
+        image
+    }
+
+    /// Render the 256 colors of the CGRAM as a grid of 16x16 squares
+    /// with a width of `swatch_size` pixels
+    pub fn render_palette(&self, swatch_size: u32) -> Image {
+        let mut image = Image::new(16 * swatch_size, 16 * swatch_size);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let idx = (y / swatch_size) * 16 + x / swatch_size;
+                image.set(x, y, to_rgba(self.cgram.read16(idx as u8)))
+            }
+        }
+        image
+    }
+
+    /// Render the 128 sprites of the OAM in a grid of 16x8 cells
+    /// of 64x64 pixels, regardless of their position on the screen
+    pub fn render_sprites(&self) -> Image {
+        let mut image = Image::new(16 * 64, 8 * 64);
+        for (i, obj) in self.oam.objs.iter().enumerate() {
+            let (cx, cy) = ((i as u32 % 16) * 64, (i as u32 / 16) * 64);
+            let [w, h] = self.obj_size[usize::from(obj.is_large)].map(u32::from);
+            let base = self.obj_tile_addr[usize::from(obj.attrs & 1)];
+            for y in 0..h {
+                for x in 0..w {
+                    let px = if obj.is_xflip() { w - 1 - x } else { x };
+                    let py = if obj.is_yflip() { h - 1 - y } else { y };
+                    let addr = obj.get_tile_addr(base, (px >> 3) as u8, (py >> 3) as u8);
+                    let idx = self.tile_pixel(addr, 0, 4, px as u8, py as u8);
+                    let color = self.palette_color(8 + obj.get_palette_nr(), 4, idx);
+                    image.set(cx + x, cy + y, color)
+                }
+            }
+        }
+        image
+    }
+}
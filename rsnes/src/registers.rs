@@ -3,66 +3,115 @@ use save_state_macro::*;
 
 const CHIP_5A22_VERSION: u8 = 2;
 
+/// The multiplication and division unit of the 5A22.
+///
+/// The unit computes one bit per CPU cycle, so a multiplication takes
+/// 8 cycles and a division takes 16 cycles. Reading the result registers
+/// before the computation finished returns the intermediate values.
+/// Writes to WRMPYB or WRDIVB during a computation only reset the
+/// product or remainder register.
+///
+/// See <https://github.com/bsnes-emu/bsnes/blob/master/bsnes/sfc/cpu/timing.cpp>
 #[derive(Debug, Clone, InSaveState)]
 pub struct MathRegisters {
     multiplicands: [u8; 2],
     dividend: u16,
     divisor: u8,
-    math_timer: u16,
-    result_after: [u8; 4],
-    result_before: [u8; 4],
+    /// RDDIV, the quotient or the shifted multiplicand
+    quotient: u16,
+    /// RDMPY, the product or the remainder
+    product: u16,
+    /// The operand which gets shifted in each step
+    shift: u32,
+    multiply_steps: u8,
+    divide_steps: u8,
+    /// Master cycles which have not yet been used for a step
+    cycles: u16,
 }
 
 impl MathRegisters {
+    /// Master cycles of one step of the computation
+    const STEP_CYCLES: u16 = 6;
+
     pub const fn new() -> Self {
         Self {
             multiplicands: [0xff, 0xff],
             dividend: 0xffff,
             divisor: 0xff,
-            math_timer: 0,
-            result_after: [0; 4],
-            result_before: [0; 4],
+            quotient: 0,
+            product: 0,
+            shift: 0,
+            multiply_steps: 0,
+            divide_steps: 0,
+            cycles: 0,
         }
     }
 
-    pub fn tick(&mut self, cycles: u16) {
-        self.math_timer = self.math_timer.saturating_sub(cycles)
+    pub const fn is_busy(&self) -> bool {
+        self.multiply_steps > 0 || self.divide_steps > 0
     }
 
-    pub fn fire_multiply(&mut self) {
-        if self.math_timer == 0 {
-            self.result_before = self.result_after
+    fn step(&mut self) {
+        if self.multiply_steps > 0 {
+            self.multiply_steps -= 1;
+            if self.quotient & 1 > 0 {
+                self.product = self.product.wrapping_add(self.shift as u16)
+            }
+            self.quotient >>= 1;
+            self.shift <<= 1;
+        }
+        if self.divide_steps > 0 {
+            self.divide_steps -= 1;
+            self.quotient <<= 1;
+            self.shift >>= 1;
+            if u32::from(self.product) >= self.shift {
+                self.product -= self.shift as u16;
+                self.quotient |= 1;
+            }
         }
-        let [lower, higher] =
-            (u16::from(self.multiplicands[0]) * u16::from(self.multiplicands[1])).to_le_bytes();
-        self.math_timer = 48;
-        self.result_after = [self.multiplicands[1], 0, lower, higher]
     }
 
-    pub fn fire_divide(&mut self) {
-        if self.math_timer == 0 {
-            self.result_before = self.result_after
+    pub fn tick(&mut self, cycles: u16) {
+        if !self.is_busy() {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= Self::STEP_CYCLES && self.is_busy() {
+            self.cycles -= Self::STEP_CYCLES;
+            self.step()
         }
-        let (div, rem) = if self.divisor == 0 {
-            (0xffff, self.dividend)
-        } else {
-            (
-                self.dividend / u16::from(self.divisor),
-                self.dividend % u16::from(self.divisor),
-            )
-        };
-        let [div_low, div_high] = div.to_le_bytes();
-        let [rem_low, rem_high] = rem.to_le_bytes();
-        self.math_timer = 96;
-        self.result_after = [div_low, div_high, rem_low, rem_high]
     }
 
-    pub const fn get_result(&self) -> &[u8; 4] {
-        if self.math_timer == 0 {
-            &self.result_after
-        } else {
-            &self.result_before
+    /// Write WRMPYB and start a multiplication
+    pub fn fire_multiply(&mut self, val: u8) {
+        self.product = 0;
+        if self.is_busy() {
+            return;
         }
+        self.multiplicands[1] = val;
+        self.quotient = u16::from_le_bytes(self.multiplicands);
+        self.shift = val.into();
+        self.multiply_steps = 8;
+        self.cycles = 0;
+    }
+
+    /// Write WRDIVB and start a division
+    pub fn fire_divide(&mut self, val: u8) {
+        self.product = self.dividend;
+        if self.is_busy() {
+            return;
+        }
+        self.divisor = val;
+        self.shift = u32::from(val) << 16;
+        self.divide_steps = 16;
+        self.cycles = 0;
+    }
+
+    /// The values of the registers RDDIVL, RDDIVH, RDMPYL and RDMPYH
+    pub const fn get_result(&self) -> [u8; 4] {
+        let [div_low, div_high] = self.quotient.to_le_bytes();
+        let [mpy_low, mpy_high] = self.product.to_le_bytes();
+        [div_low, div_high, mpy_low, mpy_high]
     }
 }
 
@@ -76,22 +125,42 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             }
             0x4017 => {
                 // JOYSER1 - NES-style Joypad access
-                Some(self.controllers.port2.read_port_data() | 0b11100 | (self.open_bus & 0xfc))
+                Some(self.controllers.port2.read_port_data() | 0b11100 | (self.open_bus & 0xe0))
+            }
+            0x4210 => {
+                // RDNMI - reading acknowledges the NMI flag
+                let val = self.peek_internal_register(id);
+                self.nmi_vblank_bit.set(false);
+                val
+            }
+            0x4211 => {
+                // TIMEUP - The IRQ flag, reading acknowledges the IRQ
+                let val = self.peek_internal_register(id);
+                self.cpu.irq_bit = 0;
+                val
             }
+            _ => self.peek_internal_register(id),
+        }
+    }
+
+    /// Read an internal register (4000 - 43ff) without side effects.
+    ///
+    /// The flags are returned without being acknowledged.
+    /// `None` is returned for the registers, whose value can't be read
+    /// without side effects.
+    pub fn peek_internal_register(&self, id: u16) -> Option<u8> {
+        assert!((0x4000..=0x43ff).contains(&id));
+        match id {
             0x4210 => {
-                // NMI Flag & CPU version
+                // RDNMI - NMI Flag & CPU version
                 // TODO: check if version 2 is appropriate
                 Some(
-                    ((self.nmi_vblank_bit.replace(false) as u8) << 7)
+                    ((self.nmi_vblank_bit.get() as u8) << 7)
                         | CHIP_5A22_VERSION
                         | (self.open_bus & 0x70),
                 )
             }
-            0x4211 => {
-                // TIMEUP - The IRQ flag
-                self.shall_irq = false;
-                Some(core::mem::take(&mut self.cpu.irq_bit) | (self.open_bus & 0x7f))
-            }
+            0x4211 => Some(self.cpu.irq_bit | (self.open_bus & 0x7f)), // TIMEUP
             0x4212 => {
                 // HVBJOY - PPU status
                 // TODO: better timing and auto joypad timing
@@ -99,7 +168,7 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
                 Some(
                     ((self.ppu.is_in_vblank() as u8) << 7)
                         | ((in_hblank as u8) << 6)
-                        | (self.controllers.auto_joypad_timer > 0) as u8
+                        | self.controllers.is_auto_joypad_busy() as u8
                         | (self.open_bus & 0x3e),
                 )
             }
@@ -115,11 +184,17 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
                 // JOYnL/JOYnH
                 Some(self.controllers.access(id))
             }
-            0x4300..=0x43ff => {
+            0x4300..=0x437f => {
                 // DMA Registers
                 self.dma.read(id)
             }
-            0x4000..=0x4015 | 0x4018..=0x41ff | 0x4200..=0x420f | 0x4220..=0x42ff => None,
+            // JOYSER0/1 shift the serial data of the controllers
+            0x4016 | 0x4017 => None,
+            0x4000..=0x4015
+            | 0x4018..=0x41ff
+            | 0x4200..=0x420f
+            | 0x4220..=0x42ff
+            | 0x4380..=0x43ff => None,
             _ => unreachable!(),
         }
     }
@@ -137,7 +212,15 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             }
             0x4200 => {
                 // NMITIMEN - Interrupt Enable Flags
-                // TODO: implement expected behavior
+                // > Enabling NMI while the NMI flag is set triggers an NMI
+                // > Disabling both H and V IRQs acknowledges a pending IRQ
+                // source: FullSNES
+                if val & 0x80 > 0 && self.cpu.nmitimen & 0x80 == 0 && self.nmi_vblank_bit.get() {
+                    self.shall_nmi = true
+                }
+                if val & 0x30 == 0 {
+                    self.cpu.irq_bit = 0
+                }
                 self.cpu.nmitimen = val;
             }
             0x4201 => {
@@ -152,8 +235,7 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             }
             0x4203 => {
                 // WRMPYB
-                self.math_registers.multiplicands[1] = val;
-                self.math_registers.fire_multiply()
+                self.math_registers.fire_multiply(val)
             }
             0x4204 => {
                 // WRDIVL
@@ -167,8 +249,7 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             }
             0x4206 => {
                 // WRDIVB
-                self.math_registers.divisor = val;
-                self.math_registers.fire_divide()
+                self.math_registers.fire_divide(val)
             }
             0x4207 => {
                 // HTIMEL
@@ -188,24 +269,29 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             }
             0x420b => {
                 // MDMAEN - DMA Enable
-                // TODO: implement expected behavior
-                self.dma.enable_dma(val)
+                #[cfg(feature = "trace")]
+                if val > 0 {
+                    self.trace_event(crate::trace::EventKind::DmaStart { channels: val })
+                }
+                self.dma.enable_dma(val, self.master_cycles)
             }
             0x420c => {
                 // HDMAEN - HDMA Enable
-                // TODO: implement expected behavior
                 self.dma.enable_hdma(val)
             }
             0x420d => {
                 // MEMSEL - ROM access speed
                 self.cpu.access_speed = val & 1 > 0
             }
-            0x4300..=0x43ff => {
+            0x4300..=0x437f => {
                 // DMA Registers
                 self.dma.write(id, val)
             }
-            0x4000..=0x4015 | 0x4017..=0x41ff | 0x420e..=0x42ff => (),
+            0x4000..=0x4015 | 0x4017..=0x41ff | 0x420e..=0x42ff | 0x4380..=0x43ff => (),
             _ => unreachable!(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,53 @@
+use super::*;
+
+fn run_steps(math: &mut MathRegisters, steps: u16) {
+    math.tick(steps * MathRegisters::STEP_CYCLES)
+}
+
+#[test]
+fn multiply_latency() {
+    let mut math = MathRegisters::new();
+    math.multiplicands[0] = 0xc5;
+    math.fire_multiply(0x3b);
+    assert!(math.is_busy());
+    run_steps(&mut math, 4);
+    // the lower four bits of the multiplicand A are processed
+    assert_eq!(math.get_result()[2..], (0x5 * 0x3bu16).to_le_bytes());
+    run_steps(&mut math, 4);
+    assert!(!math.is_busy());
+    assert_eq!(math.get_result(), [0x3b, 0, 0x67, 0x2d]);
+}
+
+#[test]
+fn divide_latency() {
+    let mut math = MathRegisters::new();
+    math.dividend = 50000;
+    math.fire_divide(7);
+    math.tick(MathRegisters::STEP_CYCLES * 16 - 1);
+    assert!(math.is_busy());
+    math.tick(1);
+    assert!(!math.is_busy());
+    let [q0, q1] = (50000u16 / 7).to_le_bytes();
+    let [r0, r1] = (50000u16 % 7).to_le_bytes();
+    assert_eq!(math.get_result(), [q0, q1, r0, r1]);
+    // a division by zero results in a quotient of 0xffff
+    math.fire_divide(0);
+    run_steps(&mut math, 16);
+    assert_eq!(math.get_result(), [0xff, 0xff, 0x50, 0xc3]);
+}
+
+#[test]
+fn write_during_computation() {
+    let mut math = MathRegisters::new();
+    math.multiplicands[0] = 3;
+    math.fire_multiply(5);
+    run_steps(&mut math, 2);
+    // the product gets cleared, but the multiplication continues
+    math.fire_multiply(7);
+    run_steps(&mut math, 6);
+    assert_eq!(math.multiplicands[1], 5);
+    assert_eq!(math.get_result()[2..], [0, 0]);
+    math.fire_multiply(7);
+    run_steps(&mut math, 8);
+    assert_eq!(math.get_result()[2..], [21, 0]);
+}
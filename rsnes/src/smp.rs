@@ -1,10 +1,27 @@
+//! The S-SMP, which runs the SPC700 and the DSP in sync with the main CPU
+//!
+//! In threaded mode the SPC700 and the DSP run on a worker thread. Every
+//! access to the I/O ports is sent to the worker together with the number
+//! of cycles to run before it, so the worker sees the port writes at the same
+//! time as in unthreaded mode. Reading a port and saving a state wait for the
+//! worker to catch up, which makes both modes produce the same results and
+//! save states, that can be loaded in either mode.
+//!
+//! The SPC700 runs cycle-stepped, so its port accesses happen in the last
+//! cycle of an instruction. The main CPU executes an instruction as a whole,
+//! so before it accesses a port, the SMP catches up to the master cycle of
+//! the access within the instruction (see [`Smp::catch_up`]).
+
 use crate::{
     backend::AudioBackend as Backend,
-    spc700::Spc700,
+    enhancement::msu1::AudioOutput,
+    spc700::{
+        debug::{DspDebugState, SpcDebugState},
+        Interpolation, Spc700, StereoSample,
+    },
     timing::{Cycles, APU_CPU_TIMING_PROPORTION_NTSC, APU_CPU_TIMING_PROPORTION_PAL},
 };
 use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
-use save_state_macro::InSaveState;
 use std::sync::mpsc::{channel, Receiver, RecvError, Sender};
 
 #[derive(Debug, Clone)]
@@ -21,6 +38,15 @@ enum ThreadCommand {
     },
     SaveState(Box<Spc700>),
     GetSaveState,
+    Reset,
+    SetChannelMask(u8),
+    SetInterpolation(Interpolation),
+    ApplyDspPatch(Vec<(u8, u8)>),
+    SetSpeed(f32),
+    SetExpansionAudio(Option<AudioOutput>),
+    SetBreakpoints(Vec<u16>),
+    TakeBreakpointHits,
+    StepInstruction,
     KillMe,
 }
 
@@ -28,10 +54,53 @@ enum ThreadCommand {
 enum MainCommand {
     Data(u8),
     SaveState(Box<Spc700>),
+    BreakpointHits(Vec<u16>),
+    Stepped(Cycles),
 }
 
 type ReturnType = Result<(), RecvError>;
 
+/// Drops or repeats samples, so that the audio output keeps
+/// running in real time, when the emulation speed is not 1x
+#[derive(Debug, Clone)]
+struct SpeedAdjust {
+    /// Output samples per emulated sample in 16.16 fixed point
+    step: u32,
+    acc: u32,
+}
+
+impl SpeedAdjust {
+    const ONE: u32 = 1 << 16;
+
+    const fn new() -> Self {
+        Self {
+            step: Self::ONE,
+            acc: 0,
+        }
+    }
+
+    fn set_speed(&mut self, speed: f32) {
+        self.step = (Self::ONE as f32 / speed) as u32;
+    }
+
+    fn push_sample<B: Backend>(
+        &mut self,
+        backend: &mut B,
+        expansion_audio: &mut Option<AudioOutput>,
+        sample: StereoSample,
+    ) {
+        let sample = match expansion_audio {
+            Some(output) => output.mix(sample),
+            None => sample,
+        };
+        self.acc = self.acc.saturating_add(self.step);
+        while self.acc >= Self::ONE {
+            backend.push_sample(sample);
+            self.acc -= Self::ONE;
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Thread {
     join_handle: Option<std::thread::JoinHandle<ReturnType>>,
@@ -39,15 +108,18 @@ struct Thread {
     recv: Receiver<MainCommand>,
 }
 
-#[derive(Debug, InSaveState)]
+#[derive(Debug)]
 pub struct Smp<B: Backend> {
     pub spc: Option<Spc700>,
-    #[except((|_v, _s| ()), (|_v, _s| ()))]
     pub backend: Option<B>,
-    #[except(Self::serialize_save_state, Self::deserialize_save_state)]
     thread: Option<Thread>,
     timing_proportion: (Cycles, Cycles),
     master_cycles: Cycles,
+    /// Scaled master cycles the SMP already ran ahead of the ticks by
+    /// [`Smp::catch_up`] or [`Smp::step_instruction`]
+    caught_up: Cycles,
+    speed_adjust: SpeedAdjust,
+    expansion_audio: Option<AudioOutput>,
 }
 
 fn threaded_spc<B: Backend>(
@@ -56,15 +128,19 @@ fn threaded_spc<B: Backend>(
     send: Sender<MainCommand>,
     recv: Receiver<ThreadCommand>,
 ) -> ReturnType {
+    let mut speed_adjust = SpeedAdjust::new();
+    let mut expansion_audio = None;
     loop {
         match recv.recv()? {
             ThreadCommand::RunCycles { cycles, action } => {
                 // synchronize
-                for _ in 0..cycles {
-                    if let Some(sample) = spc.run_cycle() {
-                        backend.push_sample(sample)
-                    }
-                }
+                Smp::refresh_no_thread(
+                    &mut spc,
+                    &mut backend,
+                    &mut speed_adjust,
+                    &mut expansion_audio,
+                    cycles,
+                );
                 // run action
                 match action {
                     Some(Action::WriteInputPort { addr, data }) => {
@@ -76,7 +152,26 @@ fn threaded_spc<B: Backend>(
                     None => (),
                 }
             }
-            ThreadCommand::SaveState(new_spc) => spc = *new_spc,
+            ThreadCommand::SaveState(new_spc) => replace_spc(&mut spc, *new_spc),
+            ThreadCommand::Reset => spc.reset(),
+            ThreadCommand::SetChannelMask(mask) => spc.dsp_mut().set_channel_mask(mask),
+            ThreadCommand::SetInterpolation(mode) => spc.dsp_mut().set_interpolation(mode),
+            ThreadCommand::ApplyDspPatch(writes) => spc.dsp_mut().apply_patch(&writes),
+            ThreadCommand::SetSpeed(speed) => speed_adjust.set_speed(speed),
+            ThreadCommand::SetExpansionAudio(output) => expansion_audio = output,
+            ThreadCommand::SetBreakpoints(breakpoints) => spc.set_breakpoints(breakpoints),
+            ThreadCommand::TakeBreakpointHits => {
+                let _ = send.send(MainCommand::BreakpointHits(spc.take_breakpoint_hits()));
+            }
+            ThreadCommand::StepInstruction => {
+                let cycles = Smp::step_no_thread(
+                    &mut spc,
+                    &mut backend,
+                    &mut speed_adjust,
+                    &mut expansion_audio,
+                );
+                let _ = send.send(MainCommand::Stepped(cycles));
+            }
             ThreadCommand::GetSaveState => {
                 let _ = send.send(MainCommand::SaveState(Box::new(spc.clone())));
             }
@@ -86,8 +181,12 @@ fn threaded_spc<B: Backend>(
 }
 
 impl<B: Backend> Smp<B> {
+    /// Create the SMP, which runs in its own thread if `is_threaded` is set.
+    ///
+    /// WebAssembly has no threads, so the SMP is never threaded there.
     pub fn new(backend: B, is_pal: bool, is_threaded: bool) -> Self {
-        let spc = Spc700::default();
+        let is_threaded = is_threaded && cfg!(not(target_arch = "wasm32"));
+        let spc = new_spc();
         let timing_proportion = if is_pal {
             APU_CPU_TIMING_PROPORTION_PAL
         } else {
@@ -107,6 +206,9 @@ impl<B: Backend> Smp<B> {
                 thread,
                 timing_proportion,
                 master_cycles: 0,
+                caught_up: 0,
+                speed_adjust: SpeedAdjust::new(),
+                expansion_audio: None,
             }
         } else {
             Self {
@@ -115,13 +217,47 @@ impl<B: Backend> Smp<B> {
                 thread: None,
                 timing_proportion,
                 master_cycles: 0,
+                caught_up: 0,
+                speed_adjust: SpeedAdjust::new(),
+                expansion_audio: None,
             }
         }
     }
 
     /// Tick in main CPU master cycles
     pub fn tick(&mut self, n: u16) {
-        self.master_cycles += Cycles::from(n) * self.timing_proportion.1;
+        let cycles = Cycles::from(n) * self.timing_proportion.1;
+        let caught_up = cycles.min(self.caught_up);
+        self.caught_up -= caught_up;
+        self.master_cycles += cycles - caught_up;
+    }
+
+    /// Check if more than `max_ahead` master cycles were ticked since the SMP ran.
+    /// It is never behind by less than one SPC700 cycle.
+    pub fn is_behind(&self, max_ahead: u32) -> bool {
+        self.master_cycles
+            > max_ahead
+                .saturating_mul(self.timing_proportion.1)
+                .max(self.timing_proportion.0)
+    }
+
+    /// The main CPU master cycles to tick until [`Self::is_behind`] is true
+    pub fn cycles_until_behind(&self, max_ahead: u32) -> Cycles {
+        let threshold = max_ahead
+            .saturating_mul(self.timing_proportion.1)
+            .max(self.timing_proportion.0);
+        threshold.saturating_sub(self.master_cycles) / self.timing_proportion.1 + 1
+    }
+
+    /// Let the SMP run until `offset` master cycles after the last tick before
+    /// the next port access. The following ticks are reduced by the cycles, which
+    /// ran ahead, so that the SMP never runs backwards.
+    pub fn catch_up(&mut self, offset: Cycles) {
+        let target = offset * self.timing_proportion.1;
+        if target > self.caught_up {
+            self.master_cycles += target - self.caught_up;
+            self.caught_up = target;
+        }
     }
 
     fn refresh_counters(&mut self) -> Cycles {
@@ -130,10 +266,34 @@ impl<B: Backend> Smp<B> {
         cycles
     }
 
-    fn refresh_no_thread(spc: &mut Spc700, backend: &mut B, cycles: Cycles) {
+    fn refresh_no_thread(
+        spc: &mut Spc700,
+        backend: &mut B,
+        speed_adjust: &mut SpeedAdjust,
+        expansion_audio: &mut Option<AudioOutput>,
+        cycles: Cycles,
+    ) {
         for _ in 0..cycles {
             if let Some(sample) = spc.run_cycle() {
-                backend.push_sample(sample)
+                speed_adjust.push_sample(backend, expansion_audio, sample);
+                backend.push_voice_samples(spc.dsp().voice_outputs());
+            }
+        }
+    }
+
+    /// Run the SPC700 until it finished its current instruction
+    fn step_no_thread(
+        spc: &mut Spc700,
+        backend: &mut B,
+        speed_adjust: &mut SpeedAdjust,
+        expansion_audio: &mut Option<AudioOutput>,
+    ) -> Cycles {
+        let mut cycles = 0;
+        loop {
+            Self::refresh_no_thread(spc, backend, speed_adjust, expansion_audio, 1);
+            cycles += 1;
+            if spc.is_at_instruction_boundary() {
+                break cycles;
             }
         }
     }
@@ -141,7 +301,13 @@ impl<B: Backend> Smp<B> {
     pub fn refresh(&mut self) {
         let cycles = self.refresh_counters();
         if let (Some(spc), Some(backend)) = (&mut self.spc, &mut self.backend) {
-            Self::refresh_no_thread(spc, backend, cycles)
+            Self::refresh_no_thread(
+                spc,
+                backend,
+                &mut self.speed_adjust,
+                &mut self.expansion_audio,
+                cycles,
+            )
         } else if let Some(thread) = &mut self.thread {
             let _ = thread.send.send(ThreadCommand::RunCycles {
                 cycles,
@@ -155,7 +321,13 @@ impl<B: Backend> Smp<B> {
     pub fn read_output_port(&mut self, addr: u8) -> u8 {
         let cycles = self.refresh_counters();
         if let (Some(spc), Some(backend)) = (&mut self.spc, &mut self.backend) {
-            Self::refresh_no_thread(spc, backend, cycles);
+            Self::refresh_no_thread(
+                spc,
+                backend,
+                &mut self.speed_adjust,
+                &mut self.expansion_audio,
+                cycles,
+            );
             spc.output[usize::from(addr & 3)]
         } else if let Some(thread) = &mut self.thread {
             let _ = thread.send.send(ThreadCommand::RunCycles {
@@ -175,7 +347,13 @@ impl<B: Backend> Smp<B> {
     pub fn write_input_port(&mut self, addr: u8, data: u8) {
         let cycles = self.refresh_counters();
         if let (Some(spc), Some(backend)) = (&mut self.spc, &mut self.backend) {
-            Self::refresh_no_thread(spc, backend, cycles);
+            Self::refresh_no_thread(
+                spc,
+                backend,
+                &mut self.speed_adjust,
+                &mut self.expansion_audio,
+                cycles,
+            );
             spc.input[usize::from(addr & 3)] = data
         } else if let Some(thread) = &mut self.thread {
             let _ = thread.send.send(ThreadCommand::RunCycles {
@@ -187,29 +365,263 @@ impl<B: Backend> Smp<B> {
         }
     }
 
-    pub fn is_threaded(&self) -> bool {
-        self.thread.is_some()
+    /// Reset the SPC700 with the reset line, the audio RAM keeps its contents
+    pub fn reset(&mut self) {
+        self.master_cycles = 0;
+        self.caught_up = 0;
+        if let Some(spc) = &mut self.spc {
+            spc.reset()
+        } else if let Some(thread) = &mut self.thread {
+            let _ = thread.send.send(ThreadCommand::Reset);
+        }
+    }
+
+    /// Return the SPC700 and the DSP to their power-on state
+    pub fn power_cycle(&mut self) {
+        self.master_cycles = 0;
+        self.caught_up = 0;
+        if let Some(spc) = &mut self.spc {
+            replace_spc(spc, new_spc());
+        } else if let Some(thread) = &mut self.thread {
+            let _ = thread
+                .send
+                .send(ThreadCommand::SaveState(Box::new(new_spc())));
+        }
+    }
+
+    /// Mute individual voices of the DSP, see [`crate::spc700::Dsp::set_channel_mask`]
+    pub fn set_channel_mask(&mut self, mask: u8) {
+        if let Some(spc) = &mut self.spc {
+            spc.dsp_mut().set_channel_mask(mask)
+        } else if let Some(thread) = &mut self.thread {
+            let _ = thread.send.send(ThreadCommand::SetChannelMask(mask));
+        }
+    }
+
+    /// Select the interpolation of the DSP, see [`crate::spc700::Dsp::set_interpolation`]
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        if let Some(spc) = &mut self.spc {
+            spc.dsp_mut().set_interpolation(interpolation)
+        } else if let Some(thread) = &mut self.thread {
+            let _ = thread
+                .send
+                .send(ThreadCommand::SetInterpolation(interpolation));
+        }
     }
 
-    fn serialize_save_state(thread: &Option<Thread>, ser: &mut SaveStateSerializer) {
+    /// Write DSP registers at the beginning of the next sample,
+    /// see [`crate::spc700::Dsp::apply_patch`]
+    pub fn apply_dsp_patch(&mut self, writes: Vec<(u8, u8)>) {
+        if let Some(spc) = &mut self.spc {
+            spc.dsp_mut().apply_patch(&writes)
+        } else if let Some(thread) = &mut self.thread {
+            let _ = thread.send.send(ThreadCommand::ApplyDspPatch(writes));
+        }
+    }
+
+    /// Wait for the worker thread and get a copy of its SPC700
+    fn threaded_spc(thread: &Thread) -> Box<Spc700> {
         // TODO: do not unwrap
-        if let Some(thread) = thread {
-            thread.send.send(ThreadCommand::GetSaveState).unwrap();
+        thread.send.send(ThreadCommand::GetSaveState).unwrap();
+        match thread.recv.recv().unwrap() {
+            MainCommand::SaveState(spc) => spc,
+            _ => panic!(),
+        }
+    }
+
+    /// Take a snapshot of the DSP state, see [`crate::spc700::Dsp::debug_state`]
+    pub fn dsp_debug_state(&self) -> DspDebugState {
+        if let Some(spc) = &self.spc {
+            spc.dsp().debug_state()
+        } else if let Some(thread) = &self.thread {
+            Self::threaded_spc(thread).dsp().debug_state()
+        } else {
+            unreachable!()
+        }
+    }
+
+    /// The DSP registers at the beginning of the current sample,
+    /// see [`crate::spc700::Dsp::register_snapshot`]
+    pub fn dsp_registers(&self) -> [u8; 0x80] {
+        if let Some(spc) = &self.spc {
+            spc.dsp().register_snapshot()
+        } else if let Some(thread) = &self.thread {
+            Self::threaded_spc(thread).dsp().register_snapshot()
+        } else {
+            unreachable!()
+        }
+    }
+
+    /// Mix the audio of an expansion chip into the output
+    pub fn set_expansion_audio(&mut self, output: Option<AudioOutput>) {
+        if let Some(thread) = &mut self.thread {
+            let _ = thread.send.send(ThreadCommand::SetExpansionAudio(output));
+        } else {
+            self.expansion_audio = output
+        }
+    }
+
+    /// Adjust the audio output to the emulation speed, see [`crate::device::Device::set_speed`]
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed_adjust.set_speed(speed);
+        if let Some(thread) = &mut self.thread {
+            let _ = thread.send.send(ThreadCommand::SetSpeed(speed));
+        }
+    }
+
+    /// Break at the SPC700 instructions at the addresses `breakpoints`,
+    /// see [`Spc700::set_breakpoints`]
+    pub fn set_breakpoints(&mut self, breakpoints: Vec<u16>) {
+        if let Some(spc) = &mut self.spc {
+            spc.set_breakpoints(breakpoints)
+        } else if let Some(thread) = &mut self.thread {
+            let _ = thread.send.send(ThreadCommand::SetBreakpoints(breakpoints));
+        }
+    }
+
+    /// Take the addresses of the SPC700 breakpoints, which were hit since the last call.
+    ///
+    /// In threaded mode this waits for the worker thread, so only the
+    /// breakpoints up to the last synchronization with the main CPU are returned.
+    pub fn take_breakpoint_hits(&mut self) -> Vec<u16> {
+        if let Some(spc) = &mut self.spc {
+            spc.take_breakpoint_hits()
+        } else if let Some(thread) = &mut self.thread {
+            // TODO: do not unwrap
+            thread.send.send(ThreadCommand::TakeBreakpointHits).unwrap();
             match thread.recv.recv().unwrap() {
-                MainCommand::SaveState(new_spc) => {
-                    new_spc.serialize(ser);
-                }
+                MainCommand::BreakpointHits(hits) => hits,
+                _ => panic!(),
+            }
+        } else {
+            unreachable!()
+        }
+    }
+
+    /// Run the SPC700 until it executed exactly one instruction, while the
+    /// main CPU is held. The SPC700 runs ahead of the main CPU by the returned
+    /// SPC700 cycles, which are subtracted from the following ticks, so that
+    /// both processors stay in sync.
+    pub fn step_instruction(&mut self) -> Cycles {
+        let cycles = if let (Some(spc), Some(backend)) = (&mut self.spc, &mut self.backend) {
+            Self::step_no_thread(
+                spc,
+                backend,
+                &mut self.speed_adjust,
+                &mut self.expansion_audio,
+            )
+        } else if let Some(thread) = &mut self.thread {
+            // TODO: do not unwrap
+            thread.send.send(ThreadCommand::StepInstruction).unwrap();
+            match thread.recv.recv().unwrap() {
+                MainCommand::Stepped(cycles) => cycles,
                 _ => panic!(),
             }
+        } else {
+            unreachable!()
+        };
+        let ahead = cycles * self.timing_proportion.0;
+        let pending = ahead.min(self.master_cycles);
+        self.master_cycles -= pending;
+        self.caught_up += ahead - pending;
+        cycles
+    }
+
+    /// Take a snapshot of the SPC700 registers and the audio RAM,
+    /// see [`Spc700::debug_state`]
+    pub fn spc_debug_state(&self) -> SpcDebugState {
+        if let Some(spc) = &self.spc {
+            spc.debug_state()
+        } else if let Some(thread) = &self.thread {
+            Self::threaded_spc(thread).debug_state()
+        } else {
+            unreachable!()
+        }
+    }
+
+    pub fn is_threaded(&self) -> bool {
+        self.thread.is_some()
+    }
+}
+
+const SPC_TAG: u32 = save_state::field_tag("spc");
+const TIMING_PROPORTION_TAG: u32 = save_state::field_tag("timing_proportion");
+const MASTER_CYCLES_TAG: u32 = save_state::field_tag("master_cycles");
+const CAUGHT_UP_TAG: u32 = save_state::field_tag("caught_up");
+
+/// A SPC700 in its power-on state, which executes instructions cycle-stepped
+fn new_spc() -> Spc700 {
+    let mut spc = Spc700::default();
+    spc.set_cycle_stepped(true);
+    spc
+}
+
+/// Replace the SPC700, but keep the frontend settings of the DSP and the breakpoints
+fn replace_spc(spc: &mut Spc700, mut new_spc: Spc700) {
+    let dsp = new_spc.dsp_mut();
+    dsp.set_channel_mask(spc.dsp().channel_mask());
+    dsp.set_interpolation(spc.dsp().interpolation());
+    new_spc.set_breakpoints(spc.breakpoints().to_vec());
+    *spc = new_spc;
+}
+
+/// Switch the SPC700 of an older save state to the cycle-stepped mode
+fn make_cycle_stepped(spc: &mut Spc700) {
+    if !spc.is_cycle_stepped() {
+        spc.set_cycle_stepped(true)
+    }
+}
+
+/// The fields are stored like a derived implementation does, but the
+/// SPC700 is taken from the worker thread in threaded mode, so that
+/// the save states don't depend on the mode
+impl<B: Backend> InSaveState for Smp<B> {
+    fn serialize(&self, state: &mut SaveStateSerializer) {
+        let block = state.begin_block();
+        let field = state.begin_field(SPC_TAG);
+        if let Some(spc) = &self.spc {
+            spc.serialize(state)
+        } else if let Some(thread) = &self.thread {
+            Self::threaded_spc(thread).serialize(state)
         }
+        state.end_block(field);
+        let field = state.begin_field(TIMING_PROPORTION_TAG);
+        self.timing_proportion.serialize(state);
+        state.end_block(field);
+        let field = state.begin_field(MASTER_CYCLES_TAG);
+        self.master_cycles.serialize(state);
+        state.end_block(field);
+        let field = state.begin_field(CAUGHT_UP_TAG);
+        self.caught_up.serialize(state);
+        state.end_block(field);
+        state.end_block(block);
     }
 
-    fn deserialize_save_state(thread: &mut Option<Thread>, deser: &mut SaveStateDeserializer) {
-        if let Some(thread) = thread {
-            let mut spc = Spc700::default();
-            spc.deserialize(deser);
-            let _ = thread.send.send(ThreadCommand::SaveState(Box::new(spc)));
+    fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+        // missing in older save states
+        self.caught_up = 0;
+        let mut block = state.take_block();
+        while let Some((tag, mut field)) = block.next_field() {
+            match tag {
+                SPC_TAG => {
+                    if let Some(spc) = &mut self.spc {
+                        spc.deserialize(&mut field);
+                        make_cycle_stepped(spc)
+                    } else if let Some(thread) = &self.thread {
+                        let mut spc = new_spc();
+                        spc.deserialize(&mut field);
+                        make_cycle_stepped(&mut spc);
+                        let _ = thread.send.send(ThreadCommand::SaveState(Box::new(spc)));
+                    }
+                }
+                TIMING_PROPORTION_TAG => self.timing_proportion.deserialize(&mut field),
+                MASTER_CYCLES_TAG => self.master_cycles.deserialize(&mut field),
+                CAUGHT_UP_TAG => self.caught_up.deserialize(&mut field),
+                _ => (),
+            }
+            block.propagate(field);
         }
+        state.propagate(block);
     }
 }
 
@@ -223,3 +635,6 @@ impl<B: Backend> Drop for Smp<B> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
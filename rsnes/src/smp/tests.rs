@@ -0,0 +1,332 @@
+use super::*;
+use crate::spc700::debug::EnvelopePhase;
+use std::sync::{Arc, Mutex};
+
+/// The output samples and the samples of the voices
+#[derive(Default, Clone)]
+struct Samples(Arc<Mutex<Vec<StereoSample>>>, Arc<Mutex<Vec<[i16; 8]>>>);
+
+impl Backend for Samples {
+    fn push_sample(&mut self, sample: StereoSample) {
+        self.0.lock().unwrap().push(sample)
+    }
+
+    fn push_voice_samples(&mut self, samples: [i16; 8]) {
+        self.1.lock().unwrap().push(samples)
+    }
+}
+
+fn save_state(smp: &Smp<Samples>) -> Vec<u8> {
+    let mut ser = SaveStateSerializer::new();
+    smp.serialize(&mut ser);
+    ser.into_vec()
+}
+
+/// Run the IPL ROM and upload a byte like a game does
+fn run(smp: &mut Smp<Samples>) -> Vec<u8> {
+    let mut outputs = vec![];
+    for _ in 0..100 {
+        smp.tick(1364);
+        smp.refresh();
+        outputs.push(smp.read_output_port(0));
+    }
+    smp.write_input_port(1, 0x01);
+    smp.write_input_port(2, 0x00);
+    smp.write_input_port(3, 0x02);
+    smp.write_input_port(0, 0xcc);
+    for _ in 0..10 {
+        smp.tick(1364);
+        outputs.push(smp.read_output_port(0));
+    }
+    outputs
+}
+
+#[test]
+fn threaded_is_deterministic() {
+    let (samples, threaded_samples) = (Samples::default(), Samples::default());
+    let mut smp = Smp::new(samples.clone(), false, false);
+    let mut threaded = Smp::new(threaded_samples.clone(), false, true);
+    assert!(threaded.is_threaded());
+
+    let outputs = run(&mut smp);
+    assert_eq!(outputs[99], 0xaa);
+    assert_eq!(outputs[109], 0xcc);
+    assert_eq!(run(&mut threaded), outputs);
+    let state = save_state(&smp);
+    assert_eq!(save_state(&threaded), state);
+    assert_eq!(
+        *threaded_samples.0.lock().unwrap(),
+        *samples.0.lock().unwrap()
+    );
+
+    // a save state of one mode can be loaded in the other mode
+    run(&mut threaded);
+    threaded.deserialize(&mut SaveStateDeserializer::new(&state));
+    assert_eq!(save_state(&threaded), state);
+    let threaded_state = save_state(&threaded);
+    run(&mut smp);
+    smp.deserialize(&mut SaveStateDeserializer::new(&threaded_state));
+    assert_eq!(run(&mut smp), run(&mut threaded));
+}
+
+#[test]
+fn dsp_debug_state() {
+    let mut smp = Smp::new(Samples::default(), false, false);
+    let mut threaded = Smp::new(Samples::default(), false, true);
+    run(&mut smp);
+    run(&mut threaded);
+    let state = smp.dsp_debug_state();
+    assert_eq!(state.flags, 0xe0);
+    assert!(state.voices.iter().all(|voice| voice.ended));
+    assert_eq!(state.voices[0].phase, EnvelopePhase::Release);
+    assert_eq!(threaded.dsp_debug_state(), state);
+}
+
+/// Tick the SMP until the output port `port` shows `value`
+/// and return the amount of master cycles it took
+fn wait_for(smp: &mut Smp<Samples>, port: u8, value: u8, step: u16) -> u32 {
+    let mut cycles = 0;
+    while smp.read_output_port(port) != value {
+        smp.tick(step);
+        cycles += u32::from(step);
+        assert!(
+            cycles < 1_000_000,
+            "port {} never showed {:#04x}",
+            port,
+            value
+        );
+    }
+    cycles
+}
+
+/// Upload a program with the handshake of the IPL ROM and jump to it
+fn upload(smp: &mut Smp<Samples>, addr: u16, program: &[u8]) {
+    wait_for(smp, 0, 0xaa, 64);
+    wait_for(smp, 1, 0xbb, 64);
+    let [low, high] = addr.to_le_bytes();
+    smp.write_input_port(2, low);
+    smp.write_input_port(3, high);
+    smp.write_input_port(1, 1);
+    smp.write_input_port(0, 0xcc);
+    wait_for(smp, 0, 0xcc, 64);
+    for (i, byte) in program.iter().enumerate() {
+        smp.write_input_port(1, *byte);
+        smp.write_input_port(0, i as u8);
+        wait_for(smp, 0, i as u8, 64);
+    }
+    smp.write_input_port(2, low);
+    smp.write_input_port(3, high);
+    smp.write_input_port(1, 0);
+    smp.write_input_port(0, program.len() as u8 + 1);
+}
+
+/// Break in a program echoing the port 0 and step through its loop
+fn breakpoints_and_steps(threaded: bool) -> (Vec<u16>, Vec<(u16, Cycles)>) {
+    let mut smp = Smp::new(Samples::default(), false, threaded);
+    // loop: MOV A, $F4; MOV $F4, A; BRA loop
+    upload(&mut smp, 0x0200, &[0xe4, 0xf4, 0xc4, 0xf4, 0x2f, 0xfa]);
+    smp.set_breakpoints(vec![0x0204]);
+    run_lines(&mut smp, 10);
+    let hits = smp.take_breakpoint_hits();
+    assert!(smp.take_breakpoint_hits().is_empty());
+    smp.set_breakpoints(vec![]);
+    // finish the current instruction first
+    smp.step_instruction();
+    let steps = (0..6)
+        .map(|_| {
+            let pc = smp.spc_debug_state().pc;
+            (pc, smp.step_instruction())
+        })
+        .collect();
+    (hits, steps)
+}
+
+#[test]
+fn spc_breakpoints_and_stepping() {
+    let (hits, steps) = breakpoints_and_steps(false);
+    assert!(hits.len() > 10);
+    assert!(hits.iter().all(|&pc| pc == 0x0204));
+    // every iteration of the loop takes 11 cycles
+    let start = steps.iter().position(|&(pc, _)| pc == 0x0200).unwrap();
+    assert_eq!(
+        steps[start..start + 3],
+        [(0x0200, 3), (0x0202, 4), (0x0204, 4)]
+    );
+    assert_eq!(breakpoints_and_steps(true), (hits, steps));
+}
+
+#[test]
+fn spc_debug_state() {
+    let mut smp = Smp::new(Samples::default(), false, false);
+    let state = smp.spc_debug_state();
+    assert!(state.rom_mapped);
+    assert_eq!(state.pc, 0xffc0);
+    assert_eq!(state.disassemble(0xffc0).text, "mov x, #$ef");
+    upload(&mut smp, 0x0200, &[0xe4, 0xf4, 0xc4, 0xf4, 0x2f, 0xfa]);
+    run_lines(&mut smp, 1);
+    let state = smp.spc_debug_state();
+    assert_eq!(
+        state.ram[0x0200..0x0206],
+        [0xe4, 0xf4, 0xc4, 0xf4, 0x2f, 0xfa]
+    );
+    let instr = state.disassemble(0x0204);
+    assert_eq!(instr.text, "bra $0200");
+    assert_eq!(instr.next_addr(), 0x0206);
+}
+
+/// Send values to a program echoing the port 0 as fast as possible, while the
+/// ports are accessed at odd master cycles, and return the cycles of each echo
+fn port_handshakes(threaded: bool) -> Vec<u32> {
+    let mut smp = Smp::new(Samples::default(), false, threaded);
+    // loop: MOV A, $F4; MOV $F4, A; BRA loop
+    upload(&mut smp, 0x0200, &[0xe4, 0xf4, 0xc4, 0xf4, 0x2f, 0xfa]);
+    (0u8..=255)
+        .map(|value| {
+            let step = 1 + u16::from(value % 13);
+            smp.tick(step);
+            smp.catch_up(u32::from(value % 24));
+            smp.write_input_port(0, value.wrapping_add(0x40));
+            wait_for(&mut smp, 0, value.wrapping_add(0x40), step)
+        })
+        .collect()
+}
+
+#[test]
+fn port_handshake_stress() {
+    let cycles = port_handshakes(false);
+    // after the jump from the IPL ROM, an echo takes at most one iteration
+    // of the loop with 11 SPC700 cycles, which are about 231 master cycles
+    assert!(cycles[2..].iter().all(|&c| c <= 240), "{:?}", cycles);
+    assert!(cycles.iter().any(|&c| c > 0));
+    assert_eq!(port_handshakes(true), cycles);
+}
+
+#[test]
+fn catch_up() {
+    let mut smp = Smp::new(Samples::default(), false, false);
+    let mut ahead = Smp::new(Samples::default(), false, false);
+    for _ in 0..100 {
+        smp.tick(1364);
+        ahead.catch_up(1000);
+        ahead.tick(1364);
+        // running ahead does not change the time of the following accesses
+        assert_eq!(ahead.read_output_port(0), smp.read_output_port(0));
+    }
+    assert_eq!(save_state(&ahead), save_state(&smp));
+}
+
+/// Let the voice 2 of an unthreaded SMP play a looping BRR block, whose
+/// bytes are all `data`, at the original pitch with the full volume
+fn play_voice(smp: &mut Smp<Samples>, data: u8) {
+    let spc = smp.spc.as_mut().unwrap();
+    // the sample directory at $0200 points to the BRR block at $1000
+    spc.mem[0x200..0x204].copy_from_slice(&[0x00, 0x10, 0x00, 0x10]);
+    spc.mem[0x1000] = 0xc3;
+    spc.mem[0x1001..0x1009].fill(data);
+    let dsp = spc.dsp_mut();
+    for (addr, val) in [(0x20, 0x7f), (0x21, 0x7f), (0x23, 0x10), (0x27, 0x7f)] {
+        dsp.write(addr, val)
+    }
+    dsp.write(0x5d, 0x02);
+    dsp.write(0x6c, 0x20);
+    dsp.write(0x4c, 0x04);
+}
+
+fn run_lines(smp: &mut Smp<Samples>, lines: usize) {
+    for _ in 0..lines {
+        smp.tick(1364);
+        smp.refresh();
+    }
+}
+
+#[test]
+fn voice_samples() {
+    let samples = Samples::default();
+    let mut smp = Smp::new(samples.clone(), false, false);
+    play_voice(&mut smp, 0x77);
+    smp.set_channel_mask(!0x04);
+    run_lines(&mut smp, 20);
+    let voices = samples.1.lock().unwrap();
+    assert_eq!(voices.len(), samples.0.lock().unwrap().len());
+    assert!(voices.iter().all(|voice| voice[2] >= 0));
+    assert!(voices.iter().any(|voice| voice[2] > 0x1000));
+    assert!(voices
+        .iter()
+        .all(|voice| voice[..2] == [0; 2] && voice[3..] == [0; 5]));
+    // the muted voice is tapped, but not mixed into the output
+    assert!(samples
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|s| *s == StereoSample::default()));
+}
+
+/// The highest sample of the voice 2 playing a square wave
+fn square_wave_peak(interpolation: Interpolation, threaded: bool) -> i16 {
+    let samples = Samples::default();
+    let mut smp = Smp::new(samples.clone(), false, false);
+    play_voice(&mut smp, 0x79);
+    if threaded {
+        // the interpolation is kept, when a state is loaded
+        let state = save_state(&smp);
+        smp = Smp::new(samples.clone(), false, true);
+        smp.set_interpolation(interpolation);
+        smp.deserialize(&mut SaveStateDeserializer::new(&state));
+    } else {
+        smp.set_interpolation(interpolation);
+    }
+    run_lines(&mut smp, 40);
+    drop(smp);
+    let voices = samples.1.lock().unwrap();
+    voices.iter().map(|voice| voice[2]).max().unwrap()
+}
+
+#[test]
+fn cubic_interpolation() {
+    let gaussian = square_wave_peak(Interpolation::Gaussian, false);
+    let cubic = square_wave_peak(Interpolation::Cubic, false);
+    assert!(gaussian > 0x1000);
+    // the spline overshoots at the edges, which the Gaussian filter smoothes
+    assert!(cubic > gaussian, "{:#x} <= {:#x}", cubic, gaussian);
+    assert_eq!(square_wave_peak(Interpolation::Cubic, true), cubic);
+    assert_eq!(square_wave_peak(Interpolation::Gaussian, true), gaussian);
+}
+
+#[test]
+fn dsp_patch_waits_for_the_next_sample() {
+    let mut spc = new_spc();
+    for _ in 0..5 {
+        spc.run_cycle();
+    }
+    // MVOLL, MVOLR and the pitch of voice 0
+    let patch = [(0x0c, 0x40), (0x1c, 0x40), (0x02, 0x00), (0x03, 0x10)];
+    spc.dsp_mut().apply_patch(&patch);
+    let mut cycles = 0;
+    while spc.dsp().registers()[0x0c] != 0x40 {
+        assert_eq!(spc.dsp().register_snapshot()[0x1c], 0);
+        spc.run_cycle();
+        cycles += 1;
+    }
+    // one sample takes 32 cycles
+    assert_eq!(cycles, 32 - 5 + 1);
+    let registers = spc.dsp().register_snapshot();
+    assert!(patch
+        .iter()
+        .all(|&(addr, val)| registers[usize::from(addr)] == val));
+}
+
+#[test]
+fn dsp_patch() {
+    let mut smp = Smp::new(Samples::default(), false, false);
+    let mut threaded = Smp::new(Samples::default(), false, true);
+    for smp in [&mut smp, &mut threaded] {
+        run_lines(smp, 1);
+        smp.apply_dsp_patch(vec![(0x0c, 0x7f), (0x5d, 0x02)]);
+        run_lines(smp, 1);
+    }
+    let registers = smp.dsp_registers();
+    assert_eq!((registers[0x0c], registers[0x5d]), (0x7f, 0x02));
+    assert_eq!(threaded.dsp_registers(), registers);
+    assert_eq!(save_state(&threaded), save_state(&smp));
+}
@@ -6,7 +6,14 @@
 //! - <https://emudev.de/q00-snes/spc700-the-audio-processor/>
 //! - The first of the two official SNES documentation books
 
-use crate::timing::Cycles;
+pub mod debug;
+pub mod disasm;
+
+use crate::{
+    breakpoint::MAX_RECORDED_EVENTS,
+    spc_file::{self, Id666, SpcFileError},
+    timing::Cycles,
+};
 use core::{cell::Cell, mem::take};
 use save_state::{SaveStateDeserializer, SaveStateSerializer};
 use save_state_macro::*;
@@ -98,7 +105,20 @@ static CYCLES: [Cycles; 256] = [
        2, 8, 4, 5, 4, 5, 5, 6,   3, 4, 5, 4, 2, 2, 4, 2,  // f^
 ];
 
-const F0_RESET: u8 = 0x80;
+const TEST_RESET: u8 = 0x0a;
+const CONTROL_RESET: u8 = 0x80;
+
+/// Bits of the undocumented TEST register ($F0)
+pub mod test_flags {
+    pub const TIMERS_DISABLE: u8 = 0x01;
+    pub const RAM_WRITABLE: u8 = 0x02;
+    pub const RAM_DISABLE: u8 = 0x04;
+    pub const TIMERS_ENABLE: u8 = 0x08;
+    /// Wait states on every memory access
+    pub const CLOCK_SPEED: u8 = 0x30;
+    /// Wait states of the timers
+    pub const TIMER_SPEED: u8 = 0xc0;
+}
 
 /// Flags
 pub mod flags {
@@ -136,11 +156,54 @@ impl save_state::InSaveState for AdsrPeriod {
             1 => Self::Decay,
             2 => Self::Sustain,
             3 => Self::Release,
-            _ => panic!("unknown enum discriminant {}", i),
+            _ => return state.fail(save_state::DeserializeError::InvalidValue),
         }
     }
 }
 
+/// The interpolation between the decoded samples of a voice
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// The Gaussian filter of the hardware, which muffles high frequencies
+    #[default]
+    Gaussian,
+    /// A 4-point cubic (Catmull-Rom) spline, which sounds clearer
+    Cubic,
+}
+
+impl Interpolation {
+    /// The interpolation modes together with their names
+    pub const NAMES: [(Self, &'static str); 2] =
+        [(Self::Gaussian, "gaussian"), (Self::Cubic, "cubic")];
+
+    /// The interpolation with the name `name` as used in [`Self::NAMES`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, mode_name)| *mode_name == name)
+            .map(|(mode, _)| *mode)
+    }
+
+    pub fn name(self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(mode, _)| *mode == self)
+            .map(|(_, name)| *name)
+            .unwrap()
+    }
+}
+
+/// Interpolate between `p[1]` and `p[2]` at the position `t / 256` with a Catmull-Rom spline
+fn cubic_interpolation(p: [i16; 4], t: u16) -> i16 {
+    let [p0, p1, p2, p3] = p.map(i64::from);
+    let t = i64::from(t);
+    let a = 3 * (p1 - p2) + p3 - p0;
+    let b = 2 * p0 - 5 * p1 + 4 * p2 - p3;
+    let c = p2 - p0;
+    let v = ((((a * t + (b << 8)) * t + (c << 16)) * t) >> 25) + p1;
+    v.clamp(-0x8000, 0x7fff) as i16
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, InSaveState)]
 pub struct StereoSample<T: save_state::InSaveState = i16> {
     pub l: T,
@@ -350,6 +413,21 @@ pub struct Dsp {
     echo_sample: StereoSample,
 
     global_output: StereoSample,
+    /// Every cleared bit mutes the corresponding voice in the output.
+    /// This is a frontend setting and not part of the emulated hardware.
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    channel_mask: u8,
+    /// The last output of every voice, which is not mixed with the channel mask
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    voice_outputs: [i16; 8],
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    interpolation: Interpolation,
+    /// Register writes of the frontend, which wait for the next sample
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pending_patch: Vec<(u8, u8)>,
+    /// The registers at the beginning of the current sample
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    snapshot: [u8; 0x80],
 }
 
 impl Dsp {
@@ -382,7 +460,7 @@ impl Dsp {
             envx_buf: 0,
             outx_buf: 0,
             endx_buf: 0,
-            flag_buf: 0,
+            flag_buf: 0xe0,
             brr_head: 0,
             brr_data: 0,
             is_even: true,
@@ -395,6 +473,11 @@ impl Dsp {
             echo_sample: StereoSample::<i16>::new2(0),
 
             global_output: StereoSample::<i16>::new2(0),
+            channel_mask: 0xff,
+            voice_outputs: [0; 8],
+            interpolation: Interpolation::Gaussian,
+            pending_patch: Vec::new(),
+            snapshot: mem,
         }
     }
 
@@ -426,6 +509,88 @@ impl Dsp {
         self.mem[(adr & 0x7f) as usize]
     }
 
+    pub const fn registers(&self) -> &[u8; 0x80] {
+        &self.mem
+    }
+
+    /// Write the registers of `writes` in the given order at once.
+    ///
+    /// The writes are deferred to the beginning of the next sample, so that
+    /// the voice pipeline never sees only a part of them. This allows
+    /// external tools to change the DSP while it is playing.
+    pub fn apply_patch(&mut self, writes: &[(u8, u8)]) {
+        self.pending_patch.extend_from_slice(writes);
+        if self.step_counter == 0 {
+            self.begin_sample()
+        }
+    }
+
+    /// The registers at the beginning of the current sample.
+    ///
+    /// Unlike [`Self::registers`], the snapshot doesn't contain the
+    /// changes of the voice pipeline in the middle of a sample.
+    pub const fn register_snapshot(&self) -> [u8; 0x80] {
+        if self.step_counter == 0 {
+            self.mem
+        } else {
+            self.snapshot
+        }
+    }
+
+    /// Apply the pending patch and take the register snapshot
+    fn begin_sample(&mut self) {
+        for (addr, val) in take(&mut self.pending_patch) {
+            self.write(addr, val)
+        }
+        self.snapshot = self.mem;
+    }
+
+    /// Replace the DSP state with a fresh one using the given register values
+    pub fn load_registers(&mut self, regs: &[u8; 0x80]) {
+        *self = Self {
+            channel_mask: self.channel_mask,
+            interpolation: self.interpolation,
+            ..Self::new()
+        };
+        self.mem = *regs;
+        self.snapshot = *regs;
+        self.flag_buf = regs[usize::from(regs::FLG)];
+    }
+
+    /// Mute individual voices. Every set bit enables the
+    /// corresponding voice, every cleared bit mutes it.
+    ///
+    /// Muted voices are still processed (e.g. ENVX and OUTX
+    /// are still updated), they are just not mixed into the output.
+    pub fn set_channel_mask(&mut self, mask: u8) {
+        self.channel_mask = mask
+    }
+
+    pub const fn channel_mask(&self) -> u8 {
+        self.channel_mask
+    }
+
+    /// Select the interpolation between the samples of the voices.
+    ///
+    /// The voices are also mixed into the echo buffer in the audio RAM,
+    /// which the SPC700 can read. So any other mode than the default
+    /// [`Interpolation::Gaussian`] can change the behaviour of a game, and
+    /// must not be used, when the emulation has to be deterministic,
+    /// e.g. with movies or netplay.
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation
+    }
+
+    pub const fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    /// The last output sample of every voice after the envelope was applied
+    /// and before it is mixed with its volume. Muted voices are included.
+    pub const fn voice_outputs(&self) -> [i16; 8] {
+        self.voice_outputs
+    }
+
     pub fn run_step<const STEP: u8>(&mut self, voice: u8, ram: &[u8; MEMORY_SIZE]) {
         macro_rules! vx {
             ($id:ident) => {
@@ -458,12 +623,14 @@ impl Dsp {
                 output!(1 r)
             };
             ($channel:literal $i:ident) => {{
-                let sample =
-                    ((i32::from(self.output) * i32::from(vx!(VOLL | $channel) as i8)) >> 7).clamp(-0x8000, 0x7fff) as i16;
-                let amp = |s: &mut i16| *s = s.saturating_add(sample);
-                amp(&mut self.main_sample.$i);
-                if (self.echo_enabled >> voice) & 1 > 0 {
-                    amp(&mut self.echo_sample.$i)
+                if (self.channel_mask >> voice) & 1 > 0 {
+                    let sample =
+                        ((i32::from(self.output) * i32::from(vx!(VOLL | $channel) as i8)) >> 7).clamp(-0x8000, 0x7fff) as i16;
+                    let amp = |s: &mut i16| *s = s.saturating_add(sample);
+                    amp(&mut self.main_sample.$i);
+                    if (self.echo_enabled >> voice) & 1 > 0 {
+                        amp(&mut self.echo_sample.$i)
+                    }
                 }
             }};
         }
@@ -613,7 +780,14 @@ impl Dsp {
                         * i32::from(voice!().decode_buffer[usize::from(i % 12)]))
                         >> 11
                 };
-                let out = if (self.noise_enabled >> voice) & 1 == 0 {
+                let out = if (self.noise_enabled >> voice) & 1 > 0 {
+                    (self.noise << 1) as i16
+                } else if self.interpolation == Interpolation::Cubic {
+                    let points = core::array::from_fn(|i| {
+                        voice!().decode_buffer[(usize::from(off) + i) % 12]
+                    });
+                    cubic_interpolation(points, gauss)
+                } else {
                     ((i32::from(
                         ((gv(0xff - gauss, off)
                             + gv(0x1ff - gauss, off + 1)
@@ -621,10 +795,9 @@ impl Dsp {
                             & 0xffff) as i16,
                     ) + gv(gauss, off + 3))
                     .clamp(-0x8000, 0x7fff)) as i16
-                } else {
-                    (self.noise << 1) as i16
                 };
                 self.output = ((i32::from(out) * i32::from(voice!().gain)) >> 11) as i16;
+                self.voice_outputs[usize::from(voice)] = self.output;
                 voice!().envx_buf = ((voice!().gain >> 4) & 0xff) as u8;
                 if reg!(FLG) & 0x80 > 0 || self.brr_head & 3 == 1 {
                     voice!().period = AdsrPeriod::Release;
@@ -772,6 +945,9 @@ impl Dsp {
                 }
             }};
         }
+        if self.step_counter == 0 {
+            self.begin_sample()
+        }
         match self.step_counter {
             0 => step!(0[5], 1[2]),
             1 => step!(0[6], 1[3]),
@@ -836,6 +1012,7 @@ impl Dsp {
                 self.main_sample.r = calculate_echo!(right);
                 let out = take(&mut self.main_sample);
 
+                // the mute flag takes effect on the very next output sample
                 self.global_output = if reg!(FLG) & 0x40 > 0 {
                     StereoSample::<i16>::new2(0)
                 } else {
@@ -862,6 +1039,8 @@ impl Dsp {
                 if self.echo_index >= self.echo_length {
                     self.echo_index = 0
                 }
+                // the left channel is written with the echo write
+                // disable flag of step 28, the right one with this one
                 echo_to_ram!(left);
                 self.flag_buf = reg!(FLG);
             }
@@ -887,35 +1066,50 @@ impl Dsp {
 
 #[derive(Debug, Clone, InSaveState)]
 pub struct Spc700 {
-    mem: [u8; MEMORY_SIZE],
+    pub(crate) mem: [u8; MEMORY_SIZE],
     /// data, the main processor sends to us
     pub input: [u8; 4],
     /// data, we send to the main processor
     pub output: [u8; 4],
     dsp: Dsp,
 
-    a: u8,
-    x: u8,
-    y: u8,
-    sp: u8,
-    status: u8,
-    pc: u16,
+    pub(crate) a: u8,
+    pub(crate) x: u8,
+    pub(crate) y: u8,
+    pub(crate) sp: u8,
+    pub(crate) status: u8,
+    pub(crate) pc: u16,
 
     timer_max: [u8; 3],
     // internal timer ticks ALL in 64kHz
     timers: [u8; 3],
     timer_enable: u8,
     counters: [Cell<u8>; 3],
+    /// the TEST register ($F0)
+    test: u8,
     dispatch_counter: u16,
-    cycles_ahead: Cycles,
+    pub(crate) cycles_ahead: Cycles,
     halt: bool,
+    /// Execute instructions in their last cycle instead of their first one
+    cycle_stepped: bool,
+    /// In cycle-stepped mode: the instruction at `pc` waits for its last cycle
+    pub(crate) instruction_pending: bool,
+    /// The addresses of the instructions to break at, see [`Spc700::set_breakpoints`]
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    breakpoints: Vec<u16>,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    breakpoint_hits: Vec<u16>,
+    /// Bypass all memory mapped I/O and the IPL ROM
+    #[cfg(feature = "cpu-tests")]
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) flat_memory: bool,
 }
 
 impl Default for Spc700 {
     fn default() -> Self {
         const fn generate_power_up_memory() -> [u8; MEMORY_SIZE] {
             let mut mem = [0; MEMORY_SIZE];
-            mem[0xf0] = F0_RESET;
+            mem[0xf1] = CONTROL_RESET;
             mem
         }
         const POWER_UP_MEMORY: [u8; MEMORY_SIZE] = generate_power_up_memory();
@@ -935,16 +1129,24 @@ impl Default for Spc700 {
             timers: [0; 3],
             timer_enable: 0,
             counters: [Cell::new(0), Cell::new(0), Cell::new(0)],
+            test: TEST_RESET,
             dispatch_counter: 0,
             cycles_ahead: 2,
             halt: false,
+            cycle_stepped: false,
+            instruction_pending: false,
+            breakpoints: Vec::new(),
+            breakpoint_hits: Vec::new(),
+            #[cfg(feature = "cpu-tests")]
+            flat_memory: false,
         }
     }
 }
 
 impl Spc700 {
     pub fn reset(&mut self) {
-        self.mem[0xf0] = F0_RESET;
+        self.mem[0xf1] = CONTROL_RESET;
+        self.test = TEST_RESET;
         self.input = [0; 4];
         self.output = [0; 4];
         self.a = 0;
@@ -952,15 +1154,40 @@ impl Spc700 {
         self.y = 0;
         self.sp = 0;
         // actually self.read16(0xfffe), but this will
-        // always result in 0xffc0, because mem[0xf0] = 0x80
+        // always result in 0xffc0, because the ROM is mapped after reset
         self.pc = 0xffc0;
         self.status = 0;
         self.halt = false;
+        self.instruction_pending = false;
         // TODO: reset dsp
     }
 
+    pub const fn dsp(&self) -> &Dsp {
+        &self.dsp
+    }
+
+    pub fn dsp_mut(&mut self) -> &mut Dsp {
+        &mut self.dsp
+    }
+
     pub fn is_rom_mapped(&self) -> bool {
-        self.mem[0xf0] & 0x80 > 0
+        self.mem[0xf1] & 0x80 > 0
+    }
+
+    /// The value of the TEST register ($F0)
+    pub const fn test_register(&self) -> u8 {
+        self.test
+    }
+
+    const fn are_timers_running(&self) -> bool {
+        self.test & (test_flags::TIMERS_ENABLE | test_flags::TIMERS_DISABLE)
+            == test_flags::TIMERS_ENABLE
+    }
+
+    /// Factor, by which every instruction is slowed down
+    /// due to the memory wait states in the TEST register
+    const fn wait_factor(&self) -> Cycles {
+        [1, 2, 5, 10][((self.test & test_flags::CLOCK_SPEED) >> 4) as usize]
     }
 
     pub fn read16(&self, addr: u16) -> u16 {
@@ -969,19 +1196,42 @@ impl Spc700 {
 
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
+            #[cfg(feature = "cpu-tests")]
+            _ if self.flat_memory => self.mem[usize::from(addr)],
+            0xfd..=0xff => self.counters[usize::from(addr - 0xfd)].take(),
+            addr => self.peek(addr),
+        }
+    }
+
+    /// Read a byte without any side effects on the I/O registers
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            #[cfg(feature = "cpu-tests")]
+            _ if self.flat_memory => self.mem[usize::from(addr)],
             0xf3 => self.dsp.read(self.mem[0xf2]),
             0xf4..=0xf7 => self.input[usize::from(addr - 0xf4)],
-            0xfd..=0xff => self.counters[usize::from(addr - 0xfd)].take(),
+            0xfd..=0xff => self.counters[usize::from(addr - 0xfd)].get(),
             0xf0..=0xf1 | 0xfa..=0xfc => 0,
             0xffc0..=0xffff if self.is_rom_mapped() => ROM[(addr & 0x3f) as usize],
+            0xf2 | 0xf8 | 0xf9 => self.mem[usize::from(addr)],
+            // TODO: this value is taken from higan, verify it on real hardware
+            _ if self.test & test_flags::RAM_DISABLE > 0 => 0x5a,
             addr => self.mem[addr as usize],
         }
     }
 
     pub fn write(&mut self, addr: u16, val: u8) {
         match addr {
-            0xf0 => todo!("undocumented SPC register TEST(f0) written"),
+            #[cfg(feature = "cpu-tests")]
+            _ if self.flat_memory => self.mem[usize::from(addr)] = val,
+            0xf0 => {
+                // writes are ignored while the P flag is set (taken from higan)
+                if self.status & flags::ZERO_PAGE == 0 {
+                    self.test = val
+                }
+            }
             0xf1 => {
+                self.mem[0xf1] = val;
                 if val & 0x10 > 0 {
                     self.input[0..2].fill(0)
                 }
@@ -1000,6 +1250,8 @@ impl Spc700 {
             0xf3 => self.dsp.write(self.mem[0xf2], val),
             0xf4..=0xf7 => self.output[(addr - 0xf4) as usize] = val,
             0xfa..=0xfc => self.timer_max[usize::from(addr & 3) ^ 2] = val,
+            0xf2 | 0xf8 | 0xf9 => self.mem[usize::from(addr)] = val,
+            _ if self.test & test_flags::RAM_WRITABLE == 0 => (),
             addr => self.mem[addr as usize] = val,
         }
     }
@@ -2451,7 +2703,7 @@ impl Spc700 {
     }
 
     pub fn update_timer(&mut self, i: usize) {
-        if self.timer_enable & (1 << i) > 0 {
+        if self.timer_enable & (1 << i) > 0 && self.are_timers_running() {
             self.timers[i] = self.timers[i].wrapping_add(1);
             if self.timers[i] == self.timer_max[i] {
                 self.timers[i] = 0;
@@ -2460,11 +2712,144 @@ impl Spc700 {
         }
     }
 
+    /// Create a SPC sound file image of the current state
+    pub fn export_spc(&self, tag: Option<&Id666>) -> Vec<u8> {
+        use spc_file::{DSP_OFFSET, EXTRA_RAM_OFFSET, RAM_OFFSET, REGS_OFFSET};
+        let mut data = spc_file::new_image(tag);
+        let [pcl, pch] = self.pc.to_le_bytes();
+        data[REGS_OFFSET..REGS_OFFSET + 7].copy_from_slice(&[
+            pcl,
+            pch,
+            self.a,
+            self.x,
+            self.y,
+            self.status,
+            self.sp,
+        ]);
+        let ram = &mut data[RAM_OFFSET..RAM_OFFSET + MEMORY_SIZE];
+        ram.copy_from_slice(&self.mem);
+        ram[0xf0] = self.test;
+        ram[0xf3] = self.dsp.read(self.mem[0xf2]);
+        ram[0xf4..0xf8].copy_from_slice(&self.input);
+        ram[0xfa..0xfd].copy_from_slice(&self.timer_max);
+        for (i, counter) in self.counters.iter().enumerate() {
+            ram[0xfd + i] = counter.get();
+        }
+        data[DSP_OFFSET..DSP_OFFSET + 0x80].copy_from_slice(self.dsp.registers());
+        data[EXTRA_RAM_OFFSET..].copy_from_slice(&self.mem[0xffc0..]);
+        data
+    }
+
+    /// Load a SPC sound file image and return its ID666 tag, if there is one
+    pub fn load_spc(&mut self, data: &[u8]) -> Result<Option<Id666>, SpcFileError> {
+        use spc_file::{DSP_OFFSET, EXTRA_RAM_OFFSET, RAM_OFFSET, REGS_OFFSET};
+        spc_file::validate(data)?;
+        self.reset();
+        self.output = [0; 4];
+        self.mem
+            .copy_from_slice(&data[RAM_OFFSET..RAM_OFFSET + MEMORY_SIZE]);
+        self.input.copy_from_slice(&self.mem[0xf4..0xf8]);
+        self.timer_max.copy_from_slice(&self.mem[0xfa..0xfd]);
+        self.timer_enable = 0;
+        self.timers = [0; 3];
+        let control = self.mem[0xf1];
+        self.write(0xf1, control & 0x87);
+        for (i, counter) in self.counters.iter().enumerate() {
+            counter.set(self.mem[0xfd + i] & 0xf);
+        }
+        if let Some(extra) = data.get(EXTRA_RAM_OFFSET..spc_file::FILE_SIZE) {
+            if self.is_rom_mapped() {
+                self.mem[0xffc0..].copy_from_slice(extra)
+            }
+        }
+        let regs = &data[REGS_OFFSET..REGS_OFFSET + 7];
+        self.pc = u16::from_le_bytes([regs[0], regs[1]]);
+        self.a = regs[2];
+        self.x = regs[3];
+        self.y = regs[4];
+        self.status = regs[5];
+        self.sp = regs[6];
+        let mut dsp_regs = [0; 0x80];
+        dsp_regs.copy_from_slice(&data[DSP_OFFSET..DSP_OFFSET + 0x80]);
+        self.dsp.load_registers(&dsp_regs);
+        self.dispatch_counter = 0;
+        self.cycles_ahead = 0;
+        Ok(spc_file::Id666::read(data))
+    }
+
+    /// Enable or disable the cycle-stepped execution mode.
+    ///
+    /// By default an instruction is executed as a whole in its first cycle.
+    /// In cycle-stepped mode the CPU idles for the table cycles of the
+    /// instruction first and executes it in its last cycle, so that all
+    /// bus accesses see the timers and ports of the time they are finished.
+    pub fn set_cycle_stepped(&mut self, cycle_stepped: bool) {
+        self.cycle_stepped = cycle_stepped;
+        self.instruction_pending = false;
+    }
+
+    pub const fn is_cycle_stepped(&self) -> bool {
+        self.cycle_stepped
+    }
+
+    /// Test if the last instruction is finished and the next one at `pc`
+    /// did not start yet. This is always the case while the SPC700 is halted.
+    pub const fn is_at_instruction_boundary(&self) -> bool {
+        self.cycles_ahead == 0 && !self.instruction_pending
+    }
+
+    /// Break at the instructions at the addresses `breakpoints`.
+    ///
+    /// The breakpoints are checked after every instruction, i.e. before the
+    /// instruction at `pc` is executed. Breakpoints are not part of save states.
+    pub fn set_breakpoints(&mut self, breakpoints: Vec<u16>) {
+        self.breakpoints = breakpoints;
+    }
+
+    pub fn breakpoints(&self) -> &[u16] {
+        &self.breakpoints
+    }
+
+    /// Take the addresses of the breakpoints, which were hit since the last call
+    pub fn take_breakpoint_hits(&mut self) -> Vec<u16> {
+        take(&mut self.breakpoint_hits)
+    }
+
+    fn check_breakpoints(&mut self) {
+        if self.is_at_instruction_boundary()
+            && !self.halt
+            && self.breakpoints.contains(&self.pc)
+            && self.breakpoint_hits.len() < MAX_RECORDED_EVENTS
+        {
+            self.breakpoint_hits.push(self.pc)
+        }
+    }
+
+    fn dispatch_cycle_stepped(&mut self) -> Cycles {
+        let factor = self.wait_factor();
+        let base = CYCLES[usize::from(self.peek(self.pc))];
+        if take(&mut self.instruction_pending) {
+            // only the additional cycles of taken branches are left
+            (self.dispatch_instruction() - base) * factor + 1
+        } else {
+            self.instruction_pending = true;
+            base * factor - 1
+        }
+    }
+
     pub fn run_cycle(&mut self) -> Option<StereoSample> {
         if self.cycles_ahead == 0 && !self.halt {
-            self.cycles_ahead = self.dispatch_instruction();
+            self.cycles_ahead = if self.cycle_stepped {
+                self.dispatch_cycle_stepped()
+            } else {
+                let factor = self.wait_factor();
+                self.dispatch_instruction() * factor
+            };
         }
         self.cycles_ahead = self.cycles_ahead.saturating_sub(1);
+        if !self.breakpoints.is_empty() {
+            self.check_breakpoints()
+        }
         self.dsp.run_one_step(&mut self.mem);
         let mut output = None;
         if self.dispatch_counter & 0xf == 0 {
@@ -0,0 +1,152 @@
+//! Snapshots of the SPC700 and DSP state for debugger user interfaces
+
+use super::{
+    disasm::{disassemble, Instruction},
+    regs, AdsrPeriod, Dsp, Spc700, MEMORY_SIZE, ROM,
+};
+
+/// The phase of the volume envelope of a voice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopePhase {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoiceDebugState {
+    /// The left and right volume (VOLL/VOLR)
+    pub volume: [i8; 2],
+    /// The 14-bit pitch, where `0x1000` plays the sample at 32kHz
+    pub pitch: u16,
+    /// The number of the sample in the sample directory (SRCN)
+    pub source: u8,
+    /// The address of the BRR block being decoded
+    pub brr_addr: u16,
+    /// The envelope is controlled by ADSR1/ADSR2 instead of GAIN
+    pub adsr_enabled: bool,
+    pub adsr: [u8; 2],
+    pub gain: u8,
+    pub phase: EnvelopePhase,
+    /// The current 11-bit envelope level
+    pub envelope: u16,
+    /// The last output sample of the voice (OUTX)
+    pub output: i8,
+    /// The pitch is modulated by the previous voice (PMON)
+    pub pitch_modulation: bool,
+    /// The voice plays noise instead of the sample (NON)
+    pub noise: bool,
+    /// The voice is written into the echo buffer (EON)
+    pub echo: bool,
+    /// The voice reached a BRR block with the end flag (ENDX)
+    pub ended: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DspDebugState {
+    /// The left and right main volume (MVOLL/MVOLR)
+    pub main_volume: [i8; 2],
+    /// The left and right echo volume (EVOLL/EVOLR)
+    pub echo_volume: [i8; 2],
+    /// The address of the sample directory (DIR)
+    pub dir_addr: u16,
+    /// The address of the echo buffer (ESA)
+    pub echo_addr: u16,
+    /// The echo delay in steps of 16ms (EDL)
+    pub echo_delay: u8,
+    /// The flags of FLG (reset, mute, echo write disable and noise clock)
+    pub flags: u8,
+    pub voices: [VoiceDebugState; 8],
+}
+
+impl Dsp {
+    /// Take a snapshot of the registers and the state of all voices
+    pub fn debug_state(&self) -> DspDebugState {
+        let reg = |addr: u8| self.mem[usize::from(addr)];
+        let bit = |addr: u8, i: usize| reg(addr) & (1 << i) > 0;
+        DspDebugState {
+            main_volume: [reg(regs::MVOLL) as i8, reg(regs::MVOLL | 0x10) as i8],
+            echo_volume: [reg(regs::EVOLL) as i8, reg(regs::EVOLL | 0x10) as i8],
+            dir_addr: u16::from(reg(regs::DIR)) << 8,
+            echo_addr: u16::from(reg(regs::ESA)) << 8,
+            echo_delay: reg(regs::EDL) & 15,
+            flags: reg(regs::FLG),
+            voices: core::array::from_fn(|i| {
+                let voice = &self.voices[i];
+                let vreg = |addr: u8| reg(((i as u8) << 4) | addr);
+                VoiceDebugState {
+                    volume: [vreg(regs::VOLL) as i8, vreg(regs::VOLL + 1) as i8],
+                    pitch: u16::from_le_bytes([vreg(regs::PITCHL), vreg(regs::PITCHH)]) & 0x3fff,
+                    source: vreg(regs::SRCN),
+                    brr_addr: voice.brr_base,
+                    adsr_enabled: vreg(regs::ADSR1) & 0x80 > 0,
+                    adsr: [vreg(regs::ADSR1), vreg(regs::ADSR2)],
+                    gain: vreg(regs::GAIN),
+                    phase: match voice.period {
+                        AdsrPeriod::Attack => EnvelopePhase::Attack,
+                        AdsrPeriod::Decay => EnvelopePhase::Decay,
+                        AdsrPeriod::Sustain => EnvelopePhase::Sustain,
+                        AdsrPeriod::Release => EnvelopePhase::Release,
+                    },
+                    envelope: voice.gain,
+                    output: vreg(regs::OUTX) as i8,
+                    pitch_modulation: bit(regs::PMON, i),
+                    noise: bit(regs::NON, i),
+                    echo: bit(regs::EON, i),
+                    ended: bit(regs::ENDX, i),
+                }
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpcDebugState {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub status: u8,
+    /// The address of the next instruction
+    pub pc: u16,
+    /// The SPC700 executed SLEEP or STOP
+    pub halted: bool,
+    /// The IPL ROM is mapped at `$ffc0-$ffff`
+    pub rom_mapped: bool,
+    /// The contents of the audio RAM, without the IPL ROM and the I/O registers
+    pub ram: Box<[u8; MEMORY_SIZE]>,
+}
+
+impl SpcDebugState {
+    /// Read a byte of the audio RAM or the IPL ROM, like the SPC700 sees it.
+    /// The I/O registers at `$f0-$ff` read as their RAM contents.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0xffc0..=0xffff if self.rom_mapped => ROM[usize::from(addr & 0x3f)],
+            addr => self.ram[usize::from(addr)],
+        }
+    }
+
+    /// Disassemble the instruction at `addr`, see [`Self::peek`]
+    pub fn disassemble(&self, addr: u16) -> Instruction {
+        disassemble(|addr| self.peek(addr), addr)
+    }
+}
+
+impl Spc700 {
+    /// Take a snapshot of the registers and the audio RAM
+    pub fn debug_state(&self) -> SpcDebugState {
+        SpcDebugState {
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            sp: self.sp,
+            status: self.status,
+            pc: self.pc,
+            halted: self.halt,
+            rom_mapped: self.is_rom_mapped(),
+            ram: Box::new(self.mem),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Disassembler of the SPC700 instruction set
+//!
+//! Direct page operands are printed without the page selected by the P flag,
+//! absolute operands as 16-bit addresses and branches with their target.
+
+use core::fmt;
+
+/// The templates of all opcodes. The operands are encoded as follows:
+///
+/// - `%n`: direct page address in byte `n`
+/// - `#n`: immediate value in byte `n`
+/// - `~n`: relative branch target in byte `n`
+/// - `!`: absolute address in bytes 1 and 2
+/// - `@`: 13-bit absolute address and bit number in bytes 1 and 2
+/// - `^`: address in the uppermost page in byte 1
+#[rustfmt::skip]
+static TEMPLATES: [&str; 256] = [
+    // 0x00
+    "nop", "tcall 0", "set1 %1.0", "bbs %1.0, ~2",
+    "or a, %1", "or a, !", "or a, (x)", "or a, [%1+x]",
+    "or a, #1", "or %2, %1", "or1 c, @", "asl %1",
+    "asl !", "push psw", "tset1 !", "brk",
+    // 0x10
+    "bpl ~1", "tcall 1", "clr1 %1.0", "bbc %1.0, ~2",
+    "or a, %1+x", "or a, !+x", "or a, !+y", "or a, [%1]+y",
+    "or %2, #1", "or (x), (y)", "decw %1", "asl %1+x",
+    "asl a", "dec x", "cmp x, !", "jmp [!+x]",
+    // 0x20
+    "clrp", "tcall 2", "set1 %1.1", "bbs %1.1, ~2",
+    "and a, %1", "and a, !", "and a, (x)", "and a, [%1+x]",
+    "and a, #1", "and %2, %1", "or1 c, /@", "rol %1",
+    "rol !", "push a", "cbne %1, ~2", "bra ~1",
+    // 0x30
+    "bmi ~1", "tcall 3", "clr1 %1.1", "bbc %1.1, ~2",
+    "and a, %1+x", "and a, !+x", "and a, !+y", "and a, [%1]+y",
+    "and %2, #1", "and (x), (y)", "incw %1", "rol %1+x",
+    "rol a", "inc x", "cmp x, %1", "call !",
+    // 0x40
+    "setp", "tcall 4", "set1 %1.2", "bbs %1.2, ~2",
+    "eor a, %1", "eor a, !", "eor a, (x)", "eor a, [%1+x]",
+    "eor a, #1", "eor %2, %1", "and1 c, @", "lsr %1",
+    "lsr !", "push x", "tclr1 !", "pcall ^",
+    // 0x50
+    "bvc ~1", "tcall 5", "clr1 %1.2", "bbc %1.2, ~2",
+    "eor a, %1+x", "eor a, !+x", "eor a, !+y", "eor a, [%1]+y",
+    "eor %2, #1", "eor (x), (y)", "cmpw ya, %1", "lsr %1+x",
+    "lsr a", "mov x, a", "cmp y, !", "jmp !",
+    // 0x60
+    "clrc", "tcall 6", "set1 %1.3", "bbs %1.3, ~2",
+    "cmp a, %1", "cmp a, !", "cmp a, (x)", "cmp a, [%1+x]",
+    "cmp a, #1", "cmp %2, %1", "and1 c, /@", "ror %1",
+    "ror !", "push y", "dbnz %1, ~2", "ret",
+    // 0x70
+    "bvs ~1", "tcall 7", "clr1 %1.3", "bbc %1.3, ~2",
+    "cmp a, %1+x", "cmp a, !+x", "cmp a, !+y", "cmp a, [%1]+y",
+    "cmp %2, #1", "cmp (x), (y)", "addw ya, %1", "ror %1+x",
+    "ror a", "mov a, x", "cmp y, %1", "reti",
+    // 0x80
+    "setc", "tcall 8", "set1 %1.4", "bbs %1.4, ~2",
+    "adc a, %1", "adc a, !", "adc a, (x)", "adc a, [%1+x]",
+    "adc a, #1", "adc %2, %1", "eor1 c, @", "dec %1",
+    "dec !", "mov y, #1", "pop psw", "mov %2, #1",
+    // 0x90
+    "bcc ~1", "tcall 9", "clr1 %1.4", "bbc %1.4, ~2",
+    "adc a, %1+x", "adc a, !+x", "adc a, !+y", "adc a, [%1]+y",
+    "adc %2, #1", "adc (x), (y)", "subw ya, %1", "dec %1+x",
+    "dec a", "mov x, sp", "div ya, x", "xcn a",
+    // 0xa0
+    "ei", "tcall 10", "set1 %1.5", "bbs %1.5, ~2",
+    "sbc a, %1", "sbc a, !", "sbc a, (x)", "sbc a, [%1+x]",
+    "sbc a, #1", "sbc %2, %1", "mov1 c, @", "inc %1",
+    "inc !", "cmp y, #1", "pop a", "mov (x)+, a",
+    // 0xb0
+    "bcs ~1", "tcall 11", "clr1 %1.5", "bbc %1.5, ~2",
+    "sbc a, %1+x", "sbc a, !+x", "sbc a, !+y", "sbc a, [%1]+y",
+    "sbc %2, #1", "sbc (x), (y)", "movw ya, %1", "inc %1+x",
+    "inc a", "mov sp, x", "das a", "mov a, (x)+",
+    // 0xc0
+    "di", "tcall 12", "set1 %1.6", "bbs %1.6, ~2",
+    "mov %1, a", "mov !, a", "mov (x), a", "mov [%1+x], a",
+    "cmp x, #1", "mov !, x", "mov1 @, c", "mov %1, y",
+    "mov !, y", "mov x, #1", "pop x", "mul ya",
+    // 0xd0
+    "bne ~1", "tcall 13", "clr1 %1.6", "bbc %1.6, ~2",
+    "mov %1+x, a", "mov !+x, a", "mov !+y, a", "mov [%1]+y, a",
+    "mov %1, x", "mov %1+y, x", "movw %1, ya", "mov %1+x, y",
+    "dec y", "mov a, y", "cbne %1+x, ~2", "daa a",
+    // 0xe0
+    "clrv", "tcall 14", "set1 %1.7", "bbs %1.7, ~2",
+    "mov a, %1", "mov a, !", "mov a, (x)", "mov a, [%1+x]",
+    "mov a, #1", "mov x, !", "not1 @", "mov y, %1",
+    "mov y, !", "notc", "pop y", "sleep",
+    // 0xf0
+    "beq ~1", "tcall 15", "clr1 %1.7", "bbc %1.7, ~2",
+    "mov a, %1+x", "mov a, !+x", "mov a, !+y", "mov a, [%1]+y",
+    "mov x, %1", "mov x, %1+y", "mov %2, %1", "mov y, %1+x",
+    "inc y", "mov y, a", "dbnz y, ~1", "stop",
+];
+
+/// The length in bytes of the instruction with the template `template`
+fn template_len(template: &str) -> u8 {
+    let mut chars = template.chars().peekable();
+    let mut len = 1;
+    while let Some(c) = chars.next() {
+        len = len.max(match c {
+            '%' | '#' | '~' => chars.peek().and_then(|c| c.to_digit(10)).unwrap_or(0) as u8 + 1,
+            '!' | '@' => 3,
+            '^' => 2,
+            _ => 1,
+        });
+    }
+    len
+}
+
+/// A single disassembled instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// The address of the opcode
+    pub addr: u16,
+    /// The opcode and its operands, only the first `len` bytes are used
+    pub bytes: [u8; 3],
+    pub len: u8,
+    /// The mnemonic with its operands, e.g. `mov a, [$12]+y`
+    pub text: String,
+}
+
+impl Instruction {
+    /// The address of the instruction following this one
+    pub const fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.len as u16)
+    }
+
+    /// The opcode and its operands
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.len)]
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}  ", self.addr)?;
+        for i in 0..3 {
+            match self.bytes().get(i) {
+                Some(byte) => write!(f, "{:02x} ", byte)?,
+                None => write!(f, "   ")?,
+            }
+        }
+        write!(f, " {}", self.text)
+    }
+}
+
+/// Disassemble the instruction at `addr`. The bytes are read with `read`,
+/// e.g. [`super::Spc700::peek`], which never has side effects.
+pub fn disassemble(read: impl Fn(u16) -> u8, addr: u16) -> Instruction {
+    let template = TEMPLATES[usize::from(read(addr))];
+    let len = template_len(template);
+    let mut bytes = [0; 3];
+    for (i, byte) in bytes.iter_mut().enumerate().take(usize::from(len)) {
+        *byte = read(addr.wrapping_add(i as u16));
+    }
+    let next_addr = addr.wrapping_add(len.into());
+    let word = u16::from_le_bytes([bytes[1], bytes[2]]);
+    let mut text = String::with_capacity(template.len() + 4);
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        let mut byte = || {
+            let i = chars.next().and_then(|c| c.to_digit(10)).unwrap_or(1);
+            bytes[i as usize]
+        };
+        match c {
+            '%' => text.push_str(&format!("${:02x}", byte())),
+            '#' => text.push_str(&format!("#${:02x}", byte())),
+            '~' => {
+                let target = next_addr.wrapping_add(byte() as i8 as u16);
+                text.push_str(&format!("${:04x}", target))
+            }
+            '!' => text.push_str(&format!("${:04x}", word)),
+            '@' => text.push_str(&format!("${:04x}.{}", word & 0x1fff, word >> 13)),
+            '^' => text.push_str(&format!("$ff{:02x}", bytes[1])),
+            c => text.push(c),
+        }
+    }
+    Instruction {
+        addr,
+        bytes,
+        len,
+        text,
+    }
+}
+
+#[cfg(test)]
+mod tests;
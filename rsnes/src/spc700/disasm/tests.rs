@@ -0,0 +1,72 @@
+use super::*;
+
+fn disasm(bytes: &[u8]) -> Instruction {
+    disassemble(|addr| bytes.get(usize::from(addr)).copied().unwrap_or(0), 0)
+}
+
+#[test]
+fn operands() {
+    for (bytes, text) in [
+        (&[0x00][..], "nop"),
+        (&[0xcd, 0xef], "mov x, #$ef"),
+        (&[0xf7, 0x12], "mov a, [$12]+y"),
+        (&[0x1f, 0x34, 0x12], "jmp [$1234+x]"),
+        (&[0x8f, 0x56, 0x34], "mov $34, #$56"),
+        (&[0xfa, 0x12, 0x34], "mov $34, $12"),
+        (&[0x29, 0x12, 0x34], "and $34, $12"),
+        (&[0x2a, 0x34, 0xb2], "or1 c, /$1234.5"),
+        (&[0xca, 0x34, 0x12], "mov1 $1234.0, c"),
+        (&[0x4f, 0x80], "pcall $ff80"),
+        (&[0xc1], "tcall 12"),
+        (&[0xe2, 0x12], "set1 $12.7"),
+        (&[0x9e], "div ya, x"),
+    ] {
+        let instr = disasm(bytes);
+        assert_eq!(instr.text, text);
+        assert_eq!(instr.bytes(), bytes);
+    }
+}
+
+#[test]
+fn branch_targets() {
+    // BRA and DBNZ Y are relative to the next instruction
+    assert_eq!(disasm(&[0x2f, 0xfe]).text, "bra $0000");
+    assert_eq!(disasm(&[0xfe, 0x10]).text, "dbnz y, $0012");
+    // the relative offset is the last byte of three byte branches
+    assert_eq!(disasm(&[0x03, 0x12, 0xfd]).text, "bbs $12.0, $0000");
+    assert_eq!(disasm(&[0xde, 0x12, 0x05]).text, "cbne $12+x, $0008");
+    let instr = disassemble(|_| 0xf0, 0xffff);
+    assert_eq!(instr.text, "beq $fff1");
+    assert_eq!(instr.next_addr(), 0x0001);
+}
+
+#[test]
+fn lengths() {
+    let len = |op: u8| disasm(&[op]).len;
+    for op in 0..=0xf {
+        // TCALL
+        assert_eq!(len(op << 4 | 1), 1);
+        // SET1, CLR1 and BBS, BBC
+        assert_eq!(len(op << 4 | 2), 2);
+        assert_eq!(len(op << 4 | 3), 3);
+    }
+    for (op, expected) in [
+        (0x05, 3),
+        (0x0d, 1),
+        (0x18, 3),
+        (0x2e, 3),
+        (0x3f, 3),
+        (0xcf, 1),
+    ] {
+        assert_eq!(len(op), expected, "opcode {:02x}", op);
+    }
+}
+
+#[test]
+fn display() {
+    assert_eq!(
+        disasm(&[0x8f, 0x56, 0x34]).to_string(),
+        "0000  8f 56 34  mov $34, #$56"
+    );
+    assert_eq!(disasm(&[0xbc]).to_string(), "0000  bc        inc a");
+}
@@ -0,0 +1,155 @@
+//! SPC sound file format
+//!
+//! A `.spc` file is a snapshot of the whole sound subsystem, which
+//! can be played back without emulating the rest of the console.
+//! Only the text variant of the ID666 tag is supported.
+//!
+//! # Literature
+//!
+//! - <https://wiki.superfamicom.org/spc-and-rsn-file-format>
+
+use crate::spc700::MEMORY_SIZE;
+
+pub const SIGNATURE: &[u8; 33] = b"SNES-SPC700 Sound File Data v0.30";
+/// Size of a complete file including the extra RAM
+pub const FILE_SIZE: usize = 0x10200;
+/// Files without the extra RAM are accepted, too
+pub const MINIMUM_SIZE: usize = 0x10180;
+
+pub const REGS_OFFSET: usize = 0x25;
+pub const RAM_OFFSET: usize = 0x100;
+pub const DSP_OFFSET: usize = RAM_OFFSET + MEMORY_SIZE;
+pub const EXTRA_RAM_OFFSET: usize = 0x101c0;
+
+const HAS_TAG: u8 = 26;
+const HAS_NO_TAG: u8 = 27;
+const MINOR_VERSION: u8 = 30;
+
+#[derive(Debug)]
+pub enum SpcFileError {
+    TooSmall(usize),
+    InvalidSignature,
+}
+
+impl std::fmt::Display for SpcFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::TooSmall(size) => write!(f, "file too small ({} < {})", size, MINIMUM_SIZE),
+            Self::InvalidSignature => write!(f, "not a SPC file (invalid signature)"),
+        }
+    }
+}
+
+/// The ID666 tag with meta information about the song
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Id666 {
+    pub song_title: String,
+    pub game_title: String,
+    pub dumper: String,
+    pub comments: String,
+    /// Date in the format `MM/DD/YYYY`
+    pub dump_date: String,
+    /// Seconds to play the song before fading out
+    pub play_seconds: u32,
+    /// Length of the fade out in milliseconds
+    pub fade_millis: u32,
+    pub artist: String,
+    /// Every set bit mutes the corresponding channel
+    pub muted_channels: u8,
+}
+
+/// Offsets and lengths of the text fields in the ID666 tag
+mod fields {
+    pub const SONG_TITLE: (usize, usize) = (0x2e, 32);
+    pub const GAME_TITLE: (usize, usize) = (0x4e, 32);
+    pub const DUMPER: (usize, usize) = (0x6e, 16);
+    pub const COMMENTS: (usize, usize) = (0x7e, 32);
+    pub const DUMP_DATE: (usize, usize) = (0x9e, 11);
+    pub const PLAY_SECONDS: (usize, usize) = (0xa9, 3);
+    pub const FADE_MILLIS: (usize, usize) = (0xac, 5);
+    pub const ARTIST: (usize, usize) = (0xb1, 32);
+    pub const MUTED_CHANNELS: usize = 0xd1;
+}
+
+fn read_text(data: &[u8], (offset, len): (usize, usize)) -> String {
+    let field = &data[offset..offset + len];
+    let end = field.iter().position(|&b| b == 0).unwrap_or(len);
+    String::from_utf8_lossy(&field[..end])
+        .trim_end()
+        .to_string()
+}
+
+fn read_number(data: &[u8], field: (usize, usize)) -> u32 {
+    read_text(data, field).trim().parse().unwrap_or(0)
+}
+
+fn write_text(data: &mut [u8], (offset, len): (usize, usize), text: &str) {
+    let bytes = text.as_bytes();
+    let n = bytes.len().min(len);
+    data[offset..offset + n].copy_from_slice(&bytes[..n]);
+    data[offset + n..offset + len].fill(0);
+}
+
+fn write_number(data: &mut [u8], field: (usize, usize), num: u32) {
+    let max = 10u32.pow(field.1 as u32) - 1;
+    write_text(data, field, &num.min(max).to_string())
+}
+
+impl Id666 {
+    pub(crate) fn read(data: &[u8]) -> Option<Self> {
+        if data[0x23] != HAS_TAG {
+            return None;
+        }
+        Some(Self {
+            song_title: read_text(data, fields::SONG_TITLE),
+            game_title: read_text(data, fields::GAME_TITLE),
+            dumper: read_text(data, fields::DUMPER),
+            comments: read_text(data, fields::COMMENTS),
+            dump_date: read_text(data, fields::DUMP_DATE),
+            play_seconds: read_number(data, fields::PLAY_SECONDS),
+            fade_millis: read_number(data, fields::FADE_MILLIS),
+            artist: read_text(data, fields::ARTIST),
+            muted_channels: data[fields::MUTED_CHANNELS],
+        })
+    }
+
+    pub(crate) fn write(&self, data: &mut [u8]) {
+        write_text(data, fields::SONG_TITLE, &self.song_title);
+        write_text(data, fields::GAME_TITLE, &self.game_title);
+        write_text(data, fields::DUMPER, &self.dumper);
+        write_text(data, fields::COMMENTS, &self.comments);
+        write_text(data, fields::DUMP_DATE, &self.dump_date);
+        write_number(data, fields::PLAY_SECONDS, self.play_seconds);
+        write_number(data, fields::FADE_MILLIS, self.fade_millis);
+        write_text(data, fields::ARTIST, &self.artist);
+        data[fields::MUTED_CHANNELS] = self.muted_channels;
+    }
+}
+
+/// Create an empty file image with the header filled in
+pub(crate) fn new_image(tag: Option<&Id666>) -> Vec<u8> {
+    let mut data = vec![0; FILE_SIZE];
+    data[..SIGNATURE.len()].copy_from_slice(SIGNATURE);
+    data[0x21] = 26;
+    data[0x22] = 26;
+    data[0x23] = if tag.is_some() { HAS_TAG } else { HAS_NO_TAG };
+    data[0x24] = MINOR_VERSION;
+    if let Some(tag) = tag {
+        tag.write(&mut data)
+    }
+    data
+}
+
+/// Check the size and the signature of a file image
+pub(crate) fn validate(data: &[u8]) -> Result<(), SpcFileError> {
+    if data.len() < MINIMUM_SIZE {
+        Err(SpcFileError::TooSmall(data.len()))
+    } else if !data.starts_with(&SIGNATURE[..27]) {
+        Err(SpcFileError::InvalidSignature)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
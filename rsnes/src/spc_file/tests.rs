@@ -0,0 +1,54 @@
+use super::*;
+use crate::spc700::Spc700;
+
+#[test]
+fn tag_roundtrip() {
+    let tag = Id666 {
+        song_title: "Title".to_string(),
+        game_title: "Game".to_string(),
+        dumper: "rsnes".to_string(),
+        comments: "a very long comment, which does not fit into the tag".to_string(),
+        dump_date: "01/02/2003".to_string(),
+        play_seconds: 120,
+        fade_millis: 10000,
+        artist: "Artist".to_string(),
+        muted_channels: 0x81,
+    };
+    let data = new_image(Some(&tag));
+    let read = Id666::read(&data).unwrap();
+    assert_eq!(read.comments, "a very long comment, which does");
+    assert_eq!(
+        read,
+        Id666 {
+            comments: read.comments.clone(),
+            ..tag
+        }
+    );
+    assert_eq!(Id666::read(&new_image(None)), None);
+}
+
+#[test]
+fn spc_roundtrip() {
+    let mut spc = Spc700::default();
+    for _ in 0..10000 {
+        spc.run_cycle();
+    }
+    let data = spc.export_spc(None);
+    assert_eq!(data.len(), FILE_SIZE);
+    let mut loaded = Spc700::default();
+    assert_eq!(loaded.load_spc(&data).unwrap(), None);
+    assert_eq!(loaded.export_spc(None), data);
+}
+
+#[test]
+fn invalid_files() {
+    let mut spc = Spc700::default();
+    assert!(matches!(
+        spc.load_spc(&[0; 16]),
+        Err(SpcFileError::TooSmall(16))
+    ));
+    assert!(matches!(
+        spc.load_spc(&[0; FILE_SIZE]),
+        Err(SpcFileError::InvalidSignature)
+    ));
+}
@@ -0,0 +1,89 @@
+//! Synchronization between the emulated audio output and the audio device
+//!
+//! The emulated sample rate and the sample rate of the audio device never
+//! match exactly, because they are driven by different clocks and the
+//! emulation is paced by the video output. Without any correction the output
+//! buffer slowly runs empty (clicks) or overflows (growing latency).
+//!
+//! Dynamic rate control resamples the output with a ratio slightly above or
+//! below one, depending on the fill level of the output buffer, so it stays
+//! half full. The maximum deviation is small enough to not be audible.
+//!
+//! # Literature
+//!
+//! - <https://github.com/libretro/docs/blob/master/archive/ratecontrol.pdf>
+
+use crate::{backend::AudioBackend, spc700::StereoSample};
+
+/// Default maximum deviation of the resampling ratio from one
+pub const DEFAULT_MAX_DELTA: f32 = 0.005;
+
+/// Audio backend wrapper, which resamples the output to keep
+/// the output buffer of the inner backend half full.
+#[derive(Debug, Clone)]
+pub struct RateControl<B: AudioBackend> {
+    pub backend: B,
+    max_delta: f32,
+    ratio: f32,
+    /// Position of the next output sample between `prev` and the next input sample
+    pos: f32,
+    prev: StereoSample,
+}
+
+impl<B: AudioBackend> RateControl<B> {
+    pub fn new(backend: B) -> Self {
+        Self::with_max_delta(backend, DEFAULT_MAX_DELTA)
+    }
+
+    pub fn with_max_delta(backend: B, max_delta: f32) -> Self {
+        Self {
+            backend,
+            max_delta,
+            ratio: 1.0,
+            pos: 0.0,
+            prev: StereoSample::<i16>::new2(0),
+        }
+    }
+
+    /// The current ratio of output samples per input sample
+    pub fn ratio(&self) -> f32 {
+        self.ratio
+    }
+
+    /// Calculate the resampling ratio for a given fill level
+    pub fn ratio_for_fill_level(&self, fill: f32) -> f32 {
+        1.0 + self.max_delta * (1.0 - 2.0 * fill.clamp(0.0, 1.0))
+    }
+}
+
+impl<B: AudioBackend> AudioBackend for RateControl<B> {
+    fn push_sample(&mut self, sample: StereoSample) {
+        self.ratio = match self.backend.fill_level() {
+            Some(fill) => self.ratio_for_fill_level(fill),
+            None => 1.0,
+        };
+        let step = 1.0 / self.ratio;
+        while self.pos < 1.0 {
+            let fract = (self.pos * 256.0) as i32;
+            let interpolated = self.prev.zip_with(sample, |a, b| {
+                let (a, b) = (i32::from(a), i32::from(b));
+                (a + (((b - a) * fract) >> 8)) as i16
+            });
+            self.backend.push_sample(interpolated);
+            self.pos += step;
+        }
+        self.pos -= 1.0;
+        self.prev = sample;
+    }
+
+    fn fill_level(&self) -> Option<f32> {
+        self.backend.fill_level()
+    }
+
+    fn push_voice_samples(&mut self, samples: [i16; 8]) {
+        self.backend.push_voice_samples(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests;
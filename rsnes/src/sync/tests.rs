@@ -0,0 +1,45 @@
+use super::*;
+
+#[derive(Debug, Default)]
+struct Counter {
+    samples: Vec<StereoSample>,
+    fill: Option<f32>,
+}
+
+impl AudioBackend for Counter {
+    fn push_sample(&mut self, sample: StereoSample) {
+        self.samples.push(sample)
+    }
+
+    fn fill_level(&self) -> Option<f32> {
+        self.fill
+    }
+}
+
+fn push_samples(fill: Option<f32>, n: i16) -> Vec<StereoSample> {
+    let mut rc = RateControl::new(Counter {
+        fill,
+        ..Default::default()
+    });
+    for i in 1..=n {
+        rc.push_sample(StereoSample::<i16>::new2(i));
+    }
+    rc.backend.samples
+}
+
+#[test]
+fn unknown_fill_level() {
+    let samples = push_samples(None, 1000);
+    assert_eq!(samples.len(), 1000);
+    assert!(samples[1..]
+        .iter()
+        .enumerate()
+        .all(|(i, s)| *s == StereoSample::<i16>::new2(i as i16 + 1)));
+}
+
+#[test]
+fn adjust_ratio() {
+    assert_eq!(push_samples(Some(0.5), 1000).len(), 1000);
+    assert_eq!(push_samples(Some(0.0), 1000).len(), 1005);
+    assert_eq!(push_samples(Some(1.0), 1000).len(), 995);
+}
@@ -0,0 +1,52 @@
+//! Fixtures shared by the unit tests of the modules
+
+use crate::{
+    backend::{AudioDummy, NullFrameBuffer},
+    cartridge::Cartridge,
+    device::{Device, DeviceOptions, Region},
+};
+
+pub(crate) type TestDevice = Device<AudioDummy, NullFrameBuffer>;
+
+/// Create a NTSC console without video and audio output
+pub(crate) fn new_device() -> Box<TestDevice> {
+    new_device_with_options(DeviceOptions::default())
+}
+
+/// Like [`new_device`] with the given `options`
+pub(crate) fn new_device_with_options(options: DeviceOptions) -> Box<TestDevice> {
+    Box::new(Device::new_with_options(
+        AudioDummy,
+        NullFrameBuffer,
+        Region::Ntsc,
+        false,
+        options,
+    ))
+}
+
+/// Create a LoROM cartridge, whose program starts at `$00:8000` with `code`
+pub(crate) fn new_cartridge(code: &[u8], fast_code: &[u8]) -> Cartridge {
+    new_cartridge_with_vectors(code, fast_code, [0xeaea; 2])
+}
+
+/// Like [`new_cartridge`] with the emulation mode NMI and IRQ/BRK vectors
+pub(crate) fn new_cartridge_with_vectors(
+    code: &[u8],
+    fast_code: &[u8],
+    [nmi, irq]: [u16; 2],
+) -> Cartridge {
+    let mut rom = vec![0xea; 0x8000];
+    let header = &mut rom[0x7fc0..];
+    header[..21].copy_from_slice(b"DEVICE TEST          ");
+    header[21] = 0x20;
+    // ROM only, without a coprocessor
+    header[22] = 0;
+    header[23] = 5;
+    header[25] = 1;
+    header[58..60].copy_from_slice(&nmi.to_le_bytes());
+    header[60..62].copy_from_slice(&0x8000u16.to_le_bytes());
+    header[62..64].copy_from_slice(&irq.to_le_bytes());
+    rom[..code.len()].copy_from_slice(code);
+    rom[0x1000..0x1000 + fast_code.len()].copy_from_slice(fast_code);
+    Cartridge::from_bytes(&rom).unwrap()
+}
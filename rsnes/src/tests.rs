@@ -0,0 +1,74 @@
+/// A struct, which had a field removed and another one added
+mod old {
+    #[derive(save_state_macro::InSaveState)]
+    pub struct State {
+        pub removed: u32,
+        pub kept: [u16; 2],
+    }
+}
+
+#[derive(save_state_macro::InSaveState)]
+struct State {
+    kept: [u16; 2],
+    added: Option<u8>,
+    #[save_state(default)]
+    added_default: u16,
+    #[save_state(default = [7; 3])]
+    added_custom: [u8; 3],
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    excluded: bool,
+}
+
+#[test]
+fn save_state_field_changes() {
+    use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
+    let old = old::State {
+        removed: 0xdeadbeef,
+        kept: [1, 2],
+    };
+    let mut ser = SaveStateSerializer::new();
+    old.serialize(&mut ser);
+    let mut state = State {
+        kept: [0; 2],
+        added: Some(3),
+        added_default: 5,
+        added_custom: [6; 3],
+        excluded: true,
+    };
+    state.deserialize(&mut SaveStateDeserializer::new(ser.data()));
+    assert_eq!(state.kept, [1, 2]);
+    assert_eq!(state.added, Some(3));
+    assert_eq!(state.added_default, 0);
+    assert_eq!(state.added_custom, [7; 3]);
+    assert!(state.excluded);
+
+    let mut ser = SaveStateSerializer::new();
+    state.serialize(&mut ser);
+    let mut old = old::State {
+        removed: 4,
+        kept: [0; 2],
+    };
+    old.deserialize(&mut SaveStateDeserializer::new(ser.data()));
+    assert_eq!((old.removed, old.kept), (4, [1, 2]));
+
+    // fields with a default are only reset, if they are missing
+    let new = State {
+        kept: [8; 2],
+        added: None,
+        added_default: 9,
+        added_custom: [10; 3],
+        excluded: false,
+    };
+    let mut ser = SaveStateSerializer::new();
+    new.serialize(&mut ser);
+    state.deserialize(&mut SaveStateDeserializer::new(ser.data()));
+    assert_eq!(
+        (
+            state.kept,
+            state.added,
+            state.added_default,
+            state.added_custom
+        ),
+        ([8; 2], None, 9, [10; 3])
+    );
+}
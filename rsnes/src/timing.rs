@@ -1,14 +1,23 @@
 //! Timing control implementation
 //!
+//! All components are driven by the master clock of the console, whose
+//! cycles are counted by [`Device::master_cycles`]. [`Device::run_cycle`]
+//! advances the components, and the events, which happen at fixed positions
+//! of a scanline (e.g. the timer IRQ or the HDMA), are handled in the cycles
+//! they fall into. [`Device::upcoming_events`] collects these events in a
+//! [`Scheduler`], so that cycles without events can be skipped at once.
+//!
 //! # Literature
 //!
 //! - <https://wiki.superfamicom.org/timing>
 
 use crate::{
-    cpu::Status,
+    cpu::{CpuStep, Interrupt},
     device::{Addr24, Device},
 };
+use std::{cmp::Reverse, collections::BinaryHeap};
 
+/// A number of master cycles or cycles of a coprocessor
 pub type Cycles = u32;
 
 // The SNES master clock runs at ca. (945/44) MHz which is ca. 21_477kHz;
@@ -21,41 +30,151 @@ pub(crate) const APU_CPU_TIMING_PROPORTION_PAL: (Cycles, Cycles) = (665, 32);
 pub(crate) const NECDSP_CPU_TIMING_PROPORTION_NTSC: (Cycles, Cycles) = (118125, 45056);
 pub(crate) const NECDSP_CPU_TIMING_PROPORTION_PAL: (Cycles, Cycles) = (40591, 15625);
 
+/// Master cycles run at once while single stepping the CPU
+const STEP_CYCLES: u16 = 2;
+
+/// Master cycles after the start of dot HTIME until a H-IRQ gets triggered
+const IRQ_H_DELAY_CYCLES: u16 = 14;
+/// Master cycles after the start of a scanline until a V-IRQ gets triggered
+const IRQ_V_DELAY_CYCLES: u16 = 10;
+/// Master cycles after the start of a scanline until the work RAM refresh begins
+const WRAM_REFRESH_START: u16 = 536;
+/// Master cycles the CPU and DMA are paused while the work RAM gets refreshed
+const WRAM_REFRESH_CYCLES: u16 = 40;
+
+/// The first visible dot of a scanline, as seen by a light gun
+const LIGHTGUN_H_OFFSET: u16 = 22;
+
+/// An event of the console, which happens at a certain master cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Event {
+    /// The PPU draws the current scanline shortly before its end
+    DrawScanline,
+    /// The HDMA channels transfer the data of the next scanline
+    Hdma,
+    /// The H/V timer ($4207-$420A) raises the IRQ line
+    TimerIrq,
+    /// A light gun sees the beam and latches the H/V counters
+    LightGunLatch,
+    /// The work RAM refresh pauses the main CPU and the DMA
+    WramRefresh,
+    /// The audio processor catches up with the main CPU,
+    /// see [`crate::device::DeviceOptions::apu_sync_cycles`]
+    ApuSync,
+    /// The last master cycles of the scanline are run, after which the next scanline starts
+    ScanlineEnd,
+    /// The vertical blanking period starts with the next scanline,
+    /// which requests the NMI if it is enabled
+    VBlank,
+}
+
+/// An [`Event`] together with the master cycle it happens at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ScheduledEvent {
+    /// The master cycle as counted by [`Device::master_cycles`]
+    pub master_cycle: u64,
+    pub event: Event,
+}
+
+/// A priority queue of events, which returns the earliest event first.
+/// Events at the same master cycle are ordered like the variants of [`Event`].
+#[derive(Debug, Clone, Default)]
+pub struct Scheduler {
+    queue: BinaryHeap<Reverse<ScheduledEvent>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn schedule(&mut self, master_cycle: u64, event: Event) {
+        self.queue.push(Reverse(ScheduledEvent {
+            master_cycle,
+            event,
+        }))
+    }
+
+    /// The earliest event
+    pub fn peek(&self) -> Option<ScheduledEvent> {
+        self.queue.peek().map(|event| event.0)
+    }
+
+    /// Remove and return the earliest event
+    pub fn pop(&mut self) -> Option<ScheduledEvent> {
+        self.queue.pop().map(|event| event.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear()
+    }
+
+    /// Return all events in the order they happen
+    pub fn into_sorted_vec(self) -> Vec<ScheduledEvent> {
+        let mut events: Vec<_> = self.queue.into_iter().map(|event| event.0).collect();
+        events.sort_unstable();
+        events
+    }
+}
+
 impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B, FB> {
+    /// Run the device for `N` master cycles.
+    ///
+    /// While the main CPU idles after WAI or STP, more cycles may be run at once,
+    /// up to the next event of [`Self::upcoming_events`].
+    /// A call never runs past the end of a scanline.
     pub fn run_cycle<const N: u16>(&mut self) {
+        self.skip_idle_cycles::<N>();
         self.smp.tick(N);
+        if self.smp.is_behind(self.options().apu_sync_cycles) {
+            self.smp.refresh();
+        }
         self.cartridge.as_mut().unwrap().tick(N.into());
         let vend = self.ppu.vend();
-        if self.is_auto_joypad() && self.new_scanline && self.ppu.get_pos().y == vend + 2 {
-            self.controllers.auto_joypad_timer = 4224;
-            self.controllers.auto_joypad()
+        // the automatic joypad read starts at the beginning of the vertical blank
+        if self.is_auto_joypad() && self.new_scanline && self.ppu.get_pos().y == vend {
+            self.controllers.start_auto_joypad()
         }
-        self.controllers.auto_joypad_timer -= self.controllers.auto_joypad_timer.min(N);
+        self.controllers.tick_auto_joypad(N);
         // > The CPU is paused for 40 cycles beginning about 536 cycles
         // > after the start of each scanline
         // source: <https://wiki.superfamicom.org/timing>
-        if self.ppu.is_cpu_active() && self.cpu.active {
+        //
+        // The CPU and DMA share the bus, so a HDMA stalls both the CPU and
+        // a running DMA, while a DMA stalls the CPU until it is finished.
+        if !self.is_refreshing_wram() && self.cpu.active {
             if self.dma.hdma_ahead_cycles > 0 {
                 self.dma.hdma_ahead_cycles -= i32::from(N);
-            } else if self.dma.is_dma_running() {
-                if self.dma.ahead_cycles > 0 {
+            } else {
+                // the next unit is transferred as soon as the previous one is
+                // done, so that no cycles are lost between the units
+                while self.dma.is_dma_running() && self.dma.ahead_cycles <= 0 {
+                    self.do_dma_first_channel()
+                }
+                if self.dma.is_dma_running() {
                     self.dma.ahead_cycles -= i32::from(N)
                 } else {
-                    self.do_dma_first_channel()
+                    self.run_cpu::<N>();
                 }
-            } else {
-                self.run_cpu::<N>();
             }
         }
         if self.cartridge.as_ref().unwrap().has_sa1() {
             self.with_sa1_cpu().run_cpu::<N>();
         }
         if self.new_frame {
-            self.dma.hdma_ahead_cycles = self.reset_hdma();
+            self.dma.hdma_ahead_cycles += self.reset_hdma();
         }
         if self.do_hdma && !self.ppu.is_in_vblank() && self.ppu.get_pos().x >= 1024 {
             self.do_hdma = false;
-            self.dma.hdma_ahead_cycles = self.do_hdma();
+            self.dma.hdma_ahead_cycles += self.do_hdma();
         }
         let vblanked = self.new_scanline && self.ppu.get_pos().y == vend;
         if vblanked {
@@ -68,23 +187,179 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             self.scanline_drawn = true;
             self.ppu.draw_scanline();
         }
-        let h_irq_enabled = self.cpu.nmitimen & 0x10 > 0;
-        let v_irq_enabled = self.cpu.nmitimen & 0x20 > 0;
-        self.shall_irq = self.shall_irq
-            || ((h_irq_enabled || v_irq_enabled)
-                && (!h_irq_enabled
-                    || ((self.ppu.get_pos().x as i16 - N as i16) >> 2 < self.irq_time_h as i16
-                        && self.ppu.get_pos().x >> 2 >= self.irq_time_h))
-                && (!v_irq_enabled || self.ppu.get_pos().y == self.irq_time_v)
-                && (h_irq_enabled || !v_irq_enabled || self.new_scanline));
+        self.update_irq::<N>();
+        self.update_lightgun::<N>();
         self.nmi_vblank_bit
             .set(self.nmi_vblank_bit.get() || vblanked);
-        self.shall_nmi = self.cpu.nmitimen & 0x80 > 0 && (self.shall_nmi || vblanked);
+        // an NMI, which hijacked BRK or COP, was serviced already
+        let nmi_edge = vblanked && !core::mem::take(&mut self.nmi_hijacked);
+        self.shall_nmi = self.cpu.nmitimen & 0x80 > 0 && (self.shall_nmi || nmi_edge);
         self.update_counters::<N>();
     }
 
+    /// Fast-forward the device while the main CPU waits for an interrupt,
+    /// spins in an idle loop (see [`crate::idle_loop`]) or is stopped.
+    ///
+    /// The cycles until the next event of [`Self::upcoming_events`] are
+    /// skipped in multiples of `N`, so that the following cycles of
+    /// [`Self::run_cycle`] handle the event at the same position as without
+    /// skipping. The NMI is requested at the start of a scanline, which is
+    /// never skipped.
+    fn skip_idle_cycles<const N: u16>(&mut self) {
+        let irq = self.is_irq_line_asserted();
+        let idles = !self.cpu.active
+            || self.cpu.wait_mode && !self.shall_nmi && !irq
+            || self.idle_loop.detected && !self.shall_nmi && (!irq || self.cpu.irq_disable_polled);
+        if !idles
+            || self.new_scanline
+            || self.dma.hdma_ahead_cycles > 0
+            || self.dma.is_dma_running()
+            || self.cartridge.as_ref().unwrap().has_sa1()
+        {
+            return;
+        }
+        let Some(next) = self.upcoming_events::<N>().peek() else {
+            return;
+        };
+        let cycles = ((next.master_cycle - self.master_cycles) as u16 / N) * N;
+        if cycles == 0 {
+            return;
+        }
+        self.smp.tick(cycles);
+        if self.smp.is_behind(self.options().apu_sync_cycles) {
+            self.smp.refresh();
+        }
+        self.cartridge.as_mut().unwrap().tick(cycles.into());
+        self.controllers.tick_auto_joypad(cycles);
+        self.math_registers.tick(cycles);
+        self.ppu.mut_pos().x += cycles;
+        self.master_cycles += u64::from(cycles);
+    }
+
+    /// The master cycles counted since the power-on
+    pub const fn master_cycles(&self) -> u64 {
+        self.master_cycles
+    }
+
+    /// Schedule the events of the rest of the current scanline, which get
+    /// handled by [`Self::run_cycle`] with steps of `N` master cycles,
+    /// and the next synchronization of the audio processor.
+    ///
+    /// The events depend on the registers, so the schedule gets outdated,
+    /// when the CPU writes to them.
+    pub fn upcoming_events<const N: u16>(&self) -> Scheduler {
+        let pos = self.ppu.get_pos();
+        let line_length = self.ppu.get_scanline_cycles();
+        let vend = self.ppu.vend();
+        let mut scheduler = Scheduler::new();
+        let mut schedule = |x: Option<u16>, event| {
+            if let Some(x) = x.filter(|&x| x >= pos.x) {
+                scheduler.schedule(self.master_cycles + u64::from(x - pos.x), event)
+            }
+        };
+        schedule(
+            (!self.scanline_drawn && pos.y + 1 < vend)
+                .then_some(line_length - crate::ppu::RAY_AHEAD_CYCLES),
+            Event::DrawScanline,
+        );
+        schedule(
+            (self.do_hdma && !self.ppu.is_in_vblank()).then_some(1024),
+            Event::Hdma,
+        );
+        schedule(
+            self.timer_irq_cycle().filter(|_| self.is_timer_irq_line()),
+            Event::TimerIrq,
+        );
+        schedule(self.lightgun_cycle(), Event::LightGunLatch);
+        schedule(Some(WRAM_REFRESH_START), Event::WramRefresh);
+        schedule(Some((line_length - N).max(pos.x)), Event::ScanlineEnd);
+        schedule(
+            (pos.y + 1 == vend).then_some(line_length - N),
+            Event::VBlank,
+        );
+        let apu_sync = self.smp.cycles_until_behind(self.options().apu_sync_cycles);
+        scheduler.schedule(self.master_cycles + u64::from(apu_sync), Event::ApuSync);
+        scheduler
+    }
+
+    /// The position in the scanline, at which the H/V timer IRQ is triggered,
+    /// if the timer is enabled
+    fn timer_irq_cycle(&self) -> Option<u16> {
+        // > The IRQ is triggered at H=HTIME+~3.5 [...] for V-IRQs at H=~2.5
+        // source: FullSNES
+        if self.cpu.nmitimen & 0x10 > 0 {
+            Some((self.irq_time_h << 2) + IRQ_H_DELAY_CYCLES)
+        } else {
+            (self.cpu.nmitimen & 0x20 > 0).then_some(IRQ_V_DELAY_CYCLES)
+        }
+    }
+
+    /// Test if the V condition of the timer IRQ is met by the current scanline
+    fn is_timer_irq_line(&self) -> bool {
+        self.cpu.nmitimen & 0x20 == 0 || self.ppu.get_pos().y == self.irq_time_v
+    }
+
+    /// The position in the current scanline, at which a light gun sees the beam
+    fn lightgun_cycle(&self) -> Option<u16> {
+        self.controllers
+            .lightgun_position()
+            .filter(|&[_, y]| self.ppu.get_pos().y == y + 1)
+            .map(|[x, _]| (x + LIGHTGUN_H_OFFSET) << 2)
+    }
+
+    /// Check if the work RAM refresh pauses the CPU and DMA at the current position
+    fn is_refreshing_wram(&self) -> bool {
+        let start = WRAM_REFRESH_START;
+        (start..start + WRAM_REFRESH_CYCLES).contains(&self.ppu.get_pos().x)
+    }
+
+    /// Check if the H/V timer IRQ condition ($4207-$420A) is met in the next `N` cycles
+    /// and set the TIMEUP flag accordingly
+    fn update_irq<const N: u16>(&mut self) {
+        if self.timer_irq_within(N.into()) {
+            self.cpu.irq_bit = 0x80;
+        }
+    }
+
+    /// Test if the H/V timer IRQ condition is met in the next `cycles` master cycles
+    /// of the current scanline
+    pub(crate) fn timer_irq_within(&self, cycles: Cycles) -> bool {
+        let Some(h_cycle) = self.timer_irq_cycle() else {
+            return false;
+        };
+        let x = Cycles::from(self.ppu.get_pos().x);
+        (x..x + cycles).contains(&Cycles::from(h_cycle)) && self.is_timer_irq_line()
+    }
+
+    /// Test if the NMI at the start of the vertical blanking period
+    /// gets requested in the current or the next `cycles` master cycles
+    pub(crate) fn nmi_within(&self, cycles: Cycles) -> bool {
+        let pos = self.ppu.get_pos();
+        let vend = self.ppu.vend();
+        let now = self.new_scanline && pos.y == vend;
+        let ahead = pos.y + 1 == vend
+            && Cycles::from(pos.x) + cycles >= self.ppu.get_scanline_cycles().into();
+        self.cpu.nmitimen & 0x80 > 0 && (now || ahead)
+    }
+
+    /// Latch the H/V counters if a light gun sees the CRT beam in the next `N` cycles
+    fn update_lightgun<const N: u16>(&mut self) {
+        if let Some(h_cycle) = self.lightgun_cycle() {
+            let x = self.ppu.get_pos().x;
+            if (x..x + N).contains(&h_cycle) {
+                self.ppu.latch()
+            }
+        }
+    }
+
+    /// Test if the IRQ line of the main CPU is asserted
+    pub fn is_irq_line_asserted(&self) -> bool {
+        self.cpu.irq_bit > 0 || self.get_irq_pin()
+    }
+
     pub fn update_counters<const N: u16>(&mut self) {
         self.ppu.mut_pos().x += N;
+        self.master_cycles += u64::from(N);
         self.math_registers.tick(N);
         self.new_scanline = false;
         self.new_frame = false;
@@ -103,14 +378,15 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             if self.ppu.get_pos().y >= scanline_count {
                 self.ppu.mut_pos().y -= scanline_count;
                 self.new_frame = true;
+                #[cfg(feature = "trace")]
+                self.trace.next_frame();
                 self.nmi_vblank_bit.set(false);
                 self.ppu.end_vblank();
                 self.smp.refresh();
                 self.cartridge.as_mut().unwrap().refresh_coprocessors();
-            } else if self.smp.is_threaded() {
-                // if the S-SMP is threaded, refresh it every scanline
-                self.smp.refresh();
+                self.apply_ram_cheats();
             }
+            self.call_scanline_hook();
         }
     }
 
@@ -122,28 +398,144 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             // > in case of IRQs this works even if IRQs are disabled (via I=1).
             // source: FullSNES
             if self.cpu.wait_mode {
-                self.cpu.wait_mode = !self.shall_nmi && !self.shall_irq;
-                self.cpu_ahead_cycles += 1;
+                self.cpu.wait_mode = !self.shall_nmi && !self.is_irq_line_asserted();
+                // the waiting CPU doesn't fall behind
+                self.cpu_ahead_cycles = 0;
                 return;
             }
-            self.memory_cycles = 0;
-            let cycles = (if self.shall_nmi {
-                self.shall_nmi = false;
-                self.with_main_cpu().nmi()
-            } else if (self.shall_irq || self.get_irq_pin())
-                && !self.cpu.regs.status.has(Status::IRQ_DISABLE)
-            {
-                self.shall_irq = false;
-                self.with_main_cpu().irq()
-            } else {
-                // > Internal operation CPU cycles always take 6 master cycles
-                // source: <https://wiki.superfamicom.org/memory-mapping>
-                self.with_main_cpu().dispatch_instruction() * 6
-            }) + self.memory_cycles;
-            self.cpu_ahead_cycles += cycles as i32;
+            let step = self.execute_cpu_step();
+            if !self.cpu.active {
+                self.notify("CPU stopped")
+            }
+            self.cpu_ahead_cycles += step.cycles as i32;
+            self.cpu.last_step = Some(step);
+        }
+    }
+
+    /// Poll the interrupt lines and either enter the interrupt handler
+    /// or execute the next instruction.
+    ///
+    /// The interrupt lines are only polled between two instructions,
+    /// so an interrupt request during an instruction is serviced
+    /// after the instruction completed.
+    fn execute_cpu_step(&mut self) -> CpuStep {
+        self.memory_cycles = 0;
+        let (cycles, interrupt) = if self.shall_nmi {
+            self.shall_nmi = false;
+            #[cfg(feature = "trace")]
+            self.trace_event(crate::trace::EventKind::Nmi);
+            (self.with_main_cpu().nmi(), Some(Interrupt::Nmi))
+        } else if self.is_irq_line_asserted() && !self.cpu.irq_disable_polled {
+            #[cfg(feature = "trace")]
+            self.trace_event(crate::trace::EventKind::Irq);
+            (self.with_main_cpu().irq(), Some(Interrupt::Irq))
+        } else {
+            // > Internal operation CPU cycles always take 6 master cycles
+            // source: <https://wiki.superfamicom.org/memory-mapping>
+            let pc = self.cpu.regs.pc;
+            if let Some(heatmap) = &mut self.heatmap {
+                heatmap.record_execute(pc)
+            }
+            let cycles = self.with_main_cpu().dispatch_instruction() * 6;
+            if self.options().skip_idle_loops {
+                self.detect_idle_loop(pc);
+            }
+            (cycles, None)
+        };
+        if interrupt.is_some() {
+            // the interrupt handler may end the loop
+            self.idle_loop.reset();
         }
+        self.check_breakpoints();
+        CpuStep {
+            cycles: cycles + self.memory_cycles,
+            interrupt,
+        }
+    }
+
+    /// Run the whole device until the main CPU executed exactly one instruction.
+    ///
+    /// If an interrupt is pending, the interrupt handler is entered and its
+    /// first instruction is executed. The returned step contains the master
+    /// cycles of the interrupt entry and the instruction.
+    /// `None` is returned if the CPU is stopped or waits longer than
+    /// two frames for an interrupt.
+    pub fn step_cpu_instruction(&mut self) -> Option<CpuStep> {
+        let mut result: Option<CpuStep> = None;
+        let mut frames = 0;
+        loop {
+            if !self.cpu.active {
+                return None;
+            }
+            self.cpu.last_step = None;
+            while self.cpu.last_step.is_none() {
+                self.run_cycle::<STEP_CYCLES>();
+                if self.new_frame {
+                    frames += 1;
+                    if frames > 2 || !self.cpu.active {
+                        return None;
+                    }
+                }
+            }
+            let step = self.cpu.last_step.take().unwrap();
+            let done = step.interrupt.is_none();
+            result = Some(match result {
+                Some(prev) => CpuStep {
+                    cycles: prev.cycles + step.cycles,
+                    interrupt: prev.interrupt,
+                },
+                None => step,
+            });
+            if done {
+                return result;
+            }
+        }
+    }
+
+    /// Run the SPC700 until it executed exactly one instruction, while the
+    /// main CPU and the rest of the device are held.
+    /// Returns the SPC700 cycles of the instruction, see [`crate::smp::Smp::step_instruction`].
+    pub fn step_apu_instruction(&mut self) -> Cycles {
+        self.smp.step_instruction()
+    }
+
+    /// Test if the device is at the boundary between two frames.
+    ///
+    /// This is the only point at which save states are taken. The
+    /// auto-joypad read is long over and the frontend didn't give the input
+    /// of the next frame yet, so no controller is in the middle of shifting
+    /// out its buttons and a loaded state continues with the input the
+    /// frontend gives for its first frame.
+    pub const fn is_at_frame_boundary(&self) -> bool {
+        self.new_frame
+    }
+
+    /// Serialize the state of the device at a frame boundary.
+    ///
+    /// If the device is in the middle of a frame, the frame is emulated to its
+    /// end before the state is taken. See [`Self::is_at_frame_boundary`].
+    pub fn serialize_at_frame_boundary(&mut self) -> Vec<u8> {
+        while !self.new_frame {
+            self.run_cycle::<STEP_CYCLES>();
+        }
+        let mut state = Vec::new();
+        save_state::serialize_in_place(self, &mut state);
+        state
+    }
+
+    /// Master cycles of accessing the bytes of `D` at `addr`
+    /// in addition to the 6 master cycles of an internal operation
+    pub(crate) fn get_access_cycles<D: crate::device::Data>(&self, addr: Addr24) -> Cycles {
+        (0..core::mem::size_of::<D::Arr>() as u16)
+            .map(|i| self.get_memory_cycle(Addr24::new(addr.bank, addr.addr.wrapping_add(i))) - 6)
+            .sum()
     }
 
+    /// Master cycles of accessing a byte at `addr`, which depend on the memory region.
+    ///
+    /// The ROM at banks `$80-$FF` is accessed in 6 master cycles instead of 8,
+    /// if FastROM is selected with MEMSEL (`$420D`).
+    /// source: <https://wiki.superfamicom.org/memory-mapping>
     pub fn get_memory_cycle(&self, addr: Addr24) -> Cycles {
         #[repr(u8)]
         enum Speed {
@@ -0,0 +1,170 @@
+//! Tracing of hardware events for debugger timelines
+//!
+//! The events are recorded into a ring buffer of a fixed capacity,
+//! so that the oldest events are dropped when it is full. Only the
+//! events of the enabled [`Category`]s are recorded, which are none
+//! by default.
+
+use std::collections::VecDeque;
+
+/// The default amount of events kept in the ring buffer
+pub const DEFAULT_CAPACITY: usize = 0x10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Category {
+    /// NMIs and IRQs entered by the main CPU
+    Interrupt = 0,
+    /// General purpose DMA transfers
+    Dma = 1,
+    /// Writes to the PPU registers `$2100-$2133`
+    PpuRegister = 2,
+    /// Reads and writes of the APU ports `$2140-$2143`
+    ApuPort = 3,
+}
+
+impl Category {
+    pub const ALL: [Self; 4] = [Self::Interrupt, Self::Dma, Self::PpuRegister, Self::ApuPort];
+
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Nmi,
+    Irq,
+    /// A DMA of the channels, whose bits are set, started
+    DmaStart {
+        channels: u8,
+    },
+    /// All DMA channels finished their transfers
+    DmaEnd,
+    /// A write to the PPU register `$2100 + addr`
+    PpuWrite {
+        addr: u8,
+        value: u8,
+    },
+    /// The CPU wrote to an APU port `0..4`
+    ApuWrite {
+        port: u8,
+        value: u8,
+    },
+    /// The CPU read from an APU port `0..4`
+    ApuRead {
+        port: u8,
+        value: u8,
+    },
+}
+
+impl EventKind {
+    pub const fn category(&self) -> Category {
+        match self {
+            Self::Nmi | Self::Irq => Category::Interrupt,
+            Self::DmaStart { .. } | Self::DmaEnd => Category::Dma,
+            Self::PpuWrite { .. } => Category::PpuRegister,
+            Self::ApuWrite { .. } | Self::ApuRead { .. } => Category::ApuPort,
+        }
+    }
+}
+
+/// The position of the video output at an event
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    /// Frames since power-on
+    pub frame: u64,
+    pub scanline: u16,
+    /// The dot of the scanline, which takes 4 master cycles
+    pub dot: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub time: Timestamp,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct EventTrace {
+    events: VecDeque<Event>,
+    capacity: usize,
+    categories: u8,
+    frame: u64,
+}
+
+impl Default for EventTrace {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl EventTrace {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::new(),
+            capacity,
+            categories: 0,
+            frame: 0,
+        }
+    }
+
+    pub fn set_enabled(&mut self, category: Category, enabled: bool) {
+        if enabled {
+            self.categories |= category.bit()
+        } else {
+            self.categories &= !category.bit()
+        }
+    }
+
+    pub const fn is_enabled(&self, category: Category) -> bool {
+        self.categories & category.bit() > 0
+    }
+
+    /// Change the amount of events kept, the oldest surplus events are dropped
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        let surplus = self.events.len().saturating_sub(capacity);
+        self.events.drain(..surplus);
+    }
+
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn push(&mut self, scanline: u16, dot: u16, kind: EventKind) {
+        if !self.is_enabled(kind.category()) || self.capacity == 0 {
+            return;
+        }
+        if self.events.len() >= self.capacity {
+            self.events.pop_front();
+        }
+        let time = Timestamp {
+            frame: self.frame,
+            scanline,
+            dot,
+        };
+        self.events.push_back(Event { time, kind })
+    }
+
+    pub(crate) fn next_frame(&mut self) {
+        self.frame += 1
+    }
+
+    /// The recorded events, the oldest first
+    pub fn events(&self) -> impl Iterator<Item = &Event> + '_ {
+        self.events.iter()
+    }
+
+    /// Remove and return all recorded events, the oldest first
+    pub fn take_events(&mut self) -> Vec<Event> {
+        self.events.drain(..).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear()
+    }
+}
+
+#[cfg(test)]
+mod tests;
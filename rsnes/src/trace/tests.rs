@@ -0,0 +1,25 @@
+use super::*;
+
+#[test]
+fn ring_buffer() {
+    let mut trace = EventTrace::new(3);
+    trace.push(0, 0, EventKind::Nmi);
+    assert_eq!(trace.events().count(), 0);
+    trace.set_enabled(Category::Interrupt, true);
+    trace.set_enabled(Category::PpuRegister, true);
+    for i in 0..4 {
+        trace.push(i, 0, EventKind::PpuWrite { addr: 0, value: 0 });
+    }
+    trace.push(10, 0, EventKind::DmaEnd);
+    trace.next_frame();
+    trace.push(20, 5, EventKind::Irq);
+    let scanlines: Vec<_> = trace.events().map(|event| event.time.scanline).collect();
+    assert_eq!(scanlines, [2, 3, 20]);
+    let last = trace.events().last().unwrap();
+    assert_eq!(last.time.frame, 1);
+    assert_eq!(last.time.dot, 5);
+
+    trace.set_capacity(1);
+    assert_eq!(trace.take_events().len(), 1);
+    assert_eq!(trace.events().count(), 0);
+}
@@ -0,0 +1,86 @@
+//! Recording of memory accesses in watched address ranges
+//!
+//! The accesses are collected while emulating and taken by the frontend
+//! afterwards, e.g. to call the memory hooks of a script.
+//! Watches are not part of save states.
+
+use crate::device::{Addr24, Data};
+use core::ops::RangeInclusive;
+
+/// Accesses beyond this count are dropped until the accesses are taken
+const MAX_RECORDED_ACCESSES: usize = 0x10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub addr: Addr24,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchId(u32);
+
+#[derive(Debug, Clone)]
+struct Watch {
+    id: WatchId,
+    kind: AccessKind,
+    range: RangeInclusive<Addr24>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Watches {
+    watches: Vec<Watch>,
+    next_id: u32,
+    accesses: Vec<(WatchId, MemoryAccess)>,
+}
+
+impl Watches {
+    /// Watch the accesses of the given kind to the address range
+    pub fn add(&mut self, kind: AccessKind, range: RangeInclusive<Addr24>) -> WatchId {
+        let id = WatchId(self.next_id);
+        self.next_id += 1;
+        self.watches.push(Watch { id, kind, range });
+        id
+    }
+
+    /// Remove a watch, `false` is returned if the watch does not exist
+    pub fn remove(&mut self, id: WatchId) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|watch| watch.id != id);
+        self.accesses.retain(|(i, _)| *i != id);
+        self.watches.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// Take the recorded accesses in the order they happened
+    pub fn take_accesses(&mut self) -> Vec<(WatchId, MemoryAccess)> {
+        core::mem::take(&mut self.accesses)
+    }
+
+    pub(crate) fn record<D: Data>(&mut self, kind: AccessKind, mut addr: Addr24, value: D) {
+        for &value in value.to_bytes().as_ref() {
+            for watch in &self.watches {
+                if watch.kind == kind
+                    && watch.range.contains(&addr)
+                    && self.accesses.len() < MAX_RECORDED_ACCESSES
+                {
+                    let access = MemoryAccess { kind, addr, value };
+                    self.accesses.push((watch.id, access))
+                }
+            }
+            addr.addr = addr.addr.wrapping_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,36 @@
+use super::*;
+
+#[test]
+fn record_accesses() {
+    let mut watches = Watches::default();
+    let ram = watches.add(
+        AccessKind::Write,
+        Addr24::new(0x7e, 0x0010)..=Addr24::new(0x7e, 0x0011),
+    );
+    let rom = watches.add(
+        AccessKind::Read,
+        Addr24::new(0x00, 0x8000)..=Addr24::new(0x00, 0xffff),
+    );
+    watches.record(AccessKind::Write, Addr24::new(0x7e, 0x000f), 0x1234u16);
+    watches.record(AccessKind::Read, Addr24::new(0x7e, 0x0010), 0x56u8);
+    watches.record(AccessKind::Read, Addr24::new(0x00, 0xffff), 0x789au16);
+    let write = |addr, value| MemoryAccess {
+        kind: AccessKind::Write,
+        addr: Addr24::new(0x7e, addr),
+        value,
+    };
+    let read = MemoryAccess {
+        kind: AccessKind::Read,
+        addr: Addr24::new(0x00, 0xffff),
+        value: 0x9a,
+    };
+    assert_eq!(
+        watches.take_accesses(),
+        [(ram, write(0x0010, 0x12)), (rom, read)]
+    );
+    assert!(watches.take_accesses().is_empty());
+    watches.record(AccessKind::Write, Addr24::new(0x7e, 0x0011), 0u8);
+    assert!(watches.remove(ram));
+    assert!(!watches.remove(ram));
+    assert!(watches.take_accesses().is_empty());
+}
@@ -20,122 +20,200 @@ impl syn::parse::Parse for ParseExprList {
     }
 }
 
-fn get_struct_fields(
-    struct_fields: &syn::Fields,
-) -> (Vec<impl quote::ToTokens>, Vec<impl quote::ToTokens>) {
-    let fields: Vec<_> = struct_fields
+/// The attribute `#[save_state(default)]` or `#[save_state(default = expr)]`
+struct ParseDefault(Option<syn::Expr>);
+
+impl syn::parse::Parse for ParseDefault {
+    fn parse(input: syn::parse::ParseStream<'_>) -> syn::parse::Result<Self> {
+        let ident: syn::Ident = input.parse()?;
+        if ident != "default" {
+            return Err(syn::parse::Error::new(
+                ident.span(),
+                format_args!("expected `default`, got `{}`", ident),
+            ));
+        }
+        if input.is_empty() {
+            return Ok(Self(None));
+        }
+        input.parse::<syn::Token!(=)>()?;
+        Ok(Self(Some(input.parse()?)))
+    }
+}
+
+/// The same hash as `save_state::field_tag`, which can't be used
+/// here, because it is needed while the macro is expanded
+fn field_tag(name: &str) -> u32 {
+    name.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+struct StructField {
+    cfgs: Vec<syn::Attribute>,
+    tag: u32,
+    ser: proc_macro2::TokenStream,
+    deser: proc_macro2::TokenStream,
+    /// The assignment of the default value, if the field is missing in the state
+    default: Option<proc_macro2::TokenStream>,
+}
+
+fn get_struct_fields(struct_fields: &syn::Fields) -> syn::parse::Result<Vec<StructField>> {
+    let mut tags = std::collections::HashMap::new();
+    struct_fields
         .iter()
-        .map(|field| {
-            if let Some(attr) = field.attrs.iter().find(|attr| {
+        .enumerate()
+        .map(|(i, field)| {
+            let ser_deser = match field.attrs.iter().find(|attr| {
                 attr.path
                     .segments
                     .last()
                     .filter(|i| i.ident.to_string() == "except")
                     .is_some()
             }) {
-                (Some(attr.parse_args::<ParseExprList>().unwrap().0), field)
-            } else {
-                (None, field)
+                Some(attr) => Some(attr.parse_args::<ParseExprList>()?.0),
+                None => None,
+            };
+            let member = match &field.ident {
+                Some(ident) => syn::Member::Named(ident.clone()),
+                None => syn::Member::Unnamed(syn::Index::from(i)),
+            };
+            let name = match &member {
+                syn::Member::Named(ident) => ident.to_string(),
+                syn::Member::Unnamed(index) => index.index.to_string(),
+            };
+            let default = match field
+                .attrs
+                .iter()
+                .find(|attr| attr.path.is_ident("save_state"))
+            {
+                Some(attr) => Some(match attr.parse_args::<ParseDefault>()?.0 {
+                    Some(expr) => quote::quote! { self.#member = #expr },
+                    None => quote::quote! { self.#member = Default::default() },
+                }),
+                None => None,
+            };
+            let tag = field_tag(&name);
+            if let Some(other) = tags.insert(tag, name.clone()) {
+                let text = format!("the fields `{}` and `{}` have the same tag", other, name);
+                return Err(syn::parse::Error::new_spanned(field, text));
             }
-        })
-        .collect();
-    let (ser_expr, deser_expr) = (
-        fields
-            .iter()
-            .enumerate()
-            .map(|(i, (ser_deser, field))| {
-                let field_name = &field.ident;
-                let i = syn::Index::from(i);
-                if let Some(field_name) = field_name {
-                    if let Some([ser, _deser]) = ser_deser {
-                        quote::quote! {{
-                            let f = (#ser);
-                            let state: &mut save_state::SaveStateSerializer = state;
-                            let _: () = f(&self.#field_name, state);
-                        }}
-                    } else {
-                        quote::quote! {
-                            self.#field_name.serialize(state)
-                        }
-                    }
-                } else {
-                    if let Some([ser, _deser]) = ser_deser {
-                        quote::quote! {{
-                            let f = (#ser);
-                            let state: &mut save_state::SaveStateSerializer = state;
-                            let _: () = f(&self.#i, state);
-                        }}
-                    } else {
-                        quote::quote! {
-                            self.#i.serialize(state)
-                        }
-                    }
-                }
-            })
-            .collect::<Vec<_>>(),
-        fields
-            .iter()
-            .enumerate()
-            .map(|(i, (ser_deser, field))| {
-                let field_name = &field.ident;
-                let i = syn::Index::from(i);
-                if let Some(field_name) = field_name {
-                    if let Some([_ser, deser]) = ser_deser {
-                        quote::quote! {{
-                            let f = (#deser);
-                            let state: &mut save_state::SaveStateDeserializer = state;
-                            let _: () = f(&mut self.#field_name, state);
-                        }}
-                    } else {
-                        quote::quote! {
-                            self.#field_name.deserialize(state)
-                        }
-                    }
-                } else {
-                    if let Some([_ser, deser]) = ser_deser {
-                        quote::quote! {{
-                            let f = (#deser);
-                            let state: &mut save_state::SaveStateDeserializer = state;
-                            let _: () = f(&mut self.#i, state)
-                        }}
-                    } else {
-                        quote::quote! {
-                            self.#i.deserialize(state)
-                        }
-                    }
-                }
+            // forward `#[cfg(...)]` attributes, so conditionally compiled fields are supported
+            let cfgs = field
+                .attrs
+                .iter()
+                .filter(|attr| attr.path.is_ident("cfg"))
+                .cloned()
+                .collect();
+            let (ser, deser) = if let Some([ser, deser]) = ser_deser {
+                (
+                    quote::quote! {{
+                        let f = (#ser);
+                        let state: &mut save_state::SaveStateSerializer = state;
+                        let _: () = f(&self.#member, state);
+                    }},
+                    quote::quote! {{
+                        let f = (#deser);
+                        let state: &mut save_state::SaveStateDeserializer = state;
+                        let _: () = f(&mut self.#member, state);
+                    }},
+                )
+            } else {
+                (
+                    quote::quote! { self.#member.serialize(state) },
+                    quote::quote! { self.#member.deserialize(state) },
+                )
+            };
+            Ok(StructField {
+                cfgs,
+                tag,
+                ser,
+                deser,
+                default,
             })
-            .collect::<Vec<_>>(),
-    );
-    (ser_expr, deser_expr)
+        })
+        .collect()
 }
 
-#[proc_macro_derive(InSaveState, attributes(except))]
+/// Store a struct as a block of tagged fields, see the `save-state` crate.
+///
+/// - `#[except(ser, deser)]` replaces the (de)serialization of a field
+///   with the two closures.
+/// - `#[save_state(default)]` or `#[save_state(default = expr)]` resets a
+///   field, which is missing in the state, to its default or to `expr`.
+///   Fields without it keep their current value.
+#[proc_macro_derive(InSaveState, attributes(except, save_state))]
 pub fn derive_in_save_state(input_struct: TokenStream) -> TokenStream {
     match syn::parse::<syn::DeriveInput>(input_struct.clone()) {
         Ok(derive_input) => {
             let (impl_generics, ty_generics, where_clause) = derive_input.generics.split_for_impl();
             let ty_name = &derive_input.ident;
-            let (ser_expr, deser_expr) = match derive_input.data {
+            let fields = match &derive_input.data {
                 syn::Data::Struct(field_struct) => get_struct_fields(&field_struct.fields),
                 _ => {
-                    return {
-                        let text = format!("expected struct, got `{}`", derive_input.ident);
-                        syn::parse::Error::new_spanned(derive_input, text)
-                    }
-                    .into_compile_error()
-                    .into()
+                    let text = format!("expected struct, got `{}`", derive_input.ident);
+                    Err(syn::parse::Error::new_spanned(&derive_input, text))
                 }
             };
+            let fields = match fields {
+                Ok(fields) => fields,
+                Err(err) => return err.into_compile_error().into(),
+            };
+            let ser_expr = fields.iter().map(|field| {
+                let (cfgs, tag, ser) = (&field.cfgs, field.tag, &field.ser);
+                quote::quote! {
+                    #(#cfgs)*
+                    {
+                        let field = state.begin_field(#tag);
+                        #ser;
+                        state.end_block(field);
+                    }
+                }
+            });
+            let missing = |i| quote::format_ident!("missing_{}", i);
+            let deser_arms = fields.iter().enumerate().map(|(i, field)| {
+                let (cfgs, tag, deser) = (&field.cfgs, field.tag, &field.deser);
+                match field.default {
+                    Some(_) => {
+                        let missing = missing(i);
+                        quote::quote! { #(#cfgs)* #tag => { #missing = false; #deser } }
+                    }
+                    None => quote::quote! { #(#cfgs)* #tag => #deser, },
+                }
+            });
+            let (missing_decls, defaults): (Vec<_>, Vec<_>) = fields
+                .iter()
+                .enumerate()
+                .filter_map(|(i, field)| {
+                    let (cfgs, default) = (&field.cfgs, field.default.as_ref()?);
+                    let missing = missing(i);
+                    Some((
+                        quote::quote! { #(#cfgs)* let mut #missing = true; },
+                        quote::quote! { #(#cfgs)* if #missing { #default } },
+                    ))
+                })
+                .unzip();
             quote::quote!(
                 impl #impl_generics save_state::InSaveState
                         for #ty_name #ty_generics #where_clause {
                     fn serialize(&self, state: &mut save_state::SaveStateSerializer) {
-                        #(#ser_expr;)*
+                        let block = state.begin_block();
+                        #(#ser_expr)*
+                        state.end_block(block);
                     }
 
                     fn deserialize(&mut self, state: &mut save_state::SaveStateDeserializer) {
-                        #(#deser_expr;)*
+                        let mut block = state.take_block();
+                        #(#missing_decls)*
+                        while let Some((tag, mut field)) = block.next_field() {
+                            let state = &mut field;
+                            match tag {
+                                #(#deser_arms)*
+                                _ => (),
+                            }
+                            block.propagate(field);
+                        }
+                        #(#defaults)*
+                        state.propagate(block);
                     }
                 }
             )
@@ -1,24 +1,319 @@
+//! Binary save states
+//!
+//! Primitive values are stored positionally in little endian. The derive
+//! macro `InSaveState` of `save-state-macro` instead stores a struct as a
+//! length-prefixed block of tagged fields:
+//!
+//! ```text
+//! struct = length: u32, field*
+//! field  = tag: u32, length: u32, data: [u8; length]
+//! ```
+//!
+//! The tag is a hash of the field name (see [`field_tag`]). When a struct
+//! is deserialized, fields with unknown tags are skipped, so states stay
+//! loadable after fields were added or removed. Fields missing in the state
+//! are reset, if they are marked with `#[save_state(default)]` or
+//! `#[save_state(default = expr)]`. Other fields keep their current value,
+//! because the structs are deserialized in place and e.g. values derived
+//! from the cartridge or the configuration must survive an older state.
+
 #[cfg(test)]
 mod tests;
 
-pub struct SaveStateSerializer {
-    pub data: Vec<u8>,
+enum Output<'a> {
+    Vec(Vec<u8>),
+    /// A buffer of the caller and the amount of bytes written.
+    /// Bytes beyond the end of the buffer are only counted.
+    Slice(&'a mut [u8], usize),
+}
+
+pub struct SaveStateSerializer<'a> {
+    output: Output<'a>,
+}
+
+impl Default for SaveStateSerializer<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> SaveStateSerializer<'a> {
+    pub fn new() -> Self {
+        Self::reuse(Vec::new())
+    }
+
+    /// Create a serializer, which doesn't allocate until `capacity` bytes are written
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::reuse(Vec::with_capacity(capacity))
+    }
+
+    /// Create a serializer writing into `buffer`.
+    /// The buffer gets cleared, but its allocation is reused.
+    pub fn reuse(mut buffer: Vec<u8>) -> Self {
+        buffer.clear();
+        Self {
+            output: Output::Vec(buffer),
+        }
+    }
+
+    /// Create a serializer writing into the start of `buffer`, which never allocates.
+    /// If the state doesn't fit, [`Self::written`] returns an error.
+    pub fn for_slice(buffer: &'a mut [u8]) -> Self {
+        Self {
+            output: Output::Slice(buffer, 0),
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        match &mut self.output {
+            Output::Vec(data) => data.extend_from_slice(bytes),
+            Output::Slice(buffer, len) => {
+                if let Some(target) = buffer.get_mut(*len..*len + bytes.len()) {
+                    target.copy_from_slice(bytes)
+                }
+                *len += bytes.len()
+            }
+        }
+    }
+
+    /// The size of the state written so far, including bytes, which didn't fit into the slice
+    pub fn len(&self) -> usize {
+        match &self.output {
+            Output::Vec(data) => data.len(),
+            Output::Slice(_, len) => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The written bytes, which fit into the slice
+    pub fn data(&self) -> &[u8] {
+        match &self.output {
+            Output::Vec(data) => data,
+            Output::Slice(buffer, len) => &buffer[..(*len).min(buffer.len())],
+        }
+    }
+
+    /// Mutable access to the written bytes, see [`Self::data`]
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        match &mut self.output {
+            Output::Vec(data) => data,
+            Output::Slice(buffer, len) => {
+                let end = (*len).min(buffer.len());
+                &mut buffer[..end]
+            }
+        }
+    }
+
+    /// The size of the state or an error, if it didn't fit into the slice
+    pub fn written(&self) -> Result<usize, BufferTooSmall> {
+        match &self.output {
+            Output::Slice(buffer, len) if *len > buffer.len() => {
+                Err(BufferTooSmall { required: *len })
+            }
+            _ => Ok(self.len()),
+        }
+    }
+
+    /// Take the written bytes, the bytes in a slice get copied
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.output {
+            Output::Vec(data) => data,
+            Output::Slice(..) => self.data().to_vec(),
+        }
+    }
+
+    /// Write the state into `writer` and return the buffer of the
+    /// serializer, so that it can be reused with [`Self::reuse`].
+    ///
+    /// The lengths of blocks are only known after their end, so the
+    /// state is written as a whole after it has been serialized.
+    pub fn into_writer<W: std::io::Write>(self, mut writer: W) -> std::io::Result<Vec<u8>> {
+        self.written().map_err(std::io::Error::other)?;
+        writer.write_all(self.data())?;
+        Ok(match self.output {
+            Output::Vec(data) => data,
+            Output::Slice(..) => Vec::new(),
+        })
+    }
+
+    /// Start a block prefixed with its length, which is written by [`Self::end_block`]
+    pub fn begin_block(&mut self) -> usize {
+        let start = self.len();
+        self.write(&[0; 4]);
+        start
+    }
+
+    pub fn end_block(&mut self, start: usize) {
+        let len = (self.len() - start - 4) as u32;
+        if let Some(target) = self.data_mut().get_mut(start..start + 4) {
+            target.copy_from_slice(&len.to_le_bytes())
+        }
+    }
+
+    /// Start a field of a struct, see the crate documentation
+    pub fn begin_field(&mut self, tag: u32) -> usize {
+        tag.serialize(self);
+        self.begin_block()
+    }
+}
+
+/// The tag of a struct field with the name `name` (a 32-bit FNV-1a hash).
+/// Fields of tuple structs are named by their index.
+pub const fn field_tag(name: &str) -> u32 {
+    let (bytes, mut hash, mut i) = (name.as_bytes(), 0x811c_9dc5u32, 0);
+    while i < bytes.len() {
+        hash = (hash ^ bytes[i] as u32).wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
+/// Serialize `value` into `buffer` reusing its allocation.
+///
+/// Serializing the same kind of state into the same buffer over and over
+/// again does not allocate after the first time.
+pub fn serialize_in_place<T: InSaveState>(value: &T, buffer: &mut Vec<u8>) {
+    let mut state = SaveStateSerializer::reuse(core::mem::take(buffer));
+    value.serialize(&mut state);
+    *buffer = state.into_vec();
+}
+
+/// The buffer given to [`serialize_into`] is smaller than the state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferTooSmall {
+    /// The size of the state in bytes
+    pub required: usize,
+}
+
+impl std::fmt::Display for BufferTooSmall {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "the buffer is too small for {} bytes", self.required)
+    }
+}
+
+impl std::error::Error for BufferTooSmall {}
+
+/// Serialize `value` directly into the caller's `buffer` and return the size
+/// of the state, so snapshots taken every frame don't allocate.
+/// If `buffer` is too small, its content is unspecified and the required
+/// size is returned.
+pub fn serialize_into<T: InSaveState>(
+    value: &T,
+    buffer: &mut [u8],
+) -> Result<usize, BufferTooSmall> {
+    let mut state = SaveStateSerializer::for_slice(buffer);
+    value.serialize(&mut state);
+    state.written()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeserializeError {
+    /// The state ended before all values were read
+    NotEnoughData,
+    /// A value can't be represented, e.g. an unknown enum discriminant
+    InvalidValue,
+}
+
+impl std::fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::NotEnoughData => write!(f, "the save state is truncated"),
+            Self::InvalidValue => write!(f, "the save state contains an invalid value"),
+        }
+    }
 }
 
+impl std::error::Error for DeserializeError {}
+
 pub struct SaveStateDeserializer<'a> {
     pub data: core::slice::Iter<'a, u8>,
+    error: Option<DeserializeError>,
 }
 
 impl<'a> SaveStateDeserializer<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data: data.iter(),
+            error: None,
+        }
+    }
+
     pub fn consume(&mut self, n: usize) {
         if n > 0 {
             let _ = self.data.nth(n - 1);
         }
     }
+
+    /// Mark the state as invalid. The first error is kept and
+    /// the remaining data is skipped, so all further reads fail.
+    pub fn fail(&mut self, error: DeserializeError) {
+        self.error.get_or_insert(error);
+        self.data = [].iter();
+    }
+
+    pub const fn error(&self) -> Option<DeserializeError> {
+        self.error
+    }
+
+    /// Take over the error of a deserializer returned by [`Self::take_block`]
+    pub fn propagate(&mut self, block: SaveStateDeserializer<'a>) {
+        if let Some(error) = block.error {
+            self.fail(error)
+        }
+    }
+
+    /// Take a block prefixed with its length
+    pub fn take_block(&mut self) -> SaveStateDeserializer<'a> {
+        let mut len: u32 = 0;
+        len.deserialize(self);
+        let data = self.data.as_slice();
+        if data.len() < len as usize {
+            self.fail(DeserializeError::NotEnoughData)
+        }
+        let mut block = Self::new(data.get(..len as usize).unwrap_or_default());
+        block.error = self.error;
+        self.consume(len as usize);
+        block
+    }
+
+    /// Take the next field of a struct block and return its tag and data
+    pub fn next_field(&mut self) -> Option<(u32, SaveStateDeserializer<'a>)> {
+        if self.data.as_slice().is_empty() {
+            return None;
+        }
+        let mut tag: u32 = 0;
+        tag.deserialize(self);
+        Some((tag, self.take_block()))
+    }
+
+    /// Return the error of the state, if any
+    pub fn finish(self) -> Result<(), DeserializeError> {
+        self.error.map_or(Ok(()), Err)
+    }
+}
+
+/// Deserialize `value` from `data`. If the state is invalid,
+/// `value` is restored and the error is returned.
+pub fn deserialize_checked<T: InSaveState>(
+    value: &mut T,
+    data: &[u8],
+) -> Result<(), DeserializeError> {
+    let mut backup = vec![];
+    serialize_in_place(value, &mut backup);
+    let mut state = SaveStateDeserializer::new(data);
+    value.deserialize(&mut state);
+    state
+        .finish()
+        .inspect_err(|_| value.deserialize(&mut SaveStateDeserializer::new(&backup)))
 }
 
 pub trait InSaveState: Sized {
     fn serialize(&self, state: &mut SaveStateSerializer);
+    /// Read the value from the state. Invalid data is reported with
+    /// [`SaveStateDeserializer::fail`] instead of panicking.
     fn deserialize(&mut self, state: &mut SaveStateDeserializer);
 }
 
@@ -26,7 +321,7 @@ macro_rules! impl_for_int {
     ($t:ty) => {
         impl InSaveState for $t {
             fn serialize(&self, state: &mut SaveStateSerializer) {
-                state.data.extend_from_slice(&self.to_le_bytes())
+                state.write(&self.to_le_bytes())
             }
 
             fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
@@ -34,7 +329,7 @@ macro_rules! impl_for_int {
                     *self = Self::from_le_bytes(state.data.as_slice()[..core::mem::size_of::<$t>()].try_into().unwrap());
                     state.consume(core::mem::size_of::<$t>());
                 } else {
-                    panic!("not enough data to deserialize")
+                    state.fail(DeserializeError::NotEnoughData)
                 }
             }
         }
@@ -75,7 +370,7 @@ impl<const N: usize, T: InSaveState + 'static> InSaveState for [T; N] {
     fn serialize(&self, state: &mut SaveStateSerializer) {
         if is_u8_or_i8(self) {
             let arr: &[u8; N] = unsafe { core::mem::transmute(self) };
-            state.data.extend_from_slice(arr)
+            state.write(arr)
         } else {
             for i in self.iter() {
                 T::serialize(i, state)
@@ -94,7 +389,7 @@ impl<const N: usize, T: InSaveState + 'static> InSaveState for [T; N] {
                 // see https://github.com/rust-lang/rust/issues/60471
                 *self = unsafe { core::mem::transmute_copy(res.unwrap()) }
             } else {
-                panic!("not enough data to deserialize")
+                state.fail(DeserializeError::NotEnoughData)
             }
         } else {
             self.iter_mut().for_each(|i| i.deserialize(state))
@@ -138,51 +433,138 @@ impl<T: InSaveState + Default> InSaveState for Option<T> {
         }
     }
 
+    /// An existing value is deserialized in place,
+    /// so that its fields excluded from the save state keep their values.
     fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
         let mut i = false;
         i.deserialize(state);
-        *self = if i {
-            let mut i = T::default();
-            i.deserialize(state);
-            Some(i)
+        if !i {
+            *self = None
+        } else if let Some(value) = self {
+            value.deserialize(state)
         } else {
-            None
+            let mut value = T::default();
+            value.deserialize(state);
+            *self = Some(value)
         }
     }
 }
 
-impl<T1: InSaveState, T2: InSaveState> InSaveState for (T1, T2) {
+macro_rules! impl_for_tuple {
+    ($($t:ident $i:tt),*) => {
+        impl<$($t: InSaveState),*> InSaveState for ($($t,)*) {
+            fn serialize(&self, state: &mut SaveStateSerializer) {
+                $(self.$i.serialize(state);)*
+            }
+
+            fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+                $(self.$i.deserialize(state);)*
+            }
+        }
+    };
+}
+
+impl_for_tuple!(T1 0, T2 1);
+impl_for_tuple!(T1 0, T2 1, T3 2);
+impl_for_tuple!(T1 0, T2 1, T3 2, T4 3);
+
+impl<T: InSaveState> InSaveState for Box<T> {
     fn serialize(&self, state: &mut SaveStateSerializer) {
-        self.0.serialize(state);
-        self.1.serialize(state);
+        (**self).serialize(state)
     }
 
     fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
-        self.0.deserialize(state);
-        self.1.deserialize(state);
+        (**self).deserialize(state)
     }
 }
 
+/// The value is copied before deserializing if it is shared
+impl<T: InSaveState + Clone> InSaveState for std::sync::Arc<T> {
+    fn serialize(&self, state: &mut SaveStateSerializer) {
+        (**self).serialize(state)
+    }
+
+    fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+        std::sync::Arc::make_mut(self).deserialize(state)
+    }
+}
+
+/// Serialize the length and the elements of a sequence
+fn serialize_seq<'a, T: InSaveState + 'a>(
+    len: usize,
+    items: impl Iterator<Item = &'a T>,
+    state: &mut SaveStateSerializer,
+) {
+    len.serialize(state);
+    for i in items {
+        i.serialize(state)
+    }
+}
+
+/// Deserialize the length of a sequence and return it together with the
+/// capacity to reserve, which is limited by the remaining data, so that
+/// a corrupted length doesn't allocate too much
+fn deserialize_len(state: &mut SaveStateDeserializer) -> Option<(usize, usize)> {
+    let mut len: usize = 0;
+    len.deserialize(state);
+    let capacity = len.min(state.data.as_slice().len());
+    state.error().is_none().then_some((len, capacity))
+}
+
+fn deserialize_new<T: InSaveState + Default>(state: &mut SaveStateDeserializer) -> T {
+    let mut val = T::default();
+    val.deserialize(state);
+    val
+}
+
 impl<T: InSaveState + Default> InSaveState for Vec<T> {
     fn serialize(&self, state: &mut SaveStateSerializer) {
-        self.len().serialize(state);
-        for i in self {
-            i.serialize(state)
-        }
+        serialize_seq(self.len(), self.iter(), state)
     }
 
     fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
-        let mut len: usize = 0;
-        len.deserialize(state);
-        if self.capacity() < len {
-            *self = Vec::with_capacity(len);
-        } else {
-            self.clear();
+        let Some((len, capacity)) = deserialize_len(state) else {
+            return;
+        };
+        self.clear();
+        self.reserve(capacity);
+        for _ in 0..len {
+            if state.error().is_some() {
+                break;
+            }
+            self.push(deserialize_new(state))
         }
+    }
+}
+
+impl<T: InSaveState + Default> InSaveState for Box<[T]> {
+    fn serialize(&self, state: &mut SaveStateSerializer) {
+        serialize_seq(self.len(), self.iter(), state)
+    }
+
+    fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+        let mut vec = core::mem::take(self).into_vec();
+        vec.deserialize(state);
+        *self = vec.into_boxed_slice()
+    }
+}
+
+impl<T: InSaveState + Default> InSaveState for std::collections::VecDeque<T> {
+    fn serialize(&self, state: &mut SaveStateSerializer) {
+        serialize_seq(self.len(), self.iter(), state)
+    }
+
+    fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+        let Some((len, capacity)) = deserialize_len(state) else {
+            return;
+        };
+        self.clear();
+        self.reserve(capacity);
         for _ in 0..len {
-            let mut val = T::default();
-            val.deserialize(state);
-            self.push(val)
+            if state.error().is_some() {
+                break;
+            }
+            self.push_back(deserialize_new(state))
         }
     }
 }
@@ -190,19 +572,22 @@ impl<T: InSaveState + Default> InSaveState for Vec<T> {
 impl InSaveState for String {
     fn serialize(&self, state: &mut SaveStateSerializer) {
         self.len().serialize(state);
-        state.data.extend_from_slice(self.as_bytes())
+        state.write(self.as_bytes())
     }
 
     fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
         let mut n: usize = 0;
         n.deserialize(state);
-        if state.data.as_slice().len() >= n {
-            *self = core::str::from_utf8(&state.data.as_slice()[..n])
-                .unwrap()
-                .to_string();
-            state.consume(n);
-        } else {
-            panic!("not enough data to deserialize")
+        if state.error().is_some() {
+            return;
+        }
+        match state.data.as_slice().get(..n).map(core::str::from_utf8) {
+            Some(Ok(text)) => {
+                *self = text.to_string();
+                state.consume(n);
+            }
+            Some(Err(_)) => state.fail(DeserializeError::InvalidValue),
+            None => state.fail(DeserializeError::NotEnoughData),
         }
     }
 }
@@ -7,14 +7,12 @@ pub fn test_serialize_i8_array() {
         i += 1;
         (i & 0xff) as i8
     });
-    let mut s = SaveStateSerializer { data: vec![] };
+    let mut s = SaveStateSerializer::new();
     a.serialize(&mut s);
-    for (i, v) in s.data.iter().enumerate() {
+    for (i, v) in s.data().iter().enumerate() {
         assert_eq!(((i + 1) & 0xff) as i8, *v as i8)
     }
-    let mut d = SaveStateDeserializer {
-        data: s.data.iter(),
-    };
+    let mut d = SaveStateDeserializer::new(s.data());
     let mut res = [0i8; 2050];
     res.deserialize(&mut d);
     for (i, v) in res.iter().enumerate() {
@@ -25,20 +23,16 @@ pub fn test_serialize_i8_array() {
 
 macro_rules! test_serialize_int {
     ($t:ty, $iter:expr) => {{
-        let mut s = SaveStateSerializer {
-            data: Vec::with_capacity(core::mem::size_of::<$t>()),
-        };
+        let mut s = SaveStateSerializer::with_capacity(core::mem::size_of::<$t>());
         for i in $iter {
             i.serialize(&mut s);
-            assert_eq!(s.data.as_slice(), i.to_le_bytes().as_slice());
-            let mut d = SaveStateDeserializer {
-                data: s.data.iter(),
-            };
+            assert_eq!(s.data(), i.to_le_bytes().as_slice());
+            let mut d = SaveStateDeserializer::new(s.data());
             let mut v: $t = 0;
             v.deserialize(&mut d);
             assert_eq!(i, v);
             assert!(d.data.as_slice().is_empty());
-            s.data.clear();
+            s = SaveStateSerializer::reuse(s.into_vec());
         }
     }};
 }
@@ -109,3 +103,173 @@ pub fn test_serialize_u128() {
 pub fn test_serialize_i128() {
     test_serialize_int!(i128, generate_u64_random_seq().map(|i| i128::from(i)))
 }
+
+#[test]
+pub fn test_serialize_in_place() {
+    let value = ([0x1234u16; 100], String::from("state"));
+    let mut buffer = vec![];
+    serialize_in_place(&value, &mut buffer);
+    let len = buffer.len();
+    let ptr = buffer.as_ptr();
+    serialize_in_place(&value, &mut buffer);
+    assert_eq!(buffer.len(), len);
+    assert_eq!(buffer.as_ptr(), ptr);
+
+    let mut s = SaveStateSerializer::new();
+    value.serialize(&mut s);
+    assert_eq!(buffer, s.data());
+}
+
+#[test]
+pub fn test_deserialize_option_in_place() {
+    /// Only the first value is part of the save state
+    #[derive(Default)]
+    struct Partial(u8, u8);
+
+    impl InSaveState for Partial {
+        fn serialize(&self, state: &mut SaveStateSerializer) {
+            self.0.serialize(state)
+        }
+
+        fn deserialize(&mut self, state: &mut SaveStateDeserializer) {
+            self.0.deserialize(state)
+        }
+    }
+
+    let mut s = SaveStateSerializer::new();
+    Some(Partial(1, 2)).serialize(&mut s);
+    let mut value = Some(Partial(3, 4));
+    value.deserialize(&mut SaveStateDeserializer::new(s.data()));
+    assert!(matches!(value, Some(Partial(1, 4))));
+    let mut value = None;
+    value.deserialize(&mut SaveStateDeserializer::new(s.data()));
+    assert!(matches!(value, Some(Partial(1, 0))));
+}
+
+#[test]
+pub fn test_tagged_fields() {
+    let mut s = SaveStateSerializer::new();
+    let block = s.begin_block();
+    let field = s.begin_field(field_tag("a"));
+    0x1234u16.serialize(&mut s);
+    s.end_block(field);
+    let field = s.begin_field(field_tag("b"));
+    s.end_block(field);
+    s.end_block(block);
+    0xffu8.serialize(&mut s);
+    assert_eq!(s.len(), 4 + 10 + 8 + 1);
+
+    let mut d = SaveStateDeserializer::new(s.data());
+    let mut block = d.take_block();
+    let (tag, mut field) = block.next_field().unwrap();
+    assert_eq!(tag, field_tag("a"));
+    let mut value = 0u16;
+    value.deserialize(&mut field);
+    assert_eq!(value, 0x1234);
+    let (tag, field) = block.next_field().unwrap();
+    assert_eq!(tag, field_tag("b"));
+    assert!(field.data.as_slice().is_empty());
+    assert!(block.next_field().is_none());
+    assert_eq!(d.data.as_slice(), [0xff]);
+}
+
+fn round_trip<T: InSaveState>(value: &T, target: &mut T) {
+    let mut s = SaveStateSerializer::new();
+    value.serialize(&mut s);
+    let mut d = SaveStateDeserializer::new(s.data());
+    target.deserialize(&mut d);
+    assert!(d.data.as_slice().is_empty());
+}
+
+#[test]
+pub fn test_containers() {
+    let value: Box<[u16]> = vec![1, 2, 3].into_boxed_slice();
+    let mut res: Box<[u16]> = Box::new([]);
+    round_trip(&value, &mut res);
+    assert_eq!(res, value);
+
+    let value: std::collections::VecDeque<_> = [(1u8, true, -2i16), (3, false, 4)].into();
+    let mut res = [(5, true, 6)].into();
+    round_trip(&value, &mut res);
+    assert_eq!(res, value);
+
+    let value = Some(Box::new([[7u32; 3]; 2]));
+    let mut res = None;
+    round_trip(&value, &mut res);
+    assert_eq!(res, value);
+
+    let value = std::sync::Arc::new(String::from("shared"));
+    let mut res = std::sync::Arc::new(String::new());
+    let shared = res.clone();
+    round_trip(&value, &mut res);
+    assert_eq!((res.as_str(), shared.as_str()), ("shared", ""));
+}
+
+#[test]
+pub fn test_serialize_into() {
+    let value = ([0x5678u16; 50], String::from("buffer"));
+    let mut s = SaveStateSerializer::with_capacity(0x100);
+    value.serialize(&mut s);
+    let mut buffer = [0; 0x100];
+    assert_eq!(
+        serialize_into(&value, &mut buffer[..10]),
+        Err(BufferTooSmall { required: s.len() })
+    );
+    assert_eq!(buffer[10..], [0; 0xf6]);
+    assert_eq!(serialize_into(&value, &mut buffer), Ok(s.len()));
+    assert_eq!(buffer[..s.len()], *s.data());
+
+    // a block, whose length doesn't fit
+    let mut buffer = [0; 6];
+    let mut s = SaveStateSerializer::for_slice(&mut buffer[..2]);
+    let block = s.begin_block();
+    7u8.serialize(&mut s);
+    s.end_block(block);
+    assert_eq!(s.written(), Err(BufferTooSmall { required: 5 }));
+    assert_eq!(buffer, [0; 6]);
+}
+
+#[test]
+pub fn test_into_writer() {
+    let mut s = SaveStateSerializer::new();
+    let block = s.begin_block();
+    0x1234u16.serialize(&mut s);
+    s.end_block(block);
+    let mut file = vec![0xff];
+    let buffer = s.into_writer(&mut file).unwrap();
+    assert_eq!(file, [0xff, 2, 0, 0, 0, 0x34, 0x12]);
+    assert_eq!(buffer, file[1..]);
+
+    let mut buffer = [0; 4];
+    let mut s = SaveStateSerializer::for_slice(&mut buffer);
+    0x1234u32.serialize(&mut s);
+    0u8.serialize(&mut s);
+    assert!(s.into_writer(&mut file).is_err());
+    assert_eq!(file.len(), 7);
+}
+
+#[test]
+pub fn test_invalid_state() {
+    let mut value = (0u32, String::from("kept"));
+    let mut d = SaveStateDeserializer::new(&[1, 2, 3]);
+    value.deserialize(&mut d);
+    assert_eq!(d.error(), Some(DeserializeError::NotEnoughData));
+    assert!(d.data.as_slice().is_empty());
+
+    let mut s = SaveStateSerializer::new();
+    (7u32, String::from("new")).serialize(&mut s);
+    let len = s.len();
+    assert_eq!(
+        deserialize_checked(&mut value, &s.data()[..len - 1]),
+        Err(DeserializeError::NotEnoughData)
+    );
+    assert_eq!(value, (0, String::from("kept")));
+    s.data_mut()[len - 1] = 0xff;
+    assert_eq!(
+        deserialize_checked(&mut value, s.data()),
+        Err(DeserializeError::InvalidValue)
+    );
+    s.data_mut()[len - 1] = b'w';
+    assert_eq!(deserialize_checked(&mut value, s.data()), Ok(()));
+    assert_eq!(value, (7, String::from("new")));
+}
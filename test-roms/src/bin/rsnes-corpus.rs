@@ -0,0 +1,87 @@
+//! Record or compare the regression corpus in a directory of ROMs
+//!
+//! - `rsnes-corpus record DIR FRAMES` runs every ROM in `DIR` for `FRAMES`
+//!   frames and writes the hashes into the manifest of the corpus
+//! - `rsnes-corpus compare DIR` runs the ROMs of the manifest again
+//!   and fails, if any frame differs from the recording
+
+use rsnes_test_roms::corpus::{find_roms, CorpusManifest, Recording, CORPUS_MANIFEST_NAME};
+use std::{path::Path, process::ExitCode};
+
+const USAGE: &str = "usage: rsnes-corpus record DIR FRAMES | rsnes-corpus compare DIR";
+
+fn record(dir: &Path, frames: u32) -> Result<bool, rsnes_test_roms::Error> {
+    let mut manifest = CorpusManifest::default();
+    let mut failed = false;
+    for path in find_roms(dir)? {
+        let rom = path.display().to_string();
+        match Recording::record(dir, path, frames)? {
+            Ok(recording) => {
+                println!("REC  {}", rom);
+                manifest.recordings.push(recording)
+            }
+            Err(failure) => {
+                failed = true;
+                println!("FAIL {}: {}", rom, failure)
+            }
+        }
+    }
+    manifest.store(dir)?;
+    println!(
+        "recorded {} ROMs into {}",
+        manifest.recordings.len(),
+        CORPUS_MANIFEST_NAME
+    );
+    Ok(!failed)
+}
+
+fn compare(dir: &Path) -> Result<bool, rsnes_test_roms::Error> {
+    let manifest = CorpusManifest::load(dir)?;
+    let mut failed = 0usize;
+    for recording in &manifest.recordings {
+        let rom = recording.path.display();
+        match recording.compare(dir)? {
+            Ok(None) => println!("PASS {}", rom),
+            Ok(Some(regression)) => {
+                failed += 1;
+                println!("FAIL {}: {}", rom, regression)
+            }
+            Err(failure) => {
+                failed += 1;
+                println!("FAIL {}: {}", rom, failure)
+            }
+        }
+    }
+    println!(
+        "{} of {} ROMs match the recording",
+        manifest.recordings.len() - failed,
+        manifest.recordings.len()
+    );
+    Ok(failed == 0)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["record", dir, frames] => match frames.parse() {
+            Ok(frames) => record(Path::new(dir), frames),
+            Err(_) => {
+                eprintln!("[error] Invalid amount of frames \"{}\"", frames);
+                return ExitCode::FAILURE;
+            }
+        },
+        ["compare", dir] => compare(Path::new(dir)),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("[error] {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Regression corpus of ROMs with the hashes of every frame
+//!
+//! A corpus is a directory of ROMs (`.sfc` and `.smc` files, also in
+//! subdirectories). Recording it runs every ROM for a number of frames and
+//! stores the hash of the picture (see [`ArrayFrameBuffer::hash`]) and a
+//! checksum of the audio samples of every frame in [`CORPUS_MANIFEST_NAME`]:
+//!
+//! ```toml
+//! [[rom]]
+//! path = "games/demo.sfc"
+//! frames = 2
+//! video = ["3f1c0de7a54b9e20", "0c6d5a13fe2b9a41"]
+//! audio = ["cbf29ce484222325", "5a3d8e1b4c7f0962"]
+//! ```
+//!
+//! Comparing runs the ROMs of the manifest again and reports the first frame,
+//! whose picture or audio differs from the recording. The emulation is
+//! deterministic, so any difference is a change of the emulated behaviour.
+
+use crate::{run_frame, run_isolated, Error, Failure};
+use rsnes::{
+    backend::{ArrayFrameBuffer, AudioBackend},
+    cartridge::Cartridge,
+    spc700::StereoSample,
+    EmulatorBuilder,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::{Path, PathBuf};
+
+/// The file name of the manifest in the directory of the corpus
+pub const CORPUS_MANIFEST_NAME: &str = "corpus.toml";
+
+/// The file extensions of the ROMs in a corpus
+const ROM_EXTENSIONS: [&str; 2] = ["sfc", "smc"];
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// An audio backend, which computes the 64-bit FNV-1a hash of the samples
+#[derive(Debug, Clone, Copy)]
+struct AudioChecksum(u64);
+
+impl AudioChecksum {
+    /// Return the checksum of the samples since the last call
+    fn take(&mut self) -> u64 {
+        core::mem::replace(&mut self.0, FNV_OFFSET_BASIS)
+    }
+}
+
+impl AudioBackend for AudioChecksum {
+    fn push_sample(&mut self, sample: StereoSample) {
+        let bytes = sample.l.to_le_bytes().into_iter();
+        self.0 = bytes
+            .chain(sample.r.to_le_bytes())
+            .fold(self.0, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+            });
+    }
+}
+
+/// The hashes exceed the integers of TOML, so they are written as hex strings
+fn serialize_hashes<S: Serializer>(hashes: &[u64], ser: S) -> Result<S::Ok, S::Error> {
+    ser.collect_seq(hashes.iter().map(|hash| format!("{:016x}", hash)))
+}
+
+fn deserialize_hashes<'de, D: Deserializer<'de>>(de: D) -> Result<Vec<u64>, D::Error> {
+    Vec::<String>::deserialize(de)?
+        .iter()
+        .map(|hash| u64::from_str_radix(hash, 16).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// The recording of a single ROM
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recording {
+    /// The path of the ROM relative to the manifest
+    pub path: PathBuf,
+    pub frames: u32,
+    /// The hash of the picture after every frame
+    #[serde(serialize_with = "serialize_hashes")]
+    #[serde(deserialize_with = "deserialize_hashes")]
+    pub video: Vec<u64>,
+    /// The checksum of the audio samples of every frame
+    #[serde(serialize_with = "serialize_hashes")]
+    #[serde(deserialize_with = "deserialize_hashes")]
+    pub audio: Vec<u64>,
+}
+
+/// The first difference between two recordings of a ROM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Regression {
+    /// The number of the first differing frame, starting at 1
+    pub frame: u32,
+    pub video: bool,
+    pub audio: bool,
+}
+
+impl std::fmt::Display for Regression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let what = match (self.video, self.audio) {
+            (true, true) => "the picture and the audio",
+            (true, false) => "the picture",
+            _ => "the audio",
+        };
+        write!(f, "{} diverged at frame {}", what, self.frame)
+    }
+}
+
+impl Recording {
+    /// Read the ROM relative to the directory `dir` and record `frames` frames
+    pub fn record(dir: &Path, path: PathBuf, frames: u32) -> Result<Result<Self, Failure>, Error> {
+        let full_path = dir.join(&path);
+        let rom = std::fs::read(&full_path).map_err(|err| Error::Io(full_path, err))?;
+        Ok(Self::record_rom(path, frames, rom))
+    }
+
+    /// Record `frames` frames of the ROM contents `rom`
+    pub fn record_rom(path: PathBuf, frames: u32, rom: Vec<u8>) -> Result<Self, Failure> {
+        let cartridge = Cartridge::from_bytes(&rom).map_err(Failure::Rom)?;
+        let (video, audio) = run_isolated(move || {
+            let mut device =
+                EmulatorBuilder::new(AudioChecksum(FNV_OFFSET_BASIS), ArrayFrameBuffer::new())
+                    .cartridge(cartridge)
+                    .build();
+            (0..frames)
+                .map(|_| {
+                    run_frame(&mut device);
+                    let audio = device.smp.backend.as_mut().map_or(0, AudioChecksum::take);
+                    (device.ppu.frame_buffer.hash(), audio)
+                })
+                .unzip()
+        })
+        .map_err(Failure::Panic)?;
+        Ok(Self {
+            path,
+            frames,
+            video,
+            audio,
+        })
+    }
+
+    /// Record the ROM again with the same amount of frames and compare it
+    pub fn compare(&self, dir: &Path) -> Result<Result<Option<Regression>, Failure>, Error> {
+        Ok(Self::record(dir, self.path.clone(), self.frames)?
+            .map(|recording| self.first_difference(&recording)))
+    }
+
+    /// Find the first frame, which differs in `other`
+    pub fn first_difference(&self, other: &Self) -> Option<Regression> {
+        let frames = self.video.len().max(other.video.len());
+        (0..frames).find_map(|i| {
+            let video = self.video.get(i) != other.video.get(i);
+            let audio = self.audio.get(i) != other.audio.get(i);
+            (video || audio).then(|| Regression {
+                frame: i as u32 + 1,
+                video,
+                audio,
+            })
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorpusManifest {
+    #[serde(default, rename = "rom")]
+    pub recordings: Vec<Recording>,
+}
+
+impl CorpusManifest {
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        toml::from_str(content).map_err(Error::Manifest)
+    }
+
+    /// Read the manifest in the directory `dir`
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(CORPUS_MANIFEST_NAME);
+        let content = std::fs::read_to_string(&path).map_err(|err| Error::Io(path, err))?;
+        Self::parse(&content)
+    }
+
+    /// Write the manifest into the directory `dir`
+    pub fn store(&self, dir: &Path) -> Result<(), Error> {
+        let path = dir.join(CORPUS_MANIFEST_NAME);
+        // the manifest only contains strings, integers and tables
+        let content = toml::to_string_pretty(self).unwrap();
+        std::fs::write(&path, content).map_err(|err| Error::Write(path, err))
+    }
+}
+
+/// Find the ROMs in the directory `dir` and its subdirectories.
+/// The paths are relative to `dir` and sorted.
+pub fn find_roms(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    fn visit(dir: &Path, prefix: &Path, roms: &mut Vec<PathBuf>) -> Result<(), Error> {
+        let entries = std::fs::read_dir(dir).map_err(|err| Error::Io(dir.to_owned(), err))?;
+        for entry in entries {
+            let entry = entry.map_err(|err| Error::Io(dir.to_owned(), err))?;
+            let path = entry.path();
+            let name = prefix.join(entry.file_name());
+            if path.is_dir() {
+                visit(&path, &name, roms)?
+            } else if path.extension().is_some_and(|ext| {
+                ROM_EXTENSIONS
+                    .iter()
+                    .any(|rom_ext| ext.eq_ignore_ascii_case(rom_ext))
+            }) {
+                roms.push(name)
+            }
+        }
+        Ok(())
+    }
+    let mut roms = vec![];
+    visit(dir, Path::new(""), &mut roms)?;
+    roms.sort();
+    Ok(roms)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,88 @@
+use super::*;
+use crate::tests::test_rom;
+
+#[test]
+fn record_and_compare() {
+    let recording = Recording::record_rom("test.sfc".into(), 4, test_rom()).unwrap();
+    assert_eq!((recording.video.len(), recording.audio.len()), (4, 4));
+    let again = Recording::record_rom("test.sfc".into(), 4, test_rom()).unwrap();
+    assert_eq!(recording.first_difference(&again), None);
+
+    let mut changed = again.clone();
+    changed.video[2] ^= 1;
+    changed.audio[3] ^= 1;
+    assert_eq!(
+        recording.first_difference(&changed),
+        Some(Regression {
+            frame: 3,
+            video: true,
+            audio: false
+        })
+    );
+    changed.audio[2] ^= 1;
+    let regression = recording.first_difference(&changed).unwrap();
+    assert_eq!(
+        regression.to_string(),
+        "the picture and the audio diverged at frame 3"
+    );
+
+    // a shorter run diverges after its last frame
+    let shorter = Recording::record_rom("test.sfc".into(), 3, test_rom()).unwrap();
+    assert_eq!(
+        recording.first_difference(&shorter),
+        Some(Regression {
+            frame: 4,
+            video: true,
+            audio: true
+        })
+    );
+    assert!(matches!(
+        Recording::record_rom("test.sfc".into(), 1, vec![0; 0x100]),
+        Err(Failure::Rom(_))
+    ));
+}
+
+#[test]
+fn manifest_round_trip() {
+    let manifest = CorpusManifest {
+        recordings: vec![Recording {
+            path: "games/demo.sfc".into(),
+            frames: 2,
+            video: vec![0x3f1c0de7a54b9e20, 0x0c6d5a13fe2b9a41],
+            audio: vec![FNV_OFFSET_BASIS, u64::MAX],
+        }],
+    };
+    let content = toml::to_string_pretty(&manifest).unwrap();
+    assert!(content.contains("'0c6d5a13fe2b9a41'"));
+    assert_eq!(CorpusManifest::parse(&content).unwrap(), manifest);
+    assert!(CorpusManifest::parse(
+        "[[rom]]\npath = \"a.sfc\"\nframes = 1\nvideo = [\"xyz\"]\naudio = []"
+    )
+    .is_err());
+}
+
+#[test]
+fn corpus_directory() {
+    let dir = std::env::temp_dir().join(format!("rsnes-corpus-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("b.sfc"), test_rom()).unwrap();
+    std::fs::write(dir.join("sub").join("a.SMC"), test_rom()).unwrap();
+    std::fs::write(dir.join("notes.txt"), "").unwrap();
+    let roms = find_roms(&dir).unwrap();
+    assert_eq!(
+        roms,
+        [PathBuf::from("b.sfc"), Path::new("sub").join("a.SMC")]
+    );
+
+    let recordings = roms
+        .into_iter()
+        .map(|path| Recording::record(&dir, path, 2).unwrap().unwrap())
+        .collect();
+    CorpusManifest { recordings }.store(&dir).unwrap();
+    let manifest = CorpusManifest::load(&dir).unwrap();
+    assert_eq!(manifest.recordings.len(), 2);
+    for recording in &manifest.recordings {
+        assert_eq!(recording.compare(&dir).unwrap().unwrap(), None);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
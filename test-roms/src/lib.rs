@@ -0,0 +1,255 @@
+//! Headless runner of test ROMs
+//!
+//! The tests are listed in a `manifest.toml` in the directory of the ROMs,
+//! which is usually given by the environment variable [`ROMS_DIR_VAR`].
+//! Each ROM is run for a fixed amount of frames without video or audio
+//! output. Afterwards the hash of the picture (see
+//! [`ArrayFrameBuffer::hash`]) and a byte of the memory map are compared
+//! with the expected values:
+//!
+//! ```toml
+//! [[test]]
+//! rom = "CPUTest/CPU/ADC/CPUADC.sfc"
+//! frames = 120
+//! hash = "3f1c0de7a54b9e20"
+//!
+//! [[test]]
+//! rom = "spc_dsp6.sfc"
+//! frames = 900
+//! memory = { address = 0x7e0000, value = 1 }
+//! ```
+//!
+//! A test without an expected hash passes if the memory check succeeds,
+//! which allows to find out the hash of the picture of a passing ROM.
+//!
+//! The [`corpus`] compares the picture and the audio of every frame instead.
+
+pub mod corpus;
+
+use rsnes::{
+    backend::{ArrayFrameBuffer, AudioBackend, AudioDummy, FrameBuffer},
+    cartridge::{Cartridge, ReadRomError},
+    device::{Addr24, Device},
+    EmulatorBuilder,
+};
+use serde::{Deserialize, Deserializer};
+use std::path::{Path, PathBuf};
+
+/// The environment variable with the directory of the test ROMs
+pub const ROMS_DIR_VAR: &str = "RSNES_TEST_ROMS_DIR";
+/// The file name of the manifest in the directory of the test ROMs
+pub const MANIFEST_NAME: &str = "manifest.toml";
+
+const MASTER_CYCLES_PER_TICK: u16 = 2;
+
+/// Stack size of the threads running the ROMs, the device contains
+/// the frame buffer, which may exceed the stack of the calling thread
+const RUNNER_STACK_SIZE: usize = 0x2000000;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, std::io::Error),
+    Write(PathBuf, std::io::Error),
+    Manifest(toml::de::Error),
+    /// The test of the ROM has neither a hash nor a memory check
+    NoCheck(PathBuf),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(path, err) => write!(f, "failed to read `{}`: {}", path.display(), err),
+            Self::Write(path, err) => write!(f, "failed to write `{}`: {}", path.display(), err),
+            Self::Manifest(err) => write!(f, "invalid manifest: {}", err),
+            Self::NoCheck(rom) => write!(
+                f,
+                "the test of `{}` has neither a hash nor a memory check",
+                rom.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct MemoryCheck {
+    /// The 24-bit address in the memory map of the CPU
+    pub address: u32,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TestCase {
+    /// The path of the ROM relative to the manifest
+    pub rom: PathBuf,
+    pub frames: u32,
+    /// The expected hash of the picture after the last frame
+    #[serde(default, deserialize_with = "deserialize_hash")]
+    pub hash: Option<u64>,
+    /// The memory is checked after the last frame
+    #[serde(default)]
+    pub memory: Option<MemoryCheck>,
+}
+
+/// The hashes exceed the integers of TOML, so they are written as hex strings
+fn deserialize_hash<'de, D: Deserializer<'de>>(de: D) -> Result<Option<u64>, D::Error> {
+    Option::<String>::deserialize(de)?
+        .map(|hash| {
+            u64::from_str_radix(hash.trim_start_matches("0x"), 16).map_err(serde::de::Error::custom)
+        })
+        .transpose()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Manifest {
+    #[serde(default, rename = "test")]
+    pub tests: Vec<TestCase>,
+}
+
+impl Manifest {
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let manifest: Self = toml::from_str(content).map_err(Error::Manifest)?;
+        match manifest
+            .tests
+            .iter()
+            .find(|test| test.hash.is_none() && test.memory.is_none())
+        {
+            Some(test) => Err(Error::NoCheck(test.rom.clone())),
+            None => Ok(manifest),
+        }
+    }
+
+    /// Read the manifest in the directory `dir`
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(MANIFEST_NAME);
+        let content = std::fs::read_to_string(&path).map_err(|err| Error::Io(path, err))?;
+        Self::parse(&content)
+    }
+}
+
+/// The directory of the test ROMs given by [`ROMS_DIR_VAR`]
+pub fn roms_dir() -> Option<PathBuf> {
+    std::env::var_os(ROMS_DIR_VAR).map(PathBuf::from)
+}
+
+#[derive(Debug)]
+pub enum Failure {
+    Rom(ReadRomError),
+    /// The emulator panicked with the given message
+    Panic(String),
+    Hash {
+        expected: u64,
+    },
+    Memory {
+        check: MemoryCheck,
+        value: u8,
+    },
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Rom(err) => write!(f, "invalid ROM: {}", err),
+            Self::Panic(msg) => write!(f, "the emulator panicked: {}", msg),
+            Self::Hash { expected } => write!(f, "expected the hash {:016x}", expected),
+            Self::Memory { check, value } => write!(
+                f,
+                "expected ${:02x} at ${:06x}, found ${:02x}",
+                check.value, check.address, value
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    /// The hash of the picture after the last frame, if all frames were run
+    pub hash: Option<u64>,
+    pub failures: Vec<Failure>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl TestCase {
+    /// Read the ROM relative to the directory `dir` and run the test
+    pub fn run(&self, dir: &Path) -> Result<Report, Error> {
+        let path = dir.join(&self.rom);
+        let rom = std::fs::read(&path).map_err(|err| Error::Io(path, err))?;
+        Ok(self.run_rom(rom))
+    }
+
+    /// Run the test with the ROM contents `rom`
+    pub fn run_rom(&self, rom: Vec<u8>) -> Report {
+        let test = self.clone();
+        run_isolated(move || test.run_device(&rom)).unwrap_or_else(|msg| Report {
+            hash: None,
+            failures: vec![Failure::Panic(msg)],
+        })
+    }
+
+    fn run_device(&self, rom: &[u8]) -> Report {
+        let cartridge = match Cartridge::from_bytes(rom) {
+            Ok(cartridge) => cartridge,
+            Err(err) => {
+                return Report {
+                    hash: None,
+                    failures: vec![Failure::Rom(err)],
+                }
+            }
+        };
+        let mut device = EmulatorBuilder::new(AudioDummy, ArrayFrameBuffer::new())
+            .cartridge(cartridge)
+            .build();
+        for _ in 0..self.frames {
+            run_frame(&mut device);
+        }
+        let hash = device.ppu.frame_buffer.hash();
+        let mut failures = vec![];
+        if let Some(expected) = self.hash.filter(|expected| *expected != hash) {
+            failures.push(Failure::Hash { expected })
+        }
+        if let Some(check) = self.memory {
+            let [addr @ .., bank, _] = check.address.to_le_bytes();
+            let value = device.peek(Addr24::new(bank, u16::from_le_bytes(addr)));
+            if value != check.value {
+                failures.push(Failure::Memory { check, value })
+            }
+        }
+        Report {
+            hash: Some(hash),
+            failures,
+        }
+    }
+}
+
+/// Run `f` in a thread with a stack large enough for the device.
+/// A panic is caught and its message returned.
+fn run_isolated<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Result<T, String> {
+    let result = std::thread::Builder::new()
+        .stack_size(RUNNER_STACK_SIZE)
+        .spawn(f)
+        .unwrap()
+        .join();
+    result.map_err(|err| {
+        err.downcast_ref::<&str>()
+            .map(ToString::to_string)
+            .or_else(|| err.downcast_ref::<String>().cloned())
+            .unwrap_or_default()
+    })
+}
+
+/// Run the device until the next frame starts
+fn run_frame<B: AudioBackend, FB: FrameBuffer>(device: &mut Device<B, FB>) {
+    device.run_cycle::<MASTER_CYCLES_PER_TICK>();
+    while !device.new_frame {
+        device.run_cycle::<MASTER_CYCLES_PER_TICK>();
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,59 @@
+//! Run the test ROMs in the directory given as the first argument
+//! or by the environment variable `RSNES_TEST_ROMS_DIR`
+
+use rsnes_test_roms::{roms_dir, Manifest, ROMS_DIR_VAR};
+use std::{path::PathBuf, process::ExitCode};
+
+fn main() -> ExitCode {
+    let Some(dir) = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .or_else(roms_dir)
+    else {
+        eprintln!(
+            "[error] No directory of test ROMs given, pass it as an argument or set `{}`",
+            ROMS_DIR_VAR
+        );
+        return ExitCode::FAILURE;
+    };
+    let manifest = match Manifest::load(&dir) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            eprintln!("[error] {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut failed = 0usize;
+    for test in &manifest.tests {
+        let rom = test.rom.display();
+        match test.run(&dir) {
+            Ok(report) if report.passed() => match report.hash {
+                Some(hash) if test.hash.is_none() => println!("PASS {} (hash {:016x})", rom, hash),
+                _ => println!("PASS {}", rom),
+            },
+            Ok(report) => {
+                failed += 1;
+                for failure in &report.failures {
+                    println!("FAIL {}: {}", rom, failure)
+                }
+                if let Some(hash) = report.hash {
+                    println!("     {}: the hash is {:016x}", rom, hash)
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                println!("FAIL {}", err)
+            }
+        }
+    }
+    println!(
+        "{} of {} tests passed",
+        manifest.tests.len() - failed,
+        manifest.tests.len()
+    );
+    if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
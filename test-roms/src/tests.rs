@@ -0,0 +1,103 @@
+use super::*;
+
+/// A LoROM, which stores `$42` at `$7e0000` and then loops forever
+pub(crate) fn test_rom() -> Vec<u8> {
+    let mut rom = vec![0xea; 0x8000];
+    let header = &mut rom[0x7fc0..];
+    header[..21].copy_from_slice(b"TEST ROM RUNNER      ");
+    header[21] = 0x20;
+    header[23] = 5;
+    header[25] = 1;
+    header[60..62].copy_from_slice(&0x8000u16.to_le_bytes());
+    rom[..8].copy_from_slice(&[
+        0xa9, 0x42, // lda #$42
+        0x8f, 0x00, 0x00, 0x7e, // sta $7e0000
+        0x80, 0xfe, // bra -2
+    ]);
+    rom
+}
+
+#[test]
+fn parse_manifest() {
+    let manifest = Manifest::parse(
+        r#"
+        [[test]]
+        rom = "a.sfc"
+        frames = 10
+        hash = "0x00000000deadbeef"
+
+        [[test]]
+        rom = "b.sfc"
+        frames = 20
+        memory = { address = 0x7e0010, value = 1 }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(manifest.tests[0].hash, Some(0xdeadbeef));
+    assert_eq!(manifest.tests[0].memory, None);
+    assert_eq!(
+        manifest.tests[1].memory,
+        Some(MemoryCheck {
+            address: 0x7e0010,
+            value: 1
+        })
+    );
+    assert!(matches!(
+        Manifest::parse("[[test]]\nrom = \"c.sfc\"\nframes = 1"),
+        Err(Error::NoCheck(_))
+    ));
+}
+
+#[test]
+fn run_test_rom() {
+    let mut test = TestCase {
+        rom: "test.sfc".into(),
+        frames: 2,
+        hash: None,
+        memory: Some(MemoryCheck {
+            address: 0x7e0000,
+            value: 0x42,
+        }),
+    };
+    let report = test.run_rom(test_rom());
+    assert!(report.passed());
+    let hash = report.hash.unwrap();
+
+    test.hash = Some(hash);
+    assert!(test.run_rom(test_rom()).passed());
+
+    test.hash = Some(!hash);
+    test.memory.as_mut().unwrap().value = 0x43;
+    let report = test.run_rom(test_rom());
+    assert!(matches!(
+        report.failures[..],
+        [Failure::Hash { .. }, Failure::Memory { value: 0x42, .. }]
+    ));
+
+    let report = test.run_rom(vec![0; 0x100]);
+    assert!(matches!(report.failures[..], [Failure::Rom(_)]));
+}
+
+/// Run the tests of the manifest in the directory given by
+/// the environment variable `RSNES_TEST_ROMS_DIR`.
+#[test]
+#[ignore]
+fn external_test_roms() {
+    let dir = roms_dir().expect("the environment variable `RSNES_TEST_ROMS_DIR` is not set");
+    let manifest = Manifest::load(&dir).unwrap();
+    let mut failed = 0usize;
+    for test in &manifest.tests {
+        let report = test.run(&dir).unwrap();
+        for failure in &report.failures {
+            println!("{}: {}", test.rom.display(), failure)
+        }
+        failed += usize::from(!report.passed());
+    }
+    assert_eq!(
+        failed,
+        0,
+        "{} of {} tests failed",
+        failed,
+        manifest.tests.len()
+    );
+}